@@ -0,0 +1,64 @@
+/*
+ * C interface to nix-parser.
+ *
+ * Link against libnix_parser_capi (built with `cargo build -p nix-parser-capi --release`).
+ * Every object returned by this library is owned by the caller and must be released with the
+ * matching `*_free` function. Strings are NUL-terminated UTF-8.
+ */
+
+#ifndef NIX_PARSER_H
+#define NIX_PARSER_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define NIX_PARSER_ABI_VERSION 1
+
+/* The result of parsing a source file. */
+typedef struct NixParseResult NixParseResult;
+
+/* Returns the ABI version implemented by the loaded library. */
+uint32_t nix_parser_abi_version(void);
+
+/*
+ * Parses `len` bytes of UTF-8 source text, recovering from syntax errors where possible.
+ * Returns NULL if the source is not valid UTF-8.
+ */
+NixParseResult *nix_parse(const char *source, size_t len);
+
+/* Returns true if the source was parsed without any errors. */
+bool nix_parse_result_ok(const NixParseResult *result);
+
+/* Returns the number of syntax errors reported while parsing. */
+size_t nix_parse_result_error_count(const NixParseResult *result);
+
+/*
+ * Returns the syntax errors as a JSON array of
+ * { "severity", "message", "start", "end", "line", "column", "labels", "notes" } objects.
+ * Release with nix_string_free.
+ */
+char *nix_parse_result_diagnostics_json(const NixParseResult *result);
+
+/*
+ * Returns the syntax tree as nested { "kind", "start", "end", "children" } JSON objects, or NULL
+ * if no tree could be recovered. Identifiers and literals also carry their source "text".
+ * Release with nix_string_free.
+ */
+char *nix_parse_result_ast_json(const NixParseResult *result);
+
+/* Releases a parse result. NULL is ignored. */
+void nix_parse_result_free(NixParseResult *result);
+
+/* Releases a string returned by this library. NULL is ignored. */
+void nix_string_free(char *string);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* NIX_PARSER_H */
@@ -0,0 +1,20 @@
+//! JSON encodings of syntax trees and diagnostics handed out through the C API.
+
+use nix_parser::ast::node::Node;
+use nix_parser::ast::SourceFile;
+use nix_parser::error::Errors;
+use serde_json::Value;
+
+/// Encodes the expression tree of `source` as nested
+/// `{ "kind", "start", "end", "children" }` objects. Identifiers and literals also carry their
+/// source `text`.
+pub fn ast(source: &SourceFile) -> Value {
+    serde_json::to_value(Node::new(source.expr())).expect("nodes are always serializable")
+}
+
+/// Encodes `errors` as an array of
+/// `{ "severity", "message", "start", "end", "line", "column", "labels", "notes" }` objects, with
+/// zero-based lines and columns.
+pub fn diagnostics(source: &str, errors: &Errors) -> Value {
+    serde_json::to_value(errors.to_located(source)).expect("diagnostics are always serializable")
+}
@@ -0,0 +1,215 @@
+//! A C ABI for `nix-parser`, for embedding the parser in editors and tools not written in Rust.
+//!
+//! The matching declarations live in `include/nix_parser.h`. Every object handed out by this
+//! library is owned by the caller and must be released with the corresponding `*_free` function.
+//! Strings are NUL-terminated UTF-8. No function unwinds into the caller: panics are caught and
+//! reported as a null return value.
+
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::{ptr, slice, str};
+
+use nix_parser::ast::SourceFile;
+use nix_parser::error::Errors;
+use nix_parser::parser::parse_source_file_partial;
+
+mod json;
+
+/// Version of the C ABI, bumped whenever a declaration in `nix_parser.h` changes incompatibly.
+pub const NIX_PARSER_ABI_VERSION: u32 = 1;
+
+/// The result of parsing a source file. Opaque to C callers.
+#[derive(Debug)]
+pub struct NixParseResult {
+    source: String,
+    ast: Option<SourceFile>,
+    errors: Errors,
+}
+
+impl NixParseResult {
+    fn new(source: &str) -> Self {
+        let (ast, errors) = match parse_source_file_partial(source) {
+            Ok(partial) => {
+                let errors = partial.errors().unwrap_or_default();
+                (partial.value().cloned(), errors)
+            }
+            Err(errors) => (None, errors),
+        };
+
+        NixParseResult {
+            source: source.to_owned(),
+            ast,
+            errors,
+        }
+    }
+}
+
+/// Returns the version of the C ABI implemented by this library.
+#[no_mangle]
+pub extern "C" fn nix_parser_abi_version() -> u32 {
+    NIX_PARSER_ABI_VERSION
+}
+
+/// Parses `len` bytes of UTF-8 source text, recovering from syntax errors where possible.
+///
+/// Returns null if the source is not valid UTF-8. The result must be released with
+/// `nix_parse_result_free`.
+///
+/// # Safety
+///
+/// `source` must point to at least `len` readable bytes, and may only be null if `len` is zero.
+#[no_mangle]
+pub unsafe extern "C" fn nix_parse(source: *const c_char, len: usize) -> *mut NixParseResult {
+    let bytes = if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(source as *const u8, len)
+    };
+
+    guard(|| {
+        let source = str::from_utf8(bytes).ok()?;
+        Some(Box::into_raw(Box::new(NixParseResult::new(source))))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Returns `true` if the source was parsed without any errors.
+///
+/// # Safety
+///
+/// `result` must be a live pointer returned by `nix_parse`.
+#[no_mangle]
+pub unsafe extern "C" fn nix_parse_result_ok(result: *const NixParseResult) -> bool {
+    let result = &*result;
+    result.ast.is_some() && result.errors.is_empty()
+}
+
+/// Returns the number of syntax errors reported while parsing.
+///
+/// # Safety
+///
+/// `result` must be a live pointer returned by `nix_parse`.
+#[no_mangle]
+pub unsafe extern "C" fn nix_parse_result_error_count(result: *const NixParseResult) -> usize {
+    (*result).errors.iter().count()
+}
+
+/// Returns the syntax errors as a JSON array, to be released with `nix_string_free`.
+///
+/// # Safety
+///
+/// `result` must be a live pointer returned by `nix_parse`.
+#[no_mangle]
+pub unsafe extern "C" fn nix_parse_result_diagnostics_json(
+    result: *const NixParseResult,
+) -> *mut c_char {
+    let result = &*result;
+    guard(|| to_c_string(json::diagnostics(&result.source, &result.errors).to_string()))
+        .unwrap_or(ptr::null_mut())
+}
+
+/// Returns the syntax tree as a JSON object, to be released with `nix_string_free`.
+///
+/// Returns null if no syntax tree could be recovered from the source.
+///
+/// # Safety
+///
+/// `result` must be a live pointer returned by `nix_parse`.
+#[no_mangle]
+pub unsafe extern "C" fn nix_parse_result_ast_json(result: *const NixParseResult) -> *mut c_char {
+    let result = &*result;
+    guard(|| {
+        let ast = result.ast.as_ref()?;
+        to_c_string(json::ast(ast).to_string())
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Releases a parse result. Passing null is a no-op.
+///
+/// # Safety
+///
+/// `result` must be null or a pointer returned by `nix_parse` which has not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn nix_parse_result_free(result: *mut NixParseResult) {
+    if !result.is_null() {
+        drop(Box::from_raw(result));
+    }
+}
+
+/// Releases a string returned by this library. Passing null is a no-op.
+///
+/// # Safety
+///
+/// `string` must be null or a pointer returned by this library which has not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn nix_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// Runs `f`, turning a panic into `None` so it does not unwind across the C boundary.
+fn guard<T, F: FnOnce() -> Option<T>>(f: F) -> Option<T> {
+    panic::catch_unwind(AssertUnwindSafe(f)).ok().flatten()
+}
+
+fn to_c_string(string: String) -> Option<*mut c_char> {
+    CString::new(string).ok().map(CString::into_raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CStr;
+
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    unsafe fn take_json(string: *mut c_char) -> Value {
+        assert!(!string.is_null());
+        let value = serde_json::from_slice(CStr::from_ptr(string).to_bytes()).unwrap();
+        nix_string_free(string);
+        value
+    }
+
+    #[test]
+    fn round_trips_through_the_c_abi() {
+        let source = "{ foo = 1; }";
+        unsafe {
+            let result = nix_parse(source.as_ptr() as *const c_char, source.len());
+            assert!(nix_parse_result_ok(result));
+            assert_eq!(nix_parse_result_error_count(result), 0);
+
+            let ast = take_json(nix_parse_result_ast_json(result));
+            assert_eq!(ast["kind"], "set");
+            assert_eq!(ast["children"][0]["kind"], "literal");
+            assert_eq!(ast["children"][0]["text"], "1");
+            assert_eq!(
+                take_json(nix_parse_result_diagnostics_json(result)),
+                json!([])
+            );
+
+            nix_parse_result_free(result);
+        }
+    }
+
+    #[test]
+    fn reports_diagnostics_and_rejects_invalid_utf8() {
+        let source = "{ foo = 1 }";
+        unsafe {
+            let result = nix_parse(source.as_ptr() as *const c_char, source.len());
+            assert!(!nix_parse_result_ok(result));
+            assert!(nix_parse_result_error_count(result) > 0);
+
+            let diagnostics = take_json(nix_parse_result_diagnostics_json(result));
+            assert_eq!(diagnostics[0]["severity"], "error");
+            nix_parse_result_free(result);
+
+            let invalid = [0xffu8, 0xfe];
+            assert!(nix_parse(invalid.as_ptr() as *const c_char, invalid.len()).is_null());
+            nix_parse_result_free(ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,231 @@
+//! Derive macros for the span traits of `nix-parser`.
+//!
+//! The generated impls refer to the traits through `crate::`, so they are only meant to be used
+//! within `nix-parser` itself:
+//!
+//! * `HasSpan` and `ToSpan` return the span of a node. For structs and each variant of an enum,
+//!   the span is the field marked `#[span]`, the field named `span`, the only field of type
+//!   `Span`, or failing all those the only field, whose own span is returned.
+//! * `SpansMut` visits the spans of a node and all of its children, which is every field not
+//!   marked `#[span(skip)]`.
+
+#![forbid(unsafe_code)]
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, Attribute, Data, DeriveInput, Error, Field, Fields, Ident, Meta, NestedMeta,
+    Result, Type,
+};
+
+#[proc_macro_derive(HasSpan, attributes(span))]
+pub fn derive_has_span(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let method = quote!(fn span(&self) -> ::codespan::Span);
+    expand_accessor(&input, quote!(crate::HasSpan), method, quote!(span))
+        .unwrap_or_else(|error| error.to_compile_error())
+        .into()
+}
+
+#[proc_macro_derive(ToSpan, attributes(span))]
+pub fn derive_to_span(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let method = quote!(fn to_span(&self) -> ::codespan::Span);
+    expand_accessor(&input, quote!(crate::ToSpan), method, quote!(to_span))
+        .unwrap_or_else(|error| error.to_compile_error())
+        .into()
+}
+
+#[proc_macro_derive(SpansMut, attributes(span))]
+pub fn derive_spans_mut(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_spans_mut(&input)
+        .unwrap_or_else(|error| error.to_compile_error())
+        .into()
+}
+
+/// How a field is treated, as given by its `#[span]` attribute.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Role {
+    Child,
+    Span,
+    Skip,
+}
+
+fn expand_accessor(
+    input: &DeriveInput,
+    trait_path: TokenStream2,
+    signature: TokenStream2,
+    method: TokenStream2,
+) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let arms = arms(input, |fields, bindings| {
+        let index = span_field(fields)?;
+        let binding = &bindings[index];
+        Ok(quote!(#binding.#method()))
+    })?;
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #trait_path for #name #ty_generics #where_clause {
+            #signature {
+                use #trait_path as _;
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+    })
+}
+
+fn expand_spans_mut(input: &DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let arms = arms(input, |fields, bindings| {
+        let mut visits = Vec::new();
+        for (field, binding) in fields.iter().zip(bindings) {
+            if role(field)? != Role::Skip {
+                visits.push(quote!(#binding.for_each_span_mut(f);));
+            }
+        }
+        Ok(quote!({ #(#visits)* }))
+    })?;
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics crate::ast::edit::SpansMut for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn for_each_span_mut(&mut self, f: &mut dyn FnMut(&mut ::codespan::Span)) {
+                use crate::ast::edit::SpansMut as _;
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+    })
+}
+
+/// Returns a match arm for the struct or each variant of the enum, binding every field and
+/// evaluating the body built by `body`.
+fn arms<F>(input: &DeriveInput, mut body: F) -> Result<Vec<TokenStream2>>
+where
+    F: FnMut(&[&Field], &[Ident]) -> Result<TokenStream2>,
+{
+    let mut arms = Vec::new();
+    match input.data {
+        Data::Struct(ref data) => {
+            let (pattern, fields, bindings) = pattern(quote!(Self), &data.fields);
+            let body = body(&fields, &bindings)?;
+            arms.push(quote!(#pattern => #body,));
+        }
+        Data::Enum(ref data) => {
+            for variant in &data.variants {
+                let name = &variant.ident;
+                let (pattern, fields, bindings) = pattern(quote!(Self::#name), &variant.fields);
+                let body = body(&fields, &bindings)?;
+                arms.push(quote!(#pattern => #body,));
+            }
+        }
+        Data::Union(_) => {
+            let message = "span traits cannot be derived for unions";
+            return Err(Error::new(input.ident.span(), message));
+        }
+    }
+    Ok(arms)
+}
+
+fn pattern(path: TokenStream2, fields: &Fields) -> (TokenStream2, Vec<&Field>, Vec<Ident>) {
+    let fields: Vec<_> = fields.iter().collect();
+    let bindings: Vec<_> = fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| match field.ident {
+            Some(ref ident) => format_ident!("__{}", ident),
+            None => format_ident!("__field{}", i),
+        })
+        .collect();
+
+    let pattern = match fields.first().map(|field| field.ident.is_some()) {
+        None => quote!(#path { .. }),
+        Some(true) => {
+            let names = fields.iter().map(|field| &field.ident);
+            quote!(#path { #(#names: #bindings),* })
+        }
+        Some(false) => quote!(#path(#(#bindings),*)),
+    };
+    (pattern, fields, bindings)
+}
+
+/// Returns the index of the field holding the span of the node made up of `fields`.
+fn span_field(fields: &[&Field]) -> Result<usize> {
+    let mut marked = None;
+    for (i, field) in fields.iter().enumerate() {
+        if role(field)? == Role::Span {
+            marked = Some(i);
+        }
+    }
+
+    let named = || {
+        fields.iter().position(|field| match field.ident {
+            Some(ref ident) => ident == "span",
+            None => false,
+        })
+    };
+    let typed = || {
+        let spans: Vec<_> = (0..fields.len())
+            .filter(|&i| is_span(&fields[i].ty))
+            .collect();
+        match spans[..] {
+            [i] => Some(i),
+            _ => None,
+        }
+    };
+    let only = || match fields.len() {
+        1 => Some(0),
+        _ => None,
+    };
+
+    marked
+        .or_else(named)
+        .or_else(typed)
+        .or_else(only)
+        .ok_or_else(|| {
+            let message = "cannot find the span of this node, mark the field holding it `#[span]`";
+            let span = fields.first().map_or_else(Span::call_site, |field| {
+                field
+                    .ident
+                    .as_ref()
+                    .map_or_else(Span::call_site, Ident::span)
+            });
+            Error::new(span, message)
+        })
+}
+
+fn role(field: &Field) -> Result<Role> {
+    let mut role = Role::Child;
+    for attr in field.attrs.iter().filter(|attr| attr.path.is_ident("span")) {
+        role = match attr.parse_meta()? {
+            Meta::Path(_) => Role::Span,
+            Meta::List(ref list) => match list.nested.iter().collect::<Vec<_>>()[..] {
+                [NestedMeta::Meta(Meta::Path(ref path))] if path.is_ident("skip") => Role::Skip,
+                _ => return Err(invalid(attr)),
+            },
+            Meta::NameValue(_) => return Err(invalid(attr)),
+        };
+    }
+    Ok(role)
+}
+
+fn invalid(attr: &Attribute) -> Error {
+    let message = "expected `#[span]` or `#[span(skip)]`";
+    Error::new_spanned(attr, message)
+}
+
+fn is_span(ty: &Type) -> bool {
+    match *ty {
+        Type::Path(ref path) => path.qself.is_none() && path.path.is_ident("Span"),
+        _ => false,
+    }
+}
@@ -0,0 +1,297 @@
+//! Python bindings to `nix-parser`, built into the `nix_parser` wheel with
+//! `maturin build -m nix-parser-py/Cargo.toml`.
+//!
+//! ```python
+//! import nix_parser
+//!
+//! result = nix_parser.parse(open("default.nix").read())
+//! for diagnostic in result.diagnostics:
+//!     print(diagnostic.line, diagnostic.message)
+//!
+//! class Idents:
+//!     def visit_ident(self, node):
+//!         print(node.text, node.span.start)
+//!
+//! result.walk(Idents())
+//! ```
+
+#![forbid(unsafe_code)]
+
+use nix_parser::ast::node::Node as AstNode;
+use nix_parser::error::Errors;
+use nix_parser::parser::parse_source_file_partial;
+use pyo3::prelude::*;
+
+/// A range of byte offsets into the source text.
+#[pyclass(module = "nix_parser", frozen, eq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    #[pyo3(get)]
+    start: usize,
+    #[pyo3(get)]
+    end: usize,
+}
+
+#[pymethods]
+impl Span {
+    fn __repr__(&self) -> String {
+        format!("Span({}, {})", self.start, self.end)
+    }
+
+    fn __len__(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+/// A syntax error reported while parsing.
+#[pyclass(module = "nix_parser", frozen)]
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    #[pyo3(get)]
+    severity: &'static str,
+    #[pyo3(get)]
+    message: String,
+    #[pyo3(get)]
+    span: Span,
+    /// Zero-based line of the start of the span.
+    #[pyo3(get)]
+    line: usize,
+    /// Zero-based column of the start of the span.
+    #[pyo3(get)]
+    column: usize,
+}
+
+#[pymethods]
+impl Diagnostic {
+    fn __repr__(&self) -> String {
+        format!(
+            "Diagnostic({}:{}: {}: {})",
+            self.line + 1,
+            self.column + 1,
+            self.severity,
+            self.message
+        )
+    }
+}
+
+/// An expression in the syntax tree.
+#[pyclass(module = "nix_parser", frozen)]
+#[derive(Debug)]
+pub struct Node {
+    /// The kind of expression, e.g. `"set"`, `"ident"` or `"fn_app"`.
+    #[pyo3(get)]
+    kind: &'static str,
+    #[pyo3(get)]
+    span: Span,
+    /// The source text of identifiers and literals.
+    #[pyo3(get)]
+    text: Option<String>,
+    children: Vec<Py<Node>>,
+}
+
+#[pymethods]
+impl Node {
+    #[getter]
+    fn children(&self, py: Python<'_>) -> Vec<Py<Node>> {
+        self.children.iter().map(|c| c.clone_ref(py)).collect()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Node({}, {}..{})",
+            self.kind, self.span.start, self.span.end
+        )
+    }
+}
+
+/// The result of parsing a source file.
+#[pyclass(module = "nix_parser", frozen)]
+#[derive(Debug)]
+pub struct ParseResult {
+    root: Option<Py<Node>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+#[pymethods]
+impl ParseResult {
+    /// `True` if the source parsed without any errors.
+    #[getter]
+    fn ok(&self) -> bool {
+        self.root.is_some() && self.diagnostics.is_empty()
+    }
+
+    /// The root expression, or `None` if no syntax tree could be recovered.
+    #[getter]
+    fn root(&self, py: Python<'_>) -> Option<Py<Node>> {
+        self.root.as_ref().map(|root| root.clone_ref(py))
+    }
+
+    #[getter]
+    fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.clone()
+    }
+
+    /// Walks the syntax tree in source order, calling `visitor.visit_<kind>(node)` for every node
+    /// or `visitor.visit(node)` if there is no method for its kind. Returning `False` from a visit
+    /// method skips the children of that node.
+    fn walk(&self, py: Python<'_>, visitor: &Bound<'_, PyAny>) -> PyResult<()> {
+        match self.root {
+            Some(ref root) => walk(py, root, visitor),
+            None => Ok(()),
+        }
+    }
+}
+
+fn walk(py: Python<'_>, node: &Py<Node>, visitor: &Bound<'_, PyAny>) -> PyResult<()> {
+    let inner = node.get();
+    let method = format!("visit_{}", inner.kind);
+    let result = if visitor.hasattr(method.as_str())? {
+        Some(visitor.call_method1(method.as_str(), (node.clone_ref(py),))?)
+    } else if visitor.hasattr("visit")? {
+        Some(visitor.call_method1("visit", (node.clone_ref(py),))?)
+    } else {
+        None
+    };
+
+    let descend = match result {
+        Some(value) if !value.is_none() => value.is_truthy()?,
+        _ => true,
+    };
+
+    if descend {
+        for child in &inner.children {
+            walk(py, child, visitor)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses a Nix source file, recovering from syntax errors where possible.
+#[pyfunction]
+fn parse(py: Python<'_>, source: &str) -> PyResult<ParseResult> {
+    let (ast, errors) = match parse_source_file_partial(source) {
+        Ok(partial) => {
+            let errors = partial.errors().unwrap_or_default();
+            (partial.value().cloned(), errors)
+        }
+        Err(errors) => (None, errors),
+    };
+
+    let root = match ast {
+        Some(ref ast) => Some(to_node(py, AstNode::new(ast.expr()))?),
+        None => None,
+    };
+
+    Ok(ParseResult {
+        root,
+        diagnostics: to_diagnostics(source, &errors),
+    })
+}
+
+fn to_node(py: Python<'_>, node: AstNode) -> PyResult<Py<Node>> {
+    let children = node
+        .children
+        .into_iter()
+        .map(|child| to_node(py, child))
+        .collect::<PyResult<_>>()?;
+
+    let node = Node {
+        kind: node.kind,
+        span: Span {
+            start: node.start,
+            end: node.end,
+        },
+        text: node.text,
+        children,
+    };
+    Py::new(py, node)
+}
+
+fn to_diagnostics(source: &str, errors: &Errors) -> Vec<Diagnostic> {
+    errors
+        .to_located(source)
+        .into_iter()
+        .map(|diag| Diagnostic {
+            severity: diag.severity,
+            message: diag.message,
+            span: Span {
+                start: diag.start,
+                end: diag.end,
+            },
+            line: diag.line,
+            column: diag.column,
+        })
+        .collect()
+}
+
+#[pymodule]
+#[pyo3(name = "nix_parser")]
+fn init(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(parse, m)?)?;
+    m.add_class::<ParseResult>()?;
+    m.add_class::<Node>()?;
+    m.add_class::<Diagnostic>()?;
+    m.add_class::<Span>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use pyo3::types::PyDict;
+
+    use super::*;
+
+    fn run(script: &str) -> PyResult<()> {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "nix_parser")?;
+            init(&module)?;
+            let globals = PyDict::new(py);
+            globals.set_item("nix_parser", module)?;
+            let script = CString::new(script).unwrap();
+            py.run(&script, Some(&globals), None)
+        })
+    }
+
+    #[test]
+    fn exposes_tree_and_diagnostics() {
+        run(r#"
+result = nix_parser.parse("{ foo = bar; }")
+assert result.ok
+assert result.root.kind == "set"
+[child] = result.root.children
+assert (child.kind, child.text) == ("ident", "bar")
+assert (child.span.start, child.span.end) == (8, 11)
+
+result = nix_parser.parse("{ foo = 1 }")
+assert not result.ok
+assert result.diagnostics[0].severity == "error"
+assert result.diagnostics[0].line == 0
+"#)
+        .unwrap();
+    }
+
+    #[test]
+    fn walks_with_visitor() {
+        run(r#"
+class Visitor:
+    def __init__(self):
+        self.seen = []
+
+    def visit_ident(self, node):
+        self.seen.append(node.text)
+
+    def visit(self, node):
+        self.seen.append(node.kind)
+        return node.kind != "list"
+
+visitor = Visitor()
+nix_parser.parse("{ a = x; b = [ y ]; }").walk(visitor)
+assert visitor.seen == ["set", "x", "list"], visitor.seen
+"#)
+        .unwrap();
+    }
+}
@@ -0,0 +1,147 @@
+//! JavaScript bindings to `nix-parser`, for use in browsers and web extensions.
+//!
+//! Build with `wasm-pack build nix-parser-wasm` or
+//! `cargo build -p nix-parser-wasm --target wasm32-unknown-unknown`, then run `wasm-bindgen` on
+//! the resulting module.
+//!
+//! Diagnostics are returned as JSON arrays of objects with the following shape:
+//!
+//! ```json
+//! { "severity": "error", "message": "...", "start": 4, "end": 5,
+//!   "line": 0, "column": 4, "labels": ["..."], "notes": ["..."] }
+//! ```
+//!
+//! `start` and `end` are byte offsets into the UTF-8 encoded source, while `line` and `column`
+//! are zero-based. Problems which are not tied to a place in the source, such as comments the
+//! formatter cannot keep, span the whole source.
+//!
+//! Syntax trees are returned as nested JSON objects, with the source text of identifiers and
+//! literals:
+//!
+//! ```json
+//! { "kind": "set", "start": 0, "end": 12, "children": [
+//!   { "kind": "literal", "start": 8, "end": 9, "text": "1", "children": [] } ] }
+//! ```
+
+#![forbid(unsafe_code)]
+
+use nix_parser::ast::node::Node;
+use nix_parser::error::LocatedDiagnostic as Diagnostic;
+use nix_parser::fmt::{self, FormatError, Options};
+use nix_parser::parser::parse_source_file_partial;
+use wasm_bindgen::prelude::*;
+
+/// The result of parsing a source file.
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct Parsed {
+    ast: Option<Node>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+#[wasm_bindgen]
+impl Parsed {
+    /// Returns `true` if the source parsed without errors.
+    #[wasm_bindgen(getter)]
+    pub fn ok(&self) -> bool {
+        self.ast.is_some() && self.diagnostics.is_empty()
+    }
+
+    /// Returns the syntax tree as JSON, if any could be recovered.
+    #[wasm_bindgen(getter)]
+    pub fn ast(&self) -> Option<String> {
+        self.ast
+            .as_ref()
+            .map(|ast| serde_json::to_string(ast).expect("nodes are always serializable"))
+    }
+
+    /// Returns the diagnostics reported while parsing, as a JSON array.
+    #[wasm_bindgen(getter)]
+    pub fn diagnostics(&self) -> String {
+        to_json(&self.diagnostics)
+    }
+}
+
+/// Parses a Nix source file, recovering from errors where possible.
+#[wasm_bindgen]
+pub fn parse(source: &str) -> Parsed {
+    let (ast, errors) = match parse_source_file_partial(source) {
+        Ok(partial) => {
+            let errors = partial.errors().unwrap_or_default();
+            (partial.value().map(|ast| Node::new(ast.expr())), errors)
+        }
+        Err(errors) => (None, errors),
+    };
+
+    Parsed {
+        ast,
+        diagnostics: errors.to_located(source),
+    }
+}
+
+/// Returns the diagnostics for a Nix source file as a JSON array.
+#[wasm_bindgen]
+pub fn diagnostics(source: &str) -> String {
+    parse(source).diagnostics()
+}
+
+/// Formats a Nix source file in the canonical style of `nix_parser::fmt`.
+///
+/// Throws the diagnostics as a JSON array if the source cannot be formatted.
+#[wasm_bindgen]
+pub fn format(source: &str) -> Result<String, JsValue> {
+    format_source(source).map_err(|diagnostics| JsValue::from_str(&to_json(&diagnostics)))
+}
+
+fn format_source(source: &str) -> Result<String, Vec<Diagnostic>> {
+    fmt::format(source, &Options::default()).map_err(|err| match err {
+        FormatError::Syntax(ref errors) => errors.to_located(source),
+        err => vec![Diagnostic {
+            severity: "error",
+            message: err.to_string(),
+            start: 0,
+            end: source.len(),
+            line: 0,
+            column: 0,
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }],
+    })
+}
+
+fn to_json(diagnostics: &[Diagnostic]) -> String {
+    serde_json::to_string(diagnostics).expect("diagnostics are always serializable")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_formats_valid_source() {
+        let parsed = parse("{ foo = 1; }");
+        assert!(parsed.ok());
+        assert_eq!(parsed.diagnostics(), "[]");
+        assert_eq!(format_source("{foo=1;}").unwrap(), "{ foo = 1; }\n");
+
+        let ast: serde_json::Value = serde_json::from_str(&parsed.ast().unwrap()).unwrap();
+        assert_eq!(ast["kind"], "set");
+        assert_eq!(ast["children"][0]["text"], "1");
+    }
+
+    #[test]
+    fn reports_diagnostics_for_invalid_source() {
+        let parsed = parse("{ foo = 1 }");
+        assert!(!parsed.ok());
+
+        let diagnostics = format_source("{ foo = 1 }").unwrap_err();
+        assert!(!diagnostics.is_empty());
+        assert_eq!(diagnostics[0].severity, "error");
+        assert_eq!(diagnostics[0].line, 0);
+        assert!(diagnostics[0].start <= diagnostics[0].end);
+
+        let diagnostics = format_source("{ a = /* kept */ 1; }").unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, FormatError::Comments.to_string());
+    }
+}
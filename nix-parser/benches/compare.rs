@@ -0,0 +1,357 @@
+//! Compares this parser against rnix and `nix-instantiate --parse` on a corpus of Nix files.
+//!
+//! ```text
+//! cargo bench -p nix-parser --bench compare -- [FILE_OR_DIR]... [--iterations N]
+//! ```
+//!
+//! Without arguments, `example.nix` is used as the corpus. For every parser the harness reports
+//! the median parse time, the bytes allocated while parsing, and how well it recovers from syntax
+//! errors. Recovery is measured by deleting single tokens from every corpus file and checking
+//! whether the parser still produces a tree and how close its first error lands to the damage.
+//! `nix-instantiate` is skipped when it cannot be found on the `PATH`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use std::{env, fs};
+
+use codespan::Files;
+use nix_parser::parser::parse_source_file_partial;
+
+const EXAMPLE_FILE_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/example.nix");
+
+/// Deleting these tokens is the most common way of breaking a Nix file while editing it.
+const DAMAGE: &[&str] = &[";", "}", "=", "in"];
+
+/// Counts the bytes allocated by the process, so allocations made while parsing can be measured.
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size.saturating_sub(layout.size()), Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// The outcome of parsing a single source text.
+#[derive(Clone, Copy, Debug, Default)]
+struct Outcome {
+    /// Whether a syntax tree was produced, possibly containing error nodes.
+    tree: bool,
+    errors: usize,
+    /// Byte offset of the first reported error, if the parser reports locations.
+    first_error: Option<usize>,
+}
+
+trait Parser {
+    fn name(&self) -> &'static str;
+
+    fn parse(&self, source: &str) -> Outcome;
+
+    /// Whether allocations made by this parser happen inside this process.
+    fn in_process(&self) -> bool {
+        true
+    }
+}
+
+struct NixParser;
+
+impl Parser for NixParser {
+    fn name(&self) -> &'static str {
+        "nix-parser"
+    }
+
+    fn parse(&self, source: &str) -> Outcome {
+        let (tree, errors) = match parse_source_file_partial(source) {
+            Ok(partial) => (
+                partial.value().is_some(),
+                partial.errors().unwrap_or_default(),
+            ),
+            Err(errors) => (false, errors),
+        };
+
+        if errors.is_empty() {
+            return Outcome {
+                tree,
+                ..Outcome::default()
+            };
+        }
+
+        let mut files = Files::new();
+        let id = files.add("corpus", source);
+        let diagnostics = errors.to_diagnostics(id);
+        Outcome {
+            tree,
+            errors: diagnostics.len(),
+            first_error: diagnostics
+                .iter()
+                .map(|d| d.primary_label.span.start().to_usize())
+                .min(),
+        }
+    }
+}
+
+struct Rnix;
+
+impl Parser for Rnix {
+    fn name(&self) -> &'static str {
+        "rnix"
+    }
+
+    fn parse(&self, source: &str) -> Outcome {
+        let ast = rnix::parse(source);
+        let errors = ast.errors();
+        Outcome {
+            tree: true,
+            errors: errors.len(),
+            first_error: errors.iter().filter_map(rnix_error_offset).min(),
+        }
+    }
+}
+
+fn rnix_error_offset(error: &rnix::parser::ParseError) -> Option<usize> {
+    use rnix::parser::ParseError::*;
+    let range = match *error {
+        Unexpected(range) | UnexpectedExtra(range) | UnexpectedWanted(_, range, _) => range,
+        UnexpectedDoubleBind(range) | DuplicatedArgs(range, _) => range,
+        _ => return None,
+    };
+    Some(usize::from(range.start()))
+}
+
+struct NixInstantiate;
+
+impl NixInstantiate {
+    fn available() -> bool {
+        Command::new("nix-instantiate")
+            .arg("--version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false)
+    }
+}
+
+impl Parser for NixInstantiate {
+    fn name(&self) -> &'static str {
+        "nix-instantiate"
+    }
+
+    fn parse(&self, source: &str) -> Outcome {
+        let mut child = Command::new("nix-instantiate")
+            .args(["--parse", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to spawn nix-instantiate");
+
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        stdin.write_all(source.as_bytes()).ok();
+        drop(stdin);
+
+        let ok = child.wait().map(|s| s.success()).unwrap_or(false);
+        Outcome {
+            tree: ok,
+            errors: if ok { 0 } else { 1 },
+            first_error: None,
+        }
+    }
+
+    fn in_process(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Default)]
+struct Summary {
+    time: Duration,
+    allocated: usize,
+    errors_on_valid: usize,
+    damaged: usize,
+    trees: usize,
+    errors: usize,
+    distances: Vec<usize>,
+}
+
+fn main() {
+    let mut paths = Vec::new();
+    let mut iterations = 10;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--iterations" => {
+                let n = args.next().expect("--iterations needs a value");
+                iterations = n.parse().expect("--iterations must be a number");
+            }
+            // Passed along by `cargo bench`.
+            "--bench" => {}
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    if paths.is_empty() {
+        paths.push(PathBuf::from(EXAMPLE_FILE_PATH));
+    }
+
+    let mut corpus = Vec::new();
+    for path in &paths {
+        collect(path, &mut corpus);
+    }
+
+    let mut parsers: Vec<Box<dyn Parser>> = vec![Box::new(NixParser), Box::new(Rnix)];
+    if NixInstantiate::available() {
+        parsers.push(Box::new(NixInstantiate));
+    } else {
+        eprintln!("nix-instantiate not found, skipping");
+    }
+
+    let bytes: usize = corpus.iter().map(|(_, text)| text.len()).sum();
+    println!("corpus: {} files, {} bytes", corpus.len(), bytes);
+    println!();
+    println!(
+        "{:<16} {:>12} {:>14} {:>8} {:>10} {:>8} {:>12}",
+        "parser", "time", "allocated", "false+", "trees", "errors", "distance"
+    );
+
+    let mut baseline: Option<Summary> = None;
+    for parser in &parsers {
+        let summary = measure(parser.as_ref(), &corpus, iterations);
+        report(parser.as_ref(), &summary, baseline.as_ref());
+        baseline.get_or_insert(summary);
+    }
+}
+
+fn collect(path: &Path, corpus: &mut Vec<(PathBuf, String)>) {
+    if path.is_dir() {
+        let mut entries: Vec<_> = fs::read_dir(path)
+            .expect("failed to read corpus directory")
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .collect();
+        entries.sort();
+        for entry in entries {
+            collect(&entry, corpus);
+        }
+    } else if path.extension().is_some_and(|ext| ext == "nix") {
+        match fs::read_to_string(path) {
+            Ok(text) => corpus.push((path.to_owned(), text)),
+            Err(e) => eprintln!("skipping {}: {}", path.display(), e),
+        }
+    }
+}
+
+fn measure(parser: &dyn Parser, corpus: &[(PathBuf, String)], iterations: usize) -> Summary {
+    let mut summary = Summary::default();
+
+    for (_, text) in corpus {
+        let mut times = Vec::with_capacity(iterations);
+        for _ in 0..iterations.max(1) {
+            let start = Instant::now();
+            parser.parse(text);
+            times.push(start.elapsed());
+        }
+        times.sort();
+        summary.time += times[times.len() / 2];
+
+        let before = ALLOCATED.load(Ordering::Relaxed);
+        let outcome = parser.parse(text);
+        summary.allocated += ALLOCATED.load(Ordering::Relaxed) - before;
+        summary.errors_on_valid += outcome.errors;
+
+        for (offset, len) in damage_sites(text) {
+            let damaged = format!("{}{}", &text[..offset], &text[offset + len..]);
+            let outcome = parser.parse(&damaged);
+            summary.damaged += 1;
+            summary.trees += outcome.tree as usize;
+            summary.errors += outcome.errors;
+            if let Some(first) = outcome.first_error {
+                summary
+                    .distances
+                    .push(first.max(offset) - first.min(offset));
+            }
+        }
+    }
+
+    summary
+}
+
+/// Returns the byte ranges of the tokens to delete when measuring error recovery.
+///
+/// At most one site is chosen per kind of token, in the middle of the file, so every parser sees
+/// the same damage.
+fn damage_sites(text: &str) -> Vec<(usize, usize)> {
+    DAMAGE
+        .iter()
+        .filter_map(|token| {
+            let sites: Vec<_> = text
+                .match_indices(token)
+                .filter(|&(i, _)| is_token_boundary(text, i, token.len()))
+                .map(|(i, _)| i)
+                .collect();
+            sites.get(sites.len() / 2).map(|&i| (i, token.len()))
+        })
+        .collect()
+}
+
+fn is_token_boundary(text: &str, start: usize, len: usize) -> bool {
+    let word = |c: char| c.is_alphanumeric() || c == '_' || c == '-' || c == '\'';
+    let before = text[..start].chars().next_back().is_none_or(|c| !word(c));
+    let after = text[start + len..].chars().next().is_none_or(|c| !word(c));
+    before && after
+}
+
+fn report(parser: &dyn Parser, summary: &Summary, baseline: Option<&Summary>) {
+    let allocated = if parser.in_process() {
+        format!("{} KiB", summary.allocated / 1024)
+    } else {
+        "n/a".to_string()
+    };
+
+    let distance = if summary.distances.is_empty() {
+        "n/a".to_string()
+    } else {
+        let mut distances = summary.distances.clone();
+        distances.sort_unstable();
+        format!("{} B", distances[distances.len() / 2])
+    };
+
+    println!(
+        "{:<16} {:>12} {:>14} {:>8} {:>10} {:>8} {:>12}",
+        parser.name(),
+        format!("{:.3} ms", summary.time.as_secs_f64() * 1000.0),
+        allocated,
+        summary.errors_on_valid,
+        format!("{}/{}", summary.trees, summary.damaged),
+        summary.errors,
+        distance,
+    );
+
+    if let Some(base) = baseline {
+        let ratio = |a: f64, b: f64| if b > 0.0 { a / b } else { f64::NAN };
+        let time = ratio(summary.time.as_secs_f64(), base.time.as_secs_f64());
+        print!("{:<16} {:>12}", "", format!("x{:.2}", time));
+        if parser.in_process() {
+            let mem = ratio(summary.allocated as f64, base.allocated as f64);
+            print!(" {:>14}", format!("x{:.2}", mem));
+        }
+        println!();
+    }
+}
@@ -33,7 +33,7 @@ fn full(expr: &str) {
 }
 
 fn partial(expr: &str) {
-    let partial = parse_source_file_partial(&expr).unwrap_or_else(|e| {
+    let partial = parse_source_file_partial(expr).unwrap_or_else(|e| {
         print_diagnostics(expr, e);
         process::exit(1);
     });
@@ -1,16 +1,36 @@
+use std::borrow::Cow;
 use std::fmt::{Display, Formatter, Result as FmtResult};
 
 use codespan::Span;
+use nix_parser_derive::{HasSpan, SpansMut};
 
-use self::tokens::{Comment, Ident, Literal};
+use self::tokens::{Comment, Ident, Literal, StringKind};
 use crate::HasSpan;
 
+pub mod comments;
+pub(crate) mod edit;
+#[cfg(any(feature = "image", all(test, feature = "serde")))]
+pub mod image;
+pub mod node;
+pub mod paths;
+#[cfg(any(feature = "query", test))]
+pub mod query;
+pub mod rewrite;
+pub mod syntax;
 pub mod tokens;
+pub mod trivia;
+pub mod visit;
 
+#[cfg(any(feature = "json", test))]
+mod json;
 mod macros;
 
+#[cfg(any(feature = "json", test))]
+pub use self::json::{from_json_value, merge_json_value, to_json_value, NotData};
+
 /// A source file with a top-level doc comment.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SourceFile {
     comment: Option<Comment>,
     expr: Expr,
@@ -28,6 +48,10 @@ impl SourceFile {
     pub fn expr(&self) -> &Expr {
         &self.expr
     }
+
+    pub(crate) fn expr_mut(&mut self) -> &mut Expr {
+        &mut self.expr
+    }
 }
 
 impl Display for SourceFile {
@@ -48,7 +72,8 @@ impl HasSpan for SourceFile {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Expr {
     /// A parenthesized expression.
     ///
@@ -136,6 +161,36 @@ impl Display for Expr {
     }
 }
 
+impl Expr {
+    /// Returns the name of the kind of this expression in snake case, e.g. `"fn_app"`, as the
+    /// bindings to other languages report it.
+    pub fn kind_name(&self) -> &'static str {
+        match *self {
+            Expr::Paren(_) => "paren",
+            Expr::Ident(_) => "ident",
+            Expr::Interpolation(_) => "interpolation",
+            Expr::Literal(_) => "literal",
+            Expr::List(_) => "list",
+            Expr::String(_) => "string",
+            Expr::Set(_) => "set",
+            Expr::Unary(_) => "unary",
+            Expr::Binary(_) => "binary",
+            Expr::Let(_) => "let",
+            Expr::Rec(_) => "rec",
+            Expr::Proj(_) => "proj",
+            Expr::If(_) => "if",
+            Expr::Or(_) => "or",
+            Expr::Assert(_) => "assert",
+            Expr::With(_) => "with",
+            Expr::LetIn(_) => "let_in",
+            Expr::FnDecl(_) => "fn_decl",
+            Expr::FnApp(_) => "fn_app",
+            Expr::Error(_) => "error",
+            Expr::Trap(_) => "trap",
+        }
+    }
+}
+
 impl From<Ident> for Expr {
     fn from(ident: Ident) -> Self {
         Expr::Ident(ident)
@@ -148,40 +203,8 @@ impl From<Literal> for Expr {
     }
 }
 
-impl HasSpan for Expr {
-    fn span(&self) -> Span {
-        match *self {
-            Expr::Paren(ref e) => e.span(),
-            Expr::Ident(ref e) => e.span(),
-            Expr::Interpolation(ref e) => e.span(),
-            Expr::Literal(ref e) => e.span(),
-            Expr::List(ref e) => e.span(),
-            Expr::String(ref e) => e.span(),
-            Expr::Set(ref e) => e.span(),
-
-            Expr::Unary(ref e) => e.span(),
-            Expr::Binary(ref e) => e.span(),
-
-            Expr::Let(ref e) => e.span(),
-            Expr::Rec(ref e) => e.span(),
-            Expr::Proj(ref e) => e.span(),
-
-            Expr::If(ref e) => e.span(),
-            Expr::Or(ref e) => e.span(),
-            Expr::Assert(ref e) => e.span(),
-            Expr::With(ref e) => e.span(),
-
-            Expr::LetIn(ref e) => e.span(),
-            Expr::FnDecl(ref e) => e.span(),
-            Expr::FnApp(ref e) => e.span(),
-
-            Expr::Error(ref e) => *e,
-            Expr::Trap(ref e) => *e,
-        }
-    }
-}
-
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExprParen {
     expr: Expr,
     span: Span,
@@ -209,19 +232,14 @@ impl From<ExprParen> for Expr {
     }
 }
 
-impl HasSpan for ExprParen {
-    fn span(&self) -> Span {
-        self.span
-    }
-}
-
 impl PartialEq for ExprParen {
     fn eq(&self, other: &Self) -> bool {
         self.expr == other.expr
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExprInterpolation {
     inner: Expr,
     span: Span,
@@ -249,27 +267,25 @@ impl From<ExprInterpolation> for Expr {
     }
 }
 
-impl HasSpan for ExprInterpolation {
-    fn span(&self) -> Span {
-        self.span
-    }
-}
-
 impl PartialEq for ExprInterpolation {
     fn eq(&self, other: &Self) -> bool {
         self.inner == other.inner
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExprList {
-    elems: Vec<Expr>,
+    elems: Box<[Expr]>,
     span: Span,
 }
 
 impl ExprList {
     pub fn new(elems: Vec<Expr>, span: Span) -> Self {
-        ExprList { elems, span }
+        ExprList {
+            elems: elems.into_boxed_slice(),
+            span,
+        }
     }
 
     pub fn elems(&self) -> &[Expr] {
@@ -295,12 +311,6 @@ impl Display for ExprList {
     }
 }
 
-impl HasSpan for ExprList {
-    fn span(&self) -> Span {
-        self.span
-    }
-}
-
 impl From<ExprList> for Expr {
     fn from(e: ExprList) -> Self {
         Expr::List(e)
@@ -313,20 +323,29 @@ impl PartialEq for ExprList {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExprSet {
-    binds: Vec<Bind>,
+    binds: Box<[Bind]>,
     span: Span,
 }
 
 impl ExprSet {
     pub fn new(binds: Vec<Bind>, span: Span) -> Self {
-        ExprSet { binds, span }
+        ExprSet {
+            binds: binds.into_boxed_slice(),
+            span,
+        }
     }
 
     pub fn binds(&self) -> &[Bind] {
         &self.binds[..]
     }
+
+    /// Returns the value of the attribute at `path`, as described by [`lookup`].
+    pub fn get(&self, path: &[&str]) -> Option<&Expr> {
+        lookup(&self.binds, path)
+    }
 }
 
 impl Display for ExprSet {
@@ -347,12 +366,6 @@ impl Display for ExprSet {
     }
 }
 
-impl HasSpan for ExprSet {
-    fn span(&self) -> Span {
-        self.span
-    }
-}
-
 impl From<ExprSet> for Expr {
     fn from(e: ExprSet) -> Self {
         Expr::Set(e)
@@ -365,50 +378,137 @@ impl PartialEq for ExprSet {
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct ExprString(Vec<StringFragment>, Span);
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExprString(Box<[StringFragment]>, #[span(skip)] StringKind, Span);
 
 impl ExprString {
-    pub fn new(fragments: Vec<StringFragment>, span: Span) -> Self {
-        ExprString(fragments, span)
+    pub fn new(fragments: Vec<StringFragment>, kind: StringKind, span: Span) -> Self {
+        ExprString(fragments.into_boxed_slice(), kind, span)
+    }
+
+    pub fn fragments(&self) -> &[StringFragment] {
+        &self.0[..]
+    }
+
+    /// Returns the quotes this string was written with.
+    pub fn kind(&self) -> StringKind {
+        self.1
+    }
+
+    /// Returns the text of this string, unless it contains interpolations.
+    pub fn as_literal(&self) -> Option<Cow<'_, str>> {
+        match *self.fragments() {
+            [] => Some(Cow::Borrowed("")),
+            [StringFragment::Literal(ref text, _)] => Some(Cow::Borrowed(text)),
+            ref fragments => fragments
+                .iter()
+                .map(|fragment| match *fragment {
+                    StringFragment::Literal(ref text, _) => Some(text.as_str()),
+                    StringFragment::Interpolation(_) => None,
+                })
+                .collect::<Option<String>>()
+                .map(Cow::Owned),
+        }
     }
 }
 
 impl Display for ExprString {
     fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
-        write!(fmt, "\"")?;
+        // Indented strings start on a new line, so that none of their text is taken for
+        // indentation when parsed again.
+        let quote = match self.1 {
+            StringKind::Normal => "\"",
+            StringKind::Indented => "''",
+        };
+        fmt.write_str(quote)?;
+        if self.1 == StringKind::Indented {
+            fmt.write_str("\n")?;
+        }
 
-        // FIXME: Should record whether this string is a single or multi string so we can properly
-        // escape the string here.
-        for segment in &self.0 {
-            write!(fmt, "{}", segment)?;
+        for (i, segment) in self.0.iter().enumerate() {
+            match *segment {
+                StringFragment::Literal(ref text, _) if self.1 == StringKind::Normal => {
+                    write_escaped(fmt, text)?
+                }
+                StringFragment::Literal(ref text, _) => {
+                    let first = i == 0;
+                    let last = i + 1 == self.0.len();
+                    write_indented(fmt, text, first, last)?
+                }
+                StringFragment::Interpolation(ref expr) => write!(fmt, "{}", expr)?,
+            }
         }
 
-        write!(fmt, "\"")
+        fmt.write_str(quote)
     }
 }
 
-impl From<ExprString> for Expr {
-    fn from(e: ExprString) -> Self {
-        Expr::String(e)
+/// Writes `text` escaped for use inside a double-quoted string.
+fn write_escaped(fmt: &mut Formatter, text: &str) -> FmtResult {
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => fmt.write_str("\\\"")?,
+            '\\' => fmt.write_str("\\\\")?,
+            '\n' => fmt.write_str("\\n")?,
+            '\r' => fmt.write_str("\\r")?,
+            '\t' => fmt.write_str("\\t")?,
+            '$' if chars.peek() == Some(&'{') => fmt.write_str("\\$")?,
+            c => write!(fmt, "{}", c)?,
+        }
+    }
+    Ok(())
+}
+
+/// Writes `text` escaped for use inside an indented string, as fragment `first` or `last` of it.
+fn write_indented(fmt: &mut Formatter, text: &str, first: bool, last: bool) -> FmtResult {
+    let mut chars = text.chars().peekable();
+    if first {
+        // Leading whitespace would be skipped along with the line break after the quotes.
+        let escaped = match chars.peek() {
+            Some(' ') => Some("''\\ "),
+            Some('\n') => Some("''\\n"),
+            Some('\r') => Some("''\\r"),
+            Some('\t') => Some("''\\t"),
+            _ => None,
+        };
+        if let Some(escaped) = escaped {
+            fmt.write_str(escaped)?;
+            chars.next();
+        }
+    }
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' if chars.peek() == Some(&'\'') => {
+                chars.next();
+                fmt.write_str("'''")?;
+            }
+            '\'' if last && chars.peek().is_none() => fmt.write_str("''\\'")?,
+            '$' if chars.peek() == Some(&'{') => fmt.write_str("''$")?,
+            c => write!(fmt, "{}", c)?,
+        }
     }
+    Ok(())
 }
 
-impl HasSpan for ExprString {
-    fn span(&self) -> Span {
-        self.1
+impl From<ExprString> for Expr {
+    fn from(e: ExprString) -> Self {
+        Expr::String(e)
     }
 }
 
 impl PartialEq for ExprString {
     fn eq(&self, other: &Self) -> bool {
-        self.0 == other.0
+        self.0 == other.0 && self.1 == other.1
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum StringFragment {
-    Literal(String, Span),
+    Literal(#[span(skip)] String, Span),
     Interpolation(ExprInterpolation),
 }
 
@@ -421,15 +521,6 @@ impl Display for StringFragment {
     }
 }
 
-impl HasSpan for StringFragment {
-    fn span(&self) -> Span {
-        match *self {
-            StringFragment::Literal(_, ref span) => *span,
-            StringFragment::Interpolation(ref expr) => expr.span(),
-        }
-    }
-}
-
 impl PartialEq for StringFragment {
     fn eq(&self, other: &Self) -> bool {
         use StringFragment::*;
@@ -442,6 +533,7 @@ impl PartialEq for StringFragment {
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum UnaryOp {
     /// The unary `-` operator.
     Neg,
@@ -449,6 +541,27 @@ pub enum UnaryOp {
     Not,
 }
 
+impl UnaryOp {
+    /// Returns how tightly this operator binds, on the same scale as [`BinaryOp::precedence`].
+    ///
+    /// The operand of a prefix operator extends over all operators binding more tightly, so
+    /// `-a ? b` is `(-a) ? b` while `!a + b` is `!(a + b)`.
+    pub fn precedence(self) -> u8 {
+        match self {
+            UnaryOp::Neg => 12,
+            UnaryOp::Not => 7,
+        }
+    }
+
+    /// Returns a short description of this operator, e.g. for hover text.
+    pub fn description(self) -> &'static str {
+        match self {
+            UnaryOp::Neg => "arithmetic negation",
+            UnaryOp::Not => "logical negation",
+        }
+    }
+}
+
 impl Display for UnaryOp {
     fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
         match *self {
@@ -458,8 +571,10 @@ impl Display for UnaryOp {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExprUnary {
+    #[span(skip)]
     op: UnaryOp,
     expr: Expr,
     span: Span,
@@ -481,13 +596,12 @@ impl ExprUnary {
 
 impl Display for ExprUnary {
     fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
-        write!(fmt, "{}{}", self.op, self.expr)
-    }
-}
-
-impl HasSpan for ExprUnary {
-    fn span(&self) -> Span {
-        self.span
+        write!(fmt, "{}", self.op)?;
+        write_operand(
+            fmt,
+            &self.expr,
+            precedence(&self.expr) < self.op.precedence(),
+        )
     }
 }
 
@@ -504,6 +618,7 @@ impl PartialEq for ExprUnary {
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BinaryOp {
     /// The binary `+` operator.
     Add,
@@ -539,6 +654,144 @@ pub enum BinaryOp {
     Impl,
 }
 
+/// The way chains of a binary operator with the same precedence are grouped.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Assoc {
+    /// `a - b - c` is `(a - b) - c`.
+    Left,
+    /// `a // b // c` is `a // (b // c)`.
+    Right,
+    /// The operator cannot be chained without parentheses, e.g. `a == b == c` is an error.
+    None,
+}
+
+/// The precedence of function application, which binds more tightly than any operator.
+const APPLY_PRECEDENCE: u8 = 13;
+
+/// The precedence of `or` fallbacks, which bind more tightly than function application.
+const OR_PRECEDENCE: u8 = 14;
+
+/// The precedence of atoms and attribute selections, which never need parentheses.
+const ATOM_PRECEDENCE: u8 = 15;
+
+impl BinaryOp {
+    /// All binary operators, from the loosest to the tightest binding.
+    pub const ALL: &'static [BinaryOp] = &[
+        BinaryOp::Impl,
+        BinaryOp::Or,
+        BinaryOp::And,
+        BinaryOp::Eq,
+        BinaryOp::NotEq,
+        BinaryOp::LessThan,
+        BinaryOp::LessThanEq,
+        BinaryOp::GreaterThan,
+        BinaryOp::GreaterThanEq,
+        BinaryOp::Update,
+        BinaryOp::Add,
+        BinaryOp::Sub,
+        BinaryOp::Mul,
+        BinaryOp::Div,
+        BinaryOp::Concat,
+        BinaryOp::HasAttr,
+    ];
+
+    /// Returns how tightly this operator binds, where higher values bind more tightly.
+    ///
+    /// The order is that of the operator table in the Nix manual. Function application binds
+    /// more tightly than every operator.
+    pub fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Impl => 1,
+            BinaryOp::Or => 2,
+            BinaryOp::And => 3,
+            BinaryOp::Eq | BinaryOp::NotEq => 4,
+            BinaryOp::LessThan
+            | BinaryOp::LessThanEq
+            | BinaryOp::GreaterThan
+            | BinaryOp::GreaterThanEq => 5,
+            BinaryOp::Update => 6,
+            BinaryOp::Add | BinaryOp::Sub => 8,
+            BinaryOp::Mul | BinaryOp::Div => 9,
+            BinaryOp::Concat => 10,
+            BinaryOp::HasAttr => 11,
+        }
+    }
+
+    pub fn associativity(self) -> Assoc {
+        match self {
+            BinaryOp::Impl | BinaryOp::Update | BinaryOp::Concat => Assoc::Right,
+            BinaryOp::Eq
+            | BinaryOp::NotEq
+            | BinaryOp::LessThan
+            | BinaryOp::LessThanEq
+            | BinaryOp::GreaterThan
+            | BinaryOp::GreaterThanEq
+            | BinaryOp::HasAttr => Assoc::None,
+            BinaryOp::Or | BinaryOp::And | BinaryOp::Add | BinaryOp::Sub => Assoc::Left,
+            BinaryOp::Mul | BinaryOp::Div => Assoc::Left,
+        }
+    }
+
+    /// Returns the name of the function in `builtins` which computes the same result from the
+    /// same operands, if there is one.
+    pub fn builtin(self) -> Option<&'static str> {
+        match self {
+            BinaryOp::Add => Some("add"),
+            BinaryOp::Sub => Some("sub"),
+            BinaryOp::Mul => Some("mul"),
+            BinaryOp::Div => Some("div"),
+            BinaryOp::LessThan => Some("lessThan"),
+            _ => None,
+        }
+    }
+
+    /// Returns a short description of this operator, e.g. for hover text.
+    pub fn description(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "addition or string concatenation",
+            BinaryOp::Sub => "subtraction",
+            BinaryOp::Mul => "multiplication",
+            BinaryOp::Div => "division",
+            BinaryOp::Eq => "equality",
+            BinaryOp::NotEq => "inequality",
+            BinaryOp::LessThan => "less than",
+            BinaryOp::LessThanEq => "less than or equal",
+            BinaryOp::GreaterThan => "greater than",
+            BinaryOp::GreaterThanEq => "greater than or equal",
+            BinaryOp::And => "logical conjunction",
+            BinaryOp::Or => "logical disjunction",
+            BinaryOp::Concat => "list concatenation",
+            BinaryOp::Update => "attribute set update",
+            BinaryOp::HasAttr => "attribute presence test",
+            BinaryOp::Impl => "logical implication",
+        }
+    }
+}
+
+/// Returns how tightly the outermost construct of `expr` binds, on the scale of
+/// [`BinaryOp::precedence`]. Constructs extending as far to the right as possible, such as `if`
+/// and functions, have the lowest precedence.
+fn precedence(expr: &Expr) -> u8 {
+    match *expr {
+        Expr::Unary(ref unary) => unary.op.precedence(),
+        Expr::Binary(ref binary) => binary.op.precedence(),
+        Expr::FnApp(_) => APPLY_PRECEDENCE,
+        Expr::Or(_) => OR_PRECEDENCE,
+        Expr::If(_) | Expr::Assert(_) | Expr::With(_) | Expr::LetIn(_) | Expr::FnDecl(_) => 0,
+        Expr::Literal(Literal::Integer(i, _)) if i < 0 => UnaryOp::Neg.precedence(),
+        Expr::Literal(Literal::Float(f, _)) if f.is_sign_negative() => UnaryOp::Neg.precedence(),
+        _ => ATOM_PRECEDENCE,
+    }
+}
+
+fn write_operand(fmt: &mut Formatter, expr: &Expr, parens: bool) -> FmtResult {
+    if parens {
+        write!(fmt, "({})", expr)
+    } else {
+        write!(fmt, "{}", expr)
+    }
+}
+
 impl Display for BinaryOp {
     fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
         match *self {
@@ -562,8 +815,10 @@ impl Display for BinaryOp {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExprBinary {
+    #[span(skip)]
     op: BinaryOp,
     lhs: Expr,
     rhs: Expr,
@@ -590,13 +845,19 @@ impl ExprBinary {
 
 impl Display for ExprBinary {
     fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
-        write!(fmt, "{} {} {}", self.lhs, self.op, self.rhs)
-    }
-}
-
-impl HasSpan for ExprBinary {
-    fn span(&self) -> Span {
-        self.span
+        let (op, assoc) = (self.op.precedence(), self.op.associativity());
+        let (lhs, rhs) = (precedence(&self.lhs), precedence(&self.rhs));
+        write_operand(
+            fmt,
+            &self.lhs,
+            lhs < op || (lhs == op && assoc != Assoc::Left),
+        )?;
+        write!(fmt, " {} ", self.op)?;
+        write_operand(
+            fmt,
+            &self.rhs,
+            rhs < op || (rhs == op && assoc != Assoc::Right),
+        )
     }
 }
 
@@ -612,7 +873,8 @@ impl PartialEq for ExprBinary {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Bind {
     Simple(BindSimple),
     Inherit(BindInherit),
@@ -629,19 +891,10 @@ impl Display for Bind {
     }
 }
 
-impl HasSpan for Bind {
-    fn span(&self) -> Span {
-        match *self {
-            Bind::Simple(ref b) => b.span(),
-            Bind::Inherit(ref b) => b.span(),
-            Bind::InheritExpr(ref b) => b.span(),
-        }
-    }
-}
-
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BindSimple {
-    comment: Option<Comment>,
+    comment: Option<Box<Comment>>,
     attr: AttrPath,
     expr: Expr,
     span: Span,
@@ -650,7 +903,7 @@ pub struct BindSimple {
 impl BindSimple {
     pub fn new(comment: Option<Comment>, attr: AttrPath, expr: Expr, span: Span) -> Self {
         BindSimple {
-            comment,
+            comment: comment.map(Box::new),
             attr,
             expr,
             span,
@@ -658,7 +911,7 @@ impl BindSimple {
     }
 
     pub fn comment(&self) -> Option<&Comment> {
-        self.comment.as_ref()
+        self.comment.as_deref()
     }
 
     pub fn attr(&self) -> &AttrPath {
@@ -680,19 +933,14 @@ impl Display for BindSimple {
     }
 }
 
-impl HasSpan for BindSimple {
-    fn span(&self) -> Span {
-        self.span
-    }
-}
-
 impl PartialEq for BindSimple {
     fn eq(&self, other: &Self) -> bool {
         self.attr == other.attr && self.expr == other.expr && self.comment == other.comment
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BindInherit {
     names: Vec<Ident>,
     span: Span,
@@ -726,19 +974,14 @@ impl Display for BindInherit {
     }
 }
 
-impl HasSpan for BindInherit {
-    fn span(&self) -> Span {
-        self.span
-    }
-}
-
 impl PartialEq for BindInherit {
     fn eq(&self, other: &Self) -> bool {
         self.names == other.names
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BindInheritExpr {
     expr: Expr,
     names: Vec<Ident>,
@@ -777,32 +1020,35 @@ impl Display for BindInheritExpr {
     }
 }
 
-impl HasSpan for BindInheritExpr {
-    fn span(&self) -> Span {
-        self.span
-    }
-}
-
 impl PartialEq for BindInheritExpr {
     fn eq(&self, other: &Self) -> bool {
         self.expr == other.expr && self.names == other.names
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExprLet {
-    binds: Vec<Bind>,
+    binds: Box<[Bind]>,
     span: Span,
 }
 
 impl ExprLet {
     pub fn new(binds: Vec<Bind>, span: Span) -> Self {
-        ExprLet { binds, span }
+        ExprLet {
+            binds: binds.into_boxed_slice(),
+            span,
+        }
     }
 
     pub fn binds(&self) -> &[Bind] {
         &self.binds[..]
     }
+
+    /// Returns the value of the attribute at `path`, as described by [`lookup`].
+    pub fn get(&self, path: &[&str]) -> Option<&Expr> {
+        lookup(&self.binds, path)
+    }
 }
 
 impl Display for ExprLet {
@@ -823,12 +1069,6 @@ impl Display for ExprLet {
     }
 }
 
-impl HasSpan for ExprLet {
-    fn span(&self) -> Span {
-        self.span
-    }
-}
-
 impl From<ExprLet> for Expr {
     fn from(e: ExprLet) -> Self {
         Expr::Let(e)
@@ -841,20 +1081,29 @@ impl PartialEq for ExprLet {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExprRec {
-    binds: Vec<Bind>,
+    binds: Box<[Bind]>,
     span: Span,
 }
 
 impl ExprRec {
     pub fn new(binds: Vec<Bind>, span: Span) -> Self {
-        ExprRec { binds, span }
+        ExprRec {
+            binds: binds.into_boxed_slice(),
+            span,
+        }
     }
 
     pub fn binds(&self) -> &[Bind] {
         &self.binds[..]
     }
+
+    /// Returns the value of the attribute at `path`, as described by [`lookup`].
+    pub fn get(&self, path: &[&str]) -> Option<&Expr> {
+        lookup(&self.binds, path)
+    }
 }
 
 impl Display for ExprRec {
@@ -875,12 +1124,6 @@ impl Display for ExprRec {
     }
 }
 
-impl HasSpan for ExprRec {
-    fn span(&self) -> Span {
-        self.span
-    }
-}
-
 impl From<ExprRec> for Expr {
     fn from(e: ExprRec) -> Self {
         Expr::Rec(e)
@@ -893,8 +1136,9 @@ impl PartialEq for ExprRec {
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct AttrPath(Vec<AttrSegment>, Span);
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AttrPath(Box<[AttrSegment]>, Span);
 
 impl AttrPath {
     pub fn new(segments: Vec<AttrSegment>) -> Self {
@@ -905,7 +1149,11 @@ impl AttrPath {
             .map(|(first, second)| Span::merge(first, second))
             .unwrap_or_default();
 
-        AttrPath(segments, span)
+        AttrPath(segments.into_boxed_slice(), span)
+    }
+
+    pub fn segments(&self) -> &[AttrSegment] {
+        &self.0[..]
     }
 }
 
@@ -921,41 +1169,86 @@ impl Display for AttrPath {
     }
 }
 
-impl HasSpan for AttrPath {
-    fn span(&self) -> Span {
-        self.1
-    }
-}
-
 impl PartialEq for AttrPath {
     fn eq(&self, other: &Self) -> bool {
         self.0 == other.0
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AttrSegment {
     Ident(Ident),
     Interpolation(ExprInterpolation),
     String(ExprString),
 }
 
-impl Display for AttrSegment {
-    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+impl AttrSegment {
+    /// Returns the name of this segment, unless it is computed by interpolation.
+    pub fn name(&self) -> Option<&str> {
         match *self {
-            AttrSegment::Ident(ref ident) => write!(fmt, "{}", ident),
-            AttrSegment::Interpolation(ref expr) => write!(fmt, "{}", expr),
-            AttrSegment::String(ref expr) => write!(fmt, "{}", expr),
+            AttrSegment::Ident(ref ident) => Some(ident.as_str()),
+            AttrSegment::String(ref string) => match string.fragments() {
+                [] => Some(""),
+                [StringFragment::Literal(ref text, _)] => Some(text),
+                _ => None,
+            },
+            AttrSegment::Interpolation(_) => None,
         }
     }
 }
 
-impl HasSpan for AttrSegment {
-    fn span(&self) -> Span {
+/// Returns the value of the attribute at `path` defined by `binds`.
+///
+/// This follows the desugaring of nested attribute paths, so `a.b.c` is found whether it is
+/// written `a.b.c = x;`, `a = { b.c = x; };` or `a.b = { c = x; };`, and across several bindings
+/// extending the same set. Attributes defined by `inherit` and the implicit sets created by
+/// longer paths have no expression of their own, so looking them up yields `None`, as do
+/// bindings with interpolated names.
+pub fn lookup<'a>(binds: &'a [Bind], path: &[&str]) -> Option<&'a Expr> {
+    if path.is_empty() {
+        return None;
+    }
+
+    binds.iter().find_map(|bind| {
+        let simple = match *bind {
+            Bind::Simple(ref simple) => simple,
+            _ => return None,
+        };
+
+        let segments = simple.attr().segments();
+        let matches = segments.len() <= path.len()
+            && segments
+                .iter()
+                .zip(path)
+                .all(|(s, name)| s.name() == Some(name));
+        if !matches {
+            return None;
+        }
+
+        let rest = &path[segments.len()..];
+        if rest.is_empty() {
+            return Some(simple.expr());
+        }
+
+        let mut value = simple.expr();
+        while let Expr::Paren(ref paren) = *value {
+            value = paren.expr();
+        }
+        match *value {
+            Expr::Set(ref set) => set.get(rest),
+            Expr::Rec(ref rec) => rec.get(rest),
+            _ => None,
+        }
+    })
+}
+
+impl Display for AttrSegment {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
         match *self {
-            AttrSegment::Ident(ref ident) => ident.span(),
-            AttrSegment::Interpolation(ref expr) => expr.span(),
-            AttrSegment::String(ref expr) => expr.span(),
+            AttrSegment::Ident(ref ident) => write!(fmt, "{}", ident),
+            AttrSegment::Interpolation(ref expr) => write!(fmt, "{}", expr),
+            AttrSegment::String(ref expr) => write!(fmt, "{}", expr),
         }
     }
 }
@@ -972,7 +1265,8 @@ impl PartialEq for AttrSegment {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExprProj {
     base: Expr,
     attr: AttrPath,
@@ -1013,12 +1307,6 @@ impl Display for ExprProj {
     }
 }
 
-impl HasSpan for ExprProj {
-    fn span(&self) -> Span {
-        self.span
-    }
-}
-
 impl From<ExprProj> for Expr {
     fn from(e: ExprProj) -> Expr {
         Expr::Proj(Box::new(e))
@@ -1031,7 +1319,8 @@ impl PartialEq for ExprProj {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExprIf {
     cond: Expr,
     body: Expr,
@@ -1072,12 +1361,6 @@ impl Display for ExprIf {
     }
 }
 
-impl HasSpan for ExprIf {
-    fn span(&self) -> Span {
-        self.span
-    }
-}
-
 impl From<ExprIf> for Expr {
     fn from(e: ExprIf) -> Self {
         Expr::If(Box::new(e))
@@ -1090,7 +1373,8 @@ impl PartialEq for ExprIf {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExprOr {
     expr: Expr,
     fallback: Expr,
@@ -1121,12 +1405,6 @@ impl Display for ExprOr {
     }
 }
 
-impl HasSpan for ExprOr {
-    fn span(&self) -> Span {
-        self.span
-    }
-}
-
 impl From<ExprOr> for Expr {
     fn from(e: ExprOr) -> Expr {
         Expr::Or(Box::new(e))
@@ -1139,7 +1417,8 @@ impl PartialEq for ExprOr {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExprAssert {
     cond: Expr,
     expr: Expr,
@@ -1166,12 +1445,6 @@ impl Display for ExprAssert {
     }
 }
 
-impl HasSpan for ExprAssert {
-    fn span(&self) -> Span {
-        self.span
-    }
-}
-
 impl From<ExprAssert> for Expr {
     fn from(e: ExprAssert) -> Self {
         Expr::Assert(Box::new(e))
@@ -1184,7 +1457,8 @@ impl PartialEq for ExprAssert {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExprWith {
     with: Expr,
     expr: Expr,
@@ -1211,12 +1485,6 @@ impl Display for ExprWith {
     }
 }
 
-impl HasSpan for ExprWith {
-    fn span(&self) -> Span {
-        self.span
-    }
-}
-
 impl From<ExprWith> for Expr {
     fn from(e: ExprWith) -> Self {
         Expr::With(Box::new(e))
@@ -1229,22 +1497,44 @@ impl PartialEq for ExprWith {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExprLetIn {
-    binds: Vec<Bind>,
+    binds: Box<[Bind]>,
+    comment: Option<Comment>,
     body: Expr,
     span: Span,
 }
 
 impl ExprLetIn {
-    pub fn new(binds: Vec<Bind>, body: Expr, span: Span) -> Self {
-        ExprLetIn { binds, body, span }
+    pub fn new(binds: Vec<Bind>, comment: Option<Comment>, body: Expr, span: Span) -> Self {
+        ExprLetIn {
+            binds: binds.into_boxed_slice(),
+            comment,
+            body,
+            span,
+        }
     }
 
     pub fn binds(&self) -> &[Bind] {
         &self.binds[..]
     }
 
+    /// Returns the value bound to the variable `name`.
+    pub fn binding(&self, name: &str) -> Option<&Expr> {
+        lookup(&self.binds, &[name])
+    }
+
+    /// Returns the value at `path` within the bound variables, as described by [`lookup`].
+    pub fn get(&self, path: &[&str]) -> Option<&Expr> {
+        lookup(&self.binds, path)
+    }
+
+    /// Returns the comment between the last binding and the `in` keyword.
+    pub fn comment(&self) -> Option<&Comment> {
+        self.comment.as_ref()
+    }
+
     pub fn body(&self) -> &Expr {
         &self.body
     }
@@ -1264,13 +1554,11 @@ impl Display for ExprLetIn {
             write!(fmt, " {}", bind)?;
         }
 
-        write!(fmt, "in {}", self.body)
-    }
-}
+        if let Some(ref comment) = self.comment {
+            write!(fmt, " {}", comment)?;
+        }
 
-impl HasSpan for ExprLetIn {
-    fn span(&self) -> Span {
-        self.span
+        write!(fmt, "in {}", self.body)
     }
 }
 
@@ -1282,11 +1570,12 @@ impl From<ExprLetIn> for Expr {
 
 impl PartialEq for ExprLetIn {
     fn eq(&self, other: &Self) -> bool {
-        self.binds == other.binds && self.body == other.body
+        self.binds == other.binds && self.comment == other.comment && self.body == other.body
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ExprFnDecl {
     Simple(FnDeclSimple),
     Formals(FnDeclFormals),
@@ -1301,22 +1590,14 @@ impl Display for ExprFnDecl {
     }
 }
 
-impl HasSpan for ExprFnDecl {
-    fn span(&self) -> Span {
-        match *self {
-            ExprFnDecl::Simple(ref d) => d.span(),
-            ExprFnDecl::Formals(ref d) => d.span(),
-        }
-    }
-}
-
 impl From<ExprFnDecl> for Expr {
     fn from(e: ExprFnDecl) -> Self {
         Expr::FnDecl(Box::new(e))
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FnDeclSimple {
     name: Ident,
     body: Expr,
@@ -1343,19 +1624,14 @@ impl Display for FnDeclSimple {
     }
 }
 
-impl HasSpan for FnDeclSimple {
-    fn span(&self) -> Span {
-        self.span
-    }
-}
-
 impl PartialEq for FnDeclSimple {
     fn eq(&self, other: &Self) -> bool {
         self.name == other.name && self.body == other.body
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Formal {
     name: Ident,
     default: Option<Expr>,
@@ -1391,19 +1667,14 @@ impl Display for Formal {
     }
 }
 
-impl HasSpan for Formal {
-    fn span(&self) -> Span {
-        self.span
-    }
-}
-
 impl PartialEq for Formal {
     fn eq(&self, other: &Self) -> bool {
         self.name == other.name && self.default == other.default
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FnDeclFormals {
     formals: Vec<Formal>,
     ellipsis: Option<Span>,
@@ -1428,6 +1699,22 @@ impl FnDeclFormals {
             span,
         }
     }
+
+    pub fn formals(&self) -> &[Formal] {
+        &self.formals[..]
+    }
+
+    pub fn ellipsis(&self) -> Option<Span> {
+        self.ellipsis
+    }
+
+    pub fn extra(&self) -> Option<&Ident> {
+        self.extra.as_ref()
+    }
+
+    pub fn body(&self) -> &Expr {
+        &self.body
+    }
 }
 
 impl Display for FnDeclFormals {
@@ -1460,21 +1747,16 @@ impl Display for FnDeclFormals {
     }
 }
 
-impl HasSpan for FnDeclFormals {
-    fn span(&self) -> Span {
-        self.span
-    }
-}
-
 impl PartialEq for FnDeclFormals {
     fn eq(&self, other: &Self) -> bool {
         self.formals == other.formals
-            && (self.ellipsis.is_some() && other.ellipsis.is_some())
+            && self.ellipsis.is_some() == other.ellipsis.is_some()
             && self.body == other.body
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExprFnApp {
     function: Expr,
     argument: Expr,
@@ -1501,13 +1783,17 @@ impl ExprFnApp {
 
 impl Display for ExprFnApp {
     fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
-        write!(fmt, "{} {}", self.function, self.argument)
-    }
-}
-
-impl HasSpan for ExprFnApp {
-    fn span(&self) -> Span {
-        self.span
+        write_operand(
+            fmt,
+            &self.function,
+            precedence(&self.function) < APPLY_PRECEDENCE,
+        )?;
+        write!(fmt, " ")?;
+        write_operand(
+            fmt,
+            &self.argument,
+            precedence(&self.argument) < ATOM_PRECEDENCE,
+        )
     }
 }
 
@@ -1522,3 +1808,188 @@ impl PartialEq for ExprFnApp {
         self.function == other.function && self.argument == other.argument
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
+
+    use super::tokens::CommentKind;
+    use super::*;
+
+    // Whole-workspace indexing keeps every AST in memory, so guard against variants growing the
+    // enums by accident. Box new variants whose payload does not fit. The sizes depend on the
+    // width of pointers, so they are only checked where they were measured.
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn node_sizes() {
+        assert_eq!(size_of::<Literal>(), 24);
+        assert_eq!(size_of::<Expr>(), 32);
+        assert_eq!(size_of::<Bind>(), 72);
+    }
+
+    #[test]
+    fn prints_strings_with_their_quotes() {
+        let sources = [
+            r#""a \"${b}\" \${c}\n""#,
+            "''\n  a ''${b} '''c''' ${d}\n    e'\n''",
+            "''\n  ''\\ a\n b'''\n''",
+            "''\n  a''\\'''",
+        ];
+        for source in &sources {
+            let file: SourceFile = source.parse().unwrap();
+            let printed: SourceFile = file.expr().to_string().parse().unwrap();
+            assert_eq!(printed.expr(), file.expr(), "{}", file.expr());
+        }
+
+        let file: SourceFile = "''\n  a ''${b}\n''".parse().unwrap();
+        assert_eq!(file.expr().to_string(), "''\na ''${b}\n''");
+    }
+
+    #[test]
+    fn reads_literal_strings() {
+        let literal = |source: &str| match *source.parse::<SourceFile>().unwrap().expr() {
+            Expr::String(ref string) => string.as_literal().map(Cow::into_owned),
+            ref other => panic!("expected a string, found {}", other),
+        };
+        assert_eq!(literal(r#""""#).as_deref(), Some(""));
+        assert_eq!(literal(r#""a\tb""#).as_deref(), Some("a\tb"));
+        assert_eq!(literal("''\n  a\n''").as_deref(), Some("a\n"));
+        assert_eq!(literal(r#""a${b}""#), None);
+    }
+
+    #[test]
+    fn looks_up_nested_attributes() {
+        let source = r#"let
+  a.b = { c = 1; };
+  a.d.e = 2;
+  a = rec { "f g" = (3); };
+  inherit h;
+in { outputs = { x.y = 4; }; }"#;
+        let file: SourceFile = source.parse().unwrap();
+        let let_in = match *file.expr() {
+            Expr::LetIn(ref let_in) => let_in,
+            ref other => panic!("expected a let expression, found {}", other),
+        };
+
+        let value = |path: &[&str]| let_in.get(path).map(ToString::to_string);
+        assert_eq!(value(&["a", "b", "c"]).as_deref(), Some("1"));
+        assert_eq!(value(&["a", "d", "e"]).as_deref(), Some("2"));
+        assert_eq!(value(&["a", "f g"]).as_deref(), Some("(3)"));
+        assert_eq!(value(&["a", "d"]), None);
+        assert_eq!(value(&["a", "b", "x"]), None);
+        assert_eq!(let_in.binding("h"), None);
+
+        let body = match *let_in.body() {
+            Expr::Set(ref set) => set,
+            ref other => panic!("expected a set, found {}", other),
+        };
+        assert_eq!(body.get(&["outputs", "x", "y"]).unwrap().to_string(), "4");
+    }
+
+    #[test]
+    fn follows_operator_table() {
+        let cases = [
+            ("a // b // c", "a // (b // c)"),
+            ("a || b && c", "a || (b && c)"),
+            ("a -> b -> c", "a -> (b -> c)"),
+            ("a - b - c", "(a - b) - c"),
+            ("!a + b", "!(a + b)"),
+            ("-a ? b", "(-a) ? b"),
+        ];
+        fn grouped(expr: &Expr) -> String {
+            match *expr {
+                Expr::Unary(ref e) => format!("({}{})", e.op(), grouped(e.expr())),
+                Expr::Binary(ref e) => {
+                    format!("({} {} {})", grouped(e.left()), e.op(), grouped(e.right()))
+                }
+                Expr::Paren(ref e) => grouped(e.expr()),
+                ref other => other.to_string(),
+            }
+        }
+
+        for &(source, explicit) in &cases {
+            let expr: Expr = source.parse().unwrap();
+            let explicit: Expr = explicit.parse().unwrap();
+            assert_eq!(grouped(&expr), grouped(&explicit), "{}", source);
+            assert_eq!(expr.to_string(), source);
+        }
+
+        let update = BinaryOp::Update;
+        assert_eq!(update.associativity(), Assoc::Right);
+        assert_eq!(update.description(), "attribute set update");
+        assert!("a == b == c".parse::<Expr>().is_err());
+
+        let minus = Expr::Unary(Box::new(ExprUnary::new(
+            UnaryOp::Neg,
+            "a + b".parse().unwrap(),
+            Span::initial(),
+        )));
+        assert_eq!(minus.to_string(), "-(a + b)");
+    }
+
+    #[test]
+    fn keeps_comment_before_in() {
+        let source: SourceFile = "let\n  a = 1;\n\n  # b\n  b = a;\n  # done\nin b"
+            .parse()
+            .unwrap();
+        let let_in = match *source.expr() {
+            Expr::LetIn(ref let_in) => let_in,
+            ref other => panic!("expected `let ... in`, found {:?}", other),
+        };
+
+        assert_eq!(let_in.binds().len(), 2);
+        assert_eq!(let_in.comment(), Some(&Comment::from(" done")));
+    }
+
+    #[test]
+    fn keeps_comments_as_written() {
+        let source = "{\n  /**\n    Doc.\n\n      Indented.\n  */\n  a = 1;\n  # one\n  #  two\n  b = 2;\n  /*** c ***/\n  c = 3;\n}";
+        let file: SourceFile = source.parse().unwrap();
+        let set = match *file.expr() {
+            Expr::Set(ref set) => set,
+            ref other => panic!("expected a set, found {:?}", other),
+        };
+        let comments: Vec<_> = set
+            .binds()
+            .iter()
+            .map(|bind| match *bind {
+                Bind::Simple(ref simple) => simple.comment().unwrap(),
+                ref other => panic!("expected a simple binding, found {:?}", other),
+            })
+            .collect();
+
+        assert!(comments[0].is_doc());
+        assert_eq!(comments[0].text(), "Doc.\n\n  Indented.");
+        assert_eq!(comments[1].kind(), CommentKind::Line);
+        assert_eq!(comments[1].lines().collect::<Vec<_>>(), [" one", "  two"]);
+        assert_eq!(comments[2].kind(), CommentKind::Block);
+        assert_eq!(comments[2].text(), "c");
+
+        for comment in comments {
+            let span = comment.span();
+            let written = &source[span.start().to_usize()..span.end().to_usize()];
+            if comment.kind() == CommentKind::Line {
+                // Only the indentation before each `#` is layout rather than part of the comment.
+                let written: Vec<_> = written.lines().map(str::trim_start).collect();
+                assert_eq!(comment.to_string().lines().collect::<Vec<_>>(), written);
+            } else {
+                assert_eq!(comment.to_string(), written);
+            }
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn round_trips_through_json() {
+        let source = "# doc\nf: { a = [ 1 ./b.nix ''c ${f}'' ]; inherit (f) e; }.a";
+        let file: SourceFile = source.parse().unwrap();
+        let json = serde_json::to_value(&file).unwrap();
+        assert_eq!(json["comment"]["kind"], "Line");
+        let name = &json["expr"]["FnDecl"]["Simple"]["name"];
+        assert_eq!(*name, serde_json::json!(["f", { "start": 6, "end": 7 }]));
+
+        let read: SourceFile = serde_json::from_value(json).unwrap();
+        assert_eq!(read, file);
+        assert_eq!(read.to_string(), file.to_string());
+    }
+}
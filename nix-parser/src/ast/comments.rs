@@ -0,0 +1,227 @@
+//! Comments attached to the expressions around them.
+//!
+//! The AST only keeps the comment at the start of a file and those documenting simple bindings.
+//! A [`CommentMap`] recovers every other comment from the source text and attaches it to an
+//! expression, keyed by the span of that expression:
+//!
+//! * a comment on the same line as the end of an expression trails it, as in `[ a # first`;
+//! * otherwise a comment leads the expression starting right after it, as in `# then\n  b`;
+//! * a comment with neither, such as one in an empty list, dangles inside the innermost
+//!   expression containing it.
+//!
+//! Comments are attached to the outermost expression starting or ending at the token next to
+//! them, within the innermost expression containing the comment. So `# doc\nf x` leads the
+//! application rather than `f`, while `[ # doc\n f x ]` leads `f x` rather than the list.
+
+use std::collections::HashMap;
+
+use codespan::Span;
+
+use super::tokens::Comment;
+use super::visit::descendants;
+use super::SourceFile;
+use crate::error::Errors;
+use crate::lexer::{Lexer, StringFragment, Token};
+use crate::{HasSpan, ToSpan};
+
+/// The comments of a source file, attached to its expressions.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CommentMap {
+    leading: HashMap<Span, Vec<Comment>>,
+    trailing: HashMap<Span, Vec<Comment>>,
+    dangling: HashMap<Span, Vec<Comment>>,
+    len: usize,
+}
+
+impl CommentMap {
+    /// Attaches the comments of `source` to the expressions of `file`, which was parsed from it.
+    pub fn new(file: &SourceFile, source: &str) -> Result<Self, Errors> {
+        let lexer = Lexer::new(source)?;
+        let spans: Vec<Span> = descendants(file.expr()).map(HasSpan::span).collect();
+        let root = file.expr().span();
+
+        let mut map = CommentMap::default();
+        let mut tokens = Vec::new();
+        flatten(lexer.tokens().iter(), &mut tokens);
+        for (i, token) in tokens.iter().enumerate() {
+            let comment = match **token {
+                Token::Comment(ref text, kind, span) => Comment::new(text.as_ref(), kind, span),
+                _ => continue,
+            };
+            let span = comment.span();
+
+            // Strings come before the tokens interpolated in them, so skip any string around the
+            // comment.
+            let code = |token: &&&Token| !token.is_comment() && !matches!(***token, Token::Eof(_));
+            let mut spans_before = tokens[..i].iter().rev().filter(code).map(|t| t.to_span());
+            let prev = spans_before
+                .find(|s| s.end() <= span.start())
+                .map(|s| s.end());
+            let mut spans_after = tokens[i + 1..].iter().filter(code).map(|t| t.to_span());
+            let next = spans_after
+                .find(|s| s.start() >= span.end())
+                .map(|s| s.start());
+            let same_line = prev.is_some_and(|end| {
+                let between = &source[end.to_usize()..span.start().to_usize()];
+                !between.contains('\n')
+            });
+
+            // The innermost expression containing the comment, and the expressions within it.
+            let context = spans
+                .iter()
+                .rev()
+                .find(|s| s.start() < span.start() && span.end() <= s.end())
+                .cloned();
+            let inside = |s: &&Span| match context {
+                Some(context) => **s != context && within(**s, context),
+                None => true,
+            };
+            let ending = prev.and_then(|end| spans.iter().filter(inside).find(|s| s.end() == end));
+            let starting = next.and_then(|start| {
+                let mut starting = spans.iter().filter(inside);
+                starting.find(|s| s.start() == start)
+            });
+
+            let (table, key) = match (ending, starting, context) {
+                (Some(&ending), _, _) if same_line => (&mut map.trailing, ending),
+                (_, Some(&starting), _) => (&mut map.leading, starting),
+                (Some(&ending), None, _) => (&mut map.trailing, ending),
+                (None, None, Some(context)) => (&mut map.dangling, context),
+                (None, None, None) if next.is_none() => (&mut map.trailing, root),
+                (None, None, None) => (&mut map.leading, root),
+            };
+            table.entry(key).or_default().push(comment);
+            map.len += 1;
+        }
+
+        Ok(map)
+    }
+
+    /// Returns the comments directly before the expression at `span`.
+    pub fn leading(&self, span: Span) -> &[Comment] {
+        self.leading.get(&span).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the comments directly after the expression at `span`.
+    pub fn trailing(&self, span: Span) -> &[Comment] {
+        self.trailing.get(&span).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the comments inside the expression at `span` which are next to none of its
+    /// subexpressions.
+    pub fn dangling(&self, span: Span) -> &[Comment] {
+        self.dangling.get(&span).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the number of comments in the file.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Collects `tokens` along with the tokens interpolated in them.
+fn flatten<'a, 'b>(tokens: impl Iterator<Item = &'b Token<'a>>, out: &mut Vec<&'b Token<'a>>)
+where
+    'a: 'b,
+{
+    for token in tokens {
+        match *token {
+            Token::Interpolation(ref inner, _) => {
+                out.push(token);
+                flatten(inner.iter(), out);
+            }
+            Token::String(ref fragments, ..) => {
+                out.push(token);
+                for fragment in fragments {
+                    if let StringFragment::Interpolation(ref inner, _) = *fragment {
+                        flatten(inner.iter(), out);
+                    }
+                }
+            }
+            _ => out.push(token),
+        }
+    }
+}
+
+fn within(inner: Span, outer: Span) -> bool {
+    outer.start() <= inner.start() && inner.end() <= outer.end()
+}
+
+impl SourceFile {
+    /// Attaches the comments of `source`, which this file was parsed from, to its expressions.
+    pub fn comments(&self, source: &str) -> Result<CommentMap, Errors> {
+        CommentMap::new(self, source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attaches_comments_to_expressions() {
+        let source = "# file
+let
+  xs = [
+    # first
+    a # one
+    (f /* arg */ b)
+  ];
+  ys = [ /* none */ ];
+in
+  if c # cond
+  then
+    # yes
+    x
+  else y
+# end
+";
+        let file: SourceFile = source.parse().unwrap();
+        let map = file.comments(source).unwrap();
+        assert_eq!(map.len(), 8);
+
+        let span = |needle: &str, len: usize| {
+            let start = source.find(needle).unwrap() as u32;
+            Span::new(start, start + len as u32)
+        };
+        let raw = |comments: &[Comment]| -> Vec<String> {
+            comments.iter().map(|c| c.raw().trim().to_owned()).collect()
+        };
+
+        assert_eq!(raw(map.leading(span("a # one", 1))), ["first"]);
+        assert_eq!(raw(map.trailing(span("a # one", 1))), ["one"]);
+        assert_eq!(raw(map.trailing(span("f /*", 1))), ["arg"]);
+        assert_eq!(raw(map.dangling(span("[ /* none */ ]", 14))), ["none"]);
+        assert_eq!(raw(map.trailing(span("c # cond", 1))), ["cond"]);
+        assert_eq!(raw(map.leading(span("x\n", 1))), ["yes"]);
+
+        let root = file.expr().span();
+        assert_eq!(raw(map.leading(root)), ["file"]);
+        assert_eq!(raw(map.trailing(root)), ["end"]);
+    }
+
+    #[test]
+    fn parses_comments_between_any_tokens() {
+        let sources = [
+            "if c # c\nthen x # x\nelse y\n",
+            "f # f\n x\n",
+            "a + # a\n b # b\n - c\n",
+            "{ a # a\n = 1; inherit # i\n b; }\n",
+            "{ x # x\n, y }: x # x\n.z\n",
+            "- # n\n 1\n",
+            "\"${ # i\n a }\"\n",
+            "[ \"${a}\" # s\n ]\n",
+        ];
+        for source in &sources {
+            let file: SourceFile = source.parse().unwrap();
+            assert_eq!(
+                file.comments(source).unwrap().len(),
+                source.matches('#').count()
+            );
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! In-place mutation of syntax trees, used when splicing reparsed subtrees into an existing tree.
+
+use codespan::Span;
+
+use super::*;
+
+/// Types containing source spans which can be rewritten in place.
+///
+/// Syntax tree nodes implement this with `#[derive(SpansMut)]`, which visits every field not marked
+/// `#[span(skip)]`.
+pub(crate) trait SpansMut {
+    fn for_each_span_mut(&mut self, f: &mut dyn FnMut(&mut Span));
+}
+
+/// Shifts all spans in `node` to account for replacing the bytes in `edit` with `new_len` bytes.
+///
+/// Spans must not overlap the edited range, except for ones enclosing it completely.
+pub(crate) fn shift_spans<T: SpansMut + ?Sized>(node: &mut T, edit: Span, new_len: u32) {
+    let old_end = edit.end().to_usize() as i64;
+    let delta = i64::from(new_len) - (edit.end() - edit.start()).to_usize() as i64;
+    let shift = |offset: u32| {
+        if i64::from(offset) >= old_end {
+            (i64::from(offset) + delta) as u32
+        } else {
+            offset
+        }
+    };
+
+    node.for_each_span_mut(&mut |span: &mut Span| {
+        *span = Span::new(
+            shift(span.start().to_usize() as u32),
+            shift(span.end().to_usize() as u32),
+        );
+    });
+}
+
+/// Offsets all spans in `node` by `offset` bytes.
+pub(crate) fn offset_spans<T: SpansMut + ?Sized>(node: &mut T, offset: u32) {
+    node.for_each_span_mut(&mut |span: &mut Span| {
+        let start = span.start().to_usize() as u32 + offset;
+        let end = span.end().to_usize() as u32 + offset;
+        *span = Span::new(start, end);
+    });
+}
+
+/// Returns the direct subexpressions of `expr`, in the same order as `visit::children`.
+pub(crate) fn children_mut(expr: &mut Expr) -> Vec<&mut Expr> {
+    let mut out = Vec::new();
+    match *expr {
+        Expr::Paren(ref mut e) => out.push(&mut e.expr),
+        Expr::Interpolation(ref mut e) => out.push(&mut e.inner),
+        Expr::List(ref mut e) => out.extend(e.elems.iter_mut()),
+        Expr::String(ref mut e) => fragments_mut(&mut e.0, &mut out),
+        Expr::Set(ref mut e) => binds_mut(&mut e.binds, &mut out),
+        Expr::Unary(ref mut e) => out.push(&mut e.expr),
+        Expr::Binary(ref mut e) => out.extend(vec![&mut e.lhs, &mut e.rhs]),
+        Expr::Let(ref mut e) => binds_mut(&mut e.binds, &mut out),
+        Expr::Rec(ref mut e) => binds_mut(&mut e.binds, &mut out),
+        Expr::Proj(ref mut e) => {
+            let ExprProj {
+                ref mut base,
+                ref mut attr,
+                ref mut fallback,
+                ..
+            } = **e;
+            out.push(base);
+            segments_mut(&mut attr.0, &mut out);
+            out.extend(fallback.as_mut());
+        }
+        Expr::If(ref mut e) => out.extend(vec![&mut e.cond, &mut e.body, &mut e.fallback]),
+        Expr::Or(ref mut e) => out.extend(vec![&mut e.expr, &mut e.fallback]),
+        Expr::Assert(ref mut e) => out.extend(vec![&mut e.cond, &mut e.expr]),
+        Expr::With(ref mut e) => out.extend(vec![&mut e.with, &mut e.expr]),
+        Expr::LetIn(ref mut e) => {
+            binds_mut(&mut e.binds, &mut out);
+            out.push(&mut e.body);
+        }
+        Expr::FnDecl(ref mut e) => match **e {
+            ExprFnDecl::Simple(ref mut simple) => out.push(&mut simple.body),
+            ExprFnDecl::Formals(ref mut formals) => {
+                let defaults = formals
+                    .formals
+                    .iter_mut()
+                    .filter_map(|f| f.default.as_mut());
+                out.extend(defaults);
+                out.push(&mut formals.body);
+            }
+        },
+        Expr::FnApp(ref mut e) => out.extend(vec![&mut e.function, &mut e.argument]),
+        Expr::Ident(_) | Expr::Literal(_) | Expr::Error(_) | Expr::Trap(_) => {}
+    }
+    out
+}
+
+fn fragments_mut<'a>(fragments: &'a mut [StringFragment], out: &mut Vec<&'a mut Expr>) {
+    for fragment in fragments {
+        if let StringFragment::Interpolation(ref mut interp) = *fragment {
+            out.push(&mut interp.inner);
+        }
+    }
+}
+
+fn segments_mut<'a>(segments: &'a mut [AttrSegment], out: &mut Vec<&'a mut Expr>) {
+    for segment in segments {
+        match *segment {
+            AttrSegment::Interpolation(ref mut interp) => out.push(&mut interp.inner),
+            AttrSegment::String(ref mut string) => fragments_mut(&mut string.0, out),
+            AttrSegment::Ident(_) => {}
+        }
+    }
+}
+
+fn binds_mut<'a>(binds: &'a mut [Bind], out: &mut Vec<&'a mut Expr>) {
+    for bind in binds {
+        match *bind {
+            Bind::Simple(ref mut simple) => {
+                segments_mut(&mut simple.attr.0, out);
+                out.push(&mut simple.expr);
+            }
+            Bind::InheritExpr(ref mut inherit) => out.push(&mut inherit.expr),
+            Bind::Inherit(_) => {}
+        }
+    }
+}
+
+impl<T: SpansMut> SpansMut for [T] {
+    fn for_each_span_mut(&mut self, f: &mut dyn FnMut(&mut Span)) {
+        self.iter_mut().for_each(|node| node.for_each_span_mut(f));
+    }
+}
+
+impl<T: SpansMut + ?Sized> SpansMut for Box<T> {
+    fn for_each_span_mut(&mut self, f: &mut dyn FnMut(&mut Span)) {
+        (**self).for_each_span_mut(f);
+    }
+}
+
+impl<T: SpansMut> SpansMut for Option<T> {
+    fn for_each_span_mut(&mut self, f: &mut dyn FnMut(&mut Span)) {
+        if let Some(ref mut node) = *self {
+            node.for_each_span_mut(f);
+        }
+    }
+}
+
+impl SpansMut for Span {
+    fn for_each_span_mut(&mut self, f: &mut dyn FnMut(&mut Span)) {
+        f(self)
+    }
+}
@@ -0,0 +1,481 @@
+//! A serialized form of syntax trees which can be read in place, e.g. from a memory-mapped file.
+//!
+//! An image lays out the expressions of a tree in pre-order as flat little-endian records, followed
+//! by the contents of every node and the source text of the file:
+//!
+//! ```text
+//! header    magic "NXTI", version, node count, child count, data length, text length (6 × u32)
+//! nodes     kind, span start, span end, parent, first child, child count,
+//!           data start, data end                                      (8 × u32 each)
+//! children  the IDs of the children of every node, back to back       (u32 each)
+//! data      the contents of every node, back to back                  (JSON)
+//! text      the source text, as UTF-8
+//! ```
+//!
+//! The data of a node is the node serialized with serde, with each of its subexpressions replaced
+//! by an error node of the same span, so that every node is stored once whatever its depth.
+//!
+//! Opening an image with [`TreeImage::new`] only checks that the records are consistent, so that
+//! the structure of a tree (the kind, span, parent and children of every node, and the text it
+//! covers) can be walked without building any [`Expr`]. Only the subtrees actually needed are
+//! turned back into expressions with [`TreeImage::decode`], which deserializes the data of each of
+//! their nodes. A [`NodeId`] is the position of a node in pre-order, so it can be stored elsewhere to
+//! refer to a node of the image.
+//!
+//! Records need no particular alignment, so an image can be read from any byte slice.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str;
+
+use codespan::Span;
+
+use super::edit::children_mut;
+use super::visit::children;
+use super::{Expr, ExprFnDecl, SourceFile};
+use crate::HasSpan;
+
+/// The bytes every image starts with.
+pub const MAGIC: [u8; 4] = *b"NXTI";
+
+/// The version of the format, increased whenever its layout changes.
+pub const VERSION: u32 = 2;
+
+const HEADER_LEN: usize = 24;
+const NODE_LEN: usize = 32;
+const NO_PARENT: u32 = u32::MAX;
+
+/// The index of a node of an image, in pre-order.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct NodeId(u32);
+
+impl NodeId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl Display for NodeId {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        write!(fmt, "#{}", self.0)
+    }
+}
+
+/// An expression of a tree being encoded, with the indices of its parent and children.
+struct Node<'a> {
+    expr: &'a Expr,
+    parent: Option<u32>,
+    children: Vec<u32>,
+}
+
+/// Appends `expr` and the expressions below it to `nodes` in pre-order, returning its index.
+fn flatten<'a>(expr: &'a Expr, parent: Option<u32>, nodes: &mut Vec<Node<'a>>) -> u32 {
+    let index = nodes.len() as u32;
+    nodes.push(Node {
+        expr,
+        parent,
+        children: Vec::new(),
+    });
+    let children = children(expr)
+        .into_iter()
+        .map(|child| flatten(child, Some(index), nodes))
+        .collect();
+    nodes[index as usize].children = children;
+    index
+}
+
+/// The kind of expression a node of an image stands for.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum NodeKind {
+    Paren,
+    Ident,
+    Interpolation,
+    Literal,
+    List,
+    String,
+    Set,
+    Unary,
+    Binary,
+    Let,
+    Rec,
+    Proj,
+    If,
+    Or,
+    Assert,
+    With,
+    LetIn,
+    /// A function taking a single argument, `x: ...`.
+    FnDeclSimple,
+    /// A function taking a set of named arguments, `{ x, y }: ...`.
+    FnDeclFormals,
+    FnApp,
+    Error,
+    Trap,
+}
+
+impl NodeKind {
+    const ALL: [NodeKind; 22] = [
+        NodeKind::Paren,
+        NodeKind::Ident,
+        NodeKind::Interpolation,
+        NodeKind::Literal,
+        NodeKind::List,
+        NodeKind::String,
+        NodeKind::Set,
+        NodeKind::Unary,
+        NodeKind::Binary,
+        NodeKind::Let,
+        NodeKind::Rec,
+        NodeKind::Proj,
+        NodeKind::If,
+        NodeKind::Or,
+        NodeKind::Assert,
+        NodeKind::With,
+        NodeKind::LetIn,
+        NodeKind::FnDeclSimple,
+        NodeKind::FnDeclFormals,
+        NodeKind::FnApp,
+        NodeKind::Error,
+        NodeKind::Trap,
+    ];
+
+    /// Returns the kind of the given expression.
+    pub fn of(expr: &Expr) -> Self {
+        match *expr {
+            Expr::Paren(_) => NodeKind::Paren,
+            Expr::Ident(_) => NodeKind::Ident,
+            Expr::Interpolation(_) => NodeKind::Interpolation,
+            Expr::Literal(_) => NodeKind::Literal,
+            Expr::List(_) => NodeKind::List,
+            Expr::String(_) => NodeKind::String,
+            Expr::Set(_) => NodeKind::Set,
+            Expr::Unary(_) => NodeKind::Unary,
+            Expr::Binary(_) => NodeKind::Binary,
+            Expr::Let(_) => NodeKind::Let,
+            Expr::Rec(_) => NodeKind::Rec,
+            Expr::Proj(_) => NodeKind::Proj,
+            Expr::If(_) => NodeKind::If,
+            Expr::Or(_) => NodeKind::Or,
+            Expr::Assert(_) => NodeKind::Assert,
+            Expr::With(_) => NodeKind::With,
+            Expr::LetIn(_) => NodeKind::LetIn,
+            Expr::FnDecl(ref decl) => match **decl {
+                ExprFnDecl::Simple(_) => NodeKind::FnDeclSimple,
+                ExprFnDecl::Formals(_) => NodeKind::FnDeclFormals,
+            },
+            Expr::FnApp(_) => NodeKind::FnApp,
+            Expr::Error(_) => NodeKind::Error,
+            Expr::Trap(_) => NodeKind::Trap,
+        }
+    }
+
+    fn tag(self) -> u32 {
+        NodeKind::ALL.iter().position(|&kind| kind == self).unwrap() as u32
+    }
+
+    fn from_tag(tag: u32) -> Option<Self> {
+        NodeKind::ALL.get(tag as usize).cloned()
+    }
+}
+
+/// Returned by [`TreeImage::new`] for bytes which do not hold a valid image.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ImageError {
+    /// The bytes do not start with [`MAGIC`].
+    BadMagic,
+    /// The image was written in another version of the format.
+    UnsupportedVersion(u32),
+    /// The bytes are shorter or longer than the header says.
+    BadLength,
+    /// A node has an unknown kind, a span outside of the text, or refers to a missing node.
+    BadNode(u32),
+    /// The source text is not valid UTF-8.
+    BadText,
+}
+
+impl Display for ImageError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match *self {
+            ImageError::BadMagic => write!(fmt, "not a syntax tree image"),
+            ImageError::UnsupportedVersion(version) => write!(
+                fmt,
+                "syntax tree image has version {}, expected {}",
+                version, VERSION
+            ),
+            ImageError::BadLength => write!(fmt, "syntax tree image has the wrong length"),
+            ImageError::BadNode(index) => {
+                write!(fmt, "syntax tree image has invalid node {}", index)
+            }
+            ImageError::BadText => write!(fmt, "syntax tree image holds invalid UTF-8"),
+        }
+    }
+}
+
+impl Error for ImageError {}
+
+/// Serializes the tree of `source`, parsed from `text`, into an image.
+pub fn encode(source: &SourceFile, text: &str) -> Vec<u8> {
+    let mut nodes = Vec::new();
+    flatten(source.expr(), None, &mut nodes);
+    let child_count: usize = nodes.iter().map(|node| node.children.len()).sum();
+
+    let mut data = Vec::new();
+    let mut ranges = Vec::with_capacity(nodes.len());
+    for Node { expr, .. } in &nodes {
+        let mut node = (*expr).clone();
+        for child in children_mut(&mut node) {
+            *child = Expr::Error(child.span());
+        }
+        let start = data.len();
+        serde_json::to_writer(&mut data, &node).expect("syntax trees serialize to JSON");
+        ranges.push((start as u32, data.len() as u32));
+    }
+
+    let len = HEADER_LEN + nodes.len() * NODE_LEN + child_count * 4 + data.len() + text.len();
+    let mut bytes = Vec::with_capacity(len);
+    bytes.extend_from_slice(&MAGIC);
+    for value in &[
+        VERSION,
+        nodes.len() as u32,
+        child_count as u32,
+        data.len() as u32,
+        text.len() as u32,
+    ] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    let mut first_child = 0;
+    for (node, (data_start, data_end)) in nodes.iter().zip(ranges) {
+        let span = node.expr.span();
+        let children = node.children.len() as u32;
+        let record = [
+            NodeKind::of(node.expr).tag(),
+            span.start().to_usize() as u32,
+            span.end().to_usize() as u32,
+            node.parent.unwrap_or(NO_PARENT),
+            first_child,
+            children,
+            data_start,
+            data_end,
+        ];
+        for value in &record {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        first_child += children;
+    }
+
+    for child in nodes.iter().flat_map(|node| &node.children) {
+        bytes.extend_from_slice(&child.to_le_bytes());
+    }
+
+    bytes.extend_from_slice(&data);
+    bytes.extend_from_slice(text.as_bytes());
+    bytes
+}
+
+/// A syntax tree read in place from an image.
+#[derive(Clone, Copy, Debug)]
+pub struct TreeImage<'a> {
+    nodes: &'a [u8],
+    children: &'a [u8],
+    data: &'a [u8],
+    text: &'a str,
+}
+
+impl<'a> TreeImage<'a> {
+    /// Opens the image held by `bytes`, checking every record without decoding any expression.
+    pub fn new(bytes: &'a [u8]) -> Result<Self, ImageError> {
+        if bytes.len() < HEADER_LEN || bytes[..4] != MAGIC {
+            return Err(ImageError::BadMagic);
+        }
+        let version = read_u32(bytes, 4);
+        if version != VERSION {
+            return Err(ImageError::UnsupportedVersion(version));
+        }
+
+        let node_count = read_u32(bytes, 8) as usize;
+        let child_count = read_u32(bytes, 12) as usize;
+        let data_len = read_u32(bytes, 16) as usize;
+        let text_len = read_u32(bytes, 20) as usize;
+        let nodes_end = HEADER_LEN + node_count * NODE_LEN;
+        let children_end = nodes_end + child_count * 4;
+        let data_end = children_end + data_len;
+        if node_count == 0 || bytes.len() != data_end + text_len {
+            return Err(ImageError::BadLength);
+        }
+
+        let text = str::from_utf8(&bytes[data_end..]).map_err(|_| ImageError::BadText)?;
+        let image = TreeImage {
+            nodes: &bytes[HEADER_LEN..nodes_end],
+            children: &bytes[nodes_end..children_end],
+            data: &bytes[children_end..data_end],
+            text,
+        };
+
+        for index in 0..node_count {
+            if !image.is_valid_node(index) {
+                return Err(ImageError::BadNode(index as u32));
+            }
+        }
+        Ok(image)
+    }
+
+    fn is_valid_node(&self, index: usize) -> bool {
+        let field = |i: usize| read_u32(self.nodes, index * NODE_LEN + i * 4) as usize;
+        let (start, end) = (field(1), field(2));
+        let (parent, first_child, children) = (field(3), field(4), field(5));
+        let (data_start, data_end) = (field(6), field(7));
+        let child_count = self.children.len() / 4;
+
+        NodeKind::from_tag(field(0) as u32).is_some()
+            && start <= end
+            && self.text.is_char_boundary(start)
+            && self.text.is_char_boundary(end)
+            && end <= self.text.len()
+            && (parent == NO_PARENT as usize || parent < index)
+            && first_child + children <= child_count
+            && data_start <= data_end
+            && data_end <= self.data.len()
+            && (first_child..first_child + children).all(|i| {
+                (index + 1..self.len()).contains(&(read_u32(self.children, i * 4) as usize))
+            })
+    }
+
+    fn field(&self, id: NodeId, i: usize) -> u32 {
+        read_u32(self.nodes, id.index() * NODE_LEN + i * 4)
+    }
+
+    /// Returns the ID of the root expression.
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    /// Returns the number of expressions in the image.
+    pub fn len(&self) -> usize {
+        self.nodes.len() / NODE_LEN
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Returns the source text the tree was parsed from.
+    pub fn text(&self) -> &'a str {
+        self.text
+    }
+
+    pub fn kind(&self, id: NodeId) -> NodeKind {
+        NodeKind::from_tag(self.field(id, 0)).unwrap()
+    }
+
+    pub fn span(&self, id: NodeId) -> Span {
+        Span::new(self.field(id, 1), self.field(id, 2))
+    }
+
+    /// Returns the source text of the given expression.
+    pub fn source(&self, id: NodeId) -> &'a str {
+        let span = self.span(id);
+        &self.text[span.start().to_usize()..span.end().to_usize()]
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        Some(self.field(id, 3))
+            .filter(|&parent| parent != NO_PARENT)
+            .map(NodeId)
+    }
+
+    /// Returns the direct subexpressions of the given expression, in source order.
+    pub fn children(&self, id: NodeId) -> impl Iterator<Item = NodeId> + 'a {
+        let (first, count) = (self.field(id, 4) as usize, self.field(id, 5) as usize);
+        let children = self.children;
+        (first..first + count).map(move |i| NodeId(read_u32(children, i * 4)))
+    }
+
+    /// Returns the innermost expression whose span contains the given byte offset.
+    pub fn find_at(&self, offset: usize) -> Option<NodeId> {
+        let contains = |id: NodeId| {
+            let span = self.span(id);
+            span.start().to_usize() <= offset && offset <= span.end().to_usize()
+        };
+
+        let mut current = Some(self.root()).filter(|&id| contains(id))?;
+        while let Some(child) = self.children(current).find(|&id| contains(id)) {
+            current = child;
+        }
+
+        Some(current)
+    }
+
+    /// Builds the expression with the given ID, with spans relative to the whole text, by
+    /// deserializing the data of its nodes.
+    ///
+    /// Returns `None` if the data of a node is not a serialized expression of its kind with as
+    /// many subexpressions as it has children.
+    pub fn decode(&self, id: NodeId) -> Option<Expr> {
+        let (start, end) = (self.field(id, 6) as usize, self.field(id, 7) as usize);
+        let mut expr: Expr = serde_json::from_slice(&self.data[start..end]).ok()?;
+        if NodeKind::of(&expr) != self.kind(id) {
+            return None;
+        }
+
+        let mut slots = children_mut(&mut expr);
+        if slots.len() != self.field(id, 5) as usize {
+            return None;
+        }
+        for (slot, child) in slots.iter_mut().zip(self.children(id)) {
+            **slot = self.decode(child)?;
+        }
+        Some(expr)
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_trees_in_place() {
+        let text = "let\n  x = { a = ''\n    b ${c}\n  ''; };\nin [ x.a (y: y) ]";
+        let source: SourceFile = text.parse().unwrap();
+        let bytes = encode(&source, text);
+        let image = TreeImage::new(&bytes).unwrap();
+
+        let mut nodes = Vec::new();
+        flatten(source.expr(), None, &mut nodes);
+        assert_eq!(image.len(), nodes.len());
+        for (i, node) in nodes.iter().enumerate() {
+            let id = NodeId(i as u32);
+            assert_eq!(image.kind(id), NodeKind::of(node.expr));
+            assert_eq!(image.span(id), node.expr.span());
+            assert_eq!(image.parent(id), node.parent.map(NodeId));
+            assert!(image
+                .children(id)
+                .eq(node.children.iter().cloned().map(NodeId)));
+            assert_eq!(image.decode(id).as_ref(), Some(node.expr));
+        }
+
+        let offset = text.find("y)").unwrap();
+        let id = image.find_at(offset).unwrap();
+        assert_eq!((image.kind(id), image.source(id)), (NodeKind::Ident, "y"));
+
+        assert_eq!(TreeImage::new(b"NXTI").unwrap_err(), ImageError::BadMagic);
+        let mut truncated = bytes.clone();
+        truncated.pop();
+        assert_eq!(
+            TreeImage::new(&truncated).unwrap_err(),
+            ImageError::BadLength
+        );
+        let mut corrupt = bytes.clone();
+        corrupt[HEADER_LEN] = 0xff;
+        assert_eq!(
+            TreeImage::new(&corrupt).unwrap_err(),
+            ImageError::BadNode(0)
+        );
+    }
+}
@@ -0,0 +1,301 @@
+//! Conversion between plain data expressions and JSON values.
+//!
+//! Only expressions built from literals, strings without interpolations, lists and attribute sets
+//! have a JSON representation. This covers files such as lists of pinned sources, which tools want
+//! to read and regenerate without evaluating them.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use codespan::Span;
+use serde_json::{Map, Number, Value};
+
+use super::*;
+
+/// Keywords which cannot be used as unquoted attribute names.
+const KEYWORDS: &[&str] = &[
+    "assert", "else", "if", "in", "inherit", "let", "null", "or", "rec", "then", "with",
+];
+
+/// Returned by [`to_json_value`] for an expression which is not plain data.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NotData(Span);
+
+impl Display for NotData {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        write!(fmt, "expression cannot be converted to JSON")
+    }
+}
+
+impl Error for NotData {}
+
+impl HasSpan for NotData {
+    fn span(&self) -> Span {
+        self.0
+    }
+}
+
+/// Converts a plain data expression into a JSON value.
+///
+/// Attribute sets (including `rec` sets) become objects, with nested attribute paths such as
+/// `a.b = 1;` merged into nested objects. Paths and URIs become strings. Any other expression,
+/// including `inherit`, interpolations and search paths like `<nixpkgs>`, is rejected.
+pub fn to_json_value(expr: &Expr) -> Result<Value, NotData> {
+    match *expr {
+        Expr::Paren(ref paren) => to_json_value(paren.expr()),
+        Expr::Literal(ref lit) => literal(lit),
+        Expr::Unary(ref unary) if unary.op() == UnaryOp::Neg => match *unary.expr() {
+            Expr::Literal(Literal::Integer(i, _)) => Ok(Value::from(-i)),
+            Expr::Literal(Literal::Float(f, _)) => float(-f, unary.span()),
+            _ => Err(NotData(unary.span())),
+        },
+        Expr::String(ref string) => match string.as_literal() {
+            Some(text) => Ok(Value::String(text.into_owned())),
+            None => Err(NotData(string.span())),
+        },
+        Expr::List(ref list) => list.elems().iter().map(to_json_value).collect(),
+        Expr::Set(ref set) => binds(set.binds()).map(Value::Object),
+        Expr::Rec(ref rec) => binds(rec.binds()).map(Value::Object),
+        ref other => Err(NotData(other.span())),
+    }
+}
+
+fn literal(lit: &Literal) -> Result<Value, NotData> {
+    match *lit {
+        Literal::Null(_) => Ok(Value::Null),
+        Literal::Boolean(b, _) => Ok(Value::Bool(b)),
+        Literal::Float(f, span) => float(f, span),
+        Literal::Integer(i, _) => Ok(Value::from(i)),
+        Literal::Path(ref path, _) => Ok(Value::String(path.to_string_lossy().into_owned())),
+        Literal::Uri(ref uri, _) => Ok(Value::String(uri.to_string())),
+        Literal::PathTemplate(_, span) => Err(NotData(span)),
+    }
+}
+
+fn float(f: f64, span: Span) -> Result<Value, NotData> {
+    Number::from_f64(f).map(Value::Number).ok_or(NotData(span))
+}
+
+fn binds(binds: &[Bind]) -> Result<Map<String, Value>, NotData> {
+    let mut map = Map::new();
+    for bind in binds {
+        let simple = match *bind {
+            Bind::Simple(ref simple) => simple,
+            ref other => return Err(NotData(other.span())),
+        };
+
+        let keys = static_keys(simple.attr()).ok_or(NotData(simple.attr().span()))?;
+        let value = to_json_value(simple.expr())?;
+        insert(&mut map, &keys, value).map_err(|_| NotData(simple.span()))?;
+    }
+
+    Ok(map)
+}
+
+/// Inserts `value` at the given attribute path, merging it with any sets defined there already.
+fn insert(map: &mut Map<String, Value>, keys: &[String], value: Value) -> Result<(), ()> {
+    let (first, rest) = keys.split_first().ok_or(())?;
+    if rest.is_empty() {
+        return match map.get_mut(first) {
+            None => {
+                map.insert(first.clone(), value);
+                Ok(())
+            }
+            Some(Value::Object(existing)) => match value {
+                Value::Object(other) => other
+                    .into_iter()
+                    .try_for_each(|(key, value)| insert(existing, &[key], value)),
+                _ => Err(()),
+            },
+            Some(_) => Err(()),
+        };
+    }
+
+    let entry = map
+        .entry(first.clone())
+        .or_insert_with(|| Value::Object(Map::new()));
+    match *entry {
+        Value::Object(ref mut inner) => insert(inner, rest, value),
+        _ => Err(()),
+    }
+}
+
+/// Returns the attribute names of a path without interpolations.
+fn static_keys(path: &AttrPath) -> Option<Vec<String>> {
+    path.segments()
+        .iter()
+        .map(|segment| match *segment {
+            AttrSegment::Ident(ref ident) => Some(ident.to_string()),
+            AttrSegment::String(ref string) => string.as_literal().map(Cow::into_owned),
+            AttrSegment::Interpolation(_) => None,
+        })
+        .collect()
+}
+
+/// Generates an expression from a JSON value.
+///
+/// The generated expression has no spans or comments. Use [`merge_json_value`] to update an
+/// existing expression instead, keeping its comments.
+pub fn from_json_value(value: &Value) -> Expr {
+    let span = Span::initial();
+    match *value {
+        Value::Null => Expr::Literal(Literal::Null(span)),
+        Value::Bool(b) => Expr::Literal(Literal::Boolean(b, span)),
+        Value::Number(ref n) => match n.as_i64() {
+            Some(i) => Expr::Literal(Literal::Integer(i, span)),
+            None => Expr::Literal(Literal::Float(n.as_f64().unwrap_or_default(), span)),
+        },
+        Value::String(ref s) => Expr::String(string(s)),
+        Value::Array(ref values) => {
+            let elems = values.iter().map(from_json_value).collect();
+            Expr::List(ExprList::new(elems, span))
+        }
+        Value::Object(ref map) => {
+            let binds = map
+                .iter()
+                .map(|(key, value)| new_bind(key, value))
+                .collect();
+            Expr::Set(ExprSet::new(binds, span))
+        }
+    }
+}
+
+fn string(text: &str) -> ExprString {
+    let fragments = if text.is_empty() {
+        Vec::new()
+    } else {
+        vec![StringFragment::Literal(text.to_owned(), Span::initial())]
+    };
+    ExprString::new(fragments, StringKind::Normal, Span::initial())
+}
+
+fn new_bind(key: &str, value: &Value) -> Bind {
+    let mut chars = key.chars();
+    let is_ident = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || "_-'".contains(c))
+        && !KEYWORDS.contains(&key);
+
+    let segment = if is_ident {
+        AttrSegment::Ident(Ident::from(key))
+    } else {
+        AttrSegment::String(string(key))
+    };
+
+    let attr = AttrPath::new(vec![segment]);
+    Bind::Simple(BindSimple::new(
+        None,
+        attr,
+        from_json_value(value),
+        Span::initial(),
+    ))
+}
+
+/// Updates `original` to hold `value`, keeping as much of the original expression as possible.
+///
+/// Parts of `original` which already hold the right value are returned unchanged. Bindings which
+/// are still present keep their comments and their order, new bindings are appended and removed
+/// ones are dropped. Attribute sets using `inherit` or nested attribute paths are regenerated with
+/// [`from_json_value`] if their value changes.
+pub fn merge_json_value(original: &Expr, value: &Value) -> Expr {
+    if to_json_value(original).as_ref() == Ok(value) {
+        return original.clone();
+    }
+
+    match (original, value) {
+        (Expr::Paren(ref paren), _) => merge_json_value(paren.expr(), value),
+        (Expr::List(ref list), Value::Array(ref values)) => {
+            let elems = values
+                .iter()
+                .enumerate()
+                .map(|(i, value)| match list.elems().get(i) {
+                    Some(elem) => merge_json_value(elem, value),
+                    None => from_json_value(value),
+                })
+                .collect();
+            Expr::List(ExprList::new(elems, list.span()))
+        }
+        (Expr::Set(ref set), Value::Object(ref map)) => match merge_binds(set.binds(), map) {
+            Some(binds) => Expr::Set(ExprSet::new(binds, set.span())),
+            None => from_json_value(value),
+        },
+        (Expr::Rec(ref rec), Value::Object(ref map)) => match merge_binds(rec.binds(), map) {
+            Some(binds) => Expr::Rec(ExprRec::new(binds, rec.span())),
+            None => from_json_value(value),
+        },
+        _ => from_json_value(value),
+    }
+}
+
+fn merge_binds(binds: &[Bind], map: &Map<String, Value>) -> Option<Vec<Bind>> {
+    let mut merged = Vec::new();
+    let mut seen = HashSet::new();
+    for bind in binds {
+        let simple = match *bind {
+            Bind::Simple(ref simple) => simple,
+            _ => return None,
+        };
+
+        let key = match static_keys(simple.attr()) {
+            Some(ref keys) if keys.len() == 1 => keys[0].clone(),
+            _ => return None,
+        };
+
+        if let Some(value) = map.get(&key) {
+            let expr = merge_json_value(simple.expr(), value);
+            let comment = simple.comment().cloned();
+            let attr = simple.attr().clone();
+            merged.push(Bind::Simple(BindSimple::new(
+                comment,
+                attr,
+                expr,
+                simple.span(),
+            )));
+            seen.insert(key);
+        }
+    }
+
+    let added = map.iter().filter(|(key, _)| !seen.contains(*key));
+    merged.extend(added.map(|(key, value)| new_bind(key, value)));
+    Some(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn parse(source: &str) -> Expr {
+        source.parse::<SourceFile>().unwrap().expr().clone()
+    }
+
+    #[test]
+    fn converts_data_to_json() {
+        let expr =
+            parse(r#"{ a.b = 1; a.c = -2.5; "d e" = [ null true ./foo.nix ]; f = "x\"y"; }"#);
+        let value =
+            json!({ "a": { "b": 1, "c": -2.5 }, "d e": [null, true, "./foo.nix"], "f": "x\"y" });
+        assert_eq!(to_json_value(&expr), Ok(value));
+
+        assert!(to_json_value(&parse("{ inherit a; }")).is_err());
+        assert!(to_json_value(&parse(r#"{ a = "${b}"; }"#)).is_err());
+        assert!(to_json_value(&parse("{ a = 1; a = 2; }")).is_err());
+    }
+
+    #[test]
+    fn generates_and_merges_expressions() {
+        let value = json!({ "if": "a\nb", "name": "${x}", "list": [1, -2.5, [-3]] });
+        let expr = from_json_value(&value);
+        assert_eq!(to_json_value(&expr), Ok(value));
+
+        let original = parse("{\n  # pinned by hand\n  foo = { rev = \"a\"; };\n  bar = 1;\n}");
+        let updated = json!({ "foo": { "rev": "b" }, "baz": true });
+        let merged = merge_json_value(&original, &updated);
+        assert_eq!(
+            merged.to_string(),
+            "{# pinned by hand\nfoo = {rev = \"b\";}; baz = true;}"
+        );
+        assert_eq!(to_json_value(&merged), Ok(updated));
+    }
+}
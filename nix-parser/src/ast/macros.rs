@@ -48,11 +48,11 @@ macro_rules! nix_token {
     };
 
     (/ $($path:tt)/+) => {
-        Literal::Path(["/", $(stringify!($path)),+].into_iter().collect(), Default::default())
+        Literal::Path(Box::new(["/", $(stringify!($path)),+].into_iter().collect()), Default::default())
     };
 
     ($prefix:tt / $($path:tt)/+) => {
-        Literal::Path([stringify!($prefix), $(stringify!($path)),+].into_iter().collect(), Default::default())
+        Literal::Path(Box::new([stringify!($prefix), $(stringify!($path)),+].into_iter().collect()), Default::default())
     };
 
     (false) => {
@@ -218,7 +218,7 @@ macro_rules! nix_set {
 /// # use nix_parser::ast::{tokens::Literal, Expr};
 /// #
 /// let (expr, s) = nix_expr_and_str!(~/foo/bar);
-/// assert_eq!(expr, Expr::Literal(Literal::Path("~/foo/bar".into(), Default::default())));
+/// assert_eq!(expr, Expr::Literal(Literal::Path(Box::new("~/foo/bar".into()), Default::default())));
 /// assert_eq!(s, "~/foo/bar");
 /// ```
 #[doc(hidden)]
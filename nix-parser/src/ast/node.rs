@@ -0,0 +1,63 @@
+//! A plain view of the syntax tree, shared by the bindings to other languages.
+//!
+//! Each expression becomes a [`Node`] with the name of its kind, its span and its child
+//! expressions, so that every binding hands out trees of the same shape. With the `serde`
+//! feature, nodes serialize as nested `{ "kind", "start", "end", "text", "children" }` objects.
+
+use super::visit::children;
+use super::Expr;
+use crate::HasSpan;
+
+/// An expression of the syntax tree.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Node {
+    /// The kind of expression, as given by [`Expr::kind_name`].
+    pub kind: &'static str,
+    /// The byte offset of the start of the expression.
+    pub start: usize,
+    /// The byte offset of the end of the expression.
+    pub end: usize,
+    /// The source text of identifiers and literals.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub text: Option<String>,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    /// Builds the node of `expr` and of all of its descendants.
+    pub fn new(expr: &Expr) -> Self {
+        let span = expr.span();
+        let text = match *expr {
+            Expr::Ident(ref ident) => Some(ident.to_string()),
+            Expr::Literal(ref lit) => Some(lit.to_string()),
+            _ => None,
+        };
+
+        Node {
+            kind: expr.kind_name(),
+            start: span.start().to_usize(),
+            end: span.end().to_usize(),
+            text,
+            children: children(expr).into_iter().map(Node::new).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_source_file;
+
+    #[test]
+    fn mirrors_the_syntax_tree() {
+        let file = parse_source_file("{ foo = bar 1; }").unwrap();
+        let node = Node::new(file.expr());
+        assert_eq!((node.kind, node.start, node.end), ("set", 0, 16));
+
+        let app = &node.children[0];
+        assert_eq!(app.kind, "fn_app");
+        let texts: Vec<_> = app.children.iter().map(|c| c.text.as_deref()).collect();
+        assert_eq!(texts, [Some("bar"), Some("1")]);
+    }
+}
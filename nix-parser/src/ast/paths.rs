@@ -0,0 +1,243 @@
+//! Resolution of path literals to the files they point to.
+//!
+//! Nix resolves a relative path such as `./foo.nix` or `../bar` against the directory of the file
+//! containing it, and a path starting with `~/` against the home directory of the user. A search
+//! path such as `<nixpkgs/lib>` is looked up in the entries of `NIX_PATH`, the first of which
+//! containing it is used. Paths are normalized lexically, without following symbolic links, as
+//! Nix does.
+//!
+//! Nothing here touches the file system: whether the resolved files exist is left to the caller.
+
+use std::path::{Component, Path, PathBuf};
+
+use codespan::Span;
+
+use super::tokens::Literal;
+use super::visit::descendants;
+use super::{Expr, SourceFile};
+
+/// The entries of a search path, in the format of `NIX_PATH`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SearchPath {
+    /// The prefix of each entry, if any, and the directory or URL it stands for.
+    entries: Vec<(Option<String>, String)>,
+}
+
+impl SearchPath {
+    /// Parses a search path made of entries separated by `:`, each either a directory or
+    /// `prefix=directory`. As in Nix, a `:` followed by `//` is part of a URL rather than a
+    /// separator, as in `nixpkgs=https://example.org/nixpkgs.tar.gz`.
+    pub fn new(nix_path: &str) -> Self {
+        let mut pieces: Vec<String> = Vec::new();
+        let mut rest = nix_path;
+        let mut joined = false;
+        while !rest.is_empty() {
+            let end = rest.find(':').unwrap_or(rest.len());
+            match pieces.last_mut() {
+                Some(last) if joined => last.push_str(&rest[..end]),
+                _ => pieces.push(rest[..end].to_owned()),
+            }
+            rest = rest.get(end + 1..).unwrap_or("");
+            joined = rest.starts_with("//");
+            if joined {
+                pieces.last_mut().unwrap().push(':');
+            }
+        }
+
+        let entries = pieces
+            .into_iter()
+            .filter(|piece| !piece.is_empty())
+            .map(|piece| match piece.find('=') {
+                Some(index) => (
+                    Some(piece[..index].to_owned()),
+                    piece[index + 1..].to_owned(),
+                ),
+                None => (None, piece),
+            })
+            .collect();
+        SearchPath { entries }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the paths `<lookup>` may refer to, in the order Nix tries them.
+    ///
+    /// Returns `None` if a matching entry is a URL, whose contents are not known without
+    /// fetching it.
+    pub fn candidates(&self, lookup: &str) -> Option<Vec<PathBuf>> {
+        let mut candidates = Vec::new();
+        for (prefix, dir) in &self.entries {
+            let rest = match *prefix {
+                Some(ref prefix) if lookup == prefix => "",
+                Some(ref prefix) => match lookup.strip_prefix(prefix.as_str()) {
+                    Some(rest) if rest.starts_with('/') => &rest[1..],
+                    _ => continue,
+                },
+                None => lookup,
+            };
+            if dir.contains("://") {
+                return None;
+            }
+            let path = Path::new(dir);
+            candidates.push(normalize(&if rest.is_empty() {
+                path.to_owned()
+            } else {
+                path.join(rest)
+            }));
+        }
+        Some(candidates)
+    }
+}
+
+/// Where the path literals of a file are resolved from.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PathContext {
+    dir: PathBuf,
+    home: Option<PathBuf>,
+    search_path: SearchPath,
+}
+
+impl PathContext {
+    /// Creates a context resolving relative paths against `dir`, the directory of the file
+    /// containing them.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        PathContext {
+            dir: dir.into(),
+            ..PathContext::default()
+        }
+    }
+
+    /// Resolves paths starting with `~/` against the given home directory.
+    pub fn with_home<P: Into<PathBuf>>(mut self, home: P) -> Self {
+        self.home = Some(home.into());
+        self
+    }
+
+    /// Looks up search paths such as `<nixpkgs>` in the given entries.
+    pub fn with_search_path(mut self, search_path: SearchPath) -> Self {
+        self.search_path = search_path;
+        self
+    }
+
+    pub fn search_path(&self) -> &SearchPath {
+        &self.search_path
+    }
+
+    /// Returns the paths the literal may refer to, in the order Nix tries them: a single path for
+    /// a path literal, and one for every matching entry of the search path for a search path.
+    ///
+    /// Returns `None` for literals which are not paths, and for paths which cannot be resolved
+    /// here, such as `~/` paths without a home directory.
+    pub fn resolve(&self, literal: &Literal) -> Option<Vec<PathBuf>> {
+        match *literal {
+            Literal::Path(ref path, _) => {
+                let path = match path.strip_prefix("~") {
+                    Ok(rest) => self.home.as_ref()?.join(rest),
+                    Err(_) => self.dir.join(path.as_path()),
+                };
+                Some(vec![normalize(&path)])
+            }
+            Literal::PathTemplate(ref lookup, _) => {
+                self.search_path.candidates(&lookup.to_string_lossy())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Removes `.` and `..` components without touching the file system.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if normalized.file_name().is_some() => {
+                normalized.pop();
+            }
+            other => normalized.push(other.as_os_str()),
+        }
+    }
+    normalized
+}
+
+/// Returns the path and search path literals of `file`, with their spans, in source order.
+pub fn path_literals(file: &SourceFile) -> Vec<(&Literal, Span)> {
+    let mut literals: Vec<_> = descendants(file.expr())
+        .filter_map(|expr| match *expr {
+            Expr::Literal(ref literal @ Literal::Path(_, span))
+            | Expr::Literal(ref literal @ Literal::PathTemplate(_, span)) => Some((literal, span)),
+            _ => None,
+        })
+        .collect();
+    literals.sort_by_key(|&(_, span)| span.start());
+    literals
+}
+
+impl SourceFile {
+    /// Returns the path and search path literals of this file, in source order.
+    pub fn path_literals(&self) -> Vec<(&Literal, Span)> {
+        path_literals(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_search_paths() {
+        let search_path = SearchPath::new(
+            "nixpkgs=/nix/channels/nixpkgs:/etc/nix/path::x=https://example.org/x.tar.gz",
+        );
+        assert_eq!(
+            search_path.entries,
+            [
+                (Some("nixpkgs".into()), "/nix/channels/nixpkgs".into()),
+                (None, "/etc/nix/path".into()),
+                (Some("x".into()), "https://example.org/x.tar.gz".into()),
+            ]
+        );
+
+        let candidates = |lookup: &str| search_path.candidates(lookup);
+        assert_eq!(
+            candidates("nixpkgs/lib"),
+            Some(vec![
+                PathBuf::from("/nix/channels/nixpkgs/lib"),
+                PathBuf::from("/etc/nix/path/nixpkgs/lib"),
+            ])
+        );
+        assert_eq!(candidates("nixpkgs-unstable").unwrap().len(), 1);
+        assert_eq!(candidates("x"), None);
+        assert!(SearchPath::new("").is_empty());
+    }
+
+    #[test]
+    fn resolves_path_literals() {
+        let source = "[ ./a.nix ../b/./c ~/d <nixpkgs/lib> 1 ]\n";
+        let file: SourceFile = source.parse().unwrap();
+        let context = PathContext::new("/src/pkgs")
+            .with_home("/home/user")
+            .with_search_path(SearchPath::new("nixpkgs=/channels/nixpkgs"));
+
+        let resolved: Vec<_> = file
+            .path_literals()
+            .into_iter()
+            .map(|(literal, _)| context.resolve(literal).unwrap())
+            .collect();
+        let paths = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+        assert_eq!(
+            resolved,
+            [
+                paths(&["/src/pkgs/a.nix"]),
+                paths(&["/src/b/c"]),
+                paths(&["/home/user/d"]),
+                paths(&["/channels/nixpkgs/lib"]),
+            ]
+        );
+
+        let without_home = PathContext::new("/src");
+        assert_eq!(without_home.resolve(&Literal::from(Path::new("~/d"))), None);
+    }
+}
@@ -0,0 +1,272 @@
+//! Structural search over syntax trees.
+//!
+//! A [`Pattern`] describes the shape of an expression, such as "`fetchGit` applied to a set with
+//! a `url` attribute", and [`find`] returns every expression of a file which has that shape, in
+//! source order. Parts of a pattern wrapped in [`Pattern::capture`] bind the expressions they
+//! match to a name, so that lints and code actions can point at or rewrite exactly those parts.
+//!
+//! Parentheses are looked through, so `(fetchGit) { }` matches the same patterns as
+//! `fetchGit { }`.
+//!
+//! ```
+//! use nix_parser::ast::query::{find, Pattern};
+//! use nix_parser::ast::SourceFile;
+//!
+//! let source = r#"{ src = builtins.fetchGit { url = "https://example.org/a.git"; }; }"#;
+//! let file: SourceFile = source.parse().unwrap();
+//!
+//! let fetch_git = Pattern::ident("fetchGit")
+//!     .or(Pattern::select(Pattern::ident("builtins"), &["fetchGit"]));
+//! let url = Pattern::capture("url", Pattern::Any);
+//! let matches = find(&file, &Pattern::apply(fetch_git, Pattern::with_attr(&["url"], url)));
+//!
+//! assert_eq!(matches.len(), 1);
+//! let url = matches[0].capture("url").unwrap();
+//! assert_eq!(url.to_string(), r#""https://example.org/a.git""#);
+//! ```
+
+use std::collections::BTreeMap;
+
+use codespan::Span;
+
+use super::visit::descendants;
+use super::{Expr, SourceFile};
+use crate::HasSpan;
+
+/// The shape of an expression.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pattern {
+    /// Any expression.
+    Any,
+    /// An identifier with the given name.
+    Ident(String),
+    /// A string without interpolations, with the given text.
+    String(String),
+    /// `base.a.b`, selecting exactly the given attribute path without an `or` fallback.
+    Select(Box<Pattern>, Vec<String>),
+    /// A function applied to an argument.
+    Apply(Box<Pattern>, Box<Pattern>),
+    /// A set or recursive set defining the given attribute path, with a value matching the
+    /// pattern.
+    WithAttr(Vec<String>, Box<Pattern>),
+    /// An expression matching any of the patterns, tried in order.
+    Either(Vec<Pattern>),
+    /// An expression matching the pattern, bound to the given name in the match.
+    Capture(String, Box<Pattern>),
+}
+
+impl Pattern {
+    pub fn ident(name: &str) -> Self {
+        Pattern::Ident(name.to_owned())
+    }
+
+    pub fn string(text: &str) -> Self {
+        Pattern::String(text.to_owned())
+    }
+
+    pub fn select(base: Pattern, path: &[&str]) -> Self {
+        Pattern::Select(Box::new(base), owned(path))
+    }
+
+    pub fn apply(function: Pattern, argument: Pattern) -> Self {
+        Pattern::Apply(Box::new(function), Box::new(argument))
+    }
+
+    pub fn with_attr(path: &[&str], value: Pattern) -> Self {
+        Pattern::WithAttr(owned(path), Box::new(value))
+    }
+
+    pub fn capture(name: &str, pattern: Pattern) -> Self {
+        Pattern::Capture(name.to_owned(), Box::new(pattern))
+    }
+
+    /// Returns a pattern matching either this pattern or `other`.
+    pub fn or(self, other: Pattern) -> Self {
+        match self {
+            Pattern::Either(mut patterns) => {
+                patterns.push(other);
+                Pattern::Either(patterns)
+            }
+            pattern => Pattern::Either(vec![pattern, other]),
+        }
+    }
+
+    /// Returns whether `expr` has the shape of this pattern.
+    pub fn matches(&self, expr: &Expr) -> bool {
+        self.bind(expr, &mut BTreeMap::new())
+    }
+
+    /// Matches `expr` against this pattern, adding the expressions it captures to `captures`.
+    ///
+    /// On failure, `captures` may hold some of the captures of the subpatterns which matched.
+    fn bind<'a>(&self, expr: &'a Expr, captures: &mut BTreeMap<String, &'a Expr>) -> bool {
+        let mut expr = expr;
+        while let Expr::Paren(ref paren) = *expr {
+            expr = paren.expr();
+        }
+
+        match (self, expr) {
+            (Pattern::Any, _) => true,
+            (Pattern::Ident(name), Expr::Ident(ident)) => ident.as_str() == name,
+            (Pattern::String(text), Expr::String(string)) => {
+                string.as_literal().is_some_and(|literal| literal == **text)
+            }
+            (Pattern::Select(base, path), Expr::Proj(proj)) => {
+                let segments = proj.attr().segments();
+                proj.fallback().is_none()
+                    && segments.len() == path.len()
+                    && segments
+                        .iter()
+                        .zip(path)
+                        .all(|(segment, name)| segment.name() == Some(name))
+                    && base.bind(proj.base(), captures)
+            }
+            (Pattern::Apply(function, argument), Expr::FnApp(app)) => {
+                function.bind(app.function(), captures) && argument.bind(app.argument(), captures)
+            }
+            (Pattern::WithAttr(path, value), _) => {
+                let path: Vec<_> = path.iter().map(String::as_str).collect();
+                let attr = match *expr {
+                    Expr::Set(ref set) => set.get(&path),
+                    Expr::Rec(ref rec) => rec.get(&path),
+                    _ => None,
+                };
+                attr.is_some_and(|attr| value.bind(attr, captures))
+            }
+            (Pattern::Either(patterns), _) => patterns.iter().any(|pattern| {
+                let before = captures.clone();
+                let matched = pattern.bind(expr, captures);
+                if !matched {
+                    *captures = before;
+                }
+                matched
+            }),
+            (Pattern::Capture(name, pattern), _) => {
+                let matched = pattern.bind(expr, captures);
+                if matched {
+                    captures.insert(name.clone(), expr);
+                }
+                matched
+            }
+            _ => false,
+        }
+    }
+}
+
+fn owned(path: &[&str]) -> Vec<String> {
+    path.iter().map(|&name| name.to_owned()).collect()
+}
+
+/// An expression matching a pattern.
+#[derive(Clone, Debug)]
+pub struct Match<'a> {
+    expr: &'a Expr,
+    captures: BTreeMap<String, &'a Expr>,
+}
+
+impl<'a> Match<'a> {
+    pub fn expr(&self) -> &'a Expr {
+        self.expr
+    }
+
+    /// Returns the expression bound to `name` by a [`Pattern::Capture`].
+    pub fn capture(&self, name: &str) -> Option<&'a Expr> {
+        self.captures.get(name).copied()
+    }
+
+    /// Iterates over the captured expressions, sorted by name.
+    pub fn captures(&self) -> impl Iterator<Item = (&str, &'a Expr)> + '_ {
+        self.captures
+            .iter()
+            .map(|(name, &expr)| (name.as_str(), expr))
+    }
+}
+
+impl HasSpan for Match<'_> {
+    fn span(&self) -> Span {
+        self.expr.span()
+    }
+}
+
+/// Returns every expression of `file` which matches `pattern`, in source order.
+///
+/// A parenthesized expression is only reported once, without its parentheses.
+pub fn find<'a>(file: &'a SourceFile, pattern: &Pattern) -> Vec<Match<'a>> {
+    descendants(file.expr())
+        .filter(|expr| !matches!(**expr, Expr::Paren(_)))
+        .filter_map(|expr| {
+            let mut captures = BTreeMap::new();
+            if pattern.bind(expr, &mut captures) {
+                Some(Match { expr, captures })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn found(source: &str, pattern: &Pattern) -> Vec<String> {
+        let file: SourceFile = source.parse().unwrap();
+        find(&file, pattern)
+            .iter()
+            .map(|found| {
+                let span = found.span();
+                source[span.start().to_usize()..span.end().to_usize()].to_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matches_function_applications() {
+        let source = "[ (fetchGit { url = a; }) ((fetchGit) b) (fetchTarball c) (x.fetchGit d) ]";
+        let pattern = Pattern::apply(Pattern::ident("fetchGit"), Pattern::Any);
+        assert_eq!(
+            found(source, &pattern),
+            ["fetchGit { url = a; }", "(fetchGit) b"]
+        );
+
+        let pattern = Pattern::apply(
+            Pattern::select(Pattern::Any, &["fetchGit"]),
+            Pattern::capture("arg", Pattern::Any),
+        );
+        let file: SourceFile = source.parse().unwrap();
+        let matches = find(&file, &pattern);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].capture("arg").unwrap().to_string(), "d");
+        assert_eq!(matches[0].captures().count(), 1);
+    }
+
+    #[test]
+    fn matches_attributes_and_strings() {
+        let source = r#"{ a = { src.url = "x"; }; b = rec { src = { url = "y"; }; }; c.d = "x"; }"#;
+        let pattern = Pattern::with_attr(&["src", "url"], Pattern::string("x"));
+        assert_eq!(found(source, &pattern), [r#"{ src.url = "x"; }"#]);
+
+        let pattern = Pattern::with_attr(&["url"], Pattern::Any);
+        assert_eq!(found(source, &pattern), [r#"{ url = "y"; }"#]);
+
+        let pattern = Pattern::select(Pattern::ident("c"), &["d"]);
+        assert_eq!(found("[ c.d c.d.e ]", &pattern), ["c.d"]);
+    }
+
+    #[test]
+    fn keeps_captures_of_the_alternative_which_matched() {
+        let first = Pattern::apply(
+            Pattern::capture("f", Pattern::Any),
+            Pattern::ident("missing"),
+        );
+        let second = Pattern::apply(Pattern::Any, Pattern::capture("x", Pattern::Any));
+        let pattern = first.or(second);
+        assert!(matches!(pattern, Pattern::Either(ref patterns) if patterns.len() == 2));
+
+        let file: SourceFile = "f x".parse().unwrap();
+        let matches = find(&file, &pattern);
+        let names: Vec<_> = matches[0].captures().map(|(name, _)| name).collect();
+        assert_eq!(names, ["x"]);
+        assert!(Pattern::ident("f").matches("(f)".parse::<SourceFile>().unwrap().expr()));
+    }
+}
@@ -0,0 +1,423 @@
+//! Minimal text edits for changing attribute sets and lists in place.
+//!
+//! Tools which pin sources or bump versions need to change a single value without disturbing the
+//! layout and comments around it, which printing a modified tree would lose. These functions
+//! instead compute the one [`TextEdit`] to apply to the source text a file was parsed from,
+//! following the indentation of the neighbouring bindings or elements.
+//!
+//! Values are found by attribute path, as in [`lookup`](super::lookup), starting from the set a
+//! file evaluates to. Both the file and the values along the path are looked through functions,
+//! `let`, `with` and function applications, so that `["src", "rev"]` finds the revision in
+//! `{ fetchFromGitHub }: stdenv.mkDerivation { src = fetchFromGitHub { rev = "..."; }; }`.
+//!
+//! ```
+//! use nix_parser::ast::rewrite::set_attr_value;
+//! use nix_parser::ast::SourceFile;
+//!
+//! let source = "{ pname = \"hello\"; version = \"2.10\"; }";
+//! let file: SourceFile = source.parse().unwrap();
+//! let edit = set_attr_value(&file, &["version"], "\"2.12\"").unwrap();
+//! assert_eq!(edit.apply(source), "{ pname = \"hello\"; version = \"2.12\"; }");
+//! ```
+
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use codespan::Span;
+
+use super::{Bind, BindSimple, Expr, ExprFnDecl, SourceFile};
+use crate::HasSpan;
+
+/// The replacement of a span of source text.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TextEdit {
+    pub span: Span,
+    pub text: String,
+}
+
+impl TextEdit {
+    /// Returns `source` with this edit applied.
+    pub fn apply(&self, source: &str) -> String {
+        let mut edited = source.to_owned();
+        let range = self.span.start().to_usize()..self.span.end().to_usize();
+        edited.replace_range(range, &self.text);
+        edited
+    }
+}
+
+/// The reason an edit could not be made.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RewriteError {
+    /// No value is bound at the given path.
+    NotFound,
+    /// The value at the given path is not an attribute set.
+    NotASet(Span),
+    /// The value at the given path is not a list.
+    NotAList(Span),
+    /// The attribute to add is already bound.
+    AlreadyBound(Span),
+}
+
+impl Display for RewriteError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match *self {
+            RewriteError::NotFound => write!(fmt, "no value is bound at this path"),
+            RewriteError::NotASet(_) => write!(fmt, "value is not an attribute set"),
+            RewriteError::NotAList(_) => write!(fmt, "value is not a list"),
+            RewriteError::AlreadyBound(_) => write!(fmt, "attribute is already bound"),
+        }
+    }
+}
+
+impl Error for RewriteError {}
+
+/// Returns the value bound at `path`, or the set the file evaluates to if `path` is empty.
+pub fn find<'a>(file: &'a SourceFile, path: &[&str]) -> Result<&'a Expr, RewriteError> {
+    match path {
+        [] => root(file),
+        _ => find_value(root(file)?, path),
+    }
+}
+
+/// Replaces the value bound at `path` with `value`, which is inserted as written.
+pub fn set_attr_value(
+    file: &SourceFile,
+    path: &[&str],
+    value: &str,
+) -> Result<TextEdit, RewriteError> {
+    let bind = find_bind(root(file)?, path)?;
+    Ok(TextEdit {
+        span: bind.expr().span(),
+        text: value.to_owned(),
+    })
+}
+
+/// Adds the binding `name = value;` to the set at `path`, or to the set the file evaluates to if
+/// `path` is empty.
+///
+/// Both `name` and `value` are inserted as written, so `name` may be an attribute path such as
+/// `meta.license`. The binding is added after the last one of the set, on a line of its own if
+/// the set spans several lines.
+pub fn add_bind(
+    source: &str,
+    file: &SourceFile,
+    path: &[&str],
+    name: &str,
+    value: &str,
+) -> Result<TextEdit, RewriteError> {
+    let set = find(file, path)?;
+    let (span, binds) = match set_binds(set) {
+        Some(found) => found,
+        None => return Err(RewriteError::NotASet(set.span())),
+    };
+    let name_path: Vec<&str> = name.split('.').map(str::trim).collect();
+    if let Ok(existing) = find_bind(set, &name_path) {
+        return Err(RewriteError::AlreadyBound(existing.span()));
+    }
+
+    let open = span.start().to_usize();
+    let close = closing(source, span, '}').ok_or(RewriteError::NotASet(span))?;
+    let bind = format!("{} = {};", name, value);
+    let multi_line = source[open..close].contains('\n');
+    let last = binds.last().map(|bind| end_of_bind(source, bind.span()));
+
+    let (at, text) = match last {
+        Some(end) if multi_line => {
+            let line_end = line_end(source, end);
+            let indent = indent(source, binds[binds.len() - 1].span().start().to_usize());
+            (line_end, format!("\n{}{}", indent, bind))
+        }
+        Some(end) => (end, format!(" {}", bind)),
+        None if multi_line => {
+            let indent = format!("{}  ", indent(source, open));
+            (open + 1, format!("\n{}{}", indent, bind))
+        }
+        None => {
+            let edit = TextEdit {
+                span: Span::new(open as u32 + 1, close as u32),
+                text: format!(" {} ", bind),
+            };
+            return Ok(edit);
+        }
+    };
+    Ok(insert(at, text))
+}
+
+/// Removes the binding at `path`, along with the line it is on if it is the only code there and
+/// the comments directly above it.
+pub fn remove_bind(
+    source: &str,
+    file: &SourceFile,
+    path: &[&str],
+) -> Result<TextEdit, RewriteError> {
+    let bind = find_bind(root(file)?, path)?;
+    let span = bind.span();
+    let start = span.start().to_usize();
+    let end = end_of_bind(source, span);
+
+    let line_start = start - indent(source, start).len();
+    let rest = &source[end..line_end(source, end)];
+    let alone = source[line_start..start].trim().is_empty() && rest.trim().is_empty();
+    if !alone {
+        let trailing = source[end..].len() - source[end..].trim_start_matches(' ').len();
+        return Ok(TextEdit {
+            span: Span::new(start as u32, (end + trailing) as u32),
+            text: String::new(),
+        });
+    }
+
+    let mut from = line_start;
+    while from > 0 {
+        let previous = source[..from - 1].rfind('\n').map_or(0, |i| i + 1);
+        if !source[previous..from].trim_start().starts_with('#') {
+            break;
+        }
+        from = previous;
+    }
+    let to = (line_end(source, end) + 1).min(source.len());
+    Ok(TextEdit {
+        span: Span::new(from as u32, to as u32),
+        text: String::new(),
+    })
+}
+
+/// Appends `element`, inserted as written, to the list at `path`.
+///
+/// The element is added on a line of its own if the list spans several lines. Elements which are
+/// function applications must be parenthesized by the caller.
+pub fn append_list_element(
+    source: &str,
+    file: &SourceFile,
+    path: &[&str],
+    element: &str,
+) -> Result<TextEdit, RewriteError> {
+    let mut value = find(file, path)?;
+    while let Expr::Paren(ref paren) = *value {
+        value = paren.expr();
+    }
+    let list = match *value {
+        Expr::List(ref list) => list,
+        ref other => return Err(RewriteError::NotAList(other.span())),
+    };
+
+    let span = list.span();
+    let open = span.start().to_usize();
+    let close = closing(source, span, ']').ok_or(RewriteError::NotAList(span))?;
+    let multi_line = source[open..close].contains('\n');
+    let (at, text) = match list.elems().last() {
+        Some(last) if multi_line => {
+            let indent = indent(source, last.span().start().to_usize());
+            let end = last.span().end().to_usize();
+            (end, format!("\n{}{}", indent, element))
+        }
+        Some(last) => (last.span().end().to_usize(), format!(" {}", element)),
+        None if multi_line => {
+            let indent = format!("{}  ", indent(source, open));
+            (open + 1, format!("\n{}{}", indent, element))
+        }
+        None => {
+            let edit = TextEdit {
+                span: Span::new(open as u32 + 1, close as u32),
+                text: format!(" {} ", element),
+            };
+            return Ok(edit);
+        }
+    };
+    Ok(insert(at, text))
+}
+
+/// Returns the set a file evaluates to.
+fn root(file: &SourceFile) -> Result<&Expr, RewriteError> {
+    let expr = file.expr();
+    match set_of(expr) {
+        Some(set) => Ok(set),
+        None => Err(RewriteError::NotASet(expr.span())),
+    }
+}
+
+/// Looks through functions, `let`, `with`, parentheses and function applications for a set.
+fn set_of(mut expr: &Expr) -> Option<&Expr> {
+    loop {
+        expr = match *expr {
+            Expr::Set(_) | Expr::Rec(_) => return Some(expr),
+            Expr::Paren(ref paren) => paren.expr(),
+            Expr::FnDecl(ref decl) => match **decl {
+                ExprFnDecl::Simple(ref decl) => decl.body(),
+                ExprFnDecl::Formals(ref decl) => decl.body(),
+            },
+            Expr::LetIn(ref let_in) => let_in.body(),
+            Expr::With(ref with) => with.expr(),
+            Expr::Assert(ref assert) => assert.expr(),
+            Expr::FnApp(ref app) => app.argument(),
+            _ => return None,
+        };
+    }
+}
+
+fn set_binds(expr: &Expr) -> Option<(Span, &[Bind])> {
+    match *set_of(expr)? {
+        Expr::Set(ref set) => Some((set.span(), set.binds())),
+        Expr::Rec(ref rec) => Some((rec.span(), rec.binds())),
+        _ => None,
+    }
+}
+
+/// Returns the binding defining the value at `path` in `set`, following nested sets.
+fn find_bind<'a>(set: &'a Expr, path: &[&str]) -> Result<&'a BindSimple, RewriteError> {
+    let binds = set_binds(set).map(|(_, binds)| binds).unwrap_or_default();
+    for bind in binds {
+        let simple = match *bind {
+            Bind::Simple(ref simple) => simple,
+            _ => continue,
+        };
+        let segments = simple.attr().segments();
+        let matches = segments.len() <= path.len()
+            && segments
+                .iter()
+                .zip(path)
+                .all(|(s, name)| s.name() == Some(name));
+        if !matches {
+            continue;
+        }
+
+        let rest = &path[segments.len()..];
+        if rest.is_empty() {
+            return Ok(simple);
+        }
+        if let Ok(found) = find_bind(simple.expr(), rest) {
+            return Ok(found);
+        }
+    }
+    Err(RewriteError::NotFound)
+}
+
+fn find_value<'a>(set: &'a Expr, path: &[&str]) -> Result<&'a Expr, RewriteError> {
+    find_bind(set, path).map(BindSimple::expr)
+}
+
+/// Returns the offset of the delimiter `delim` closing the block at `span`.
+fn closing(source: &str, span: Span, delim: char) -> Option<usize> {
+    let start = span.start().to_usize();
+    source[start..span.end().to_usize()]
+        .rfind(delim)
+        .map(|i| start + i)
+}
+
+/// Returns the offset after the `;` ending the binding at `span`.
+fn end_of_bind(source: &str, span: Span) -> usize {
+    let end = span.end().to_usize();
+    match source[end..].find(|c: char| !c.is_whitespace()) {
+        Some(i) if source[end + i..].starts_with(';') => end + i + 1,
+        _ => end,
+    }
+}
+
+/// Returns the whitespace starting the line containing `offset`.
+fn indent(source: &str, offset: usize) -> &str {
+    let start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line = &source[start..];
+    &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+}
+
+fn line_end(source: &str, offset: usize) -> usize {
+    source[offset..]
+        .find('\n')
+        .map_or(source.len(), |i| offset + i)
+}
+
+fn insert(at: usize, text: String) -> TextEdit {
+    TextEdit {
+        span: Span::new(at as u32, at as u32),
+        text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKAGE: &str = r#"{ stdenv, fetchFromGitHub }:
+
+stdenv.mkDerivation rec {
+  pname = "hello";
+  version = "2.10"; # bumped by hand
+
+  src = fetchFromGitHub {
+    owner = "gnu";
+    repo = pname;
+    rev = "v${version}";
+  };
+
+  # Not needed since 2.9.
+  patches = [ ./fix.patch ];
+  buildInputs = [
+    zlib
+  ];
+}
+"#;
+
+    fn edit(f: impl Fn(&str, &SourceFile) -> Result<TextEdit, RewriteError>) -> String {
+        let file: SourceFile = PACKAGE.parse().unwrap();
+        f(PACKAGE, &file).unwrap().apply(PACKAGE)
+    }
+
+    #[test]
+    fn sets_and_adds_bindings() {
+        let edited = edit(|_, f| set_attr_value(f, &["src", "rev"], "\"abc\""));
+        assert!(edited.contains("    rev = \"abc\";\n"));
+        let edited = edit(|_, f| set_attr_value(f, &["version"], "\"2.12\""));
+        assert!(edited.contains("version = \"2.12\"; # bumped by hand\n"));
+
+        let edited = edit(|s, f| add_bind(s, f, &["src"], "hash", "\"\""));
+        assert!(edited.contains("    rev = \"v${version}\";\n    hash = \"\";\n  };"));
+        let edited = edit(|s, f| add_bind(s, f, &[], "meta.license", "lib.licenses.gpl3"));
+        assert!(edited.contains("  ];\n  meta.license = lib.licenses.gpl3;\n}"));
+
+        let file: SourceFile = PACKAGE.parse().unwrap();
+        let existing = add_bind(PACKAGE, &file, &["src"], "owner", "\"x\"");
+        assert!(matches!(existing, Err(RewriteError::AlreadyBound(_))));
+        let missing = set_attr_value(&file, &["src", "sha256"], "\"\"");
+        assert_eq!(missing, Err(RewriteError::NotFound));
+
+        let source = "{ a = { }; b = { c = 1; }; }";
+        let file: SourceFile = source.parse().unwrap();
+        let edited = add_bind(source, &file, &["a"], "d", "2")
+            .unwrap()
+            .apply(source);
+        assert_eq!(edited, "{ a = { d = 2; }; b = { c = 1; }; }");
+        let edited = add_bind(source, &file, &["b"], "d", "2")
+            .unwrap()
+            .apply(source);
+        assert_eq!(edited, "{ a = { }; b = { c = 1; d = 2; }; }");
+    }
+
+    #[test]
+    fn removes_bindings() {
+        let edited = edit(|s, f| remove_bind(s, f, &["patches"]));
+        assert!(edited.contains("  };\n\n  buildInputs = ["));
+        let edited = edit(|s, f| remove_bind(s, f, &["src", "repo"]));
+        assert!(edited.contains("    owner = \"gnu\";\n    rev = "));
+
+        let source = "{ a = 1; b = 2; }";
+        let file: SourceFile = source.parse().unwrap();
+        let edited = remove_bind(source, &file, &["a"]).unwrap().apply(source);
+        assert_eq!(edited, "{ b = 2; }");
+    }
+
+    #[test]
+    fn appends_list_elements() {
+        let edited = edit(|s, f| append_list_element(s, f, &["buildInputs"], "openssl"));
+        assert!(edited.contains("  buildInputs = [\n    zlib\n    openssl\n  ];"));
+        let edited = edit(|s, f| append_list_element(s, f, &["patches"], "./b.patch"));
+        assert!(edited.contains("patches = [ ./fix.patch ./b.patch ];"));
+
+        let source = "{ a = [ ]; b = [\n]; c = 1; }";
+        let file: SourceFile = source.parse().unwrap();
+        let edited = append_list_element(source, &file, &["a"], "x").unwrap();
+        assert_eq!(edited.apply(source), "{ a = [ x ]; b = [\n]; c = 1; }");
+        let edited = append_list_element(source, &file, &["b"], "x").unwrap();
+        assert_eq!(edited.apply(source), "{ a = [ ]; b = [\n  x\n]; c = 1; }");
+        let not_a_list = append_list_element(source, &file, &["c"], "x");
+        assert!(matches!(not_a_list, Err(RewriteError::NotAList(_))));
+    }
+}
@@ -0,0 +1,369 @@
+//! A lossless syntax tree layered over the AST.
+//!
+//! The AST drops whitespace, most comments and the original form of escape sequences, which
+//! formatters and refactoring tools need to keep intact. A [`SyntaxTree`] covers every byte of the
+//! source text instead: its nodes are the expressions of the AST, and its leaves are the tokens of
+//! the file along with the trivia between them, each keeping the slice of source text it was
+//! lexed from. Writing out the leaves in order, e.g. with the `Display` implementation of a node,
+//! reproduces the text it covers byte for byte.
+//!
+//! Trivia and tokens outside of any expression belong to the innermost expression containing
+//! them, and to the root node of the tree if there is none.
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use codespan::Span;
+
+use super::visit::{children, descendants};
+use super::{Expr, SourceFile};
+use crate::error::Errors;
+use crate::lexer::{Lexer, StringFragment, Token};
+use crate::{HasSpan, ToSpan};
+
+/// The kind of text a [`SyntaxToken`] holds.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TokenKind {
+    /// Spaces, tabs and line breaks between tokens.
+    Whitespace,
+    /// A line or block comment, including its delimiters.
+    Comment,
+    /// The text of a string between its delimiters and interpolations, with escape sequences as
+    /// written.
+    StringText,
+    /// The quotes around a string, or the `${` and `}` around an interpolation.
+    Delimiter,
+    /// Any other token, such as an identifier, a literal, a keyword or an operator.
+    Token,
+}
+
+/// A leaf of a syntax tree.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SyntaxToken<'a> {
+    kind: TokenKind,
+    span: Span,
+    text: &'a str,
+}
+
+impl<'a> SyntaxToken<'a> {
+    pub fn kind(&self) -> TokenKind {
+        self.kind
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Returns the source text of this token.
+    pub fn text(&self) -> &'a str {
+        self.text
+    }
+
+    /// Returns whether this token is whitespace or a comment.
+    pub fn is_trivia(&self) -> bool {
+        self.kind == TokenKind::Whitespace || self.kind == TokenKind::Comment
+    }
+}
+
+/// A child of a [`SyntaxNode`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum SyntaxElement<'a> {
+    Node(SyntaxNode<'a>),
+    Token(SyntaxToken<'a>),
+}
+
+/// An expression of the AST, or the whole file, along with the tokens and trivia it spans.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyntaxNode<'a> {
+    expr: Option<&'a Expr>,
+    span: Span,
+    children: Vec<SyntaxElement<'a>>,
+}
+
+impl<'a> SyntaxNode<'a> {
+    /// Returns the expression of this node, or `None` for the root node of the file.
+    pub fn expr(&self) -> Option<&'a Expr> {
+        self.expr
+    }
+
+    /// Returns the span of the text this node covers.
+    ///
+    /// This may be wider than the span of its expression when trivia next to the expression
+    /// belongs to no subexpression of it.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn children(&self) -> &[SyntaxElement<'a>] {
+        &self.children
+    }
+
+    /// Iterates over the tokens of this node and of all its descendants, in source order.
+    pub fn tokens(&self) -> impl Iterator<Item = &SyntaxToken<'a>> + '_ {
+        let mut tokens = Vec::new();
+        collect_tokens(self, &mut tokens);
+        tokens.into_iter()
+    }
+}
+
+fn collect_tokens<'n, 'a>(node: &'n SyntaxNode<'a>, out: &mut Vec<&'n SyntaxToken<'a>>) {
+    for child in &node.children {
+        match *child {
+            SyntaxElement::Node(ref node) => collect_tokens(node, out),
+            SyntaxElement::Token(ref token) => out.push(token),
+        }
+    }
+}
+
+impl<'a> Display for SyntaxNode<'a> {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        self.tokens()
+            .try_for_each(|token| fmt.write_str(token.text))
+    }
+}
+
+/// A lossless syntax tree of a source file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyntaxTree<'a> {
+    root: SyntaxNode<'a>,
+}
+
+impl<'a> SyntaxTree<'a> {
+    /// Builds the syntax tree of `file`, which was parsed from `source`.
+    pub fn new(file: &'a SourceFile, source: &'a str) -> Result<Self, Errors> {
+        let lexer = Lexer::new(source)?;
+
+        let mut leaves = Vec::new();
+        flatten(lexer.tokens().iter(), TokenKind::Token, &mut leaves);
+        leaves.sort_by_key(|&(_, span)| span.start());
+
+        // Split the gaps between tokens wherever an expression starts or ends, so that trivia
+        // never straddles the boundary of a node.
+        let mut boundaries: Vec<usize> = descendants(file.expr())
+            .flat_map(|expr| {
+                let span = expr.span();
+                vec![span.start().to_usize(), span.end().to_usize()]
+            })
+            .collect();
+        boundaries.sort();
+        boundaries.dedup();
+
+        let mut tokens = Vec::new();
+        let mut offset = 0;
+        let push_gap = |tokens: &mut Vec<SyntaxToken<'a>>, start: usize, end: usize| {
+            let cuts = boundaries.iter().filter(|&&b| start < b && b < end);
+            let mut from = start;
+            for to in cuts.cloned().chain(Some(end)) {
+                gap(source, from, to, tokens);
+                from = to;
+            }
+        };
+        for (kind, span) in leaves {
+            let (start, end) = (span.start().to_usize(), span.end().to_usize());
+            if start < offset || start == end {
+                continue;
+            }
+            push_gap(&mut tokens, offset, start);
+            tokens.push(SyntaxToken {
+                kind,
+                span,
+                text: &source[start..end],
+            });
+            offset = end;
+        }
+        push_gap(&mut tokens, offset, source.len());
+
+        let mut tokens = tokens.into_iter().peekable();
+        let mut children = Vec::new();
+        let mut root = Some(file.expr());
+        while let Some(token) = tokens.peek().cloned() {
+            match root {
+                Some(expr) if token.span.start() >= expr.span().start() => {
+                    children.push(SyntaxElement::Node(node(expr, &mut tokens)));
+                    root = None;
+                }
+                _ => {
+                    children.push(SyntaxElement::Token(token));
+                    tokens.next();
+                }
+            }
+        }
+
+        let root = SyntaxNode {
+            expr: None,
+            span: Span::new(0, source.len() as u32),
+            children,
+        };
+        Ok(SyntaxTree { root })
+    }
+
+    /// Returns the node covering the whole file.
+    pub fn root(&self) -> &SyntaxNode<'a> {
+        &self.root
+    }
+}
+
+impl<'a> Display for SyntaxTree<'a> {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        self.root.fmt(fmt)
+    }
+}
+
+/// Builds the node of `expr` out of the tokens starting within its span.
+fn node<'a, I>(expr: &'a Expr, tokens: &mut std::iter::Peekable<I>) -> SyntaxNode<'a>
+where
+    I: Iterator<Item = SyntaxToken<'a>>,
+{
+    let span = expr.span();
+    let mut subexprs = children(expr).into_iter().peekable();
+    let mut children = Vec::new();
+    let mut end = span.end();
+
+    while let Some(token) = tokens.peek().cloned() {
+        let start = token.span.start();
+        if start >= span.end() {
+            break;
+        }
+        match subexprs.peek().cloned() {
+            Some(child) if start >= child.span().start() => {
+                subexprs.next();
+                let node = node(child, tokens);
+                end = end.max(node.span.end());
+                children.push(SyntaxElement::Node(node));
+            }
+            _ => {
+                end = end.max(token.span.end());
+                children.push(SyntaxElement::Token(token));
+                tokens.next();
+            }
+        }
+    }
+
+    // Expressions recovered from errors may span no text at all.
+    for child in subexprs {
+        children.push(SyntaxElement::Node(node(child, tokens)));
+    }
+
+    SyntaxNode {
+        expr: Some(expr),
+        span: Span::new(span.start(), end),
+        children,
+    }
+}
+
+/// Collects the spans of `tokens`, along with those of the text and tokens within strings.
+fn flatten<'a, 'b>(
+    tokens: impl Iterator<Item = &'b Token<'a>>,
+    kind: TokenKind,
+    out: &mut Vec<(TokenKind, Span)>,
+) where
+    'a: 'b,
+{
+    for token in tokens {
+        match *token {
+            Token::Eof(_) => {}
+            Token::Comment(..) => out.push((TokenKind::Comment, token.to_span())),
+            Token::Interpolation(ref inner, _) => flatten(inner.iter(), kind, out),
+            Token::String(ref fragments, ..) => {
+                for fragment in fragments {
+                    match *fragment {
+                        StringFragment::Literal(_, span) => out.push((TokenKind::StringText, span)),
+                        StringFragment::Interpolation(ref inner, _) => {
+                            flatten(inner.iter(), kind, out)
+                        }
+                    }
+                }
+            }
+            _ => out.push((kind, token.to_span())),
+        }
+    }
+}
+
+/// Pushes the text between two tokens, splitting it into whitespace and delimiters.
+fn gap<'a>(source: &'a str, start: usize, end: usize, out: &mut Vec<SyntaxToken<'a>>) {
+    let text = &source[start..end];
+    let mut from = 0;
+    while from < text.len() {
+        let is_space = text[from..].starts_with(char::is_whitespace);
+        let len = text[from..]
+            .find(|c: char| c.is_whitespace() != is_space)
+            .unwrap_or(text.len() - from);
+        let kind = if is_space {
+            TokenKind::Whitespace
+        } else {
+            TokenKind::Delimiter
+        };
+        let span = Span::new((start + from) as u32, (start + from + len) as u32);
+        out.push(SyntaxToken {
+            kind,
+            span,
+            text: &text[from..from + len],
+        });
+        from += len;
+    }
+}
+
+impl SourceFile {
+    /// Builds the lossless syntax tree of this file, given the source text it was parsed from.
+    pub fn syntax_tree<'a>(&'a self, source: &'a str) -> Result<SyntaxTree<'a>, Errors> {
+        SyntaxTree::new(self, source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HasSpan;
+
+    #[test]
+    fn reproduces_source_text() {
+        let sources = [
+            include_str!("../../example.nix"),
+            "# file\n{ a = \"x\\n${ b /* c */ }\\${d}\"; b = ''\n  ''${x} ${y}\n''; }\n\n",
+            "let\n  f = { x ? 1, ... }: x # trailing\n    + 2;\nin f { }\n",
+        ];
+        for source in &sources {
+            let file: SourceFile = source.parse().unwrap();
+            let tree = file.syntax_tree(source).unwrap();
+            assert_eq!(tree.to_string(), *source);
+        }
+    }
+
+    #[test]
+    fn nests_tokens_in_expressions() {
+        let source = "[ 1 /* one */ \"a\\tb\" ]\n";
+        let file: SourceFile = source.parse().unwrap();
+        let tree = file.syntax_tree(source).unwrap();
+
+        let list = match tree.root().children() {
+            [SyntaxElement::Node(list), SyntaxElement::Token(newline)] => {
+                assert_eq!(newline.kind(), TokenKind::Whitespace);
+                list
+            }
+            children => panic!("unexpected children: {:?}", children),
+        };
+        assert_eq!(list.expr().map(HasSpan::span), Some(file.expr().span()));
+
+        let kinds: Vec<_> = list.tokens().map(|t| (t.kind(), t.text())).collect();
+        assert_eq!(
+            kinds,
+            [
+                (TokenKind::Token, "["),
+                (TokenKind::Whitespace, " "),
+                (TokenKind::Token, "1"),
+                (TokenKind::Whitespace, " "),
+                (TokenKind::Comment, "/* one */"),
+                (TokenKind::Whitespace, " "),
+                (TokenKind::Delimiter, "\""),
+                (TokenKind::StringText, "a\\tb"),
+                (TokenKind::Delimiter, "\""),
+                (TokenKind::Whitespace, " "),
+                (TokenKind::Token, "]"),
+            ]
+        );
+        let string = list.children().iter().find_map(|child| match *child {
+            SyntaxElement::Node(ref node) if node.to_string() == "\"a\\tb\"" => Some(node),
+            _ => None,
+        });
+        assert!(string.is_some());
+    }
+}
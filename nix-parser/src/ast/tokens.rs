@@ -1,32 +1,107 @@
+use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use codespan::Span;
+use nix_parser_derive::{HasSpan, SpansMut};
 use url::Url;
 
-use crate::{HasSpan, ToSpan};
+use crate::intern::intern;
+pub use crate::lexer::{CommentKind, StringKind};
+use crate::ToSpan;
+
+/// A comment, kept as written so that it can be printed back exactly.
+///
+/// Consecutive `#` lines form a single line comment, whose text is the lines following each `#`.
+/// The text of a block comment is everything between its delimiters.
+#[derive(Clone, Debug, Eq, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Comment {
+    // Boxed rather than a `String` so that the kind fits without growing `Bind`.
+    #[span(skip)]
+    text: Box<str>,
+    #[span(skip)]
+    kind: CommentKind,
+    span: Span,
+}
+
+impl Comment {
+    pub fn new<S: ToSpan>(text: impl Into<String>, kind: CommentKind, span: S) -> Self {
+        Comment {
+            text: text.into().into_boxed_str(),
+            kind,
+            span: span.to_span(),
+        }
+    }
+
+    pub fn kind(&self) -> CommentKind {
+        self.kind
+    }
+
+    /// Returns whether this is a documentation comment, delimited by `/**` and `*/`.
+    pub fn is_doc(&self) -> bool {
+        self.kind == CommentKind::Doc
+    }
 
-#[derive(Clone, Debug, Eq)]
-pub struct Comment(String, Span);
+    /// Returns the text of the comment as written, without its delimiters.
+    pub fn raw(&self) -> &str {
+        &self.text
+    }
+
+    /// Returns the lines of the comment as written, without the leading `#` of line comments.
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.text.split('\n')
+    }
+
+    /// Returns the content of the comment, with the decoration and indentation of block comments
+    /// removed.
+    pub fn text(&self) -> Cow<'_, str> {
+        if self.kind == CommentKind::Line {
+            return Cow::Borrowed(&self.text);
+        }
+
+        let text = self.text.trim_start_matches('*').trim_end_matches('*');
+        let mut lines = text.split('\n');
+        // The text on the line of the opening delimiter is not indented like the lines below it.
+        let first = lines.next().unwrap_or_default().trim();
+        let rest: Vec<_> = lines.collect();
+        let indent = rest
+            .iter()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.len() - line.trim_start().len())
+            .min()
+            .unwrap_or(0);
+
+        let mut content = first.to_owned();
+        for line in rest {
+            content.push('\n');
+            content.push_str(line.get(indent..).unwrap_or_else(|| line.trim_start()));
+        }
+        Cow::Owned(content.trim_start_matches('\n').trim_end().to_owned())
+    }
+}
 
 impl Display for Comment {
     fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
-        self.0
-            .lines()
-            .try_for_each(|line| writeln!(fmt, "#{}", line))
+        match self.kind {
+            CommentKind::Line => self.lines().try_for_each(|line| writeln!(fmt, "#{}", line)),
+            CommentKind::Block => write!(fmt, "/*{}*/", self.text),
+            CommentKind::Doc => write!(fmt, "/**{}*/", self.text),
+        }
     }
 }
 
 impl<'a> From<&'a str> for Comment {
     fn from(s: &'a str) -> Self {
-        Comment(s.to_owned(), Span::initial())
+        Comment::new(s, CommentKind::Line, Span::initial())
     }
 }
 
 impl From<String> for Comment {
     fn from(s: String) -> Self {
-        Comment(s, Span::initial())
+        Comment::new(s, CommentKind::Line, Span::initial())
     }
 }
 
@@ -36,30 +111,56 @@ where
     S: ToSpan,
 {
     fn from((string, span): (T, S)) -> Self {
-        Comment(string.into(), span.to_span())
-    }
-}
-
-impl HasSpan for Comment {
-    fn span(&self) -> Span {
-        self.1
+        Comment::new(string, CommentKind::Line, span)
     }
 }
 
 impl PartialEq for Comment {
     fn eq(&self, other: &Self) -> bool {
-        self.0 == other.0
+        self.kind == other.kind && self.text == other.text
     }
 }
 
 impl PartialOrd for Comment {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.0.partial_cmp(&other.0)
+        self.text.partial_cmp(&other.text)
     }
 }
 
-#[derive(Clone, Debug, Eq)]
-pub struct Ident(String, Span);
+/// An identifier, whose name may be shared with other identifiers through an
+/// [`Interner`](crate::intern::Interner).
+#[derive(Clone, Debug, Eq, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ident(
+    #[span(skip)]
+    #[cfg_attr(feature = "serde", serde(with = "name"))]
+    Arc<str>,
+    Span,
+);
+
+/// Serializes the name of an identifier as a string, interning it again when read back.
+#[cfg(feature = "serde")]
+mod name {
+    use std::sync::Arc;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::intern::intern;
+
+    pub fn serialize<S: Serializer>(name: &Arc<str>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(name)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Arc<str>, D::Error> {
+        String::deserialize(deserializer).map(|name| intern(&name))
+    }
+}
+
+impl Ident {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
 
 impl Display for Ident {
     fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
@@ -69,29 +170,23 @@ impl Display for Ident {
 
 impl<'a> From<&'a str> for Ident {
     fn from(s: &'a str) -> Self {
-        Ident(s.to_owned(), Span::initial())
+        Ident(intern(s), Span::initial())
     }
 }
 
 impl From<String> for Ident {
     fn from(s: String) -> Self {
-        Ident(s, Span::initial())
+        Ident(intern(&s), Span::initial())
     }
 }
 
 impl<T, S> From<(T, S)> for Ident
 where
-    T: Into<String>,
+    T: AsRef<str>,
     S: ToSpan,
 {
     fn from((string, span): (T, S)) -> Self {
-        Ident(string.into(), span.to_span())
-    }
-}
-
-impl HasSpan for Ident {
-    fn span(&self) -> Span {
-        self.1
+        Ident(intern(string.as_ref()), span.to_span())
     }
 }
 
@@ -107,17 +202,20 @@ impl PartialOrd for Ident {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, HasSpan, SpansMut)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Literal {
     Null(Span),
-    Boolean(bool, Span),
-    Float(f64, Span),
-    Integer(i64, Span),
-    Path(PathBuf, Span),
-    PathTemplate(PathBuf, Span),
-    Uri(Url, Span),
+    Boolean(#[span(skip)] bool, Span),
+    Float(#[span(skip)] f64, Span),
+    Integer(#[span(skip)] i64, Span),
+    Path(#[span(skip)] Box<PathBuf>, Span),
+    PathTemplate(#[span(skip)] Box<PathBuf>, Span),
+    Uri(#[span(skip)] Box<Url>, Span),
 }
 
+impl Literal {}
+
 impl Display for Literal {
     fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
         match *self {
@@ -166,13 +264,13 @@ impl From<i64> for Literal {
 
 impl<'a> From<&'a Path> for Literal {
     fn from(path: &'a Path) -> Self {
-        Literal::Path(path.to_owned(), Span::initial())
+        Literal::Path(Box::new(path.to_owned()), Span::initial())
     }
 }
 
 impl From<PathBuf> for Literal {
     fn from(path: PathBuf) -> Self {
-        Literal::Path(path, Span::initial())
+        Literal::Path(Box::new(path), Span::initial())
     }
 }
 
@@ -202,33 +300,19 @@ impl<S: ToSpan> From<(i64, S)> for Literal {
 
 impl<'a, S: ToSpan> From<(&'a Path, S)> for Literal {
     fn from((path, span): (&'a Path, S)) -> Self {
-        Literal::Path(path.to_owned(), span.to_span())
+        Literal::Path(Box::new(path.to_owned()), span.to_span())
     }
 }
 
 impl<S: ToSpan> From<(PathBuf, S)> for Literal {
     fn from((path, span): (PathBuf, S)) -> Self {
-        Literal::Path(path, span.to_span())
+        Literal::Path(Box::new(path), span.to_span())
     }
 }
 
 impl<S: ToSpan> From<(Url, S)> for Literal {
     fn from((uri, span): (Url, S)) -> Self {
-        Literal::Uri(uri, span.to_span())
-    }
-}
-
-impl HasSpan for Literal {
-    fn span(&self) -> Span {
-        match *self {
-            Literal::Null(ref e) => *e,
-            Literal::Boolean(_, ref e) => *e,
-            Literal::Float(_, ref e) => *e,
-            Literal::Integer(_, ref e) => *e,
-            Literal::Path(_, ref e) => *e,
-            Literal::PathTemplate(_, ref e) => *e,
-            Literal::Uri(_, ref e) => *e,
-        }
+        Literal::Uri(Box::new(uri), span.to_span())
     }
 }
 
@@ -0,0 +1,181 @@
+//! Source-order iteration over bindings together with the comments and blank lines around them.
+//!
+//! The AST keeps the comment documenting a simple binding, but not the blank lines separating
+//! bindings, nor comments trailing them on the same line. Formatters and documentation generators
+//! need both, so this recovers them from the source text the AST was parsed from.
+
+use super::{Bind, ExprLet, ExprLetIn, ExprRec, ExprSet};
+use crate::HasSpan;
+
+/// A binding along with the trivia surrounding it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BindTrivia<'a> {
+    pub bind: &'a Bind,
+    /// The comments between the previous binding, or the start of the block, and this one.
+    pub comments: Vec<&'a str>,
+    /// The number of blank lines before this binding, or before its leading comments.
+    pub blank_lines: usize,
+    /// A comment following the binding on the same line.
+    pub trailing: Option<&'a str>,
+}
+
+/// An iterator over bindings with their trivia, in source order.
+#[derive(Clone, Debug)]
+pub struct Trivia<'a> {
+    source: &'a str,
+    binds: std::slice::Iter<'a, Bind>,
+    offset: usize,
+}
+
+impl<'a> Trivia<'a> {
+    /// Iterates over `binds`, parsed from `source`, whose block starts before byte `start`.
+    pub fn new(source: &'a str, start: usize, binds: &'a [Bind]) -> Self {
+        Trivia {
+            source,
+            binds: binds.iter(),
+            offset: start,
+        }
+    }
+}
+
+impl<'a> Iterator for Trivia<'a> {
+    type Item = BindTrivia<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bind = self.binds.next()?;
+        let source = self.source;
+
+        let mut comments = Vec::new();
+        let mut blank_lines = None;
+        let mut offset = self.offset;
+        loop {
+            let rest = &source[offset..];
+            let trimmed = rest.trim_start();
+            let layout = &rest[..rest.len() - trimmed.len()];
+            blank_lines.get_or_insert(layout.matches('\n').count().saturating_sub(1));
+            offset += layout.len();
+
+            let len = if trimmed.starts_with('#') {
+                trimmed.find('\n').unwrap_or(trimmed.len())
+            } else if trimmed.starts_with("/*") {
+                trimmed.find("*/").map_or(trimmed.len(), |end| end + 2)
+            } else {
+                break;
+            };
+            comments.push(trimmed[..len].trim_end());
+            offset += len;
+        }
+
+        let end = bind.span().end().to_usize().max(offset);
+        let end = source[end..]
+            .find(';')
+            .map_or(source.len(), |semi| end + semi + 1);
+        let rest = &source[end..];
+        let line = rest[..rest.find('\n').unwrap_or(rest.len())].trim();
+        let trailing = if line.starts_with('#') || line.starts_with("/*") && line.ends_with("*/") {
+            Some(line)
+        } else {
+            None
+        };
+        self.offset = match trailing {
+            Some(comment) => comment.as_ptr() as usize - source.as_ptr() as usize + comment.len(),
+            None => end,
+        };
+
+        Some(BindTrivia {
+            bind,
+            comments,
+            blank_lines: blank_lines.unwrap_or(0),
+            trailing,
+        })
+    }
+}
+
+/// Returns the offset just past the first `{` at or after `start`.
+fn after_brace(source: &str, start: usize) -> usize {
+    source[start..].find('{').map_or(start, |i| start + i + 1)
+}
+
+impl ExprSet {
+    /// Iterates over the bindings of this set with their trivia, given the source text it was
+    /// parsed from.
+    pub fn binds_with_trivia<'a>(&'a self, source: &'a str) -> Trivia<'a> {
+        let start = after_brace(source, self.span().start().to_usize());
+        Trivia::new(source, start, self.binds())
+    }
+}
+
+impl ExprRec {
+    /// Iterates over the bindings of this set with their trivia, given the source text it was
+    /// parsed from.
+    pub fn binds_with_trivia<'a>(&'a self, source: &'a str) -> Trivia<'a> {
+        let start = after_brace(source, self.span().start().to_usize());
+        Trivia::new(source, start, self.binds())
+    }
+}
+
+impl ExprLet {
+    /// Iterates over the bindings of this block with their trivia, given the source text it was
+    /// parsed from.
+    pub fn binds_with_trivia<'a>(&'a self, source: &'a str) -> Trivia<'a> {
+        let start = after_brace(source, self.span().start().to_usize());
+        Trivia::new(source, start, self.binds())
+    }
+}
+
+impl ExprLetIn {
+    /// Iterates over the bindings of this block with their trivia, given the source text it was
+    /// parsed from.
+    pub fn binds_with_trivia<'a>(&'a self, source: &'a str) -> Trivia<'a> {
+        let start = self.span().start().to_usize() + "let".len();
+        Trivia::new(source, start, self.binds())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Expr, SourceFile};
+
+    #[test]
+    fn attaches_comments_and_blank_lines() {
+        let source = "{
+  # The first.
+  a = 1; # one
+
+
+  /* inherited */
+  # names
+  inherit b c;
+  d = { e = 2; }; f = 3;
+}";
+        let file: SourceFile = source.parse().unwrap();
+        let set = match *file.expr() {
+            Expr::Set(ref set) => set,
+            ref other => panic!("expected a set, found {}", other),
+        };
+
+        let trivia: Vec<_> = set
+            .binds_with_trivia(source)
+            .map(|t| (t.bind.to_string(), t.comments, t.blank_lines, t.trailing))
+            .collect();
+        assert_eq!(
+            trivia,
+            [
+                (
+                    "# The first.\na = 1;".into(),
+                    vec!["# The first."],
+                    0,
+                    Some("# one")
+                ),
+                (
+                    "inherit b c;".into(),
+                    vec!["/* inherited */", "# names"],
+                    2,
+                    None
+                ),
+                ("d = {e = 2;};".into(), vec![], 0, None),
+                ("f = 3;".into(), vec![], 0, None),
+            ]
+        );
+    }
+}
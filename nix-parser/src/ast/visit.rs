@@ -0,0 +1,852 @@
+//! Traversal of syntax trees without matching on every node type.
+//!
+//! [`Visitor`] has a `visit_*` method for each node type, whose default implementation calls the
+//! matching `walk_*` function to visit the node's children in source order. Implementors override
+//! only the methods for the nodes they care about, and call the `walk_*` function from them to
+//! keep descending. [`VisitorMut`] does the same for mutable references.
+//!
+//! A few helpers are built on [`Visitor`] for code which only cares about expressions:
+//! [`children`] returns the direct subexpressions of an expression in source order, and
+//! [`descendants`] every expression below one in pre-order. Expressions do not link to their
+//! parents, so [`walk`] and [`path_at`] hand out the chain of expressions enclosing each one
+//! instead, outermost first.
+//!
+//! ```
+//! use nix_parser::ast::tokens::Ident;
+//! use nix_parser::ast::visit::Visitor;
+//! use nix_parser::ast::SourceFile;
+//!
+//! struct Names(Vec<String>);
+//!
+//! impl<'a> Visitor<'a> for Names {
+//!     fn visit_ident(&mut self, ident: &'a Ident) {
+//!         self.0.push(ident.to_string());
+//!     }
+//! }
+//!
+//! let file: SourceFile = "{ a, b ? 1 }: a + b".parse().unwrap();
+//! let mut names = Names(Vec::new());
+//! names.visit_source_file(&file);
+//! assert_eq!(names.0, ["a", "b", "a", "b"]);
+//! ```
+
+use std::iter::FusedIterator;
+
+use super::tokens::{Comment, Ident, Literal};
+use super::*;
+
+/// Visits the nodes of a syntax tree by reference.
+pub trait Visitor<'a> {
+    fn visit_source_file(&mut self, file: &'a SourceFile) {
+        walk_source_file(self, file)
+    }
+
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        walk_expr(self, expr)
+    }
+
+    fn visit_paren(&mut self, paren: &'a ExprParen) {
+        walk_paren(self, paren)
+    }
+
+    fn visit_interpolation(&mut self, interp: &'a ExprInterpolation) {
+        walk_interpolation(self, interp)
+    }
+
+    fn visit_list(&mut self, list: &'a ExprList) {
+        walk_list(self, list)
+    }
+
+    fn visit_string(&mut self, string: &'a ExprString) {
+        walk_string(self, string)
+    }
+
+    fn visit_string_fragment(&mut self, fragment: &'a StringFragment) {
+        walk_string_fragment(self, fragment)
+    }
+
+    fn visit_set(&mut self, set: &'a ExprSet) {
+        walk_set(self, set)
+    }
+
+    fn visit_unary(&mut self, unary: &'a ExprUnary) {
+        walk_unary(self, unary)
+    }
+
+    fn visit_binary(&mut self, binary: &'a ExprBinary) {
+        walk_binary(self, binary)
+    }
+
+    fn visit_let(&mut self, let_: &'a ExprLet) {
+        walk_let(self, let_)
+    }
+
+    fn visit_rec(&mut self, rec: &'a ExprRec) {
+        walk_rec(self, rec)
+    }
+
+    fn visit_proj(&mut self, proj: &'a ExprProj) {
+        walk_proj(self, proj)
+    }
+
+    fn visit_if(&mut self, if_: &'a ExprIf) {
+        walk_if(self, if_)
+    }
+
+    fn visit_or(&mut self, or: &'a ExprOr) {
+        walk_or(self, or)
+    }
+
+    fn visit_assert(&mut self, assert: &'a ExprAssert) {
+        walk_assert(self, assert)
+    }
+
+    fn visit_with(&mut self, with: &'a ExprWith) {
+        walk_with(self, with)
+    }
+
+    fn visit_let_in(&mut self, let_in: &'a ExprLetIn) {
+        walk_let_in(self, let_in)
+    }
+
+    fn visit_fn_decl(&mut self, decl: &'a ExprFnDecl) {
+        walk_fn_decl(self, decl)
+    }
+
+    fn visit_fn_decl_simple(&mut self, decl: &'a FnDeclSimple) {
+        walk_fn_decl_simple(self, decl)
+    }
+
+    fn visit_fn_decl_formals(&mut self, decl: &'a FnDeclFormals) {
+        walk_fn_decl_formals(self, decl)
+    }
+
+    fn visit_formal(&mut self, formal: &'a Formal) {
+        walk_formal(self, formal)
+    }
+
+    fn visit_fn_app(&mut self, app: &'a ExprFnApp) {
+        walk_fn_app(self, app)
+    }
+
+    fn visit_bind(&mut self, bind: &'a Bind) {
+        walk_bind(self, bind)
+    }
+
+    fn visit_bind_simple(&mut self, bind: &'a BindSimple) {
+        walk_bind_simple(self, bind)
+    }
+
+    fn visit_bind_inherit(&mut self, bind: &'a BindInherit) {
+        walk_bind_inherit(self, bind)
+    }
+
+    fn visit_bind_inherit_expr(&mut self, bind: &'a BindInheritExpr) {
+        walk_bind_inherit_expr(self, bind)
+    }
+
+    fn visit_attr_path(&mut self, path: &'a AttrPath) {
+        walk_attr_path(self, path)
+    }
+
+    fn visit_attr_segment(&mut self, segment: &'a AttrSegment) {
+        walk_attr_segment(self, segment)
+    }
+
+    fn visit_ident(&mut self, _ident: &'a Ident) {}
+
+    fn visit_literal(&mut self, _literal: &'a Literal) {}
+
+    fn visit_comment(&mut self, _comment: &'a Comment) {}
+
+    /// Visits the span of a node which could not be parsed, i.e. `Expr::Error` or `Expr::Trap`.
+    fn visit_error(&mut self, _span: Span) {}
+}
+
+pub fn walk_source_file<'a, V: Visitor<'a> + ?Sized>(v: &mut V, file: &'a SourceFile) {
+    if let Some(ref comment) = file.comment {
+        v.visit_comment(comment);
+    }
+    v.visit_expr(&file.expr);
+}
+
+pub fn walk_expr<'a, V: Visitor<'a> + ?Sized>(v: &mut V, expr: &'a Expr) {
+    match *expr {
+        Expr::Paren(ref e) => v.visit_paren(e),
+        Expr::Ident(ref e) => v.visit_ident(e),
+        Expr::Interpolation(ref e) => v.visit_interpolation(e),
+        Expr::Literal(ref e) => v.visit_literal(e),
+        Expr::List(ref e) => v.visit_list(e),
+        Expr::String(ref e) => v.visit_string(e),
+        Expr::Set(ref e) => v.visit_set(e),
+        Expr::Unary(ref e) => v.visit_unary(e),
+        Expr::Binary(ref e) => v.visit_binary(e),
+        Expr::Let(ref e) => v.visit_let(e),
+        Expr::Rec(ref e) => v.visit_rec(e),
+        Expr::Proj(ref e) => v.visit_proj(e),
+        Expr::If(ref e) => v.visit_if(e),
+        Expr::Or(ref e) => v.visit_or(e),
+        Expr::Assert(ref e) => v.visit_assert(e),
+        Expr::With(ref e) => v.visit_with(e),
+        Expr::LetIn(ref e) => v.visit_let_in(e),
+        Expr::FnDecl(ref e) => v.visit_fn_decl(e),
+        Expr::FnApp(ref e) => v.visit_fn_app(e),
+        Expr::Error(span) | Expr::Trap(span) => v.visit_error(span),
+    }
+}
+
+pub fn walk_paren<'a, V: Visitor<'a> + ?Sized>(v: &mut V, paren: &'a ExprParen) {
+    v.visit_expr(&paren.expr);
+}
+
+pub fn walk_interpolation<'a, V: Visitor<'a> + ?Sized>(v: &mut V, interp: &'a ExprInterpolation) {
+    v.visit_expr(&interp.inner);
+}
+
+pub fn walk_list<'a, V: Visitor<'a> + ?Sized>(v: &mut V, list: &'a ExprList) {
+    for elem in &list.elems {
+        v.visit_expr(elem);
+    }
+}
+
+pub fn walk_string<'a, V: Visitor<'a> + ?Sized>(v: &mut V, string: &'a ExprString) {
+    for fragment in string.0.iter() {
+        v.visit_string_fragment(fragment);
+    }
+}
+
+pub fn walk_string_fragment<'a, V: Visitor<'a> + ?Sized>(v: &mut V, fragment: &'a StringFragment) {
+    if let StringFragment::Interpolation(ref interp) = *fragment {
+        v.visit_interpolation(interp);
+    }
+}
+
+pub fn walk_set<'a, V: Visitor<'a> + ?Sized>(v: &mut V, set: &'a ExprSet) {
+    for bind in &set.binds {
+        v.visit_bind(bind);
+    }
+}
+
+pub fn walk_unary<'a, V: Visitor<'a> + ?Sized>(v: &mut V, unary: &'a ExprUnary) {
+    v.visit_expr(&unary.expr);
+}
+
+pub fn walk_binary<'a, V: Visitor<'a> + ?Sized>(v: &mut V, binary: &'a ExprBinary) {
+    v.visit_expr(&binary.lhs);
+    v.visit_expr(&binary.rhs);
+}
+
+pub fn walk_let<'a, V: Visitor<'a> + ?Sized>(v: &mut V, let_: &'a ExprLet) {
+    for bind in &let_.binds {
+        v.visit_bind(bind);
+    }
+}
+
+pub fn walk_rec<'a, V: Visitor<'a> + ?Sized>(v: &mut V, rec: &'a ExprRec) {
+    for bind in &rec.binds {
+        v.visit_bind(bind);
+    }
+}
+
+pub fn walk_proj<'a, V: Visitor<'a> + ?Sized>(v: &mut V, proj: &'a ExprProj) {
+    v.visit_expr(&proj.base);
+    v.visit_attr_path(&proj.attr);
+    if let Some(ref fallback) = proj.fallback {
+        v.visit_expr(fallback);
+    }
+}
+
+pub fn walk_if<'a, V: Visitor<'a> + ?Sized>(v: &mut V, if_: &'a ExprIf) {
+    v.visit_expr(&if_.cond);
+    v.visit_expr(&if_.body);
+    v.visit_expr(&if_.fallback);
+}
+
+pub fn walk_or<'a, V: Visitor<'a> + ?Sized>(v: &mut V, or: &'a ExprOr) {
+    v.visit_expr(&or.expr);
+    v.visit_expr(&or.fallback);
+}
+
+pub fn walk_assert<'a, V: Visitor<'a> + ?Sized>(v: &mut V, assert: &'a ExprAssert) {
+    v.visit_expr(&assert.cond);
+    v.visit_expr(&assert.expr);
+}
+
+pub fn walk_with<'a, V: Visitor<'a> + ?Sized>(v: &mut V, with: &'a ExprWith) {
+    v.visit_expr(&with.with);
+    v.visit_expr(&with.expr);
+}
+
+pub fn walk_let_in<'a, V: Visitor<'a> + ?Sized>(v: &mut V, let_in: &'a ExprLetIn) {
+    for bind in &let_in.binds {
+        v.visit_bind(bind);
+    }
+    if let Some(ref comment) = let_in.comment {
+        v.visit_comment(comment);
+    }
+    v.visit_expr(&let_in.body);
+}
+
+pub fn walk_fn_decl<'a, V: Visitor<'a> + ?Sized>(v: &mut V, decl: &'a ExprFnDecl) {
+    match *decl {
+        ExprFnDecl::Simple(ref simple) => v.visit_fn_decl_simple(simple),
+        ExprFnDecl::Formals(ref formals) => v.visit_fn_decl_formals(formals),
+    }
+}
+
+pub fn walk_fn_decl_simple<'a, V: Visitor<'a> + ?Sized>(v: &mut V, decl: &'a FnDeclSimple) {
+    v.visit_ident(&decl.name);
+    v.visit_expr(&decl.body);
+}
+
+pub fn walk_fn_decl_formals<'a, V: Visitor<'a> + ?Sized>(v: &mut V, decl: &'a FnDeclFormals) {
+    for formal in &decl.formals {
+        v.visit_formal(formal);
+    }
+    if let Some(ref extra) = decl.extra {
+        v.visit_ident(extra);
+    }
+    v.visit_expr(&decl.body);
+}
+
+pub fn walk_formal<'a, V: Visitor<'a> + ?Sized>(v: &mut V, formal: &'a Formal) {
+    v.visit_ident(&formal.name);
+    if let Some(ref default) = formal.default {
+        v.visit_expr(default);
+    }
+}
+
+pub fn walk_fn_app<'a, V: Visitor<'a> + ?Sized>(v: &mut V, app: &'a ExprFnApp) {
+    v.visit_expr(&app.function);
+    v.visit_expr(&app.argument);
+}
+
+pub fn walk_bind<'a, V: Visitor<'a> + ?Sized>(v: &mut V, bind: &'a Bind) {
+    match *bind {
+        Bind::Simple(ref simple) => v.visit_bind_simple(simple),
+        Bind::Inherit(ref inherit) => v.visit_bind_inherit(inherit),
+        Bind::InheritExpr(ref inherit) => v.visit_bind_inherit_expr(inherit),
+    }
+}
+
+pub fn walk_bind_simple<'a, V: Visitor<'a> + ?Sized>(v: &mut V, bind: &'a BindSimple) {
+    if let Some(ref comment) = bind.comment {
+        v.visit_comment(comment);
+    }
+    v.visit_attr_path(&bind.attr);
+    v.visit_expr(&bind.expr);
+}
+
+pub fn walk_bind_inherit<'a, V: Visitor<'a> + ?Sized>(v: &mut V, bind: &'a BindInherit) {
+    for name in &bind.names {
+        v.visit_ident(name);
+    }
+}
+
+pub fn walk_bind_inherit_expr<'a, V: Visitor<'a> + ?Sized>(v: &mut V, bind: &'a BindInheritExpr) {
+    v.visit_expr(&bind.expr);
+    for name in &bind.names {
+        v.visit_ident(name);
+    }
+}
+
+pub fn walk_attr_path<'a, V: Visitor<'a> + ?Sized>(v: &mut V, path: &'a AttrPath) {
+    for segment in &path.0 {
+        v.visit_attr_segment(segment);
+    }
+}
+
+pub fn walk_attr_segment<'a, V: Visitor<'a> + ?Sized>(v: &mut V, segment: &'a AttrSegment) {
+    match *segment {
+        AttrSegment::Ident(ref ident) => v.visit_ident(ident),
+        AttrSegment::Interpolation(ref interp) => v.visit_interpolation(interp),
+        AttrSegment::String(ref string) => v.visit_string(string),
+    }
+}
+
+/// Collects the expressions directly below a node, without descending into them.
+struct Children<'s, 'a>(&'s mut Vec<&'a Expr>);
+
+impl<'a> Visitor<'a> for Children<'_, 'a> {
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        self.0.push(expr);
+    }
+}
+
+/// Returns the direct subexpressions of `expr`, in source order.
+pub fn children(expr: &Expr) -> Vec<&Expr> {
+    let mut children = Vec::new();
+    walk_expr(&mut Children(&mut children), expr);
+    children
+}
+
+/// Returns an iterator over `root` and every expression below it, in pre-order.
+pub fn descendants(root: &Expr) -> Descendants<'_> {
+    Descendants { stack: vec![root] }
+}
+
+/// An iterator over the expressions of a tree in pre-order, created by [`descendants`].
+#[derive(Clone, Debug)]
+pub struct Descendants<'a> {
+    stack: Vec<&'a Expr>,
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = &'a Expr;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let expr = self.stack.pop()?;
+        let start = self.stack.len();
+        walk_expr(&mut Children(&mut self.stack), expr);
+        self.stack[start..].reverse();
+        Some(expr)
+    }
+}
+
+impl FusedIterator for Descendants<'_> {}
+
+/// Calls `f` with `root` and every expression below it in pre-order, along with the expressions
+/// enclosing each, outermost first.
+pub fn walk<'a, F>(root: &'a Expr, f: F)
+where
+    F: FnMut(&'a Expr, &[&'a Expr]),
+{
+    struct Walk<'a, F> {
+        path: Vec<&'a Expr>,
+        f: F,
+    }
+
+    impl<'a, F: FnMut(&'a Expr, &[&'a Expr])> Visitor<'a> for Walk<'a, F> {
+        fn visit_expr(&mut self, expr: &'a Expr) {
+            (self.f)(expr, &self.path);
+            self.path.push(expr);
+            walk_expr(self, expr);
+            self.path.pop();
+        }
+    }
+
+    Walk {
+        path: Vec::new(),
+        f,
+    }
+    .visit_expr(root)
+}
+
+/// Returns the expressions whose span contains the byte `offset`, from `root` to the innermost.
+///
+/// The result is empty if `offset` lies outside of `root`.
+pub fn path_at(root: &Expr, offset: usize) -> Vec<&Expr> {
+    let contains = |expr: &&Expr| {
+        let span = expr.span();
+        span.start().to_usize() <= offset && offset <= span.end().to_usize()
+    };
+
+    let mut path: Vec<_> = Some(root).filter(contains).into_iter().collect();
+    while let Some(child) = path
+        .last()
+        .and_then(|&e| children(e).into_iter().find(contains))
+    {
+        path.push(child);
+    }
+    path
+}
+
+/// Visits the nodes of a syntax tree by mutable reference.
+///
+/// This mirrors [`Visitor`], with `walk_*_mut` functions visiting the children of each node.
+pub trait VisitorMut {
+    fn visit_source_file_mut(&mut self, file: &mut SourceFile) {
+        walk_source_file_mut(self, file)
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr)
+    }
+
+    fn visit_paren_mut(&mut self, paren: &mut ExprParen) {
+        walk_paren_mut(self, paren)
+    }
+
+    fn visit_interpolation_mut(&mut self, interp: &mut ExprInterpolation) {
+        walk_interpolation_mut(self, interp)
+    }
+
+    fn visit_list_mut(&mut self, list: &mut ExprList) {
+        walk_list_mut(self, list)
+    }
+
+    fn visit_string_mut(&mut self, string: &mut ExprString) {
+        walk_string_mut(self, string)
+    }
+
+    fn visit_string_fragment_mut(&mut self, fragment: &mut StringFragment) {
+        walk_string_fragment_mut(self, fragment)
+    }
+
+    fn visit_set_mut(&mut self, set: &mut ExprSet) {
+        walk_set_mut(self, set)
+    }
+
+    fn visit_unary_mut(&mut self, unary: &mut ExprUnary) {
+        walk_unary_mut(self, unary)
+    }
+
+    fn visit_binary_mut(&mut self, binary: &mut ExprBinary) {
+        walk_binary_mut(self, binary)
+    }
+
+    fn visit_let_mut(&mut self, let_: &mut ExprLet) {
+        walk_let_mut(self, let_)
+    }
+
+    fn visit_rec_mut(&mut self, rec: &mut ExprRec) {
+        walk_rec_mut(self, rec)
+    }
+
+    fn visit_proj_mut(&mut self, proj: &mut ExprProj) {
+        walk_proj_mut(self, proj)
+    }
+
+    fn visit_if_mut(&mut self, if_: &mut ExprIf) {
+        walk_if_mut(self, if_)
+    }
+
+    fn visit_or_mut(&mut self, or: &mut ExprOr) {
+        walk_or_mut(self, or)
+    }
+
+    fn visit_assert_mut(&mut self, assert: &mut ExprAssert) {
+        walk_assert_mut(self, assert)
+    }
+
+    fn visit_with_mut(&mut self, with: &mut ExprWith) {
+        walk_with_mut(self, with)
+    }
+
+    fn visit_let_in_mut(&mut self, let_in: &mut ExprLetIn) {
+        walk_let_in_mut(self, let_in)
+    }
+
+    fn visit_fn_decl_mut(&mut self, decl: &mut ExprFnDecl) {
+        walk_fn_decl_mut(self, decl)
+    }
+
+    fn visit_fn_decl_simple_mut(&mut self, decl: &mut FnDeclSimple) {
+        walk_fn_decl_simple_mut(self, decl)
+    }
+
+    fn visit_fn_decl_formals_mut(&mut self, decl: &mut FnDeclFormals) {
+        walk_fn_decl_formals_mut(self, decl)
+    }
+
+    fn visit_formal_mut(&mut self, formal: &mut Formal) {
+        walk_formal_mut(self, formal)
+    }
+
+    fn visit_fn_app_mut(&mut self, app: &mut ExprFnApp) {
+        walk_fn_app_mut(self, app)
+    }
+
+    fn visit_bind_mut(&mut self, bind: &mut Bind) {
+        walk_bind_mut(self, bind)
+    }
+
+    fn visit_bind_simple_mut(&mut self, bind: &mut BindSimple) {
+        walk_bind_simple_mut(self, bind)
+    }
+
+    fn visit_bind_inherit_mut(&mut self, bind: &mut BindInherit) {
+        walk_bind_inherit_mut(self, bind)
+    }
+
+    fn visit_bind_inherit_expr_mut(&mut self, bind: &mut BindInheritExpr) {
+        walk_bind_inherit_expr_mut(self, bind)
+    }
+
+    fn visit_attr_path_mut(&mut self, path: &mut AttrPath) {
+        walk_attr_path_mut(self, path)
+    }
+
+    fn visit_attr_segment_mut(&mut self, segment: &mut AttrSegment) {
+        walk_attr_segment_mut(self, segment)
+    }
+
+    fn visit_ident_mut(&mut self, _ident: &mut Ident) {}
+
+    fn visit_literal_mut(&mut self, _literal: &mut Literal) {}
+
+    fn visit_comment_mut(&mut self, _comment: &mut Comment) {}
+
+    /// Visits the span of a node which could not be parsed, i.e. `Expr::Error` or `Expr::Trap`.
+    fn visit_error_mut(&mut self, _span: &mut Span) {}
+}
+
+pub fn walk_source_file_mut<V: VisitorMut + ?Sized>(v: &mut V, file: &mut SourceFile) {
+    if let Some(ref mut comment) = file.comment {
+        v.visit_comment_mut(comment);
+    }
+    v.visit_expr_mut(&mut file.expr);
+}
+
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(v: &mut V, expr: &mut Expr) {
+    match *expr {
+        Expr::Paren(ref mut e) => v.visit_paren_mut(e),
+        Expr::Ident(ref mut e) => v.visit_ident_mut(e),
+        Expr::Interpolation(ref mut e) => v.visit_interpolation_mut(e),
+        Expr::Literal(ref mut e) => v.visit_literal_mut(e),
+        Expr::List(ref mut e) => v.visit_list_mut(e),
+        Expr::String(ref mut e) => v.visit_string_mut(e),
+        Expr::Set(ref mut e) => v.visit_set_mut(e),
+        Expr::Unary(ref mut e) => v.visit_unary_mut(e),
+        Expr::Binary(ref mut e) => v.visit_binary_mut(e),
+        Expr::Let(ref mut e) => v.visit_let_mut(e),
+        Expr::Rec(ref mut e) => v.visit_rec_mut(e),
+        Expr::Proj(ref mut e) => v.visit_proj_mut(e),
+        Expr::If(ref mut e) => v.visit_if_mut(e),
+        Expr::Or(ref mut e) => v.visit_or_mut(e),
+        Expr::Assert(ref mut e) => v.visit_assert_mut(e),
+        Expr::With(ref mut e) => v.visit_with_mut(e),
+        Expr::LetIn(ref mut e) => v.visit_let_in_mut(e),
+        Expr::FnDecl(ref mut e) => v.visit_fn_decl_mut(e),
+        Expr::FnApp(ref mut e) => v.visit_fn_app_mut(e),
+        Expr::Error(ref mut span) | Expr::Trap(ref mut span) => v.visit_error_mut(span),
+    }
+}
+
+pub fn walk_paren_mut<V: VisitorMut + ?Sized>(v: &mut V, paren: &mut ExprParen) {
+    v.visit_expr_mut(&mut paren.expr);
+}
+
+pub fn walk_interpolation_mut<V: VisitorMut + ?Sized>(v: &mut V, interp: &mut ExprInterpolation) {
+    v.visit_expr_mut(&mut interp.inner);
+}
+
+pub fn walk_list_mut<V: VisitorMut + ?Sized>(v: &mut V, list: &mut ExprList) {
+    for elem in &mut list.elems {
+        v.visit_expr_mut(elem);
+    }
+}
+
+pub fn walk_string_mut<V: VisitorMut + ?Sized>(v: &mut V, string: &mut ExprString) {
+    for fragment in string.0.iter_mut() {
+        v.visit_string_fragment_mut(fragment);
+    }
+}
+
+pub fn walk_string_fragment_mut<V: VisitorMut + ?Sized>(v: &mut V, fragment: &mut StringFragment) {
+    if let StringFragment::Interpolation(ref mut interp) = *fragment {
+        v.visit_interpolation_mut(interp);
+    }
+}
+
+pub fn walk_set_mut<V: VisitorMut + ?Sized>(v: &mut V, set: &mut ExprSet) {
+    for bind in &mut set.binds {
+        v.visit_bind_mut(bind);
+    }
+}
+
+pub fn walk_unary_mut<V: VisitorMut + ?Sized>(v: &mut V, unary: &mut ExprUnary) {
+    v.visit_expr_mut(&mut unary.expr);
+}
+
+pub fn walk_binary_mut<V: VisitorMut + ?Sized>(v: &mut V, binary: &mut ExprBinary) {
+    v.visit_expr_mut(&mut binary.lhs);
+    v.visit_expr_mut(&mut binary.rhs);
+}
+
+pub fn walk_let_mut<V: VisitorMut + ?Sized>(v: &mut V, let_: &mut ExprLet) {
+    for bind in &mut let_.binds {
+        v.visit_bind_mut(bind);
+    }
+}
+
+pub fn walk_rec_mut<V: VisitorMut + ?Sized>(v: &mut V, rec: &mut ExprRec) {
+    for bind in &mut rec.binds {
+        v.visit_bind_mut(bind);
+    }
+}
+
+pub fn walk_proj_mut<V: VisitorMut + ?Sized>(v: &mut V, proj: &mut ExprProj) {
+    v.visit_expr_mut(&mut proj.base);
+    v.visit_attr_path_mut(&mut proj.attr);
+    if let Some(ref mut fallback) = proj.fallback {
+        v.visit_expr_mut(fallback);
+    }
+}
+
+pub fn walk_if_mut<V: VisitorMut + ?Sized>(v: &mut V, if_: &mut ExprIf) {
+    v.visit_expr_mut(&mut if_.cond);
+    v.visit_expr_mut(&mut if_.body);
+    v.visit_expr_mut(&mut if_.fallback);
+}
+
+pub fn walk_or_mut<V: VisitorMut + ?Sized>(v: &mut V, or: &mut ExprOr) {
+    v.visit_expr_mut(&mut or.expr);
+    v.visit_expr_mut(&mut or.fallback);
+}
+
+pub fn walk_assert_mut<V: VisitorMut + ?Sized>(v: &mut V, assert: &mut ExprAssert) {
+    v.visit_expr_mut(&mut assert.cond);
+    v.visit_expr_mut(&mut assert.expr);
+}
+
+pub fn walk_with_mut<V: VisitorMut + ?Sized>(v: &mut V, with: &mut ExprWith) {
+    v.visit_expr_mut(&mut with.with);
+    v.visit_expr_mut(&mut with.expr);
+}
+
+pub fn walk_let_in_mut<V: VisitorMut + ?Sized>(v: &mut V, let_in: &mut ExprLetIn) {
+    for bind in &mut let_in.binds {
+        v.visit_bind_mut(bind);
+    }
+    if let Some(ref mut comment) = let_in.comment {
+        v.visit_comment_mut(comment);
+    }
+    v.visit_expr_mut(&mut let_in.body);
+}
+
+pub fn walk_fn_decl_mut<V: VisitorMut + ?Sized>(v: &mut V, decl: &mut ExprFnDecl) {
+    match *decl {
+        ExprFnDecl::Simple(ref mut simple) => v.visit_fn_decl_simple_mut(simple),
+        ExprFnDecl::Formals(ref mut formals) => v.visit_fn_decl_formals_mut(formals),
+    }
+}
+
+pub fn walk_fn_decl_simple_mut<V: VisitorMut + ?Sized>(v: &mut V, decl: &mut FnDeclSimple) {
+    v.visit_ident_mut(&mut decl.name);
+    v.visit_expr_mut(&mut decl.body);
+}
+
+pub fn walk_fn_decl_formals_mut<V: VisitorMut + ?Sized>(v: &mut V, decl: &mut FnDeclFormals) {
+    for formal in &mut decl.formals {
+        v.visit_formal_mut(formal);
+    }
+    if let Some(ref mut extra) = decl.extra {
+        v.visit_ident_mut(extra);
+    }
+    v.visit_expr_mut(&mut decl.body);
+}
+
+pub fn walk_formal_mut<V: VisitorMut + ?Sized>(v: &mut V, formal: &mut Formal) {
+    v.visit_ident_mut(&mut formal.name);
+    if let Some(ref mut default) = formal.default {
+        v.visit_expr_mut(default);
+    }
+}
+
+pub fn walk_fn_app_mut<V: VisitorMut + ?Sized>(v: &mut V, app: &mut ExprFnApp) {
+    v.visit_expr_mut(&mut app.function);
+    v.visit_expr_mut(&mut app.argument);
+}
+
+pub fn walk_bind_mut<V: VisitorMut + ?Sized>(v: &mut V, bind: &mut Bind) {
+    match *bind {
+        Bind::Simple(ref mut simple) => v.visit_bind_simple_mut(simple),
+        Bind::Inherit(ref mut inherit) => v.visit_bind_inherit_mut(inherit),
+        Bind::InheritExpr(ref mut inherit) => v.visit_bind_inherit_expr_mut(inherit),
+    }
+}
+
+pub fn walk_bind_simple_mut<V: VisitorMut + ?Sized>(v: &mut V, bind: &mut BindSimple) {
+    if let Some(ref mut comment) = bind.comment {
+        v.visit_comment_mut(comment);
+    }
+    v.visit_attr_path_mut(&mut bind.attr);
+    v.visit_expr_mut(&mut bind.expr);
+}
+
+pub fn walk_bind_inherit_mut<V: VisitorMut + ?Sized>(v: &mut V, bind: &mut BindInherit) {
+    for name in &mut bind.names {
+        v.visit_ident_mut(name);
+    }
+}
+
+pub fn walk_bind_inherit_expr_mut<V: VisitorMut + ?Sized>(v: &mut V, bind: &mut BindInheritExpr) {
+    v.visit_expr_mut(&mut bind.expr);
+    for name in &mut bind.names {
+        v.visit_ident_mut(name);
+    }
+}
+
+pub fn walk_attr_path_mut<V: VisitorMut + ?Sized>(v: &mut V, path: &mut AttrPath) {
+    for segment in &mut path.0 {
+        v.visit_attr_segment_mut(segment);
+    }
+}
+
+pub fn walk_attr_segment_mut<V: VisitorMut + ?Sized>(v: &mut V, segment: &mut AttrSegment) {
+    match *segment {
+        AttrSegment::Ident(ref mut ident) => v.visit_ident_mut(ident),
+        AttrSegment::Interpolation(ref mut interp) => v.visit_interpolation_mut(interp),
+        AttrSegment::String(ref mut string) => v.visit_string_mut(string),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Exprs(usize);
+
+    impl<'a> Visitor<'a> for Exprs {
+        fn visit_expr(&mut self, expr: &'a Expr) {
+            self.0 += 1;
+            walk_expr(self, expr);
+        }
+    }
+
+    struct Rename<'s>(&'s str, &'s str);
+
+    impl VisitorMut for Rename<'_> {
+        fn visit_ident_mut(&mut self, ident: &mut Ident) {
+            if ident.as_str() == self.0 {
+                *ident = Ident::from((self.1, ident.span()));
+            }
+        }
+    }
+
+    #[test]
+    fn visits_every_expression() {
+        let source = "let a = { b = [ 1 \"${c}\" ]; }; in with a; if x then y.z else f a";
+        let file: SourceFile = source.parse().unwrap();
+        let mut exprs = Exprs(0);
+        exprs.visit_source_file(&file);
+        assert_eq!(exprs.0, descendants(file.expr()).count());
+    }
+
+    #[test]
+    fn walks_in_pre_order() {
+        let source: SourceFile = "let x = { a = 1; b = 2; }; in x".parse().unwrap();
+        let root = source.expr();
+
+        let rendered: Vec<_> = descendants(root).map(|e| e.to_string()).collect();
+        assert_eq!(rendered.len(), 5);
+        assert_eq!(rendered[2..], ["1", "2", "x"]);
+        assert_eq!(children(children(root)[0]).len(), 2);
+
+        let mut depths = Vec::new();
+        walk(root, |_, path| depths.push(path.len()));
+        assert_eq!(depths, [0, 1, 2, 2, 1]);
+
+        let offset = "let x = { a = 1; b = ".len();
+        let path: Vec<_> = path_at(root, offset)
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(path.len(), 3);
+        assert_eq!(path[2], "2");
+        assert!(path_at(root, 1000).is_empty());
+    }
+
+    #[test]
+    fn rewrites_identifiers_in_place() {
+        let mut file: SourceFile = "{ x ? 1, ... }: let inherit x; y = x; in x.x + y"
+            .parse()
+            .unwrap();
+        Rename("x", "w").visit_source_file_mut(&mut file);
+        let expected: SourceFile = "{ w ? 1, ... }: let inherit w; y = w; in w.w + y"
+            .parse()
+            .unwrap();
+        assert_eq!(file, expected);
+    }
+}
@@ -1,5 +1,7 @@
+pub use self::code::ErrorCode;
 pub use self::expected_found::ExpectedFoundError;
 pub use self::incorrect_delim::IncorrectDelimError;
+pub use self::located::LocatedDiagnostic;
 pub use self::unclosed_delim::UnclosedDelimError;
 pub use self::unexpected::UnexpectedError;
 
@@ -8,14 +10,16 @@ use std::iter::FromIterator;
 use std::slice::Iter;
 use std::vec::IntoIter;
 
-use codespan::{FileId, Span};
+use codespan::{FileId, Files, Span};
 use codespan_reporting::diagnostic::{Diagnostic, Label};
 use nom::error::{ErrorKind, ParseError};
 
 use crate::ToSpan;
 
+mod code;
 mod expected_found;
 mod incorrect_delim;
+mod located;
 mod unclosed_delim;
 mod unexpected;
 
@@ -39,6 +43,11 @@ impl Errors {
         self.errors.is_empty()
     }
 
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.errors.len()
+    }
+
     #[inline]
     pub fn push<E>(&mut self, error: E)
     where
@@ -58,13 +67,24 @@ impl Errors {
     }
 
     #[inline]
-    pub fn iter(&self) -> Iter<Error> {
+    pub fn iter(&self) -> Iter<'_, Error> {
         self.errors.iter()
     }
 
     pub fn to_diagnostics(&self, file: FileId) -> Vec<Diagnostic> {
         self.errors.iter().map(|e| e.to_diagnostic(file)).collect()
     }
+
+    /// Returns the errors as diagnostics located by line and column in `source`, the text they
+    /// were found in.
+    pub fn to_located(&self, source: &str) -> Vec<LocatedDiagnostic> {
+        let mut files = Files::new();
+        let id = files.add("<input>", source);
+        self.to_diagnostics(id)
+            .into_iter()
+            .map(|diag| LocatedDiagnostic::new(&files, diag))
+            .collect()
+    }
 }
 
 impl Default for Errors {
@@ -152,6 +172,23 @@ pub enum Error {
     Unexpected(UnexpectedError),
     Nom(Span, ErrorKind),
     Message(Span, String),
+    /// A binding missing its `;`, at the span of its last token.
+    MissingSemicolon(Span),
+}
+
+impl Error {
+    /// Returns the code identifying the kind of this error.
+    pub fn code(&self) -> ErrorCode {
+        match *self {
+            Error::ExpectedFound(_) => ErrorCode::ExpectedFound,
+            Error::IncorrectDelim(_) => ErrorCode::IncorrectDelim,
+            Error::UnclosedDelim(_) => ErrorCode::UnclosedDelim,
+            Error::Unexpected(_) => ErrorCode::UnexpectedToken,
+            Error::Nom(..) => ErrorCode::Internal,
+            Error::Message(..) => ErrorCode::Syntax,
+            Error::MissingSemicolon(_) => ErrorCode::MissingSemicolon,
+        }
+    }
 }
 
 impl Display for Error {
@@ -163,6 +200,7 @@ impl Display for Error {
             Error::Unexpected(ref e) => write!(fmt, "{}", e),
             Error::Nom(_, ref e) => write!(fmt, "nom error: {:?}", e),
             Error::Message(_, ref e) => write!(fmt, "{}", e),
+            Error::MissingSemicolon(_) => write!(fmt, "missing semicolon after binding"),
         }
     }
 }
@@ -203,12 +241,60 @@ impl ToDiagnostic for Error {
             Error::Nom(ref span, ref kind) => {
                 let label = Label::new(file, *span, self.to_string());
                 let note = "note: this indicates an unhandled case in the parser".to_string();
-                Diagnostic::new_bug(format!("nom error: {:?}", kind), label).with_notes(vec![note])
+                let message = format!("nom error: {:?}", kind);
+                self.code()
+                    .diagnostic(message, label)
+                    .with_notes(vec![note])
             }
             Error::Message(ref span, ref msg) => {
                 let label = Label::new(file, *span, msg.clone());
-                Diagnostic::new_error(msg.clone(), label)
+                self.code().diagnostic(msg.clone(), label)
+            }
+            Error::MissingSemicolon(ref span) => {
+                let label = Label::new(file, *span, "expected `;` after this");
+                self.code().diagnostic(self.to_string(), label)
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use codespan::Files;
+    use codespan_reporting::diagnostic::Severity;
+
+    use super::*;
+    use crate::parser::parse_source_file;
+
+    #[test]
+    fn attaches_codes_to_diagnostics() {
+        let mut files = Files::new();
+        let cases = [
+            ("{ a = 1; $ }", "E0001"),
+            ("{ a = [ 1; }", "E0003"),
+            ("{ a = [ 1 ];\n", "E0002"),
+            ("{ a = 1; } }", "E0004"),
+            ("\"${}\"", "E0005"),
+            ("{ a = 1 b = 2; }", "E0006"),
+        ];
+
+        for &(source, code) in &cases {
+            let id = files.add("default.nix", source);
+            let errors = parse_source_file(source).unwrap_err();
+            let diagnostics = errors.to_diagnostics(id);
+            let codes: Vec<_> = diagnostics.iter().filter_map(|d| d.code.clone()).collect();
+            assert!(codes.iter().any(|c| c == code), "{}: {:?}", source, codes);
+            assert_eq!(codes.len(), diagnostics.len());
+            assert!(diagnostics.iter().all(|d| d.severity == Severity::Error));
+        }
+    }
+
+    #[test]
+    fn parses_error_codes() {
+        for &code in ErrorCode::ALL {
+            assert_eq!(ErrorCode::from_code(code.as_str()), Some(code));
+        }
+        assert_eq!(ErrorCode::from_code("E1234"), None);
+        assert_eq!(ErrorCode::Internal.severity(), Severity::Bug);
+    }
+}
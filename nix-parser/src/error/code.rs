@@ -0,0 +1,112 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use codespan_reporting::diagnostic::{Diagnostic, Label, Severity};
+
+/// A stable code identifying a kind of syntax error.
+///
+/// Every diagnostic produced through [`ToDiagnostic`](super::ToDiagnostic) carries one of these
+/// codes, such as `E0001`, so that editors can filter syntax errors and link to their
+/// documentation without matching on messages.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum ErrorCode {
+    /// A token which is not valid Nix, such as a stray `$` (`E0001`).
+    UnexpectedToken,
+    /// Delimiters which are still open at the end of the file (`E0002`).
+    UnclosedDelim,
+    /// A closing delimiter which does not match the one opened last (`E0003`).
+    IncorrectDelim,
+    /// A valid token in a place where the grammar expects something else (`E0004`).
+    ExpectedFound,
+    /// Any other syntax error, e.g. an empty interpolation or an unterminated string (`E0005`).
+    Syntax,
+    /// A binding which is not terminated by a `;` (`E0006`).
+    MissingSemicolon,
+    /// A case the parser does not handle, which indicates a bug in the parser itself (`E0999`).
+    Internal,
+}
+
+impl ErrorCode {
+    /// All error codes, in order.
+    pub const ALL: &'static [ErrorCode] = &[
+        ErrorCode::UnexpectedToken,
+        ErrorCode::UnclosedDelim,
+        ErrorCode::IncorrectDelim,
+        ErrorCode::ExpectedFound,
+        ErrorCode::Syntax,
+        ErrorCode::MissingSemicolon,
+        ErrorCode::Internal,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::UnexpectedToken => "E0001",
+            ErrorCode::UnclosedDelim => "E0002",
+            ErrorCode::IncorrectDelim => "E0003",
+            ErrorCode::ExpectedFound => "E0004",
+            ErrorCode::Syntax => "E0005",
+            ErrorCode::MissingSemicolon => "E0006",
+            ErrorCode::Internal => "E0999",
+        }
+    }
+
+    /// Returns the error code written as `code`, e.g. `E0001`.
+    pub fn from_code(code: &str) -> Option<Self> {
+        ErrorCode::ALL.iter().cloned().find(|c| c.as_str() == code)
+    }
+
+    /// Returns the severity diagnostics with this code are reported with.
+    pub fn severity(self) -> Severity {
+        match self {
+            ErrorCode::Internal => Severity::Bug,
+            _ => Severity::Error,
+        }
+    }
+
+    /// Returns a long-form explanation of the error, suitable for documentation.
+    pub fn explanation(self) -> &'static str {
+        match self {
+            ErrorCode::UnexpectedToken => {
+                "The file contains characters which do not form any Nix token, e.g. a stray `$` \
+                 or `\\`.\n\nRemove them, or quote them inside a string if they are meant \
+                 literally."
+            }
+            ErrorCode::UnclosedDelim => {
+                "A `(`, `[`, `{` or `${` is never closed before the end of the file.\n\nAdd the \
+                 matching closing delimiter where the expression ends."
+            }
+            ErrorCode::IncorrectDelim => {
+                "A closing delimiter does not match the delimiter opened last, as in `[ 1 }`.\n\n\
+                 Close the inner delimiter first, or fix the one which was mistyped."
+            }
+            ErrorCode::ExpectedFound => {
+                "A token appears where the grammar expects something else, such as a missing \
+                 `then` after the condition of an `if` or `in` after the bindings of a `let`.\n\n\
+                 The message names what was expected at that point."
+            }
+            ErrorCode::Syntax => {
+                "The file is not a valid Nix expression, e.g. because an interpolation is empty, \
+                 a string is not terminated or a path ends with a slash.\n\nThe message \
+                 describes the problem."
+            }
+            ErrorCode::MissingSemicolon => {
+                "A binding in an attribute set or `let` is not followed by a `;`, as in \
+                 `{ a = 1 b = 2; }`.\n\nInsert the `;` after the value of the binding."
+            }
+            ErrorCode::Internal => {
+                "The parser reached a case it does not handle. This is a bug in the parser \
+                 rather than in the file; please report it along with the input."
+            }
+        }
+    }
+
+    /// Creates a diagnostic with this code and its severity.
+    pub(crate) fn diagnostic(self, message: impl Into<String>, label: Label) -> Diagnostic {
+        Diagnostic::new(self.severity(), message, label).with_code(self.as_str())
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        write!(fmt, "{}", self.as_str())
+    }
+}
@@ -4,7 +4,7 @@ use std::fmt::{Display, Formatter, Result as FmtResult};
 use codespan::{FileId, Span};
 use codespan_reporting::diagnostic::{Diagnostic, Label};
 
-use super::ToDiagnostic;
+use super::{ErrorCode, ToDiagnostic};
 use crate::ToSpan;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -40,6 +40,6 @@ impl Error for ExpectedFoundError {}
 impl ToDiagnostic for ExpectedFoundError {
     fn to_diagnostic(&self, file: FileId) -> Diagnostic {
         let label = Label::new(file, self.span, format!("expected {} here", self.expected));
-        Diagnostic::new_error(self.to_string(), label)
+        ErrorCode::ExpectedFound.diagnostic(self.to_string(), label)
     }
 }
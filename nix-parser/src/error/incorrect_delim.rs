@@ -4,7 +4,7 @@ use std::fmt::{Display, Formatter, Result as FmtResult};
 use codespan::{FileId, Span};
 use codespan_reporting::diagnostic::{Diagnostic, Label};
 
-use super::ToDiagnostic;
+use super::{ErrorCode, ToDiagnostic};
 use crate::ToSpan;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -42,7 +42,7 @@ impl Error for IncorrectDelimError {}
 impl ToDiagnostic for IncorrectDelimError {
     fn to_diagnostic(&self, file: FileId) -> Diagnostic {
         let primary = Label::new(file, self.unmatched_delim.1, "incorrect close delimiter");
-        let mut diagnostic = Diagnostic::new_error(self.to_string(), primary);
+        let mut diagnostic = ErrorCode::IncorrectDelim.diagnostic(self.to_string(), primary);
 
         if let Some(span) = self.candidate_span {
             let candidate = Label::new(file, span, "close delimiter possibly meant for this");
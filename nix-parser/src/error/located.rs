@@ -0,0 +1,63 @@
+use std::iter;
+
+use codespan::Files;
+use codespan_reporting::diagnostic::{Diagnostic, Severity};
+
+/// A diagnostic located by line and column, in the shape the bindings to other languages report.
+///
+/// With the `serde` feature, it serializes as an object with the same fields.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LocatedDiagnostic {
+    /// The severity in lowercase, e.g. `"error"`.
+    pub severity: &'static str,
+    pub message: String,
+    /// The byte offset of the start of the primary label.
+    pub start: usize,
+    /// The byte offset of the end of the primary label.
+    pub end: usize,
+    /// The zero-based line of `start`.
+    pub line: usize,
+    /// The zero-based column of `start`.
+    pub column: usize,
+    /// The messages of the labels which have one, starting with the primary label.
+    pub labels: Vec<String>,
+    pub notes: Vec<String>,
+}
+
+impl LocatedDiagnostic {
+    /// Locates `diagnostic` in the file of its primary label.
+    pub fn new(files: &Files, diagnostic: Diagnostic) -> Self {
+        let label = diagnostic.primary_label;
+        let (line, column) = files
+            .location(label.file_id, label.span.start())
+            .map(|loc| (loc.line.to_usize(), loc.column.to_usize()))
+            .unwrap_or_default();
+
+        let labels = iter::once(label.message)
+            .chain(diagnostic.secondary_labels.into_iter().map(|l| l.message))
+            .filter(|message| !message.is_empty())
+            .collect();
+
+        LocatedDiagnostic {
+            severity: severity_name(diagnostic.severity),
+            message: diagnostic.message,
+            start: label.span.start().to_usize(),
+            end: label.span.end().to_usize(),
+            line,
+            column,
+            labels,
+            notes: diagnostic.notes,
+        }
+    }
+}
+
+fn severity_name(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Bug => "bug",
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Note => "note",
+        Severity::Help => "help",
+    }
+}
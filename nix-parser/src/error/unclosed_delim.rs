@@ -4,7 +4,7 @@ use std::fmt::{Display, Formatter, Result as FmtResult};
 use codespan::{FileId, Span};
 use codespan_reporting::diagnostic::{Diagnostic, Label};
 
-use super::ToDiagnostic;
+use super::{ErrorCode, ToDiagnostic};
 use crate::ToSpan;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -37,7 +37,7 @@ impl Error for UnclosedDelimError {}
 impl ToDiagnostic for UnclosedDelimError {
     fn to_diagnostic(&self, file: FileId) -> Diagnostic {
         let primary = Label::new(file, self.eof_span, "expected matching delimiter here");
-        let mut diagnostic = Diagnostic::new_error(self.to_string(), primary);
+        let mut diagnostic = ErrorCode::UnclosedDelim.diagnostic(self.to_string(), primary);
 
         for span in &self.unclosed_delims {
             let unclosed = Label::new(file, *span, "unmatched delimiter");
@@ -4,7 +4,7 @@ use std::fmt::{Display, Formatter, Result as FmtResult};
 use codespan::{FileId, Span};
 use codespan_reporting::diagnostic::{Diagnostic, Label};
 
-use super::ToDiagnostic;
+use super::{ErrorCode, ToDiagnostic};
 use crate::ToSpan;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -37,6 +37,6 @@ impl Error for UnexpectedError {}
 impl ToDiagnostic for UnexpectedError {
     fn to_diagnostic(&self, file: FileId) -> Diagnostic {
         let label = Label::new(file, self.span, "found unexpected token here");
-        Diagnostic::new_error(self.to_string(), label)
+        ErrorCode::UnexpectedToken.diagnostic(self.to_string(), label)
     }
 }
@@ -0,0 +1,857 @@
+//! Pretty-printing of source files in a canonical style.
+//!
+//! [`format`] lays out each expression on a single line if it fits within the configured width,
+//! and breaks it over several lines otherwise. Attribute sets, `let` blocks and lists then get one
+//! binding or element per line, a function application keeps its last argument on the line of the
+//! function so that `mkDerivation {` opens a block, and a chain of binary operators starts a new
+//! line at each operator. Strings, paths and other literals are kept exactly as written.
+//!
+//! Comments are kept where the new layout has a place for them: around the bindings of sets and
+//! `let` blocks, around list elements and around the whole file. Blank lines between bindings or
+//! elements are kept too, up to a configurable number. Files with comments anywhere else are not
+//! formatted rather than losing them, and neither are files whose formatted text would parse
+//! differently.
+//!
+//! Parts of a file can be kept as written with comments: `# nixfmt: skip` keeps the binding or
+//! list element following it, and `# nixfmt: off` keeps everything up to a `# nixfmt: on` comment
+//! or the end of the enclosing block. Either directive before the expression of the file keeps the
+//! whole file.
+//!
+//! ```
+//! use nix_parser::fmt::{format, Options};
+//!
+//! let source = "{a=1;b=[1 2];}";
+//! assert_eq!(format(source, &Options::default()).unwrap(), "{ a = 1; b = [ 1 2 ]; }\n");
+//! ```
+
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use codespan::Span;
+
+use crate::ast::{AttrPath, Bind, Expr, ExprFnApp, ExprFnDecl, FnDeclFormals};
+use crate::error::Errors;
+use crate::lexer::{Lexer, Token};
+use crate::parser::parse_source_file;
+use crate::HasSpan;
+
+/// Settings for [`format`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Options {
+    /// The number of spaces each nested block is indented by.
+    pub indent: usize,
+    /// The column lines should end before. Only literals and names too long to break may exceed it.
+    pub max_width: usize,
+    /// The number of consecutive blank lines kept between bindings or list elements.
+    pub max_blank_lines: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            indent: 2,
+            max_width: 100,
+            max_blank_lines: 1,
+        }
+    }
+}
+
+/// The reasons a source file cannot be formatted.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FormatError {
+    /// The file contains syntax errors.
+    Syntax(Errors),
+    /// The file contains comments in places the formatter cannot keep them.
+    Comments,
+    /// The formatted text would parse to a different expression, which is a bug in the formatter.
+    Changed,
+}
+
+impl Display for FormatError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match *self {
+            FormatError::Syntax(ref errors) => {
+                write!(fmt, "file contains syntax errors: {}", errors)
+            }
+            FormatError::Comments => write!(fmt, "file contains comments which cannot be kept"),
+            FormatError::Changed => write!(fmt, "formatting would change the meaning of the file"),
+        }
+    }
+}
+
+impl Error for FormatError {}
+
+/// Formats `source`, returning the new text of the file.
+pub fn format(source: &str, options: &Options) -> Result<String, FormatError> {
+    let file = parse_source_file(source).map_err(FormatError::Syntax)?;
+    let comments = comments(source)?;
+    let directives = directives(source, &comments);
+    let printer = Printer {
+        source,
+        options,
+        comments: &comments,
+        directives: &directives,
+    };
+
+    let mut out = String::new();
+    let expr = file.expr();
+    if printer.kept(0, std::slice::from_ref(expr)).is_some() {
+        return Ok(source.to_owned());
+    }
+    printer.comments(&mut out, &printer.gap(0), false, "");
+    if !out.is_empty() {
+        let blank_lines = printer.gap(0).blank_lines;
+        out.push_str(&"\n".repeat(blank_lines.min(options.max_blank_lines)));
+    }
+    out.push('\n');
+    out.push_str(&printer.expr(expr, 0, 0));
+    printer.comments(
+        &mut out,
+        &printer.gap(expr.span().end().to_usize()),
+        true,
+        "",
+    );
+    let mut formatted = out.trim_start_matches('\n').to_owned();
+    formatted.push('\n');
+
+    if self::comments(&formatted)?.len() != comments.len() {
+        return Err(FormatError::Comments);
+    }
+    match parse_source_file(&formatted) {
+        Ok(ref reparsed) if *reparsed == file => Ok(formatted),
+        _ => Err(FormatError::Changed),
+    }
+}
+
+/// Returns the spans of all comments in `source`.
+fn comments(source: &str) -> Result<Vec<Span>, FormatError> {
+    let lexer = Lexer::new(source).map_err(FormatError::Syntax)?;
+    let spans = lexer.tokens().iter().filter_map(|token| match *token {
+        Token::Comment(_, _, span) => Some(span),
+        _ => None,
+    });
+    Ok(spans.collect())
+}
+
+/// A comment asking for part of a file to be kept as written.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Directive {
+    Off,
+    On,
+    Skip,
+}
+
+/// Returns the directives among the `comments` of `source`.
+fn directives(source: &str, comments: &[Span]) -> Vec<(Span, Directive)> {
+    let directive = |span: Span| {
+        let text = &source[span.start().to_usize()..span.end().to_usize()];
+        let text = match text.strip_prefix("/*") {
+            Some(block) => block.trim_end_matches("*/"),
+            None => text.trim_start_matches('#'),
+        };
+        match text.trim().strip_prefix("nixfmt:").map(str::trim) {
+            Some("off") => Some((span, Directive::Off)),
+            Some("on") => Some((span, Directive::On)),
+            Some("skip") => Some((span, Directive::Skip)),
+            _ => None,
+        }
+    };
+    comments
+        .iter()
+        .filter_map(|&span| directive(span))
+        .collect()
+}
+
+/// A comment found between two nodes.
+struct Comment<'a> {
+    text: &'a str,
+    /// The number of blank lines before the comment.
+    blank_lines: usize,
+    /// Whether the comment is on the same line as the node before it.
+    same_line: bool,
+}
+
+/// The whitespace, comments and semicolons following a node.
+struct Gap<'a> {
+    comments: Vec<Comment<'a>>,
+    /// The number of blank lines before the next node.
+    blank_lines: usize,
+    /// The offset of the next token.
+    end: usize,
+}
+
+struct Printer<'a> {
+    source: &'a str,
+    options: &'a Options,
+    comments: &'a [Span],
+    directives: &'a [(Span, Directive)],
+}
+
+impl<'a> Printer<'a> {
+    fn text(&self, span: Span) -> &'a str {
+        &self.source[span.start().to_usize()..span.end().to_usize()]
+    }
+
+    fn has_comments(&self, span: Span) -> bool {
+        self.comments
+            .iter()
+            .any(|c| span.start() <= c.start() && c.end() <= span.end())
+    }
+
+    fn fits(&self, text: &str, col: usize) -> bool {
+        !text.contains('\n') && col + text.chars().count() <= self.options.max_width
+    }
+
+    fn pad(&self, indent: usize) -> String {
+        " ".repeat(indent)
+    }
+
+    /// Scans the gap starting at `offset` up to the next token which is not a semicolon.
+    fn gap(&self, mut offset: usize) -> Gap<'a> {
+        let mut comments = Vec::new();
+        let mut newlines = 0;
+        loop {
+            let rest = &self.source[offset..];
+            let trimmed = rest.trim_start();
+            let layout = &rest[..rest.len() - trimmed.len()];
+            newlines += layout.matches('\n').count();
+            offset += layout.len();
+
+            let len = if trimmed.starts_with(';') {
+                offset += 1;
+                continue;
+            } else if trimmed.starts_with('#') {
+                trimmed.find('\n').unwrap_or(trimmed.len())
+            } else if trimmed.starts_with("/*") {
+                trimmed.find("*/").map_or(trimmed.len(), |end| end + 2)
+            } else {
+                break;
+            };
+            comments.push(Comment {
+                text: trimmed[..len].trim_end(),
+                blank_lines: newlines.saturating_sub(1),
+                same_line: newlines == 0,
+            });
+            offset += len;
+            newlines = 0;
+        }
+
+        Gap {
+            comments,
+            blank_lines: newlines.saturating_sub(1),
+            end: offset,
+        }
+    }
+
+    /// Prints the comments of `gap`, each on its own line unless it followed a node on its line.
+    fn comments(&self, out: &mut String, gap: &Gap, after_node: bool, pad: &str) {
+        for (i, comment) in gap.comments.iter().enumerate() {
+            if i == 0 && after_node && comment.same_line {
+                out.push(' ');
+                out.push_str(comment.text);
+                continue;
+            }
+            if after_node || i > 0 {
+                let blank_lines = comment.blank_lines.min(self.options.max_blank_lines);
+                out.push_str(&"\n".repeat(blank_lines));
+            }
+            out.push('\n');
+            out.push_str(pad);
+            out.push_str(comment.text);
+        }
+    }
+
+    /// Prints `nodes` one per line, with the comments and blank lines around them, where the
+    /// first node follows `start`. Returns the offset of the token following the last node.
+    fn block<T: HasSpan>(
+        &self,
+        out: &mut String,
+        start: usize,
+        nodes: &[T],
+        indent: usize,
+        print: impl Fn(&T) -> String,
+    ) -> usize {
+        let pad = self.pad(indent);
+        let mut offset = start;
+        let mut i = 0;
+        while i < nodes.len() {
+            let gap = self.gap(offset);
+            self.comments(out, &gap, i > 0, &pad);
+            if i > 0 || !gap.comments.is_empty() {
+                let blank_lines = gap.blank_lines.min(self.options.max_blank_lines);
+                out.push_str(&"\n".repeat(blank_lines));
+            }
+            out.push('\n');
+
+            let node = nodes[i].span().start().to_usize();
+            match self.kept(offset, &nodes[i..]) {
+                Some((count, end)) => {
+                    let line = self.source[..node].rfind('\n').map_or(0, |i| i + 1);
+                    if self.source[line..node].trim().is_empty() {
+                        out.push_str(&self.source[line..end]);
+                    } else {
+                        out.push_str(&pad);
+                        out.push_str(&self.source[node..end]);
+                    }
+                    offset = end;
+                    i += count;
+                }
+                None => {
+                    out.push_str(&pad);
+                    out.push_str(&print(&nodes[i]));
+                    offset = nodes[i].span().end().to_usize();
+                    i += 1;
+                }
+            }
+        }
+
+        let gap = self.gap(offset);
+        self.comments(out, &gap, !nodes.is_empty(), &pad);
+        gap.end
+    }
+
+    /// Returns how many of `nodes` a directive in the gap at `offset` before them keeps as written,
+    /// along with the offset the text kept ends at.
+    fn kept<T: HasSpan>(&self, offset: usize, nodes: &[T]) -> Option<(usize, usize)> {
+        let start = nodes[0].span().start().to_usize();
+        let directive = self
+            .directives
+            .iter()
+            .filter(|(span, _)| offset <= span.start().to_usize() && span.end().to_usize() <= start)
+            .map(|&(_, directive)| directive)
+            .next_back()?;
+
+        match directive {
+            Directive::On => None,
+            Directive::Skip => Some((1, self.semicolon(nodes[0].span().end().to_usize()))),
+            Directive::Off => {
+                let on = self.directives.iter().find(|&&(span, directive)| {
+                    directive == Directive::On && start <= span.start().to_usize()
+                });
+                let count = nodes
+                    .iter()
+                    .take_while(|node| on.is_none_or(|(on, _)| node.span().start() < on.start()))
+                    .count();
+                let mut end = self.semicolon(nodes[count - 1].span().end().to_usize());
+                match on {
+                    Some((on, _)) if on.end().to_usize() <= self.gap(end).end => {
+                        end = on.end().to_usize()
+                    }
+                    _ => {}
+                }
+                Some((count, end))
+            }
+        }
+    }
+
+    /// Returns the offset after the `;` ending a binding at `end`, or `end` if there is none.
+    fn semicolon(&self, end: usize) -> usize {
+        let rest = &self.source[end..];
+        match rest.trim_start().strip_prefix(';') {
+            Some(after) => self.source.len() - after.len(),
+            None => end,
+        }
+    }
+
+    /// Prints `expr`, starting at column `col` of a line indented by `indent`.
+    fn expr(&self, expr: &Expr, indent: usize, col: usize) -> String {
+        if !self.has_comments(expr.span()) {
+            let flat = self.flat(expr);
+            if self.fits(&flat, col) {
+                return flat;
+            }
+        }
+        self.broken(expr, indent, col)
+    }
+
+    /// Prints `expr` on a single line, except for multi-line strings.
+    fn flat(&self, expr: &Expr) -> String {
+        match *expr {
+            Expr::Paren(ref paren) => format!("({})", self.flat(paren.expr())),
+            Expr::Interpolation(ref interp) => format!("${{{}}}", self.flat(interp.inner())),
+            Expr::List(ref list) if list.elems().is_empty() => "[ ]".to_owned(),
+            Expr::List(ref list) => {
+                let elems: Vec<_> = list.elems().iter().map(|e| self.flat(e)).collect();
+                format!("[ {} ]", elems.join(" "))
+            }
+            Expr::Set(ref set) => self.flat_binds("{", set.binds()),
+            Expr::Rec(ref rec) => self.flat_binds("rec {", rec.binds()),
+            Expr::Let(ref let_) => self.flat_binds("let {", let_.binds()),
+            Expr::Unary(ref unary) => format!("{}{}", unary.op(), self.flat(unary.expr())),
+            Expr::Binary(ref binary) => format!(
+                "{} {} {}",
+                self.flat(binary.left()),
+                binary.op(),
+                self.flat(binary.right())
+            ),
+            Expr::Proj(ref proj) => {
+                let mut out = format!("{}.{}", self.flat(proj.base()), self.attr(proj.attr()));
+                if let Some(fallback) = proj.fallback() {
+                    out.push_str(&format!(" or {}", self.flat(fallback)));
+                }
+                out
+            }
+            Expr::If(ref if_) => format!(
+                "if {} then {} else {}",
+                self.flat(if_.condition()),
+                self.flat(if_.body()),
+                self.flat(if_.fallback())
+            ),
+            Expr::Or(ref or) => format!("{} or {}", self.flat(or.expr()), self.flat(or.fallback())),
+            Expr::Assert(ref assert) => format!(
+                "assert {}; {}",
+                self.flat(assert.condition()),
+                self.flat(assert.expr())
+            ),
+            Expr::With(ref with) => {
+                format!(
+                    "with {}; {}",
+                    self.flat(with.with()),
+                    self.flat(with.expr())
+                )
+            }
+            Expr::LetIn(ref let_in) => {
+                let binds: Vec<_> = let_in.binds().iter().map(|b| self.flat_bind(b)).collect();
+                let body = self.flat(let_in.body());
+                if binds.is_empty() {
+                    format!("let in {}", body)
+                } else {
+                    format!("let {} in {}", binds.join(" "), body)
+                }
+            }
+            Expr::FnDecl(ref decl) => match **decl {
+                ExprFnDecl::Simple(ref simple) => {
+                    format!("{}: {}", simple.name(), self.flat(simple.body()))
+                }
+                ExprFnDecl::Formals(ref formals) => {
+                    let header = self.flat_formals(formals);
+                    format!("{}: {}", header, self.flat(formals.body()))
+                }
+            },
+            Expr::FnApp(ref app) => format!(
+                "{}{}{}",
+                self.flat(app.function()),
+                self.separator(app),
+                self.flat(app.argument())
+            ),
+            Expr::Ident(_) | Expr::Literal(_) | Expr::String(_) => {
+                self.text(expr.span()).to_owned()
+            }
+            Expr::Error(span) | Expr::Trap(span) => self.text(span).to_owned(),
+        }
+    }
+
+    /// Returns the text between the function and the argument of `app`. Interpolated paths such as
+    /// `./${name}.nix` are parsed as applications of their parts, which must stay adjacent, so
+    /// parts written without whitespace between them are kept that way.
+    fn separator(&self, app: &ExprFnApp) -> &'static str {
+        let start = app.argument().span().start().to_usize();
+        if self.source[..start].ends_with(char::is_whitespace) {
+            " "
+        } else {
+            ""
+        }
+    }
+
+    fn flat_binds(&self, open: &str, binds: &[Bind]) -> String {
+        if binds.is_empty() {
+            return format!("{} }}", open);
+        }
+        let binds: Vec<_> = binds.iter().map(|b| self.flat_bind(b)).collect();
+        format!("{} {} }}", open, binds.join(" "))
+    }
+
+    fn flat_bind(&self, bind: &Bind) -> String {
+        match *bind {
+            Bind::Simple(ref simple) => {
+                format!(
+                    "{} = {};",
+                    self.attr(simple.attr()),
+                    self.flat(simple.expr())
+                )
+            }
+            _ => self.inherit(bind),
+        }
+    }
+
+    fn inherit(&self, bind: &Bind) -> String {
+        let (from, names) = match *bind {
+            Bind::Inherit(ref inherit) => (None, inherit.names()),
+            Bind::InheritExpr(ref inherit) => (Some(inherit.expr()), inherit.names()),
+            Bind::Simple(_) => unreachable!("not an inherit"),
+        };
+        let mut out = "inherit".to_owned();
+        if let Some(from) = from {
+            out.push_str(&format!(" ({})", self.flat(from)));
+        }
+        for name in names {
+            out.push(' ');
+            out.push_str(name.as_str());
+        }
+        out.push(';');
+        out
+    }
+
+    fn attr(&self, path: &AttrPath) -> String {
+        let segments: Vec<_> = path
+            .segments()
+            .iter()
+            // The span of an interpolated segment includes the whitespace following it.
+            .map(|segment| self.text(segment.span()).trim_end())
+            .collect();
+        segments.join(".")
+    }
+
+    fn flat_formals(&self, decl: &FnDeclFormals) -> String {
+        let mut formals: Vec<_> = decl
+            .formals()
+            .iter()
+            .map(|formal| match formal.default() {
+                Some(default) => format!("{} ? {}", formal.name(), self.flat(default)),
+                None => formal.name().to_string(),
+            })
+            .collect();
+        if decl.ellipsis().is_some() {
+            formals.push("...".to_owned());
+        }
+
+        let set = if formals.is_empty() {
+            "{ }".to_owned()
+        } else {
+            format!("{{ {} }}", formals.join(", "))
+        };
+        self.with_extra(decl, set)
+    }
+
+    /// Adds the name bound to the whole argument of `decl`, on the side it was written on.
+    fn with_extra(&self, decl: &FnDeclFormals, set: String) -> String {
+        match decl.extra() {
+            Some(extra) if self.text(decl.span()).starts_with('{') => format!("{}@{}", set, extra),
+            Some(extra) => format!("{}@{}", extra, set),
+            None => set,
+        }
+    }
+
+    /// Prints `expr` over several lines.
+    fn broken(&self, expr: &Expr, indent: usize, col: usize) -> String {
+        let inner = indent + self.options.indent;
+        match *expr {
+            Expr::Set(ref set) => self.binds_block("{", set.span(), set.binds(), indent),
+            Expr::Rec(ref rec) => self.binds_block("rec {", rec.span(), rec.binds(), indent),
+            Expr::Let(ref let_) => self.binds_block("let {", let_.span(), let_.binds(), indent),
+            Expr::List(ref list) => {
+                let mut out = "[".to_owned();
+                let start = list.span().start().to_usize() + 1;
+                self.block(&mut out, start, list.elems(), inner, |elem| {
+                    self.expr(elem, inner, inner)
+                });
+                out.push('\n');
+                out.push_str(&self.pad(indent));
+                out.push(']');
+                out
+            }
+            Expr::LetIn(ref let_in) => {
+                let mut out = "let".to_owned();
+                let start = let_in.span().start().to_usize() + "let".len();
+                let end = self.block(&mut out, start, let_in.binds(), inner, |bind| {
+                    self.bind(bind, inner)
+                });
+                out.push('\n');
+                out.push_str(&self.pad(indent));
+                out.push_str("in");
+                let gap = self.gap(end + "in".len());
+                self.comments(&mut out, &gap, false, &self.pad(indent));
+                out.push('\n');
+                out.push_str(&self.pad(indent));
+                out.push_str(&self.expr(let_in.body(), indent, indent));
+                out
+            }
+            Expr::FnDecl(ref decl) => {
+                let (header, body) = match **decl {
+                    ExprFnDecl::Simple(ref simple) => {
+                        (format!("{}:", simple.name()), simple.body())
+                    }
+                    ExprFnDecl::Formals(ref formals) => {
+                        let header = self.formals(formals, indent, col);
+                        (format!("{}:", header), formals.body())
+                    }
+                };
+                self.statement(header, body, indent, col)
+            }
+            Expr::With(ref with) => {
+                let header = format!("with {};", self.expr(with.with(), indent, col + 5));
+                self.statement(header, with.expr(), indent, col)
+            }
+            Expr::Assert(ref assert) => {
+                let header = format!("assert {};", self.expr(assert.condition(), indent, col + 7));
+                self.statement(header, assert.expr(), indent, col)
+            }
+            Expr::If(ref if_) => {
+                let pad = self.pad(inner);
+                let cond = self.expr(if_.condition(), indent, col + 3);
+                let body = self.expr(if_.body(), inner, inner);
+                let mut out = format!(
+                    "if {} then\n{}{}\n{}else",
+                    cond,
+                    pad,
+                    body,
+                    self.pad(indent)
+                );
+                match *if_.fallback() {
+                    Expr::If(_) => {
+                        out.push(' ');
+                        out.push_str(&self.expr(if_.fallback(), indent, indent + 5));
+                    }
+                    ref fallback => {
+                        out.push('\n');
+                        out.push_str(&pad);
+                        out.push_str(&self.expr(fallback, inner, inner));
+                    }
+                }
+                out
+            }
+            Expr::FnApp(_) => {
+                let mut apps = Vec::new();
+                let mut function = expr;
+                while let Expr::FnApp(ref app) = *function {
+                    apps.push(app);
+                    function = app.function();
+                }
+
+                let mut out = self.expr(function, indent, col);
+                for app in apps.into_iter().rev() {
+                    let arg = app.argument();
+                    out.push_str(self.separator(app));
+                    let col = column(&out, col);
+                    out.push_str(&self.expr(arg, indent, col));
+                }
+                out
+            }
+            Expr::Binary(ref binary) => {
+                let mut operands = Vec::new();
+                chain(expr, binary.op(), &mut operands);
+                let op = binary.op().to_string();
+                let mut out = self.expr(operands[0], indent, col);
+                for operand in &operands[1..] {
+                    out.push('\n');
+                    out.push_str(&self.pad(inner));
+                    out.push_str(&op);
+                    out.push(' ');
+                    out.push_str(&self.expr(operand, inner, inner + op.len() + 1));
+                }
+                out
+            }
+            Expr::Paren(ref paren) => format!("({})", self.expr(paren.expr(), indent, col + 1)),
+            Expr::Interpolation(ref interp) => {
+                format!("${{{}}}", self.expr(interp.inner(), indent, col + 2))
+            }
+            Expr::Unary(ref unary) => {
+                format!("{}{}", unary.op(), self.expr(unary.expr(), indent, col + 1))
+            }
+            Expr::Proj(ref proj) => {
+                let mut out = self.expr(proj.base(), indent, col);
+                out.push('.');
+                out.push_str(&self.attr(proj.attr()));
+                if let Some(fallback) = proj.fallback() {
+                    out.push_str(" or ");
+                    let col = column(&out, col);
+                    out.push_str(&self.expr(fallback, indent, col));
+                }
+                out
+            }
+            Expr::Or(ref or) => {
+                let mut out = self.expr(or.expr(), indent, col);
+                out.push_str(" or ");
+                let col = column(&out, col);
+                out.push_str(&self.expr(or.fallback(), indent, col));
+                out
+            }
+            Expr::Ident(_)
+            | Expr::Literal(_)
+            | Expr::String(_)
+            | Expr::Error(_)
+            | Expr::Trap(_) => self.flat(expr),
+        }
+    }
+
+    /// Prints a header such as a function's arguments or `with x;` followed by `body`, which
+    /// starts on the next line if the header starts a line, and on the same line otherwise.
+    fn statement(&self, header: String, body: &Expr, indent: usize, col: usize) -> String {
+        if col == indent {
+            format!(
+                "{}\n{}{}",
+                header,
+                self.pad(indent),
+                self.expr(body, indent, indent)
+            )
+        } else {
+            let col = column(&header, col) + 1;
+            format!("{} {}", header, self.expr(body, indent, col))
+        }
+    }
+
+    fn binds_block(&self, open: &str, span: Span, binds: &[Bind], indent: usize) -> String {
+        let inner = indent + self.options.indent;
+        let start = span.start().to_usize();
+        let start = self.source[start..]
+            .find('{')
+            .map_or(start, |i| start + i + 1);
+        let mut out = open.to_owned();
+        self.block(&mut out, start, binds, inner, |bind| self.bind(bind, inner));
+        out.push('\n');
+        out.push_str(&self.pad(indent));
+        out.push('}');
+        out
+    }
+
+    fn bind(&self, bind: &Bind, indent: usize) -> String {
+        match *bind {
+            Bind::Simple(ref simple) => {
+                let head = format!("{} = ", self.attr(simple.attr()));
+                let value = self.expr(simple.expr(), indent, indent + head.chars().count());
+                format!("{}{};", head, value)
+            }
+            _ => self.inherit(bind),
+        }
+    }
+
+    /// Prints the arguments of a function, one per line if they do not fit on the line.
+    fn formals(&self, decl: &FnDeclFormals, indent: usize, col: usize) -> String {
+        let flat = self.flat_formals(decl);
+        if self.fits(&flat, col + 1) || decl.formals().is_empty() {
+            return flat;
+        }
+
+        let inner = indent + self.options.indent;
+        let mut out = String::new();
+        for (i, formal) in decl.formals().iter().enumerate() {
+            if i > 0 {
+                out.push('\n');
+                out.push_str(&self.pad(indent));
+            }
+            out.push_str(if i == 0 { "{ " } else { ", " });
+            out.push_str(formal.name().as_str());
+            if let Some(default) = formal.default() {
+                out.push_str(" ? ");
+                let col = column(&out, col);
+                out.push_str(&self.expr(default, inner, col));
+            }
+        }
+        if decl.ellipsis().is_some() {
+            out.push('\n');
+            out.push_str(&self.pad(indent));
+            out.push_str(", ...");
+        }
+        out.push('\n');
+        out.push_str(&self.pad(indent));
+        out.push('}');
+        self.with_extra(decl, out)
+    }
+}
+
+/// Collects the operands of a chain of binary operators `op`, in source order.
+fn chain<'e>(expr: &'e Expr, op: crate::ast::BinaryOp, out: &mut Vec<&'e Expr>) {
+    match *expr {
+        Expr::Binary(ref binary) if binary.op() == op => {
+            chain(binary.left(), op, out);
+            chain(binary.right(), op, out);
+        }
+        _ => out.push(expr),
+    }
+}
+
+/// Returns the column at the end of `text`, printed from column `col`.
+fn column(text: &str, col: usize) -> usize {
+    match text.rfind('\n') {
+        Some(newline) => text[newline + 1..].chars().count(),
+        None => col + text.chars().count(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn narrow(width: usize) -> Options {
+        Options {
+            max_width: width,
+            ..Options::default()
+        }
+    }
+
+    #[test]
+    fn breaks_long_expressions() {
+        let source = "{ stdenv, fetchurl, lib }: stdenv.mkDerivation { pname = \"hello\"; \
+                      buildInputs = [ lib.a lib.b ]; meta = with lib; { license = licenses.mit; }; \
+                      passthru = if x then y else z; }";
+        let expected = "\
+{ stdenv, fetchurl, lib }:
+stdenv.mkDerivation {
+  pname = \"hello\";
+  buildInputs = [ lib.a lib.b ];
+  meta = with lib; { license = licenses.mit; };
+  passthru = if x then y else z;
+}
+";
+        assert_eq!(format(source, &narrow(50)).unwrap(), expected);
+
+        let expected = "\
+{ stdenv
+, fetchurl
+, lib
+}:
+stdenv.mkDerivation {
+  pname = \"hello\";
+  buildInputs = [
+    lib.a
+    lib.b
+  ];
+  meta = with lib; {
+    license = licenses.mit;
+  };
+  passthru = if x then
+    y
+  else
+    z;
+}
+";
+        assert_eq!(format(source, &narrow(20)).unwrap(), expected);
+    }
+
+    #[test]
+    fn keeps_comments_and_blank_lines() {
+        let source = "# Top.\n\nlet\n  # The answer.\n  a = 42; # trailing\n\n\n\n  b = [\n    \
+                      1 # one\n    2\n  ];\n  # dangling\nin\n{ inherit a b; }";
+        let expected = "# Top.\n\nlet\n  # The answer.\n  a = 42; # trailing\n\n  b = [\n    \
+                        1 # one\n    2\n  ];\n  # dangling\nin\n{ inherit a b; }\n";
+        assert_eq!(format(source, &Options::default()).unwrap(), expected);
+        assert_eq!(format(expected, &Options::default()).unwrap(), expected);
+    }
+
+    #[test]
+    fn refuses_to_drop_comments() {
+        let source = "{ a = /* one */ 1; }";
+        assert_eq!(
+            format(source, &Options::default()),
+            Err(FormatError::Comments)
+        );
+        assert!(matches!(
+            format("{ a = ; }", &Options::default()),
+            Err(FormatError::Syntax(_))
+        ));
+    }
+
+    #[test]
+    fn keeps_text_marked_by_directives() {
+        let source = "{  \n  # nixfmt: off\n  a   = 1;  \n\n\n  # nixfmt: on  \n  b = 2;  \n  \
+                      # nixfmt: skip\n  c = [ 1   \n    2 ];  \n  d = [ 3\n  /* nixfmt: off */ 4   5 ]; \n}";
+        let expected = "{\n  # nixfmt: off\n  a   = 1;  \n\n\n  # nixfmt: on  \n  b = 2;\n  \
+                        # nixfmt: skip\n  c = [ 1   \n    2 ];\n  d = [\n    3\n    \
+                        /* nixfmt: off */\n    4   5\n  ];\n}\n";
+        assert_eq!(format(source, &Options::default()).unwrap(), expected);
+        assert_eq!(format(expected, &Options::default()).unwrap(), expected);
+
+        let source = "# nixfmt: off\n{ a   = 1; }";
+        assert_eq!(format(source, &Options::default()).unwrap(), source);
+    }
+}
@@ -0,0 +1,150 @@
+//! Interning of identifiers shared between syntax trees.
+//!
+//! Identifiers hold their name as an `Arc<str>`. Outside of [`Interner::scope`], every identifier
+//! allocates its own name. Within it, identifiers with equal names share a single allocation,
+//! which keeps memory down when parsing many files that mostly use the same few thousand names.
+//!
+//! To parse on several threads at once, each thread uses an interner created by
+//! [`Interner::caching`], which asks a shared interner for the names it has not seen yet.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+thread_local! {
+    static ACTIVE: RefCell<Option<Interner>> = const { RefCell::new(None) };
+}
+
+/// A set of interned names.
+#[derive(Clone, Debug, Default)]
+pub struct Interner {
+    names: HashSet<Arc<str>>,
+    lookups: usize,
+    /// The interner which allocates the names this one has not seen yet, if any.
+    shared: Option<Arc<Mutex<Interner>>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Interner::default()
+    }
+
+    /// Creates an interner which takes the names it has not seen yet from `shared`, so that
+    /// identifiers interned by different threads share their allocations too.
+    pub fn caching(shared: Arc<Mutex<Interner>>) -> Self {
+        Interner {
+            shared: Some(shared),
+            ..Interner::default()
+        }
+    }
+
+    /// Returns the shared allocation of `name`, adding it if it was not interned yet.
+    pub fn intern(&mut self, name: &str) -> Arc<str> {
+        self.lookups += 1;
+        if let Some(interned) = self.names.get(name) {
+            return interned.clone();
+        }
+
+        let interned = match self.shared {
+            Some(ref shared) => shared
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(name),
+            None => Arc::from(name),
+        };
+        self.names.insert(interned.clone());
+        interned
+    }
+
+    /// Returns the allocation of `name`, adding it if needed, without counting a lookup.
+    fn insert(&mut self, name: &str) -> Arc<str> {
+        if let Some(interned) = self.names.get(name) {
+            return interned.clone();
+        }
+        let interned: Arc<str> = Arc::from(name);
+        self.names.insert(interned.clone());
+        interned
+    }
+
+    /// Counts `lookups` made by interners caching this one.
+    #[cfg(any(feature = "workspace", test))]
+    pub(crate) fn count_lookups(&mut self, lookups: usize) {
+        self.lookups += lookups;
+    }
+
+    /// Returns the number of distinct names interned.
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Returns the number of names looked up, including repeated ones.
+    pub fn lookups(&self) -> usize {
+        self.lookups
+    }
+
+    /// Runs `f` with this interner used for every identifier created on the current thread.
+    pub fn scope<R, F: FnOnce() -> R>(&mut self, f: F) -> R {
+        struct Restore<'a> {
+            interner: &'a mut Interner,
+            previous: Option<Interner>,
+        }
+
+        impl Drop for Restore<'_> {
+            fn drop(&mut self) {
+                let active = ACTIVE.with(|active| active.replace(self.previous.take()));
+                *self.interner = active.unwrap_or_default();
+            }
+        }
+
+        let previous = ACTIVE.with(|active| active.replace(Some(mem::take(self))));
+        let _restore = Restore {
+            interner: self,
+            previous,
+        };
+        f()
+    }
+}
+
+/// Interns `name` with the interner in scope on the current thread, if any.
+pub(crate) fn intern(name: &str) -> Arc<str> {
+    ACTIVE.with(|active| match *active.borrow_mut() {
+        Some(ref mut interner) => interner.intern(name),
+        None => Arc::from(name),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares_names_within_scope() {
+        let mut interner = Interner::new();
+        let (a, b) = interner.scope(|| (intern("pkgs"), intern("pkgs")));
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!((interner.len(), interner.lookups()), (1, 2));
+
+        let c = intern("pkgs");
+        assert!(!Arc::ptr_eq(&a, &c));
+        assert!(Arc::ptr_eq(&a, &interner.intern("pkgs")));
+    }
+
+    #[test]
+    fn shares_names_across_threads() {
+        let shared = Arc::new(Mutex::new(Interner::new()));
+        let names: Vec<_> = (0..2)
+            .map(|_| {
+                let shared = shared.clone();
+                std::thread::spawn(move || Interner::caching(shared).scope(|| intern("lib")))
+            })
+            .map(|thread| thread.join().unwrap())
+            .collect();
+        assert!(Arc::ptr_eq(&names[0], &names[1]));
+        assert_eq!(shared.lock().unwrap().len(), 1);
+    }
+}
@@ -1,4 +1,5 @@
-pub use self::tokens::{CommentKind, StringFragment, Token, Tokens};
+pub use self::excerpt::Excerpt;
+pub use self::tokens::{CommentKind, StringFragment, StringKind, Token, Tokens};
 
 use codespan::Span;
 use nom::branch::alt;
@@ -9,10 +10,11 @@ use nom::multi::many0;
 use nom::sequence::{preceded, terminated};
 
 use self::lexers::{comment, identifier, interpolation, literal, operator, punctuation, string};
-use self::util::check_delims_balanced;
+use self::util::{check_delims_balanced, close_unterminated_strings};
 use crate::error::{Error, Errors, UnexpectedError};
 use crate::ToSpan;
 
+mod excerpt;
 mod lexers;
 mod tokens;
 mod util;
@@ -49,7 +51,7 @@ impl<'a> Lexer<'a> {
             Ok((_, tokens)) => {
                 let (mut tokens, mut errors) = filter_unexpected_tokens(tokens);
 
-                let end = input.fragment.len().saturating_sub(1) as u32;
+                let end = input.fragment.len() as u32;
                 let eof_span = Span::new(end, end);
 
                 let only_comments = tokens.iter().all(|t| t.is_comment());
@@ -58,6 +60,7 @@ impl<'a> Lexer<'a> {
                     errors.push(Error::Message(Span::initial(), message));
                     return Err(errors);
                 } else {
+                    errors.extend(close_unterminated_strings(&mut tokens));
                     errors.extend(check_delims_balanced(&tokens, eof_span));
                     errors
                 };
@@ -68,6 +71,15 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    /// Converts the lexed tokens into ones which do not borrow from the source text, so they can
+    /// be cached and sent across threads independently of it.
+    pub fn into_owned(self) -> Lexer<'static> {
+        Lexer {
+            tokens: self.tokens.into_iter().map(Token::into_owned).collect(),
+            errors: self.errors,
+        }
+    }
+
     pub fn tokens(&self) -> Tokens<'_> {
         Tokens::new(self.tokens.as_slice())
     }
@@ -77,6 +89,58 @@ impl<'a> Lexer<'a> {
     }
 }
 
+/// A token or a run of whitespace, as returned by [`lex`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Lexeme<'a> {
+    Token(Token<'a>),
+    Whitespace(Span),
+}
+
+impl<'a> ToSpan for Lexeme<'a> {
+    fn to_span(&self) -> Span {
+        match *self {
+            Lexeme::Token(ref token) => token.to_span(),
+            Lexeme::Whitespace(span) => span,
+        }
+    }
+}
+
+/// Splits `source` into tokens, comments and the whitespace between them, which together cover all
+/// of it in order.
+///
+/// This is meant for tools which work on the source text rather than on the syntax tree, such as
+/// highlighters. Unlike [`Lexer::new`], it never fails: text which is not valid Nix is returned as
+/// `Token::Unknown`, unterminated strings are left as they are and no `Eof` token is added.
+pub fn lex(source: &str) -> Vec<Lexeme<'_>> {
+    let mut lexemes = Vec::new();
+    let mut input = LocatedSpan::new(source);
+    while !input.fragment.is_empty() {
+        if let Ok((rest, space)) = multispace0::<_, Errors>(input) {
+            if !space.fragment.is_empty() {
+                lexemes.push(Lexeme::Whitespace(space.to_span()));
+            }
+            input = rest;
+        }
+        if input.fragment.is_empty() {
+            break;
+        }
+
+        match token(input) {
+            Ok((rest, token)) => {
+                lexemes.push(Lexeme::Token(token));
+                input = rest;
+            }
+            Err(_) => {
+                let error = UnexpectedError::new(format!("`{}`", input.fragment), input.to_span());
+                let token = Token::Unknown(input.fragment.into(), input.to_span(), error.into());
+                lexemes.push(Lexeme::Token(token));
+                break;
+            }
+        }
+    }
+    lexemes
+}
+
 fn token(input: LocatedSpan) -> IResult<Token> {
     alt((
         literal,
@@ -99,10 +163,9 @@ fn unknown(input: LocatedSpan) -> IResult<Token> {
 
 fn filter_unexpected_tokens(tokens: Vec<Token>) -> (Vec<Token>, Errors) {
     // FIXME: Replace this with `Vec::drain_filter()` once stabilized.
-    let (invalid, valid): (Vec<_>, _) = tokens.into_iter().partition(|token| match token {
-        Token::Unknown(..) => true,
-        _ => false,
-    });
+    let (invalid, valid): (Vec<_>, _) = tokens
+        .into_iter()
+        .partition(|token| matches!(token, Token::Unknown(..)));
     let errors: Errors = invalid
         .into_iter()
         .filter_map(|token| match token {
@@ -112,3 +175,140 @@ fn filter_unexpected_tokens(tokens: Vec<Token>) -> (Vec<Token>, Errors) {
         .collect();
     (valid, errors)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::parser::{parse_source_file_partial, parse_source_file_tokens};
+    use crate::HasSpan;
+
+    const SOURCE: &str = r#"{ a = "x ${y}"; /* c */ b = ./foo; }"#;
+
+    #[test]
+    fn owned_tokens_outlive_source() {
+        let lexer = {
+            let source = SOURCE.to_owned();
+            Lexer::new(&source).unwrap().into_owned()
+        };
+
+        let parsed = thread::spawn(move || {
+            let partial = parse_source_file_tokens(&lexer).unwrap();
+            partial.value().cloned()
+        });
+
+        let expected = parse_source_file_partial(SOURCE).unwrap();
+        assert_eq!(parsed.join().unwrap().as_ref(), expected.value());
+    }
+
+    #[test]
+    fn lexes_trivia() {
+        let source = "# doc\n{ a = 1; } $\n";
+        let lexemes = lex(source);
+        let mut offset = 0;
+        for lexeme in &lexemes {
+            let span = lexeme.to_span();
+            assert_eq!(span.start().to_usize(), offset);
+            offset = span.end().to_usize();
+        }
+        assert_eq!(offset, source.len());
+
+        let kinds: Vec<_> = lexemes
+            .iter()
+            .map(|lexeme| match *lexeme {
+                Lexeme::Token(Token::Unknown(..)) => "Unknown".to_string(),
+                Lexeme::Token(ref token) => format!("{:?}", token),
+                Lexeme::Whitespace(span) => format!(
+                    "{:?}",
+                    &source[span.start().to_usize()..span.end().to_usize()]
+                ),
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                "Comment(\" doc\")",
+                "\"\\n\"",
+                "LBrace",
+                "\" \"",
+                "Identifier(\"a\")",
+                "\" \"",
+                "Eq",
+                "\" \"",
+                "Integer(\"1\")",
+                "Semi",
+                "\" \"",
+                "RBrace",
+                "\" \"",
+                "Unknown",
+                "\"\\n\"",
+            ]
+        );
+
+        assert_eq!(lex("# only a comment").len(), 1);
+        assert!(lex("").is_empty());
+    }
+
+    #[test]
+    fn recovers_from_unterminated_strings() {
+        let source = "{\n  a = ''\n    hello\n\n  b = 2;\n  c = 3;\n}\n";
+        let partial = parse_source_file_partial(source).unwrap();
+        let errors: Vec<_> = partial.errors().unwrap().into_iter().collect();
+        assert_eq!(
+            errors,
+            [Error::Message(
+                Span::new(8, 10),
+                "unterminated multi-line string".to_string()
+            )]
+        );
+        let file = partial.value().unwrap().to_string();
+        assert!(file.contains("b = 2;") && file.contains("c = 3;"));
+
+        let source = "let a = ''\n  x = 1;\n\n  y = 2;\nin a";
+        let lexer = Lexer::new(source).unwrap();
+        let tokens: Vec<_> = lexer.tokens().iter().collect();
+        assert_eq!(tokens.len(), 12);
+        match (tokens[3], tokens[4]) {
+            (Token::String(_, _, string, _), Token::Semi(semi)) => {
+                assert_eq!(
+                    &source[string.start().to_usize()..],
+                    "''\n  x = 1;\n\n  y = 2;\nin a"
+                );
+                assert_eq!(
+                    &source[..string.end().to_usize()],
+                    "let a = ''\n  x = 1;\n\n"
+                );
+                assert_eq!(semi.start(), semi.end());
+            }
+            other => panic!("expected a string and a semicolon, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reports_unterminated_interpolations_in_multi_line_strings() {
+        let source = "''\n x ${ a ''${x}\n ''";
+        let lexer = Lexer::new(source).unwrap();
+        let errors: Vec<_> = lexer.errors().iter().cloned().collect();
+        assert_eq!(
+            errors,
+            [Error::Message(
+                Span::new(6, 8),
+                "unterminated interpolation".to_string()
+            )]
+        );
+        assert!(parse_source_file_partial(source).unwrap().has_errors());
+    }
+
+    #[test]
+    fn ends_at_the_end_of_input() {
+        for source in &["{ a = 1; }", "{ a = 1; }\n"] {
+            let lexer = Lexer::new(source).unwrap();
+            let eof = lexer.tokens().iter().last().unwrap().to_span();
+            assert_eq!(eof, Span::new(source.len() as u32, source.len() as u32));
+
+            let file = parse_source_file_partial(source).unwrap();
+            assert_eq!(file.value().unwrap().expr().span(), Span::new(0, 10));
+        }
+    }
+}
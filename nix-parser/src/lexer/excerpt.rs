@@ -0,0 +1,134 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use codespan::Span;
+use unicode_width::UnicodeWidthChar;
+
+use super::{Token, Tokens};
+use crate::ToSpan;
+
+/// A plain text rendering of the source code around a span, with the span underlined by carets.
+///
+/// This is meant for places where full `codespan-reporting` output is unsuitable, such as log
+/// files or the related information of LSP diagnostics. Lines are prefixed with their numbers,
+/// and carets are aligned by display width, so wide characters and tabs do not shift them.
+///
+/// ```text
+/// 2 | b = 1 + ;
+///   |         ^
+/// ```
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Excerpt<'a> {
+    source: &'a str,
+    window: Span,
+    span: Span,
+}
+
+impl<'a> Excerpt<'a> {
+    /// Creates an excerpt showing `window` of `source`, with `span` underlined.
+    pub fn new(source: &'a str, window: Span, span: Span) -> Self {
+        Excerpt {
+            source,
+            window: Span::new(
+                window.start().min(span.start()),
+                window.end().max(span.end()),
+            ),
+            span,
+        }
+    }
+}
+
+impl<'a> Display for Excerpt<'a> {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        let start = self.window.start().to_usize().min(self.source.len());
+        let end = self.window.end().to_usize().min(self.source.len());
+        let (span_start, span_end) = (self.span.start().to_usize(), self.span.end().to_usize());
+
+        let first_line = self.source[..start].matches('\n').count() + 1;
+        let last_line = first_line + self.source[start..end].matches('\n').count();
+        let gutter = last_line.to_string().len();
+
+        let mut line_start = start;
+        for (number, raw) in (first_line..).zip(self.source[start..end].split('\n')) {
+            let line = raw.trim_end_matches('\r');
+            let line_end = line_start + line.len();
+            writeln!(fmt, "{:>width$} | {}", number, line, width = gutter)?;
+
+            let from = span_start.max(line_start);
+            let to = span_end.min(line_end);
+            let touches_empty_span = span_start == span_end && from == span_start && from <= to;
+            if from < to || touches_empty_span {
+                let mut marker = String::new();
+                for c in line[..from - line_start].chars() {
+                    match c {
+                        '\t' => marker.push('\t'),
+                        c => marker.extend((0..c.width().unwrap_or(0)).map(|_| ' ')),
+                    }
+                }
+                let width: usize = line[from - line_start..to - line_start]
+                    .chars()
+                    .map(|c| if c == '\t' { 1 } else { c.width().unwrap_or(0) })
+                    .sum();
+                marker.extend((0..width.max(1)).map(|_| '^'));
+                writeln!(fmt, "{:width$} | {}", "", marker, width = gutter)?;
+            }
+
+            line_start += raw.len() + 1;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> Tokens<'a> {
+    /// Returns an excerpt of `source` showing the tokens overlapping `span`, along with up to
+    /// `context` tokens on either side of them.
+    pub fn excerpt<'s>(&self, source: &'s str, span: Span, context: usize) -> Excerpt<'s> {
+        let spans: Vec<Span> = self
+            .iter()
+            .filter(|token| !matches!(token, Token::Eof(_)))
+            .map(ToSpan::to_span)
+            .collect();
+
+        let overlapping = |s: &Span| s.end() > span.start() && s.start() < span.end();
+        let first = spans
+            .iter()
+            .position(overlapping)
+            .unwrap_or_else(|| spans.iter().filter(|s| s.end() <= span.start()).count());
+        let last = spans.iter().rposition(overlapping).unwrap_or(first);
+
+        let before = first.saturating_sub(context);
+        let after = (last + context).min(spans.len().saturating_sub(1));
+        let window = match (spans.get(before), spans.get(after)) {
+            (Some(start), Some(end)) => start.merge(*end),
+            _ => span,
+        };
+
+        Excerpt::new(source, window, span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Lexer;
+    use super::*;
+
+    #[test]
+    fn underlines_span_in_window() {
+        let source = "{\n  a = \"日本\";\n  b = 1 +\t;\n  c = 3;\n}";
+        let lexer = Lexer::new(source).unwrap();
+        let semi = source.find(";\n  c").unwrap() as u32;
+        let excerpt = lexer.tokens().excerpt(source, Span::new(semi, semi + 1), 6);
+        assert_eq!(
+            excerpt.to_string(),
+            "2 | \"日本\";\n3 |   b = 1 +\t;\n  |          \t^\n4 |   c = 3;\n5 | }\n"
+        );
+
+        let quote = source.find('"').unwrap() as u32 + 1;
+        let excerpt = Excerpt::new(
+            source,
+            Span::new(quote - 4, quote + 7),
+            Span::new(quote, quote + 6),
+        );
+        assert_eq!(excerpt.to_string(), "2 |  = \"日本\"\n  |     ^^^^\n");
+    }
+}
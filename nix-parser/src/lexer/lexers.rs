@@ -4,9 +4,9 @@ use codespan::Span;
 use nom::branch::alt;
 use nom::bytes::complete::{tag, take_while};
 use nom::character::complete::{anychar, char, line_ending, multispace0, not_line_ending, space0};
-use nom::combinator::{map, peek, recognize, verify};
-use nom::multi::{many0, many1, separated_nonempty_list};
-use nom::sequence::{pair, preceded, terminated, tuple};
+use nom::combinator::{map, not, recognize, verify};
+use nom::multi::separated_nonempty_list;
+use nom::sequence::{pair, preceded, terminated};
 use nom::Slice;
 use once_cell::sync::OnceCell;
 use regex::Regex;
@@ -14,7 +14,7 @@ use regex::Regex;
 use self::number::{float, integer};
 use self::path::{path, path_template};
 use self::uri::uri;
-use super::util::{map_spanned, split_lines_without_indentation};
+use super::util::{join_lines, map_spanned};
 use super::{token, CommentKind, IResult, LocatedSpan, Token};
 use crate::error::Error;
 use crate::ToSpan;
@@ -27,25 +27,27 @@ mod uri;
 pub fn comment(input: LocatedSpan) -> IResult<Token> {
     let span = map(not_line_ending, |s: LocatedSpan| s.fragment);
     let rows = separated_nonempty_list(pair(line_ending, space0), preceded(char('#'), span));
-    let text = map(rows, |rows| rows.join("\n"));
+    let text = map(rows, join_lines);
     let line_comment = map_spanned(text, |span, t| Token::Comment(t, CommentKind::Line, span));
     alt((line_comment, block_comment))(input)
 }
 
 fn block_comment(input: LocatedSpan) -> IResult<Token> {
-    let close_tag = recognize(pair(many1(char('*')), char('/')));
-    let maybe_consume_stars = alt((peek(close_tag), recognize(many0(char('*')))));
-    let (input, _) = tuple((tag("/*"), maybe_consume_stars, multispace0))(input)?;
+    let doc = terminated(tag("/**"), not(alt((char('*'), char('/')))));
+    let open = alt((
+        map(doc, |_| CommentKind::Doc),
+        map(tag("/*"), |_| CommentKind::Block),
+    ));
+    let (text, kind) = open(input)?;
 
     static REGEX: OnceCell<Regex> = OnceCell::new();
-    let regex = REGEX.get_or_init(|| Regex::new(r#"\*+/"#).unwrap());
-
-    if let Some(m) = regex.find(input.fragment) {
-        let span = input.slice(..m.start());
-        let remaining = input.slice(m.end()..);
-        let rows: Vec<_> = split_lines_without_indentation(span).collect();
-        let comment = Token::Comment(rows.join("\n"), CommentKind::Block, span.to_span());
-        Ok((remaining, comment))
+    let regex = REGEX.get_or_init(|| Regex::new(r#"\*/"#).unwrap());
+
+    if let Some(m) = regex.find(text.fragment) {
+        let raw = text.slice(..m.start());
+        let remaining = text.slice(m.end()..);
+        let span = Span::new(input.offset as u32, remaining.offset as u32);
+        Ok((remaining, Token::Comment(raw.fragment.into(), kind, span)))
     } else {
         let end = input.fragment.len();
         let remaining = input.slice((end - 1)..end);
@@ -1,16 +1,29 @@
+use std::borrow::Cow;
+
 use codespan::Span;
 use nom::branch::alt;
-use nom::bytes::complete::{escaped_transform, is_not, tag};
+use nom::bytes::complete::{escaped_transform, is_not, tag, take};
 use nom::character::complete::{anychar, char, multispace0, one_of};
-use nom::combinator::{cond, map, peek, recognize};
+use nom::combinator::{cond, map, not, peek, recognize};
 use nom::multi::many_till;
-use nom::sequence::{pair, terminated};
+use nom::sequence::{pair, preceded, terminated};
+use once_cell::sync::Lazy;
+use regex::Regex;
 
 use super::{punct_interpolate, punct_quote_double, punct_quote_single};
-use crate::lexer::util::split_lines_without_indentation;
-use crate::lexer::{token, IResult, LocatedSpan, StringFragment, Token};
+use crate::error::Error;
+use crate::lexer::util::{join_lines, split_lines_without_indentation};
+use crate::lexer::{token, IResult, LocatedSpan, StringFragment, StringKind, Token};
 use crate::ToSpan;
 
+/// Matches lines starting with a binding, `inherit`, `in` or a closing brace.
+static RECOVERY_LINE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r#"^[ \t]*(inherit(?-u:\b)|in(?-u:\b)|\}|[A-Za-z_][A-Za-z0-9_'-]*([ \t]*\.[ \t]*("[^"]*"|[A-Za-z_][A-Za-z0-9_'-]*))*[ \t]*=[^=])"#,
+    )
+    .unwrap()
+});
+
 pub fn string(input: LocatedSpan) -> IResult<Token> {
     let single = string_body(punct_quote_double, false);
     let multi = string_body(punct_quote_single, true);
@@ -27,12 +40,14 @@ where
     move |input| {
         let start = input;
         let (input, _) = pair(&delimiter, cond(is_multiline, multispace0))(input)?;
+        let indent_level = input.get_column() - 1;
 
         let mut remaining = input;
         let mut fragments = Vec::new();
+        let mut unterminated = None;
 
-        loop {
-            if let Ok((input, _)) = delimiter(remaining) {
+        while unterminated.is_none() {
+            if let Ok((input, _)) = preceded(not(indented_escape), &delimiter)(remaining) {
                 remaining = input;
                 break;
             } else if let Ok((input, _)) = terminated(punct_interpolate, multispace0)(remaining) {
@@ -61,29 +76,121 @@ where
 
                 let span = Span::new(start.offset as u32, remaining.offset as u32);
                 fragments.push(StringFragment::Interpolation(tokens, span));
+                if depth > 0 && is_multiline {
+                    // The input ended before the closing `}`, and with it the string.
+                    let open = Span::new(start.offset as u32, start.offset as u32 + 2);
+                    let message = "unterminated interpolation".to_string();
+                    unterminated = Some(Error::Message(open, message));
+                }
             } else {
                 let boundary = alt((&delimiter, punct_interpolate));
                 let (string, span) = if is_multiline {
-                    let (input, string) = recognize(many_till(anychar, peek(boundary)))(remaining)?;
-                    let lines: Vec<_> = split_lines_without_indentation(string).collect();
+                    let chars = alt((indented_escape, recognize(anychar)));
+                    let boundary = preceded(not(indented_escape), boundary);
+                    let (input, string) =
+                        match recognize(many_till(chars, peek(boundary)))(remaining) {
+                            Ok(found) => found,
+                            Err(_) => {
+                                // Rather than failing and lexing the rest of the file as
+                                // code, end the string where the code following it most
+                                // likely resumes. The missing delimiter is reported later.
+                                let open = Span::new(start.offset as u32, start.offset as u32 + 2);
+                                let message = "unterminated multi-line string".to_string();
+                                unterminated = Some(Error::Message(open, message));
+                                take(recovery_point(remaining.fragment))(remaining)?
+                            }
+                        };
                     remaining = input;
-                    (lines.join("\n"), string.to_span())
+                    if string.fragment.is_empty() {
+                        continue;
+                    }
+                    let text = join_lines(split_lines_without_indentation(string, indent_level));
+                    (unescape_indented(text), string.to_span())
                 } else {
                     let escape = recognize(pair(tag("\\"), one_of("\\\"$")));
                     let chars = alt((escape, recognize(anychar)));
                     let (input, string) = recognize(many_till(chars, peek(boundary)))(remaining)?;
-                    let (_, escaped) = escaped_transform(is_not("\\"), '\\', escape_codes)(string)?;
+                    let text = if string.fragment.contains('\\') {
+                        let transform = escaped_transform(is_not("\\"), '\\', escape_codes);
+                        Cow::Owned(transform(string)?.1)
+                    } else {
+                        Cow::Borrowed(string.fragment)
+                    };
                     remaining = input;
-                    (escaped, string.to_span())
+                    (text, string.to_span())
                 };
 
                 fragments.push(StringFragment::Literal(string, span));
             }
         }
 
+        let kind = if is_multiline {
+            StringKind::Indented
+        } else {
+            StringKind::Normal
+        };
         let span = Span::new(start.offset as u32, remaining.offset as u32);
-        Ok((remaining, Token::String(fragments, span)))
+        Ok((
+            remaining,
+            Token::String(fragments, kind, span, unterminated),
+        ))
+    }
+}
+
+/// Returns the offset in the remaining `text` of an unterminated multi-line string at which to
+/// resume lexing code, which is the first line looking like a binding or the end of a block after
+/// a blank line, or failing that the end of the file.
+fn recovery_point(text: &str) -> usize {
+    let mut offset = 0;
+    let mut after_blank = false;
+    for line in text.split_inclusive('\n') {
+        if after_blank && RECOVERY_LINE.is_match(line) {
+            return offset;
+        }
+        after_blank = line.trim().is_empty();
+        offset += line.len();
+    }
+    text.len()
+}
+
+/// Matches an escape sequence of an indented string: `'''` for `''`, `''$` for `$`, or `''\`
+/// followed by a character escaped as in a regular string.
+fn indented_escape(input: LocatedSpan) -> IResult<LocatedSpan> {
+    let escape = recognize(pair(tag("''\\"), anychar));
+    alt((tag("'''"), tag("''$"), escape))(input)
+}
+
+/// Replaces the escape sequences of an indented string with the characters they stand for.
+fn unescape_indented(text: Cow<str>) -> Cow<str> {
+    if !text.contains("''") {
+        return text;
+    }
+
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = &text[..];
+    while let Some(index) = rest.find("''") {
+        unescaped.push_str(&rest[..index]);
+        rest = &rest[index + 2..];
+        let mut chars = rest.chars();
+        match chars.next() {
+            Some('\'') => unescaped.push_str("''"),
+            Some('$') => unescaped.push('$'),
+            Some('\\') => match chars.next() {
+                Some('n') => unescaped.push('\n'),
+                Some('r') => unescaped.push('\r'),
+                Some('t') => unescaped.push('\t'),
+                Some(c) => unescaped.push(c),
+                None => {}
+            },
+            _ => {
+                unescaped.push_str("''");
+                continue;
+            }
+        }
+        rest = chars.as_str();
     }
+    unescaped.push_str(rest);
+    Cow::Owned(unescaped)
 }
 
 fn escape_codes(input: LocatedSpan) -> IResult<char> {
@@ -94,3 +201,48 @@ fn escape_codes(input: LocatedSpan) -> IResult<char> {
         anychar,
     ))(input)
 }
+
+#[cfg(test)]
+mod tests {
+    use nom::combinator::all_consuming;
+
+    use super::*;
+
+    fn lex_fragments(source: &str) -> Vec<StringFragment<'_>> {
+        match all_consuming(string)(LocatedSpan::new(source)) {
+            Ok((_, Token::String(fragments, ..))) => fragments,
+            Ok((_, token)) => panic!("lexing {:?} produced token: {:?}", source, token),
+            Err(err) => panic!("lexing {:?} failed: {:?}", source, err),
+        }
+    }
+
+    fn assert_literal(fragment: &StringFragment, text: &str, borrowed: bool) {
+        match *fragment {
+            StringFragment::Literal(ref value, _) => {
+                assert_eq!(value, text);
+                assert_eq!(matches!(value, Cow::Borrowed(_)), borrowed);
+            }
+            ref other => panic!("expected literal, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn borrows_unescaped_text() {
+        let fragments = lex_fragments(r#""foo ${bar} baz""#);
+        assert_literal(&fragments[0], "foo ", true);
+        assert_literal(&fragments[2], " baz", true);
+
+        let fragments = lex_fragments("''\n  single line\n''");
+        assert_literal(&fragments[0], "single line\n", false);
+    }
+
+    #[test]
+    fn cooks_escaped_text() {
+        let fragments = lex_fragments(r#""foo\n\"bar\"""#);
+        assert_literal(&fragments[0], "foo\n\"bar\"", false);
+
+        let fragments = lex_fragments("''\n  ''${a} '''b''' ''\\t\n    c ${d} e\n''");
+        assert_literal(&fragments[0], "${a} ''b'' \t\n  c ", false);
+        assert_literal(&fragments[2], " e\n", false);
+    }
+}
@@ -30,6 +30,11 @@ impl<'a> Tokens<'a> {
     pub fn current(&self) -> &'a Token<'a> {
         &self.tokens[0]
     }
+
+    #[inline]
+    pub fn iter(&self) -> slice::Iter<'a, Token<'a>> {
+        self.tokens.iter()
+    }
 }
 
 impl<'a> Debug for Tokens<'a> {
@@ -100,7 +105,7 @@ impl<'a> InputIter for Tokens<'a> {
     where
         P: Fn(Self::Item) -> bool,
     {
-        self.tokens.iter().position(|b| predicate(b))
+        self.tokens.iter().position(predicate)
     }
 
     #[inline]
@@ -165,18 +170,48 @@ impl<'a> ToSpan for Tokens<'a> {
     }
 }
 
+/// The syntax a comment is written in.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CommentKind {
+    /// One or more consecutive lines starting with `#`.
     Line,
+    /// A comment delimited by `/*` and `*/`.
     Block,
+    /// A documentation comment delimited by `/**` and `*/`.
+    Doc,
+}
+
+/// The quotes a string is delimited by.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StringKind {
+    /// A string delimited by `"`, in which `\` starts an escape sequence.
+    Normal,
+    /// A multi-line string delimited by `''`, whose lines are stripped of their common
+    /// indentation, and in which `''` starts an escape sequence.
+    Indented,
 }
 
 #[derive(Clone, PartialEq)]
 pub enum StringFragment<'a> {
-    Literal(String, Span),
+    Literal(Cow<'a, str>, Span),
     Interpolation(Vec<Token<'a>>, Span),
 }
 
+impl<'a> StringFragment<'a> {
+    /// Converts this fragment into one which does not borrow from the source text.
+    pub fn into_owned(self) -> StringFragment<'static> {
+        match self {
+            StringFragment::Literal(text, span) => StringFragment::Literal(owned(text), span),
+            StringFragment::Interpolation(tokens, span) => {
+                let tokens = tokens.into_iter().map(Token::into_owned);
+                StringFragment::Interpolation(tokens.collect(), span)
+            }
+        }
+    }
+}
+
 impl<'a> Debug for StringFragment<'a> {
     fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
         match *self {
@@ -196,7 +231,9 @@ pub enum Token<'a> {
     Unknown(Cow<'a, str>, Span, Error),
 
     // Literals
-    Comment(String, CommentKind, Span),
+    /// A comment with its text as written: the lines following each `#` of a line comment, or
+    /// everything between the delimiters of a block comment.
+    Comment(Cow<'a, str>, CommentKind, Span),
     Identifier(Cow<'a, str>, Span),
     Null(Span),
     Boolean(bool, Span),
@@ -205,7 +242,9 @@ pub enum Token<'a> {
     Interpolation(Vec<Token<'a>>, Span),
     Path(Cow<'a, str>, Span),
     PathTemplate(Cow<'a, str>, Span),
-    String(Vec<StringFragment<'a>>, Span),
+    /// A string, along with the error to report if it is never closed and the lexer ended it at a
+    /// recovery point instead.
+    String(Vec<StringFragment<'a>>, StringKind, Span, Option<Error>),
     Uri(Cow<'a, str>, Span),
 
     // Operators
@@ -259,27 +298,93 @@ pub enum Token<'a> {
 }
 
 impl<'a> Token<'a> {
-    pub fn is_comment(&self) -> bool {
-        match *self {
-            Token::Comment(..) => true,
-            _ => false,
+    /// Converts this token into one which does not borrow from the source text, so it can be
+    /// stored independently of it or sent across threads.
+    pub fn into_owned(self) -> Token<'static> {
+        match self {
+            Token::Eof(span) => Token::Eof(span),
+            Token::Unknown(text, span, error) => Token::Unknown(owned(text), span, error),
+            Token::Comment(text, kind, span) => Token::Comment(owned(text), kind, span),
+            Token::Identifier(text, span) => Token::Identifier(owned(text), span),
+            Token::Null(span) => Token::Null(span),
+            Token::Boolean(value, span) => Token::Boolean(value, span),
+            Token::Float(text, span) => Token::Float(owned(text), span),
+            Token::Integer(text, span) => Token::Integer(owned(text), span),
+            Token::Interpolation(tokens, span) => {
+                Token::Interpolation(tokens.into_iter().map(Token::into_owned).collect(), span)
+            }
+            Token::Path(text, span) => Token::Path(owned(text), span),
+            Token::PathTemplate(text, span) => Token::PathTemplate(owned(text), span),
+            Token::String(fragments, kind, span, error) => {
+                let fragments = fragments.into_iter().map(StringFragment::into_owned);
+                Token::String(fragments.collect(), kind, span, error)
+            }
+            Token::Uri(text, span) => Token::Uri(owned(text), span),
+
+            Token::Add(span) => Token::Add(span),
+            Token::Sub(span) => Token::Sub(span),
+            Token::Mul(span) => Token::Mul(span),
+            Token::Div(span) => Token::Div(span),
+            Token::IsEq(span) => Token::IsEq(span),
+            Token::NotEq(span) => Token::NotEq(span),
+            Token::LessThan(span) => Token::LessThan(span),
+            Token::LessThanEq(span) => Token::LessThanEq(span),
+            Token::GreaterThan(span) => Token::GreaterThan(span),
+            Token::GreaterThanEq(span) => Token::GreaterThanEq(span),
+            Token::LogicalAnd(span) => Token::LogicalAnd(span),
+            Token::LogicalOr(span) => Token::LogicalOr(span),
+            Token::Concat(span) => Token::Concat(span),
+            Token::Update(span) => Token::Update(span),
+            Token::Question(span) => Token::Question(span),
+            Token::Imply(span) => Token::Imply(span),
+            Token::Not(span) => Token::Not(span),
+            Token::Assert(span) => Token::Assert(span),
+            Token::Else(span) => Token::Else(span),
+            Token::If(span) => Token::If(span),
+            Token::In(span) => Token::In(span),
+            Token::Inherit(span) => Token::Inherit(span),
+            Token::Let(span) => Token::Let(span),
+            Token::Or(span) => Token::Or(span),
+            Token::Rec(span) => Token::Rec(span),
+            Token::Then(span) => Token::Then(span),
+            Token::With(span) => Token::With(span),
+            Token::At(span) => Token::At(span),
+            Token::Colon(span) => Token::Colon(span),
+            Token::Comma(span) => Token::Comma(span),
+            Token::Dot(span) => Token::Dot(span),
+            Token::Ellipsis(span) => Token::Ellipsis(span),
+            Token::Eq(span) => Token::Eq(span),
+            Token::Interpolate(span) => Token::Interpolate(span),
+            Token::LBrace(span) => Token::LBrace(span),
+            Token::RBrace(span) => Token::RBrace(span),
+            Token::LBracket(span) => Token::LBracket(span),
+            Token::RBracket(span) => Token::RBracket(span),
+            Token::LParen(span) => Token::LParen(span),
+            Token::RParen(span) => Token::RParen(span),
+            Token::QuoteDouble(span) => Token::QuoteDouble(span),
+            Token::QuoteSingle(span) => Token::QuoteSingle(span),
+            Token::Semi(span) => Token::Semi(span),
         }
     }
 
+    pub fn is_comment(&self) -> bool {
+        matches!(*self, Token::Comment(..))
+    }
+
     pub fn is_keyword(&self) -> bool {
-        match *self {
+        matches!(
+            *self,
             Token::Assert(_)
-            | Token::Else(_)
-            | Token::If(_)
-            | Token::In(_)
-            | Token::Inherit(_)
-            | Token::Let(_)
-            | Token::Or(_)
-            | Token::Rec(_)
-            | Token::Then(_)
-            | Token::With(_) => true,
-            _ => false,
-        }
+                | Token::Else(_)
+                | Token::If(_)
+                | Token::In(_)
+                | Token::Inherit(_)
+                | Token::Let(_)
+                | Token::Or(_)
+                | Token::Rec(_)
+                | Token::Then(_)
+                | Token::With(_)
+        )
     }
 
     pub fn description(&self) -> String {
@@ -296,7 +401,7 @@ impl<'a> Token<'a> {
             Token::Interpolation(_, _) => "interpolation".to_string(),
             Token::Path(_, _) => "path literal".to_string(),
             Token::PathTemplate(_, _) => "path template".to_string(),
-            Token::String(_, _) => "string".to_string(),
+            Token::String(..) => "string".to_string(),
             Token::Uri(_, _) => "URI".to_string(),
 
             Token::Add(_) => "operator `+`".to_string(),
@@ -371,7 +476,11 @@ impl<'a> Debug for Token<'a> {
             Token::PathTemplate(ref value, _) => {
                 fmt.debug_tuple("PathTemplate").field(&value).finish()
             }
-            Token::String(ref value, _) => fmt.debug_tuple("String").field(&value).finish(),
+            Token::String(ref value, kind, ..) => fmt
+                .debug_tuple("String")
+                .field(&value)
+                .field(&kind)
+                .finish(),
             Token::Uri(ref value, _) => fmt.debug_tuple("Uri").field(&value).finish(),
 
             Token::Add(_) => fmt.write_str("Add"),
@@ -438,7 +547,7 @@ impl<'a> ToSpan for Token<'a> {
             Token::Interpolation(_, ref span) => *span,
             Token::Path(_, ref span) => *span,
             Token::PathTemplate(_, ref span) => *span,
-            Token::String(_, ref span) => *span,
+            Token::String(_, _, ref span, _) => *span,
             Token::Uri(_, ref span) => *span,
 
             Token::Add(ref span) => *span,
@@ -496,3 +605,7 @@ impl<'a> InputLength for Token<'a> {
         1
     }
 }
+
+fn owned(text: Cow<'_, str>) -> Cow<'static, str> {
+    Cow::Owned(text.into_owned())
+}
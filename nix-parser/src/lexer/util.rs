@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+
 use codespan::Span;
 use nom::Slice;
 
@@ -62,13 +64,69 @@ pub fn check_delims_balanced(tokens: &[Token], eof_span: Span) -> Errors {
     errors
 }
 
-pub fn split_lines_without_indentation(input: LocatedSpan) -> impl Iterator<Item = &str> {
-    let indent_level = input.get_column();
-    input.fragment.split('\n').map(move |row| {
+/// Reports the multi-line strings which the lexer ended at a recovery point because they are never
+/// closed.
+///
+/// Such a string most likely ends a binding whose `;` is still to be written, so an empty `;` is
+/// inserted after it unless it is followed by one or by the end of the file. This lets the parser
+/// carry on with the code following the string.
+pub fn close_unterminated_strings(tokens: &mut Vec<Token>) -> Errors {
+    let mut errors = Errors::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        let (span, error) = match tokens[i] {
+            Token::String(_, _, span, Some(ref error)) => (span, error.clone()),
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        i += 1;
+
+        errors.push(error);
+        let end = Span::new(span.end(), span.end());
+        match tokens.get(i) {
+            None | Some(Token::Semi(_)) => {}
+            Some(_) => tokens.insert(i, Token::Semi(end)),
+        }
+    }
+    errors
+}
+
+/// Splits `input` into lines, stripping up to `indent_level` whitespace characters from the start
+/// of every line but the first, which starts after the quotes or an interpolation.
+pub fn split_lines_without_indentation(
+    input: LocatedSpan<'_>,
+    indent_level: usize,
+) -> impl Iterator<Item = &str> {
+    let mut rows = input.fragment.split('\n');
+    let first = rows.next();
+    first.into_iter().chain(rows.map(move |row| {
         let trim_start = row
             .char_indices()
             .take_while(|(i, c)| c.is_whitespace() && *i < indent_level)
             .count();
         &row[trim_start..]
-    })
+    }))
+}
+
+/// Joins the given rows with newlines, borrowing the row instead of allocating if there is only
+/// one.
+pub fn join_lines<'a, I>(rows: I) -> Cow<'a, str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut rows = rows.into_iter();
+    let first = rows.next().unwrap_or_default();
+    match rows.next() {
+        None => Cow::Borrowed(first),
+        Some(second) => {
+            let mut joined = format!("{}\n{}", first, second);
+            rows.for_each(|row| {
+                joined.push('\n');
+                joined.push_str(row);
+            });
+            Cow::Owned(joined)
+        }
+    }
 }
@@ -1,14 +1,47 @@
+//! A parser for the Nix expression language, which recovers from errors.
+//!
+//! Only the lexer, the parser and the syntax tree are built by default. The following features
+//! add optional functionality:
+//!
+//! * `fmt`: the formatter, in `fmt`.
+//! * `json`: conversion between plain data expressions and JSON values, in [`ast`].
+//! * `image`: flat syntax tree images which can be read in place, in `ast::image`.
+//! * `lint`: lints over the syntax tree, such as deprecated syntax, in `lint`.
+//! * `query`: structural search over syntax trees, in `ast::query`.
+//! * `reduce`: minimization of inputs which trigger a bug, in `reduce`.
+//! * `serde`: `Serialize` and `Deserialize` for the syntax tree and its spans, so that tools in
+//!   other languages can read it as JSON. Identifiers are interned again when deserialized.
+//! * `text-size`: conversion of spans to and from `text_size::TextRange`.
+//! * `workspace`: parsing of many files at once, in `workspace`.
+
 #![forbid(unsafe_code)]
 
 use codespan::Span;
 
 pub mod ast;
 pub mod error;
+#[cfg(any(feature = "fmt", test))]
+pub mod fmt;
+pub mod intern;
 pub mod lexer;
+#[cfg(any(feature = "lint", test))]
+pub mod lint;
 pub mod parser;
+#[cfg(any(feature = "reduce", test))]
+pub mod reduce;
+mod span;
+#[cfg(any(feature = "workspace", test))]
+pub mod workspace;
+
+pub use self::span::SpanRepr;
 
 pub trait HasSpan {
     fn span(&self) -> Span;
+
+    /// Returns the span in the given representation.
+    fn span_as<S: SpanRepr>(&self) -> S {
+        S::from_span(self.span())
+    }
 }
 
 impl HasSpan for Span {
@@ -27,7 +60,20 @@ impl ToSpan for Span {
     }
 }
 
-impl<'a, T: ToSpan> ToSpan for &'a T {
+impl ToSpan for (u32, u32) {
+    fn to_span(&self) -> Span {
+        self.into_span()
+    }
+}
+
+#[cfg(feature = "text-size")]
+impl ToSpan for text_size::TextRange {
+    fn to_span(&self) -> Span {
+        self.into_span()
+    }
+}
+
+impl<T: ToSpan> ToSpan for &T {
     fn to_span(&self) -> Span {
         (*self).to_span()
     }
@@ -0,0 +1,89 @@
+//! Lints over the syntax tree, checked without evaluating anything.
+//!
+//! Every lint is found by a [`Rule`] registered in [`RULES`] under a unique name, by which tools
+//! enable, suppress and explain it. A lint may come with a [`Suggestion`], a replacement of part
+//! of the source which fixes it.
+
+use codespan::Span;
+
+use crate::ast::SourceFile;
+
+mod deprecated;
+
+/// The rules known to this crate, sorted by name.
+pub const RULES: &[Rule] = &[deprecated::LET, deprecated::URI];
+
+/// A named check over a source file.
+#[derive(Clone, Copy, Debug)]
+pub struct Rule {
+    /// The name of the rule in kebab case, e.g. `deprecated-uri`.
+    pub name: &'static str,
+    /// What the rule flags, in a few words.
+    pub description: &'static str,
+    check: fn(&str, &SourceFile) -> Vec<Lint>,
+}
+
+impl Rule {
+    /// Returns the problems found by this rule in `file`, parsed from `source`.
+    pub fn check(&self, source: &str, file: &SourceFile) -> Vec<Lint> {
+        (self.check)(source, file)
+    }
+}
+
+/// A problem found by a rule.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Lint {
+    /// The name of the rule which found the problem.
+    pub rule: &'static str,
+    pub span: Span,
+    pub message: String,
+    /// What is wrong with the code at `span`.
+    pub label: String,
+    pub suggestion: Option<Suggestion>,
+}
+
+/// A fix for a lint.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Suggestion {
+    /// What the fix does, phrased as the title of an action, e.g. "Quote the URL".
+    pub message: String,
+    pub span: Span,
+    pub replacement: String,
+}
+
+/// Returns the rule with the given name.
+pub fn rule(name: &str) -> Option<&'static Rule> {
+    RULES.iter().find(|rule| rule.name == name)
+}
+
+/// Returns the problems found by every rule in `file`, parsed from `source`, in source order.
+pub fn check(source: &str, file: &SourceFile) -> Vec<Lint> {
+    let mut lints: Vec<_> = RULES
+        .iter()
+        .flat_map(|rule| rule.check(source, file))
+        .collect();
+    lints.sort_by_key(|lint| (lint.span.start(), lint.span.end()));
+    lints
+}
+
+/// Returns the text of `source` at `span`.
+fn slice(source: &str, span: Span) -> Option<&str> {
+    source.get(span.start().to_usize()..span.end().to_usize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registers_rules_by_name() {
+        let names: Vec<_> = RULES.iter().map(|rule| rule.name).collect();
+        let mut sorted = names.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(names, sorted);
+
+        assert_eq!(rule("deprecated-uri").unwrap().name, "deprecated-uri");
+        assert!(rule("no-such-rule").is_none());
+    }
+}
@@ -0,0 +1,185 @@
+//! Syntax which Nix still accepts but has deprecated.
+
+use codespan::Span;
+
+use super::{slice, Lint, Rule, Suggestion};
+use crate::ast::tokens::Literal;
+use crate::ast::visit::descendants;
+use crate::ast::{AttrSegment, Bind, Expr, ExprLet, SourceFile};
+use crate::lexer::{Lexer, Token};
+use crate::{HasSpan, ToSpan};
+
+/// Flags `let { ...; body = ...; }`, the old form of `let ... in ...`.
+pub const LET: Rule = Rule {
+    name: "deprecated-let",
+    description: "`let { }` expressions",
+    check: check_let,
+};
+
+/// Flags unquoted URLs such as `https://example.org`, which are disabled by the
+/// `no-url-literals` experimental feature.
+pub const URI: Rule = Rule {
+    name: "deprecated-uri",
+    description: "unquoted URL literals",
+    check: check_uri,
+};
+
+fn check_let(source: &str, file: &SourceFile) -> Vec<Lint> {
+    descendants(file.expr())
+        .filter_map(|expr| match *expr {
+            Expr::Let(ref let_) => Some(Lint {
+                rule: LET.name,
+                span: let_.span(),
+                message: "`let { ... }` is deprecated".to_string(),
+                label: "the result is the `body` attribute of this set".to_string(),
+                suggestion: let_in(source, let_).map(|(span, replacement)| Suggestion {
+                    message: "Rewrite as `let ... in`".to_string(),
+                    span,
+                    replacement,
+                }),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns the span of `let_` along with its rewrite as a `let ... in` expression, keeping the
+/// layout of its bindings.
+///
+/// The `body` binding is moved after `in`, unless the name `body` appears elsewhere in the
+/// expression, in which case it is kept and `in body` added.
+fn let_in(source: &str, let_: &ExprLet) -> Option<(Span, String)> {
+    let mut bodies = let_.binds().iter().filter(|bind| is_body(bind));
+    let body = match (bodies.next()?, bodies.next()) {
+        (Bind::Simple(ref body), None) if body.attr().segments().len() == 1 => body,
+        _ => return None,
+    };
+
+    // The braces and the uses of `body` are found from the tokens, which skips over strings and
+    // comments.
+    let lexer = Lexer::new(source).ok()?;
+    let tokens = lexer.tokens();
+    let mut tokens = tokens
+        .iter()
+        .skip_while(|token| token.to_span().start() < let_.span().start())
+        .skip(1);
+    let open = tokens.next()?.to_span().end().to_usize();
+    let (mut depth, mut uses) = (0, 0);
+    let close = tokens.find_map(|token| {
+        match *token {
+            Token::LBrace(_) => depth += 1,
+            Token::RBrace(span) if depth == 0 => return Some(span.start().to_usize()),
+            Token::RBrace(_) => depth -= 1,
+            Token::Identifier(ref name, _) if name == "body" => uses += 1,
+            _ => {}
+        }
+        None
+    })?;
+    let span = Span::new(let_.span().start(), close as u32 + 1);
+    let inner = source.get(open..close)?;
+    if uses > 1 {
+        return Some((span, format!("let{}in body", inner)));
+    }
+
+    let start = body.span().start().to_usize();
+    let end = body.span().end().to_usize();
+    let end = end + source[end..].find(';')? + 1;
+    let before = source.get(open..start)?.trim_end();
+    let after = source.get(end..close)?;
+    let value = slice(source, body.expr().span())?;
+    Some((span, format!("let{}{}in {}", before, after, value)))
+}
+
+/// Returns whether `bind` defines the `body` attribute, or one nested in it.
+fn is_body(bind: &Bind) -> bool {
+    let first = match *bind {
+        Bind::Simple(ref bind) => bind.attr().segments().first(),
+        _ => None,
+    };
+    matches!(first, Some(AttrSegment::Ident(ref ident)) if ident.as_str() == "body")
+}
+
+fn check_uri(source: &str, file: &SourceFile) -> Vec<Lint> {
+    descendants(file.expr())
+        .filter_map(|expr| match *expr {
+            Expr::Literal(Literal::Uri(_, span)) => Some(uri_lint(source, span)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn uri_lint(source: &str, span: Span) -> Lint {
+    Lint {
+        rule: URI.name,
+        span,
+        message: "unquoted URLs are deprecated".to_string(),
+        label: "write this URL as a string".to_string(),
+        suggestion: slice(source, span).map(|text| Suggestion {
+            message: "Quote the URL".to_string(),
+            span,
+            replacement: format!("\"{}\"", text),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lint::check;
+
+    fn fixed(source: &str) -> Vec<String> {
+        let file: SourceFile = source.parse().unwrap();
+        check(source, &file)
+            .into_iter()
+            .map(|lint| {
+                let suggestion = lint.suggestion.unwrap();
+                let (start, end) = (suggestion.span.start(), suggestion.span.end());
+                let (start, end) = (start.to_usize(), end.to_usize());
+                format!(
+                    "{}{}{}",
+                    &source[..start],
+                    suggestion.replacement,
+                    &source[end..]
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quotes_urls() {
+        let source = "{ src = https://example.org/a.tar.gz?x=1; }";
+        let file: SourceFile = source.parse().unwrap();
+        let lints = check(source, &file);
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].rule, "deprecated-uri");
+        assert_eq!(lints[0].message, "unquoted URLs are deprecated");
+        assert_eq!(
+            fixed(source),
+            [r#"{ src = "https://example.org/a.tar.gz?x=1"; }"#]
+        );
+
+        let source = r#"{ src = "https://example.org"; }"#;
+        assert!(check(source, &source.parse().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn converts_let_to_let_in() {
+        assert_eq!(
+            fixed("let { a = 1; body = a + 1; }"),
+            ["let a = 1; in a + 1"]
+        );
+        assert_eq!(
+            fixed("let {\n  a = 1;\n  body = a;\n  b = 2;\n}\n"),
+            ["let\n  a = 1;\n  b = 2;\nin a\n"]
+        );
+        assert_eq!(
+            fixed("let { body = 1; b = body; }"),
+            ["let body = 1; b = body; in body"]
+        );
+
+        let source = "let { a = 1; }";
+        let lints = check(source, &source.parse().unwrap());
+        assert_eq!(lints[0].message, "`let { ... }` is deprecated");
+        assert_eq!(lints[0].suggestion, None);
+    }
+}
@@ -1,7 +1,10 @@
+pub use self::expected::{expected_tokens, Expected};
 pub use self::partial::Partial;
+pub use self::reparse::reparse;
 
 use std::str::FromStr;
 
+use codespan::Span;
 use nom::combinator::{all_consuming, map, opt};
 use nom::sequence::terminated;
 
@@ -10,11 +13,14 @@ use crate::ast::{Expr, SourceFile};
 use crate::error::Errors;
 use crate::lexer::{Lexer, Tokens};
 
+mod expected;
 mod expr;
-mod partial;
+pub mod partial;
+mod reparse;
 mod tokens;
 
-type IResult<'a, T> = nom::IResult<Tokens<'a>, T, Errors>;
+/// The result of parsing a stream of tokens.
+pub type IResult<'a, T> = nom::IResult<Tokens<'a>, T, Errors>;
 
 impl FromStr for Expr {
     type Err = Errors;
@@ -60,6 +66,31 @@ pub fn parse_source_file(source: &str) -> Result<SourceFile, Errors> {
 
 pub fn parse_source_file_partial(source: &str) -> Result<Partial<SourceFile>, Errors> {
     let lexer = Lexer::new(source)?;
+    parse_source_file_tokens(&lexer)
+}
+
+/// Parses `source`, which is the text `previous` was parsed from with the bytes in `edit` replaced
+/// by `new_len` bytes.
+///
+/// Only the region of `previous` enclosing the edit is parsed again if possible, as with
+/// [`reparse`], and the spans of the nodes around it are shifted rather than recomputed.
+/// Otherwise the whole file is parsed from scratch.
+pub fn parse_source_file_incremental(
+    mut previous: SourceFile,
+    source: &str,
+    edit: Span,
+    new_len: u32,
+) -> Result<Partial<SourceFile>, Errors> {
+    if reparse(&mut previous, source, edit, new_len) {
+        Ok(Partial::from(previous))
+    } else {
+        parse_source_file_partial(source)
+    }
+}
+
+/// Parses a source file from tokens lexed ahead of time, e.g. ones cached with
+/// [`Lexer::into_owned`].
+pub fn parse_source_file_tokens(lexer: &Lexer) -> Result<Partial<SourceFile>, Errors> {
     let tokens = lexer.tokens();
     let errors = lexer.errors().clone();
 
@@ -0,0 +1,271 @@
+//! Computation of the tokens which may follow a position in a source file.
+//!
+//! This walks the tokens preceding the position while tracking the constructs left open by them,
+//! following the same structure as the parser: `if` awaits `then` and `else`, `let` awaits `in`,
+//! `assert`, `with` and bindings await `;`, and brackets await their closing counterparts.
+
+use crate::lexer::{Lexer, Token};
+use crate::ToSpan;
+
+/// A kind of token which may appear at a position.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Expected {
+    /// The start of any expression.
+    Expr,
+    /// An identifier naming an attribute or a formal argument.
+    Ident,
+    Then,
+    Else,
+    In,
+    Inherit,
+    Semi,
+    Eq,
+    Dot,
+    Comma,
+    Question,
+    Ellipsis,
+    RBrace,
+    RBracket,
+    RParen,
+}
+
+impl Expected {
+    /// Returns the source text of this token, or `None` if it stands for more than one token.
+    pub fn text(&self) -> Option<&'static str> {
+        match *self {
+            Expected::Expr | Expected::Ident => None,
+            Expected::Then => Some("then"),
+            Expected::Else => Some("else"),
+            Expected::In => Some("in"),
+            Expected::Inherit => Some("inherit"),
+            Expected::Semi => Some(";"),
+            Expected::Eq => Some("="),
+            Expected::Dot => Some("."),
+            Expected::Comma => Some(","),
+            Expected::Question => Some("?"),
+            Expected::Ellipsis => Some("..."),
+            Expected::RBrace => Some("}"),
+            Expected::RBracket => Some("]"),
+            Expected::RParen => Some(")"),
+        }
+    }
+
+    /// Returns whether this token is a keyword.
+    pub fn is_keyword(&self) -> bool {
+        matches!(
+            *self,
+            Expected::Then | Expected::Else | Expected::In | Expected::Inherit
+        )
+    }
+}
+
+/// A construct which has been opened but not closed yet.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Frame {
+    Paren,
+    Bracket,
+    Brace,
+    LetBinds,
+    If,
+    Then,
+    Assert,
+    With,
+    Bind,
+    Inherit,
+}
+
+/// Returns the tokens which may appear at byte `offset` of `source`, besides binary operators.
+///
+/// A word ending at `offset` is treated as the prefix of the token being typed, so `if a th|`
+/// expects `then`. Nothing is expected inside strings and comments.
+pub fn expected_tokens(source: &str, offset: usize) -> Vec<Expected> {
+    let lexer = match Lexer::new(source) {
+        Ok(lexer) => lexer,
+        Err(_) => return vec![Expected::Expr],
+    };
+
+    let mut preceding = Vec::new();
+    for token in lexer.tokens().iter() {
+        let span = token.to_span();
+        let (start, end) = (span.start().to_usize(), span.end().to_usize());
+        let is_word = matches!(*token, Token::Identifier(..)) || token.is_keyword();
+        if start >= offset || (end == offset && is_word) || matches!(*token, Token::Eof(_)) {
+            break;
+        } else if end > offset {
+            return Vec::new();
+        } else if !token.is_comment() {
+            preceding.push(token);
+        }
+    }
+
+    let mut stack = Vec::new();
+    for (i, token) in preceding.iter().enumerate() {
+        match **token {
+            Token::LParen(_) => stack.push(Frame::Paren),
+            Token::RParen(_) => close(&mut stack, Frame::Paren),
+            Token::LBracket(_) => stack.push(Frame::Bracket),
+            Token::RBracket(_) => close(&mut stack, Frame::Bracket),
+            Token::LBrace(_) => stack.push(Frame::Brace),
+            Token::RBrace(_) => close(&mut stack, Frame::Brace),
+            Token::Let(_) => match preceding.get(i + 1) {
+                Some(Token::LBrace(_)) => {}
+                _ => stack.push(Frame::LetBinds),
+            },
+            Token::In(_) => close(&mut stack, Frame::LetBinds),
+            Token::If(_) => stack.push(Frame::If),
+            Token::Then(_) => {
+                close(&mut stack, Frame::If);
+                stack.push(Frame::Then);
+            }
+            Token::Else(_) => close(&mut stack, Frame::Then),
+            Token::Assert(_) => stack.push(Frame::Assert),
+            Token::With(_) => stack.push(Frame::With),
+            Token::Inherit(_) => stack.push(Frame::Inherit),
+            Token::Eq(_) if in_binds(&stack) => stack.push(Frame::Bind),
+            Token::Semi(_) => {
+                let end = [Frame::Bind, Frame::Assert, Frame::With, Frame::Inherit];
+                let found = stack.iter().rposition(|frame| end.contains(frame));
+                let blocked = stack.iter().rposition(|frame| !is_expr(*frame));
+                if let Some(found) = found.filter(|&found| blocked.is_none_or(|b| b <= found)) {
+                    stack.truncate(found);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let top = stack.last().cloned();
+    let last = preceding.last().map(|token| &**token);
+    let before_last = preceding.len().checked_sub(2).map(|i| &*preceding[i]);
+
+    if top == Some(Frame::Inherit) {
+        return match last {
+            Some(Token::Inherit(_)) => vec![Expected::Ident, Expected::Expr],
+            _ => vec![Expected::Ident, Expected::Semi],
+        };
+    }
+
+    if !last.is_some_and(ends_operand) {
+        return match (top, last) {
+            (Some(Frame::Brace), Some(Token::LBrace(_))) => vec![
+                Expected::Ident,
+                Expected::Inherit,
+                Expected::Ellipsis,
+                Expected::RBrace,
+            ],
+            (Some(Frame::Brace), Some(Token::Semi(_))) => {
+                vec![Expected::Ident, Expected::Inherit, Expected::RBrace]
+            }
+            (Some(Frame::Brace), Some(Token::Comma(_))) => {
+                vec![Expected::Ident, Expected::Ellipsis]
+            }
+            (Some(Frame::LetBinds), Some(Token::Let(_))) => {
+                vec![Expected::Ident, Expected::Inherit]
+            }
+            (Some(Frame::LetBinds), Some(Token::Semi(_))) => {
+                vec![Expected::Ident, Expected::Inherit, Expected::In]
+            }
+            (_, Some(Token::Dot(_))) => vec![Expected::Ident],
+            (Some(Frame::Bracket), Some(Token::LBracket(_))) => {
+                vec![Expected::Expr, Expected::RBracket]
+            }
+            _ => vec![Expected::Expr],
+        };
+    }
+
+    match top {
+        None => Vec::new(),
+        Some(Frame::Paren) => vec![Expected::RParen],
+        Some(Frame::Bracket) => vec![Expected::Expr, Expected::RBracket],
+        Some(Frame::If) => vec![Expected::Then],
+        Some(Frame::Then) => vec![Expected::Else],
+        Some(Frame::Assert) | Some(Frame::With) | Some(Frame::Bind) => vec![Expected::Semi],
+        Some(Frame::LetBinds) => vec![Expected::Eq, Expected::Dot],
+        Some(Frame::Brace) => match before_last {
+            Some(Token::LBrace(_)) | Some(Token::Comma(_)) => vec![
+                Expected::Eq,
+                Expected::Dot,
+                Expected::Comma,
+                Expected::Question,
+                Expected::RBrace,
+            ],
+            _ => vec![Expected::Eq, Expected::Dot],
+        },
+        Some(Frame::Inherit) => unreachable!(),
+    }
+}
+
+/// Pops frames up to and including the innermost `frame`, if there is one.
+fn close(stack: &mut Vec<Frame>, frame: Frame) {
+    if let Some(i) = stack.iter().rposition(|f| *f == frame) {
+        stack.truncate(i);
+    }
+}
+
+/// Returns whether an `=` at this point starts the value of a binding.
+fn in_binds(stack: &[Frame]) -> bool {
+    matches!(stack.last(), Some(Frame::Brace) | Some(Frame::LetBinds))
+}
+
+/// Returns whether `frame` is part of an expression, rather than delimiting one.
+fn is_expr(frame: Frame) -> bool {
+    !matches!(
+        frame,
+        Frame::Paren | Frame::Bracket | Frame::Brace | Frame::LetBinds
+    )
+}
+
+/// Returns whether an expression may end with `token`.
+fn ends_operand(token: &Token) -> bool {
+    matches!(
+        *token,
+        Token::Identifier(..)
+            | Token::Null(_)
+            | Token::Boolean(..)
+            | Token::Float(..)
+            | Token::Integer(..)
+            | Token::Path(..)
+            | Token::PathTemplate(..)
+            | Token::String(..)
+            | Token::Uri(..)
+            | Token::RParen(_)
+            | Token::RBracket(_)
+            | Token::RBrace(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expected(source: &str) -> Vec<Expected> {
+        let offset = source.find('|').unwrap();
+        let source = source.replace('|', "");
+        expected_tokens(&source, offset)
+    }
+
+    #[test]
+    fn expects_closing_keywords() {
+        assert_eq!(expected("if a |"), [Expected::Then]);
+        assert_eq!(expected("if a th|"), [Expected::Then]);
+        assert_eq!(expected("if a then b |"), [Expected::Else]);
+        assert_eq!(expected("if a then b else |"), [Expected::Expr]);
+        assert_eq!(
+            expected("let a = 1; |"),
+            [Expected::Ident, Expected::Inherit, Expected::In]
+        );
+        assert_eq!(
+            expected("let a = if b then c else d; |"),
+            [Expected::Ident, Expected::Inherit, Expected::In]
+        );
+        assert_eq!(expected("with pkgs |"), [Expected::Semi]);
+        assert_eq!(expected("{ a = [ b ]|"), [Expected::Semi]);
+        assert_eq!(
+            expected("{ a = 1; |}"),
+            [Expected::Ident, Expected::Inherit, Expected::RBrace]
+        );
+        assert_eq!(expected("{ inherit a |"), [Expected::Ident, Expected::Semi]);
+        assert_eq!(expected("f (a |"), [Expected::RParen]);
+        assert_eq!(expected("\"a |\""), []);
+    }
+}
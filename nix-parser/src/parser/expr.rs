@@ -1,5 +1,3 @@
-use std::iter::{self, FromIterator};
-
 use codespan::Span;
 use nom::branch::alt;
 use nom::bytes::complete::take;
@@ -11,7 +9,9 @@ use super::partial::{
     expect_terminated, map_partial, map_partial_spanned, pair_partial, verify_full, Partial,
 };
 use super::{tokens, IResult};
-use crate::ast::{BinaryOp, Expr, ExprBinary, ExprFnApp, ExprIf, ExprProj, ExprUnary, UnaryOp};
+use crate::ast::{
+    Assoc, BinaryOp, Expr, ExprBinary, ExprFnApp, ExprIf, ExprProj, ExprUnary, UnaryOp,
+};
 use crate::error::{Errors, UnexpectedError};
 use crate::lexer::{Token, Tokens};
 use crate::{HasSpan, ToSpan};
@@ -55,148 +55,75 @@ fn if_else(input: Tokens) -> IResult<Partial<Expr>> {
 }
 
 fn imply(input: Tokens) -> IResult<Partial<Expr>> {
-    let expr = pair(and, many0(preceded(tokens::op_imply, and)));
-    map(expr, |(first, rest)| {
-        rest.into_iter().fold(first, |lhs, rhs| {
-            lhs.flat_map(|lhs| {
-                rhs.map(|rhs| {
-                    let span = Span::merge(lhs.span(), rhs.span());
-                    Expr::Binary(Box::new(ExprBinary::new(BinaryOp::Impl, lhs, rhs, span)))
-                })
-            })
-        })
-    })(input)
-}
-
-fn and(input: Tokens) -> IResult<Partial<Expr>> {
-    let expr = pair(or, many0(preceded(tokens::op_and, or)));
-    map(expr, |(first, rest)| {
-        rest.into_iter().fold(first, |lhs, rhs| {
-            lhs.flat_map(|lhs| {
-                rhs.map(|rhs| {
-                    let span = Span::merge(lhs.span(), rhs.span());
-                    Expr::Binary(Box::new(ExprBinary::new(BinaryOp::And, lhs, rhs, span)))
-                })
-            })
-        })
-    })(input)
-}
-
-fn or(input: Tokens) -> IResult<Partial<Expr>> {
-    let expr = pair(equality, many0(preceded(tokens::op_or, equality)));
-    map(expr, |(first, rest)| {
-        rest.into_iter().fold(first, |lhs, rhs| {
-            lhs.flat_map(|lhs| {
-                rhs.map(|rhs| {
-                    let span = Span::merge(lhs.span(), rhs.span());
-                    Expr::Binary(Box::new(ExprBinary::new(BinaryOp::Or, lhs, rhs, span)))
-                })
-            })
-        })
-    })(input)
-}
-
-fn equality(input: Tokens) -> IResult<Partial<Expr>> {
-    let eq = map(tokens::op_eq, |_| BinaryOp::Eq);
-    let neq = map(tokens::op_neq, |_| BinaryOp::NotEq);
-    let expr = pair(compare, opt(pair(alt((eq, neq)), compare)));
-    map(expr, |(lhs, op)| match op {
-        None => lhs,
-        Some((op, rhs)) => lhs.flat_map(|lhs| {
-            rhs.map(|rhs| {
-                let span = Span::merge(lhs.span(), rhs.span());
-                Expr::Binary(Box::new(ExprBinary::new(op, lhs, rhs, span)))
-            })
-        }),
-    })(input)
-}
-
-fn compare(input: Tokens) -> IResult<Partial<Expr>> {
-    let lte = map(tokens::op_lte, |_| BinaryOp::LessThanEq);
-    let lt = map(tokens::op_lt, |_| BinaryOp::LessThan);
-    let gte = map(tokens::op_gte, |_| BinaryOp::GreaterThanEq);
-    let gt = map(tokens::op_gt, |_| BinaryOp::GreaterThan);
-    let expr = pair(update, opt(pair(alt((lte, lt, gte, gt)), update)));
-    map(expr, |(lhs, op)| match op {
-        None => lhs,
-        Some((op, rhs)) => lhs.flat_map(|lhs| {
+    binary(input, 0)
+}
+
+/// Parses a chain of binary operators binding at least as tightly as `min`, using the precedence
+/// and associativity given by [`BinaryOp::precedence`] and [`BinaryOp::associativity`].
+fn binary(input: Tokens, min: u8) -> IResult<Partial<Expr>> {
+    let (mut input, mut lhs) = unary(input)?;
+    let mut unchainable = None;
+    while let Ok((remaining, op)) = binary_op(input) {
+        let precedence = op.precedence();
+        if precedence < min || unchainable == Some(precedence) {
+            break;
+        }
+
+        let (remaining, rhs) = match op.associativity() {
+            _ if op == BinaryOp::HasAttr => project(remaining)?,
+            Assoc::Right => binary(remaining, precedence)?,
+            Assoc::Left | Assoc::None => binary(remaining, precedence + 1)?,
+        };
+        unchainable = Some(precedence).filter(|_| op.associativity() == Assoc::None);
+        lhs = lhs.flat_map(|lhs| {
             rhs.map(|rhs| {
                 let span = Span::merge(lhs.span(), rhs.span());
                 Expr::Binary(Box::new(ExprBinary::new(op, lhs, rhs, span)))
             })
-        }),
-    })(input)
-}
+        });
+        input = remaining;
+    }
 
-fn update(input: Tokens) -> IResult<Partial<Expr>> {
-    let expr = pair(sum, many0(preceded(tokens::op_update, sum)));
-    map(expr, |(first, rest)| {
-        let exprs = Partial::from_iter(iter::once(first).chain(rest));
-        exprs.map(|mut exprs| {
-            let last = exprs.pop().unwrap();
-            exprs.into_iter().rev().fold(last, |lhs, rhs| {
-                let span = Span::merge(rhs.span(), lhs.span());
-                Expr::Binary(Box::new(ExprBinary::new(BinaryOp::Update, lhs, rhs, span)))
-            })
-        })
-    })(input)
+    Ok((input, lhs))
 }
 
-fn sum(input: Tokens) -> IResult<Partial<Expr>> {
-    let add = map(tokens::op_add, |_| BinaryOp::Add);
-    let sub = map(tokens::op_sub, |_| BinaryOp::Sub);
-    let expr = pair(product, many0(pair(alt((add, sub)), product)));
-    map(expr, |(first, rest)| {
-        rest.into_iter().fold(first, |lhs, (op, rhs)| {
-            lhs.flat_map(|lhs| {
-                rhs.map(|rhs| {
-                    let span = Span::merge(lhs.span(), rhs.span());
-                    Expr::Binary(Box::new(ExprBinary::new(op, lhs, rhs, span)))
-                })
-            })
-        })
-    })(input)
-}
-
-fn product(input: Tokens) -> IResult<Partial<Expr>> {
-    let mul = map(tokens::op_mul, |_| BinaryOp::Mul);
-    let div = map(tokens::op_div, |_| BinaryOp::Div);
-    let expr = pair(concat, many0(pair(alt((mul, div)), concat)));
-    map(expr, |(first, rest)| {
-        rest.into_iter().fold(first, |lhs, (op, rhs)| {
-            lhs.flat_map(|lhs| {
-                rhs.map(|rhs| {
-                    let span = Span::merge(lhs.span(), rhs.span());
-                    Expr::Binary(Box::new(ExprBinary::new(op, lhs, rhs, span)))
-                })
-            })
-        })
-    })(input)
+fn binary_op(input: Tokens) -> IResult<BinaryOp> {
+    alt((
+        map(tokens::op_imply, |_| BinaryOp::Impl),
+        map(tokens::op_or, |_| BinaryOp::Or),
+        map(tokens::op_and, |_| BinaryOp::And),
+        map(tokens::op_eq, |_| BinaryOp::Eq),
+        map(tokens::op_neq, |_| BinaryOp::NotEq),
+        map(tokens::op_lte, |_| BinaryOp::LessThanEq),
+        map(tokens::op_lt, |_| BinaryOp::LessThan),
+        map(tokens::op_gte, |_| BinaryOp::GreaterThanEq),
+        map(tokens::op_gt, |_| BinaryOp::GreaterThan),
+        map(tokens::op_update, |_| BinaryOp::Update),
+        map(tokens::op_add, |_| BinaryOp::Add),
+        map(tokens::op_sub, |_| BinaryOp::Sub),
+        map(tokens::op_mul, |_| BinaryOp::Mul),
+        map(tokens::op_div, |_| BinaryOp::Div),
+        map(tokens::op_concat, |_| BinaryOp::Concat),
+        map(tokens::op_question, |_| BinaryOp::HasAttr),
+    ))(input)
 }
 
-fn concat(input: Tokens) -> IResult<Partial<Expr>> {
-    let expr = pair(unary, many0(preceded(tokens::op_concat, unary)));
-    map(expr, |(first, rest)| {
-        let exprs = Partial::from_iter(iter::once(first).chain(rest));
-        exprs.map(|mut exprs| {
-            let last = exprs.pop().unwrap();
-            exprs.into_iter().rev().fold(last, |lhs, rhs| {
-                let span = Span::merge(rhs.span(), lhs.span());
-                Expr::Binary(Box::new(ExprBinary::new(BinaryOp::Concat, lhs, rhs, span)))
-            })
-        })
-    })(input)
+fn unary(input: Tokens) -> IResult<Partial<Expr>> {
+    alt((prefixed, fn_app, error))(input)
 }
 
-fn unary(input: Tokens) -> IResult<Partial<Expr>> {
+/// Parses a prefix operator, whose operand extends over the operators binding more tightly.
+fn prefixed(input: Tokens) -> IResult<Partial<Expr>> {
     let neg = map(tokens::op_sub, |_| UnaryOp::Neg);
     let not = map(tokens::op_not, |_| UnaryOp::Not);
-    let unary = pair_partial(map(opt(alt((neg, not))), Partial::from), fn_app);
-    let expr = map_partial_spanned(unary, |span, (unary, expr)| match unary {
-        Some(op) => Expr::Unary(Box::new(ExprUnary::new(op, expr, span))),
-        None => expr,
+    let start = tokens::skip_comments(input).to_span().start();
+    let (remaining, op) = alt((neg, not))(input)?;
+    let (remaining, expr) = binary(remaining, op.precedence())?;
+    let unary = expr.map(|expr| {
+        let span = Span::new(start, expr.span().end());
+        Expr::Unary(Box::new(ExprUnary::new(op, expr, span)))
     });
-    alt((expr, error))(input)
+    Ok((remaining, unary))
 }
 
 fn fn_app(input: Tokens) -> IResult<Partial<Expr>> {
@@ -240,7 +167,7 @@ fn atomic(input: Tokens) -> IResult<Partial<Expr>> {
 }
 
 fn error(input: Tokens) -> IResult<Partial<Expr>> {
-    let (remaining, tokens) = take(1usize)(input)?;
+    let (remaining, tokens) = take(1usize)(tokens::skip_comments(input))?;
     let mut errors = Errors::new();
     errors.push(UnexpectedError::new(
         tokens.current().description(),
@@ -1,37 +1,50 @@
+use codespan::Span;
 use nom::branch::alt;
 use nom::combinator::map;
 use nom::multi::many0;
 use nom::sequence::{pair, preceded, terminated};
 
-use super::{bind, expr, unary};
+use super::{bind, error, expr, project};
 use crate::ast::tokens::{Ident, Literal};
 use crate::ast::{
     Bind, Expr, ExprInterpolation, ExprLet, ExprList, ExprParen, ExprRec, ExprSet, ExprString,
     StringFragment,
 };
 use crate::error::{Error, Errors};
-use crate::lexer::{StringFragment as LexerFragment, Tokens};
-use crate::parser::partial::{expect_terminated, many_till_partial, map_partial_spanned, Partial};
+use crate::lexer::{StringFragment as LexerFragment, Token, Tokens};
+use crate::parser::partial::{
+    delimited_partial, expect_terminated, many_till_partial, map_partial_spanned, Partial,
+};
 use crate::parser::{tokens, IResult};
 
 pub fn paren(input: Tokens) -> IResult<Partial<ExprParen>> {
     let expr = terminated(expr, many0(tokens::comment));
-    let paren = expect_terminated(preceded(tokens::paren_left, expr), tokens::paren_right);
+    let paren = delimited_partial(tokens::paren_left, expr, tokens::paren_right);
     map_partial_spanned(paren, |span, inner| ExprParen::new(inner, span))(input)
 }
 
 pub fn interpolation(input: Tokens) -> IResult<Partial<ExprInterpolation>> {
     let (remaining, (tokens, span)) = tokens::interpolation(input)?;
-    let expr = if tokens.is_empty() {
+    let expr = interpolated(tokens, span)?;
+    Ok((remaining, expr.map(|e| ExprInterpolation::new(e, span))))
+}
+
+/// Parses the expression of an interpolation spanning `span` from its `tokens`.
+///
+/// The tokens are copied and followed by `Eof` like every token stream, so that recovering from
+/// errors within them stops at their end even if the interpolation is never closed. Any tokens
+/// following the expression are reported rather than dropped.
+fn interpolated(tokens: &[Token], span: Span) -> Result<Partial<Expr>, nom::Err<Errors>> {
+    if tokens.is_empty() {
         let mut errors = Errors::new();
         errors.push(Error::Message(span, "interpolation cannot be empty".into()));
-        Partial::with_errors(Some(Expr::Error(span)), errors)
-    } else {
-        let (_, expr) = expr(Tokens::new(&tokens))?;
-        expr
-    };
+        return Ok(Partial::with_errors(Some(Expr::Error(span)), errors));
+    }
 
-    Ok((remaining, expr.map(|e| ExprInterpolation::new(e, span))))
+    let mut tokens = tokens.to_vec();
+    tokens.push(Token::Eof(Span::new(span.end(), span.end())));
+    let (_, expr) = expect_terminated(expr, tokens::eof)(Tokens::new(&tokens))?;
+    Ok(expr)
 }
 
 pub fn set(input: Tokens) -> IResult<Partial<ExprSet>> {
@@ -52,37 +65,32 @@ fn set_binds(input: Tokens) -> IResult<Partial<Vec<Bind>>> {
     let term = alt((tokens::brace_right, tokens::semi));
     let binds = many_till_partial(bind::bind, pair(many0(tokens::comment), term));
     let set = terminated(binds, many0(tokens::comment));
-    expect_terminated(preceded(tokens::brace_left, set), tokens::brace_right)(input)
+    delimited_partial(tokens::brace_left, set, tokens::brace_right)(input)
 }
 
 pub fn list(input: Tokens) -> IResult<Partial<ExprList>> {
-    let unary = terminated(unary, many0(tokens::comment));
-    let elems = many_till_partial(unary, tokens::bracket_right);
+    // List elements bind tighter than function application, so `[ f x ]` has two elements.
+    let elem = terminated(alt((project, error)), many0(tokens::comment));
+    let elems = many_till_partial(elem, tokens::bracket_right);
     let inner = preceded(many0(tokens::comment), elems);
-    let list = expect_terminated(preceded(tokens::bracket_left, inner), tokens::bracket_right);
+    let list = delimited_partial(tokens::bracket_left, inner, tokens::bracket_right);
     map_partial_spanned(list, |span, exprs| ExprList::new(exprs, span))(input)
 }
 
 pub fn string(input: Tokens) -> IResult<Partial<ExprString>> {
-    let (remaining, (fragments, span)) = tokens::string(input)?;
+    let (remaining, (fragments, kind, span)) = tokens::string(input)?;
     let mut parts = Vec::with_capacity(fragments.len());
 
     for frag in fragments {
         match frag {
             LexerFragment::Literal(text, span) => {
-                parts.push(Partial::from(StringFragment::Literal(text.clone(), *span)));
+                parts.push(Partial::from(StringFragment::Literal(
+                    text.to_string(),
+                    *span,
+                )));
             }
             LexerFragment::Interpolation(tokens, span) => {
-                let expr = if tokens.is_empty() {
-                    let mut errors = Errors::new();
-                    let message = "interpolation cannot be empty".to_string();
-                    errors.push(Error::Message(*span, message));
-                    Partial::with_errors(Some(Expr::Error(*span)), errors)
-                } else {
-                    let (_, expr) = expr(Tokens::new(&tokens))?;
-                    expr
-                };
-
+                let expr = interpolated(tokens, *span)?;
                 parts.push(expr.map(|expr| {
                     StringFragment::Interpolation(ExprInterpolation::new(expr, *span))
                 }));
@@ -91,15 +99,18 @@ pub fn string(input: Tokens) -> IResult<Partial<ExprString>> {
     }
 
     let partial: Partial<Vec<_>> = parts.into_iter().collect();
-    Ok((remaining, partial.map(|frags| ExprString::new(frags, span))))
+    Ok((
+        remaining,
+        partial.map(|frags| ExprString::new(frags, kind, span)),
+    ))
 }
 
 pub fn literal(input: Tokens) -> IResult<Partial<Literal>> {
     let boolean = map(tokens::boolean, Partial::from);
     let null = map(tokens::null, Partial::from);
     let path = map(tokens::path, Partial::from);
-    let float = map(tokens::float, Partial::from);
-    let integer = map(tokens::integer, Partial::from);
+    let float = tokens::float;
+    let integer = tokens::integer;
     let path_template = map(tokens::path_template, Partial::from);
     let uri = map(tokens::uri, Partial::from);
     alt((boolean, null, float, integer, path, path_template, uri))(input)
@@ -2,24 +2,29 @@ use codespan::Span;
 use nom::branch::alt;
 use nom::combinator::map;
 use nom::multi::many0;
-use nom::sequence::{pair, preceded};
+use nom::sequence::{pair, preceded, terminated};
+use nom::{InputLength, Slice};
 
 use super::{attr, expr, util};
 use crate::ast::tokens::{Comment, Ident};
 use crate::ast::{Bind, BindInherit, BindInheritExpr, BindSimple};
 use crate::error::{Error, Errors, UnexpectedError};
-use crate::lexer::Tokens;
+use crate::lexer::{Token, Tokens};
 use crate::parser::partial::{
-    expect_terminated, map_partial, map_partial_spanned, pair_partial, Partial,
+    delimited_partial, expect_terminated, map_partial, map_partial_spanned, pair_partial, Partial,
 };
 use crate::parser::{tokens, IResult};
 use crate::{HasSpan, ToSpan};
 
 pub fn bind(input: Tokens) -> IResult<Partial<Bind>> {
-    let inherit_expr = map_partial(inherit_expr, Bind::InheritExpr);
-    let inherit = map_partial(inherit, Bind::Inherit);
-    let simple = map_partial(simple, Bind::Simple);
-    match expect_terminated(alt((inherit_expr, inherit, simple)), tokens::semi)(input) {
+    if let Ok(output) = terminated(unterminated, tokens::semi)(input) {
+        return Ok(output);
+    }
+    if let Some(output) = missing_semicolon(input) {
+        return Ok(output);
+    }
+
+    match expect_terminated(unterminated, tokens::semi)(input) {
         Ok(output) => Ok(output),
         Err(_) => {
             let mut errors = Errors::new();
@@ -31,6 +36,100 @@ pub fn bind(input: Tokens) -> IResult<Partial<Bind>> {
     }
 }
 
+/// Parses a binding without its `;`.
+fn unterminated(input: Tokens) -> IResult<Partial<Bind>> {
+    let inherit_expr = map_partial(inherit_expr, Bind::InheritExpr);
+    let inherit = map_partial(inherit, Bind::Inherit);
+    let simple = map_partial(simple, Bind::Simple);
+    alt((inherit_expr, inherit, simple))(input)
+}
+
+/// Recovers from a binding missing its `;`, as in `{ a = 1 b = 2; }` or `{ a = 1 }`, by ending it
+/// where the next binding or the enclosing set starts.
+///
+/// Otherwise the value of the binding would swallow the name of the next one, e.g. as the
+/// application `1 b`, and the error would only surface at the `=` following it.
+fn missing_semicolon(input: Tokens) -> Option<(Tokens, Partial<Bind>)> {
+    let end = match next_bind(input) {
+        Some(end) => end,
+        None => {
+            let (remaining, partial) = unterminated(input).ok()?;
+            return match remaining.iter().next() {
+                Some(Token::RBrace(_)) | Some(Token::In(_)) => {
+                    end_of_bind(input, remaining, partial)
+                }
+                _ => None,
+            };
+        }
+    };
+
+    // The binding is parsed from a copy of its tokens which ends in `Eof` where the next binding
+    // starts, like every token stream, so that recovering from errors within it stops there.
+    let next = input.slice(end..).current().to_span().start();
+    let mut truncated: Vec<_> = input.iter().take(end).cloned().collect();
+    truncated.push(Token::Eof(Span::new(next, next)));
+    let (remaining, partial) = unterminated(Tokens::new(&truncated)).ok()?;
+    if tokens::eof(remaining).is_err() {
+        return None;
+    }
+    end_of_bind(input, input.slice(end..), partial)
+}
+
+/// Returns `remaining` along with the binding parsed from the start of `input`, marked as missing
+/// its `;`.
+fn end_of_bind<'a>(
+    input: Tokens<'a>,
+    remaining: Tokens<'a>,
+    mut partial: Partial<Bind>,
+) -> Option<(Tokens<'a>, Partial<Bind>)> {
+    let consumed = input.input_len() - remaining.input_len();
+    let last = input.iter().take(consumed).rfind(|t| !t.is_comment())?;
+    partial.extend_errors(Some(Error::MissingSemicolon(last.to_span())));
+    Some((remaining, partial))
+}
+
+/// Returns the index of the token in `input` where a binding following the one at its start
+/// begins, if the latter has no `;`.
+fn next_bind(input: Tokens) -> Option<usize> {
+    let tokens = input.iter().as_slice();
+    let inherit = matches!(tokens.first(), Some(Token::Inherit(_)));
+    let mut depth = 0usize;
+    let mut value = inherit;
+    for (index, token) in tokens.iter().enumerate() {
+        match *token {
+            Token::LBrace(_) | Token::LBracket(_) | Token::LParen(_) | Token::Let(_) => depth += 1,
+            Token::RBrace(_) | Token::RBracket(_) | Token::RParen(_) | Token::In(_) => {
+                depth = depth.checked_sub(1)?;
+            }
+            Token::Semi(_) | Token::Eof(_) if depth == 0 => return None,
+            Token::Eq(_) if depth == 0 => value = true,
+            _ if depth == 0 && value && index > 0 && starts_bind(&tokens[index..]) => {
+                return Some(index);
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Returns whether `tokens` start with an attribute path followed by `=`.
+fn starts_bind(tokens: &[Token]) -> bool {
+    let mut tokens = tokens.iter().filter(|t| !t.is_comment());
+    loop {
+        match tokens.next() {
+            Some(Token::Identifier(..))
+            | Some(Token::String(..))
+            | Some(Token::Interpolation(..)) => {}
+            _ => return false,
+        }
+        match tokens.next() {
+            Some(Token::Dot(_)) => {}
+            Some(Token::Eq(_)) => return true,
+            _ => return false,
+        }
+    }
+}
+
 fn simple(input: Tokens) -> IResult<Partial<BindSimple>> {
     let found = "one of `;` or `}`";
     let error = util::error_expr_if(alt((tokens::semi, tokens::brace_right)), found);
@@ -52,7 +151,7 @@ fn inherit(input: Tokens) -> IResult<Partial<BindInherit>> {
 fn inherit_expr(input: Tokens) -> IResult<Partial<BindInheritExpr>> {
     let keyword_inherit = pair(many0(tokens::comment), tokens::keyword_inherit);
     let inner = alt((expr, util::error_expr_if(tokens::paren_right, "`}`")));
-    let expr = expect_terminated(preceded(tokens::paren_left, inner), tokens::paren_right);
+    let expr = delimited_partial(tokens::paren_left, inner, tokens::paren_right);
     let bind = preceded(keyword_inherit, pair_partial(expr, ident_sequence));
     map_partial_spanned(bind, |span, (expr, idents)| {
         BindInheritExpr::new(expr, idents, span)
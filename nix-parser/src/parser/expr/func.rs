@@ -1,7 +1,7 @@
 use codespan::Span;
 use nom::branch::alt;
 use nom::bytes::complete::take;
-use nom::combinator::{map, opt};
+use nom::combinator::{map, opt, peek};
 use nom::multi::many0;
 use nom::sequence::{delimited, pair, preceded, terminated};
 
@@ -39,14 +39,35 @@ fn formals(input: Tokens) -> IResult<Partial<FnDeclFormals>> {
         Partial::from(Formal::new(name, def, Span::merge(name_span, default_span)))
     });
 
-    let args = separated_list_partial(tokens::comma, tokens::brace_right, formal);
+    let ellipsis = || preceded(opt(tokens::comma), tokens::ellipsis);
+    let end = alt((tokens::brace_right, ellipsis()));
+    let args = separated_list_partial(tokens::comma, end, formal);
+    let args = map(pair(args, opt(ellipsis())), |(args, ellipsis)| {
+        args.map(|args| (args, ellipsis))
+    });
     let term = pair(tokens::brace_right, tokens::colon);
-    let formals = delimited(tokens::brace_left, args, term);
+    let formals = preceded(
+        peek(formals_start),
+        delimited(tokens::brace_left, args, term),
+    );
 
     let expr = alt((expr, util::error_expr_if(tokens::eof, "<eof>")));
-    map_partial_spanned(pair_partial(formals, expr), |span, (formals, expr)| {
-        FnDeclFormals::new(formals, None, None, expr, span)
-    })(input)
+    map_partial_spanned(
+        pair_partial(formals, expr),
+        |span, ((formals, ellipsis), expr)| FnDeclFormals::new(formals, ellipsis, None, expr, span),
+    )(input)
+}
+
+/// Recognizes the start of a formal argument list, telling it apart from an attribute set.
+///
+/// Without this, the error recovery of the argument list would happily skip over the bindings
+/// of a set such as `{ a = { b }: b; }` up to the `}:` of the function nested inside it.
+fn formals_start(input: Tokens) -> IResult<()> {
+    let empty = map(pair(tokens::brace_right, tokens::colon), |_| ());
+    let ellipsis = map(tokens::ellipsis, |_| ());
+    let next = alt((tokens::comma, tokens::op_question, tokens::brace_right));
+    let formal = map(pair(tokens::identifier, next), |_| ());
+    preceded(tokens::brace_left, alt((empty, ellipsis, formal)))(input)
 }
 
 fn identifier_arg(input: Tokens) -> IResult<Partial<Ident>> {
@@ -1,5 +1,7 @@
 use nom::branch::alt;
-use nom::sequence::preceded;
+use nom::combinator::map;
+use nom::multi::many0;
+use nom::sequence::{pair, preceded};
 
 use super::{bind, expr, util};
 use crate::ast::{ExprAssert, ExprLetIn, ExprWith};
@@ -26,10 +28,14 @@ pub fn assert(input: Tokens) -> IResult<Partial<ExprAssert>> {
 }
 
 pub fn let_in(input: Tokens) -> IResult<Partial<ExprLetIn>> {
-    let binds = many_till_partial(bind::bind, tokens::keyword_in);
+    let binds = many_till_partial(bind::bind, pair(many0(tokens::comment), tokens::keyword_in));
+    let comment = map(many0(tokens::comment), |mut comments| {
+        Partial::new(Some(comments.pop()))
+    });
+    let binds = pair_partial(binds, comment);
     let let_binds = expect_terminated(preceded(tokens::keyword_let, binds), tokens::keyword_in);
     let stmt = pair_partial(let_binds, expr);
-    map_partial_spanned(stmt, |span, (binds, body)| {
-        ExprLetIn::new(binds, body, span)
+    map_partial_spanned(stmt, |span, ((binds, comment), body)| {
+        ExprLetIn::new(binds, comment, body, span)
     })(input)
 }
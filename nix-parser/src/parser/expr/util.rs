@@ -10,7 +10,7 @@ use crate::ToSpan;
 pub fn error_expr_if<'a, O, F>(
     parser: F,
     found: &'a str,
-) -> impl Fn(Tokens<'a>) -> IResult<Partial<Expr>>
+) -> impl Fn(Tokens<'a>) -> IResult<'a, Partial<Expr>>
 where
     F: Fn(Tokens<'a>) -> IResult<O>,
     O: ToSpan,
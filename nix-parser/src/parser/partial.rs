@@ -1,15 +1,66 @@
+//! Error-tolerant parser combinators producing [`Partial`] values.
+//!
+//! A partial parser is any `Fn(Tokens) -> IResult<Partial<O>>`. Rather than failing on the first
+//! malformed construct, it records the error in the returned `Partial` and keeps going, so that
+//! the rest of the input still yields a value. Plain `nom` parsers can be mixed in freely, e.g.
+//! for tokens which must be present, and are lifted with `map(parser, Partial::from)`.
+//!
+//! The combinators here mirror their `nom` namesakes, and the [`PartialParser`] trait offers them
+//! as methods on any partial parser. They work on the tokens of the Nix [`Lexer`], which makes
+//! them usable for other grammars sharing its lexical syntax.
+//!
+//! # Examples
+//!
+//! A bracketed list of identifiers separated by commas, recovering from stray tokens:
+//!
+//! ```rust
+//! use nix_parser::error::{Error, Errors};
+//! use nix_parser::lexer::{Lexer, Token, Tokens};
+//! use nix_parser::parser::partial::{delimited_partial, separated_list_partial, Partial};
+//! use nix_parser::parser::IResult;
+//! use nix_parser::ToSpan;
+//! use nom::Slice;
+//!
+//! fn token<'a>(expects: &'static str) -> impl Fn(Tokens<'a>) -> IResult<'a, String> {
+//!     move |input: Tokens<'a>| match input.current() {
+//!         Token::Identifier(ref name, _) if expects == "identifier" => {
+//!             Ok((input.slice(1..), name.to_string()))
+//!         }
+//!         token if token.description() == expects => Ok((input.slice(1..), expects.into())),
+//!         token => {
+//!             let message = format!("expected {}", expects);
+//!             let errors: Errors = Some(Error::Message(token.to_span(), message)).into_iter().collect();
+//!             Err(nom::Err::Error(errors))
+//!         }
+//!     }
+//! }
+//!
+//! let lexer = Lexer::new("[ a, b 1, c ]").unwrap();
+//! let ident = |input| token("identifier")(input).map(|(rest, name)| (rest, Partial::from(name)));
+//! let list = separated_list_partial(token("comma"), token("right bracket"), ident);
+//! let list = delimited_partial(token("left bracket"), list, token("right bracket"));
+//!
+//! let (_, partial) = list(lexer.tokens()).unwrap();
+//! assert_eq!(partial.value().unwrap().as_slice(), ["a", "b", "c"]);
+//! assert!(partial.has_errors());
+//! ```
+//!
+//! [`Lexer`]: crate::lexer::Lexer
+
 use std::iter::FromIterator;
 
 use codespan::Span;
-use nom::bytes::complete::take;
 use nom::sequence::{preceded, terminated};
-use nom::InputLength;
+use nom::{InputLength, Slice};
 
 use super::{tokens, IResult};
 use crate::error::{Error, Errors};
-use crate::lexer::Tokens;
+use crate::lexer::{Token, Tokens};
 use crate::ToSpan;
 
+/// The number of tokens searched for a place to resume parsing a sequence after an error.
+const RECOVERY_WINDOW: usize = 32;
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Partial<T> {
     value: Option<T>,
@@ -232,6 +283,94 @@ where
     }
 }
 
+/// Combinator which runs the partial parser `f` between the parsers `open` and `close`.
+///
+/// The opening delimiter is required, but if the closing one is missing, an unclosed delimiter
+/// error will be appended to the `Partial` as in `expect_terminated()`.
+pub fn delimited_partial<'a, O1, O2, O3, F, G, H>(
+    open: G,
+    f: F,
+    close: H,
+) -> impl Fn(Tokens<'a>) -> IResult<Partial<O1>>
+where
+    F: Fn(Tokens<'a>) -> IResult<Partial<O1>>,
+    G: Fn(Tokens<'a>) -> IResult<O2>,
+    H: Fn(Tokens<'a>) -> IResult<O3>,
+{
+    expect_terminated(preceded(open, f), close)
+}
+
+/// Combinator which behaves like `nom::combinator::opt()`, producing a `Partial` containing
+/// `None` if the partial parser `f` does not apply.
+pub fn opt_partial<'a, O, F>(f: F) -> impl Fn(Tokens<'a>) -> IResult<Partial<Option<O>>>
+where
+    F: Fn(Tokens<'a>) -> IResult<Partial<O>>,
+{
+    move |input| match f(input) {
+        Ok((remaining, partial)) => Ok((remaining, partial.map(Some))),
+        Err(nom::Err::Error(_)) => Ok((input, Partial::from(None::<O>))),
+        Err(err) => Err(err),
+    }
+}
+
+/// A choice between partial parsers, implemented for tuples of up to eight of them.
+pub trait AltPartial<'a, O> {
+    /// Runs the alternatives on `input` as described in `alt_partial()`.
+    fn choice(&self, input: Tokens<'a>) -> IResult<'a, Partial<O>>;
+}
+
+macro_rules! alt_partial_tuple {
+    ($($parser:ident $index:tt),+) => {
+        impl<'a, O, $($parser),+> AltPartial<'a, O> for ($($parser,)+)
+        where
+            $($parser: Fn(Tokens<'a>) -> IResult<'a, Partial<O>>,)+
+        {
+            fn choice(&self, input: Tokens<'a>) -> IResult<'a, Partial<O>> {
+                let mut best: Option<(Tokens<'a>, Partial<O>)> = None;
+                let mut last = Errors::new();
+                $(
+                    match (self.$index)(input) {
+                        Ok((remaining, partial)) if !partial.has_errors() => {
+                            return Ok((remaining, partial));
+                        }
+                        Ok((remaining, partial)) => {
+                            let fewer = |(_, best): &(_, Partial<O>)| {
+                                partial.errors.len() < best.errors.len()
+                            };
+                            if best.as_ref().is_none_or(fewer) {
+                                best = Some((remaining, partial));
+                            }
+                        }
+                        Err(nom::Err::Error(err)) => last = err,
+                        Err(err) => return Err(err),
+                    }
+                )+
+                best.map(Ok).unwrap_or(Err(nom::Err::Error(last)))
+            }
+        }
+    };
+}
+
+alt_partial_tuple!(A 0, B 1);
+alt_partial_tuple!(A 0, B 1, C 2);
+alt_partial_tuple!(A 0, B 1, C 2, D 3);
+alt_partial_tuple!(A 0, B 1, C 2, D 3, E 4);
+alt_partial_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+alt_partial_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+alt_partial_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+/// Combinator which behaves like `nom::branch::alt()` over a tuple of partial parsers.
+///
+/// The first alternative parsing without errors wins. Failing that, the one which parsed with the
+/// fewest errors is picked, earlier ones winning ties, so that a recovered parse is preferred over
+/// none at all. If no alternative applies, the error of the last one is returned.
+pub fn alt_partial<'a, O, L>(alternatives: L) -> impl Fn(Tokens<'a>) -> IResult<Partial<O>>
+where
+    L: AltPartial<'a, O>,
+{
+    move |input| alternatives.choice(input)
+}
+
 /// Combinator which behaves like `nom::combinator::map()`, except it is a shorthand for:
 ///
 /// ```rust,ignore
@@ -264,8 +403,20 @@ where
 {
     move |input| {
         let (remainder, partial) = partial(input)?;
+        let start = tokens::skip_comments(input).to_span().start();
         let span = if remainder.input_len() > 0 {
-            Span::new(input.to_span().start(), remainder.to_span().start())
+            // Comments directly before the remainder belong to whatever follows.
+            let consumed = input.input_len() - remainder.input_len();
+            let last = input
+                .slice(..consumed)
+                .iter()
+                .rev()
+                .find(|t| !t.is_comment());
+            let end = match last {
+                Some(token) => token.to_span().end(),
+                None => remainder.to_span().start(),
+            };
+            Span::new(start, end)
         } else {
             input.to_span()
         };
@@ -300,12 +451,15 @@ where
                 }
                 Err(nom::Err::Failure(_)) | Err(nom::Err::Error(_)) => match f(input) {
                     Err(nom::Err::Failure(err)) | Err(nom::Err::Error(err)) => {
-                        if tokens::eof(input).is_ok() {
+                        if input.input_len() == 0 || tokens::eof(input).is_ok() {
                             let partial: Partial<_> = partials.into_iter().collect();
                             return Ok((input, partial));
-                        } else if let Ok((remainder, _)) = take::<_, _, Errors>(1usize)(input) {
+                        } else {
                             errors.extend(err);
-                            input = remainder;
+                            input = recover(input, |token| match *token {
+                                Token::Semi(_) => Some(Resume::After),
+                                _ => None,
+                            });
                         }
                     }
                     Err(err) => return Err(err),
@@ -394,12 +548,15 @@ where
                 Err(nom::Err::Failure(_)) | Err(nom::Err::Error(_)) => {
                     match preceded(&sep, &f)(input) {
                         Err(nom::Err::Failure(err)) | Err(nom::Err::Error(err)) => {
-                            if tokens::eof(input).is_ok() {
+                            if input.input_len() == 0 || tokens::eof(input).is_ok() {
                                 let partial: Partial<_> = partials.into_iter().collect();
                                 return Ok((input, partial));
-                            } else if let Ok((remainder, _)) = take::<_, _, Errors>(1usize)(input) {
+                            } else {
                                 errors.extend(err);
-                                input = remainder;
+                                input = recover(input, |token| match *token {
+                                    Token::Comma(_) => Some(Resume::Before),
+                                    _ => None,
+                                });
                             }
                         }
                         Err(err) => return Err(err),
@@ -415,6 +572,105 @@ where
     }
 }
 
+/// Extension methods offering the combinators of this module on any partial parser.
+pub trait PartialParser<'a, O>: Fn(Tokens<'a>) -> IResult<'a, Partial<O>> + Sized {
+    /// See `map_partial()`.
+    fn map_partial<U, F>(self, f: F) -> impl Fn(Tokens<'a>) -> IResult<'a, Partial<U>>
+    where
+        F: Fn(O) -> U,
+    {
+        map_partial(self, f)
+    }
+
+    /// See `map_partial_spanned()`.
+    fn map_partial_spanned<U, F>(self, f: F) -> impl Fn(Tokens<'a>) -> IResult<'a, Partial<U>>
+    where
+        F: Fn(Span, O) -> U,
+    {
+        map_partial_spanned(self, f)
+    }
+
+    /// See `pair_partial()`.
+    fn and_partial<U, G>(self, second: G) -> impl Fn(Tokens<'a>) -> IResult<'a, Partial<(O, U)>>
+    where
+        G: Fn(Tokens<'a>) -> IResult<'a, Partial<U>>,
+    {
+        pair_partial(self, second)
+    }
+
+    /// See `expect_terminated()`.
+    fn expect_terminated<U, G>(self, term: G) -> impl Fn(Tokens<'a>) -> IResult<'a, Partial<O>>
+    where
+        G: Fn(Tokens<'a>) -> IResult<'a, U>,
+    {
+        expect_terminated(self, term)
+    }
+
+    /// See `opt_partial()`.
+    fn opt_partial(self) -> impl Fn(Tokens<'a>) -> IResult<'a, Partial<Option<O>>> {
+        opt_partial(self)
+    }
+
+    /// See `verify_full()`.
+    fn verify_full(self) -> impl Fn(Tokens<'a>) -> IResult<'a, O> {
+        verify_full(self)
+    }
+}
+
+impl<'a, O, P> PartialParser<'a, O> for P where P: Fn(Tokens<'a>) -> IResult<'a, Partial<O>> {}
+
+/// Where to resume parsing relative to a token which separates the elements of a sequence.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Resume {
+    Before,
+    After,
+}
+
+/// Returns where to resume parsing a sequence after an element failed to parse at the start of
+/// `input`, which is always past at least one token.
+///
+/// Skipping a single token tends to produce another error for every remaining token of a broken
+/// element, so instead the nearest token within the next few which ends an element is looked for:
+/// a separator, as classified by `separator`, or a closing `}`, `]`, `)` or `in`, before which
+/// parsing resumes so that the terminator of the sequence gets a chance to match it. Delimited
+/// groups are skipped as a whole. If there is no such token, a single token is skipped. Empty input
+/// is returned unchanged.
+fn recover<'a>(input: Tokens<'a>, separator: fn(&Token) -> Option<Resume>) -> Tokens<'a> {
+    if input.input_len() == 0 {
+        return input;
+    }
+
+    let mut depth = 0usize;
+    for (i, token) in input.iter().enumerate().take(RECOVERY_WINDOW) {
+        let resume = match *token {
+            Token::LBrace(_) | Token::LBracket(_) | Token::LParen(_) | Token::Let(_) => {
+                depth += 1;
+                None
+            }
+            Token::RBrace(_) | Token::RBracket(_) | Token::RParen(_) | Token::In(_)
+                if depth > 0 =>
+            {
+                depth -= 1;
+                None
+            }
+            Token::RBrace(_) | Token::RBracket(_) | Token::RParen(_) | Token::In(_) => {
+                Some(Resume::Before)
+            }
+            Token::Eof(_) => Some(Resume::Before),
+            ref token if depth == 0 => separator(token),
+            _ => None,
+        };
+
+        match resume {
+            Some(Resume::Before) => return input.slice(i.max(1)..),
+            Some(Resume::After) => return input.slice(i + 1..),
+            None => {}
+        }
+    }
+
+    input.slice(1..)
+}
+
 /// Combinator which asserts that a given partial parser produces a value and contains no errors.
 pub fn verify_full<'a, O, F>(f: F) -> impl Fn(Tokens<'a>) -> IResult<O>
 where
@@ -428,3 +684,123 @@ where
             .map_err(nom::Err::Error)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nom::combinator::map;
+
+    use super::*;
+    use crate::error::UnexpectedError;
+    use crate::lexer::Lexer;
+    use crate::parser::parse_source_file_partial;
+
+    fn recovered(text: &str) -> (usize, String) {
+        let partial = parse_source_file_partial(text).unwrap();
+        let errors = partial.errors().map_or(0, |errors| errors.iter().count());
+        let value = partial.value().map(ToString::to_string).unwrap_or_default();
+        (errors, value)
+    }
+
+    #[test]
+    fn resynchronizes_at_structural_tokens() {
+        let (errors, value) = recovered("{ a = 1; 5 6 7 8 9; b = 3; }");
+        assert_eq!(errors, 1);
+        assert_eq!(value, "{a = 1; b = 3;}");
+
+        let (_, value) = recovered("{ a = 1; 5 { b = 2; } 6; c = 3; }");
+        assert_eq!(value, "{a = 1; c = 3;}");
+
+        let (errors, value) = recovered("{ a = foo bar baz = 1; b = 2; }");
+        assert_eq!(errors, 1);
+        assert_eq!(value, "{a = foo bar; baz = 1; b = 2;}");
+
+        let (errors, value) = recovered("{ a, b c d, e }: a");
+        assert_eq!(errors, 1);
+        assert_eq!(value, "{a, b, e}: a");
+    }
+
+    #[test]
+    fn reports_unexpected_tokens_in_lists() {
+        let partial = parse_source_file_partial("[ 1 2 ) 3 4 ]").unwrap();
+        let messages: Vec<_> = partial
+            .errors()
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(messages[0], "unexpected token: right parentheses");
+        assert!(messages
+            .iter()
+            .all(|message| !message.contains("nom error")));
+        assert_eq!(
+            partial.value().unwrap().to_string(),
+            "[1, 2, <error>, 3, 4]"
+        );
+    }
+
+    #[test]
+    fn recovers_from_missing_semicolons() {
+        let text = "{\n  a = f x\n  b.c = 2;\n  inherit d\n  \"e\" = 3\n}";
+        let partial = parse_source_file_partial(text).unwrap();
+        let errors: Vec<_> = partial.errors().unwrap().into_iter().collect();
+        let value = partial.value().unwrap().to_string();
+        assert_eq!(value, "{a = f x; b.c = 2; inherit d; \"e\" = 3;}");
+
+        let missing = |needle: &str| {
+            let start = text.find(needle).unwrap() as u32;
+            Error::MissingSemicolon(Span::new(start, start + needle.len() as u32))
+        };
+        assert_eq!(errors, [missing("x"), missing("d"), missing("3")]);
+
+        let (errors, value) = recovered("let a = 1\n  b = [ a ]\nin b\n");
+        assert_eq!(errors, 2);
+        assert_eq!(value, "let a = 1; b = [a];in b");
+    }
+
+    #[test]
+    fn recovers_within_bindings_missing_semicolons() {
+        let partial = parse_source_file_partial("let a = [ { } } s = 1; in a").unwrap();
+        assert!(partial.has_errors());
+
+        let text = "{\n  a = [ 1 2}\n  b = ''\n    x ${y}\n  '';\n}";
+        let partial = parse_source_file_partial(text).unwrap();
+        assert!(partial.has_errors());
+    }
+
+    #[test]
+    fn combines_partial_parsers() {
+        let lexer = Lexer::new("a ( b").unwrap();
+        let ident = || map(tokens::identifier, |ident| Partial::from(ident.to_string()));
+        fn broken(input: Tokens<'_>) -> IResult<'_, Partial<String>> {
+            let (remaining, _) = tokens::identifier(input)?;
+            let mut errors = Errors::new();
+            errors.push(UnexpectedError::new("identifier", input.to_span()));
+            Ok((
+                remaining,
+                Partial::with_errors(Some("broken".to_owned()), errors),
+            ))
+        }
+
+        let paren_left = || map(tokens::paren_left, Partial::from);
+        let (remaining, partial) = paren_left().opt_partial()(lexer.tokens()).unwrap();
+        assert_eq!(partial, Partial::from(None::<Span>));
+        assert_eq!(remaining.to_span(), lexer.tokens().to_span());
+
+        let (_, partial) = alt_partial((broken, ident()))(lexer.tokens()).unwrap();
+        assert_eq!(partial.verify().unwrap(), "a");
+        let paren = paren_left().map_partial(|_| String::new());
+        assert!(
+            alt_partial((paren, paren_left().map_partial(|_| String::new())))(lexer.tokens())
+                .is_err()
+        );
+        let (_, partial) =
+            alt_partial((paren_left().map_partial(|_| String::new()), broken))(lexer.tokens())
+                .unwrap();
+        assert!(partial.has_errors());
+
+        let paren = delimited_partial(tokens::paren_left, ident(), tokens::paren_right);
+        let (_, partial) = ident().and_partial(paren)(lexer.tokens()).unwrap();
+        assert_eq!(partial.value(), Some(&("a".to_owned(), "b".to_owned())));
+        assert_eq!(partial.errors().map(|errors| errors.len()), Some(1));
+    }
+}
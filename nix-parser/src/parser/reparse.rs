@@ -0,0 +1,214 @@
+//! Incremental reparsing of edited source files.
+
+use std::iter;
+use std::mem;
+
+use codespan::Span;
+
+use super::parse_expr;
+use crate::ast::edit::{children_mut, offset_spans, shift_spans};
+use crate::ast::visit::children;
+use crate::ast::{Expr, SourceFile};
+use crate::HasSpan;
+
+/// Updates `source` in place to reflect replacing the bytes in `edit` with `new_len` bytes, where
+/// `text` is the full source text after the edit.
+///
+/// Only the innermost attribute set, list or string enclosing the edit is parsed again. The new
+/// subtree is spliced into the tree and the spans of everything following it are shifted. Returns
+/// `false` and leaves `source` untouched if there is no such region, or if it no longer parses as
+/// the same kind of expression; the whole file must then be parsed from scratch.
+pub fn reparse(source: &mut SourceFile, text: &str, edit: Span, new_len: u32) -> bool {
+    let path = match enclosing_region(source.expr(), edit) {
+        Some(path) => path,
+        None => return false,
+    };
+
+    let region = path
+        .iter()
+        .fold(source.expr(), |expr, &i| children(expr)[i]);
+
+    let span = region.span();
+    let old_len = (edit.end() - edit.start()).to_usize();
+    let start = span.start().to_usize();
+    let end = (span.end().to_usize() + new_len as usize).saturating_sub(old_len);
+    let expr = match parse_region(text, Span::new(start as u32, end as u32)) {
+        Some(expr) if mem::discriminant(&expr) == mem::discriminant(region) => expr,
+        _ => return false,
+    };
+
+    // The spans inside the old region may overlap the edit, so it is taken out of the tree before
+    // shifting the rest. Only its own span is left in its place, which encloses the edit.
+    *region_mut(source, &path) = Expr::Error(span);
+    shift_spans(source, edit, new_len);
+    *region_mut(source, &path) = expr;
+    true
+}
+
+/// Returns the expression at the end of `path`, as given by [`enclosing_region`].
+fn region_mut<'a>(source: &'a mut SourceFile, path: &[usize]) -> &'a mut Expr {
+    path.iter().fold(source.expr_mut(), |expr, &i| {
+        children_mut(expr).swap_remove(i)
+    })
+}
+
+/// Parses the expression spanning exactly `span` of `text`, with spans relative to the whole text.
+fn parse_region(text: &str, span: Span) -> Option<Expr> {
+    let (start, end) = (span.start().to_usize(), span.end().to_usize());
+    let body = text.get(start..end)?;
+
+    // Pad the region with the preceding text on its line, so that columns (and therefore the
+    // indentation stripped from multi-line strings) match the original file.
+    let line_start = text[..start].rfind('\n').map_or(0, |i| i + 1);
+    let padding = text[line_start..start].chars().map(char::len_utf8).sum();
+    let padded: String = iter::repeat_n(' ', padding).chain(body.chars()).collect();
+
+    let mut expr = parse_expr(&padded).ok()?;
+    offset_spans(&mut expr, line_start as u32);
+    Some(expr).filter(|expr| expr.span() == span)
+}
+
+/// Returns the path of child indices leading to the innermost balanced region strictly enclosing
+/// the edited range, so that its delimiters are left untouched.
+fn enclosing_region(root: &Expr, edit: Span) -> Option<Vec<usize>> {
+    let mut path = Vec::new();
+    let mut found = None;
+    let mut expr = root;
+
+    loop {
+        let span = expr.span();
+        let is_region = matches!(
+            *expr,
+            Expr::Set(_) | Expr::Rec(_) | Expr::Let(_) | Expr::List(_) | Expr::String(_)
+        );
+        if is_region && span.start() < edit.start() && edit.end() < span.end() {
+            found = Some(path.len());
+        }
+
+        let children = children(expr);
+        let contains = |child: &&Expr| {
+            let span = child.span();
+            span.start() <= edit.start() && edit.end() <= span.end()
+        };
+
+        match children.iter().position(contains) {
+            Some(i) => {
+                path.push(i);
+                expr = children[i];
+            }
+            None => break,
+        }
+    }
+
+    found.map(|len| {
+        path.truncate(len);
+        path
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::visit::descendants;
+    use crate::parser::parse_source_file_incremental;
+
+    fn spans(source: &SourceFile) -> Vec<Span> {
+        descendants(source.expr()).map(HasSpan::span).collect()
+    }
+
+    fn apply(text: &str, start: u32, end: u32, insert: &str) -> (String, Span, u32) {
+        let mut new = text.to_owned();
+        new.replace_range(start as usize..end as usize, insert);
+        (new, Span::new(start, end), insert.len() as u32)
+    }
+
+    #[test]
+    fn reparses_enclosing_region() {
+        let text = "{\n  a = [ 1 2 ];\n  b = ''\n    x ${y}\n  '';\n  c = 3;\n}";
+        let mut source: SourceFile = text.parse().unwrap();
+
+        let offset = text.find('2').unwrap() as u32;
+        let (text, edit, new_len) = apply(text, offset, offset + 1, "20 30");
+        assert!(reparse(&mut source, &text, edit, new_len));
+        let expected: SourceFile = text.parse().unwrap();
+        assert_eq!(source, expected);
+        assert_eq!(spans(&source), spans(&expected));
+
+        let offset = text.find('x').unwrap() as u32;
+        let (text, edit, new_len) = apply(&text, offset, offset, "z ");
+        assert!(reparse(&mut source, &text, edit, new_len));
+        let expected: SourceFile = text.parse().unwrap();
+        assert_eq!(source, expected);
+        assert_eq!(spans(&source), spans(&expected));
+    }
+
+    #[test]
+    fn reparses_edits_overlapping_nested_spans() {
+        let text = r#"{ b = "x ${y} z"; }"#;
+        let mut source: SourceFile = text.parse().unwrap();
+
+        let offset = text.find("${y").unwrap() as u32;
+        let (text, edit, new_len) = apply(text, offset, offset + 3, "?");
+        assert!(reparse(&mut source, &text, edit, new_len));
+        let expected: SourceFile = text.parse().unwrap();
+        assert_eq!(source, expected);
+        assert_eq!(spans(&source), spans(&expected));
+    }
+
+    #[test]
+    fn agrees_with_full_parse_on_every_small_edit() {
+        let text = "{\n  a = [ 1 2 ];\n  b = ''\n    x ${y}\n  '';\n  c = \"x ${y} z\";\n}";
+        let source: SourceFile = text.parse().unwrap();
+        let inserts = [
+            "", "?", "z", " ", ";", "}", "]", "${", "$", "\"", "''", "{ }",
+        ];
+
+        for start in 0..text.len() {
+            for end in start..(start + 4).min(text.len()) {
+                for insert in &inserts {
+                    let (new, edit, new_len) = apply(text, start as u32, end as u32, insert);
+                    let mut reparsed = source.clone();
+                    if !reparse(&mut reparsed, &new, edit, new_len) {
+                        continue;
+                    }
+                    let expected: SourceFile = match new.parse() {
+                        Ok(expected) => expected,
+                        Err(_) => panic!("reparsed invalid edit {:?} of {:?}", new, text),
+                    };
+                    assert_eq!(reparsed, expected, "{:?}", new);
+                    assert_eq!(spans(&reparsed), spans(&expected), "{:?}", new);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_unbalanced_edits() {
+        let text = "{ a = [ 1 ]; b = 2; }";
+        let mut source: SourceFile = text.parse().unwrap();
+        let original = spans(&source);
+
+        let offset = text.find('1').unwrap() as u32;
+        let (new, edit, new_len) = apply(text, offset, offset, "] [");
+        assert!(!reparse(&mut source, &new, edit, new_len));
+        assert_eq!(spans(&source), original);
+
+        let (new, edit, new_len) = apply(text, 0, 1, "[");
+        assert!(!reparse(&mut source, &new, edit, new_len));
+    }
+
+    #[test]
+    fn falls_back_to_full_parse() {
+        let text = "{ a = [ 1 ]; b = 2; }";
+        let source: SourceFile = text.parse().unwrap();
+
+        let offset = text.find('1').unwrap() as u32;
+        let (new, edit, new_len) = apply(text, offset, offset + 1, "1 2");
+        let partial = parse_source_file_incremental(source.clone(), &new, edit, new_len).unwrap();
+        assert_eq!(partial.verify().unwrap(), new.parse().unwrap());
+
+        let (new, edit, new_len) = apply(text, 0, 1, "[");
+        let partial = parse_source_file_incremental(source, &new, edit, new_len).unwrap();
+        assert!(partial.has_errors());
+    }
+}
@@ -1,15 +1,16 @@
-use std::i64;
 use std::path::PathBuf;
 use std::str::FromStr;
 
 use codespan::Span;
 use nom::bytes::complete::take;
+use nom::{InputIter, Slice};
 use url::Url;
 
+use super::partial::Partial;
 use super::IResult;
 use crate::ast::tokens::{Comment, Ident, Literal};
-use crate::error::{Errors, ExpectedFoundError};
-use crate::lexer::{StringFragment, Token, Tokens};
+use crate::error::{Error, Errors, ExpectedFoundError};
+use crate::lexer::{StringFragment, StringKind, Token, Tokens};
 use crate::ToSpan;
 
 macro_rules! define_tokens {
@@ -26,8 +27,8 @@ macro_rules! define_tokens {
     };
 
     (@token $function:ident { returns: $ret:ty, parse: $variant:pat => $value:expr, expects: $expects:expr, }) => {
-        pub fn $function(input: Tokens) -> IResult<$ret> {
-            let (remaining, tokens) = take(1usize)(input)?;
+        pub fn $function(input: Tokens<'_>) -> IResult<'_, $ret> {
+            let (remaining, tokens) = take(1usize)(skip_comments(input))?;
             match tokens.current() {
                 $variant => Ok((remaining, $value)),
                 token => {
@@ -41,17 +42,36 @@ macro_rules! define_tokens {
     };
 }
 
+/// Skips the comments at the start of `input`.
+///
+/// Comments may appear between any two tokens, so every token parser but [`comment`] skips them.
+/// Only the parsers of constructs which keep their comments in the AST consume them explicitly.
+pub fn skip_comments(input: Tokens<'_>) -> Tokens<'_> {
+    let comments = input.iter_elements().take_while(|t| t.is_comment()).count();
+    input.slice(comments..)
+}
+
+pub fn comment(input: Tokens<'_>) -> IResult<'_, Comment> {
+    let (remaining, tokens) = take(1usize)(input)?;
+    match tokens.current() {
+        Token::Comment(ref text, ref kind, ref span) => {
+            Ok((remaining, Comment::new(text.clone(), *kind, *span)))
+        }
+        token => {
+            let mut errors = Errors::new();
+            let found = token.description();
+            errors.push(ExpectedFoundError::new("comment", found, token.to_span()));
+            Err(nom::Err::Error(errors))
+        }
+    }
+}
+
 define_tokens! {
     eof { Eof, "<eof>" }
 
-    comment {
-        returns: Comment,
-        parse: Token::Comment(ref text, _, ref span) => Comment::from((text.clone(), *span)),
-        expects: "comment",
-    }
     identifier {
         returns: Ident,
-        parse: Token::Identifier(ref ident, ref span) => Ident::from((ident.clone(), *span)),
+        parse: Token::Identifier(ref ident, ref span) => Ident::from((ident, *span)),
         expects: "identifier",
     }
     null {
@@ -65,18 +85,18 @@ define_tokens! {
         expects: "boolean",
     }
     float {
-        returns: Literal,
-        parse: Token::Float(ref value, ref span) => Literal::from((lexical_core::parse::<f64>(value.as_bytes()).unwrap(), *span)),
+        returns: Partial<Literal>,
+        parse: Token::Float(ref value, ref span) => float_literal(value, *span),
         expects: "floating-point number",
     }
 
     integer {
-        returns: Literal,
-        parse: Token::Integer(ref value, ref span) => Literal::from((i64::from_str(&value).unwrap(), *span)),
+        returns: Partial<Literal>,
+        parse: Token::Integer(ref value, ref span) => integer_literal(value, *span),
         expects: "integer",
     }
     interpolation {
-        returns: (&[Token], Span),
+        returns: (&[Token<'_>], Span),
         parse: Token::Interpolation(ref tokens, ref span) => (tokens.as_slice(), *span),
         expects: "interpolation",
     }
@@ -87,17 +107,17 @@ define_tokens! {
     }
     path_template {
         returns: Literal,
-        parse: Token::PathTemplate(ref value, ref span) => Literal::PathTemplate(value.to_string().into(), *span),
+        parse: Token::PathTemplate(ref value, ref span) => Literal::PathTemplate(Box::new(value.to_string().into()), *span),
         expects: "path template",
     }
     string {
-        returns: (&[StringFragment], Span),
-        parse: Token::String(ref frags, ref span) => (frags.as_slice(), *span),
+        returns: (&[StringFragment<'_>], StringKind, Span),
+        parse: Token::String(ref frags, kind, ref span, _) => (frags.as_slice(), *kind, *span),
         expects: "string",
     }
     uri {
         returns: Literal,
-        parse: Token::Uri(ref value, ref span) => Literal::from((Url::from_str(&value).unwrap(), *span)),
+        parse: Token::Uri(ref value, ref span) => Literal::from((Url::from_str(value).unwrap(), *span)),
         expects: "URI",
     }
 
@@ -125,25 +145,99 @@ define_tokens! {
     keyword_in { In, "keyword `in`" }
     keyword_inherit { Inherit, "keyword `inherit`" }
     keyword_let { Let, "keyword `let`" }
-    keyword_or { Or, "keyword `or`" }
     keyword_rec { Rec, "keyword `rec`" }
     keyword_then { Then, "keyword `then`" }
     keyword_with { With, "keyword `with`" }
 
-    at { At, "at symbol (`@`)" }
     colon { Colon, "colon" }
     comma { Comma, "comma" }
     ellipsis { Ellipsis, "ellipsis (`...`)" }
     dot { Dot, "dot separator" }
     eq { Eq, "equals sign" }
-    interpolate { Interpolate, "interpolation sign (`${}`)" }
     brace_left { LBrace, "left brace" }
     brace_right { RBrace, "right brace" }
     bracket_left { LBracket, "left bracket" }
     bracket_right { RBracket, "right bracket" }
     paren_left { LParen, "left parentheses" }
     paren_right { RParen, "right parentheses" }
-    quote_double { QuoteDouble, "double quote" }
-    quote_single { QuoteSingle, "multiline string open (`''`)" }
     semi { Semi, "semicolon" }
 }
+
+/// Returns the value of the integer literal `text`, or `None` if it does not fit in 64 bits.
+pub fn integer_value(text: &str) -> Option<i64> {
+    i64::from_str(text).ok()
+}
+
+/// Returns the value of the float literal `text`, or `None` if it is too large or too small to be
+/// represented, which Nix rejects rather than rounding to infinity or zero.
+pub fn float_value(text: &str) -> Option<f64> {
+    let value = lexical_core::parse::<f64>(text.as_bytes()).ok()?;
+    let mantissa = text.split(['e', 'E']).next().unwrap_or(text);
+    let underflow = value == 0.0 && mantissa.chars().any(|c| c.is_ascii_digit() && c != '0');
+    Some(value).filter(|value| value.is_finite() && !underflow)
+}
+
+/// Parses an integer literal, saturating it with an error if it overflows.
+fn integer_literal(text: &str, span: Span) -> Partial<Literal> {
+    match integer_value(text) {
+        Some(value) => Partial::from(Literal::Integer(value, span)),
+        None => {
+            let mut errors = Errors::new();
+            let message = format!(
+                "integer `{}` is out of range, the largest integer is {}",
+                text,
+                i64::MAX
+            );
+            errors.push(Error::Message(span, message));
+            Partial::with_errors(Some(Literal::Integer(i64::MAX, span)), errors)
+        }
+    }
+}
+
+/// Parses a float literal, replacing it with an error if it cannot be represented.
+fn float_literal(text: &str, span: Span) -> Partial<Literal> {
+    match float_value(text) {
+        Some(value) => Partial::from(Literal::Float(value, span)),
+        None => {
+            let mut errors = Errors::new();
+            let message = format!("float `{}` is out of range", text);
+            errors.push(Error::Message(span, message));
+            Partial::with_errors(Some(Literal::Float(0.0, span)), errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::SourceFile;
+
+    #[test]
+    fn parses_numeric_values() {
+        assert_eq!(integer_value("00042"), Some(42));
+        assert_eq!(integer_value("9223372036854775807"), Some(i64::MAX));
+        assert_eq!(integer_value("9223372036854775808"), None);
+        assert_eq!(float_value(".5e1"), Some(5.0));
+        assert_eq!(float_value("0.0e-999"), Some(0.0));
+        assert_eq!(float_value("1.0e999"), None);
+        assert_eq!(float_value("1.0e-999"), None);
+    }
+
+    #[test]
+    fn reports_numbers_out_of_range() {
+        let result = "[ 1 99999999999999999999 2.5e400 ]\n".parse::<SourceFile>();
+        let errors: Vec<_> = result
+            .unwrap_err()
+            .into_iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "integer `99999999999999999999` is out of range, the largest integer is \
+                 9223372036854775807",
+                "float `2.5e400` is out of range",
+            ]
+        );
+    }
+}
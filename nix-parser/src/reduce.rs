@@ -0,0 +1,173 @@
+//! Minimization of inputs which trigger a bug, for filing reproducers.
+//!
+//! [`reduce`] applies delta debugging (the `ddmin` algorithm) to a source text: it repeatedly
+//! tries removing chunks of it, keeping every removal after which the text is still
+//! "interesting", e.g. still makes the parser panic. Lines are removed first, since that shrinks
+//! large files quickly, and then single tokens along with the whitespace after them. The result
+//! is 1-minimal at the last granularity: removing any single token of it loses the bug.
+
+use crate::lexer::{Lexer, Token};
+use crate::ToSpan;
+
+/// The units a text is split into for removal.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Granularity {
+    Lines,
+    /// Tokens along with the whitespace following them. Texts which cannot be lexed are split
+    /// into characters instead.
+    Tokens,
+}
+
+impl Granularity {
+    fn split(self, text: &str) -> Vec<&str> {
+        match self {
+            Granularity::Lines => text.split_inclusive('\n').collect(),
+            Granularity::Tokens => {
+                let mut boundaries: Vec<usize> = match Lexer::new(text) {
+                    Ok(lexer) => lexer
+                        .tokens()
+                        .iter()
+                        .filter(|token| !matches!(token, Token::Eof(_)))
+                        .map(|token| token.to_span().start().to_usize())
+                        .collect(),
+                    Err(_) => text.char_indices().map(|(i, _)| i).collect(),
+                };
+                // Leading whitespace goes with the first unit.
+                boundaries.insert(0, 0);
+                boundaries.push(text.len());
+                boundaries.dedup();
+                boundaries
+                    .windows(2)
+                    .map(|pair| &text[pair[0]..pair[1]])
+                    .filter(|unit| !unit.is_empty())
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Statistics about a reduction.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ReduceStats {
+    /// The number of times the predicate was run.
+    pub tests: usize,
+    pub original_len: usize,
+    pub reduced_len: usize,
+}
+
+/// Returns a minimized version of `text` for which `is_interesting` still holds, along with
+/// statistics about the reduction.
+///
+/// `is_interesting` should hold for `text` itself; if it does not, `text` is returned unchanged.
+pub fn reduce<F>(text: &str, mut is_interesting: F) -> (String, ReduceStats)
+where
+    F: FnMut(&str) -> bool,
+{
+    let mut stats = ReduceStats {
+        original_len: text.len(),
+        ..ReduceStats::default()
+    };
+    let mut test = |candidate: &str| {
+        stats.tests += 1;
+        is_interesting(candidate)
+    };
+
+    let mut current = text.to_owned();
+    if test(&current) {
+        for granularity in [Granularity::Lines, Granularity::Tokens] {
+            current = ddmin(&current, granularity, &mut test);
+        }
+    }
+
+    stats.reduced_len = current.len();
+    (current, stats)
+}
+
+/// Removes units of `text` at the given granularity for as long as `test` holds.
+pub fn ddmin<F>(text: &str, granularity: Granularity, mut test: F) -> String
+where
+    F: FnMut(&str) -> bool,
+{
+    let mut units = granularity.split(text);
+    let mut chunks = 2;
+
+    while units.len() >= 2 {
+        let size = units.len().div_ceil(chunks);
+        let ranges: Vec<_> = (0..units.len())
+            .step_by(size)
+            .map(|start| start..(start + size).min(units.len()))
+            .collect();
+
+        // Try keeping a single chunk first, then removing a single chunk.
+        let subset = ranges
+            .iter()
+            .map(|range| units[range.clone()].to_vec())
+            .find(|candidate| test(&candidate.concat()));
+        if let Some(subset) = subset {
+            units = subset;
+            chunks = 2;
+            continue;
+        }
+
+        let complement = ranges
+            .iter()
+            .filter(|_| ranges.len() > 2)
+            .map(|range| {
+                let mut candidate = units[..range.start].to_vec();
+                candidate.extend_from_slice(&units[range.end..]);
+                candidate
+            })
+            .find(|candidate| test(&candidate.concat()));
+        if let Some(complement) = complement {
+            units = complement;
+            chunks = (chunks - 1).max(2);
+            continue;
+        }
+
+        if chunks >= units.len() {
+            break;
+        }
+        chunks = (chunks * 2).min(units.len());
+    }
+
+    units.concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_source_file_partial;
+
+    #[test]
+    fn reduces_to_minimal_reproducer() {
+        let source = r#"{
+  a = 1;
+  b = [ 1 2 3 ];
+  c = { d = "x"; e = if true then 1 else 2; };
+  f = g: g.h;
+}"#;
+        // Pretend that the parser mishandles conditionals.
+        let mut is_interesting = |text: &str| {
+            let parse = parse_source_file_partial(text);
+            text.contains("if") && parse.is_ok_and(|partial| !partial.has_errors())
+        };
+        let (reduced, stats) = reduce(source, &mut is_interesting);
+        assert!(is_interesting(&reduced));
+        assert!(!reduced.contains("f = g"));
+        assert_eq!(stats.original_len, source.len());
+        assert_eq!(stats.reduced_len, reduced.len());
+
+        // Removing any single token loses the bug.
+        let units = Granularity::Tokens.split(&reduced);
+        for i in 0..units.len() {
+            let mut candidate = units.clone();
+            candidate.remove(i);
+            assert!(!is_interesting(&candidate.concat()));
+        }
+
+        let (reduced, _) = reduce(source, |text| text.contains("2 3"));
+        assert_eq!(reduced, "2 3 ");
+        let (unchanged, _) = reduce(source, |text| text.len() > 1000);
+        assert_eq!(unchanged, source);
+    }
+}
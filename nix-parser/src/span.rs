@@ -0,0 +1,80 @@
+//! Representations of source spans other than `codespan::Span`.
+//!
+//! The syntax tree stores `codespan::Span`s, while tools built on other libraries have range types
+//! of their own, such as `text_size::TextRange` for those based on `rowan`. [`HasSpan::span_as`]
+//! returns the span of a node in any representation implementing [`SpanRepr`], and constructors
+//! taking a [`ToSpan`] accept them too, so such tools need no conversion layer of their own.
+//!
+//! [`HasSpan::span_as`]: crate::HasSpan::span_as
+//! [`ToSpan`]: crate::ToSpan
+
+use codespan::Span;
+
+/// A representation of a range of bytes in the source text.
+pub trait SpanRepr: Copy {
+    fn from_span(span: Span) -> Self;
+
+    fn into_span(self) -> Span;
+}
+
+impl SpanRepr for Span {
+    fn from_span(span: Span) -> Self {
+        span
+    }
+
+    fn into_span(self) -> Span {
+        self
+    }
+}
+
+/// The start and end byte offsets of the span.
+impl SpanRepr for (u32, u32) {
+    fn from_span(span: Span) -> Self {
+        (span.start().to_usize() as u32, span.end().to_usize() as u32)
+    }
+
+    fn into_span(self) -> Span {
+        Span::new(self.0, self.1)
+    }
+}
+
+#[cfg(feature = "text-size")]
+impl SpanRepr for text_size::TextRange {
+    fn from_span(span: Span) -> Self {
+        let (start, end) = SpanRepr::from_span(span);
+        text_size::TextRange::new(
+            text_size::TextSize::from(start),
+            text_size::TextSize::from(end),
+        )
+    }
+
+    fn into_span(self) -> Span {
+        Span::new(u32::from(self.start()), u32::from(self.end()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::tokens::Ident;
+    use crate::ast::SourceFile;
+    use crate::HasSpan;
+
+    #[test]
+    fn converts_between_representations() {
+        let file: SourceFile = "f x".parse().unwrap();
+        assert_eq!(file.expr().span_as::<(u32, u32)>(), (0, 3));
+        assert_eq!((4, 5).into_span(), Span::new(4, 5));
+
+        let ident = Ident::from(("a", (2u32, 3u32)));
+        assert_eq!(ident.span(), Span::new(2, 3));
+
+        #[cfg(feature = "text-size")]
+        {
+            use text_size::TextRange;
+            let range: TextRange = file.expr().span_as();
+            assert_eq!(range, TextRange::new(0.into(), 3.into()));
+            assert_eq!(range.into_span(), file.expr().span());
+        }
+    }
+}
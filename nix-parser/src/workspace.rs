@@ -0,0 +1,221 @@
+//! Parsing of many files at once, such as a whole repository.
+//!
+//! Files are parsed on as many threads as there are cores, each taking the next file left once it
+//! is done with its current one. Every thread interns identifiers through its own cache of one
+//! shared [`Interner`], so that the same name is still allocated once across all files.
+
+use std::mem;
+use std::num::NonZeroUsize;
+use std::panic;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::ast::SourceFile;
+use crate::error::{Error, Errors};
+use crate::intern::Interner;
+use crate::parser::{parse_source_file_partial, Partial};
+
+/// The result of parsing a single file of a workspace.
+#[derive(Clone, Debug)]
+pub struct FileParse {
+    pub path: PathBuf,
+    pub result: Result<Partial<SourceFile>, Errors>,
+}
+
+impl FileParse {
+    /// Returns the syntax tree of this file, which may be incomplete if it has errors.
+    pub fn source_file(&self) -> Option<&SourceFile> {
+        self.result.as_ref().ok().and_then(Partial::value)
+    }
+
+    /// Returns the errors found in this file.
+    pub fn errors(&self) -> Errors {
+        match self.result {
+            Ok(ref partial) => partial.errors().unwrap_or_default(),
+            Err(ref errors) => errors.clone(),
+        }
+    }
+}
+
+/// Totals over all files of a workspace.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ParseStats {
+    pub files: usize,
+    pub bytes: usize,
+    /// The number of files with at least one error.
+    pub files_with_errors: usize,
+    /// The number of files for which no syntax tree could be built at all.
+    pub files_without_tree: usize,
+    pub errors: usize,
+    pub identifiers: usize,
+    pub unique_identifiers: usize,
+}
+
+/// The syntax trees of a set of files, whose identifiers share one [`Interner`].
+#[derive(Clone, Debug)]
+pub struct WorkspaceParse {
+    pub files: Vec<FileParse>,
+    pub interner: Interner,
+    pub stats: ParseStats,
+}
+
+impl WorkspaceParse {
+    /// Iterates over every error in the workspace along with the path of its file.
+    pub fn errors(&self) -> impl Iterator<Item = (&PathBuf, Error)> + '_ {
+        self.files
+            .iter()
+            .flat_map(|file| file.errors().into_iter().map(move |err| (&file.path, err)))
+    }
+}
+
+/// Parses every file given as a path and its text, interning their identifiers together.
+pub fn parse_files(files: &[(PathBuf, String)]) -> WorkspaceParse {
+    parse_files_with_progress(files, |_| {})
+}
+
+/// Parses files like [`parse_files`], calling `progress` with the number of files parsed so far
+/// each time one is done.
+///
+/// `progress` is called from the thread which parsed the file, so calls may overlap.
+pub fn parse_files_with_progress<P>(files: &[(PathBuf, String)], progress: P) -> WorkspaceParse
+where
+    P: Fn(usize) + Sync,
+{
+    let shared = Arc::new(Mutex::new(Interner::new()));
+    let (next, done) = (AtomicUsize::new(0), AtomicUsize::new(0));
+    let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    let threads = threads.min(files.len()).max(1);
+
+    let mut parsed = Vec::with_capacity(files.len());
+    let mut lookups = 0;
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut interner = Interner::caching(shared.clone());
+                    let mut parsed = Vec::new();
+                    interner.scope(|| loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let (path, text) = match files.get(index) {
+                            Some(file) => file,
+                            None => break,
+                        };
+                        let file = FileParse {
+                            path: path.clone(),
+                            result: parse_source_file_partial(text),
+                        };
+                        parsed.push((index, file));
+                        progress(done.fetch_add(1, Ordering::Relaxed) + 1);
+                    });
+                    (parsed, interner.lookups())
+                })
+            })
+            .collect();
+
+        for worker in workers {
+            let (files, count) = worker.join().unwrap_or_else(|e| panic::resume_unwind(e));
+            parsed.extend(files);
+            lookups += count;
+        }
+    });
+
+    parsed.sort_unstable_by_key(|&(index, _)| index);
+    let parsed: Vec<_> = parsed.into_iter().map(|(_, file)| file).collect();
+    let mut interner = mem::take(&mut *shared.lock().unwrap_or_else(|e| e.into_inner()));
+    interner.count_lookups(lookups);
+
+    let mut stats = ParseStats::default();
+    for (file, (_, text)) in parsed.iter().zip(files) {
+        let errors = file.errors().iter().count();
+        stats.files += 1;
+        stats.bytes += text.len();
+        stats.errors += errors;
+        stats.files_with_errors += (errors > 0) as usize;
+        stats.files_without_tree += file.source_file().is_none() as usize;
+    }
+    stats.identifiers = interner.lookups();
+    stats.unique_identifiers = interner.len();
+
+    WorkspaceParse {
+        files: parsed,
+        interner,
+        stats,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::ast::{Expr, ExprFnDecl};
+
+    #[test]
+    fn shares_identifiers_across_files() {
+        let files = vec![
+            (PathBuf::from("a.nix"), "{ pkgs }: pkgs.hello".to_owned()),
+            (PathBuf::from("b.nix"), "{ pkgs }: pkgs.curl".to_owned()),
+            (PathBuf::from("c.nix"), "{ pkgs = ; }".to_owned()),
+        ];
+        let workspace = parse_files(&files);
+
+        let formal = |i: usize| match *workspace.files[i].source_file().unwrap().expr() {
+            Expr::FnDecl(ref decl) => match **decl {
+                ExprFnDecl::Formals(ref formals) => formals.formals()[0].name().as_str().as_ptr(),
+                _ => panic!("expected formals"),
+            },
+            ref other => panic!("expected a function, found {}", other),
+        };
+        assert_eq!(formal(0), formal(1));
+
+        let stats = workspace.stats;
+        assert_eq!((stats.files, stats.files_with_errors), (3, 1));
+        assert_eq!(
+            stats.bytes,
+            files.iter().map(|(_, t)| t.len()).sum::<usize>()
+        );
+        assert_eq!(stats.unique_identifiers, 3);
+        assert!(stats.identifiers > stats.unique_identifiers);
+        assert_eq!(workspace.errors().next().unwrap().0, Path::new("c.nix"));
+    }
+
+    #[test]
+    fn parses_files_in_parallel() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<FileParse>();
+        assert_send_sync::<WorkspaceParse>();
+
+        let files: Vec<_> = (0..64)
+            .map(|i| {
+                (
+                    PathBuf::from(format!("{}.nix", i)),
+                    format!("{{ a{} = lib; }}", i),
+                )
+            })
+            .collect();
+        let calls = AtomicUsize::new(0);
+        let last = AtomicUsize::new(0);
+        let workspace = parse_files_with_progress(&files, |done| {
+            calls.fetch_add(1, Ordering::Relaxed);
+            last.fetch_max(done, Ordering::Relaxed);
+        });
+
+        assert_eq!(calls.into_inner(), files.len());
+        assert_eq!(last.into_inner(), files.len());
+        let paths: Vec<_> = workspace.files.iter().map(|f| f.path.clone()).collect();
+        assert_eq!(
+            paths,
+            files.iter().map(|(p, _)| p.clone()).collect::<Vec<_>>()
+        );
+        let mut serial = Interner::new();
+        serial.scope(|| {
+            files
+                .iter()
+                .for_each(|(_, text)| drop(parse_source_file_partial(text)))
+        });
+        assert_eq!(workspace.stats.identifiers, serial.lookups());
+        assert_eq!(workspace.stats.unique_identifiers, 65);
+    }
+}
@@ -0,0 +1,293 @@
+//! Checks of the attribute names defined by bindings.
+//!
+//! Attribute paths may contain dynamic segments, `"${name}" = ...`, whose names are only known at
+//! evaluation time. Such bindings cannot collide with others as far as static analysis can tell,
+//! so they are excluded from the duplicate check. A dynamic segment whose name is a constant,
+//! e.g. `${"name"}`, is treated as the static name it computes and reported, since it could be
+//! written without interpolation.
+
+use std::collections::HashMap;
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use nix_parser::ast::tokens::{Ident, StringKind};
+use nix_parser::ast::visit::descendants;
+use nix_parser::ast::{AttrPath, AttrSegment, Bind, Expr, ExprString, SourceFile, StringFragment};
+use nix_parser::HasSpan;
+
+use crate::resolve::did_you_mean_note;
+
+const KEYWORDS: &[&str] = &[
+    "assert", "else", "if", "in", "inherit", "let", "or", "rec", "then", "with",
+];
+
+/// The name of an attribute path segment, as far as it can be determined statically.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Name {
+    /// A name written literally, as an identifier or a string.
+    Static(String),
+    /// An interpolation whose name is nonetheless a constant.
+    Constant(String),
+    /// An interpolation computed at evaluation time.
+    Dynamic,
+}
+
+impl Name {
+    pub fn of(segment: &AttrSegment) -> Self {
+        match *segment {
+            AttrSegment::Ident(ref ident) => Name::Static(ident.to_string()),
+            AttrSegment::String(ref string) => match constant_string(string) {
+                Some((text, false)) => Name::Static(text),
+                Some((text, true)) => Name::Constant(text),
+                None => Name::Dynamic,
+            },
+            AttrSegment::Interpolation(ref interp) => match constant(interp.inner()) {
+                Some(text) => Name::Constant(text),
+                None => Name::Dynamic,
+            },
+        }
+    }
+
+    fn text(&self) -> Option<&str> {
+        match *self {
+            Name::Static(ref text) | Name::Constant(ref text) => Some(text),
+            Name::Dynamic => None,
+        }
+    }
+}
+
+/// Returns the name to show for a binding of `segment` in symbol lists, marking computed names.
+pub fn symbol_name(segment: &AttrSegment) -> String {
+    match Name::of(segment).text() {
+        Some(text) => text.to_owned(),
+        None => format!("{} (computed)", segment),
+    }
+}
+
+/// Returns the value of a constant string expression.
+pub fn constant(expr: &Expr) -> Option<String> {
+    match *expr {
+        Expr::String(ref string) => constant_string(string).map(|(text, _)| text),
+        Expr::Paren(ref paren) => constant(paren.expr()),
+        _ => None,
+    }
+}
+
+/// Returns the value of a string made of constant parts, and whether it uses interpolation.
+fn constant_string(string: &ExprString) -> Option<(String, bool)> {
+    let mut text = String::new();
+    let mut interpolated = false;
+    for fragment in string.fragments() {
+        match *fragment {
+            StringFragment::Literal(ref literal, _) => text.push_str(literal),
+            StringFragment::Interpolation(ref interp) => {
+                text.push_str(&constant(interp.inner())?);
+                interpolated = true;
+            }
+        }
+    }
+    Some((text, interpolated))
+}
+
+/// Returns how an attribute named `name` is written without interpolation.
+fn plain_attr(name: &str) -> String {
+    let mut chars = name.chars();
+    let is_ident = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || "_'-".contains(c))
+        && !KEYWORDS.contains(&name);
+
+    if is_ident {
+        name.to_owned()
+    } else {
+        Expr::String(ExprString::new(
+            vec![StringFragment::Literal(name.to_owned(), Span::initial())],
+            StringKind::Normal,
+            Span::initial(),
+        ))
+        .to_string()
+    }
+}
+
+/// A statically known attribute path defined in a set.
+struct Definition {
+    span: Span,
+    /// Whether the value is an attribute set literal, which later bindings may extend.
+    mergeable: bool,
+}
+
+/// Checks the attribute names defined in every set and `let` block of `file`.
+pub fn check(id: FileId, file: &SourceFile) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    for expr in descendants(file.expr()) {
+        let binds = match binds(expr) {
+            Some(binds) => binds,
+            None => continue,
+        };
+
+        let mut defined: HashMap<Vec<String>, Definition> = HashMap::new();
+        for bind in binds {
+            let paths: Vec<(Vec<String>, Definition)> = match *bind {
+                Bind::Simple(ref simple) => {
+                    diagnostics.extend(check_constant_segments(id, simple.attr()));
+                    let path = simple.attr().segments().iter().map(Name::of);
+                    match path.map(|name| name.text().map(str::to_owned)).collect() {
+                        Some(path) => {
+                            let mergeable = matches!(*simple.expr(), Expr::Set(_));
+                            let span = simple.attr().span();
+                            vec![(path, Definition { span, mergeable })]
+                        }
+                        None => continue,
+                    }
+                }
+                Bind::Inherit(ref inherit) => inherited(inherit.names()),
+                Bind::InheritExpr(ref inherit) => inherited(inherit.names()),
+            };
+
+            for (path, definition) in paths {
+                let conflict = (1..=path.len()).find_map(|len| {
+                    let previous = defined.get(&path[..len])?;
+                    let merges = previous.mergeable && (len < path.len() || definition.mergeable);
+                    Some(previous).filter(|_| !merges)
+                });
+                let conflict = conflict.or_else(|| {
+                    defined
+                        .iter()
+                        .filter(|(other, _)| other.len() > path.len() && other.starts_with(&path))
+                        .map(|(_, previous)| previous)
+                        .find(|_| !definition.mergeable)
+                });
+
+                match conflict {
+                    Some(previous) => {
+                        let label = Label::new(id, definition.span, "defined again here");
+                        let message = format!("attribute `{}` is already defined", path.join("."));
+                        let first = Label::new(id, previous.span, "first defined here");
+                        let diagnostic =
+                            Diagnostic::new_error(message, label).with_code("duplicate-attribute");
+                        diagnostics.push(diagnostic.with_secondary_labels(vec![first]));
+                    }
+                    None => {
+                        defined.entry(path).or_insert(definition);
+                    }
+                }
+            }
+        }
+    }
+
+    diagnostics
+}
+
+/// Returns the bindings of a set or `let` block.
+fn binds(expr: &Expr) -> Option<&[Bind]> {
+    match *expr {
+        Expr::Set(ref set) => Some(set.binds()),
+        Expr::Rec(ref rec) => Some(rec.binds()),
+        Expr::Let(ref let_) => Some(let_.binds()),
+        Expr::LetIn(ref let_in) => Some(let_in.binds()),
+        _ => None,
+    }
+}
+
+fn inherited(names: &[Ident]) -> Vec<(Vec<String>, Definition)> {
+    names
+        .iter()
+        .map(|name| {
+            let definition = Definition {
+                span: name.span(),
+                mergeable: false,
+            };
+            (vec![name.to_string()], definition)
+        })
+        .collect()
+}
+
+/// Returns how the dynamic segment at `span` of a binding is written without interpolation, if
+/// its name is a constant.
+pub fn plain_segment(file: &SourceFile, span: Span) -> Option<String> {
+    let segment = descendants(file.expr())
+        .filter_map(binds)
+        .flatten()
+        .filter_map(|bind| match *bind {
+            Bind::Simple(ref simple) => Some(simple.attr().segments()),
+            _ => None,
+        })
+        .flatten()
+        .find(|segment| segment.span() == span)?;
+    match Name::of(segment) {
+        Name::Constant(name) => Some(plain_attr(&name)),
+        _ => None,
+    }
+}
+
+/// Warns about the dynamic segments of `attr` which compute a constant name.
+fn check_constant_segments(id: FileId, attr: &AttrPath) -> Vec<Diagnostic> {
+    attr.segments()
+        .iter()
+        .filter_map(|segment| match Name::of(segment) {
+            Name::Constant(name) => {
+                let label = Label::new(id, segment.span(), "this name does not depend on anything");
+                let message = format!("dynamic attribute `{}` is constant", name);
+                let diagnostic =
+                    Diagnostic::new_warning(message, label).with_code("constant-dynamic-attribute");
+                Some(diagnostic.with_notes(vec![did_you_mean_note(&plain_attr(&name))]))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(source: &str) -> Vec<String> {
+        let mut files = codespan::Files::new();
+        let id = files.add("test.nix", source);
+        let file = source.parse().unwrap();
+        check(id, &file).into_iter().map(|d| d.message).collect()
+    }
+
+    #[test]
+    fn checks_static_names_only() {
+        assert_eq!(
+            messages("{ a.b = 1; a.c = 2; x = { y = 1; }; x.z = 2; }"),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            messages("{ a = 1; a = 2; b = 1; b.c = 2; inherit d; d.e = 3; }"),
+            [
+                "attribute `a` is already defined",
+                "attribute `b.c` is already defined",
+                "attribute `d.e` is already defined",
+            ]
+        );
+        assert_eq!(
+            messages("{ \"${x}\" = 1; \"${x}\" = 2; ${y}.z = 3; }"),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            messages("{ a = 1; \"${\"a\"}\" = 2; ${\"b c\"} = 3; }"),
+            [
+                "dynamic attribute `a` is constant",
+                "attribute `a` is already defined",
+                "dynamic attribute `b c` is constant",
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_constant_segments_plainly() {
+        let source = "{ ${\"a\"} = 1; ${\"b c\"}.d = 2; ${x} = 3; }";
+        let file = source.parse().unwrap();
+        let id = codespan::Files::new().add("test.nix", source);
+        let plain: Vec<_> = check(id, &file)
+            .iter()
+            .map(|d| plain_segment(&file, d.primary_label.span))
+            .collect();
+        assert_eq!(plain, [Some("a".to_owned()), Some("\"b c\"".to_owned())]);
+        assert_eq!(plain_segment(&file, Span::new(0, 1)), None);
+    }
+}
@@ -1,38 +1,831 @@
 //! HACK: All of this.
 
-use std::collections::HashMap;
-use std::sync::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use codespan::{FileId, Files};
-use codespan_lsp::{make_lsp_diagnostic, range_to_byte_span};
+use codespan::{FileId, Files, Span};
+use codespan_lsp::byte_span_to_range;
+use codespan_reporting::diagnostic::{Diagnostic as CodespanDiagnostic, Label};
 use futures::future::{self, FutureResult};
+use futures::sync::mpsc::UnboundedSender;
 use jsonrpc_core::{BoxFuture, Error, Result};
 use log::info;
-use nix_parser::ast::SourceFile;
-use serde_json::Value;
+use nix_parser::ast::paths::SearchPath;
+use nix_parser::ast::{Bind, Expr, ExprFnDecl, SourceFile};
+use nix_parser::fmt;
+use nix_parser::parser::{expected_tokens, Expected};
+use nix_parser::HasSpan;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tower_lsp::lsp_types::request::GotoDefinitionResponse;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{LanguageServer, Printer};
 
+use crate::attrs;
+use crate::binding;
+use crate::breadcrumb;
+use crate::builtins;
+use crate::bump;
+use crate::call_package::{self, Formals};
+use crate::compat::Version;
+use crate::constant;
+use crate::db::{Database, ParseResult};
+use crate::disk;
+use crate::eval::{self, EvalError, ValueCache};
+use crate::explain;
+use crate::fixes::{self, Problem};
+use crate::flake::{self, Flake, LockFile};
+use crate::glossary;
+use crate::hashes;
+use crate::highlight::{self, Kind};
+use crate::interpolate::{self, Action};
+use crate::metrics::METRICS;
+use crate::naming;
+use crate::options;
+use crate::organize::{self, Placement};
+use crate::outline::{self, Symbol};
+use crate::overrides::{self, Kind as OverrideKind};
+use crate::preview;
+use crate::progress::{Client, Progress};
+use crate::rename::{self, Reason};
+use crate::resolve::{self, NameKind, Target};
+use crate::role;
+use crate::session::RECORDER;
+use crate::severity::Severities;
+use crate::shell;
+use crate::snapshot::{Document, Snapshot, Snapshots};
+use crate::sync;
+use crate::systems;
+use crate::text::Text;
+use crate::todo;
+use crate::vfs::{self, FileLoader, PathResolver, RealFs};
+use crate::workspace::{self, Workspace};
+
+/// Keywords which start an expression, offered wherever an expression is expected.
+const EXPR_KEYWORDS: &[&str] = &["assert", "if", "let", "rec", "with"];
+
+/// How long completing `overrideAttrs` may spend evaluating the package being overridden.
+const OVERRIDE_EVAL_LIMIT: Duration = Duration::from_secs(2);
+
+/// How long previewing a value may spend evaluating it.
+const PREVIEW_EVAL_LIMIT: Duration = Duration::from_secs(10);
+
+/// How long `shellcheck` may spend on the scripts of a document.
+const SHELLCHECK_LIMIT: Duration = Duration::from_secs(10);
+
+/// How long the disk checks wait for edits to settle before running.
+const DISK_CHECK_DELAY: Duration = Duration::from_millis(300);
+
+/// How many closed documents keep their text and analysis, to be reused if they are reopened.
+const MAX_CLOSED: usize = 64;
+
+/// A check run in the background, since it may be slow.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum Check {
+    /// The checks of [`disk`], which read the files a document refers to.
+    Disk,
+    /// `shellcheck`, run over the scripts embedded in a document.
+    Shellcheck,
+}
+
+impl Check {
+    fn name(self) -> &'static str {
+        match self {
+            Check::Disk => "disk",
+            Check::Shellcheck => "shellcheck",
+        }
+    }
+
+    /// Returns the source shown with the diagnostics of this check.
+    fn source(self) -> Option<&'static str> {
+        match self {
+            Check::Disk => None,
+            Check::Shellcheck => Some("shellcheck"),
+        }
+    }
+}
+
 #[derive(Debug)]
 struct State {
     sources: HashMap<Url, FileId>,
-    files: Files,
+    /// Documents which were open before, whose files are reused if they are opened again, from
+    /// the least to the most recently closed. At most [`MAX_CLOSED`] are kept.
+    closed: VecDeque<(Url, FileId)>,
+    db: Database,
+    shellcheck: bool,
+    eval: bool,
+    eval_diagnostics: HashMap<Url, Vec<Diagnostic>>,
+    /// The diagnostics last found by each check run in the background.
+    checked: HashMap<(Url, Check), Vec<Diagnostic>>,
+    /// The version of each open document, as last given by the client.
+    versions: HashMap<Url, i64>,
+    inherits: Placement,
+    severities: Severities,
+    /// The search path `<...>` paths are checked against.
+    search_path: SearchPath,
+    /// The settings documents are formatted with, apart from the indentation.
+    format: fmt::Options,
+    /// The root of the workspace, indexed once the client is initialized.
+    root: Option<PathBuf>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Nix {
-    state: Mutex<State>,
+    state: Arc<Mutex<State>>,
+    snapshots: Arc<Snapshots>,
+    values: Arc<ValueCache>,
+    /// The files under the root of the workspace, indexed in the background.
+    workspace: Arc<RwLock<Workspace>>,
+    /// Queues documents to be checked by the worker running the disk checks.
+    disk_checks: Sender<(Url, PathBuf)>,
+    notifications: UnboundedSender<String>,
+    client: Arc<Client>,
 }
 
 impl Nix {
-    pub fn new() -> Self {
+    /// Creates a new backend, which sends any messages produced in the background through
+    /// `notifications`.
+    pub fn new(notifications: UnboundedSender<String>) -> Self {
+        let state = Arc::new(Mutex::new(State {
+            sources: HashMap::new(),
+            closed: VecDeque::new(),
+            db: Database::new(),
+            shellcheck: false,
+            eval: false,
+            eval_diagnostics: HashMap::new(),
+            checked: HashMap::new(),
+            versions: HashMap::new(),
+            inherits: Placement::default(),
+            severities: Severities::default(),
+            search_path: SearchPath::default(),
+            format: fmt::Options::default(),
+            root: None,
+        }));
+
+        let (disk_checks, queue) = mpsc::channel();
+        let (checked_state, checked_notifications) = (state.clone(), notifications.clone());
+        thread::spawn(move || run_disk_checks(&queue, &checked_state, &checked_notifications));
+
         Nix {
-            state: Mutex::new(State {
-                sources: HashMap::new(),
-                files: Files::new(),
-            }),
+            state,
+            snapshots: Arc::new(Snapshots::new()),
+            values: Arc::new(ValueCache::new()),
+            workspace: Arc::new(RwLock::new(Workspace::default())),
+            disk_checks,
+            notifications,
+            client: Arc::new(Client::default()),
         }
     }
+
+    /// Returns what is known of the client, to be filled in by a [`Routed`] service.
+    ///
+    /// [`Routed`]: crate::progress::Routed
+    pub fn client(&self) -> Arc<Client> {
+        self.client.clone()
+    }
+
+    /// Publishes a new snapshot reflecting the current contents of the given document.
+    fn publish_snapshot(&self, state: &State, uri: &Url, id: FileId) {
+        let document = Document::new(state.db.shared_text(id), state.db.parse(id))
+            .with_role(state.db.role(id));
+        let snapshot = self.snapshots.load();
+        let snapshot = snapshot.with_document(state.db.revision(), uri.clone(), document);
+        self.snapshots.publish(snapshot);
+        RECORDER.event(
+            "snapshot",
+            || json!({ "uri": uri, "revision": state.db.revision() }),
+        );
+    }
+
+    /// Runs the Nix evaluator over the given document in a background thread, republishing its
+    /// diagnostics once evaluation has finished.
+    ///
+    /// The results are dropped if the document is no longer at `version` by then, since their
+    /// locations would point into older text.
+    fn spawn_eval(&self, uri: Url, version: Option<i64>) {
+        let path = match uri.to_file_path() {
+            Ok(path) => path,
+            Err(_) => return,
+        };
+
+        let state = self.state.clone();
+        let notifications = self.notifications.clone();
+        thread::spawn(move || {
+            let errors = METRICS.time("nix-instantiate", || eval::check(&path));
+
+            let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+            let id = match state.sources.get(&uri) {
+                Some(id) if state.versions.get(&uri).cloned() == version => *id,
+                _ => return,
+            };
+
+            RECORDER.event("eval", || json!({ "uri": uri, "errors": errors.len() }));
+            let diags = errors
+                .into_iter()
+                .filter_map(|err| eval_error_to_diagnostic(&state, &uri, &path, id, err))
+                .collect();
+            state.eval_diagnostics.insert(uri.clone(), diags);
+
+            let diags = get_diagnostics(&state, &uri, id);
+            send_diagnostics(&notifications, uri, diags);
+        });
+    }
+
+    /// Queues the checks which read the files the given document refers to, such as the functions
+    /// called by `callPackage`.
+    ///
+    /// They run on a single worker once the document has not changed for [`DISK_CHECK_DELAY`], so
+    /// a burst of edits only checks the text it settles on.
+    fn queue_disk_checks(&self, uri: &Url) {
+        if let Ok(path) = uri.to_file_path() {
+            // The worker only stops once every sender is dropped.
+            let _ = self.disk_checks.send((uri.clone(), path));
+        }
+    }
+
+    /// Runs `shellcheck` over the scripts of the given document in the background, if enabled.
+    fn spawn_shellcheck(&self, state: &State, uri: &Url, id: FileId) {
+        if !state.shellcheck {
+            return;
+        }
+
+        let source = state.db.text(id).to_owned();
+        let checked = Checked::new(state, uri, id);
+        let state = self.state.clone();
+        let notifications = self.notifications.clone();
+        thread::spawn(move || {
+            checked.run(&state, &notifications, Check::Shellcheck, |file| {
+                shell::shellcheck(id, &source, file, SHELLCHECK_LIMIT)
+            })
+        });
+    }
+
+    /// Indexes the `.nix` files under `root` in a background thread.
+    ///
+    /// Files are parsed in parallel, and the progress shown in the client.
+    fn spawn_index(&self, root: PathBuf) {
+        let index = self.workspace.clone();
+        let notifications = self.notifications.clone();
+        let client = self.client.clone();
+        thread::spawn(move || {
+            let start = Instant::now();
+            let paths = match workspace::discover(&root) {
+                Ok(paths) => paths,
+                Err(e) => return info!("cannot index {}: {}", root.display(), e),
+            };
+            let progress = Progress::begin(&client, notifications, "nix/index", "Indexing");
+            let workspace = METRICS.time("workspace/index", || {
+                Workspace::index(paths, &RealFs, |done, total| {
+                    if let Some(ref progress) = progress {
+                        progress.report(done, total);
+                    }
+                })
+            });
+            if let Some(progress) = progress {
+                progress.end(&format!("Indexed {} files", workspace.len()));
+            }
+            info!(
+                "indexed {} files under {} in {:?}",
+                workspace.len(),
+                root.display(),
+                start.elapsed()
+            );
+            *index.write().unwrap_or_else(|e| e.into_inner()) = workspace;
+        });
+    }
+
+    /// Handles `textDocument/definition` requests.
+    pub fn definition(&self, params: TextDocumentPositionParams) -> Option<GotoDefinitionResponse> {
+        let _timer = METRICS.timer("textDocument/definition");
+        let snapshot = self.snapshots.load();
+        let uri = params.text_document.uri;
+        let position = params.position;
+        let document = snapshot.document(&uri)?;
+        let (offset, flake) = match get_flake(document, &uri, &position) {
+            Some(flake) => flake,
+            None => {
+                return get_store_path_definition(document, &position)
+                    .or_else(|| get_variable_definition(document, &uri, &position))
+                    .or_else(|| self.workspace_definition(document, &uri, &position))
+            }
+        };
+
+        let target = flake
+            .follows_at(offset)
+            .or_else(|| flake.input_at(offset))?;
+        let registry = flake.outputs.iter().find(|(name, _)| name == target);
+        let span = flake.input(target).or(registry.map(|&(_, span)| span))?;
+        let range = document.range(span).ok()?;
+        Some(GotoDefinitionResponse::Scalar(Location::new(uri, range)))
+    }
+
+    /// Jumps from an imported path to the file, or from an attribute selected from an import to
+    /// where the imported file binds it.
+    fn workspace_definition(
+        &self,
+        document: &Document,
+        uri: &Url,
+        position: &Position,
+    ) -> Option<GotoDefinitionResponse> {
+        let offset = document.byte_index(position).ok()?;
+        let path = uri.to_file_path().ok()?;
+        let workspace = self.workspace.read().unwrap_or_else(|e| e.into_inner());
+        let file = document.source_file()?;
+        let location = workspace.definition(&path, file, offset.to_usize(), &RealFs)?;
+        let location = workspace_location(&workspace, location)?;
+        Some(GotoDefinitionResponse::Scalar(location))
+    }
+
+    /// Handles `textDocument/references` requests, listing the variables referring to a binding
+    /// of the document, along with the imports of a file across the workspace and the attributes
+    /// selected from them.
+    pub fn references(&self, params: ReferenceParams) -> Option<Vec<Location>> {
+        let _timer = METRICS.timer("textDocument/references");
+        let snapshot = self.snapshots.load();
+        let declaration = params.context.include_declaration;
+        let params = params.text_document_position;
+        let uri = params.text_document.uri;
+        let document = snapshot.document(&uri)?;
+        let offset = document.byte_index(&params.position).ok()?;
+        let file = document.source_file()?;
+
+        let mut locations = Vec::new();
+        if let Some((binding, spans)) = resolve::references_at(file, offset.to_usize()) {
+            let binding = Some(binding).filter(|_| declaration);
+            locations.extend(binding.into_iter().chain(spans).filter_map(|span| {
+                let range = document.range(span).ok()?;
+                Some(Location::new(uri.clone(), range))
+            }));
+        }
+
+        if let Ok(path) = uri.to_file_path() {
+            let workspace = self.workspace.read().unwrap_or_else(|e| e.into_inner());
+            let references = workspace.references(&path, file, offset.to_usize(), &RealFs);
+            locations.extend(
+                references
+                    .into_iter()
+                    .filter_map(|location| workspace_location(&workspace, location)),
+            );
+        }
+        Some(locations)
+    }
+
+    /// Handles `textDocument/typeDefinition` requests on module options, jumping to the `type`
+    /// given to `mkOption` where the option is declared.
+    pub fn type_definition(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Option<GotoDefinitionResponse> {
+        let _timer = METRICS.timer("textDocument/typeDefinition");
+        let snapshot = self.snapshots.load();
+        let found = find_option(&snapshot, &params)?;
+        let declaration = &found.declaration;
+        let span = declaration
+            .type_expr
+            .as_ref()
+            .map_or(declaration.span, |&(_, span)| span);
+
+        let range = Text::new(found.text).range(span).ok()?;
+        let uri = Url::from_file_path(&found.file).ok()?;
+        Some(GotoDefinitionResponse::Scalar(Location::new(uri, range)))
+    }
+
+    /// Handles `textDocument/codeAction` requests, offering quick fixes for diagnostics,
+    /// explanations of their codes and organizing the bindings around the requested range.
+    pub fn code_action(&self, params: CodeActionParams) -> CodeActionResponse {
+        let uri = params.text_document.uri;
+        let (placement, naming) = {
+            let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            let naming = state.sources.get(&uri).map(|&id| state.db.naming(id));
+            (state.inherits, naming.unwrap_or_default())
+        };
+        let snapshot = self.snapshots.load();
+        let position = params.range.start;
+        let document = snapshot.document(&uri);
+        let organize =
+            document.and_then(|document| organize_action(document, &uri, position, placement));
+        let sri = document.and_then(|document| sri_action(document, &uri, position));
+        let strings = document
+            .map(|document| string_actions(document, &uri, position))
+            .unwrap_or_default();
+        let systems = document
+            .filter(|_| uri.path().ends_with("/flake.nix"))
+            .map(|document| systems_actions(document, &uri, position))
+            .unwrap_or_default();
+        let explanations = explain_actions(&params.context.diagnostics);
+
+        let mut actions: CodeActionResponse = params
+            .context
+            .diagnostics
+            .into_iter()
+            .filter_map(|diag| {
+                document.and_then(|document| fix_action(document, &uri, diag, naming))
+            })
+            .collect();
+        actions.extend(sri.map(CodeActionOrCommand::CodeAction));
+        actions.extend(strings.into_iter().map(CodeActionOrCommand::CodeAction));
+        actions.extend(systems.into_iter().map(CodeActionOrCommand::CodeAction));
+        actions.extend(organize.map(CodeActionOrCommand::CodeAction));
+        actions.extend(explanations);
+        actions
+    }
+
+    /// Handles `nix/valuePreview` requests, evaluating the binding at a position of a file on disk
+    /// and laying out its value as a read-only document.
+    pub fn value_preview(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<Option<ValuePreview>> {
+        let _timer = METRICS.timer("nix/valuePreview");
+        let snapshot = self.snapshots.load();
+        let uri = &params.text_document.uri;
+        let (document, path) = match (snapshot.document(uri), uri.to_file_path()) {
+            (Some(document), Ok(path)) => (document, path),
+            _ => return Ok(None),
+        };
+        let offset = match document.byte_index(&params.position) {
+            Ok(offset) => offset.to_usize(),
+            Err(_) => return Ok(None),
+        };
+        let attr_path = match document
+            .source_file()
+            .and_then(|file| breadcrumb::path_at(file, offset))
+        {
+            Some(attr_path) => attr_path,
+            None => return Ok(None),
+        };
+
+        let expr = preview::expression(&path, &attr_path, preview::DEPTH);
+        let value = self
+            .values
+            .evaluate_within(&expr, PREVIEW_EVAL_LIMIT)
+            .map_err(|errors| {
+                let mut error = Error::internal_error();
+                error.message = match errors.first() {
+                    Some(first) => format!("evaluation failed: {}", first.message),
+                    None => "evaluation failed".to_owned(),
+                };
+                error
+            })?;
+        let rendered = match preview::render(&value) {
+            Some(rendered) => rendered,
+            None => return Ok(None),
+        };
+
+        let mut preview_files = Files::new();
+        let preview_id = preview_files.add("preview", rendered.text.as_str());
+        let attrs = rendered
+            .attrs
+            .iter()
+            .filter_map(|(path, span)| {
+                Some(PreviewAttr {
+                    path: path.join("."),
+                    range: byte_span_to_range(&preview_files, preview_id, *span).ok()?,
+                })
+            })
+            .collect();
+
+        let mut preview_uri = Url::parse(&format!("{}:/", preview::SCHEME)).unwrap();
+        preview_uri.set_path(&path.to_string_lossy());
+        preview_uri.set_query(Some(&attr_path.join(".")));
+        Ok(Some(ValuePreview {
+            uri: preview_uri,
+            text: rendered.text,
+            attrs,
+        }))
+    }
+
+    /// Handles `textDocument/codeLens` requests, offering to preview the value of every top-level
+    /// binding.
+    pub fn code_lens(&self, params: CodeLensParams) -> Vec<CodeLens> {
+        let snapshot = self.snapshots.load();
+        let uri = params.text_document.uri;
+        let document = match snapshot.document(&uri) {
+            Some(document) if uri.scheme() == "file" => document,
+            _ => return Vec::new(),
+        };
+        let binds = match document.source_file() {
+            Some(file) => result_binds(file.expr()),
+            None => return Vec::new(),
+        };
+
+        binds
+            .iter()
+            .filter_map(|bind| match *bind {
+                Bind::Simple(ref simple) => {
+                    let range = document.range(simple.attr().span()).ok()?;
+                    Some(CodeLens {
+                        range,
+                        command: Some(preview_command(&uri, range.start)),
+                        data: None,
+                    })
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Handles `nix/renameReport` requests, returning the edit renaming the occurrences of a
+    /// symbol which provably refer to it, along with the occurrences which need to be reviewed.
+    pub fn rename_report(&self, params: RenameParams) -> Result<Option<RenameReport>> {
+        let _timer = METRICS.timer("nix/renameReport");
+        if !rename::is_identifier(&params.new_name) {
+            let message = format!("`{}` is not a valid identifier", params.new_name);
+            return Err(Error::invalid_params(message));
+        }
+
+        let snapshot = self.snapshots.load();
+        let position = &params.text_document_position;
+        let uri = position.text_document.uri.clone();
+        let document = match snapshot.document(&uri) {
+            Some(document) => document,
+            None => return Ok(None),
+        };
+        let offset = match document.byte_index(&position.position) {
+            Ok(offset) => offset.to_usize(),
+            Err(_) => return Ok(None),
+        };
+        let file = match document.source_file() {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let report = match rename::report(file, offset, Some(&params.new_name)) {
+            Some(report) => report,
+            None => return Ok(None),
+        };
+
+        let uncertain = report
+            .uncertain
+            .iter()
+            .filter_map(|(span, reason)| {
+                Some(UncertainOccurrence {
+                    range: document.range(*span).ok()?,
+                    reason: reason.to_string(),
+                })
+            })
+            .collect();
+
+        Ok(Some(RenameReport {
+            edit: rename_edit(document, file, uri, &report, &params.new_name),
+            uncertain,
+        }))
+    }
+
+    /// Handles `textDocument/rename` requests on a variable or a binding, renaming it along with
+    /// every occurrence which provably refers to it.
+    ///
+    /// Variables which may come from a `with` cannot be renamed, since the attributes of its set
+    /// are not known, and neither can attributes which a variable inside `with` may refer to.
+    /// Neither can symbols whose new name would change what another variable refers to. Names
+    /// of an `inherit` are rewritten as bindings, so that only the renamed side changes.
+    pub fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        let _timer = METRICS.timer("textDocument/rename");
+        if !rename::is_identifier(&params.new_name) {
+            let message = format!("`{}` is not a valid identifier", params.new_name);
+            return Err(Error::invalid_params(message));
+        }
+
+        let snapshot = self.snapshots.load();
+        let position = &params.text_document_position;
+        let uri = position.text_document.uri.clone();
+        let document = match snapshot.document(&uri) {
+            Some(document) => document,
+            None => return Ok(None),
+        };
+        let file = match document.source_file() {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let offset = match document.byte_index(&position.position) {
+            Ok(offset) => offset.to_usize(),
+            Err(_) => return Ok(None),
+        };
+
+        let mut references = resolve::references(file).into_iter();
+        let reference = references
+            .find(|r| r.span.start().to_usize() <= offset && offset <= r.span.end().to_usize());
+        if let Some(reference) = reference {
+            if let Target::With(span) = reference.target {
+                let set = &document.text()[span.start().to_usize()..span.end().to_usize()];
+                let message = format!(
+                    "cannot rename `{}`, which may come from `with {}`, whose attributes are unknown",
+                    reference.name, set
+                );
+                return Err(Error::invalid_params(message));
+            }
+        }
+
+        let report = match rename::report(file, offset, Some(&params.new_name)) {
+            Some(report) => report,
+            None => return Ok(None),
+        };
+        let conflict = report.uncertain.iter().find(|(_, reason)| {
+            matches!(
+                *reason,
+                Reason::With | Reason::Shadowed(_) | Reason::Captured | Reason::Duplicate
+            )
+        });
+        if let Some((span, reason)) = conflict {
+            let range = document.range(*span);
+            let line = range.map_or(0, |range| range.start.line + 1);
+            let message = format!(
+                "cannot rename `{}` to `{}`: a name on line {} {}",
+                report.name, params.new_name, line, reason
+            );
+            return Err(Error::invalid_params(message));
+        }
+
+        Ok(Some(rename_edit(
+            document,
+            file,
+            uri,
+            &report,
+            &params.new_name,
+        )))
+    }
+
+    /// Handles `textDocument/documentSymbol` requests, returning the outline of a document.
+    pub fn document_symbol(&self, params: DocumentSymbolParams) -> Option<DocumentSymbolResponse> {
+        let _timer = METRICS.timer("textDocument/documentSymbol");
+        let snapshot = self.snapshots.load();
+        let document = snapshot.document(&params.text_document.uri)?;
+        let symbols = outline::symbols(document.source_file()?.expr());
+        Some(document_symbols(document, symbols).into())
+    }
+
+    /// Handles `textDocument/formatting` requests, replacing the whole document with its canonical
+    /// formatting.
+    ///
+    /// Documents which do not parse, or whose comments could not all be kept, are left alone.
+    pub fn formatting(&self, params: DocumentFormattingParams) -> Option<Vec<TextEdit>> {
+        let _timer = METRICS.timer("textDocument/formatting");
+        let format = self
+            .state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .format
+            .clone();
+        let snapshot = self.snapshots.load();
+        let document = snapshot.document(&params.text_document.uri)?;
+        let options = fmt::Options {
+            indent: params.options.tab_size as usize,
+            ..format
+        };
+
+        let formatted = match fmt::format(document.text(), &options) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                info!("not formatting {}: {}", params.text_document.uri, e);
+                return None;
+            }
+        };
+        if formatted == document.text() {
+            return Some(Vec::new());
+        }
+
+        let whole = Span::new(0, document.text().len() as u32);
+        let range = document.range(whole).ok()?;
+        Some(vec![TextEdit::new(range, formatted)])
+    }
+
+    /// Handles `nix/highlightRanges` requests, returning the lexical highlighting of a document.
+    pub fn highlight_ranges(&self, params: HighlightParams) -> Vec<HighlightRange> {
+        let _timer = METRICS.timer("nix/highlightRanges");
+        let snapshot = self.snapshots.load();
+        let document = match snapshot.document(&params.text_document.uri) {
+            Some(document) => document,
+            None => return Vec::new(),
+        };
+
+        highlight::highlights(document.text())
+            .into_iter()
+            .filter(|h| {
+                params
+                    .kinds
+                    .as_ref()
+                    .is_none_or(|kinds| kinds.contains(&h.kind))
+            })
+            .filter_map(|h| {
+                let range = document.range(h.span).ok()?;
+                Some(HighlightRange {
+                    range,
+                    kind: h.kind,
+                })
+            })
+            .collect()
+    }
+
+    /// Handles `nix/embeddedShell` requests, returning the ranges of all embedded shell scripts.
+    pub fn embedded_shell(&self, params: TextDocumentIdentifier) -> Vec<EmbeddedShell> {
+        let _timer = METRICS.timer("nix/embeddedShell");
+        let snapshot = self.snapshots.load();
+        let document = match snapshot.document(&params.uri) {
+            Some(document) => document,
+            None => return Vec::new(),
+        };
+
+        let source = document.text();
+        let scripts = document
+            .source_file()
+            .map(|expr| shell::find_scripts(source, expr));
+        scripts
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|(region, _)| {
+                let range = document.range(region.span);
+                let range = range.ok()?;
+                Some(EmbeddedShell {
+                    range,
+                    language_id: "shellscript",
+                    context: region.context,
+                })
+            })
+            .collect()
+    }
+}
+
+/// A shell script embedded in a Nix string, as returned by `nix/embeddedShell`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmbeddedShell {
+    range: Range,
+    language_id: &'static str,
+    context: String,
+}
+
+/// The result of `nix/valuePreview`, a read-only document holding an evaluated value.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValuePreview {
+    uri: Url,
+    text: String,
+    /// Every attribute of the value, for navigating between them.
+    attrs: Vec<PreviewAttr>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PreviewAttr {
+    path: String,
+    range: Range,
+}
+
+/// An entry of the list returned by the `nix.listTodos` command.
+#[derive(Debug, Serialize)]
+pub struct TodoItem {
+    uri: Url,
+    range: Range,
+    marker: String,
+    owner: Option<String>,
+    text: String,
+}
+
+/// The result of `nix/renameReport`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenameReport {
+    /// The edit renaming every occurrence which provably refers to the symbol.
+    edit: WorkspaceEdit,
+    /// Occurrences which the edit leaves alone, but which might refer to the symbol or conflict
+    /// with the new name.
+    uncertain: Vec<UncertainOccurrence>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UncertainOccurrence {
+    range: Range,
+    reason: String,
+}
+
+/// The argument of the `nix.bumpVersion` command.
+#[derive(Debug, Deserialize)]
+pub struct BumpParams {
+    uri: Url,
+    version: String,
+    /// Whether to prefetch the sources to compute their hashes, instead of using a fake hash.
+    #[serde(default)]
+    prefetch: bool,
+}
+
+/// The parameters of `nix/highlightRanges`, which may restrict the kinds of ranges returned.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HighlightParams {
+    text_document: TextDocumentIdentifier,
+    #[serde(default)]
+    kinds: Option<Vec<Kind>>,
+}
+
+/// A highlighted range, as returned by `nix/highlightRanges`.
+#[derive(Debug, Serialize)]
+pub struct HighlightRange {
+    range: Range,
+    kind: Kind,
 }
 
 impl LanguageServer for Nix {
@@ -43,7 +836,28 @@ impl LanguageServer for Nix {
     type HoverFuture = BoxFuture<Option<Hover>>;
     type HighlightFuture = BoxFuture<Option<Vec<DocumentHighlight>>>;
 
-    fn initialize(&self, _: &Printer, _: InitializeParams) -> Result<InitializeResult> {
+    fn initialize(&self, _: &Printer, params: InitializeParams) -> Result<InitializeResult> {
+        let options = params.initialization_options.unwrap_or_default();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.shellcheck = options.get("shellcheck").and_then(Value::as_bool) == Some(true);
+        state.eval = options.get("evalDiagnostics").and_then(Value::as_bool) == Some(true);
+        let inherits = options.get("organizeInherits").and_then(Value::as_str);
+        state.inherits = inherits
+            .and_then(Placement::from_option)
+            .unwrap_or_default();
+        state.severities = Severities::from_settings(&options);
+        state.format = format_from_settings(&options);
+        let nix_path = options.get("nixPath").and_then(Value::as_str);
+        state.search_path = match nix_path {
+            Some(nix_path) => SearchPath::new(nix_path),
+            None => SearchPath::new(&env::var("NIX_PATH").unwrap_or_default()),
+        };
+        state.db.set_nix_version(Version::from_settings(&options));
+        state.db.set_naming(naming::Config::from_settings(&options));
+        state.db.set_todos(todo::from_settings(&options));
+        state.db.set_roles(role::Overrides::from_settings(&options));
+        state.root = params.root_uri.and_then(|uri| uri.to_file_path().ok());
+
         Ok(InitializeResult {
             capabilities: ServerCapabilities {
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
@@ -62,43 +876,252 @@ impl LanguageServer for Nix {
                 document_symbol_provider: Some(true),
                 workspace_symbol_provider: Some(true),
                 definition_provider: Some(true),
+                references_provider: Some(true),
+                rename_provider: Some(RenameProviderCapability::Simple(true)),
+                type_definition_provider: Some(TypeDefinitionProviderCapability::Simple(true)),
+                code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+                code_lens_provider: Some(CodeLensOptions {
+                    resolve_provider: Some(false),
+                }),
+                execute_command_provider: Some(ExecuteCommandOptions {
+                    commands: vec![
+                        bump::COMMAND.to_string(),
+                        explain::COMMAND.to_string(),
+                        preview::COMMAND.to_string(),
+                        todo::COMMAND.to_string(),
+                    ],
+                }),
                 ..ServerCapabilities::default()
             },
         })
     }
 
+    fn initialized(&self, printer: &Printer, _: InitializedParams) {
+        // Indexing may ask the client to show progress, which it may only be asked once
+        // initialized.
+        let root = self
+            .state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .root
+            .take();
+        if let Some(root) = root {
+            self.spawn_index(root);
+        }
+
+        let message = format!(
+            "nix-language-server {} initialized",
+            env!("CARGO_PKG_VERSION")
+        );
+        printer.log_message(MessageType::Info, message);
+    }
+
     fn shutdown(&self) -> Self::ShutdownFuture {
         future::ok(())
     }
 
-    fn symbol(&self, _: WorkspaceSymbolParams) -> Self::SymbolFuture {
-        future::ok(None)
+    fn symbol(&self, params: WorkspaceSymbolParams) -> Self::SymbolFuture {
+        let _timer = METRICS.timer("workspace/symbol");
+        let snapshot = self.snapshots.load();
+        info!("searching symbols at revision {}", snapshot.revision());
+        future::ok(Some(workspace_symbols(&snapshot, &params.query)))
     }
 
-    fn execute_command(&self, _: &Printer, _: ExecuteCommandParams) -> Self::ExecuteFuture {
-        future::ok(None)
+    fn execute_command(&self, _: &Printer, params: ExecuteCommandParams) -> Self::ExecuteFuture {
+        if params.command == preview::COMMAND {
+            let position = params
+                .arguments
+                .into_iter()
+                .next()
+                .map(serde_json::from_value);
+            return match position {
+                Some(Ok(position)) => match self.value_preview(position) {
+                    Ok(preview) => future::ok(preview.map(|p| serde_json::to_value(p).unwrap())),
+                    Err(error) => future::err(error),
+                },
+                _ => future::err(Error::invalid_params("expected a document position")),
+            };
+        } else if params.command == bump::COMMAND {
+            let _timer = METRICS.timer(bump::COMMAND);
+            let args = params
+                .arguments
+                .into_iter()
+                .next()
+                .map(serde_json::from_value);
+            return match args {
+                Some(Ok(args)) => match bump_edit(&self.snapshots.load(), args) {
+                    Ok(edit) => future::ok(Some(serde_json::to_value(edit).unwrap())),
+                    Err(message) => future::err(Error::invalid_params(message)),
+                },
+                _ => future::err(Error::invalid_params("expected a document and a version")),
+            };
+        } else if params.command == todo::COMMAND {
+            let _timer = METRICS.timer(todo::COMMAND);
+            let todos = todo_items(&self.snapshots.load());
+            return future::ok(Some(serde_json::to_value(todos).unwrap()));
+        } else if params.command != explain::COMMAND {
+            return future::ok(None);
+        }
+
+        let _timer = METRICS.timer(explain::COMMAND);
+        let code = match params.arguments.first().and_then(Value::as_str) {
+            Some(code) => code,
+            None => return future::err(Error::invalid_params("expected a diagnostic code")),
+        };
+        match explain::explain(code) {
+            Some(explanation) => future::ok(Some(Value::String(explanation))),
+            None => {
+                let message = format!("no explanation for diagnostic code `{}`", code);
+                future::err(Error::invalid_params(message))
+            }
+        }
     }
 
-    fn completion(&self, _: CompletionParams) -> Self::CompletionFuture {
-        future::ok(None)
+    fn completion(&self, params: CompletionParams) -> Self::CompletionFuture {
+        let _timer = METRICS.timer("textDocument/completion");
+        let snapshot = self.snapshots.load();
+        let params = params.text_document_position;
+        let document = snapshot.document(&params.text_document.uri);
+        future::ok(document.and_then(|document| {
+            get_flake_completions(document, params.clone())
+                .or_else(|| get_call_package_completions(document, params.clone()))
+                .or_else(|| get_override_completions(document, params.clone(), &self.values))
+                .or_else(|| get_role_completions(document, params.clone()))
+                .or_else(|| get_builtin_completions(document, &params))
+                .or_else(|| get_syntax_completions(document, params))
+        }))
     }
 
     fn did_open(&self, printer: &Printer, params: DidOpenTextDocumentParams) {
+        let _timer = METRICS.timer("textDocument/didOpen");
         let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
         let id = get_or_insert_source(&mut state, &params.text_document);
+        let document = &params.text_document;
+        state
+            .versions
+            .insert(document.uri.clone(), document.version);
+        self.publish_snapshot(&state, &params.text_document.uri, id);
+        self.queue_disk_checks(&params.text_document.uri);
+        self.spawn_shellcheck(&state, &params.text_document.uri, id);
         let diags = get_diagnostics(&state, &params.text_document.uri, id);
         printer.publish_diagnostics(params.text_document.uri, diags);
     }
 
     fn did_change(&self, printer: &Printer, params: DidChangeTextDocumentParams) {
+        let _timer = METRICS.timer("textDocument/didChange");
         let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
         let id = reload_source(&mut state, &params.text_document, params.content_changes);
+        let document = &params.text_document;
+        match document.version {
+            Some(version) => state.versions.insert(document.uri.clone(), version),
+            None => state.versions.remove(&document.uri),
+        };
+        self.publish_snapshot(&state, &params.text_document.uri, id);
+        self.queue_disk_checks(&params.text_document.uri);
+        // The disk checks run again once the edits settle, and `shellcheck` only on save. Until
+        // then their results no longer match the text.
+        for kind in [Check::Disk, Check::Shellcheck] {
+            state
+                .checked
+                .remove(&(params.text_document.uri.clone(), kind));
+        }
         let diags = get_diagnostics(&state, &params.text_document.uri, id);
         printer.publish_diagnostics(params.text_document.uri, diags);
     }
 
-    fn hover(&self, _: TextDocumentPositionParams) -> Self::HoverFuture {
-        Box::new(future::ok(None))
+    fn did_change_configuration(&self, printer: &Printer, params: DidChangeConfigurationParams) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let severities = Severities::from_settings(&params.settings);
+        state.format = format_from_settings(&params.settings);
+        let revision = state.db.revision();
+        state
+            .db
+            .set_nix_version(Version::from_settings(&params.settings));
+        state
+            .db
+            .set_naming(naming::Config::from_settings(&params.settings));
+        state.db.set_todos(todo::from_settings(&params.settings));
+        state
+            .db
+            .set_roles(role::Overrides::from_settings(&params.settings));
+        let changed = severities != state.severities || revision != state.db.revision();
+        RECORDER.event("configuration", || json!({ "changed": changed }));
+        if !changed {
+            return;
+        }
+
+        state.severities = severities;
+        for (uri, id) in &state.sources {
+            let diags = get_diagnostics(&state, uri, *id);
+            printer.publish_diagnostics(uri.clone(), diags);
+        }
+    }
+
+    fn did_close(&self, printer: &Printer, params: DidCloseTextDocumentParams) {
+        let _timer = METRICS.timer("textDocument/didClose");
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let uri = params.text_document.uri;
+        if let Some(id) = state.sources.remove(&uri) {
+            state.closed.push_back((uri.clone(), id));
+            if state.closed.len() > MAX_CLOSED {
+                if let Some((_, evicted)) = state.closed.pop_front() {
+                    state.db.release(evicted);
+                }
+            }
+            state.eval_diagnostics.remove(&uri);
+            state.checked.retain(|(checked, _), _| *checked != uri);
+            state.versions.remove(&uri);
+            let snapshot = self.snapshots.load();
+            let snapshot = snapshot.without_document(state.db.revision(), &uri);
+            self.snapshots.publish(snapshot);
+            printer.publish_diagnostics(uri, Vec::new());
+        }
+    }
+
+    fn did_save(&self, printer: &Printer, params: DidSaveTextDocumentParams) {
+        let _timer = METRICS.timer("textDocument/didSave");
+        let uri = params.text_document.uri;
+        let (text, parse) = {
+            let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            let id = match state.sources.get(&uri) {
+                Some(&id) => id,
+                None => return,
+            };
+            self.queue_disk_checks(&uri);
+            self.spawn_shellcheck(&state, &uri, id);
+
+            let diags = get_diagnostics(&state, &uri, id);
+            printer.publish_diagnostics(uri.clone(), diags);
+
+            if state.eval {
+                let version = state.versions.get(&uri).cloned();
+                self.spawn_eval(uri.clone(), version);
+            }
+            (state.db.shared_text(id), state.db.parse(id))
+        };
+
+        // The parse is reused from the database, and the imports are resolved before locking the
+        // index, so that requests reading it only wait for the file to be swapped in.
+        if let Ok(path) = uri.to_file_path() {
+            let prepared = Workspace::prepare(path, text, parse, &RealFs);
+            let mut workspace = self.workspace.write().unwrap_or_else(|e| e.into_inner());
+            workspace.insert(prepared);
+        }
+    }
+
+    fn hover(&self, params: TextDocumentPositionParams) -> Self::HoverFuture {
+        let _timer = METRICS.timer("textDocument/hover");
+        let snapshot = self.snapshots.load();
+        let document = snapshot.document(&params.text_document.uri);
+        let hover = document.and_then(|document| {
+            get_flake_hover(document, params.clone())
+                .or_else(|| get_hash_hover(document, params.clone()))
+                .or_else(|| get_option_hover(&snapshot, &params))
+                .or_else(|| get_glossary_hover(document, &params))
+                .or_else(|| get_constant_hover(document, &params))
+                .or_else(|| get_breadcrumb_hover(document, &params))
+        });
+        Box::new(future::ok(hover))
     }
 
     fn document_highlight(&self, _: TextDocumentPositionParams) -> Self::HighlightFuture {
@@ -109,10 +1132,22 @@ impl LanguageServer for Nix {
 fn get_or_insert_source(state: &mut State, document: &TextDocumentItem) -> FileId {
     if let Some(id) = state.sources.get(&document.uri) {
         *id
+    } else if let Some(index) = state
+        .closed
+        .iter()
+        .position(|(uri, _)| *uri == document.uri)
+    {
+        let (_, id) = state
+            .closed
+            .remove(index)
+            .expect("index of a closed document");
+        state.db.set_text(id, document.text.clone());
+        state.sources.insert(document.uri.clone(), id);
+        id
     } else {
         let id = state
-            .files
-            .add(document.uri.to_string(), document.text.clone());
+            .db
+            .add_file(document.uri.to_string(), document.text.clone());
         state.sources.insert(document.uri.clone(), id);
         id
     }
@@ -124,42 +1159,1089 @@ fn reload_source(
     changes: Vec<TextDocumentContentChangeEvent>,
 ) -> FileId {
     if let Some(id) = state.sources.get(&document.uri) {
-        let mut source = state.files.source(*id).to_owned();
         for change in changes {
             if let (None, None) = (change.range, change.range_length) {
-                source = change.text;
+                // Applying only what changed keeps reparsing incremental for clients which
+                // always send the whole document.
+                let edit = sync::minimal_edit(state.db.text(*id), &change.text);
+                if let Some((span, replacement)) = edit {
+                    record_edit(document, span, replacement.len());
+                    state.db.edit(*id, span, &change.text[replacement]);
+                }
             } else if let Some(range) = change.range {
-                let span = range_to_byte_span(&state.files, *id, &range).unwrap_or_default();
-                let range = (span.start().to_usize())..(span.end().to_usize());
-                source.replace_range(range, &change.text);
+                let span = state.db.shared_text(*id).span(&range).unwrap_or_default();
+                record_edit(document, span, change.text.len());
+                state.db.edit(*id, span, &change.text);
             }
         }
-        state.files.update(*id, source);
         *id
     } else {
         panic!("attempted to reload source that does not exist");
     }
 }
 
+fn record_edit(document: &VersionedTextDocumentIdentifier, span: Span, len: usize) {
+    RECORDER.event("edit", || {
+        json!({
+            "uri": document.uri,
+            "version": document.version,
+            "start": span.start().to_usize(),
+            "end": span.end().to_usize(),
+            "len": len,
+        })
+    });
+}
+
+/// A document as it was when a check started running over it in the background.
+struct Checked {
+    uri: Url,
+    id: FileId,
+    version: Option<i64>,
+    parse: Arc<ParseResult>,
+}
+
+impl Checked {
+    fn new(state: &State, uri: &Url, id: FileId) -> Self {
+        Checked {
+            uri: uri.clone(),
+            id,
+            version: state.versions.get(uri).cloned(),
+            parse: state.db.parse(id),
+        }
+    }
+
+    /// Runs `check` over the parse cached by the database, then stores its results and
+    /// republishes the diagnostics of the document.
+    ///
+    /// The state is only locked again to store the results, which are dropped if the document has
+    /// changed in the meantime.
+    fn run<C>(
+        self,
+        state: &Mutex<State>,
+        notifications: &UnboundedSender<String>,
+        kind: Check,
+        check: C,
+    ) where
+        C: FnOnce(&SourceFile) -> Vec<CodespanDiagnostic>,
+    {
+        let diags = match *self.parse {
+            Ok(ref partial) => partial
+                .value()
+                .map(|file| METRICS.time(kind.name(), || check(file))),
+            Err(_) => None,
+        };
+
+        let (uri, id) = (self.uri, self.id);
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        let current = state.sources.get(&uri) == Some(&id)
+            && state.versions.get(&uri) == self.version.as_ref();
+        if !current {
+            return;
+        }
+
+        let source = kind.source().map(str::to_owned);
+        let diags = diags
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|diag| state.db.lsp_diagnostic(diag, source.clone(), Some(&uri)))
+            .collect();
+        state.checked.insert((uri.clone(), kind), diags);
+
+        let diags = get_diagnostics(&state, &uri, id);
+        send_diagnostics(notifications, uri, diags);
+    }
+}
+
+/// Runs the disk checks of the documents received from `queue`, until every sender is dropped.
+///
+/// Documents are gathered until none has been received for [`DISK_CHECK_DELAY`], and each is then
+/// checked once against its latest parse.
+fn run_disk_checks(
+    queue: &Receiver<(Url, PathBuf)>,
+    state: &Mutex<State>,
+    notifications: &UnboundedSender<String>,
+) {
+    while let Ok((uri, path)) = queue.recv() {
+        let mut pending = HashMap::new();
+        pending.insert(uri, path);
+        loop {
+            match queue.recv_timeout(DISK_CHECK_DELAY) {
+                Ok((uri, path)) => {
+                    pending.insert(uri, path);
+                }
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+
+        for (uri, path) in pending {
+            let (checked, search_path) = {
+                let state = state.lock().unwrap_or_else(|e| e.into_inner());
+                match state.sources.get(&uri) {
+                    Some(&id) => (Checked::new(&state, &uri, id), state.search_path.clone()),
+                    None => continue,
+                }
+            };
+            let id = checked.id;
+            checked.run(state, notifications, Check::Disk, |file| {
+                disk::check(id, file, &path, &search_path, &RealFs)
+            });
+        }
+    }
+}
+
+/// Returns the diagnostics to publish for the given document, with the configured severities.
+///
+/// Running `shellcheck` is comparatively slow, so its diagnostics are only included on request.
 fn get_diagnostics(state: &State, uri: &Url, id: FileId) -> Vec<Diagnostic> {
-    let source = state.files.source(id);
-    match source.parse::<SourceFile>() {
-        Ok(expr) => {
-            info!("parsed expression: {}", expr);
-            Vec::new()
-        }
-        Err(err) => {
-            info!("expression has errors: {}", err);
-            let diagnostics = err.to_diagnostics(id);
-
-            let mut new_diags = Vec::new();
-            for diag in diagnostics {
-                let diag =
-                    make_lsp_diagnostic(&state.files, None, diag, |_| Ok(uri.clone())).unwrap();
-                new_diags.push(diag);
+    let mut diagnostics = (*state.db.diagnostics(id)).clone();
+    info!("analyzed {} at revision {}", uri, state.db.revision());
+
+    if let Some(diags) = state.eval_diagnostics.get(uri) {
+        diagnostics.extend(diags.iter().cloned());
+    }
+
+    for kind in [Check::Disk, Check::Shellcheck] {
+        if let Some(diags) = state.checked.get(&(uri.clone(), kind)) {
+            diagnostics.extend(diags.iter().cloned());
+        }
+    }
+
+    state.severities.apply(diagnostics)
+}
+
+/// Publishes diagnostics found in the background, outside of any request.
+fn send_diagnostics(notifications: &UnboundedSender<String>, uri: Url, diags: Vec<Diagnostic>) {
+    let params = PublishDiagnosticsParams::new(uri, diags);
+    let message = json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": params,
+    });
+    let _ = notifications.unbounded_send(message.to_string());
+}
+
+/// Maps an error reported by the evaluator onto the given source file.
+///
+/// Errors located in other files, such as those pulled in by `import`, are reported at the start
+/// of this file instead.
+fn eval_error_to_diagnostic(
+    state: &State,
+    uri: &Url,
+    path: &Path,
+    id: FileId,
+    err: EvalError,
+) -> Option<Diagnostic> {
+    let source = state.db.text(id);
+    let (span, message) = match err.location {
+        Some((ref file, line, column)) if file == path => {
+            let line_span = state.db.shared_text(id).line_span(line.saturating_sub(1))?;
+            let start = line_span.start().to_usize() + column.saturating_sub(1);
+            let start = start.min(line_span.end().to_usize());
+            let len = source
+                .get(start..)?
+                .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-' || c == '\''))
+                .unwrap_or(0);
+            (Span::new(start as u32, (start + len) as u32), err.message)
+        }
+        Some((ref file, line, column)) => {
+            let location = format!("{}:{}:{}", file.display(), line, column);
+            (
+                Span::initial(),
+                format!("{} (at {})", err.message, location),
+            )
+        }
+        None => (Span::initial(), err.message),
+    };
+
+    let label = Label::new(id, span, "reported by the Nix evaluator");
+    let diag = CodespanDiagnostic::new_error(message, label);
+    let source = "nix-instantiate".to_string();
+    state.db.lsp_diagnostic(diag, Some(source), Some(uri))
+}
+
+/// Returns a workspace edit applying `edit` to the document at `uri`.
+fn workspace_edit(uri: &Url, edit: TextEdit) -> WorkspaceEdit {
+    let mut changes = HashMap::new();
+    changes.insert(uri.clone(), vec![edit]);
+    WorkspaceEdit {
+        changes: Some(changes),
+        document_changes: None,
+    }
+}
+
+/// Returns the quick fix for `diag`, if there is one.
+fn fix_action(
+    document: &Document,
+    uri: &Url,
+    diag: Diagnostic,
+    naming: naming::Config,
+) -> Option<CodeActionOrCommand> {
+    let start = document.byte_index(&diag.range.start).ok()?;
+    let end = document.byte_index(&diag.range.end).ok()?;
+    let code = match diag.code {
+        Some(NumberOrString::String(ref code)) => Some(code.as_str()),
+        _ => None,
+    };
+    let path = uri.to_file_path().ok();
+    let problem = Problem {
+        source: document.text(),
+        path: path.as_deref(),
+        file: document.source_file(),
+        code,
+        span: Span::new(start, end),
+        naming,
+    };
+    let fix = fixes::fix(&problem)?;
+
+    let mut edits = Vec::new();
+    for (span, text) in fix.edits {
+        let range = document.range(span).ok()?;
+        edits.push(TextEdit::new(range, text));
+    }
+    let mut changes = HashMap::new();
+    changes.insert(uri.clone(), edits);
+    Some(CodeActionOrCommand::CodeAction(CodeAction {
+        title: fix.title,
+        kind: Some(code_action_kind::QUICKFIX.to_string()),
+        diagnostics: Some(vec![diag]),
+        edit: Some(WorkspaceEdit {
+            changes: Some(changes),
+            document_changes: None,
+        }),
+        command: None,
+    }))
+}
+
+/// Returns a command explaining the code of each of `diagnostics` which has an explanation.
+fn explain_actions(diagnostics: &[Diagnostic]) -> Vec<CodeActionOrCommand> {
+    let mut codes: Vec<_> = diagnostics
+        .iter()
+        .filter_map(|diag| match diag.code {
+            Some(NumberOrString::String(ref code)) => Some(code.as_str()),
+            _ => None,
+        })
+        .filter(|code| explain::explain(code).is_some())
+        .collect();
+    codes.sort_unstable();
+    codes.dedup();
+
+    codes
+        .into_iter()
+        .map(|code| {
+            CodeActionOrCommand::Command(Command {
+                title: format!("Explain {}", code),
+                command: explain::COMMAND.to_string(),
+                arguments: Some(vec![json!(code)]),
+            })
+        })
+        .collect()
+}
+
+fn organize_action(
+    document: &Document,
+    uri: &Url,
+    position: Position,
+    placement: Placement,
+) -> Option<CodeAction> {
+    let offset = document.byte_index(&position).ok()?;
+    let file = document.source_file()?;
+    let (span, text) = organize::organize(document.text(), file, offset.to_usize(), placement)?;
+    let range = document.range(span).ok()?;
+
+    Some(CodeAction {
+        title: "Organize inherits".to_string(),
+        kind: Some(code_action_kind::SOURCE_ORGANIZE_IMPORTS.to_string()),
+        diagnostics: None,
+        edit: Some(workspace_edit(uri, TextEdit::new(range, text))),
+        command: None,
+    })
+}
+
+fn sri_action(document: &Document, uri: &Url, position: Position) -> Option<CodeAction> {
+    let offset = document.byte_index(&position).ok()?;
+    let found = hashes::find_at(document.source_file()?, offset.to_usize())?;
+    let (span, text) = hashes::to_sri_binding(&found)?;
+    let range = document.range(span).ok()?;
+
+    Some(CodeAction {
+        title: "Convert to an SRI `hash`".to_string(),
+        kind: Some(code_action_kind::REFACTOR_REWRITE.to_string()),
+        diagnostics: None,
+        edit: Some(workspace_edit(uri, TextEdit::new(range, text))),
+        command: None,
+    })
+}
+
+fn string_actions(document: &Document, uri: &Url, position: Position) -> Vec<CodeAction> {
+    let offset = match document.byte_index(&position) {
+        Ok(offset) => offset.to_usize(),
+        Err(_) => return Vec::new(),
+    };
+    let file = match document.source_file() {
+        Some(file) => file,
+        None => return Vec::new(),
+    };
+
+    interpolate::actions(document.text(), file, offset)
+        .into_iter()
+        .filter_map(|(action, span, text)| {
+            let range = document.range(span).ok()?;
+            let kind = match action {
+                Action::Extract => code_action_kind::REFACTOR_EXTRACT,
+                Action::ToConcatenation | Action::ToInterpolation => {
+                    code_action_kind::REFACTOR_REWRITE
+                }
+            };
+            Some(CodeAction {
+                title: action.title().to_string(),
+                kind: Some(kind.to_string()),
+                diagnostics: None,
+                edit: Some(workspace_edit(uri, TextEdit::new(range, text))),
+                command: None,
+            })
+        })
+        .collect()
+}
+
+fn systems_actions(document: &Document, uri: &Url, position: Position) -> Vec<CodeAction> {
+    let offset = match document.byte_index(&position) {
+        Ok(offset) => offset.to_usize(),
+        Err(_) => return Vec::new(),
+    };
+    let file = match document.source_file() {
+        Some(file) => file,
+        None => return Vec::new(),
+    };
+
+    systems::actions(document.text(), file, offset)
+        .into_iter()
+        .filter_map(|(title, span, text)| {
+            let range = document.range(span).ok()?;
+            Some(CodeAction {
+                title,
+                kind: Some(code_action_kind::REFACTOR_REWRITE.to_string()),
+                diagnostics: None,
+                edit: Some(workspace_edit(uri, TextEdit::new(range, text))),
+                command: None,
+            })
+        })
+        .collect()
+}
+
+/// Returns the edit updating the package in the given document to a new version.
+fn bump_edit(snapshot: &Snapshot, args: BumpParams) -> std::result::Result<WorkspaceEdit, String> {
+    let document = snapshot
+        .document(&args.uri)
+        .ok_or_else(|| format!("document `{}` is not open", args.uri))?;
+    let file = document
+        .source_file()
+        .ok_or_else(|| "the document could not be parsed".to_owned())?;
+    let prefetch: bump::Prefetch = &bump::nix_prefetch_url;
+    let prefetch = Some(prefetch).filter(|_| args.prefetch);
+    let changes = bump::bump(document.text(), file, &args.version, prefetch)?;
+
+    let edits = changes
+        .into_iter()
+        .filter_map(|change| {
+            let range = document.range(change.span).ok()?;
+            Some(TextEdit::new(range, change.new))
+        })
+        .collect();
+    let mut changes = HashMap::new();
+    changes.insert(args.uri, edits);
+    Ok(WorkspaceEdit {
+        changes: Some(changes),
+        document_changes: None,
+    })
+}
+
+/// Returns the `TODO` comments of every open document, ordered by document and position.
+fn todo_items(snapshot: &Snapshot) -> Vec<TodoItem> {
+    let mut documents: Vec<_> = snapshot.documents().collect();
+    documents.sort_unstable_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+
+    let mut items = Vec::new();
+    for (uri, document) in documents {
+        for todo in todo::collect(document.text()) {
+            if let Ok(range) = document.range(todo.span) {
+                items.push(TodoItem {
+                    uri: uri.clone(),
+                    range,
+                    marker: todo.marker,
+                    owner: todo.owner,
+                    text: todo.text,
+                });
+            }
+        }
+    }
+    items
+}
+
+/// Returns the top-level bindings of all open documents whose names contain `query`.
+fn workspace_symbols(snapshot: &Snapshot, query: &str) -> Vec<SymbolInformation> {
+    let query = query.to_lowercase();
+    let mut symbols = Vec::new();
+    for (uri, document) in snapshot.documents() {
+        let binds = document
+            .source_file()
+            .map(|source| top_level_binds(source.expr()))
+            .unwrap_or_default();
+
+        for bind in binds {
+            let name = match *bind {
+                Bind::Simple(ref simple) => match simple.attr().segments().first() {
+                    Some(segment) => attrs::symbol_name(segment),
+                    None => continue,
+                },
+                _ => continue,
+            };
+
+            if !name.to_lowercase().contains(&query) {
+                continue;
+            }
+
+            let span = bind.span();
+            if let Ok(range) = document.range(span) {
+                symbols.push(SymbolInformation {
+                    name,
+                    kind: SymbolKind::Field,
+                    deprecated: None,
+                    location: Location::new(uri.clone(), range),
+                    container_name: None,
+                });
+            }
+        }
+    }
+
+    symbols
+}
+
+/// Reads the `formatLineWidth` and `formatMaxBlankLines` settings, falling back to the
+/// formatter's defaults.
+fn format_from_settings(settings: &Value) -> fmt::Options {
+    let setting = |name| {
+        settings
+            .get(name)
+            .and_then(Value::as_u64)
+            .map(|n| n as usize)
+    };
+    let default = fmt::Options::default();
+    fmt::Options {
+        max_width: setting("formatLineWidth").unwrap_or(default.max_width),
+        max_blank_lines: setting("formatMaxBlankLines").unwrap_or(default.max_blank_lines),
+        ..default
+    }
+}
+
+/// Converts the outline of `document` to symbols with ranges, dropping any outside the document.
+fn document_symbols(document: &Document, symbols: Vec<Symbol>) -> Vec<DocumentSymbol> {
+    let range = |span| document.range(span).ok();
+    symbols
+        .into_iter()
+        .filter_map(|symbol| {
+            let children = document_symbols(document, symbol.children);
+            Some(DocumentSymbol {
+                name: symbol.name,
+                detail: None,
+                kind: symbol.kind,
+                deprecated: None,
+                range: range(symbol.span)?,
+                selection_range: range(symbol.selection)?,
+                children: Some(children).filter(|children| !children.is_empty()),
+            })
+        })
+        .collect()
+}
+
+/// Returns the bindings of the attribute set or `let` expression a file evaluates to, looking
+/// through function declarations.
+fn top_level_binds(expr: &Expr) -> &[Bind] {
+    match *expr {
+        Expr::Set(ref set) => set.binds(),
+        Expr::Rec(ref rec) => rec.binds(),
+        Expr::LetIn(ref let_in) => let_in.binds(),
+        Expr::Paren(ref paren) => top_level_binds(paren.expr()),
+        Expr::FnDecl(ref decl) => match **decl {
+            ExprFnDecl::Simple(ref simple) => top_level_binds(simple.body()),
+            ExprFnDecl::Formals(ref formals) => top_level_binds(formals.body()),
+        },
+        _ => &[],
+    }
+}
+
+fn is_flake(uri: &Url) -> bool {
+    uri.to_file_path().is_ok_and(|path| flake::is_flake(&path))
+}
+
+/// Analyzes the flake in the given document, also returning the byte offset of `position`.
+fn get_flake(document: &Document, uri: &Url, position: &Position) -> Option<(usize, Flake)> {
+    if !is_flake(uri) {
+        return None;
+    }
+
+    let offset = document.byte_index(position).ok()?;
+    let flake = match *document.parse() {
+        Ok(ref partial) if !partial.has_errors() => partial.value().and_then(Flake::analyze)?,
+        _ => return None,
+    };
+
+    Some((offset.to_usize(), flake))
+}
+
+fn get_flake_completions(
+    document: &Document,
+    params: TextDocumentPositionParams,
+) -> Option<CompletionResponse> {
+    let uri = &params.text_document.uri;
+    if !is_flake(uri) {
+        return None;
+    }
+
+    let position = &params.position;
+    let offset = document.byte_index(position).ok()?;
+    let before = &document.text()[..offset.to_usize()];
+    let prefix = before.trim_end_matches(|c: char| c.is_alphanumeric() || c == '_' || c == '-');
+    if !prefix.ends_with("inputs.") {
+        return None;
+    }
+
+    let declared = document
+        .source_file()
+        .and_then(Flake::analyze)
+        .map(|flake| flake.inputs.into_iter().map(|(name, _)| name).collect())
+        .unwrap_or_default();
+    let locked = uri
+        .to_file_path()
+        .ok()
+        .and_then(|path| LockFile::for_flake(&path, &RealFs))
+        .map(|lock| lock.inputs.into_keys().collect())
+        .unwrap_or_default();
+
+    let mut names: Vec<(String, &str)> = Vec::new();
+    let sources = vec![
+        (declared, "declared input"),
+        (locked, "locked input"),
+        (
+            flake::registry_ids(&vfs::registry_paths(), &RealFs),
+            "flake registry",
+        ),
+    ];
+    for (list, detail) in sources {
+        for name in list {
+            if !names.iter().any(|(existing, _)| *existing == name) {
+                names.push((name, detail));
+            }
+        }
+    }
+
+    let items = names
+        .into_iter()
+        .map(|(name, detail)| CompletionItem {
+            label: name,
+            kind: Some(CompletionItemKind::Module),
+            detail: Some(detail.to_owned()),
+            ..CompletionItem::default()
+        })
+        .collect();
+
+    Some(CompletionResponse::Array(items))
+}
+
+/// Completes the names of the arguments which can be overridden in a `callPackage` call, or
+/// passed to the function of an imported file.
+fn get_call_package_completions(
+    document: &Document,
+    params: TextDocumentPositionParams,
+) -> Option<CompletionResponse> {
+    let offset = document.byte_index(&params.position).ok()?;
+    let call = call_package::override_position(document.source_file()?, offset.to_usize())?;
+    let path = params.text_document.uri.to_file_path().ok()?;
+    let target = call.target.resolve(&path, &RealFs);
+    let formals = Formals::load(&target, &RealFs)?;
+
+    let given: Vec<_> = call.override_names().into_iter().map(|(n, _)| n).collect();
+    let name = call_package::display_name(&target);
+    let items = formals
+        .args
+        .into_iter()
+        .filter(|(arg, _)| !given.contains(&arg.as_str()))
+        .map(|(arg, default)| CompletionItem {
+            label: arg,
+            kind: Some(CompletionItemKind::Field),
+            detail: Some(match (default, call.kind) {
+                (Some(default), _) => {
+                    format!("argument of `{}`, defaults to `{}`", name, default)
+                }
+                (None, call_package::Kind::Import) => {
+                    format!("required argument of `{}`", name)
+                }
+                (None, call_package::Kind::CallPackage) => format!("argument of `{}`", name),
+            }),
+            ..CompletionItem::default()
+        })
+        .collect();
+
+    Some(CompletionResponse::Array(items))
+}
+
+/// Completes the names of the arguments or attributes given to `pkg.override` and
+/// `pkg.overrideAttrs`, evaluating the package with a time limit if it is not defined nearby.
+fn get_override_completions(
+    document: &Document,
+    params: TextDocumentPositionParams,
+    values: &ValueCache,
+) -> Option<CompletionResponse> {
+    let offset = document.byte_index(&params.position).ok()?;
+    let file = document.source_file()?;
+    let site = overrides::site_at(file, offset.to_usize())?;
+
+    let path = params.text_document.uri.to_file_path().ok()?;
+    let target = overrides::callee(file, &site).map(|call| call.target.resolve(&path, &RealFs));
+    let mut names: Vec<(String, String)> = match (site.kind, target) {
+        (OverrideKind::Override, Some(target)) => Formals::load(&target, &RealFs)
+            .map(|formals| {
+                let name = call_package::display_name(&target);
+                let detail = format!("argument of `{}`", name);
+                formals
+                    .args
+                    .into_iter()
+                    .map(|(arg, _)| (arg, detail.clone()))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        (OverrideKind::OverrideAttrs, Some(target)) => RealFs
+            .load(&target)
+            .ok()
+            .and_then(|text| text.parse().ok())
+            .map(|file| {
+                let name = call_package::display_name(&target);
+                let detail = format!("attribute of the derivation in `{}`", name);
+                let attrs = overrides::derivation_attrs(&file);
+                attrs
+                    .into_iter()
+                    .map(|attr| (attr, detail.clone()))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        (_, None) => Vec::new(),
+    };
+
+    if names.is_empty() {
+        let query = site.query()?;
+        let output = values.evaluate_within(&query, OVERRIDE_EVAL_LIMIT);
+        let detail = "evaluated from `<nixpkgs>`".to_string();
+        let found = overrides::parse_names(&output.ok()?);
+        names = found
+            .into_iter()
+            .map(|name| (name, detail.clone()))
+            .collect();
+    }
+
+    let given = site.given();
+    let items: Vec<_> = names
+        .into_iter()
+        .filter(|(name, _)| !given.contains(&name.as_str()))
+        .map(|(name, detail)| CompletionItem {
+            label: name,
+            kind: Some(CompletionItemKind::Field),
+            detail: Some(detail),
+            ..CompletionItem::default()
+        })
+        .collect();
+
+    if items.is_empty() {
+        None
+    } else {
+        Some(CompletionResponse::Array(items))
+    }
+}
+
+/// Completes the attributes specific to the role of the document, e.g. `imports` in a module.
+fn get_role_completions(
+    document: &Document,
+    params: TextDocumentPositionParams,
+) -> Option<CompletionResponse> {
+    let offset = document.byte_index(&params.position).ok()?;
+    let file = document.source_file()?;
+    let items: Vec<_> = role::completions(document.role(), file, offset.to_usize())
+        .into_iter()
+        .map(|(name, detail)| CompletionItem {
+            label: name.to_owned(),
+            kind: Some(CompletionItemKind::Field),
+            detail: Some(detail.to_owned()),
+            ..CompletionItem::default()
+        })
+        .collect();
+
+    if items.is_empty() {
+        None
+    } else {
+        Some(CompletionResponse::Array(items))
+    }
+}
+
+/// Completes the keywords and punctuation which the parser would accept at the cursor.
+fn get_syntax_completions(
+    document: &Document,
+    params: TextDocumentPositionParams,
+) -> Option<CompletionResponse> {
+    let offset = document.byte_index(&params.position).ok()?;
+    let expected = expected_tokens(document.text(), offset.to_usize());
+
+    let mut items = Vec::new();
+    for token in &expected {
+        match token.text() {
+            Some(text) => items.push(CompletionItem {
+                label: text.to_owned(),
+                kind: Some(if token.is_keyword() {
+                    CompletionItemKind::Keyword
+                } else {
+                    CompletionItemKind::Operator
+                }),
+                ..CompletionItem::default()
+            }),
+            None if *token == Expected::Expr => {
+                items.extend(EXPR_KEYWORDS.iter().map(|keyword| CompletionItem {
+                    label: keyword.to_string(),
+                    kind: Some(CompletionItemKind::Keyword),
+                    ..CompletionItem::default()
+                }));
+                if let Some(file) = document.source_file() {
+                    items.extend(scope_completions(file, offset.to_usize()));
+                }
+                let globals = builtins::BUILTINS.iter().filter(|b| b.is_global());
+                items.extend(globals.map(builtin_completion));
+            }
+            None => {}
+        }
+    }
+
+    if items.is_empty() {
+        None
+    } else {
+        Some(CompletionResponse::Array(items))
+    }
+}
+
+/// Completes the members of `builtins` after `builtins.`.
+fn get_builtin_completions(
+    document: &Document,
+    params: &TextDocumentPositionParams,
+) -> Option<CompletionResponse> {
+    let offset = document.byte_index(&params.position).ok()?;
+    builtins::member_prefix(document.text(), offset.to_usize())?;
+    let items = builtins::BUILTINS
+        .iter()
+        .filter(|builtin| builtin.name != "builtins")
+        .map(builtin_completion)
+        .collect();
+    Some(CompletionResponse::Array(items))
+}
+
+fn builtin_completion(builtin: &builtins::Builtin) -> CompletionItem {
+    let kind = if builtin.is_function() {
+        CompletionItemKind::Function
+    } else {
+        CompletionItemKind::Constant
+    };
+    CompletionItem {
+        label: builtin.name.to_string(),
+        kind: Some(kind),
+        detail: Some(builtin.signature()),
+        documentation: Some(Documentation::MarkupContent(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: builtin.markdown(),
+        })),
+        ..CompletionItem::default()
+    }
+}
+
+/// Returns the names in scope at `offset`, innermost first.
+fn scope_completions(file: &SourceFile, offset: usize) -> impl Iterator<Item = CompletionItem> {
+    let names = resolve::names_at(file, offset);
+    names.into_iter().enumerate().map(|(i, bound)| {
+        let kind = match bound.kind {
+            NameKind::Variable => CompletionItemKind::Variable,
+            NameKind::Function => CompletionItemKind::Function,
+            NameKind::Set => CompletionItemKind::Module,
+        };
+        CompletionItem {
+            label: bound.name,
+            kind: Some(kind),
+            sort_text: Some(format!("{:04}", i)),
+            ..CompletionItem::default()
+        }
+    })
+}
+
+/// Returns the location of the store object a store path refers to, if it exists locally.
+fn get_store_path_definition(
+    document: &Document,
+    position: &Position,
+) -> Option<GotoDefinitionResponse> {
+    let offset = document.byte_index(position).ok()?;
+    let path = match hashes::find_at(document.source_file()?, offset.to_usize())? {
+        hashes::Found::StorePath { path, .. } => path,
+        hashes::Found::Hash { .. } => return None,
+    };
+
+    let path = Path::new(&path);
+    let uri = Url::from_file_path(path)
+        .ok()
+        .filter(|_| RealFs.exists(path))?;
+    Some(GotoDefinitionResponse::Scalar(Location::new(
+        uri,
+        Range::default(),
+    )))
+}
+
+/// Converts a location in an indexed file, or at the start of a file which is not, to a location
+/// of the client.
+fn workspace_location(workspace: &Workspace, location: workspace::Location) -> Option<Location> {
+    let uri = Url::from_file_path(&location.path).ok()?;
+    let indexed = match workspace.file(&location.path) {
+        Some(indexed) => indexed,
+        None => return Some(Location::new(uri, Range::default())),
+    };
+    let range = indexed.text.range(location.span).ok()?;
+    Some(Location::new(uri, range))
+}
+
+/// Returns the edit replacing every occurrence of a symbol in `report` with `new_name`.
+fn rename_edit(
+    document: &Document,
+    file: &SourceFile,
+    uri: Url,
+    report: &rename::Report,
+    new_name: &str,
+) -> WorkspaceEdit {
+    let edits = rename::edits(document.text(), file, report, new_name)
+        .into_iter()
+        .filter_map(|(span, new_text)| {
+            let range = document.range(span).ok()?;
+            Some(TextEdit::new(range, new_text))
+        })
+        .collect();
+
+    let mut changes = HashMap::new();
+    changes.insert(uri, edits);
+    WorkspaceEdit {
+        changes: Some(changes),
+        document_changes: None,
+    }
+}
+
+/// Jumps from a variable to where it is bound, or to the `with` expression it comes from.
+fn get_variable_definition(
+    document: &Document,
+    uri: &Url,
+    position: &Position,
+) -> Option<GotoDefinitionResponse> {
+    let offset = document.byte_index(position).ok()?;
+    let span = match resolve::definition_at(document.source_file()?, offset.to_usize())? {
+        Target::Binding(span) | Target::With(span) => span,
+    };
+    let range = document.range(span).ok()?;
+    Some(GotoDefinitionResponse::Scalar(Location::new(
+        uri.clone(),
+        range,
+    )))
+}
+
+fn get_hash_hover(document: &Document, params: TextDocumentPositionParams) -> Option<Hover> {
+    let offset = document.byte_index(&params.position).ok()?;
+    let found = hashes::find_at(document.source_file()?, offset.to_usize())?;
+    let span = match found {
+        hashes::Found::Hash { bind, .. } => bind.expr().span(),
+        hashes::Found::StorePath { span, .. } => span,
+    };
+
+    Some(Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: hashes::describe(&found, &RealFs)?,
+        }),
+        range: document.range(span).ok(),
+    })
+}
+
+/// Explains the hovered operator or keyword.
+fn get_glossary_hover(document: &Document, params: &TextDocumentPositionParams) -> Option<Hover> {
+    let offset = document.byte_index(&params.position).ok()?;
+    let (span, value) = glossary::describe_at(document.text(), offset.to_usize())?;
+
+    Some(Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value,
+        }),
+        range: document.range(span).ok(),
+    })
+}
+
+/// Shows the value of the hovered constant expression, e.g. `60 * 60`.
+fn get_constant_hover(document: &Document, params: &TextDocumentPositionParams) -> Option<Hover> {
+    let offset = document.byte_index(&params.position).ok()?;
+    let (span, value) = constant::at(document.source_file()?, offset.to_usize())?;
+
+    Some(Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: format!("Evaluates to `{}`", value),
+        }),
+        range: document.range(span).ok(),
+    })
+}
+
+/// Shows the attribute path from the root of the file to the hovered binding, how it is bound
+/// and its value if it is a literal.
+fn get_breadcrumb_hover(document: &Document, params: &TextDocumentPositionParams) -> Option<Hover> {
+    let offset = document.byte_index(&params.position).ok()?;
+    let file = document.source_file()?;
+    let binding = binding::at(file, offset.to_usize());
+    let path = match binding {
+        Some(ref binding) if !binding.path.is_empty() => binding.path.clone(),
+        _ => breadcrumb::path_at(file, offset.to_usize())?,
+    };
+
+    let mut value = format!("`{}`", path.join("."));
+    if let Some(binding) = binding {
+        let _ = write!(value, " ({})", binding.kind);
+        if let Some(literal) = binding.value {
+            let _ = write!(value, "\n\n```nix\n{}\n```", literal);
+        }
+    }
+    if params.text_document.uri.scheme() == "file" {
+        let command = preview_command(&params.text_document.uri, params.position);
+        let arguments = serde_json::to_string(&command.arguments).unwrap();
+        let _ = write!(
+            value,
+            "\n\n[Preview value](command:{}?{})",
+            command.command,
+            percent_encode(&arguments)
+        );
+    }
+
+    Some(Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value,
+        }),
+        range: None,
+    })
+}
+
+/// Returns the command previewing the value of the binding at `position`.
+fn preview_command(uri: &Url, position: Position) -> Command {
+    let params =
+        TextDocumentPositionParams::new(TextDocumentIdentifier::new(uri.clone()), position);
+    Command {
+        title: "Preview value".to_string(),
+        command: preview::COMMAND.to_string(),
+        arguments: Some(vec![serde_json::to_value(params).unwrap()]),
+    }
+}
+
+/// Percent-encodes `text` for use in the query of a `command:` link.
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
             }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
 
-            new_diags
+/// Returns the bindings of the attribute set a file evaluates to, looking through function
+/// declarations and `let`.
+fn result_binds(expr: &Expr) -> &[Bind] {
+    match *expr {
+        Expr::Set(ref set) => set.binds(),
+        Expr::Rec(ref rec) => rec.binds(),
+        Expr::LetIn(ref let_in) => result_binds(let_in.body()),
+        Expr::Paren(ref paren) => result_binds(paren.expr()),
+        Expr::FnDecl(ref decl) => match **decl {
+            ExprFnDecl::Simple(ref simple) => result_binds(simple.body()),
+            ExprFnDecl::Formals(ref formals) => result_binds(formals.body()),
+        },
+        _ => &[],
+    }
+}
+
+fn get_flake_hover(document: &Document, params: TextDocumentPositionParams) -> Option<Hover> {
+    let uri = &params.text_document.uri;
+    let (offset, flake) = get_flake(document, uri, &params.position)?;
+    let name = flake
+        .input_at(offset)
+        .or_else(|| flake.follows_at(offset))?;
+
+    let lock = LockFile::for_flake(&uri.to_file_path().ok()?, &RealFs)?;
+    let input = lock.inputs.get(name)?;
+
+    let mut lines = vec![format!("**{}**", name)];
+    if let Some(ref follows) = input.follows {
+        lines.push(format!("follows `{}`", follows));
+    } else {
+        lines.push(format!("locked: `{}`", input.reference));
+        if let Some(ref hash) = input.nar_hash {
+            lines.push(format!("narHash: `{}`", hash));
+        }
+        if let Some(modified) = input.last_modified {
+            lines.push(format!("lastModified: {}", modified));
         }
     }
+
+    Some(Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: lines.join("\n\n"),
+        }),
+        range: None,
+    })
+}
+
+/// Finds the declaration of the module option referred to at the requested position, searching
+/// the modules imported by the document and every other open document.
+fn find_option(snapshot: &Snapshot, params: &TextDocumentPositionParams) -> Option<options::Found> {
+    let uri = &params.text_document.uri;
+    let document = snapshot.document(uri)?;
+    let offset = document.byte_index(&params.position).ok()?;
+    let option = options::reference_at(document.source_file()?, offset.to_usize())?;
+
+    let mut roots: Vec<_> = snapshot
+        .documents()
+        .filter(|&(other, _)| other != uri)
+        .filter_map(|(other, _)| other.to_file_path().ok())
+        .collect();
+    roots.push(uri.to_file_path().ok()?);
+    options::find(&option, roots, snapshot)
+}
+
+fn get_option_hover(snapshot: &Snapshot, params: &TextDocumentPositionParams) -> Option<Hover> {
+    let found = find_option(snapshot, params)?;
+    let declaration = &found.declaration;
+
+    let mut lines = vec![format!("**{}**", declaration.path.join("."))];
+    if let Some((ref ty, _)) = declaration.type_expr {
+        lines.push(format!("type: `{}`", ty));
+    }
+    if let Some(ref default) = declaration.default {
+        lines.push(format!("default: `{}`", default));
+    }
+    let name = found.file.file_name()?.to_string_lossy();
+    match Url::from_file_path(&found.file) {
+        Ok(uri) => lines.push(format!("declared in [{}]({})", name, uri)),
+        Err(_) => lines.push(format!("declared in `{}`", name)),
+    }
+
+    Some(Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: lines.join("\n\n"),
+        }),
+        range: None,
+    })
 }
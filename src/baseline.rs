@@ -0,0 +1,103 @@
+//! Baselines of known problems, for adopting lints in existing code bases.
+//!
+//! A baseline records the problems present in a set of files when it was created. Checking
+//! against it only reports problems which are not recorded. Entries are matched by file, code
+//! and message rather than position, so unrelated edits which move a problem around do not make
+//! it count as new. If a file contains the same problem several times, each recorded entry
+//! excuses one occurrence.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use codespan::Files;
+use codespan_reporting::diagnostic::Diagnostic;
+use serde::{Deserialize, Serialize};
+
+/// A recorded problem.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Entry {
+    pub file: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    pub message: String,
+}
+
+impl Entry {
+    fn new(files: &Files, diagnostic: &Diagnostic) -> Self {
+        Entry {
+            file: files.name(diagnostic.primary_label.file_id).to_string(),
+            code: diagnostic.code.clone(),
+            message: diagnostic.message.clone(),
+        }
+    }
+}
+
+/// A set of known problems.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Baseline {
+    pub entries: Vec<Entry>,
+}
+
+impl Baseline {
+    /// Records the given problems.
+    pub fn new(files: &Files, diagnostics: &[Diagnostic]) -> Self {
+        let mut entries: Vec<_> = diagnostics.iter().map(|d| Entry::new(files, d)).collect();
+        entries.sort_by(|a, b| (&a.file, &a.message).cmp(&(&b.file, &b.message)));
+        Baseline { entries }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        serde_json::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut text = serde_json::to_string_pretty(self)?;
+        text.push('\n');
+        fs::write(path, text)
+    }
+
+    /// Removes the problems recorded in this baseline, returning the new ones.
+    pub fn filter(&self, files: &Files, diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+        let mut unused: Vec<_> = self.entries.iter().map(Some).collect();
+        diagnostics
+            .into_iter()
+            .filter(|diagnostic| {
+                let entry = Entry::new(files, diagnostic);
+                let found = unused.iter_mut().find(|e| e.is_some_and(|e| *e == entry));
+                found.and_then(Option::take).is_none()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use codespan::Span;
+    use codespan_reporting::diagnostic::Label;
+
+    use super::*;
+
+    #[test]
+    fn only_reports_new_problems() {
+        let mut files = Files::new();
+        let id = files.add("a.nix", "x x y");
+        let diagnostic = |start, name| {
+            let label = Label::new(id, Span::new(start, start + 1), "not found");
+            Diagnostic::new_error(format!("undefined variable `{}`", name), label)
+        };
+
+        let baseline = Baseline::new(&files, &[diagnostic(0, "x")]);
+        let json = serde_json::to_string(&baseline).unwrap();
+        assert_eq!(serde_json::from_str::<Baseline>(&json).unwrap(), baseline);
+
+        let current = vec![diagnostic(2, "x"), diagnostic(0, "x"), diagnostic(4, "y")];
+        let new = baseline.filter(&files, current);
+        let messages: Vec<_> = new.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            ["undefined variable `x`", "undefined variable `y`"]
+        );
+    }
+}
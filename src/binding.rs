@@ -0,0 +1,209 @@
+//! The binding at a position, described on hover.
+//!
+//! Hovering the name or the value of a binding shows the attribute path leading to it, as found by
+//! [`breadcrumb::path_at`], along with how the name is bound and, if the value is a literal, the
+//! value itself. Function formals and inherited names are described too; a formal's value is its
+//! default, if any.
+
+use std::fmt::{self, Display, Formatter};
+
+use codespan::Span;
+use nix_parser::ast::tokens::Ident;
+use nix_parser::ast::visit::path_at;
+use nix_parser::ast::{Bind, BindSimple, Expr, ExprFnDecl, SourceFile, StringFragment};
+use nix_parser::HasSpan;
+
+use crate::breadcrumb;
+
+/// How a name is bound.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Kind {
+    /// An attribute of a set, e.g. `{ name = ...; }`.
+    Attribute,
+    /// A variable bound by `let`.
+    Let,
+    /// A parameter of a function, e.g. `{ name ? ... }: ...` or `name: ...`.
+    Formal,
+    /// A name bound by `inherit`.
+    Inherited,
+}
+
+impl Display for Kind {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match *self {
+            Kind::Attribute => write!(fmt, "attribute"),
+            Kind::Let => write!(fmt, "`let` binding"),
+            Kind::Formal => write!(fmt, "function formal"),
+            Kind::Inherited => write!(fmt, "inherited name"),
+        }
+    }
+}
+
+/// A description of the binding at a position.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Binding {
+    /// The attribute path leading to the binding, or just its name for formals and variables.
+    pub path: Vec<String>,
+    pub kind: Kind,
+    /// The value bound, if it is a literal.
+    pub value: Option<String>,
+}
+
+/// Describes the innermost binding whose name or value contains `offset`.
+pub fn at(file: &SourceFile, offset: usize) -> Option<Binding> {
+    let path = path_at(file.expr(), offset);
+    let expr = *path.last()?;
+
+    if let Some(binding) = formal_at(expr, offset) {
+        return Some(binding);
+    }
+
+    if let Some(binds) = breadcrumb::binds(expr) {
+        for bind in binds {
+            let names = match *bind {
+                Bind::Simple(ref simple) if contains(simple.attr().span(), offset) => {
+                    return Some(simple_binding(file, offset, expr, simple));
+                }
+                Bind::Inherit(ref inherit) => inherit.names(),
+                Bind::InheritExpr(ref inherit) => inherit.names(),
+                _ => continue,
+            };
+            if let Some(name) = names.iter().find(|name| contains(name.span(), offset)) {
+                let mut path = match *expr {
+                    Expr::Let(_) | Expr::LetIn(_) => Vec::new(),
+                    _ => breadcrumb::path_at(file, offset).unwrap_or_default(),
+                };
+                path.push(name.to_string());
+                return Some(Binding {
+                    path,
+                    kind: Kind::Inherited,
+                    value: None,
+                });
+            }
+        }
+    }
+
+    for pair in path.windows(2).rev() {
+        let (parent, value) = (pair[0], pair[1]);
+        let simple = breadcrumb::binds(parent).and_then(|binds| {
+            binds.iter().find_map(|bind| match *bind {
+                Bind::Simple(ref simple) if std::ptr::eq(simple.expr(), value) => Some(simple),
+                _ => None,
+            })
+        });
+        if let Some(simple) = simple {
+            return Some(simple_binding(file, offset, parent, simple));
+        }
+    }
+
+    None
+}
+
+/// Describes the formal of the function `expr` whose name contains `offset`.
+fn formal_at(expr: &Expr, offset: usize) -> Option<Binding> {
+    let formal = |name: &Ident, default: Option<&Expr>| Binding {
+        path: vec![name.to_string()],
+        kind: Kind::Formal,
+        value: default.and_then(literal),
+    };
+
+    match *expr {
+        Expr::FnDecl(ref decl) => match **decl {
+            ExprFnDecl::Simple(ref simple) if contains(simple.name().span(), offset) => {
+                Some(formal(simple.name(), None))
+            }
+            ExprFnDecl::Formals(ref formals) => {
+                let extra = formals.extra().filter(|name| contains(name.span(), offset));
+                if let Some(name) = extra {
+                    return Some(formal(name, None));
+                }
+                formals
+                    .formals()
+                    .iter()
+                    .find(|f| contains(f.name().span(), offset))
+                    .map(|f| formal(f.name(), f.default()))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn simple_binding(file: &SourceFile, offset: usize, parent: &Expr, simple: &BindSimple) -> Binding {
+    let kind = match *parent {
+        Expr::Let(_) | Expr::LetIn(_) => Kind::Let,
+        _ => Kind::Attribute,
+    };
+    Binding {
+        path: breadcrumb::path_at(file, offset).unwrap_or_default(),
+        kind,
+        value: literal(simple.expr()),
+    }
+}
+
+/// Returns the source form of `expr` if it is a literal or a string without interpolations.
+fn literal(expr: &Expr) -> Option<String> {
+    match *expr {
+        Expr::Literal(ref literal) => Some(literal.to_string()),
+        Expr::String(ref string) => {
+            let constant = string
+                .fragments()
+                .iter()
+                .all(|fragment| matches!(*fragment, StringFragment::Literal(..)));
+            Some(string.to_string()).filter(|_| constant)
+        }
+        Expr::Paren(ref paren) => literal(paren.expr()),
+        _ => None,
+    }
+}
+
+fn contains(span: Span, offset: usize) -> bool {
+    span.start().to_usize() <= offset && offset <= span.end().to_usize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_bindings() {
+        let source = r#"{ stdenv, jobs ? 4 }:
+let
+  version = "1.0";
+  platforms = [ ];
+in {
+  inherit version;
+  meta = { maintainer = null; description = "hello ${version}"; };
+  build = n: n;
+}
+"#;
+        let file: SourceFile = source.parse().unwrap();
+        let describe = |needle: &str| {
+            let binding = at(&file, source.find(needle).unwrap() + 1)?;
+            let value = binding.value.unwrap_or_default();
+            Some(format!(
+                "{} {:?} {}",
+                binding.path.join("."),
+                binding.kind,
+                value
+            ))
+        };
+
+        assert_eq!(describe("stdenv").unwrap(), "stdenv Formal ");
+        assert_eq!(describe("jobs").unwrap(), "jobs Formal 4");
+        assert_eq!(describe("version =").unwrap(), "version Let \"1.0\"");
+        assert_eq!(describe("\"1.0").unwrap(), "version Let \"1.0\"");
+        assert_eq!(describe("platforms").unwrap(), "platforms Let ");
+        assert_eq!(describe("version;").unwrap(), "version Inherited ");
+        assert_eq!(
+            describe("maintainer").unwrap(),
+            "meta.maintainer Attribute null"
+        );
+        assert_eq!(
+            describe("description").unwrap(),
+            "meta.description Attribute "
+        );
+        assert_eq!(describe("n: n").unwrap(), "n Formal ");
+        assert_eq!(describe(" n;").unwrap(), "build Attribute ");
+    }
+}
@@ -0,0 +1,120 @@
+//! The attribute path leading to a position, shown on hover to keep track of deep nesting.
+//!
+//! The path is collected by walking up from the innermost expression at the position, prepending
+//! the attribute path of every binding whose value contains it. Functions, applications and other
+//! expressions in between are looked through, so a flake's `outputs = { self }: { ... }` and a
+//! package's `mkDerivation { ... }` contribute nothing. A `let` binding ends the path, which then
+//! starts at the name it binds.
+
+use nix_parser::ast::visit;
+use nix_parser::ast::{Bind, Expr, SourceFile};
+use nix_parser::HasSpan;
+
+/// Returns the segments of the attribute path from the root of `file` to `offset`, as written in
+/// the source, or `None` if `offset` is not within the value or name of any binding.
+pub fn path_at(file: &SourceFile, offset: usize) -> Option<Vec<String>> {
+    let enclosing = visit::path_at(file.expr(), offset);
+    let current = *enclosing.last()?;
+    let mut path = Vec::new();
+
+    // The position may be on the name of a binding rather than within its value.
+    if let Some(binds) = binds(current) {
+        let named = binds.iter().find_map(|bind| match *bind {
+            Bind::Simple(ref simple) if contains(simple.attr().span(), offset) => Some(simple),
+            _ => None,
+        });
+        if let Some(simple) = named {
+            let segments = simple.attr().segments();
+            let hovered = segments
+                .iter()
+                .position(|segment| contains(segment.span(), offset))
+                .unwrap_or(segments.len() - 1);
+            path.extend(segments[..=hovered].iter().map(ToString::to_string));
+            if is_let(current) {
+                return Some(path);
+            }
+        }
+    }
+
+    for pair in enclosing.windows(2).rev() {
+        let (parent, expr) = (pair[0], pair[1]);
+        if let Some(segments) = binding_of(parent, expr) {
+            path.splice(0..0, segments);
+            if is_let(parent) {
+                break;
+            }
+        }
+    }
+
+    if path.is_empty() {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+/// Returns the attribute path of the binding in `parent` whose value is `expr`.
+fn binding_of(parent: &Expr, expr: &Expr) -> Option<Vec<String>> {
+    let simple = binds(parent)?.iter().find_map(|bind| match *bind {
+        Bind::Simple(ref simple) if std::ptr::eq(simple.expr(), expr) => Some(simple),
+        _ => None,
+    })?;
+    Some(
+        simple
+            .attr()
+            .segments()
+            .iter()
+            .map(ToString::to_string)
+            .collect(),
+    )
+}
+
+/// Returns the bindings of a set or `let` expression.
+pub fn binds(expr: &Expr) -> Option<&[Bind]> {
+    match *expr {
+        Expr::Set(ref set) => Some(set.binds()),
+        Expr::Rec(ref rec) => Some(rec.binds()),
+        Expr::Let(ref e) => Some(e.binds()),
+        Expr::LetIn(ref e) => Some(e.binds()),
+        _ => None,
+    }
+}
+
+fn is_let(expr: &Expr) -> bool {
+    matches!(*expr, Expr::Let(_) | Expr::LetIn(_))
+}
+
+fn contains(span: codespan::Span, offset: usize) -> bool {
+    span.start().to_usize() <= offset && offset <= span.end().to_usize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_paths_through_functions() {
+        let source = r#"{
+  outputs = { self }: {
+    packages.x86_64-linux.hello = mkDerivation {
+      meta = { description = "Says hello"; };
+    };
+  };
+  x = let y = { z = 1; }; in y;
+}"#;
+        let file: SourceFile = source.parse().unwrap();
+        let path = |at: &str| path_at(&file, source.find(at).unwrap()).map(|p| p.join("."));
+
+        assert_eq!(
+            path("Says").unwrap(),
+            "outputs.packages.x86_64-linux.hello.meta.description"
+        );
+        assert_eq!(
+            path("description").unwrap(),
+            "outputs.packages.x86_64-linux.hello.meta.description"
+        );
+        assert_eq!(path("x86_64").unwrap(), "outputs.packages.x86_64-linux");
+        assert_eq!(path("z =").unwrap(), "y.z");
+        assert_eq!(path("{\n"), None);
+    }
+}
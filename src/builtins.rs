@@ -0,0 +1,593 @@
+//! The builtin functions and constants of the language, offered as completions.
+//!
+//! Parameters are named as in the builtins chapter of the Nix manual, which the documentation of
+//! each entry summarises. Builtins listed in [`GLOBALS`](crate::resolve::GLOBALS) are also offered
+//! without `builtins.` wherever an expression is expected.
+
+use crate::resolve::GLOBALS;
+
+const REFERENCE: &str = "https://nix.dev/manual/nix/stable/language/builtins";
+
+/// The documentation of a builtin.
+#[derive(Debug)]
+pub struct Builtin {
+    pub name: &'static str,
+    /// The names of the parameters of a function, or none for a constant.
+    pub params: &'static [&'static str],
+    pub doc: &'static str,
+}
+
+impl Builtin {
+    pub fn is_function(&self) -> bool {
+        !self.params.is_empty()
+    }
+
+    /// Returns whether the builtin is in scope without `builtins.`.
+    pub fn is_global(&self) -> bool {
+        GLOBALS.contains(&self.name)
+    }
+
+    /// Returns how the builtin is called, e.g. `builtins.elemAt xs n`.
+    pub fn signature(&self) -> String {
+        let mut signature = format!("builtins.{}", self.name);
+        for param in self.params {
+            signature.push(' ');
+            signature.push_str(param);
+        }
+        signature
+    }
+
+    /// Returns the documentation as Markdown, linking to the manual.
+    pub fn markdown(&self) -> String {
+        let anchor = format!("{}#builtins-{}", REFERENCE, self.name);
+        format!("{}\n\n[Nix manual]({})", self.doc, anchor)
+    }
+}
+
+const fn function(
+    name: &'static str,
+    params: &'static [&'static str],
+    doc: &'static str,
+) -> Builtin {
+    Builtin { name, params, doc }
+}
+
+const fn constant(name: &'static str, doc: &'static str) -> Builtin {
+    Builtin {
+        name,
+        params: &[],
+        doc,
+    }
+}
+
+/// All builtins, by name.
+pub const BUILTINS: &[Builtin] = &[
+    function(
+        "abort",
+        &["s"],
+        "Aborts evaluation with the error message `s`, which cannot be caught by `tryEval`.",
+    ),
+    function(
+        "add",
+        &["e1", "e2"],
+        "Returns the sum of the numbers `e1` and `e2`.",
+    ),
+    function(
+        "all",
+        &["pred", "list"],
+        "Returns `true` if `pred` returns `true` for every element of `list`.",
+    ),
+    function(
+        "any",
+        &["pred", "list"],
+        "Returns `true` if `pred` returns `true` for at least one element of `list`.",
+    ),
+    function(
+        "attrNames",
+        &["set"],
+        "Returns the names of the attributes of `set`, sorted alphabetically.",
+    ),
+    function(
+        "attrValues",
+        &["set"],
+        "Returns the values of the attributes of `set`, in the order of their sorted names.",
+    ),
+    function(
+        "baseNameOf",
+        &["s"],
+        "Returns the last component of the path `s`, e.g. `baseNameOf ./foo/bar` is `\"bar\"`.",
+    ),
+    function(
+        "bitAnd",
+        &["e1", "e2"],
+        "Returns the bitwise AND of the integers `e1` and `e2`.",
+    ),
+    function(
+        "bitOr",
+        &["e1", "e2"],
+        "Returns the bitwise OR of the integers `e1` and `e2`.",
+    ),
+    function(
+        "bitXor",
+        &["e1", "e2"],
+        "Returns the bitwise XOR of the integers `e1` and `e2`.",
+    ),
+    constant(
+        "builtins",
+        "The set of all builtins, including those which are also in scope directly.",
+    ),
+    function(
+        "catAttrs",
+        &["attr", "list"],
+        "Returns the values of the attribute `attr` of the sets in `list` which have it.",
+    ),
+    function(
+        "ceil",
+        &["double"],
+        "Rounds the number `double` up to the nearest integer.",
+    ),
+    function(
+        "compareVersions",
+        &["s1", "s2"],
+        "Compares two version strings, returning `-1`, `0` or `1` if `s1` is older than, the \
+         same as or newer than `s2`.",
+    ),
+    function(
+        "concatLists",
+        &["lists"],
+        "Concatenates a list of lists into a single list.",
+    ),
+    function(
+        "concatMap",
+        &["f", "list"],
+        "Applies `f` to each element of `list` and concatenates the resulting lists.",
+    ),
+    function(
+        "concatStringsSep",
+        &["separator", "list"],
+        "Concatenates the strings in `list`, with `separator` between each of them.",
+    ),
+    constant(
+        "currentSystem",
+        "The platform Nix is evaluating on, e.g. `\"x86_64-linux\"`. Not available in pure \
+         evaluation mode.",
+    ),
+    constant(
+        "currentTime",
+        "The time evaluation started, in seconds since the epoch. Not available in pure \
+         evaluation mode.",
+    ),
+    function(
+        "deepSeq",
+        &["e1", "e2"],
+        "Evaluates `e1` completely, including nested lists and sets, then returns `e2`.",
+    ),
+    function(
+        "derivation",
+        &["attrs"],
+        "Describes a build: `attrs` needs at least `name`, `system` and `builder`, and the \
+         result is a set whose `outPath` is the store path the build produces.",
+    ),
+    function(
+        "dirOf",
+        &["s"],
+        "Returns the directory part of the path `s`, e.g. `dirOf ./foo/bar` is `./foo`.",
+    ),
+    function(
+        "div",
+        &["e1", "e2"],
+        "Returns the quotient of the numbers `e1` and `e2`.",
+    ),
+    function(
+        "elem",
+        &["x", "xs"],
+        "Returns `true` if `x` is equal to an element of the list `xs`.",
+    ),
+    function(
+        "elemAt",
+        &["xs", "n"],
+        "Returns the element at index `n` of the list `xs`, counting from 0.",
+    ),
+    constant("false", "The boolean false."),
+    function(
+        "fetchGit",
+        &["args"],
+        "Fetches a Git repository into the store, given its URL or a set with `url` and \
+         optionally `rev`, `ref`, `submodules` and more.",
+    ),
+    function(
+        "fetchMercurial",
+        &["args"],
+        "Fetches a Mercurial repository into the store, given its URL or a set with `url` and \
+         optionally `rev`.",
+    ),
+    function(
+        "fetchTarball",
+        &["args"],
+        "Downloads and unpacks a tarball into the store, given its URL or a set with `url` and \
+         optionally `sha256`.",
+    ),
+    function(
+        "fetchTree",
+        &["input"],
+        "Fetches a file system tree or a flake input into the store, returning its `outPath` \
+         and metadata.",
+    ),
+    function(
+        "fetchurl",
+        &["url"],
+        "Downloads a file into the store, returning its store path.",
+    ),
+    function(
+        "filter",
+        &["f", "list"],
+        "Returns the elements of `list` for which `f` returns `true`.",
+    ),
+    function(
+        "filterSource",
+        &["e1", "e2"],
+        "Copies the path `e2` to the store, keeping only the files for which `e1 path type` \
+         returns `true`.",
+    ),
+    function(
+        "floor",
+        &["double"],
+        "Rounds the number `double` down to the nearest integer.",
+    ),
+    function(
+        "foldl'",
+        &["op", "nul", "list"],
+        "Reduces `list` from the left with the binary function `op`, starting from `nul` and \
+         forcing each intermediate result.",
+    ),
+    function(
+        "fromJSON",
+        &["e"],
+        "Parses the JSON string `e` into a Nix value.",
+    ),
+    function(
+        "fromTOML",
+        &["e"],
+        "Parses the TOML string `e` into a Nix value.",
+    ),
+    function(
+        "functionArgs",
+        &["f"],
+        "Returns a set mapping the formals of the function `f` to whether they have a default.",
+    ),
+    function(
+        "genList",
+        &["generator", "length"],
+        "Returns the list `[ (generator 0) ... (generator (length - 1)) ]`.",
+    ),
+    function(
+        "genericClosure",
+        &["attrset"],
+        "Computes the closure of `startSet` under `operator`, given as attributes of \
+         `attrset`, identifying elements by their `key` attribute.",
+    ),
+    function(
+        "getAttr",
+        &["s", "set"],
+        "Returns the attribute named `s` of `set`, like `set.${s}`.",
+    ),
+    function(
+        "getEnv",
+        &["s"],
+        "Returns the value of the environment variable `s`, or `\"\"` if it is not set. Always \
+         `\"\"` in pure evaluation mode.",
+    ),
+    function(
+        "getFlake",
+        &["args"],
+        "Fetches the flake with the given reference and returns its outputs.",
+    ),
+    function(
+        "groupBy",
+        &["f", "list"],
+        "Groups the elements of `list` into a set of lists, keyed by the string `f` returns for \
+         each.",
+    ),
+    function(
+        "hasAttr",
+        &["s", "set"],
+        "Returns `true` if `set` has an attribute named `s`, like `set ? ${s}`.",
+    ),
+    function(
+        "hashFile",
+        &["type", "p"],
+        "Returns the base-16 hash of the file at `p`, using the algorithm `type`, e.g. \
+         `\"sha256\"`.",
+    ),
+    function(
+        "hashString",
+        &["type", "s"],
+        "Returns the base-16 hash of the string `s`, using the algorithm `type`, e.g. \
+         `\"sha256\"`.",
+    ),
+    function("head", &["list"], "Returns the first element of `list`."),
+    function(
+        "import",
+        &["path"],
+        "Evaluates the Nix expression in the file at `path`, or in its `default.nix` if it is a \
+         directory.",
+    ),
+    function(
+        "intersectAttrs",
+        &["e1", "e2"],
+        "Returns the attributes of `e2` whose names are also attributes of `e1`.",
+    ),
+    function(
+        "isAttrs",
+        &["e"],
+        "Returns `true` if `e` is an attribute set.",
+    ),
+    function("isBool", &["e"], "Returns `true` if `e` is a boolean."),
+    function("isFloat", &["e"], "Returns `true` if `e` is a float."),
+    function("isFunction", &["e"], "Returns `true` if `e` is a function."),
+    function("isInt", &["e"], "Returns `true` if `e` is an integer."),
+    function("isList", &["e"], "Returns `true` if `e` is a list."),
+    function(
+        "isNull",
+        &["e"],
+        "Returns `true` if `e` is `null`. Deprecated in favour of `e == null`.",
+    ),
+    function("isPath", &["e"], "Returns `true` if `e` is a path."),
+    function("isString", &["e"], "Returns `true` if `e` is a string."),
+    constant(
+        "langVersion",
+        "The version of the Nix language, as an integer.",
+    ),
+    function(
+        "length",
+        &["e"],
+        "Returns the number of elements of the list `e`.",
+    ),
+    function(
+        "lessThan",
+        &["e1", "e2"],
+        "Returns `true` if `e1` is less than `e2`, like `e1 < e2`.",
+    ),
+    function(
+        "listToAttrs",
+        &["e"],
+        "Builds a set from a list of sets with `name` and `value` attributes. The first of \
+         several elements with the same name wins.",
+    ),
+    function(
+        "map",
+        &["f", "list"],
+        "Applies `f` to each element of `list`.",
+    ),
+    function(
+        "mapAttrs",
+        &["f", "attrset"],
+        "Applies `f name value` to each attribute of `attrset`, keeping the names.",
+    ),
+    function(
+        "match",
+        &["regex", "str"],
+        "Returns the capture groups if the extended regular expression `regex` matches the \
+         whole of `str`, or `null` otherwise.",
+    ),
+    function(
+        "mul",
+        &["e1", "e2"],
+        "Returns the product of the numbers `e1` and `e2`.",
+    ),
+    constant(
+        "nixPath",
+        "The search path used to look up `<...>` paths, as a list of sets with `path` and \
+         `prefix`.",
+    ),
+    constant(
+        "nixVersion",
+        "The version of Nix evaluating the expression, as a string.",
+    ),
+    constant("null", "The null value."),
+    function(
+        "parseDrvName",
+        &["s"],
+        "Splits the package name `s` into a set with `name` and `version`, e.g. \
+         `\"nix-0.12pre12876\"` into `nix` and `0.12pre12876`.",
+    ),
+    function(
+        "partition",
+        &["pred", "list"],
+        "Splits `list` into a set with the elements for which `pred` returns `true` as `right` \
+         and the others as `wrong`.",
+    ),
+    function(
+        "path",
+        &["args"],
+        "Copies a path to the store like a path literal would, with optional `name`, `filter`, \
+         `recursive` and `sha256` attributes.",
+    ),
+    function(
+        "pathExists",
+        &["path"],
+        "Returns `true` if `path` exists on the file system.",
+    ),
+    function(
+        "placeholder",
+        &["output"],
+        "Returns a placeholder for the store path of the output `output` of the derivation \
+         being built, replaced with the actual path at build time.",
+    ),
+    function(
+        "readDir",
+        &["path"],
+        "Returns a set mapping the entries of the directory `path` to their type, e.g. \
+         `\"regular\"` or `\"directory\"`.",
+    ),
+    function(
+        "readFile",
+        &["path"],
+        "Returns the contents of the file `path` as a string.",
+    ),
+    function(
+        "removeAttrs",
+        &["set", "list"],
+        "Returns `set` without the attributes named in `list`.",
+    ),
+    function(
+        "replaceStrings",
+        &["from", "to", "s"],
+        "Replaces each occurrence of a string in `from` by the string at the same position in \
+         `to` in `s`.",
+    ),
+    function(
+        "scopedImport",
+        &["scope", "path"],
+        "Imports `path` like `import`, with the attributes of `scope` in scope.",
+    ),
+    function(
+        "seq",
+        &["e1", "e2"],
+        "Evaluates `e1` to weak head normal form, then returns `e2`.",
+    ),
+    function(
+        "sort",
+        &["comparator", "list"],
+        "Sorts `list` with `comparator a b` returning `true` if `a` should come before `b`. The \
+         sort is stable.",
+    ),
+    function(
+        "split",
+        &["regex", "str"],
+        "Splits `str` at the matches of the extended regular expression `regex`, returning the \
+         strings in between and lists of the capture groups of each match.",
+    ),
+    function(
+        "splitVersion",
+        &["s"],
+        "Splits the version string `s` into its components, as compared by `compareVersions`.",
+    ),
+    constant(
+        "storeDir",
+        "The directory of the Nix store, usually `\"/nix/store\"`.",
+    ),
+    function(
+        "storePath",
+        &["path"],
+        "Returns the store path `path` with a dependency on it, without copying it.",
+    ),
+    function(
+        "stringLength",
+        &["e"],
+        "Returns the length of the string `e` in bytes.",
+    ),
+    function(
+        "sub",
+        &["e1", "e2"],
+        "Returns the difference of the numbers `e1` and `e2`.",
+    ),
+    function(
+        "substring",
+        &["start", "len", "s"],
+        "Returns the `len` bytes of `s` from byte `start`, or fewer at the end of `s`.",
+    ),
+    function(
+        "tail",
+        &["list"],
+        "Returns `list` without its first element.",
+    ),
+    function(
+        "throw",
+        &["s"],
+        "Throws an error with the message `s`, which `tryEval` can catch.",
+    ),
+    function(
+        "toFile",
+        &["name", "s"],
+        "Writes the string `s` to a file named `name` in the store, returning its path.",
+    ),
+    function("toJSON", &["e"], "Returns `e` serialised as a JSON string."),
+    function(
+        "toPath",
+        &["s"],
+        "Converts the absolute path string `s` to a path. Deprecated in favour of \
+         `/. + s`.",
+    ),
+    function(
+        "toString",
+        &["e"],
+        "Converts `e` to a string. Paths are not copied to the store, sets need `outPath` or \
+         `__toString`, and lists are joined with spaces.",
+    ),
+    function("toXML", &["e"], "Returns `e` serialised as an XML string."),
+    function(
+        "trace",
+        &["e1", "e2"],
+        "Prints `e1` to standard error, then returns `e2`.",
+    ),
+    constant("true", "The boolean true."),
+    function(
+        "tryEval",
+        &["e"],
+        "Evaluates `e`, returning `{ success = true; value = e; }`, or `{ success = false; \
+         value = false; }` if it throws or fails an assertion.",
+    ),
+    function(
+        "typeOf",
+        &["e"],
+        "Returns the type of `e` as a string, e.g. `\"int\"`, `\"set\"` or `\"lambda\"`.",
+    ),
+    function(
+        "unsafeDiscardStringContext",
+        &["s"],
+        "Returns `s` without its string context, dropping the dependencies it carries.",
+    ),
+    function(
+        "warn",
+        &["msg", "e"],
+        "Prints the warning `msg`, then returns `e`.",
+    ),
+    function(
+        "zipAttrsWith",
+        &["f", "list"],
+        "Merges the sets in `list`, applying `f name values` to the list of values of each \
+         name.",
+    ),
+];
+
+/// Returns the prefix of the builtin being typed if `offset` follows `builtins.` in `text`, e.g.
+/// `"conc"` for `builtins.conc|`.
+pub fn member_prefix(text: &str, offset: usize) -> Option<&str> {
+    let before = text.get(..offset)?;
+    let start = before
+        .rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '\'' || c == '-'))
+        .map_or(0, |i| i + 1);
+    let base = before[..start].strip_suffix('.')?;
+    let is_builtins = base.ends_with("builtins")
+        && !base[..base.len() - "builtins".len()]
+            .ends_with(|c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    Some(&before[start..]).filter(|_| is_builtins)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globals_are_documented() {
+        for name in GLOBALS {
+            let documented = BUILTINS.iter().any(|builtin| builtin.name == *name);
+            assert!(documented, "`{}` is not documented", name);
+        }
+        let mut names: Vec<_> = BUILTINS.iter().map(|builtin| builtin.name).collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), BUILTINS.len());
+    }
+
+    #[test]
+    fn finds_member_prefix() {
+        let text = "[ builtins.conc (builtins.) foo.builtins.x ]";
+        let offset = |needle: &str| text.find(needle).unwrap() + needle.len();
+        assert_eq!(member_prefix(text, offset("builtins.conc")), Some("conc"));
+        assert_eq!(member_prefix(text, offset("(builtins.")), Some(""));
+        assert_eq!(member_prefix(text, offset("foo.builtins.x")), None);
+        assert_eq!(member_prefix(text, offset("[ ")), None);
+    }
+}
@@ -0,0 +1,354 @@
+//! Updating a package to a new version: its `version`, the revision its sources are fetched at,
+//! and the hashes which change with them.
+//!
+//! The `version` binding of the set a file evaluates to is set to the new version. Each top-level
+//! binding whose value is a call to a fetcher, such as `src = fetchFromGitHub { ... }`, has its
+//! `rev` or `tag` updated unless it already refers to `version`, and its hash replaced. Hashes of
+//! dependencies fetched from the sources, such as `cargoHash` or `vendorHash`, are replaced too.
+//!
+//! A replaced hash is the fake hash of `lib.fakeHash`, so that building the package reports the
+//! correct one, unless the sources can be prefetched: `fetchurl`, `fetchzip`, `fetchTarball` and
+//! `fetchFromGitHub` with constant arguments are prefetched with `nix-prefetch-url` on request.
+//!
+//! The changes are made with the structured editing functions of the parser, so the rest of the
+//! file keeps its layout. They are offered by the `nix.bumpVersion` command, which returns them as
+//! a workspace edit for review, and the `bump` subcommand.
+
+use std::collections::HashMap;
+use std::process::Command;
+
+use codespan::Span;
+use nix_parser::ast::rewrite::{self, RewriteError};
+use nix_parser::ast::{Bind, Expr, ExprString, SourceFile, StringFragment};
+
+use crate::hashes::{Algo, Hash};
+
+/// The command which updates the version of a package.
+pub const COMMAND: &str = "nix.bumpVersion";
+
+/// The hash `lib.fakeHash` evaluates to.
+pub const FAKE_HASH: &str = "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+const FETCHERS: &[&str] = &[
+    "fetchCrate",
+    "fetchFromGitHub",
+    "fetchFromGitLab",
+    "fetchFromSourcehut",
+    "fetchPypi",
+    "fetchTarball",
+    "fetchgit",
+    "fetchurl",
+    "fetchzip",
+];
+
+/// The attributes holding the hash of the sources a fetcher downloads.
+const HASHES: &[&str] = &["hash", "outputHash", "sha256", "sha512"];
+
+/// The attributes holding the hash of dependencies which are fetched based on the sources.
+const DEPENDENCY_HASHES: &[&str] = &[
+    "cargoHash",
+    "cargoSha256",
+    "npmDepsHash",
+    "vendorHash",
+    "vendorSha256",
+];
+
+/// Downloads a URL into the store, unpacking it if the flag is set, and returns its hash in SRI
+/// form.
+pub type Prefetch<'a> = &'a dyn Fn(&str, bool) -> Option<String>;
+
+/// A change of a single value.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Change {
+    /// The attribute path of the value, e.g. `src.hash`.
+    pub attr: String,
+    pub span: Span,
+    pub old: String,
+    pub new: String,
+}
+
+/// Returns the changes updating the package defined by `file` to `version`, prefetching the
+/// hashes of its sources with `prefetch` if given.
+pub fn bump(
+    source: &str,
+    file: &SourceFile,
+    version: &str,
+    prefetch: Option<Prefetch>,
+) -> Result<Vec<Change>, String> {
+    let binds = match rewrite::find(file, &[]) {
+        Ok(Expr::Set(ref set)) => set.binds(),
+        Ok(Expr::Rec(ref rec)) => rec.binds(),
+        _ => return Err("the file does not evaluate to an attribute set".to_owned()),
+    };
+
+    let mut vars: HashMap<&str, String> = binds
+        .iter()
+        .filter_map(|bind| match *bind {
+            Bind::Simple(ref simple) => match (simple.attr().segments(), simple.expr()) {
+                ([segment], Expr::String(ref string)) => {
+                    Some((segment.name()?, string.as_literal()?.into_owned()))
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    let old_version = vars.insert("version", version.to_owned());
+
+    let mut changes = Vec::new();
+    let mut change = |attr: &[&str], new: String| -> Result<(), RewriteError> {
+        let edit = rewrite::set_attr_value(file, attr, &new)?;
+        let old = &source[edit.span.start().to_usize()..edit.span.end().to_usize()];
+        if old != new {
+            changes.push(Change {
+                attr: attr.join("."),
+                span: edit.span,
+                old: old.to_owned(),
+                new,
+            });
+        }
+        Ok(())
+    };
+
+    if old_version.is_some() {
+        change(&["version"], quote(version)).map_err(|e| e.to_string())?;
+    }
+
+    for bind in binds {
+        let (name, fetcher) = match *bind {
+            Bind::Simple(ref simple) => match (simple.attr().segments(), fetcher(simple.expr())) {
+                ([segment], Some(fetcher)) => match segment.name() {
+                    Some(name) => (name, fetcher),
+                    None => continue,
+                },
+                _ => continue,
+            },
+            _ => continue,
+        };
+
+        let mut args = vars.clone();
+        for key in &["rev", "tag"] {
+            let old = match rewrite::find(file, &[name, key]) {
+                Ok(Expr::String(ref string)) => string,
+                _ => continue,
+            };
+            if let Some(old) = old.as_literal() {
+                let new = match old_version {
+                    Some(ref previous) if !previous.is_empty() && old.contains(&**previous) => {
+                        old.replace(&**previous, version)
+                    }
+                    _ if old.starts_with('v') => format!("v{}", version),
+                    _ => version.to_owned(),
+                };
+                let _ = change(&[name, key], quote(&new));
+                args.insert(key, new);
+            } else if let Some(text) = render(old, &args) {
+                args.insert(key, text);
+            }
+        }
+
+        let url = prefetch.and_then(|_| url(file, name, fetcher, &args));
+        let hash = match (prefetch, url) {
+            (Some(prefetch), Some((url, unpack))) => prefetch(&url, unpack),
+            _ => None,
+        };
+        let hash = hash.unwrap_or_else(|| FAKE_HASH.to_owned());
+        for key in HASHES {
+            if let Ok(Expr::String(_)) = rewrite::find(file, &[name, key]) {
+                let _ = change(&[name, key], quote(&hash));
+            }
+        }
+    }
+
+    for key in DEPENDENCY_HASHES {
+        if let Ok(Expr::String(_)) = rewrite::find(file, &[key]) {
+            let _ = change(&[key], quote(FAKE_HASH));
+        }
+    }
+
+    if changes.is_empty() {
+        return Err("found no version, revision or hash to update".to_owned());
+    }
+    changes.sort_by_key(|change| change.span.start());
+    Ok(changes)
+}
+
+/// Returns the name of the fetcher `expr` calls.
+fn fetcher(mut expr: &Expr) -> Option<&'static str> {
+    while let Expr::Paren(ref paren) = *expr {
+        expr = paren.expr();
+    }
+    let function = match *expr {
+        Expr::FnApp(ref app) => app.function(),
+        _ => return None,
+    };
+    let name = match *function {
+        Expr::Ident(ref ident) => ident.as_str(),
+        Expr::Proj(ref proj) => proj.attr().segments().last()?.name()?,
+        _ => return None,
+    };
+    FETCHERS.iter().find(|&&fetcher| fetcher == name).copied()
+}
+
+/// Returns the URL the fetcher bound to `name` downloads, and whether it unpacks it.
+fn url(
+    file: &SourceFile,
+    name: &str,
+    fetcher: &str,
+    vars: &HashMap<&str, String>,
+) -> Option<(String, bool)> {
+    let arg = |key: &str| match rewrite::find(file, &[name, key]) {
+        Ok(Expr::String(ref string)) => render(string, vars),
+        Ok(Expr::Ident(ref ident)) => vars.get(ident.as_str()).cloned(),
+        _ => None,
+    };
+    match fetcher {
+        "fetchurl" => Some((arg("url")?, false)),
+        "fetchzip" | "fetchTarball" => Some((arg("url")?, true)),
+        "fetchFromGitHub" => {
+            let updated = |key: &str| vars.get(key).cloned().or_else(|| arg(key));
+            let rev = updated("rev").or_else(|| updated("tag"))?;
+            let url = format!(
+                "https://github.com/{}/{}/archive/{}.tar.gz",
+                arg("owner")?,
+                arg("repo")?,
+                rev
+            );
+            Some((url, true))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the contents of a string whose interpolations only refer to the variables in `vars`,
+/// possibly through `finalAttrs`.
+fn render(string: &ExprString, vars: &HashMap<&str, String>) -> Option<String> {
+    let mut text = String::new();
+    for fragment in string.fragments() {
+        match *fragment {
+            StringFragment::Literal(ref literal, _) => text.push_str(literal),
+            StringFragment::Interpolation(ref interp) => {
+                let name = match *interp.inner() {
+                    Expr::Ident(ref ident) => ident.as_str(),
+                    Expr::Proj(ref proj) => match (proj.base(), proj.attr().segments()) {
+                        (Expr::Ident(_), [segment]) => segment.name()?,
+                        _ => return None,
+                    },
+                    _ => return None,
+                };
+                text.push_str(vars.get(name)?);
+            }
+        }
+    }
+    Some(text)
+}
+
+fn quote(text: &str) -> String {
+    format!(
+        "\"{}\"",
+        text.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace("${", "\\${")
+    )
+}
+
+/// Prefetches a URL with `nix-prefetch-url`.
+pub fn nix_prefetch_url(url: &str, unpack: bool) -> Option<String> {
+    let mut command = Command::new("nix-prefetch-url");
+    if unpack {
+        command.arg("--unpack");
+    }
+    let output = command.arg(url).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    let hash = Hash::parse(stdout.lines().last()?.trim(), Some(Algo::Sha256))?;
+    Some(hash.to_sri())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    const PACKAGE: &str = r#"{ lib, rustPlatform, fetchFromGitHub, fetchurl }:
+
+rustPlatform.buildRustPackage rec {
+  pname = "ripgrep";
+  version = "13.0.0";
+
+  src = fetchFromGitHub {
+    owner = "BurntSushi";
+    repo = pname;
+    rev = version;
+    hash = "sha256-udEh+Re2PeO3DnX4fQThsaT1Y3MBHFfrX5Q5EN2XrF0=";
+  };
+
+  extra = fetchurl {
+    url = "https://example.org/extra-13.0.0.tar.gz";
+    sha256 = "0gw1k5p8z07ahqlyfk9dd4fz1qn29g32zbxsf97rbzdni6cq4qq2";
+  };
+
+  cargoHash = "sha256-fTl9Mi4bVrhSCbZXJ3ll5TUyGT7xCVd8yx1LMWsB5pg=";
+}
+"#;
+
+    fn apply(source: &str, changes: &[Change]) -> String {
+        let mut edited = source.to_owned();
+        for change in changes.iter().rev() {
+            let range = change.span.start().to_usize()..change.span.end().to_usize();
+            edited.replace_range(range, &change.new);
+        }
+        edited
+    }
+
+    #[test]
+    fn invalidates_hashes() {
+        let file: SourceFile = PACKAGE.parse().unwrap();
+        let changes = bump(PACKAGE, &file, "14.1.0", None).unwrap();
+        let attrs: Vec<_> = changes.iter().map(|c| c.attr.as_str()).collect();
+        assert_eq!(attrs, ["version", "src.hash", "extra.sha256", "cargoHash"]);
+
+        let edited = apply(PACKAGE, &changes);
+        assert!(edited.contains("version = \"14.1.0\";"));
+        assert!(edited.contains("    rev = version;\n"));
+        assert!(edited.contains(&format!("    hash = \"{}\";", FAKE_HASH)));
+        assert!(edited.contains(&format!("cargoHash = \"{}\";", FAKE_HASH)));
+        assert!(edited.contains("url = \"https://example.org/extra-13.0.0.tar.gz\";"));
+    }
+
+    #[test]
+    fn updates_revisions_and_prefetches() {
+        let source = r#"{ fetchFromGitHub }:
+{
+  src = fetchFromGitHub {
+    owner = "o";
+    repo = "r";
+    rev = "v1.0";
+    sha256 = "";
+  };
+}
+"#;
+        let file: SourceFile = source.parse().unwrap();
+        let urls = RefCell::new(Vec::new());
+        let prefetch = |url: &str, unpack: bool| {
+            urls.borrow_mut().push((url.to_owned(), unpack));
+            Some("sha256-new".to_owned())
+        };
+        let changes = bump(source, &file, "2.0", Some(&prefetch)).unwrap();
+        let edited = apply(source, &changes);
+        assert!(edited.contains("rev = \"v2.0\";\n    sha256 = \"sha256-new\";"));
+        assert_eq!(
+            *urls.borrow(),
+            [(
+                "https://github.com/o/r/archive/v2.0.tar.gz".to_owned(),
+                true
+            )]
+        );
+
+        let file: SourceFile = "{ a = 1; }".parse().unwrap();
+        assert!(bump("{ a = 1; }", &file, "2.0", None).is_err());
+    }
+}
@@ -0,0 +1,421 @@
+//! Analysis of `callPackage ./foo.nix { ... }` and `import ./foo.nix { ... }` calls.
+//!
+//! `callPackage` fills in the arguments of the function in `foo.nix` from the package set, and
+//! takes the set passed after the path as overrides for some of them. Overrides which the function
+//! does not take are either rejected by the evaluator or, if the function accepts `...`, silently
+//! ignored, so both are reported here against the formal arguments of the target file.
+//!
+//! A plain `import` passes the set as is, so it must also provide every argument without a
+//! default value. Extra arguments are only reported when the function does not accept `...`,
+//! since passing a common set such as `{ inherit pkgs lib; }` to many files is idiomatic.
+
+use std::path::Path;
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use nix_parser::ast::tokens::Literal;
+use nix_parser::ast::visit::descendants;
+use nix_parser::ast::{Bind, Expr, ExprFnDecl, ExprSet, SourceFile};
+use nix_parser::HasSpan;
+
+use crate::imports::Import;
+use crate::resolve::did_you_mean_note;
+use crate::security::builtin_name;
+use crate::suggest::did_you_mean;
+use crate::vfs::{FileLoader, PathResolver};
+
+/// The function applied to a literal path and a set.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Kind {
+    CallPackage,
+    Import,
+}
+
+/// A call of `callPackage` or `import` on a literal path with a set of arguments.
+#[derive(Clone, Debug)]
+pub struct Call<'a> {
+    pub kind: Kind,
+    pub target: Import,
+    /// The overrides given to `callPackage`, or the whole argument set given to an import.
+    pub overrides: &'a ExprSet,
+}
+
+impl<'a> Call<'a> {
+    /// Returns the names and spans of the overrides given by this call, skipping those with
+    /// computed names.
+    pub fn override_names(&self) -> Vec<(&'a str, Span)> {
+        let mut names = Vec::new();
+        for bind in self.overrides.binds() {
+            match *bind {
+                Bind::Simple(ref simple) => {
+                    if let Some(first) = simple.attr().segments().first() {
+                        if let Some(name) = first.name() {
+                            names.push((name, first.span()));
+                        }
+                    }
+                }
+                Bind::Inherit(ref inherit) => {
+                    names.extend(inherit.names().iter().map(|n| (n.as_str(), n.span())));
+                }
+                Bind::InheritExpr(ref inherit) => {
+                    names.extend(inherit.names().iter().map(|n| (n.as_str(), n.span())));
+                }
+            }
+        }
+        names
+    }
+
+    /// Returns whether every name given by this call is known statically.
+    fn has_static_names(&self) -> bool {
+        self.overrides.binds().iter().all(|bind| match *bind {
+            Bind::Simple(ref simple) => simple
+                .attr()
+                .segments()
+                .first()
+                .is_some_and(|segment| segment.name().is_some()),
+            _ => true,
+        })
+    }
+}
+
+/// The formal arguments of the function defined by a file.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Formals {
+    /// The names of the arguments, along with their default values as written in the source.
+    pub args: Vec<(String, Option<String>)>,
+    /// Whether the function accepts arguments besides those it names, with `...`.
+    pub ellipsis: bool,
+}
+
+impl Formals {
+    /// Returns the formal arguments of the function `file` evaluates to, if it takes a set of
+    /// named arguments.
+    pub fn of(file: &SourceFile) -> Option<Self> {
+        let mut expr = file.expr();
+        while let Expr::Paren(ref paren) = *expr {
+            expr = paren.expr();
+        }
+
+        let decl = match *expr {
+            Expr::FnDecl(ref decl) => match **decl {
+                ExprFnDecl::Formals(ref decl) => decl,
+                ExprFnDecl::Simple(_) => return None,
+            },
+            _ => return None,
+        };
+
+        let args = decl
+            .formals()
+            .iter()
+            .map(|formal| {
+                let default = formal.default().map(ToString::to_string);
+                (formal.name().to_string(), default)
+            })
+            .collect();
+        Some(Formals {
+            args,
+            ellipsis: decl.ellipsis().is_some(),
+        })
+    }
+
+    /// Loads the file at `path` and returns its formal arguments.
+    pub fn load(path: &Path, fs: &dyn FileLoader) -> Option<Self> {
+        let file: SourceFile = fs.load(path).ok()?.parse().ok()?;
+        Formals::of(&file)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.args.iter().any(|(arg, _)| arg == name)
+    }
+}
+
+/// Returns every call of `callPackage` on a literal path with a literal set of overrides.
+pub fn calls(file: &SourceFile) -> Vec<Call<'_>> {
+    applications(file, Kind::CallPackage, is_call_package)
+}
+
+/// Returns every import of a literal path whose function is applied to a literal set.
+pub fn import_calls(file: &SourceFile) -> Vec<Call<'_>> {
+    applications(file, Kind::Import, is_import)
+}
+
+fn applications(file: &SourceFile, kind: Kind, is_function: fn(&Expr) -> bool) -> Vec<Call<'_>> {
+    let mut calls: Vec<_> = descendants(file.expr())
+        .filter_map(|expr| {
+            let (outer, overrides) = match *expr {
+                Expr::FnApp(ref app) => match *app.argument() {
+                    Expr::Set(ref set) => (app, set),
+                    _ => return None,
+                },
+                _ => return None,
+            };
+            let inner = match *outer.function() {
+                Expr::FnApp(ref inner) if is_function(inner.function()) => inner,
+                _ => return None,
+            };
+            match *inner.argument() {
+                Expr::Literal(Literal::Path(ref path, span)) => Some(Call {
+                    kind,
+                    target: Import {
+                        path: (**path).clone(),
+                        span,
+                    },
+                    overrides,
+                }),
+                _ => None,
+            }
+        })
+        .collect();
+
+    calls.sort_by_key(|call| call.target.span.start());
+    calls
+}
+
+/// Reports overrides passed to `callPackage` which the called function does not take, and
+/// imports whose function is applied to a set which it does not accept.
+pub fn check<F>(id: FileId, file: &SourceFile, path: &Path, fs: &F) -> Vec<Diagnostic>
+where
+    F: FileLoader + PathResolver,
+{
+    let mut diagnostics = Vec::new();
+    for call in calls(file) {
+        let target = call.target.resolve(path, fs);
+        let formals = match Formals::load(&target, fs) {
+            Some(formals) => formals,
+            None => continue,
+        };
+
+        let name = display_name(&target);
+        for (arg, span) in call.override_names() {
+            if formals.contains(arg) {
+                continue;
+            }
+
+            let label = if formals.ellipsis {
+                "ignored by the called function"
+            } else {
+                "not accepted by the called function"
+            };
+            let names = formals.args.iter().map(|(name, _)| name.as_str());
+            let notes = did_you_mean(arg, names).map(did_you_mean_note);
+
+            let message = format!("`{}` takes no argument `{}`", name, arg);
+            let label = Label::new(id, span, label);
+            diagnostics.push(
+                Diagnostic::new_warning(message, label)
+                    .with_code("unknown-call-package-argument")
+                    .with_notes(notes.into_iter().collect()),
+            );
+        }
+    }
+
+    for call in import_calls(file) {
+        let target = call.target.resolve(path, fs);
+        let formals = match Formals::load(&target, fs) {
+            Some(formals) => formals,
+            None => continue,
+        };
+
+        let name = display_name(&target);
+        let given = call.override_names();
+        if !formals.ellipsis {
+            for &(arg, span) in &given {
+                if formals.contains(arg) {
+                    continue;
+                }
+
+                let names = formals.args.iter().map(|(name, _)| name.as_str());
+                let notes = did_you_mean(arg, names).map(did_you_mean_note);
+                let message = format!("`{}` takes no argument `{}`", name, arg);
+                let label = Label::new(id, span, "not accepted by the imported function");
+                diagnostics.push(
+                    Diagnostic::new_error(message, label)
+                        .with_code("unknown-import-argument")
+                        .with_notes(notes.into_iter().collect()),
+                );
+            }
+        }
+
+        // A computed name may provide any of the arguments.
+        if !call.has_static_names() {
+            continue;
+        }
+        let missing = formals.args.iter().filter(|&(arg, default)| {
+            default.is_none() && !given.iter().any(|&(name, _)| name == arg)
+        });
+        for (arg, _) in missing {
+            let message = format!("`{}` requires argument `{}`", name, arg);
+            let label = Label::new(id, call.overrides.span(), format!("`{}` is not given", arg));
+            diagnostics
+                .push(Diagnostic::new_error(message, label).with_code("missing-import-argument"));
+        }
+    }
+    diagnostics
+}
+
+/// Returns the argument taken by the called function which is spelled most like the unknown one
+/// passed at `span`, if any.
+pub fn suggestion<F>(file: &SourceFile, path: &Path, span: Span, fs: &F) -> Option<String>
+where
+    F: FileLoader + PathResolver,
+{
+    let (call, arg) = calls(file)
+        .into_iter()
+        .chain(import_calls(file))
+        .find_map(|call| {
+            let (arg, _) = call
+                .override_names()
+                .into_iter()
+                .find(|&(_, s)| s == span)?;
+            Some((call, arg))
+        })?;
+    let formals = Formals::load(&call.target.resolve(path, fs), fs)?;
+    if formals.contains(arg) {
+        return None;
+    }
+    let names = formals.args.iter().map(|(name, _)| name.as_str());
+    did_you_mean(arg, names).map(ToOwned::to_owned)
+}
+
+/// Returns the call whose set of arguments contains `offset` where a name can be written.
+pub fn override_position(file: &SourceFile, offset: usize) -> Option<Call<'_>> {
+    calls(file)
+        .into_iter()
+        .chain(import_calls(file))
+        .find(|call| is_name_position(call.overrides.binds(), call.overrides.span(), offset))
+}
+
+/// Returns whether `offset` lies within the set at `span` but outside of any of its bound values,
+/// which is where the name of another attribute can be written.
+pub fn is_name_position(binds: &[Bind], span: Span, offset: usize) -> bool {
+    let contains =
+        |span: Span| span.start().to_usize() <= offset && offset <= span.end().to_usize();
+    let inside = span.start().to_usize() < offset && offset < span.end().to_usize();
+    inside
+        && !binds.iter().any(|bind| match *bind {
+            Bind::Simple(ref simple) => contains(simple.expr().span()),
+            _ => contains(bind.span()),
+        })
+}
+
+/// Returns the name of the file at `path` as shown in messages, which is its directory for
+/// `default.nix` files since that is how they are usually called.
+pub fn display_name(path: &Path) -> String {
+    let path = match path.file_name() {
+        Some(name) if name == "default.nix" => path.parent().unwrap_or(path),
+        _ => path,
+    };
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+fn is_call_package(function: &Expr) -> bool {
+    let name = match *function {
+        Expr::Ident(ref ident) => ident.as_str(),
+        Expr::Proj(ref proj) => match proj.attr().segments().last().and_then(|s| s.name()) {
+            Some(name) => name,
+            None => return false,
+        },
+        _ => return false,
+    };
+    name == "callPackage"
+}
+
+fn is_import(function: &Expr) -> bool {
+    match *function {
+        Expr::Ident(ref ident) if ident.as_str() == "import" => true,
+        _ => builtin_name(function) == Some("import"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::vfs::MemoryFs;
+
+    #[test]
+    fn checks_overrides_against_formals() {
+        let mut fs = MemoryFs::new();
+        fs.insert(
+            "/p/hello/default.nix",
+            "{ stdenv, fetchurl, withGui ? false }: 1",
+        );
+        fs.insert("/p/open.nix", "{ lib, ... }: 1");
+        let source = "{
+  hello = pkgs.callPackage ./hello { withGUI = true; stdenv = gcc8Stdenv; };
+  open = callPackage ./open.nix { inherit extra; };
+  other = callPackage ./missing.nix { anything = 1; };
+}";
+        let mut files = codespan::Files::new();
+        let id = files.add("/p/all.nix", source);
+        let file: SourceFile = source.parse().unwrap();
+
+        let diagnostics = check(id, &file, Path::new("/p/all.nix"), &fs);
+        let messages: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.message.as_str(), d.notes.clone()))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                (
+                    "`hello` takes no argument `withGUI`",
+                    vec![did_you_mean_note("withGui")]
+                ),
+                ("`open.nix` takes no argument `extra`", vec![]),
+            ]
+        );
+
+        let offset = source.find("stdenv =").unwrap();
+        let call = override_position(&file, offset).unwrap();
+        assert_eq!(call.target.path, PathBuf::from("./hello"));
+        assert!(override_position(&file, source.find("gcc8Stdenv").unwrap()).is_none());
+
+        let path = Path::new("/p/all.nix");
+        let suggested = |span| suggestion(&file, path, span, &fs);
+        assert_eq!(
+            suggested(diagnostics[0].primary_label.span).as_deref(),
+            Some("withGui")
+        );
+        assert_eq!(suggested(diagnostics[1].primary_label.span), None);
+    }
+
+    #[test]
+    fn checks_import_arguments() {
+        let mut fs = MemoryFs::new();
+        fs.insert("/p/lib.nix", "{ pkgs, lib, system ? null }: 1");
+        fs.insert("/p/open.nix", "{ pkgs, ... }: 1");
+        let source = r#"[
+  (import ./lib.nix { inherit pkgs; lib = 1; sytem = 2; })
+  (import ./lib.nix { inherit (x) lib; })
+  (builtins.import ./lib.nix { ${name} = 1; })
+  (import ./open.nix { inherit pkgs lib; })
+]"#;
+        let mut files = codespan::Files::new();
+        let id = files.add("/p/all.nix", source);
+        let file: SourceFile = source.parse().unwrap();
+
+        let diagnostics = check(id, &file, Path::new("/p/all.nix"), &fs);
+        let messages: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.message.as_str(), d.notes.clone()))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                (
+                    "`lib.nix` takes no argument `sytem`",
+                    vec![did_you_mean_note("system")]
+                ),
+                ("`lib.nix` requires argument `pkgs`", vec![]),
+            ]
+        );
+
+        let call = override_position(&file, source.find("lib = 1").unwrap()).unwrap();
+        assert_eq!(call.kind, Kind::Import);
+        assert_eq!(call.target.path, PathBuf::from("./lib.nix"));
+    }
+}
@@ -0,0 +1,385 @@
+//! A normalized rendering of syntax trees for diffing.
+//!
+//! The canonical form drops everything which does not affect the meaning of an expression:
+//! spans, whitespace, comments, redundant parentheses and the order of bindings in attribute sets
+//! and `let` blocks. This makes it suitable as a `git diff` textconv filter:
+//!
+//! ```text
+//! # .gitattributes
+//! *.nix diff=nix
+//!
+//! $ git config diff.nix.textconv "nix-language-server dump-ast --canonical"
+//! ```
+//!
+//! Two canonical trees can also be compared directly with [`diff`], which reports changes by the
+//! attribute path at which they occur.
+
+use std::fmt::{self, Display, Formatter};
+
+use nix_parser::ast::{
+    AttrPath, AttrSegment, Bind, Expr, ExprFnDecl, ExprString, SourceFile, StringFragment,
+};
+
+/// A node of the canonical tree.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Node {
+    /// Identifies this node among its siblings, e.g. an attribute name or `then`.
+    key: String,
+    label: String,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn new(key: impl Into<String>, label: impl Into<String>, children: Vec<Node>) -> Self {
+        Node {
+            key: key.into(),
+            label: label.into(),
+            children,
+        }
+    }
+
+    fn leaf(key: impl Into<String>, label: impl Into<String>) -> Self {
+        Node::new(key, label, Vec::new())
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    pub fn children(&self) -> &[Node] {
+        &self.children
+    }
+
+    fn write(&self, fmt: &mut Formatter, depth: usize) -> fmt::Result {
+        let indent = "  ".repeat(depth);
+        if self.key.is_empty() {
+            writeln!(fmt, "{}{}", indent, self.label)?;
+        } else {
+            writeln!(fmt, "{}{}: {}", indent, self.key, self.label)?;
+        }
+
+        for child in &self.children {
+            child.write(fmt, depth + 1)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for Node {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        self.write(fmt, 0)
+    }
+}
+
+/// Returns the canonical tree of a source file.
+pub fn canonicalize(file: &SourceFile) -> Node {
+    expr("", file.expr())
+}
+
+fn expr(key: impl Into<String>, e: &Expr) -> Node {
+    let key = key.into();
+    match *e {
+        Expr::Paren(ref paren) => expr(key, paren.expr()),
+        Expr::Ident(ref ident) => Node::leaf(key, format!("ident {}", ident)),
+        Expr::Interpolation(ref interp) => {
+            Node::new(key, "interpolation", vec![expr("", interp.inner())])
+        }
+        Expr::Literal(ref lit) => Node::leaf(key, format!("literal {}", lit)),
+        Expr::List(ref list) => {
+            let elems = list.elems().iter().enumerate();
+            let children = elems.map(|(i, e)| expr(format!("[{}]", i), e)).collect();
+            Node::new(key, "list", children)
+        }
+        Expr::String(ref string) => string_node(key, string),
+        Expr::Set(ref set) => Node::new(key, "set", binds(set.binds())),
+        Expr::Rec(ref rec) => Node::new(key, "rec set", binds(rec.binds())),
+        Expr::Let(ref let_) => Node::new(key, "let set", binds(let_.binds())),
+        Expr::Unary(ref unary) => Node::new(
+            key,
+            format!("unary {}", unary.op()),
+            vec![expr("", unary.expr())],
+        ),
+        Expr::Binary(ref binary) => Node::new(
+            key,
+            format!("binary {}", binary.op()),
+            vec![expr("lhs", binary.left()), expr("rhs", binary.right())],
+        ),
+        Expr::Proj(ref proj) => {
+            let mut children = vec![expr("of", proj.base())];
+            children.extend(proj.fallback().map(|e| expr("or", e)));
+            Node::new(key, format!("select {}", attr_path(proj.attr())), children)
+        }
+        Expr::If(ref if_) => Node::new(
+            key,
+            "if",
+            vec![
+                expr("if", if_.condition()),
+                expr("then", if_.body()),
+                expr("else", if_.fallback()),
+            ],
+        ),
+        Expr::Or(ref or) => Node::new(
+            key,
+            "or",
+            vec![expr("expr", or.expr()), expr("or", or.fallback())],
+        ),
+        Expr::Assert(ref assert) => Node::new(
+            key,
+            "assert",
+            vec![
+                expr("assert", assert.condition()),
+                expr("in", assert.expr()),
+            ],
+        ),
+        Expr::With(ref with) => Node::new(
+            key,
+            "with",
+            vec![expr("with", with.with()), expr("in", with.expr())],
+        ),
+        Expr::LetIn(ref let_in) => {
+            let mut children = binds(let_in.binds());
+            children.push(expr("in", let_in.body()));
+            Node::new(key, "let", children)
+        }
+        Expr::FnDecl(ref decl) => match **decl {
+            ExprFnDecl::Simple(ref simple) => Node::new(
+                key,
+                format!("lambda {}", simple.name()),
+                vec![expr("body", simple.body())],
+            ),
+            ExprFnDecl::Formals(ref formals) => {
+                let mut names: Vec<_> = formals
+                    .formals()
+                    .iter()
+                    .map(|f| match f.default() {
+                        Some(_) => format!("{} ?", f.name()),
+                        None => f.name().to_string(),
+                    })
+                    .collect();
+                names.sort();
+                if formals.ellipsis().is_some() {
+                    names.push("...".to_string());
+                }
+
+                let mut label = format!("lambda {{ {} }}", names.join(", "));
+                if let Some(extra) = formals.extra() {
+                    label.push_str(&format!(" @ {}", extra));
+                }
+
+                let mut children: Vec<_> = formals
+                    .formals()
+                    .iter()
+                    .filter_map(|f| f.default().map(|e| expr(format!("{} ?", f.name()), e)))
+                    .collect();
+                children.sort_by(|a, b| a.key.cmp(&b.key));
+                children.push(expr("body", formals.body()));
+                Node::new(key, label, children)
+            }
+        },
+        Expr::FnApp(ref app) => Node::new(
+            key,
+            "apply",
+            vec![expr("fn", app.function()), expr("arg", app.argument())],
+        ),
+        Expr::Error(_) | Expr::Trap(_) => Node::leaf(key, "error"),
+    }
+}
+
+fn string_node(key: String, string: &ExprString) -> Node {
+    match string.as_literal() {
+        Some(text) => Node::leaf(key, format!("string {:?}", text)),
+        None => {
+            let children = string
+                .fragments()
+                .iter()
+                .map(|fragment| match *fragment {
+                    StringFragment::Literal(ref text, _) => Node::leaf("", format!("{:?}", text)),
+                    StringFragment::Interpolation(ref interp) => expr("", interp.inner()),
+                })
+                .collect();
+            Node::new(key, "string", children)
+        }
+    }
+}
+
+/// Returns the bindings as children keyed by attribute path, sorted by key.
+fn binds(binds: &[Bind]) -> Vec<Node> {
+    let mut children = Vec::new();
+    for bind in binds {
+        match *bind {
+            Bind::Simple(ref simple) => {
+                children.push(expr(attr_path(simple.attr()), simple.expr()))
+            }
+            Bind::Inherit(ref inherit) => {
+                let names = inherit.names().iter();
+                children.extend(names.map(|name| Node::leaf(name.to_string(), "inherit")));
+            }
+            Bind::InheritExpr(ref inherit) => {
+                for name in inherit.names() {
+                    let from = expr("from", inherit.expr());
+                    children.push(Node::new(name.to_string(), "inherit", vec![from]));
+                }
+            }
+        }
+    }
+
+    children.sort_by(|a, b| a.key.cmp(&b.key));
+    children
+}
+
+fn attr_path(path: &AttrPath) -> String {
+    let segments: Vec<_> = path
+        .segments()
+        .iter()
+        .map(|segment| match *segment {
+            AttrSegment::Ident(ref ident) => ident.to_string(),
+            AttrSegment::String(ref string) => match string.as_literal() {
+                Some(text) if is_identifier(&text) => text.into_owned(),
+                Some(text) => format!("{:?}", text),
+                None => string.to_string(),
+            },
+            AttrSegment::Interpolation(ref interp) => interp.to_string(),
+        })
+        .collect();
+    segments.join(".")
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    let first = chars.next();
+    first.is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '\'')
+}
+
+/// A difference between two canonical trees.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Change {
+    Added {
+        path: String,
+        label: String,
+    },
+    Removed {
+        path: String,
+        label: String,
+    },
+    Changed {
+        path: String,
+        old: String,
+        new: String,
+    },
+}
+
+impl Display for Change {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        fn root(path: &str) -> &str {
+            if path.is_empty() {
+                "<root>"
+            } else {
+                path
+            }
+        }
+
+        match *self {
+            Change::Added {
+                ref path,
+                ref label,
+            } => write!(fmt, "+ {}: {}", root(path), label),
+            Change::Removed {
+                ref path,
+                ref label,
+            } => write!(fmt, "- {}: {}", root(path), label),
+            Change::Changed {
+                ref path,
+                ref old,
+                ref new,
+            } => write!(fmt, "~ {}: {} -> {}", root(path), old, new),
+        }
+    }
+}
+
+/// Compares two canonical trees, returning the changes needed to turn `old` into `new`.
+///
+/// Children are matched up by key, so reordering bindings is not reported as a change.
+pub fn diff(old: &Node, new: &Node) -> Vec<Change> {
+    let mut changes = Vec::new();
+    diff_nodes(old, new, "", &mut changes);
+    changes
+}
+
+fn diff_nodes(old: &Node, new: &Node, path: &str, out: &mut Vec<Change>) {
+    if old.label != new.label {
+        out.push(Change::Changed {
+            path: path.to_string(),
+            old: old.label.clone(),
+            new: new.label.clone(),
+        });
+        return;
+    }
+
+    let mut unmatched: Vec<Option<&Node>> = new.children.iter().map(Some).collect();
+    for child in &old.children {
+        let path = join(path, &child.key);
+        let matching = unmatched
+            .iter_mut()
+            .find(|n| n.is_some_and(|n| n.key == child.key));
+        match matching.and_then(Option::take) {
+            Some(other) => diff_nodes(child, other, &path, out),
+            None => out.push(Change::Removed {
+                path,
+                label: child.label.clone(),
+            }),
+        }
+    }
+
+    for child in unmatched.into_iter().flatten() {
+        out.push(Change::Added {
+            path: join(path, &child.key),
+            label: child.label.clone(),
+        });
+    }
+}
+
+fn join(path: &str, key: &str) -> String {
+    match (path.is_empty(), key.is_empty()) {
+        (_, true) => path.to_string(),
+        (true, false) => key.to_string(),
+        (false, false) => format!("{}.{}", path, key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn canonical(source: &str) -> Node {
+        canonicalize(&source.parse().unwrap())
+    }
+
+    #[test]
+    fn ignores_layout_comments_and_binding_order() {
+        let a = canonical("{ b = 2; a = (1); }");
+        let b = canonical("# comment\n{\n  a = 1;\n  b = 2;\n}\n");
+        assert_eq!(a, b);
+        assert_eq!(a.to_string(), "set\n  a: literal 1\n  b: literal 2\n");
+    }
+
+    #[test]
+    fn reports_changes_by_attribute_path() {
+        let old = canonical("{ a.b = 1; c = true; d = x; }");
+        let new = canonical("{ d = x; a.b = 2; e = null; }");
+        let changes: Vec<_> = diff(&old, &new).iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            changes,
+            [
+                "~ a.b: literal 1 -> literal 2",
+                "- c: literal true",
+                "+ e: literal null",
+            ]
+        );
+    }
+}
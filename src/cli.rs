@@ -0,0 +1,908 @@
+//! Command-line subcommands which run the same analyses as the language server over files on disk.
+//!
+//! Every command exits with status 0 on success, 1 if problems (or, for `semantic-diff`, changes,
+//! for `replay`, differences from the recording, and for `bump`, nothing to update) were found in
+//! the inputs and 2 if the inputs could not be read or parsed at all. Problems recorded in a
+//! `--baseline` file are not reported and do not affect the exit status.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use std::{env, fs};
+
+use codespan::{FileId, Files, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Severity};
+use codespan_reporting::term::termcolor::{ColorChoice, StandardStream};
+use codespan_reporting::term::{emit, Config};
+use nix_parser::ast::paths::SearchPath;
+use nix_parser::ast::SourceFile;
+use nix_parser::fmt;
+use nix_parser::lexer::Lexer;
+use nix_parser::parser::{parse_source_file, parse_source_file_partial};
+use nix_parser::reduce::reduce;
+use nix_parser::ToSpan;
+use serde_json::{json, Value};
+use structopt::StructOpt;
+
+use crate::baseline::Baseline;
+use crate::bump;
+use crate::canonical::{canonicalize, diff, Node};
+use crate::compat::Version;
+use crate::dap;
+use crate::db::Database;
+use crate::disk;
+use crate::dot;
+use crate::explain;
+use crate::fixes::{self, Problem};
+use crate::impact;
+use crate::imports::{self, ImportGraph};
+use crate::naming;
+use crate::session::{self, Session};
+use crate::sexp::to_tree_sitter;
+use crate::vfs::RealFs;
+
+const SUCCESS: i32 = 0;
+const PROBLEMS_FOUND: i32 = 1;
+const INVALID_INPUT: i32 = 2;
+
+/// The files Nix evaluates by default in a directory, which `dead-files` starts from.
+const ENTRY_POINTS: &[&str] = &["flake.nix", "default.nix", "shell.nix"];
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Report syntax errors and static analysis problems, as the editor would
+    #[structopt(name = "check")]
+    Check(Report),
+    /// Report static analysis problems only, ignoring syntax errors
+    #[structopt(name = "lint")]
+    Lint(Report),
+    /// Normalize whitespace in place
+    #[structopt(name = "fmt")]
+    Fmt {
+        /// Only report files which would be changed, without writing them
+        #[structopt(long = "check")]
+        check: bool,
+        /// Keep at most this many blank lines in a row between bindings or list elements
+        #[structopt(long = "max-blank-lines")]
+        max_blank_lines: Option<usize>,
+        /// Files or directories to format
+        #[structopt(parse(from_os_str), required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Print the syntax tree of a file
+    #[structopt(name = "dump-ast")]
+    DumpAst {
+        /// Print the normalized tree without spans, comments or binding order, for diffing. Only
+        /// applies to `text`, as `dot` always draws the normalized tree
+        #[structopt(long = "canonical")]
+        canonical: bool,
+        /// Output format: `text`, `dot`, which draws the normalized tree with Graphviz, `sexp`,
+        /// which prints the tree with the node names of tree-sitter-nix, or `json`, which prints
+        /// the whole tree with its spans for other tools to read
+        #[structopt(long = "emit", default_value = "text")]
+        emit: Emit,
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+    },
+    /// Print the tokens of a file, one per line
+    #[structopt(name = "dump-tokens")]
+    DumpTokens {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+    },
+    /// Shrink a file which makes the parser panic, report an error or run slowly to a small
+    /// reproducer, which is printed
+    #[structopt(name = "reduce")]
+    Reduce {
+        /// Keep inputs with a syntax error containing this text, instead of inputs which panic
+        #[structopt(long = "error")]
+        error: Option<String>,
+        /// Keep inputs which take longer than this many milliseconds to parse, instead of inputs
+        /// which panic
+        #[structopt(long = "slower-than")]
+        slower_than: Option<u64>,
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+    },
+    /// Report the changes between two files at the level of their syntax trees
+    #[structopt(name = "semantic-diff")]
+    SemanticDiff {
+        #[structopt(parse(from_os_str))]
+        old: PathBuf,
+        #[structopt(parse(from_os_str))]
+        new: PathBuf,
+    },
+    /// Print the files imported by the given files, following imports transitively
+    #[structopt(name = "imports")]
+    Imports {
+        /// Output format: `text` or `dot`, which draws the import graph with Graphviz
+        #[structopt(long = "emit", default_value = "text")]
+        emit: Emit,
+        /// Files or directories to start from
+        #[structopt(parse(from_os_str), required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Check files and everything they import, dependencies first, grouping the problems by the
+    /// entry points they affect, such as each output of a flake
+    #[structopt(name = "check-imports")]
+    CheckImports {
+        /// Files or directories whose files are entry points
+        #[structopt(parse(from_os_str), required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Report `.nix` files which are neither imported nor otherwise referenced from any entry point
+    #[structopt(name = "dead-files")]
+    DeadFiles {
+        /// Entry points to start from, instead of the `flake.nix`, `default.nix` and `shell.nix`
+        /// at the top of each directory
+        #[structopt(long = "entry", parse(from_os_str))]
+        entries: Vec<PathBuf>,
+        /// Directories to search for files
+        #[structopt(parse(from_os_str), required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Update the version of a package, the revision of its sources and their hashes in place,
+    /// printing each change
+    #[structopt(name = "bump")]
+    Bump {
+        /// Compute the hashes of the new sources with nix-prefetch-url, instead of writing a fake
+        /// hash which building the package replaces with the correct one
+        #[structopt(long = "prefetch")]
+        prefetch: bool,
+        /// Only print the changes, without writing them
+        #[structopt(long = "dry-run")]
+        dry_run: bool,
+        /// The new version
+        version: String,
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+    },
+    /// Print the long-form explanation of a diagnostic code, e.g. `undefined-variable`
+    #[structopt(name = "explain")]
+    Explain { code: String },
+    /// Run a Debug Adapter Protocol server over stdio which evaluates files with nix-instantiate
+    #[structopt(name = "dap")]
+    Dap,
+    /// Send the client messages of a session recorded with `--record` to a fresh server, and
+    /// report where its answers differ from the recorded ones
+    #[structopt(name = "replay")]
+    Replay {
+        /// How long the server must stay silent before its answers are compared, in milliseconds
+        #[structopt(long = "settle", default_value = "500")]
+        settle: u64,
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Report {
+    /// Output format: `human`, `json` or `sarif`
+    #[structopt(long = "format", default_value = "human")]
+    format: Format,
+    /// Exit with a failure status on warnings as well as errors
+    #[structopt(long = "deny-warnings")]
+    deny_warnings: bool,
+    /// Only report problems not recorded in this baseline file, which is created with the current
+    /// problems if it does not exist
+    #[structopt(long = "baseline", parse(from_os_str))]
+    baseline: Option<PathBuf>,
+    /// Apply suggested fixes in place before reporting the remaining problems
+    #[structopt(long = "fix")]
+    fix: bool,
+    /// Report builtins which are not available in this Nix version, e.g. `2.3`
+    #[structopt(long = "nix-version")]
+    nix_version: Option<Version>,
+    /// Files or directories to check
+    #[structopt(parse(from_os_str), required = true)]
+    paths: Vec<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    Human,
+    Json,
+    Sarif,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Format::Human),
+            "json" => Ok(Format::Json),
+            "sarif" => Ok(Format::Sarif),
+            _ => Err(format!("unknown format `{}`", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Emit {
+    Text,
+    Dot,
+    Sexp,
+    Json,
+}
+
+impl FromStr for Emit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Emit::Text),
+            "dot" => Ok(Emit::Dot),
+            "sexp" => Ok(Emit::Sexp),
+            "json" => Ok(Emit::Json),
+            _ => Err(format!("unknown output format `{}`", s)),
+        }
+    }
+}
+
+/// Runs the given command, returning the exit status of the process.
+pub fn run(command: Command) -> i32 {
+    let result = match command {
+        Command::Check(report) => check(&report, true),
+        Command::Lint(report) => check(&report, false),
+        Command::Fmt {
+            check,
+            max_blank_lines,
+            paths,
+        } => fmt(&paths, check, max_blank_lines),
+        Command::DumpAst {
+            canonical,
+            emit,
+            path,
+        } => dump_ast(&path, canonical, emit),
+        Command::DumpTokens { path } => dump_tokens(&path),
+        Command::Reduce {
+            error,
+            slower_than,
+            path,
+        } => reduce_input(&path, error, slower_than),
+        Command::SemanticDiff { old, new } => semantic_diff(&old, &new),
+        Command::Imports { emit, paths } => import_graph(&paths, emit),
+        Command::CheckImports { paths } => check_imports(&paths),
+        Command::DeadFiles { entries, paths } => dead_files(&paths, entries),
+        Command::Bump {
+            prefetch,
+            dry_run,
+            version,
+            path,
+        } => bump_version(&path, &version, prefetch, dry_run),
+        Command::Explain { code } => Ok(explain_code(&code)),
+        Command::Dap => {
+            let stdin = io::stdin();
+            dap::serve(stdin.lock(), io::stdout()).map(|_| SUCCESS)
+        }
+        Command::Replay { settle, path } => replay(&path, Duration::from_millis(settle)),
+    };
+
+    result.unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        INVALID_INPUT
+    })
+}
+
+fn check(report: &Report, syntax: bool) -> io::Result<i32> {
+    let mut db = Database::new();
+    db.set_nix_version(report.nix_version);
+    let mut ids = Vec::new();
+    for path in collect(&report.paths)? {
+        let text = fs::read_to_string(&path)?;
+        ids.push(db.add_file(path.display().to_string(), text));
+    }
+
+    let search_path = SearchPath::new(&env::var("NIX_PATH").unwrap_or_default());
+    let analyze = |db: &Database| {
+        let mut diagnostics = Vec::new();
+        for &id in &ids {
+            if syntax {
+                diagnostics.extend(db.syntax_errors(id));
+            }
+            diagnostics.extend(db.lints(id));
+            if let Ok(ref partial) = *db.parse(id) {
+                if let Some(file) = partial.value() {
+                    let path = Path::new(db.name(id));
+                    diagnostics.extend(disk::check(id, file, path, &search_path, &RealFs));
+                }
+            }
+        }
+        diagnostics
+    };
+
+    let mut diagnostics = analyze(&db);
+    if report.fix {
+        let mut fixed = 0;
+        for &id in &ids {
+            let parse = db.parse(id);
+            let file = match *parse {
+                Ok(ref partial) => partial.value(),
+                Err(_) => None,
+            };
+            let path = Path::new(db.name(id));
+            let fixes: Vec<_> = diagnostics
+                .iter()
+                .filter(|d| d.primary_label.file_id == id)
+                .filter_map(|d| fix(db.text(id), path, file, d))
+                .collect();
+            if let Some((text, count)) = apply_fixes(db.text(id), fixes) {
+                fs::write(db.name(id), &text)?;
+                db.set_text(id, text);
+                fixed += count;
+            }
+        }
+
+        if fixed > 0 {
+            eprintln!("fixed {} problem(s)", fixed);
+            diagnostics = analyze(&db);
+        }
+    }
+
+    let files = db.report_files();
+    if let Some(ref path) = report.baseline {
+        if path.exists() {
+            diagnostics = Baseline::load(path)?.filter(&files, diagnostics);
+        } else {
+            Baseline::new(&files, &diagnostics).save(path)?;
+            eprintln!(
+                "recorded {} problem(s) in {}",
+                diagnostics.len(),
+                path.display()
+            );
+            diagnostics.clear();
+        }
+    }
+
+    match report.format {
+        Format::Human => {
+            let mut stream = StandardStream::stderr(ColorChoice::Auto);
+            let config = Config::default();
+            for diagnostic in &diagnostics {
+                emit(&mut stream, &config, &files, diagnostic)?;
+            }
+        }
+        Format::Json => {
+            let json: Vec<_> = diagnostics.iter().map(|d| to_json(&files, d)).collect();
+            println!("{}", Value::Array(json));
+        }
+        Format::Sarif => println!("{}", to_sarif(&files, &diagnostics)),
+    }
+
+    let failed = diagnostics.iter().any(|d| match d.severity {
+        Severity::Bug | Severity::Error => true,
+        Severity::Warning => report.deny_warnings,
+        Severity::Note | Severity::Help => false,
+    });
+
+    Ok(if failed { PROBLEMS_FOUND } else { SUCCESS })
+}
+
+/// Returns the replacement suggested by a diagnostic of `file`, read from `path`, if any.
+fn fix(
+    source: &str,
+    path: &Path,
+    file: Option<&SourceFile>,
+    diagnostic: &Diagnostic,
+) -> Option<(Span, String)> {
+    let problem = Problem {
+        source,
+        path: Some(path),
+        file,
+        code: diagnostic.code.as_deref(),
+        span: diagnostic.primary_label.span,
+        naming: naming::Config::default(),
+    };
+    let mut edits = fixes::suggestion(&problem)?.edits;
+    edits.pop()
+}
+
+/// Applies the given replacements to `text`, skipping any which overlap an earlier one.
+///
+/// Returns the new text and the number of replacements applied, or `None` if there were none.
+fn apply_fixes(text: &str, mut fixes: Vec<(Span, String)>) -> Option<(String, usize)> {
+    fixes.sort_by_key(|(span, _)| (span.start(), span.end()));
+    fixes.dedup_by_key(|(span, _)| *span);
+
+    let mut output = String::with_capacity(text.len());
+    let mut offset = 0;
+    let mut count = 0;
+    for (span, replacement) in fixes {
+        let (start, end) = (span.start().to_usize(), span.end().to_usize());
+        if start < offset || end > text.len() {
+            continue;
+        }
+        output.push_str(&text[offset..start]);
+        output.push_str(&replacement);
+        offset = end;
+        count += 1;
+    }
+    output.push_str(&text[offset..]);
+
+    if count > 0 {
+        Some((output, count))
+    } else {
+        None
+    }
+}
+
+fn fmt(paths: &[PathBuf], check: bool, max_blank_lines: Option<usize>) -> io::Result<i32> {
+    let mut options = fmt::Options::default();
+    if let Some(max_blank_lines) = max_blank_lines {
+        options.max_blank_lines = max_blank_lines;
+    }
+
+    let mut status = SUCCESS;
+    for path in collect(paths)? {
+        let text = fs::read_to_string(&path)?;
+        let formatted = match fmt::format(&text, &options) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprintln!("{}: skipped, {}", path.display(), e);
+                status = PROBLEMS_FOUND;
+                continue;
+            }
+        };
+
+        if formatted == text {
+            continue;
+        }
+
+        if check {
+            println!("{}", path.display());
+            status = PROBLEMS_FOUND;
+        } else {
+            fs::write(&path, formatted)?;
+        }
+    }
+
+    Ok(status)
+}
+
+fn dump_ast(path: &Path, canonical: bool, emit: Emit) -> io::Result<i32> {
+    if canonical && (emit == Emit::Sexp || emit == Emit::Json) {
+        let message = "`--canonical` can only be used with `--emit text` or `--emit dot`";
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+    }
+
+    let text = fs::read_to_string(path)?;
+    match parse_source_file_partial(&text) {
+        Ok(partial) => {
+            match partial.value() {
+                Some(ast) if emit == Emit::Dot => print!("{}", dot::tree(&canonicalize(ast))),
+                Some(ast) if emit == Emit::Sexp => println!("{}", to_tree_sitter(ast)),
+                Some(ast) if emit == Emit::Json => println!("{}", serde_json::to_string(ast)?),
+                Some(ast) if canonical => print!("{}", canonicalize(ast)),
+                Some(ast) => println!("{:#?}", ast),
+                None => {}
+            }
+            report_errors(path, &text, partial.errors().unwrap_or_default())
+        }
+        Err(errors) => report_errors(path, &text, errors),
+    }
+}
+
+fn dump_tokens(path: &Path) -> io::Result<i32> {
+    let text = fs::read_to_string(path)?;
+    let lexer = match Lexer::new(&text) {
+        Ok(lexer) => lexer,
+        Err(errors) => return report_errors(path, &text, errors),
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for token in lexer.tokens().iter() {
+        let span = token.to_span();
+        let range = format!("{}..{}", span.start(), span.end());
+        writeln!(out, "{:<12} {:?}", range, token)?;
+    }
+
+    report_errors(path, &text, lexer.errors().clone())
+}
+
+fn reduce_input(path: &Path, error: Option<String>, slower_than: Option<u64>) -> io::Result<i32> {
+    let text = fs::read_to_string(path)?;
+    let is_interesting = |text: &str| {
+        let start = Instant::now();
+        let parse = panic::catch_unwind(|| parse_source_file_partial(text));
+        let elapsed = start.elapsed();
+        match (parse, &error, slower_than) {
+            (Ok(parse), Some(error), _) => {
+                let errors = match parse {
+                    Ok(partial) => partial.errors().unwrap_or_default(),
+                    Err(errors) => errors,
+                };
+                errors
+                    .iter()
+                    .any(|err| err.to_string().contains(error.as_str()))
+            }
+            (_, None, Some(millis)) => elapsed > Duration::from_millis(millis),
+            (parse, None, None) => parse.is_err(),
+            (Err(_), Some(_), _) => false,
+        }
+    };
+
+    // Keep the panics of every attempt from flooding the terminal.
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let reduced = if is_interesting(&text) {
+        Some(reduce(&text, &is_interesting))
+    } else {
+        None
+    };
+    panic::set_hook(hook);
+
+    let (reduced, stats) = match reduced {
+        Some(reduced) => reduced,
+        None => {
+            eprintln!("error: {} does not reproduce the problem", path.display());
+            return Ok(INVALID_INPUT);
+        }
+    };
+
+    print!("{}", reduced);
+    eprintln!(
+        "reduced {} bytes to {} in {} attempts",
+        stats.original_len, stats.reduced_len, stats.tests
+    );
+    Ok(SUCCESS)
+}
+
+fn semantic_diff(old: &Path, new: &Path) -> io::Result<i32> {
+    let parse = |path: &Path| -> io::Result<Result<Node, i32>> {
+        let text = fs::read_to_string(path)?;
+        match parse_source_file(&text) {
+            Ok(ast) => Ok(Ok(canonicalize(&ast))),
+            Err(errors) => report_errors(path, &text, errors).map(Err),
+        }
+    };
+
+    let (old, new) = match (parse(old)?, parse(new)?) {
+        (Ok(old), Ok(new)) => (old, new),
+        _ => return Ok(INVALID_INPUT),
+    };
+
+    let changes = diff(&old, &new);
+    for change in &changes {
+        println!("{}", change);
+    }
+
+    Ok(if changes.is_empty() {
+        SUCCESS
+    } else {
+        PROBLEMS_FOUND
+    })
+}
+
+fn import_graph(paths: &[PathBuf], emit: Emit) -> io::Result<i32> {
+    if emit == Emit::Sexp || emit == Emit::Json {
+        let message = "the import graph can only be printed as `text` or `dot`";
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+    }
+
+    let graph = ImportGraph::build(collect(paths)?, &RealFs)?;
+    match emit {
+        Emit::Text => {
+            for (file, targets) in &graph.edges {
+                for target in targets {
+                    println!("{} -> {}", file.display(), target.display());
+                }
+            }
+        }
+        Emit::Dot => {
+            let base = env::current_dir()?;
+            print!("{}", dot::imports(&graph, &base));
+        }
+        Emit::Sexp | Emit::Json => unreachable!(),
+    }
+
+    Ok(SUCCESS)
+}
+
+fn report_errors(path: &Path, text: &str, errors: nix_parser::error::Errors) -> io::Result<i32> {
+    if errors.is_empty() {
+        return Ok(SUCCESS);
+    }
+
+    let mut files = Files::new();
+    let id = files.add(path.display().to_string(), text);
+    let mut stream = StandardStream::stderr(ColorChoice::Auto);
+    for diagnostic in errors.to_diagnostics(id) {
+        emit(&mut stream, &Config::default(), &files, &diagnostic)?;
+    }
+
+    Ok(PROBLEMS_FOUND)
+}
+
+fn check_imports(paths: &[PathBuf]) -> io::Result<i32> {
+    let roots = collect(paths)?;
+    let graph = ImportGraph::build(roots.iter().cloned(), &RealFs)?;
+
+    let mut db = Database::new();
+    let mut problems: HashMap<&Path, Vec<Diagnostic>> = HashMap::new();
+    let order = graph.dependency_order();
+    for path in &order {
+        let id = db.add_file(path.display().to_string(), fs::read_to_string(path)?);
+        let mut diagnostics = db.syntax_errors(id);
+        diagnostics.extend(db.lints(id));
+        problems.insert(path, diagnostics);
+    }
+
+    let files = db.report_files();
+    let mut failed = false;
+    let mut affected = 0;
+    let entries: Vec<_> = roots
+        .iter()
+        .flat_map(|root| impact::entries(root, &graph, &RealFs))
+        .collect();
+    for entry in &entries {
+        let diagnostics: Vec<_> = order
+            .iter()
+            .filter(|path| entry.files.contains(*path))
+            .flat_map(|path| &problems[path.as_path()])
+            .collect();
+        if diagnostics.is_empty() {
+            continue;
+        }
+
+        affected += 1;
+        match entry.output {
+            Some(ref output) => println!("{} (output `{}`):", entry.root.display(), output),
+            None => println!("{}:", entry.root.display()),
+        }
+        for diagnostic in diagnostics {
+            let ((line, column), _) = range(&files, diagnostic);
+            let file = files.name(diagnostic.primary_label.file_id);
+            let severity = severity_name(diagnostic.severity);
+            println!(
+                "  {}:{}:{}: {}: {}",
+                file,
+                line + 1,
+                column + 1,
+                severity,
+                diagnostic.message
+            );
+            failed |= matches!(diagnostic.severity, Severity::Bug | Severity::Error);
+        }
+    }
+
+    println!(
+        "{} of {} entry point(s) affected by problems in {} file(s)",
+        affected,
+        entries.len(),
+        problems.values().filter(|d| !d.is_empty()).count()
+    );
+    Ok(if failed { PROBLEMS_FOUND } else { SUCCESS })
+}
+
+fn dead_files(paths: &[PathBuf], mut entries: Vec<PathBuf>) -> io::Result<i32> {
+    if entries.is_empty() {
+        entries = paths
+            .iter()
+            .flat_map(|dir| ENTRY_POINTS.iter().map(move |name| dir.join(name)))
+            .filter(|path| path.is_file())
+            .collect();
+    }
+    if entries.is_empty() {
+        eprintln!("no entry points found, pass them with `--entry`");
+        return Ok(INVALID_INPUT);
+    }
+
+    let dead = imports::unreferenced(&collect(paths)?, &entries, &RealFs)?;
+    for path in &dead {
+        println!("{}", path.display());
+    }
+    Ok(if dead.is_empty() {
+        SUCCESS
+    } else {
+        PROBLEMS_FOUND
+    })
+}
+
+fn bump_version(path: &Path, version: &str, prefetch: bool, dry_run: bool) -> io::Result<i32> {
+    let text = fs::read_to_string(path)?;
+    let file = match parse_source_file(&text) {
+        Ok(file) => file,
+        Err(errors) => return report_errors(path, &text, errors),
+    };
+
+    let prefetcher: bump::Prefetch = &bump::nix_prefetch_url;
+    let prefetcher = Some(prefetcher).filter(|_| prefetch);
+    let changes = match bump::bump(&text, &file, version, prefetcher) {
+        Ok(changes) => changes,
+        Err(message) => {
+            eprintln!("{}: {}", path.display(), message);
+            return Ok(PROBLEMS_FOUND);
+        }
+    };
+
+    for change in &changes {
+        let line = text[..change.span.start().to_usize()].matches('\n').count() + 1;
+        let (attr, old, new) = (&change.attr, &change.old, &change.new);
+        println!("{}:{}: {}: {} -> {}", path.display(), line, attr, old, new);
+    }
+    let mut edited = text.clone();
+    for change in changes.iter().rev() {
+        let range = change.span.start().to_usize()..change.span.end().to_usize();
+        edited.replace_range(range, &change.new);
+    }
+    if !dry_run {
+        fs::write(path, edited)?;
+    }
+    Ok(SUCCESS)
+}
+
+fn explain_code(code: &str) -> i32 {
+    match explain::explain(code) {
+        Some(explanation) => {
+            println!("{}", explanation);
+            SUCCESS
+        }
+        None => {
+            eprintln!("error: no explanation for diagnostic code `{}`", code);
+            INVALID_INPUT
+        }
+    }
+}
+
+fn replay(path: &Path, settle: Duration) -> io::Result<i32> {
+    let session = Session::load(path)?;
+    let replayed = session::replay(&session, settle)?;
+    let differences = session::compare(&session, &replayed);
+    for difference in &differences {
+        println!("{}", difference);
+    }
+
+    eprintln!(
+        "replayed {} messages, {} differences",
+        session.incoming.len(),
+        differences.len()
+    );
+    Ok(if differences.is_empty() {
+        SUCCESS
+    } else {
+        PROBLEMS_FOUND
+    })
+}
+
+/// Expands directories into the `.nix` files they contain, in a stable order.
+fn collect(paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut entries = fs::read_dir(path)?
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<io::Result<Vec<_>>>()?;
+            entries.sort();
+            let nested: Vec<_> = entries
+                .into_iter()
+                .filter(|p| p.is_dir() || p.extension().is_some_and(|ext| ext == "nix"))
+                .collect();
+            files.extend(collect(&nested)?);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    Ok(files)
+}
+
+fn severity_name(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Bug | Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Note | Severity::Help => "note",
+    }
+}
+
+/// Returns the zero-based line and column of the start and end of the primary label.
+fn range(files: &Files, diagnostic: &Diagnostic) -> ((usize, usize), (usize, usize)) {
+    let label = &diagnostic.primary_label;
+    let position = |index| {
+        files
+            .location(label.file_id, index)
+            .map(|loc| (loc.line.to_usize(), loc.column.to_usize()))
+            .unwrap_or_default()
+    };
+    (position(label.span.start()), position(label.span.end()))
+}
+
+fn to_json(files: &Files, diagnostic: &Diagnostic) -> Value {
+    let file: FileId = diagnostic.primary_label.file_id;
+    let ((start_line, start_col), (end_line, end_col)) = range(files, diagnostic);
+    json!({
+        "file": files.name(file),
+        "severity": severity_name(diagnostic.severity),
+        "code": diagnostic.code,
+        "message": diagnostic.message,
+        "notes": diagnostic.notes,
+        "range": {
+            "start": { "line": start_line, "character": start_col },
+            "end": { "line": end_line, "character": end_col },
+        },
+    })
+}
+
+/// Renders the diagnostics as a SARIF 2.1.0 log, as understood by code scanning services.
+fn to_sarif(files: &Files, diagnostics: &[Diagnostic]) -> Value {
+    let results: Vec<_> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let file = diagnostic.primary_label.file_id;
+            let ((start_line, start_col), (end_line, end_col)) = range(files, diagnostic);
+            json!({
+                "ruleId": diagnostic.code.clone().unwrap_or_else(|| "nix".to_string()),
+                "level": severity_name(diagnostic.severity),
+                "message": { "text": diagnostic.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": files.name(file) },
+                        "region": {
+                            "startLine": start_line + 1,
+                            "startColumn": start_col + 1,
+                            "endLine": end_line + 1,
+                            "endColumn": end_col + 1,
+                        },
+                    },
+                }],
+            })
+        })
+        .collect();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "nix-language-server",
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+            },
+            "results": results,
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_sarif() {
+        let mut db = Database::new();
+        let id = db.add_file("default.nix", "let x = 1; in y");
+        let diagnostics = db.lints(id);
+        let sarif = to_sarif(&db.report_files(), &diagnostics);
+
+        let result = &sarif["runs"][0]["results"][0];
+        assert_eq!(result["level"], "error");
+        let region = &result["locations"][0]["physicalLocation"]["region"];
+        assert_eq!(region["startLine"], 1);
+        assert_eq!(region["startColumn"], 15);
+    }
+
+    #[test]
+    fn applies_suggested_fixes() {
+        let mut db = Database::new();
+        let id = db.add_file("default.nix", "let value = 1; in valu + valu");
+        let parse = db.parse(id);
+        let file = match *parse {
+            Ok(ref partial) => partial.value(),
+            Err(_) => None,
+        };
+        let path = Path::new("default.nix");
+        let fixes: Vec<_> = db
+            .lints(id)
+            .iter()
+            .filter_map(|d| fix(db.text(id), path, file, d))
+            .collect();
+        let (text, count) = apply_fixes(db.text(id), fixes).unwrap();
+        assert_eq!(text, "let value = 1; in value + value");
+        assert_eq!(count, 2);
+        assert_eq!(apply_fixes(&text, Vec::new()), None);
+    }
+}
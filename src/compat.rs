@@ -0,0 +1,176 @@
+//! Checks that a file only uses builtins available in the Nix version it targets.
+//!
+//! The target is configured through the `nixVersion` setting or the `--nix-version` flag, and
+//! nothing is checked without one. Uses guarded by `builtins ? name` anywhere in the file, or given
+//! a fallback with `builtins.name or ...`, are assumed to be handled and are not reported.
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use codespan::FileId;
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use nix_parser::ast::visit::{descendants, walk};
+use nix_parser::ast::{BinaryOp, Expr, SourceFile};
+use nix_parser::HasSpan;
+use serde_json::Value;
+
+use crate::security::builtin_name;
+
+/// A Nix release, compared by its major and minor version.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl Version {
+    pub const fn new(major: u32, minor: u32) -> Self {
+        Version { major, minor }
+    }
+
+    /// Reads the `nixVersion` setting from initialization options or workspace settings, which may
+    /// nest it under a `nix` section.
+    pub fn from_settings(settings: &Value) -> Option<Self> {
+        let section = settings.get("nix").unwrap_or(settings);
+        section.get("nixVersion")?.as_str()?.parse().ok()
+    }
+}
+
+impl FromStr for Version {
+    type Err = String;
+
+    /// Parses versions such as `2.3` or `2.18.1`, ignoring the patch version.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().split('.').map(str::parse::<u32>);
+        match (parts.next(), parts.next()) {
+            (Some(Ok(major)), Some(Ok(minor))) if parts.all(|part| part.is_ok()) => {
+                Ok(Version::new(major, minor))
+            }
+            _ => Err(format!("invalid Nix version `{}`", s)),
+        }
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(fmt, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Builtins added after Nix 2.0, with the release which introduced them.
+pub const BUILTINS: &[(&str, Version)] = &[
+    ("fromTOML", Version::new(2, 1)),
+    ("ceil", Version::new(2, 4)),
+    ("fetchTree", Version::new(2, 4)),
+    ("floor", Version::new(2, 4)),
+    ("getFlake", Version::new(2, 4)),
+    ("groupBy", Version::new(2, 5)),
+    ("zipAttrsWith", Version::new(2, 6)),
+    ("fetchClosure", Version::new(2, 8)),
+    ("break", Version::new(2, 9)),
+    ("traceVerbose", Version::new(2, 10)),
+    ("readFileType", Version::new(2, 14)),
+    ("convertHash", Version::new(2, 21)),
+    ("warn", Version::new(2, 23)),
+];
+
+/// Names of the builtins above which are also in scope without `builtins.`.
+const GLOBALS: &[&str] = &["fetchTree", "fromTOML"];
+
+/// Returns the release which introduced the builtin `name`, if it is newer than Nix 2.0.
+pub fn since(name: &str) -> Option<Version> {
+    BUILTINS
+        .iter()
+        .find(|&&(builtin, _)| builtin == name)
+        .map(|&(_, version)| version)
+}
+
+/// Reports the builtins used in `file` which are not available in `target`.
+pub fn check(id: FileId, file: &SourceFile, target: Version) -> Vec<Diagnostic> {
+    let guarded: Vec<_> = descendants(file.expr())
+        .filter_map(|expr| match *expr {
+            Expr::Binary(ref binary) if binary.op() == BinaryOp::HasAttr => {
+                match (binary.left(), binary.right()) {
+                    (Expr::Ident(ref base), Expr::Ident(ref name))
+                        if base.as_str() == "builtins" =>
+                    {
+                        Some(name.as_str())
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+        .collect();
+
+    let mut diagnostics = Vec::new();
+    walk(file.expr(), |expr, enclosing| {
+        let name = match builtin_name(expr) {
+            Some(name) => name,
+            None => match *expr {
+                Expr::Ident(ref ident) if GLOBALS.contains(&ident.as_str()) => ident.as_str(),
+                _ => return,
+            },
+        };
+        let version = match since(name) {
+            Some(version) if version > target && !guarded.contains(&name) => version,
+            _ => return,
+        };
+        let has_fallback = enclosing.last().is_some_and(|parent| match **parent {
+            Expr::Or(ref or) => std::ptr::eq(or.expr(), expr),
+            _ => false,
+        });
+        if has_fallback {
+            return;
+        }
+
+        let label = Label::new(id, expr.span(), format!("requires Nix {}", version));
+        let message = format!("`{}` is not available in Nix {}", name, target);
+        diagnostics.push(
+            Diagnostic::new_warning(message, label)
+                .with_code("unsupported-builtin")
+                .with_notes(vec![format!(
+                    "guard it with `builtins ? {}` to support older versions",
+                    name
+                )]),
+        );
+    });
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_newer_builtins() {
+        assert_eq!("2.18.1".parse(), Ok(Version::new(2, 18)));
+        assert!("2".parse::<Version>().is_err());
+
+        let source = r#"{
+  a = builtins.getFlake "nixpkgs";
+  b = fetchTree { type = "github"; };
+  c = __groupBy f [ ];
+  e = if builtins ? warn then builtins.warn "x" y else y;
+  f = builtins.fromTOML "";
+}"#;
+        let mut files = codespan::Files::new();
+        let id = files.add("test.nix", source);
+        let file: SourceFile = source.parse().unwrap();
+
+        let names = |target| -> Vec<_> {
+            check(id, &file, target)
+                .into_iter()
+                .map(|d| {
+                    let span = d.primary_label.span;
+                    source[span.start().to_usize()..span.end().to_usize()].to_owned()
+                })
+                .collect()
+        };
+        assert_eq!(
+            names(Version::new(2, 3)),
+            ["builtins.getFlake", "fetchTree", "__groupBy"]
+        );
+        assert!(names(Version::new(2, 18)).is_empty());
+    }
+}
@@ -0,0 +1,272 @@
+//! Evaluation of constant expressions, without the Nix evaluator.
+//!
+//! Expressions built from number, string, boolean and `null` literals with arithmetic,
+//! comparison, logical and string concatenation operators are folded into their value, as are
+//! `if` expressions whose condition folds. Anything else, such as a variable or an operation which
+//! fails at evaluation time like a division by zero, is not constant.
+//!
+//! The values power hovers showing what an expression evaluates to, and warnings about `if`
+//! conditions which are always true or always false.
+
+use std::fmt::{self, Display, Formatter};
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use nix_parser::ast::tokens::{Literal, StringKind};
+use nix_parser::ast::visit::{descendants, path_at};
+use nix_parser::ast::{BinaryOp, Expr, ExprString, SourceFile, StringFragment, UnaryOp};
+use nix_parser::HasSpan;
+
+/// The value of a constant expression.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+impl Display for Value {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match *self {
+            Value::Null => write!(fmt, "null"),
+            Value::Bool(value) => write!(fmt, "{}", value),
+            Value::Int(value) => write!(fmt, "{}", value),
+            Value::Float(value) => write!(fmt, "{:?}", value),
+            Value::String(ref text) => {
+                let fragment = StringFragment::Literal(text.clone(), Span::initial());
+                let string = ExprString::new(vec![fragment], StringKind::Normal, Span::initial());
+                write!(fmt, "{}", string)
+            }
+        }
+    }
+}
+
+/// Returns the value of `expr`, if it is constant.
+pub fn evaluate(expr: &Expr) -> Option<Value> {
+    match *expr {
+        Expr::Literal(ref literal) => match *literal {
+            Literal::Null(_) => Some(Value::Null),
+            Literal::Boolean(value, _) => Some(Value::Bool(value)),
+            Literal::Integer(value, _) => Some(Value::Int(value)),
+            Literal::Float(value, _) => Some(Value::Float(value)),
+            _ => None,
+        },
+        Expr::String(ref string) => {
+            let mut text = String::new();
+            for fragment in string.fragments() {
+                match *fragment {
+                    StringFragment::Literal(ref literal, _) => text.push_str(literal),
+                    StringFragment::Interpolation(ref interp) => match evaluate(interp.inner())? {
+                        Value::String(inner) => text.push_str(&inner),
+                        _ => return None,
+                    },
+                }
+            }
+            Some(Value::String(text))
+        }
+        Expr::Paren(ref paren) => evaluate(paren.expr()),
+        Expr::Unary(ref unary) => match (unary.op(), evaluate(unary.expr())?) {
+            (UnaryOp::Not, Value::Bool(value)) => Some(Value::Bool(!value)),
+            (UnaryOp::Neg, Value::Int(value)) => value.checked_neg().map(Value::Int),
+            (UnaryOp::Neg, Value::Float(value)) => Some(Value::Float(-value)),
+            _ => None,
+        },
+        Expr::Binary(ref binary) => {
+            let op = binary.op();
+            let left = evaluate(binary.left());
+            // The right operand of a logical operator is only evaluated if the left one does not
+            // decide the result.
+            match (op, &left) {
+                (BinaryOp::And, Some(Value::Bool(false))) => return Some(Value::Bool(false)),
+                (BinaryOp::Or, Some(Value::Bool(true))) => return Some(Value::Bool(true)),
+                (BinaryOp::Impl, Some(Value::Bool(false))) => return Some(Value::Bool(true)),
+                _ => {}
+            }
+            binary_op(op, left?, evaluate(binary.right())?)
+        }
+        Expr::If(ref if_else) => match evaluate(if_else.condition())? {
+            Value::Bool(true) => evaluate(if_else.body()),
+            Value::Bool(false) => evaluate(if_else.fallback()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns the value of `expr` if it is a constant boolean.
+pub fn evaluate_bool(expr: &Expr) -> Option<bool> {
+    match evaluate(expr)? {
+        Value::Bool(value) => Some(value),
+        _ => None,
+    }
+}
+
+fn binary_op(op: BinaryOp, left: Value, right: Value) -> Option<Value> {
+    use self::Value::*;
+
+    let value = match (op, left, right) {
+        (BinaryOp::And, Bool(_), Bool(right)) => Bool(right),
+        (BinaryOp::Or, Bool(_), Bool(right)) => Bool(right),
+        (BinaryOp::Impl, Bool(_), Bool(right)) => Bool(right),
+        (BinaryOp::Add, String(left), String(right)) => String(left + &right),
+        (BinaryOp::Add, Int(left), Int(right)) => Int(left.checked_add(right)?),
+        (BinaryOp::Sub, Int(left), Int(right)) => Int(left.checked_sub(right)?),
+        (BinaryOp::Mul, Int(left), Int(right)) => Int(left.checked_mul(right)?),
+        (BinaryOp::Div, Int(left), Int(right)) => Int(left.checked_div(right)?),
+        (BinaryOp::Eq, left, right) => Bool(equal(&left, &right)),
+        (BinaryOp::NotEq, left, right) => Bool(!equal(&left, &right)),
+        (op, String(left), String(right)) => Bool(compare(op, Some(left.cmp(&right)))?),
+        (op, left, right) => {
+            let (left, right, int) = match (left, right) {
+                (Int(left), Int(right)) => (left as f64, right as f64, true),
+                (Int(left), Float(right)) => (left as f64, right, false),
+                (Float(left), Int(right)) => (left, right as f64, false),
+                (Float(left), Float(right)) => (left, right, false),
+                _ => return None,
+            };
+            match op {
+                BinaryOp::Add if !int => Float(left + right),
+                BinaryOp::Sub if !int => Float(left - right),
+                BinaryOp::Mul if !int => Float(left * right),
+                BinaryOp::Div if !int && right != 0.0 => Float(left / right),
+                _ => Bool(compare(op, left.partial_cmp(&right))?),
+            }
+        }
+    };
+    Some(value)
+}
+
+/// Compares two values with `==`, under which integers equal the floats of the same value.
+fn equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Int(left), Value::Float(right)) => *left as f64 == *right,
+        (Value::Float(left), Value::Int(right)) => *left == *right as f64,
+        (left, right) => left == right,
+    }
+}
+
+/// Applies the comparison operator `op` to the ordering of its operands.
+fn compare(op: BinaryOp, ordering: Option<std::cmp::Ordering>) -> Option<bool> {
+    let ordering = ordering?;
+    match op {
+        BinaryOp::LessThan => Some(ordering.is_lt()),
+        BinaryOp::LessThanEq => Some(ordering.is_le()),
+        BinaryOp::GreaterThan => Some(ordering.is_gt()),
+        BinaryOp::GreaterThanEq => Some(ordering.is_ge()),
+        _ => None,
+    }
+}
+
+/// Returns the outermost constant expression containing `offset`, with its value, unless it is a
+/// literal whose value is plain to see.
+pub fn at(file: &SourceFile, offset: usize) -> Option<(Span, Value)> {
+    let path = path_at(file.expr(), offset);
+    let mut enclosing = path.iter().rev();
+    let mut expr = *enclosing.next()?;
+    let mut value = evaluate(expr)?;
+    for &parent in enclosing {
+        match evaluate(parent) {
+            Some(outer) => value = outer,
+            None => break,
+        }
+        expr = parent;
+    }
+
+    match *expr {
+        Expr::Literal(_) | Expr::String(_) if !interpolated(expr) => None,
+        _ => Some((expr.span(), value)),
+    }
+}
+
+fn interpolated(expr: &Expr) -> bool {
+    match *expr {
+        Expr::String(ref string) => string
+            .fragments()
+            .iter()
+            .any(|fragment| matches!(*fragment, StringFragment::Interpolation(_))),
+        _ => false,
+    }
+}
+
+/// Returns a warning for every `if` whose condition is always true or always false.
+pub fn check(id: FileId, file: &SourceFile) -> Vec<Diagnostic> {
+    descendants(file.expr())
+        .filter_map(|expr| match *expr {
+            Expr::If(ref if_else) => {
+                let cond = if_else.condition();
+                let value = evaluate_bool(cond)?;
+                let (message, unused) = if value {
+                    ("condition is always true", "`else` branch")
+                } else {
+                    ("condition is always false", "`then` branch")
+                };
+                let label = Label::new(id, cond.span(), format!("the {} is never used", unused));
+                Some(Diagnostic::new_warning(message, label).with_code("constant-condition"))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use codespan::Files;
+
+    use super::*;
+
+    fn value(source: &str) -> Option<String> {
+        let file: SourceFile = source.parse().unwrap();
+        evaluate(file.expr()).map(|value| value.to_string())
+    }
+
+    #[test]
+    fn folds_constant_expressions() {
+        assert_eq!(value("2 * (20 + 1)").unwrap(), "42");
+        assert_eq!(value("7 / 2 - -1").unwrap(), "4");
+        assert_eq!(value("1 + 0.5").unwrap(), "1.5");
+        assert_eq!(value(r#""a" + "${"b"}\n""#).unwrap(), r#""ab\n""#);
+        assert_eq!(
+            value("1 < 2 && !(2 == 2.0) -> null != null").unwrap(),
+            "true"
+        );
+        assert_eq!(value(r#""abc" >= "abd""#).unwrap(), "false");
+        assert_eq!(value("false && x").unwrap(), "false");
+        assert_eq!(value("if 1 > 2 then x else 3").unwrap(), "3");
+
+        assert_eq!(value("true && x"), None);
+        assert_eq!(value("1 / 0"), None);
+        assert_eq!(value("9223372036854775807 + 1"), None);
+        assert_eq!(value(r#""a${1}""#), None);
+        assert_eq!(value(r#""a" + 1"#), None);
+    }
+
+    #[test]
+    fn finds_outermost_constant_at_offset() {
+        let source = "{ a = 1 + 2 * 3; b = x + 1; c = 4; }";
+        let file: SourceFile = source.parse().unwrap();
+        let (span, value) = at(&file, source.find('2').unwrap()).unwrap();
+        assert_eq!(
+            &source[span.start().to_usize()..span.end().to_usize()],
+            "1 + 2 * 3"
+        );
+        assert_eq!(value, Value::Int(7));
+
+        assert_eq!(at(&file, source.find("1;").unwrap()), None);
+        assert_eq!(at(&file, source.find('4').unwrap()), None);
+    }
+
+    #[test]
+    fn reports_constant_conditions() {
+        let source = "x: if 1 == 1 then x else 0\n";
+        let file: SourceFile = source.parse().unwrap();
+        let id = Files::new().add("default.nix", source);
+        let diagnostics = check(id, &file);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "condition is always true");
+
+        let file: SourceFile = "x: if x then 1 else 0\n".parse().unwrap();
+        assert!(check(id, &file).is_empty());
+    }
+}
@@ -0,0 +1,343 @@
+//! A Debug Adapter Protocol server for evaluating Nix files, started with the `dap` subcommand.
+//!
+//! Evaluation is delegated to `nix-instantiate`, which cannot be paused, so this adapter does not
+//! support breakpoints, stepping or inspecting environments: breakpoints are reported back as
+//! unverified. What it does support is launching a file and stopping on the first evaluation
+//! error, e.g. a failed `assert` or infinite recursion, with the innermost location of the error
+//! as the only stack frame. While stopped, the console evaluates expressions with `program` bound
+//! to the launched file.
+
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+use serde_json::{json, Value};
+
+use crate::eval::{self, EvalError};
+
+const THREAD_ID: i64 = 1;
+
+/// The state of a debugging session.
+pub struct Adapter {
+    seq: i64,
+    program: Option<PathBuf>,
+    error: Option<EvalError>,
+    done: bool,
+    evaluate: fn(&str) -> Result<String, Vec<EvalError>>,
+}
+
+impl Adapter {
+    pub fn new() -> Self {
+        Adapter::with_evaluator(eval::evaluate)
+    }
+
+    fn with_evaluator(evaluate: fn(&str) -> Result<String, Vec<EvalError>>) -> Self {
+        Adapter {
+            seq: 0,
+            program: None,
+            error: None,
+            done: false,
+            evaluate,
+        }
+    }
+
+    /// Handles a request from the client, returning the response and any events to send.
+    pub fn handle(&mut self, request: &Value) -> Vec<Value> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let args = &request["arguments"];
+        let mut events = Vec::new();
+
+        let result = match command {
+            "initialize" => {
+                events.push(("initialized", json!({})));
+                Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsExceptionInfoRequest": true,
+                }))
+            }
+            "launch" => match args["program"].as_str() {
+                Some(program) => {
+                    let cwd = env::current_dir().unwrap_or_default();
+                    self.program = Some(cwd.join(program));
+                    Ok(json!({}))
+                }
+                None => Err("missing `program` launch argument".to_owned()),
+            },
+            "setBreakpoints" => {
+                let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+                let breakpoints: Vec<_> = requested
+                    .iter()
+                    .map(|bp| {
+                        json!({
+                            "verified": false,
+                            "line": bp["line"],
+                            "message": "nix-instantiate cannot pause evaluation",
+                        })
+                    })
+                    .collect();
+                Ok(json!({ "breakpoints": breakpoints }))
+            }
+            "setExceptionBreakpoints" => Ok(json!({})),
+            "configurationDone" => {
+                events.extend(self.start());
+                Ok(json!({}))
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(json!({ "scopes": [] })),
+            "variables" => Ok(json!({ "variables": [] })),
+            "exceptionInfo" => Ok(json!({
+                "exceptionId": "EvalError",
+                "description": self.error.as_ref().map(|e| e.message.clone()),
+                "breakMode": "always",
+            })),
+            "evaluate" => {
+                let expression = args["expression"].as_str().unwrap_or_default();
+                self.console(expression)
+                    .map(|result| json!({ "result": result, "variablesReference": 0 }))
+            }
+            "continue" => {
+                events.push(("terminated", json!({})));
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" | "stepIn" | "stepOut" | "pause" => {
+                Err("stepping is not supported by nix-instantiate".to_owned())
+            }
+            "disconnect" | "terminate" => {
+                self.done = true;
+                Ok(json!({}))
+            }
+            _ => Err(format!("unsupported request `{}`", command)),
+        };
+
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = Value::String(message),
+        }
+
+        let mut messages = vec![self.message(response)];
+        for (event, body) in events {
+            let event = json!({ "type": "event", "event": event, "body": body });
+            messages.push(self.message(event));
+        }
+
+        messages
+    }
+
+    /// Returns `true` once the client has ended the session.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn message(&mut self, mut message: Value) -> Value {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        message
+    }
+
+    /// Evaluates the launched program, returning the events describing the outcome.
+    fn start(&mut self) -> Vec<(&'static str, Value)> {
+        let program = match self.program {
+            Some(ref program) => program,
+            None => return vec![("terminated", json!({}))],
+        };
+
+        let expr = format!("import {}", nix_string(&program.to_string_lossy()));
+        match (self.evaluate)(&expr) {
+            Ok(value) => {
+                let output = json!({ "category": "stdout", "output": value + "\n" });
+                vec![
+                    ("output", output),
+                    ("exited", json!({ "exitCode": 0 })),
+                    ("terminated", json!({})),
+                ]
+            }
+            Err(errors) => {
+                let error = errors.into_iter().next().unwrap_or_else(|| EvalError {
+                    message: "evaluation failed".to_owned(),
+                    location: None,
+                });
+                let stopped = json!({
+                    "reason": "exception",
+                    "description": "Evaluation error",
+                    "text": error.message,
+                    "threadId": THREAD_ID,
+                    "allThreadsStopped": true,
+                });
+                self.error = Some(error);
+                vec![("stopped", stopped)]
+            }
+        }
+    }
+
+    fn stack_trace(&self) -> Value {
+        let frames: Vec<_> = self
+            .error
+            .iter()
+            .map(|error| match error.location {
+                Some((ref path, line, column)) => json!({
+                    "id": 0,
+                    "name": error.message,
+                    "source": { "path": path },
+                    "line": line,
+                    "column": column,
+                }),
+                None => json!({ "id": 0, "name": error.message, "line": 0, "column": 0 }),
+            })
+            .collect();
+
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    fn console(&self, expression: &str) -> Result<String, String> {
+        let expr = match self.program {
+            Some(ref program) => {
+                let program = nix_string(&program.to_string_lossy());
+                format!("let program = import {}; in ({})", program, expression)
+            }
+            None => expression.to_owned(),
+        };
+
+        (self.evaluate)(&expr).map_err(|errors| {
+            let messages: Vec<_> = errors.into_iter().map(|e| e.message).collect();
+            messages.join("\n")
+        })
+    }
+}
+
+/// Quotes `text` as a Nix string literal.
+fn nix_string(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${");
+    format!("\"{}\"", escaped)
+}
+
+/// Serves a debugging session over a pair of streams, using the base protocol framing of DAP.
+pub fn serve<R: BufRead, W: Write>(mut input: R, mut output: W) -> io::Result<()> {
+    let mut adapter = Adapter::new();
+    while !adapter.is_done() {
+        let request = match read_message(&mut input)? {
+            Some(request) => request,
+            None => break,
+        };
+
+        for message in adapter.handle(&request) {
+            let body = message.to_string();
+            write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        }
+        output.flush()?;
+    }
+
+    Ok(())
+}
+
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_nix(expr: &str) -> Result<String, Vec<EvalError>> {
+        if expr.contains("broken.nix") && !expr.starts_with("let") {
+            Err(vec![EvalError {
+                message: "assertion 'false' failed".to_owned(),
+                location: Some((PathBuf::from("/src/broken.nix"), 3, 5)),
+            }])
+        } else {
+            Ok(format!("<{}>", expr))
+        }
+    }
+
+    fn request(adapter: &mut Adapter, command: &str, arguments: Value) -> Vec<Value> {
+        let request =
+            json!({ "seq": 1, "type": "request", "command": command, "arguments": arguments });
+        adapter.handle(&request)
+    }
+
+    #[test]
+    fn stops_on_evaluation_errors() {
+        let mut adapter = Adapter::with_evaluator(fake_nix);
+        let init = request(&mut adapter, "initialize", json!({}));
+        assert_eq!(init[1]["event"], "initialized");
+
+        request(
+            &mut adapter,
+            "launch",
+            json!({ "program": "/src/broken.nix" }),
+        );
+        let bps = request(
+            &mut adapter,
+            "setBreakpoints",
+            json!({ "breakpoints": [{ "line": 2 }] }),
+        );
+        assert_eq!(bps[0]["body"]["breakpoints"][0]["verified"], false);
+
+        let done = request(&mut adapter, "configurationDone", json!({}));
+        assert_eq!(done[1]["event"], "stopped");
+        assert_eq!(done[1]["body"]["text"], "assertion 'false' failed");
+
+        let trace = request(&mut adapter, "stackTrace", json!({ "threadId": 1 }));
+        let frame = &trace[0]["body"]["stackFrames"][0];
+        assert_eq!(
+            (frame["line"].as_u64(), frame["column"].as_u64()),
+            (Some(3), Some(5))
+        );
+        assert_eq!(frame["source"]["path"], "/src/broken.nix");
+
+        let eval = request(
+            &mut adapter,
+            "evaluate",
+            json!({ "expression": "program.x" }),
+        );
+        assert_eq!(
+            eval[0]["body"]["result"],
+            "<let program = import \"/src/broken.nix\"; in (program.x)>"
+        );
+
+        let step = request(&mut adapter, "next", json!({}));
+        assert_eq!(step[0]["success"], false);
+        request(&mut adapter, "disconnect", json!({}));
+        assert!(adapter.is_done());
+    }
+
+    #[test]
+    fn frames_messages() {
+        let body = r#"{"seq":1,"type":"request","command":"initialize"}"#;
+        let input = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let message = read_message(&mut input.as_bytes()).unwrap().unwrap();
+        assert_eq!(message["command"], "initialize");
+        assert!(read_message(&mut "".as_bytes()).unwrap().is_none());
+    }
+}
@@ -0,0 +1,758 @@
+//! Incremental analysis database.
+//!
+//! All analyses performed by the server are expressed as queries over the source text of each
+//! file, forming a pipeline of `text -> parse -> unresolved names -> diagnostics`. Every query
+//! result is memoized along with the queries it read while being computed. When a file changes,
+//! only the queries which transitively depend on its text are recomputed, and only once they are
+//! requested again.
+//!
+//! If a recomputed value turns out to be equal to the previous one, the old value is kept and
+//! its dependents are left untouched (this is known as "backdating"). This is what keeps, e.g.,
+//! editing a comment from republishing identical diagnostics.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use codespan::{FileId, Files, Span};
+use codespan_lsp::make_lsp_severity;
+use codespan_reporting::diagnostic::Diagnostic as Report;
+use nix_parser::ast::SourceFile;
+use nix_parser::error::Errors;
+use nix_parser::parser::{parse_source_file_incremental, parse_source_file_partial, Partial};
+use tower_lsp::lsp_types::{
+    Diagnostic, DiagnosticRelatedInformation, Location, NumberOrString, Url,
+};
+
+use crate::attrs;
+use crate::compat::{self, Version};
+use crate::constant;
+use crate::failures;
+use crate::flake::Flake;
+use crate::lint;
+use crate::metrics::METRICS;
+use crate::naming;
+use crate::resolve::{self, reresolve, resolve, Unresolved};
+use crate::role::{self, Role};
+use crate::security;
+use crate::suppress;
+use crate::text::Text;
+use crate::todo;
+use crate::unused;
+
+/// A logical timestamp, incremented every time an input changes.
+pub type Revision = u64;
+
+/// The result of parsing a source file.
+pub type ParseResult = Result<Partial<SourceFile>, Errors>;
+
+/// The queries which can be memoized in the database.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum Query {
+    Text,
+    NixVersion,
+    Naming,
+    Todos,
+    Roles,
+    Parse,
+    Unresolved,
+    Diagnostics,
+}
+
+impl Query {
+    fn name(self) -> &'static str {
+        match self {
+            Query::Text => "text",
+            Query::NixVersion => "nix version",
+            Query::Naming => "naming",
+            Query::Todos => "todos",
+            Query::Roles => "roles",
+            Query::Parse => "parse",
+            Query::Unresolved => "unresolved",
+            Query::Diagnostics => "diagnostics",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Memo<V> {
+    value: V,
+    /// The revision at which this memo was last checked to be up to date.
+    verified_at: Revision,
+    /// The revision at which the value of this memo last changed.
+    changed_at: Revision,
+    /// The queries read while computing this value.
+    dependencies: Vec<(Query, FileId)>,
+}
+
+type Table<V> = RefCell<HashMap<FileId, Memo<V>>>;
+
+#[derive(Debug)]
+pub struct Database {
+    revision: Revision,
+    /// The names of the files, which hand out their ids. Their text is kept in `texts` instead.
+    files: Files,
+    /// The text of each file, shared with the snapshots of open documents.
+    texts: HashMap<FileId, Arc<Text>>,
+    text_changed_at: HashMap<FileId, Revision>,
+    nix_version: Option<Version>,
+    nix_version_changed_at: Revision,
+    naming: naming::Config,
+    naming_changed_at: Revision,
+    todos: bool,
+    todos_changed_at: Revision,
+    roles: role::Overrides,
+    roles_changed_at: Revision,
+    parse: Table<Arc<ParseResult>>,
+    unresolved: Table<Arc<Vec<Unresolved>>>,
+    diagnostics: Table<Arc<Vec<Diagnostic>>>,
+    /// Stack of dependencies recorded by the queries currently being executed.
+    active: RefCell<Vec<Vec<(Query, FileId)>>>,
+}
+
+impl Database {
+    pub fn new() -> Self {
+        Database {
+            revision: 0,
+            files: Files::new(),
+            texts: HashMap::new(),
+            text_changed_at: HashMap::new(),
+            nix_version: None,
+            nix_version_changed_at: 0,
+            naming: naming::Config::default(),
+            naming_changed_at: 0,
+            todos: false,
+            todos_changed_at: 0,
+            roles: role::Overrides::default(),
+            roles_changed_at: 0,
+            parse: Table::default(),
+            unresolved: Table::default(),
+            diagnostics: Table::default(),
+            active: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Returns the current revision of the database.
+    pub fn revision(&self) -> Revision {
+        self.revision
+    }
+
+    /// Returns the name of the given file.
+    pub fn name(&self, id: FileId) -> &str {
+        self.files.name(id)
+    }
+
+    /// Returns a copy of every file along with its text, for rendering reports with
+    /// `codespan-reporting`, which needs to own the text.
+    pub fn report_files(&self) -> Files {
+        let mut files = Files::new();
+        let mut ids: Vec<_> = self.texts.keys().collect();
+        ids.sort();
+        for &id in ids {
+            let copy = files.add(self.files.name(id), self.texts[&id].as_str());
+            debug_assert_eq!(copy, id);
+        }
+        files
+    }
+
+    /// Adds a new source file with the given name, which should be its URI.
+    pub fn add_file(&mut self, name: impl Into<String>, text: impl Into<String>) -> FileId {
+        self.revision += 1;
+        let id = self.files.add(name, String::new());
+        self.texts.insert(id, Arc::new(Text::new(text.into())));
+        self.text_changed_at.insert(id, self.revision);
+        id
+    }
+
+    /// Replaces the text of the given file, invalidating all queries which depend on it.
+    pub fn set_text(&mut self, id: FileId, text: impl Into<String>) {
+        let text = text.into();
+        if self.texts[&id].as_str() == text {
+            return;
+        }
+
+        self.revision += 1;
+        self.texts.insert(id, Arc::new(Text::new(text)));
+        self.text_changed_at.insert(id, self.revision);
+    }
+
+    /// Frees the text of the given file and the results of the queries on it, once it is no longer
+    /// used. The file itself cannot be removed, so its id stays valid and reads as empty.
+    pub fn release(&mut self, id: FileId) {
+        self.set_text(id, "");
+        self.parse.get_mut().remove(&id);
+        self.unresolved.get_mut().remove(&id);
+        self.diagnostics.get_mut().remove(&id);
+    }
+
+    /// Sets the Nix version the files are checked against, invalidating the diagnostics of every
+    /// file. No version is checked against when it is `None`.
+    pub fn set_nix_version(&mut self, version: Option<Version>) {
+        if self.nix_version == version {
+            return;
+        }
+
+        self.revision += 1;
+        self.nix_version = version;
+        self.nix_version_changed_at = self.revision;
+    }
+
+    /// Sets the naming style bindings are checked against, invalidating the diagnostics of every
+    /// file.
+    pub fn set_naming(&mut self, config: naming::Config) {
+        if self.naming == config {
+            return;
+        }
+
+        self.revision += 1;
+        self.naming = config;
+        self.naming_changed_at = self.revision;
+    }
+
+    /// Sets whether `TODO` comments are reported, invalidating the diagnostics of every file.
+    pub fn set_todos(&mut self, enabled: bool) {
+        if self.todos == enabled {
+            return;
+        }
+
+        self.revision += 1;
+        self.todos = enabled;
+        self.todos_changed_at = self.revision;
+    }
+
+    /// Sets the roles configured for file paths, invalidating the diagnostics of every file.
+    pub fn set_roles(&mut self, overrides: role::Overrides) {
+        if self.roles == overrides {
+            return;
+        }
+
+        self.revision += 1;
+        self.roles = overrides;
+        self.roles_changed_at = self.revision;
+    }
+
+    /// Replaces the given span of a file's text.
+    ///
+    /// If the file parsed without errors before the edit, it is parsed again right away with
+    /// [`parse_source_file_incremental`], which only reparses the region of the syntax tree
+    /// enclosing the edit when it can. Names are then only resolved again within the function body
+    /// or binding value enclosing the edit.
+    pub fn edit(&mut self, id: FileId, span: Span, text: &str) {
+        let old = self.texts[&id].as_str();
+        let (start, end) = (span.start().to_usize(), span.end().to_usize());
+        let mut source = String::with_capacity(old.len() - (end - start) + text.len());
+        source.push_str(&old[..start]);
+        source.push_str(text);
+        source.push_str(&old[end..]);
+
+        let text_changed_at = self.text_changed_at.get(&id).cloned().unwrap_or(0);
+        let previous = match self.parse.get_mut().get(&id) {
+            Some(memo) if memo.verified_at >= text_changed_at => match *memo.value {
+                Ok(ref partial) if !partial.has_errors() => partial
+                    .value()
+                    .map(|tree| (memo.value.clone(), tree.clone())),
+                _ => None,
+            },
+            _ => None,
+        };
+        let reparsed = previous.map(|(old, tree)| {
+            let new = METRICS.time(Query::Parse.name(), || {
+                parse_source_file_incremental(tree, &source, span, text.len() as u32)
+            });
+            (old, new)
+        });
+
+        let unresolved = match self.unresolved.get_mut().get(&id) {
+            Some(memo) if memo.verified_at >= text_changed_at => Some(memo.value.clone()),
+            _ => None,
+        };
+
+        self.set_text(id, source);
+        if let Some((old, new)) = reparsed {
+            let tree = new
+                .as_ref()
+                .ok()
+                .filter(|partial| !partial.has_errors())
+                .and_then(|partial| partial.value());
+            let reresolved = unresolved.and_then(|previous| {
+                let old = (*old).as_ref().ok().and_then(|partial| partial.value())?;
+                let value = METRICS.time(Query::Unresolved.name(), || {
+                    reresolve(&previous, old, tree?, span, text.len() as u32)
+                })?;
+                Some((previous, value))
+            });
+
+            let memo = Memo {
+                value: Arc::new(new),
+                verified_at: self.revision,
+                changed_at: self.revision,
+                dependencies: vec![(Query::Text, id)],
+            };
+            self.parse.get_mut().insert(id, memo);
+
+            if let Some((previous, value)) = reresolved {
+                let unresolved = self.unresolved.get_mut();
+                let memo = unresolved
+                    .get_mut(&id)
+                    .expect("memo was removed during reparsing");
+                memo.verified_at = self.revision;
+                memo.dependencies = vec![(Query::Parse, id)];
+                if *previous != value {
+                    memo.value = Arc::new(value);
+                    memo.changed_at = self.revision;
+                }
+            }
+        }
+    }
+
+    /// Returns the source text of the given file.
+    pub fn text(&self, id: FileId) -> &str {
+        self.record(Query::Text, id);
+        self.texts[&id].as_str()
+    }
+
+    /// Returns the source text of the given file, to be kept beyond the next edit without copying
+    /// it.
+    pub fn shared_text(&self, id: FileId) -> Arc<Text> {
+        self.record(Query::Text, id);
+        self.texts[&id].clone()
+    }
+
+    /// Returns the Nix version the given file is checked against.
+    pub fn nix_version(&self, id: FileId) -> Option<Version> {
+        self.record(Query::NixVersion, id);
+        self.nix_version
+    }
+
+    /// Returns the naming style the bindings of the given file are checked against.
+    pub fn naming(&self, id: FileId) -> naming::Config {
+        self.record(Query::Naming, id);
+        self.naming
+    }
+
+    /// Returns whether the `TODO` comments of the given file are reported.
+    pub fn todos(&self, id: FileId) -> bool {
+        self.record(Query::Todos, id);
+        self.todos
+    }
+
+    /// Returns the role of the given file, which enables checks specific to it.
+    pub fn role(&self, id: FileId) -> Role {
+        self.record(Query::Roles, id);
+        let parse = self.parse(id);
+        let file = (*parse).as_ref().ok().and_then(|partial| partial.value());
+        role::detect(self.files.name(id), self.text(id), file, &self.roles)
+    }
+
+    /// Parses the given file.
+    pub fn parse(&self, id: FileId) -> Arc<ParseResult> {
+        // Parse trees compare equal regardless of their spans, so they are never backdated.
+        let compute = |db: &Self| Arc::new(parse_source_file_partial(db.text(id)));
+        self.fetch(Query::Parse, id, &self.parse, compute, |_, _| false)
+    }
+
+    /// Returns all names in the given file which could not be resolved.
+    pub fn unresolved(&self, id: FileId) -> Arc<Vec<Unresolved>> {
+        let compute = |db: &Self| {
+            let parse = db.parse(id);
+            let expr = (*parse).as_ref().ok().and_then(|partial| partial.value());
+            Arc::new(expr.map(resolve).unwrap_or_default())
+        };
+        self.fetch(Query::Unresolved, id, &self.unresolved, compute, |a, b| {
+            a == b
+        })
+    }
+
+    /// Returns the diagnostics for the given file produced by static analysis.
+    pub fn diagnostics(&self, id: FileId) -> Arc<Vec<Diagnostic>> {
+        let compute = |db: &Self| Arc::new(db.compute_diagnostics(id));
+        self.fetch(
+            Query::Diagnostics,
+            id,
+            &self.diagnostics,
+            compute,
+            |a, b| a == b,
+        )
+    }
+
+    /// Returns the syntax errors in the given file.
+    pub fn syntax_errors(&self, id: FileId) -> Vec<Report> {
+        match *self.parse(id) {
+            Ok(ref partial) => partial
+                .errors()
+                .map(|err| err.to_diagnostics(id))
+                .unwrap_or_default(),
+            Err(ref err) => err.to_diagnostics(id),
+        }
+    }
+
+    /// Returns the problems found by static analysis of the given file, such as unresolved names,
+    /// except those suppressed by annotations in the file.
+    pub fn lints(&self, id: FileId) -> Vec<Report> {
+        let unresolved = self.unresolved(id);
+        let mut diagnostics: Vec<_> = unresolved.iter().map(|u| u.to_diagnostic(id)).collect();
+
+        let parse = self.parse(id);
+        let expr = (*parse).as_ref().ok().and_then(|partial| partial.value());
+        let shadows = expr.map(resolve::shadows).unwrap_or_default();
+        diagnostics.extend(shadows.iter().map(|shadow| shadow.to_diagnostic(id)));
+        diagnostics.extend(expr.map(|expr| attrs::check(id, expr)).unwrap_or_default());
+        diagnostics.extend(
+            expr.map(|expr| security::check(id, expr))
+                .unwrap_or_default(),
+        );
+        diagnostics.extend(
+            expr.map(|expr| failures::check(id, expr))
+                .unwrap_or_default(),
+        );
+        diagnostics.extend(
+            expr.map(|expr| constant::check(id, expr))
+                .unwrap_or_default(),
+        );
+        let source = self.text(id);
+        diagnostics.extend(
+            expr.map(|expr| lint::check(id, source, expr))
+                .unwrap_or_default(),
+        );
+        diagnostics.extend(expr.map(|expr| unused::check(id, expr)).unwrap_or_default());
+        if let Some(target) = self.nix_version(id) {
+            let compat = expr.map(|expr| compat::check(id, expr, target));
+            diagnostics.extend(compat.unwrap_or_default());
+        }
+        let config = self.naming(id);
+        let naming = expr.map(|expr| naming::check(id, expr, &config));
+        diagnostics.extend(naming.unwrap_or_default());
+        if self.todos(id) {
+            diagnostics.extend(todo::check(id, source));
+        }
+
+        let role = self.role(id);
+        if role == Role::Flake {
+            let flake = expr.and_then(Flake::analyze).map(|flake| flake.check(id));
+            diagnostics.extend(flake.unwrap_or_default());
+        }
+        diagnostics.extend(
+            expr.map(|expr| role::check(id, source, expr, role))
+                .unwrap_or_default(),
+        );
+
+        suppress::apply(id, source, diagnostics)
+    }
+
+    fn compute_diagnostics(&self, id: FileId) -> Vec<Diagnostic> {
+        let mut diagnostics = self.syntax_errors(id);
+        diagnostics.extend(self.lints(id));
+
+        let name = self.files.name(id);
+        let uri = Url::parse(name).ok();
+        diagnostics
+            .into_iter()
+            .filter_map(|diag| self.lsp_diagnostic(diag, None, uri.as_ref()))
+            .collect()
+    }
+
+    /// Converts a diagnostic to the protocol's, with its notes appended to the message as a
+    /// bulleted list. Its secondary labels are reported as related information in the file at
+    /// `uri`, so it cannot be converted without one if it has any.
+    pub fn lsp_diagnostic(
+        &self,
+        diagnostic: Report,
+        source: Option<String>,
+        uri: Option<&Url>,
+    ) -> Option<Diagnostic> {
+        let label = &diagnostic.primary_label;
+        let range = self.texts[&label.file_id].range(label.span).ok()?;
+
+        let mut message = diagnostic.message;
+        if !diagnostic.notes.is_empty() {
+            message.push_str("\n\n");
+            for note in &diagnostic.notes {
+                for (i, line) in note.lines().enumerate() {
+                    let bullet = if i == 0 { '•' } else { ' ' };
+                    message.push_str(&format!("  {} {}\n", bullet, line.trim_end()));
+                }
+            }
+        }
+
+        let related_information = diagnostic
+            .secondary_labels
+            .into_iter()
+            .map(|label| {
+                Some(DiagnosticRelatedInformation {
+                    location: Location {
+                        uri: uri?.clone(),
+                        range: self.texts[&label.file_id].range(label.span).ok()?,
+                    },
+                    message: label.message,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Diagnostic {
+            range,
+            code: diagnostic.code.map(NumberOrString::String),
+            source,
+            severity: Some(make_lsp_severity(diagnostic.severity)),
+            message,
+            related_information: if related_information.is_empty() {
+                None
+            } else {
+                Some(related_information)
+            },
+        })
+    }
+
+    /// Records a read of the given query by the query currently being executed, if any.
+    fn record(&self, query: Query, id: FileId) {
+        if let Some(frame) = self.active.borrow_mut().last_mut() {
+            frame.push((query, id));
+        }
+    }
+
+    /// Brings the given query up to date, returning the revision at which its value last changed.
+    fn changed_at(&self, query: Query, id: FileId) -> Revision {
+        fn memo_changed_at<V>(table: &Table<V>, id: FileId) -> Revision {
+            table
+                .borrow()
+                .get(&id)
+                .map(|memo| memo.changed_at)
+                .unwrap_or(0)
+        }
+
+        match query {
+            Query::Text => self.text_changed_at.get(&id).cloned().unwrap_or(0),
+            Query::NixVersion => self.nix_version_changed_at,
+            Query::Naming => self.naming_changed_at,
+            Query::Todos => self.todos_changed_at,
+            Query::Roles => self.roles_changed_at,
+            Query::Parse => {
+                self.parse(id);
+                memo_changed_at(&self.parse, id)
+            }
+            Query::Unresolved => {
+                self.unresolved(id);
+                memo_changed_at(&self.unresolved, id)
+            }
+            Query::Diagnostics => {
+                self.diagnostics(id);
+                memo_changed_at(&self.diagnostics, id)
+            }
+        }
+    }
+
+    fn fetch<V, F>(
+        &self,
+        query: Query,
+        id: FileId,
+        table: &Table<V>,
+        compute: F,
+        backdate: fn(&V, &V) -> bool,
+    ) -> V
+    where
+        V: Clone,
+        F: FnOnce(&Self) -> V,
+    {
+        self.record(query, id);
+
+        let stale = match table.borrow().get(&id) {
+            Some(memo) if memo.verified_at == self.revision => {
+                METRICS.cache(query.name(), true);
+                return memo.value.clone();
+            }
+            Some(memo) => Some((memo.verified_at, memo.dependencies.clone())),
+            None => None,
+        };
+
+        if let Some((verified_at, dependencies)) = stale {
+            // Reads performed while verifying dependencies are not dependencies of the caller.
+            self.active.borrow_mut().push(Vec::new());
+            let unchanged = dependencies
+                .iter()
+                .all(|&(query, id)| self.changed_at(query, id) <= verified_at);
+            self.active.borrow_mut().pop();
+
+            if unchanged {
+                let mut table = table.borrow_mut();
+                let memo = table
+                    .get_mut(&id)
+                    .expect("memo was removed during verification");
+                memo.verified_at = self.revision;
+                METRICS.cache(query.name(), true);
+                return memo.value.clone();
+            }
+        }
+
+        METRICS.cache(query.name(), false);
+        self.active.borrow_mut().push(Vec::new());
+        let value = METRICS.time(query.name(), || compute(self));
+        let dependencies = self.active.borrow_mut().pop().unwrap_or_default();
+
+        let mut table = table.borrow_mut();
+        match table.get_mut(&id) {
+            Some(old) if backdate(&old.value, &value) => {
+                old.verified_at = self.revision;
+                old.dependencies = dependencies;
+                old.value.clone()
+            }
+            _ => {
+                let memo = Memo {
+                    value: value.clone(),
+                    verified_at: self.revision,
+                    changed_at: self.revision,
+                    dependencies,
+                };
+                table.insert(id, memo);
+                value
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use codespan_reporting::diagnostic::Label;
+    use tower_lsp::lsp_types::{DiagnosticSeverity, Position};
+
+    use super::*;
+
+    const URI: &str = "file:///tmp/default.nix";
+
+    #[test]
+    fn memoizes_queries() {
+        let mut db = Database::new();
+        let id = db.add_file(URI, "let x = 1; in x + y");
+
+        let parse = db.parse(id);
+        assert!(Arc::ptr_eq(&parse, &db.parse(id)));
+        assert_eq!(db.diagnostics(id).len(), 1);
+
+        let text = db.shared_text(id);
+        db.set_text(id, "let x = 1; in x + y");
+        assert!(Arc::ptr_eq(&parse, &db.parse(id)));
+        assert!(Arc::ptr_eq(&text, &db.shared_text(id)));
+
+        db.set_text(id, "let x = 1; in x");
+        assert!(!Arc::ptr_eq(&parse, &db.parse(id)));
+        assert!(db.diagnostics(id).is_empty());
+
+        db.release(id);
+        assert_eq!(db.text(id), "");
+        assert!(!Arc::ptr_eq(&parse, &db.parse(id)));
+    }
+
+    #[test]
+    fn converts_diagnostics_against_the_shared_text() {
+        let mut db = Database::new();
+        let id = db.add_file(URI, "let\n  é = 1; in é");
+        let uri = Url::parse(URI).unwrap();
+        let diagnostic = Report::new_warning("unused", Label::new(id, Span::new(6, 8), "here"))
+            .with_secondary_labels(vec![Label::new(id, Span::new(17, 19), "used")])
+            .with_notes(vec!["first\nsecond".into()]);
+
+        assert!(db.lsp_diagnostic(diagnostic.clone(), None, None).is_none());
+        let converted = db.lsp_diagnostic(diagnostic, None, Some(&uri)).unwrap();
+        assert_eq!(converted.message, "unused\n\n  • first\n    second\n");
+        assert_eq!(converted.range.start, Position::new(1, 2));
+        assert_eq!(converted.range.end, Position::new(1, 3));
+        let related = &converted.related_information.unwrap()[0];
+        assert_eq!(related.location.range.start, Position::new(1, 12));
+    }
+
+    #[test]
+    fn reparses_edits_in_place() {
+        let mut db = Database::new();
+        let id = db.add_file(URI, "let x = { a = [ 1 ]; }; in x");
+        let parse = db.parse(id);
+
+        db.edit(id, Span::new(16, 17), "y");
+        assert_eq!(db.text(id), "let x = { a = [ y ]; }; in x");
+        let reparsed = db.parse(id);
+        assert!(!Arc::ptr_eq(&parse, &reparsed));
+
+        let expected = parse_source_file_partial(db.text(id)).unwrap();
+        let value = (*reparsed)
+            .as_ref()
+            .ok()
+            .and_then(|partial| partial.value());
+        assert_eq!(value, expected.value());
+        assert_eq!(db.diagnostics(id).len(), 1);
+    }
+
+    #[test]
+    fn reresolves_edited_regions() {
+        let mut db = Database::new();
+        let id = db.add_file(URI, "let f = x: [ x y ]; in f z");
+        assert_eq!(db.unresolved(id).len(), 2);
+
+        db.edit(id, Span::new(15, 16), "x");
+        let parse = db.parse(id);
+        let tree = (*parse).as_ref().ok().and_then(|p| p.value()).unwrap();
+        assert_eq!(*db.unresolved(id), resolve(tree));
+        assert_eq!(db.unresolved(id).len(), 1);
+    }
+
+    #[test]
+    fn backdates_unchanged_results() {
+        let mut db = Database::new();
+        let id = db.add_file(URI, "let x = 1; in y ");
+        let unresolved = db.unresolved(id);
+        let diagnostics = db.diagnostics(id);
+
+        db.set_text(id, "let x = 1; in y  ");
+        assert!(Arc::ptr_eq(&unresolved, &db.unresolved(id)));
+        assert!(Arc::ptr_eq(&diagnostics, &db.diagnostics(id)));
+
+        db.set_text(id, "let x = 1; in  y");
+        assert!(!Arc::ptr_eq(&unresolved, &db.unresolved(id)));
+    }
+
+    #[test]
+    fn checks_against_nix_version() {
+        let mut db = Database::new();
+        let id = db.add_file(URI, "builtins.getFlake x");
+        assert!(db.diagnostics(id).iter().all(|d| d.message.contains("`x`")));
+
+        db.set_nix_version(Some(Version::new(2, 3)));
+        assert!(db
+            .diagnostics(id)
+            .iter()
+            .any(|d| d.message.contains("`getFlake` is not available in Nix 2.3")));
+
+        db.set_nix_version(None);
+        assert_eq!(db.diagnostics(id).len(), 1);
+    }
+
+    #[test]
+    fn checks_against_naming_style() {
+        let mut db = Database::new();
+        let id = db.add_file(URI, "let my_var = 1; in my_var");
+        assert!(db.diagnostics(id).is_empty());
+
+        db.set_naming(naming::Config::from_settings(
+            &serde_json::json!({ "namingStyle": { "let": "camelCase" } }),
+        ));
+        let diagnostics = db.diagnostics(id);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.contains("renamed to `myVar`"));
+    }
+
+    #[test]
+    fn reports_todo_comments() {
+        let mut db = Database::new();
+        let id = db.add_file(
+            URI,
+            "# TODO(alice): remove
+1",
+        );
+        assert!(db.diagnostics(id).is_empty());
+
+        db.set_todos(true);
+        let diagnostics = db.diagnostics(id);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "TODO: remove");
+        assert_eq!(
+            diagnostics[0].severity,
+            Some(DiagnosticSeverity::Information)
+        );
+    }
+}
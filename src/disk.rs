@@ -0,0 +1,72 @@
+//! Checks which look at the files around a file rather than at its text alone.
+//!
+//! These follow paths out of the file, to the functions called by `callPackage` and to the
+//! targets of path literals, so they read the file system and are not part of the [`Database`].
+//! The command line runs them right after the checks of the database, while the server runs them
+//! in the background so that slow disks never hold up editing.
+//!
+//! [`Database`]: crate::db::Database
+
+use std::env;
+use std::path::Path;
+
+use codespan::FileId;
+use codespan_reporting::diagnostic::Diagnostic;
+use nix_parser::ast::paths::{PathContext, SearchPath};
+use nix_parser::ast::SourceFile;
+
+use crate::call_package;
+use crate::paths;
+use crate::vfs::{FileLoader, PathResolver};
+
+/// Returns the problems `file`, read from `path`, has with the files it refers to.
+///
+/// Search paths such as `<nixpkgs>` are looked up in `search_path`, and `~/` paths in `$HOME`.
+pub fn check<F>(
+    id: FileId,
+    file: &SourceFile,
+    path: &Path,
+    search_path: &SearchPath,
+    fs: &F,
+) -> Vec<Diagnostic>
+where
+    F: FileLoader + PathResolver,
+{
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut context = PathContext::new(dir).with_search_path(search_path.clone());
+    if let Some(home) = env::var_os("HOME") {
+        context = context.with_home(home);
+    }
+
+    let mut diagnostics = call_package::check(id, file, path, fs);
+    diagnostics.extend(paths::check(id, file, &context, fs));
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use codespan::Files;
+
+    use super::*;
+    use crate::vfs::MemoryFs;
+
+    #[test]
+    fn checks_calls_and_paths() {
+        let mut fs = MemoryFs::new();
+        fs.insert("/p/hello.nix", "{ stdenv }: 1");
+        let source = "{ hello = callPackage ./hello.nix { gui = true; }; src = ./src; }";
+        let id = Files::new().add("/p/default.nix", source);
+        let file: SourceFile = source.parse().unwrap();
+
+        let path = Path::new("/p/default.nix");
+        let diagnostics = check(id, &file, path, &SearchPath::default(), &fs);
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "`hello.nix` takes no argument `gui`",
+                "path `./src` does not exist",
+            ]
+        );
+    }
+}
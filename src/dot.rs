@@ -0,0 +1,93 @@
+//! Rendering of syntax trees and import graphs in the Graphviz DOT language.
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::canonical::Node;
+use crate::imports::ImportGraph;
+
+/// Renders an expression tree, labelling edges with the role of each child.
+pub fn tree(root: &Node) -> String {
+    fn visit(node: &Node, next_id: &mut usize, out: &mut String) -> usize {
+        let id = *next_id;
+        *next_id += 1;
+        writeln!(out, "  n{} [label=\"{}\"];", id, escape(node.label())).unwrap();
+
+        for child in node.children() {
+            let child_id = visit(child, next_id, out);
+            write!(out, "  n{} -> n{}", id, child_id).unwrap();
+            if !child.key().is_empty() {
+                write!(out, " [label=\"{}\"]", escape(child.key())).unwrap();
+            }
+            out.push_str(";\n");
+        }
+
+        id
+    }
+
+    let mut out = String::from("digraph ast {\n  node [shape=box, fontname=monospace];\n");
+    visit(root, &mut 0, &mut out);
+    out.push_str("}\n");
+    out
+}
+
+/// Renders an import graph, with an edge from every file to each file it imports.
+///
+/// Paths are shown relative to `base` where possible. Imported files which could not be read are
+/// drawn dashed.
+pub fn imports(graph: &ImportGraph, base: &Path) -> String {
+    let name = |path: &Path| escape(&path.strip_prefix(base).unwrap_or(path).to_string_lossy());
+
+    let mut out = String::from("digraph imports {\n  node [shape=box, fontname=monospace];\n");
+    for (file, targets) in &graph.edges {
+        writeln!(out, "  \"{}\";", name(file)).unwrap();
+        for target in targets {
+            writeln!(out, "  \"{}\" -> \"{}\";", name(file), name(target)).unwrap();
+        }
+    }
+
+    let missing: BTreeSet<_> = graph
+        .edges
+        .values()
+        .flatten()
+        .filter(|target| !graph.edges.contains_key(*target))
+        .collect();
+    for target in missing {
+        writeln!(out, "  \"{}\" [style=dashed];", name(target)).unwrap();
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::canonical::canonicalize;
+
+    #[test]
+    fn renders_trees_and_import_graphs() {
+        let file = "{ a = \"x\"; }".parse().unwrap();
+        let dot = tree(&canonicalize(&file));
+        assert!(dot.contains("n0 [label=\"set\"]"));
+        assert!(dot.contains("n1 [label=\"string \\\"x\\\"\"]"));
+        assert!(dot.contains("n0 -> n1 [label=\"a\"]"));
+
+        let mut edges = BTreeMap::new();
+        let root = PathBuf::from("/src/default.nix");
+        edges.insert(root, vec![PathBuf::from("/src/lib.nix")]);
+        let dot = imports(&ImportGraph { edges }, Path::new("/src"));
+        assert!(dot.contains("\"default.nix\" -> \"lib.nix\";"));
+        assert!(dot.contains("\"lib.nix\" [style=dashed];"));
+    }
+}
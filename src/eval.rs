@@ -0,0 +1,361 @@
+//! Diagnostics produced by running the Nix evaluator in the background.
+//!
+//! The static analysis performed by this server cannot catch every error, e.g. type errors or
+//! failing assertions. When enabled, the file is handed to `nix-instantiate` on save and any
+//! errors it prints are mapped back onto the source.
+//!
+//! Requests such as completions may also evaluate expressions on the side. Their values are kept
+//! in a [`ValueCache`], so that repeating a request does not force the same values again.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use nix_parser::ast::paths::SearchPath;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::metrics::METRICS;
+
+static ANSI_ESCAPE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\x1b\[[0-9;]*m").unwrap());
+static LOCATION: Lazy<Regex> = Lazy::new(|| Regex::new(r"\bat (/[^:\n]+):(\d+):(\d+)").unwrap());
+/// Matches absolute paths and `<...>` lookups in an expression.
+/// The message of a call to `abort`, whose argument is quoted after this prefix.
+static ABORTED: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^evaluation aborted with the following error message: '(.*)'$").unwrap()
+});
+static PATH: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"<([\w.+-]+(?:/[\w.+/-]*)?)>|(?:^|[^\w.])(/[\w.+/-]+)").unwrap());
+
+/// An error reported by the Nix evaluator.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EvalError {
+    pub message: String,
+    /// The file, one-based line and one-based column where the error occurred, if known.
+    pub location: Option<(PathBuf, usize, usize)>,
+}
+
+/// Parses and then evaluates the given file, returning any errors reported by Nix.
+///
+/// Evaluation is skipped if the file fails to parse. Returns an empty list if `nix-instantiate`
+/// could not be executed.
+pub fn check(path: &Path) -> Vec<EvalError> {
+    let errors = run(&["--parse".as_ref(), path.as_os_str()]);
+    if !errors.is_empty() {
+        return errors;
+    }
+
+    run(&["--eval".as_ref(), path.as_os_str()])
+}
+
+fn run(args: &[&std::ffi::OsStr]) -> Vec<EvalError> {
+    let output = match Command::new("nix-instantiate").args(args).output() {
+        Ok(output) => output,
+        Err(_) => return Vec::new(),
+    };
+
+    if output.status.success() {
+        Vec::new()
+    } else {
+        parse_errors(&String::from_utf8_lossy(&output.stderr))
+    }
+}
+
+/// Strictly evaluates a Nix expression, returning the value printed by `nix-instantiate`.
+pub fn evaluate(expr: &str) -> Result<String, Vec<EvalError>> {
+    let output = evaluate_command(expr).output().map_err(spawn_error)?;
+    eval_output(output)
+}
+
+/// Like [`evaluate`], but gives up once evaluation has taken longer than `limit`.
+///
+/// Requests which evaluate on the side, e.g. to offer completions, should not block the server
+/// while Nix fetches or builds whatever the expression happens to depend on.
+pub fn evaluate_within(expr: &str, limit: Duration) -> Result<String, Vec<EvalError>> {
+    let mut child = evaluate_command(expr)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(spawn_error)?;
+
+    let deadline = Instant::now() + limit;
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+            Ok(None) | Err(_) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(vec![EvalError {
+                    message: format!("evaluation took longer than {:?}", limit),
+                    location: None,
+                }]);
+            }
+        }
+    }
+
+    eval_output(child.wait_with_output().map_err(spawn_error)?)
+}
+
+fn evaluate_command(expr: &str) -> Command {
+    let mut command = Command::new("nix-instantiate");
+    command.args(["--eval", "--strict", "-E", expr]);
+    command
+}
+
+fn spawn_error(error: io::Error) -> Vec<EvalError> {
+    vec![EvalError {
+        message: format!("failed to run nix-instantiate: {}", error),
+        location: None,
+    }]
+}
+
+fn eval_output(output: Output) -> Result<String, Vec<EvalError>> {
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_owned())
+    } else {
+        Err(parse_errors(&String::from_utf8_lossy(&output.stderr)))
+    }
+}
+
+type EvalResult = Result<String, Vec<EvalError>>;
+
+/// Values of expressions evaluated on the side of requests, such as completions.
+///
+/// Values are keyed by the text of the expression. Along with each value, a fingerprint of the
+/// environment it was evaluated in is kept: `NIX_PATH` and the modification times of the files the
+/// expression refers to, by absolute path or through `<...>` lookups. As with the queries of the
+/// database, the fingerprint is checked on every read, and the value is evaluated again once it
+/// changed, e.g. after a channel update.
+#[derive(Debug, Default)]
+pub struct ValueCache {
+    entries: Mutex<HashMap<String, (u64, EvalResult)>>,
+}
+
+impl ValueCache {
+    pub fn new() -> Self {
+        ValueCache::default()
+    }
+
+    /// Like [`evaluate_within`], but returns the cached value if the environment is unchanged.
+    pub fn evaluate_within(&self, expr: &str, limit: Duration) -> EvalResult {
+        let nix_path = env::var("NIX_PATH").ok();
+        let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+        let fingerprint = fingerprint(expr, nix_path.as_deref(), &modified);
+        self.get_or_evaluate(expr, fingerprint, || {
+            METRICS.time("nix-instantiate", || evaluate_within(expr, limit))
+        })
+    }
+
+    fn get_or_evaluate<F: FnOnce() -> EvalResult>(
+        &self,
+        expr: &str,
+        fingerprint: u64,
+        f: F,
+    ) -> EvalResult {
+        {
+            let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some((cached, value)) = entries.get(expr) {
+                if *cached == fingerprint {
+                    METRICS.cache("eval", true);
+                    return value.clone();
+                }
+            }
+        }
+
+        // The lock is not held while evaluating, so that other requests are not blocked on it.
+        METRICS.cache("eval", false);
+        let value = f();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.insert(expr.to_owned(), (fingerprint, value.clone()));
+        value
+    }
+}
+
+/// Hashes the environment which the value of `expr` depends on besides its text.
+fn fingerprint(
+    expr: &str,
+    nix_path: Option<&str>,
+    modified: &dyn Fn(&Path) -> Option<SystemTime>,
+) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    nix_path.hash(&mut hasher);
+    for caps in PATH.captures_iter(expr) {
+        let path = match (caps.get(1), caps.get(2)) {
+            (Some(lookup), _) => lookup_path(lookup.as_str(), nix_path, modified),
+            (_, Some(path)) => Some(PathBuf::from(path.as_str())),
+            _ => None,
+        };
+        if let Some(path) = path {
+            modified(&path).hash(&mut hasher);
+            path.hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+/// Resolves a `<...>` lookup against the entries of `NIX_PATH`, as Nix does.
+fn lookup_path(
+    lookup: &str,
+    nix_path: Option<&str>,
+    modified: &dyn Fn(&Path) -> Option<SystemTime>,
+) -> Option<PathBuf> {
+    let candidates = SearchPath::new(nix_path?).candidates(lookup)?;
+    candidates.into_iter().find(|path| modified(path).is_some())
+}
+
+/// Parses the error messages printed by `nix-instantiate` to stderr.
+///
+/// Both the single-line format of Nix 2.3 (`error: foo, at /file.nix:1:2`) and the multi-line
+/// format of later versions are understood. If an error carries a stack trace, the innermost
+/// location is used.
+pub fn parse_errors(stderr: &str) -> Vec<EvalError> {
+    let stderr = ANSI_ESCAPE.replace_all(stderr, "");
+    let mut blocks: Vec<Vec<&str>> = Vec::new();
+
+    for line in stderr.lines() {
+        if line.starts_with("error:") {
+            blocks.push(vec![line]);
+        } else if let Some(block) = blocks.last_mut() {
+            block.push(line);
+        }
+    }
+
+    blocks
+        .into_iter()
+        .map(|block| {
+            let lines = || block.iter().map(|line| line.trim());
+            let first = lines()
+                .filter_map(|line| line.strip_prefix("error:"))
+                .map(str::trim)
+                .rfind(|message| !message.is_empty())
+                .or_else(|| {
+                    lines().find(|line| {
+                        !line.is_empty()
+                            && !line.starts_with('…')
+                            && !line.starts_with("at ")
+                            && !line.contains('|')
+                    })
+                })
+                .unwrap_or("evaluation failed");
+
+            let text = block.join("\n");
+            let location = LOCATION.captures_iter(&text).last().and_then(|caps| {
+                let line = caps[2].parse().ok()?;
+                let column = caps[3].parse().ok()?;
+                Some((PathBuf::from(&caps[1]), line, column))
+            });
+
+            let message = match LOCATION.find(first) {
+                Some(found) => first[..found.start()].trim_end_matches([',', ' ']),
+                None => first,
+            };
+            let message = match ABORTED.captures(message) {
+                Some(caps) => caps.get(1).map_or(message, |text| text.as_str()),
+                None => message,
+            };
+
+            EvalError {
+                message: message.to_owned(),
+                location,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caches_values_until_environment_changes() {
+        use std::cell::Cell;
+
+        let stamp = Cell::new(SystemTime::UNIX_EPOCH);
+        let modified = |path: &Path| match path.to_str() {
+            Some("/nix/channels/nixpkgs") => Some(stamp.get()),
+            _ => None,
+        };
+        let nix_path = Some("nixpkgs=/nix/channels/nixpkgs");
+        let expr = "let pkgs = import <nixpkgs> { }; in builtins.attrNames pkgs.hello.drvAttrs";
+
+        let cache = ValueCache::new();
+        let evaluations = Cell::new(0);
+        let evaluate = |fingerprint| {
+            cache.get_or_evaluate(expr, fingerprint, || {
+                evaluations.set(evaluations.get() + 1);
+                Ok(format!("[ \"name\" ] {}", evaluations.get()))
+            })
+        };
+
+        let first = evaluate(fingerprint(expr, nix_path, &modified));
+        assert_eq!(evaluate(fingerprint(expr, nix_path, &modified)), first);
+        assert_eq!(evaluations.get(), 1);
+
+        stamp.set(SystemTime::UNIX_EPOCH + Duration::from_secs(1));
+        assert_ne!(evaluate(fingerprint(expr, nix_path, &modified)), first);
+        assert!(evaluate(fingerprint(expr, None, &modified)).is_ok());
+        assert_eq!(evaluations.get(), 3);
+    }
+
+    #[test]
+    fn single_line_errors() {
+        let stderr = "error: undefined variable 'foo' at /tmp/default.nix:3:5\n";
+        let errors = parse_errors(stderr);
+        assert_eq!(
+            errors,
+            vec![EvalError {
+                message: "undefined variable 'foo'".to_owned(),
+                location: Some((PathBuf::from("/tmp/default.nix"), 3, 5)),
+            }]
+        );
+    }
+
+    #[test]
+    fn multi_line_errors() {
+        let stderr = "\x1b[31;1merror:\x1b[0m
+       … while calling the 'throw' builtin
+
+         at /tmp/default.nix:1:1:
+
+            1| x: throw \"boom\"
+             | ^
+
+       error: boom
+
+       at /tmp/default.nix:2:7:
+";
+        let errors = parse_errors(stderr);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "boom");
+        assert_eq!(
+            errors[0].location,
+            Some((PathBuf::from("/tmp/default.nix"), 2, 7))
+        );
+    }
+
+    #[test]
+    fn unwraps_abort_messages() {
+        let stderr =
+            "error: evaluation aborted with the following error message: 'unsupported system'
+";
+        assert_eq!(parse_errors(stderr)[0].message, "unsupported system");
+    }
+
+    #[test]
+    fn errors_without_location() {
+        let errors = parse_errors("error: getting status of '/nope': No such file\n");
+        assert_eq!(errors[0].message, "getting status of '/nope': No such file");
+        assert_eq!(errors[0].location, None);
+    }
+}
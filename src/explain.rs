@@ -0,0 +1,255 @@
+//! Long-form explanations of diagnostic codes, in the spirit of `rustc --explain`.
+
+use nix_parser::error::ErrorCode;
+
+/// The command which returns the explanation of the diagnostic code given as its argument.
+pub const COMMAND: &str = "nix.explainDiagnostic";
+
+/// Explanations of the codes reported by this crate, in alphabetical order.
+const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        "builtins-exec",
+        "`builtins.exec` runs an arbitrary program at evaluation time. It is only available with \
+         `allow-unsafe-native-code-during-evaluation` enabled, and makes evaluation depend on the \
+         machine it runs on.\n\nPrefer a derivation which runs the program at build time.",
+    ),
+    (
+        "constant-condition",
+        "The condition of an `if` is built from literals only, so it is always `true` or always \
+         `false` and one of the branches is never used.\n\nReplace the `if` with the branch \
+         which is used, or make the condition depend on the value it was meant to check.",
+    ),
+    (
+        "constant-dynamic-attribute",
+        "An attribute name is written as an interpolation, `${\"name\"} = ...`, but always \
+         evaluates to the same name.\n\nWrite the name directly, `name = ...`, or quote it if it \
+         is not a valid identifier, `\"my name\" = ...`.",
+    ),
+    (
+        "deprecated-let",
+        "The expression uses `let { ...; body = ...; }`, an old form of `let` whose value is \
+         the `body` attribute of a recursive set. Nix still accepts it, but it is deprecated.\n\n\
+         Write `let ... in` instead, moving the value of `body` after `in`. The quick fix does \
+         this when there is a single `body` binding.",
+    ),
+    (
+        "deprecated-uri",
+        "A URL is written without quotes, as in `src = https://example.org/a.tar.gz;`. Unquoted \
+         URLs are deprecated, and rejected when the `no-url-literals` experimental feature is \
+         enabled.\n\nQuote the URL, `src = \"https://example.org/a.tar.gz\";`.",
+    ),
+    (
+        "duplicate-attribute",
+        "The same attribute is defined twice in one attribute set or `let`, which Nix rejects \
+         when evaluating the file.\n\nRemove or rename one of the definitions.",
+    ),
+    (
+        "failing-assertion",
+        "The condition of an `assert` is always `false`, so evaluating past it always fails \
+         with the assertion's message, if it gives one with `lib.assertMsg` or `|| throw`.\n\n\
+         Remove the assertion if the code after it is meant to be used, or replace it with a \
+         plain `throw` to make the failure explicit.",
+    ),
+    (
+        "import-from-input",
+        "A file is imported from a path computed from a function argument or an environment \
+         variable. Whoever controls that value controls the code which is evaluated.\n\nImport \
+         a fixed set of files, and select among them with the value instead.",
+    ),
+    (
+        "impure-builtin",
+        "The builtin reads state outside of the expression, such as the environment, the \
+         current time or the local file system, so the result of evaluation depends on where \
+         and when it happens. Impure builtins are rejected in pure evaluation mode, which \
+         flakes use by default.\n\nPass the value in as an argument instead.",
+    ),
+    (
+        "insecure-fetch",
+        "A fetcher downloads a URL over unencrypted `http://`, which lets anyone on the network \
+         path tamper with the download. A fixed output hash catches tampering, but only once it \
+         is known; updating the hash against a tampered download trusts it.\n\nUse `https://`.",
+    ),
+    (
+        "missing-import-argument",
+        "A file is imported and its function applied to a set of arguments, but the set does \
+         not give an argument which the function requires, so evaluating the call fails.\n\nPass \
+         the argument, e.g. with `inherit`, or give it a default value in the function's \
+         formals.",
+    ),
+    (
+        "missing-path",
+        "A path literal points to a file or directory which does not exist, so evaluating it \
+         fails. Relative paths are resolved against the directory of the file, and search paths \
+         such as `<nixpkgs>` against `NIX_PATH` or the `nixPath` setting.\n\nCheck the \
+         spelling of the path, or create the file it points to.",
+    ),
+    (
+        "module-missing-ellipsis",
+        "A NixOS module is a function taking a set of arguments, but its formals do not end in \
+         `...`. The module system passes every module all of its arguments, such as `config`, \
+         `lib`, `pkgs` and `options`, so the call fails.\n\nAdd `...` to the formals, e.g. \
+         `{ config, lib, ... }:`.",
+    ),
+    (
+        "module-unsupported-attribute",
+        "A NixOS module has an explicit `config` or `options` attribute, so every option \
+         definition must be under `config`. Nix rejects other attributes besides `imports`, \
+         `disabledModules`, `meta`, `_file` and `key`.\n\nMove the attribute under `config`, or \
+         drop the explicit `config` and define every option at the top level.",
+    ),
+    (
+        "naming-style",
+        "A binding does not follow the naming style configured with the `namingStyle` \
+         setting.\n\nThe style can be set for every binding, e.g. `\"camelCase\"`, or separately \
+         for `let` bindings, attributes and function arguments, e.g. `{ \"let\": \"camelCase\", \
+         \"params\": \"snake_case\" }`. Names starting with `_` are never reported. A quickfix \
+         renames the binding when every reference to it can be renamed safely.",
+    ),
+    (
+        "overlay-self-reference",
+        "An overlay defines a package in terms of the same package of its first argument, \
+         `final`, which is the result of applying every overlay, including this one. Evaluating \
+         the package then recurses infinitely.\n\nRefer to the package being overridden \
+         through the second argument, e.g. `hello = prev.hello.override { ... };`.",
+    ),
+    (
+        "overlay-shape",
+        "The file is treated as an overlay, by its name, the `fileRoles` setting or a \
+         `nix-role` comment, but it is not a function of two arguments returning a set. \
+         nixpkgs calls an overlay as `overlay final prev`.\n\nWrite it as \
+         `final: prev: { ... }`, or give the file another role with `# nix-role: expression`.",
+    ),
+    (
+        "secret-in-store",
+        "An attribute whose name suggests a secret, such as a password or token, is set to a \
+         literal string. Everything written into an expression can end up in the Nix store, \
+         which is readable by every user of the machine.\n\nPoint to a file outside of the store \
+         holding the secret instead, e.g. with an absolute path read at runtime.",
+    ),
+    (
+        "shadowed-binding",
+        "A name bound by `let` or a function hides a binding of the same name in an enclosing \
+         scope, so the outer binding cannot be used past it. In large expressions, this often \
+         means the outer value was meant, e.g. `pkgs` when a function argument is also named \
+         `pkgs`.\n\nRename one of the bindings. In code bases where shadowing is common, lower \
+         the severity of this lint with `\"diagnosticSeverity\": { \"shadowed-binding\": \
+         \"hint\" }` in the initialization options, or turn it `off`.",
+    ),
+    (
+        "shell-argument-without-default",
+        "`nix-shell` calls the function in `shell.nix` with no arguments besides those given \
+         with `--arg`, so an argument without a default value makes it fail.\n\nGive the \
+         argument a default, e.g. `{ pkgs ? import <nixpkgs> { } }:`.",
+    ),
+    (
+        "todo-comment",
+        "A comment contains a `TODO`, `FIXME` or `XXX` marker. These are only reported when the \
+         `todoDiagnostics` setting is enabled, so that outstanding work shows up alongside other \
+         problems.\n\nAn owner can be given after the marker, as in `# TODO(alice): ...` or \
+         `# FIXME @bob ...`. The `nix.listTodos` command lists the markers of all open \
+         documents.",
+    ),
+    (
+        "unconditional-throw",
+        "Evaluating the file reaches a `throw` or `abort` on every path, so it can never be \
+         used, e.g. because it rejects every system. The message of the call is shown in the \
+         diagnostic.\n\nIf the file is kept on purpose, e.g. to report that a package was \
+         removed, suppress the lint with `# nix-lint: disable=unconditional-throw`.",
+    ),
+    (
+        "undefined-attribute",
+        "An attribute is selected from a set which is written in the same file but does not \
+         define it, so evaluating the selection fails.\n\nCheck the spelling, or give a \
+         fallback with `set.name or default`.",
+    ),
+    (
+        "undefined-variable",
+        "A name is neither bound by an enclosing `let`, function argument or `rec` set, nor a \
+         builtin in global scope, so evaluating it fails. Names inside `with` are not reported, \
+         since the set may provide them.\n\nCheck the spelling, add the name to the arguments of \
+         the enclosing function, or bind it with `let`.",
+    ),
+    (
+        "unknown-call-package-argument",
+        "An argument passed to a file through `callPackage` is not among the arguments the \
+         file's function accepts, and the function does not accept extra arguments with \
+         `...`.\n\nRemove the argument, or add it to the function's formals.",
+    ),
+    (
+        "unknown-flake-input",
+        "An input of a flake follows an input which the flake neither declares in `inputs` nor \
+         takes from the flake registry by naming it in `outputs`.\n\nDeclare it in `inputs`, \
+         or check the spelling.",
+    ),
+    (
+        "unknown-import-argument",
+        "A file is imported and its function applied to a set containing an argument which the \
+         function does not take. Since the function does not accept extra arguments with `...`, \
+         evaluating the call fails.\n\nRemove the argument, or add it to the function's \
+         formals.",
+    ),
+    (
+        "unknown-lint",
+        "A `# nix-lint: disable=...` comment names a lint which does not exist, so it \
+         suppresses nothing.\n\nCheck the spelling of the lint name.",
+    ),
+    (
+        "unsupported-builtin",
+        "A builtin is used which was introduced after the Nix version configured with the \
+         `nixVersion` setting or the `--nix-version` flag.\n\nGuard the use with \
+         `builtins ? name`, provide a fallback, or raise the minimum supported version.",
+    ),
+    (
+        "unused-binding",
+        "A binding of a `let` expression is never used, neither by the body of the `let` nor by \
+         the other bindings. It is often left over from a refactoring, or a sign that another \
+         name was used by mistake.\n\nRemove the binding, or start its name with `_` to mark it \
+         as unused on purpose.",
+    ),
+    (
+        "unused-suppression",
+        "A `# nix-lint: disable=...` comment does not suppress any problem, e.g. because the \
+         problem was fixed since.\n\nRemove the comment, so that it does not hide problems \
+         introduced later.",
+    ),
+];
+
+/// Returns the explanation of the given diagnostic code, if it is known.
+pub fn explain(code: &str) -> Option<String> {
+    if let Some(number) = code.strip_prefix("SC") {
+        if !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()) {
+            return Some(format!(
+                "{} is reported by ShellCheck in a shell script embedded in a string.\n\nSee \
+                 https://www.shellcheck.net/wiki/{} for details.",
+                code, code
+            ));
+        }
+    }
+
+    if let Some(code) = ErrorCode::from_code(code) {
+        return Some(code.explanation().to_owned());
+    }
+
+    EXPLANATIONS
+        .binary_search_by_key(&code, |&(code, _)| code)
+        .ok()
+        .map(|index| EXPLANATIONS[index].1.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{security, suppress};
+
+    #[test]
+    fn explains_every_lint() {
+        assert!(EXPLANATIONS.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        for rule in suppress::RULES.iter().chain(security::RULES) {
+            assert!(explain(rule).is_some(), "`{}` has no explanation", rule);
+        }
+        assert!(explain("SC2086").unwrap().contains("wiki/SC2086"));
+        assert_eq!(explain("SC"), None);
+        assert!(explain("E0002").unwrap().contains("never closed"));
+        assert_eq!(explain("no-such-lint"), None);
+    }
+}
@@ -0,0 +1,235 @@
+//! Checks for evaluation which always fails, reporting the message it fails with.
+//!
+//! A file whose value is a call to `throw` or `abort`, possibly behind functions, `let`, `with`
+//! or an `if` failing in both branches, cannot be evaluated, typically because it does not
+//! support the current platform. Likewise an `assert` whose condition is constant `false` always
+//! fails. Only the value of the file is checked, since a binding which throws is often
+//! deliberate, e.g. for a removed package alias.
+//!
+//! Messages are only shown when they are written as constant strings, either as the argument of
+//! `throw` or `abort`, given to `lib.assertMsg`, or thrown as a fallback as in
+//! `assert cond || throw "message";`.
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use nix_parser::ast::{BinaryOp, Expr, ExprFnDecl, SourceFile};
+use nix_parser::HasSpan;
+
+use crate::attrs;
+use crate::constant;
+use crate::security::builtin_name;
+
+/// A place where evaluation fails.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Failure {
+    span: Span,
+    message: Option<String>,
+    kind: Kind,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Kind {
+    Throw,
+    Assertion,
+}
+
+/// Returns a diagnostic for every place where evaluating `file` always fails.
+pub fn check(id: FileId, file: &SourceFile) -> Vec<Diagnostic> {
+    let mut failures = Vec::new();
+    always_fails(file.expr(), &mut failures);
+    failures
+        .into_iter()
+        .map(|failure| {
+            let (code, label, what) = match failure.kind {
+                Kind::Throw => (
+                    "unconditional-throw",
+                    "always reached",
+                    "evaluation always fails",
+                ),
+                Kind::Assertion => (
+                    "failing-assertion",
+                    "always false",
+                    "assertion always fails",
+                ),
+            };
+            let message = match failure.message {
+                Some(message) => format!("{}: {}", what, message),
+                None => what.to_owned(),
+            };
+            let label = Label::new(id, failure.span, label);
+            Diagnostic::new_warning(message, label).with_code(code)
+        })
+        .collect()
+}
+
+/// Collects the failures which evaluating `expr` always runs into, returning whether it fails on
+/// every path.
+fn always_fails(expr: &Expr, out: &mut Vec<Failure>) -> bool {
+    match *expr {
+        Expr::Paren(ref paren) => always_fails(paren.expr(), out),
+        Expr::FnDecl(ref decl) => match **decl {
+            ExprFnDecl::Simple(ref simple) => always_fails(simple.body(), out),
+            ExprFnDecl::Formals(ref formals) => always_fails(formals.body(), out),
+        },
+        Expr::LetIn(ref let_in) => always_fails(let_in.body(), out),
+        Expr::With(ref with) => always_fails(with.expr(), out),
+        Expr::Assert(ref assert) => match assertion(assert.condition()) {
+            Some(message) => {
+                out.push(Failure {
+                    span: assert.condition().span(),
+                    message,
+                    kind: Kind::Assertion,
+                });
+                true
+            }
+            None => always_fails(assert.expr(), out),
+        },
+        Expr::If(ref if_else) => match constant::evaluate_bool(if_else.condition()) {
+            Some(true) => always_fails(if_else.body(), out),
+            Some(false) => always_fails(if_else.fallback(), out),
+            None => {
+                let len = out.len();
+                if always_fails(if_else.body(), out) && always_fails(if_else.fallback(), out) {
+                    true
+                } else {
+                    out.truncate(len);
+                    false
+                }
+            }
+        },
+        Expr::FnApp(ref app) => match thrown(expr) {
+            Some(message) => {
+                out.push(Failure {
+                    span: app.span(),
+                    message,
+                    kind: Kind::Throw,
+                });
+                true
+            }
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+/// Returns the message of a call to `throw` or `abort`, which is `None` if it is not constant.
+fn thrown(expr: &Expr) -> Option<Option<String>> {
+    let app = match *expr {
+        Expr::FnApp(ref app) => app,
+        Expr::Paren(ref paren) => return thrown(paren.expr()),
+        _ => return None,
+    };
+    let name = match *app.function() {
+        Expr::Ident(ref ident) => ident.as_str(),
+        ref function => builtin_name(function)?,
+    };
+    match name {
+        "throw" | "abort" => Some(attrs::constant(app.argument())),
+        _ => None,
+    }
+}
+
+/// Returns the message of an assertion which always fails, which is `None` if it has none.
+fn assertion(cond: &Expr) -> Option<Option<String>> {
+    match *cond {
+        Expr::Paren(ref paren) => assertion(paren.expr()),
+        Expr::Binary(ref binary) if binary.op() == BinaryOp::Or => {
+            if constant::evaluate_bool(binary.left()) != Some(false) {
+                return None;
+            }
+            match thrown(binary.right()) {
+                Some(message) => Some(message),
+                None => assertion(binary.right()),
+            }
+        }
+        Expr::FnApp(ref app) => {
+            let inner = match *app.function() {
+                Expr::FnApp(ref inner) => inner,
+                _ => return None,
+            };
+            let name = match *inner.function() {
+                Expr::Ident(ref ident) => ident.as_str(),
+                Expr::Proj(ref proj) => proj.attr().segments().last()?.name()?,
+                _ => return None,
+            };
+            match (name, constant::evaluate_bool(inner.argument())) {
+                ("assertMsg", Some(false)) => Some(attrs::constant(app.argument())),
+                _ => None,
+            }
+        }
+        _ if constant::evaluate_bool(cond) == Some(false) => Some(None),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use codespan::Files;
+
+    use super::*;
+
+    fn messages(source: &str) -> Vec<(String, String)> {
+        let file: SourceFile = source.parse().unwrap();
+        let id = Files::new().add("default.nix", source);
+        check(id, &file)
+            .into_iter()
+            .map(|diagnostic| {
+                let span = diagnostic.primary_label.span;
+                let text = &source[span.start().to_usize()..span.end().to_usize()];
+                (diagnostic.message, text.to_owned())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reports_unconditional_throws() {
+        let source = r#"{ stdenv, system }:
+let
+  supported = [ "x86_64-linux" ];
+in
+if system == "aarch64-darwin" then throw "unsupported system" else abort ("no ${system}")
+"#;
+        assert_eq!(
+            messages(source),
+            [
+                (
+                    "evaluation always fails: unsupported system".to_owned(),
+                    r#"throw "unsupported system""#.to_owned()
+                ),
+                (
+                    "evaluation always fails".to_owned(),
+                    r#"abort ("no ${system}")"#.to_owned()
+                ),
+            ]
+        );
+
+        assert!(messages(r#"x: if x then throw "a" else x"#).is_empty());
+        assert!(messages(r#"{ alias = throw "removed"; }"#).is_empty());
+        assert_eq!(
+            messages(r#"if false then 1 else builtins.throw "b""#)[0].0,
+            "evaluation always fails: b"
+        );
+    }
+
+    #[test]
+    fn reports_failing_assertions() {
+        let source = r#"{ lib }:
+assert lib.assertMsg (!true) "needs a newer stdenv";
+1"#;
+        assert_eq!(
+            messages(source),
+            [(
+                "assertion always fails: needs a newer stdenv".to_owned(),
+                r#"lib.assertMsg (!true) "needs a newer stdenv""#.to_owned()
+            )]
+        );
+
+        let source = r#"assert false || throw "broken"; 1"#;
+        assert_eq!(messages(source)[0].0, "assertion always fails: broken");
+        assert_eq!(
+            messages("assert true && false; 1")[0].0,
+            "assertion always fails"
+        );
+        assert!(messages("x: assert x; 1").is_empty());
+    }
+}
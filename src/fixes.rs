@@ -0,0 +1,261 @@
+//! Quick fixes for diagnostics, offered as code actions.
+//!
+//! A fix is found from the code, message and span of a diagnostic in the document it was reported
+//! for. Diagnostics whose code has a provider in [`PROVIDERS`] or [`SUGGESTIONS`] are fixed by it,
+//! and lints of the parser's rule registry by the replacement their rule suggests.
+//!
+//! Suggestions are worked out again from the document rather than read back from the "did you
+//! mean" notes of the diagnostics, so that rewording a note never breaks its fix.
+
+use std::path::Path;
+
+use codespan::Span;
+use nix_parser::ast::SourceFile;
+use nix_parser::lexer::{Lexer, Token};
+use nix_parser::ToSpan;
+
+use crate::vfs::RealFs;
+use crate::{attrs, call_package, lint, naming, resolve, suppress, unused};
+
+/// A diagnostic to fix, in the document it was reported for.
+#[derive(Clone, Copy, Debug)]
+pub struct Problem<'a> {
+    pub source: &'a str,
+    /// The path of the document, if it is a file on disk.
+    pub path: Option<&'a Path>,
+    /// The syntax tree of `source`, if it could be parsed at all.
+    pub file: Option<&'a SourceFile>,
+    pub code: Option<&'a str>,
+    pub span: Span,
+    /// The naming style the document is checked against.
+    pub naming: naming::Config,
+}
+
+/// The edits fixing a diagnostic.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Fix {
+    pub title: String,
+    /// The spans to replace along with their replacements, which do not overlap.
+    pub edits: Vec<(Span, String)>,
+}
+
+impl Fix {
+    fn new<T: Into<String>>(title: T, span: Span, replacement: T) -> Self {
+        Fix {
+            title: title.into(),
+            edits: vec![(span, replacement.into())],
+        }
+    }
+}
+
+type Provider = fn(&Problem) -> Option<Fix>;
+
+/// The fixes of the diagnostics of a given code.
+const PROVIDERS: &[(&str, Provider)] = &[
+    ("E0001", split_update),
+    ("E0004", split_update),
+    ("E0006", missing_semicolon),
+    (unused::CODE, remove_binding),
+    (naming::CODE, rename_to_style),
+];
+
+/// The fixes replacing the span of a diagnostic of a given code with the text its note suggests.
+const SUGGESTIONS: &[(&str, Provider)] = &[
+    ("constant-dynamic-attribute", plain_segment),
+    ("undefined-attribute", misspelled_name),
+    ("undefined-variable", misspelled_name),
+    ("unknown-call-package-argument", misspelled_argument),
+    ("unknown-import-argument", misspelled_argument),
+    ("unknown-lint", misspelled_lint),
+];
+
+/// Returns the fix for `problem`, if any.
+pub fn fix(problem: &Problem) -> Option<Fix> {
+    provide(PROVIDERS, problem)
+        .or_else(|| suggestion(problem))
+        .or_else(|| registry(problem))
+}
+
+/// Returns the fix applying the suggestion of the note of `problem`, if it has one.
+pub fn suggestion(problem: &Problem) -> Option<Fix> {
+    provide(SUGGESTIONS, problem)
+}
+
+fn provide(providers: &[(&str, Provider)], problem: &Problem) -> Option<Fix> {
+    let code = problem.code?;
+    let &(_, provider) = providers.iter().find(|&&(c, _)| c == code)?;
+    provider(problem)
+}
+
+/// Inserts the `;` missing after a binding, whose last token is at the span of the problem.
+fn missing_semicolon(problem: &Problem) -> Option<Fix> {
+    let end = Span::new(problem.span.end(), problem.span.end());
+    Some(Fix::new("Insert `;`", end, ";"))
+}
+
+/// Joins two `/` operators separated by whitespace into `//`, where the parser stumbled over the
+/// second or what follows it.
+fn split_update(problem: &Problem) -> Option<Fix> {
+    let lexer = Lexer::new(problem.source).ok()?;
+    let tokens: Vec<_> = lexer.tokens().iter().collect();
+    tokens
+        .windows(3)
+        .find_map(|window| match (window[0], window[1]) {
+            (&Token::Div(first), &Token::Div(second)) => {
+                let gap = problem
+                    .source
+                    .get(first.end().to_usize()..second.start().to_usize())?;
+                let next = window[2].to_span().start();
+                let at = [second.start(), next].contains(&problem.span.start());
+                if !at || !gap.chars().all(char::is_whitespace) {
+                    return None;
+                }
+                let span = Span::new(first.start(), second.end());
+                Some(Fix::new("Replace with `//`", span, "//"))
+            }
+            _ => None,
+        })
+}
+
+fn remove_binding(problem: &Problem) -> Option<Fix> {
+    let span = unused::removal(problem.source, problem.file?, problem.span)?;
+    Some(Fix::new("Remove the unused binding", span, ""))
+}
+
+/// Renames a binding which breaks the naming style throughout the file.
+fn rename_to_style(problem: &Problem) -> Option<Fix> {
+    let (new_name, spans) = naming::renaming(problem.file?, &problem.naming, problem.span)?;
+    let edits = spans
+        .into_iter()
+        .map(|span| (span, new_name.clone()))
+        .collect();
+    Some(Fix {
+        title: format!("Rename to `{}`", new_name),
+        edits,
+    })
+}
+
+/// Applies the suggestion of the rule of the parser's lint registry which reported the problem.
+fn registry(problem: &Problem) -> Option<Fix> {
+    let (file, code) = (problem.file?, problem.code?);
+    let suggestion = lint::suggestion(problem.source, file, code, problem.span)?;
+    let edits = vec![(suggestion.span, suggestion.replacement)];
+    Some(Fix {
+        title: suggestion.message,
+        edits,
+    })
+}
+
+fn replace_with(problem: &Problem, replacement: String) -> Fix {
+    let title = format!("Replace with `{}`", replacement);
+    Fix::new(title, problem.span, replacement)
+}
+
+fn plain_segment(problem: &Problem) -> Option<Fix> {
+    let replacement = attrs::plain_segment(problem.file?, problem.span)?;
+    Some(replace_with(problem, replacement))
+}
+
+fn misspelled_name(problem: &Problem) -> Option<Fix> {
+    let replacement = resolve::suggestion(problem.file?, problem.span)?;
+    Some(replace_with(problem, replacement))
+}
+
+fn misspelled_argument(problem: &Problem) -> Option<Fix> {
+    let (file, path) = (problem.file?, problem.path?);
+    let replacement = call_package::suggestion(file, path, problem.span, &RealFs)?;
+    Some(replace_with(problem, replacement))
+}
+
+fn misspelled_lint(problem: &Problem) -> Option<Fix> {
+    let rule = problem
+        .source
+        .get(problem.span.start().to_usize()..problem.span.end().to_usize())?;
+    let replacement = suppress::suggestion(rule)?;
+    Some(replace_with(problem, replacement.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use nix_parser::parser::parse_source_file_partial;
+
+    use super::*;
+
+    /// Returns the text of `source` with the fix for the diagnostic of `code` at `needle` applied.
+    fn fixed(source: &str, code: &str, needle: &str) -> Option<String> {
+        let partial = parse_source_file_partial(source).unwrap();
+        let start = source.find(needle).unwrap() as u32;
+        let problem = Problem {
+            source,
+            path: None,
+            file: partial.value(),
+            code: Some(code),
+            span: Span::new(start, start + needle.len() as u32),
+            naming: naming::Config::default(),
+        };
+
+        let mut text = source.to_owned();
+        let mut edits = fix(&problem)?.edits;
+        edits.sort_by_key(|&(span, _)| std::cmp::Reverse(span.start()));
+        for (span, replacement) in edits {
+            let range = span.start().to_usize()..span.end().to_usize();
+            text.replace_range(range, &replacement);
+        }
+        Some(text)
+    }
+
+    #[test]
+    fn fixes_syntax_errors() {
+        assert_eq!(
+            fixed("{ a = 1\n  b = 2; }", "E0006", "1").unwrap(),
+            "{ a = 1;\n  b = 2; }"
+        );
+        assert_eq!(fixed("{ a = 1\n  b = 2; }", "E0005", "1"), None);
+
+        assert_eq!(
+            fixed("{ a = b / / c; }", "E0001", "/ c").unwrap(),
+            "{ a = b // c; }"
+        );
+        assert_eq!(fixed("{ a = b / c; }", "E0001", "/ c"), None);
+    }
+
+    #[test]
+    fn fixes_lints() {
+        assert_eq!(
+            fixed("let\n  a = 1;\n  b = 2;\nin a\n", "unused-binding", "b").unwrap(),
+            "let\n  a = 1;\nin a\n"
+        );
+        assert_eq!(
+            fixed(
+                "{ src = https://x.org; }",
+                "deprecated-uri",
+                "https://x.org"
+            )
+            .unwrap(),
+            "{ src = \"https://x.org\"; }"
+        );
+    }
+
+    #[test]
+    fn fixes_misspellings() {
+        let source = "let s = { length = 1; }; in s.lenght";
+        assert_eq!(
+            fixed(source, "undefined-attribute", "lenght").unwrap(),
+            "let s = { length = 1; }; in s.length"
+        );
+        assert_eq!(
+            fixed("let value = 1; in 1 + vlue", "undefined-variable", "vlue").unwrap(),
+            "let value = 1; in 1 + value"
+        );
+        assert_eq!(
+            fixed(
+                "# nix-lint: disable=unused-bindings\n1",
+                "unknown-lint",
+                "unused-bindings"
+            )
+            .unwrap(),
+            "# nix-lint: disable=unused-binding\n1"
+        );
+        assert_eq!(fixed("x: x.lenght", "undefined-attribute", "lenght"), None);
+    }
+}
@@ -0,0 +1,353 @@
+//! Support for flake-specific features when editing `flake.nix` files.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use nix_parser::ast::{AttrSegment, Bind, Expr, ExprFnDecl, SourceFile, StringFragment};
+use nix_parser::HasSpan;
+use serde_json::Value;
+
+use crate::vfs::FileLoader;
+
+/// Returns whether the given file path refers to a flake.
+pub fn is_flake(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name == "flake.nix")
+}
+
+/// The statically known structure of a `flake.nix` file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Flake {
+    /// Inputs declared under the `inputs` attribute, in order of declaration.
+    pub inputs: Vec<(String, Span)>,
+    /// Inputs destructured by the `outputs` function, excluding `self`.
+    ///
+    /// Those missing from `inputs` are looked up in the flake registry by Nix.
+    pub outputs: Vec<(String, Span)>,
+    /// `follows` declarations, as pairs of the targeted input and the span of the string.
+    pub follows: Vec<(String, Span)>,
+}
+
+impl Flake {
+    /// Analyzes the given source file, returning `None` if it is not an attribute set.
+    pub fn analyze(source: &SourceFile) -> Option<Self> {
+        let binds = match *source.expr() {
+            Expr::Set(ref set) => set.binds(),
+            Expr::Rec(ref rec) => rec.binds(),
+            _ => return None,
+        };
+
+        let mut paths = Vec::new();
+        flatten(binds, &mut Vec::new(), &mut paths);
+
+        let mut flake = Flake::default();
+        for (path, value) in paths {
+            match path.as_slice() {
+                [(ref root, _), (ref name, span), ref rest @ ..] if root == "inputs" => {
+                    if flake.input(name).is_none() {
+                        flake.inputs.push((name.clone(), *span));
+                    }
+
+                    if let (Some((last, _)), Some(target)) = (rest.last(), string_value(value)) {
+                        if last == "follows" {
+                            let target = target.split('/').next().unwrap_or_default();
+                            flake.follows.push((target.to_owned(), value.span()));
+                        }
+                    }
+                }
+                [(ref root, _)] if root == "outputs" => {
+                    if let Expr::FnDecl(ref decl) = *value {
+                        if let ExprFnDecl::Formals(ref formals) = **decl {
+                            flake.outputs = formals
+                                .formals()
+                                .iter()
+                                .map(|f| (f.name().as_str().to_owned(), f.name().span()))
+                                .filter(|(name, _)| name != "self")
+                                .collect();
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Some(flake)
+    }
+
+    /// Returns the span where the named input is declared.
+    pub fn input(&self, name: &str) -> Option<Span> {
+        self.inputs
+            .iter()
+            .find(|(input, _)| input == name)
+            .map(|(_, span)| *span)
+    }
+
+    /// Returns the name of the input referenced at the given byte offset, if any.
+    pub fn input_at(&self, offset: usize) -> Option<&str> {
+        self.inputs
+            .iter()
+            .chain(self.outputs.iter())
+            .find(|(_, span)| contains(*span, offset))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Returns the input targeted by the `follows` declaration at the given byte offset, if any.
+    pub fn follows_at(&self, offset: usize) -> Option<&str> {
+        self.follows
+            .iter()
+            .find(|(_, span)| contains(*span, offset))
+            .map(|(target, _)| target.as_str())
+    }
+
+    /// Returns whether the flake has an input with the given name, either declared under `inputs`
+    /// or taken from the flake registry because only the `outputs` function names it.
+    pub fn has_input(&self, name: &str) -> bool {
+        self.input(name).is_some() || self.outputs.iter().any(|(output, _)| output == name)
+    }
+
+    /// Checks that `follows` declarations only refer to inputs of this flake.
+    pub fn check(&self, file: FileId) -> Vec<Diagnostic> {
+        self.follows
+            .iter()
+            .filter(|(target, _)| !self.has_input(target))
+            .map(|(target, span)| {
+                let label = Label::new(file, *span, "not an input of this flake");
+                let message = format!("`follows` refers to unknown input `{}`", target);
+                Diagnostic::new_error(message, label).with_code("unknown-flake-input")
+            })
+            .collect()
+    }
+}
+
+fn contains(span: Span, offset: usize) -> bool {
+    span.start().to_usize() <= offset && offset <= span.end().to_usize()
+}
+
+fn string_value(expr: &Expr) -> Option<&str> {
+    match *expr {
+        Expr::String(ref string) => match string.fragments() {
+            [StringFragment::Literal(ref text, _)] => Some(text),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Flattens nested attribute set literals into a list of static attribute paths and values.
+fn flatten<'a>(
+    binds: &'a [Bind],
+    prefix: &mut Vec<(String, Span)>,
+    out: &mut Vec<(Vec<(String, Span)>, &'a Expr)>,
+) {
+    for bind in binds {
+        let simple = match *bind {
+            Bind::Simple(ref simple) => simple,
+            _ => continue,
+        };
+
+        let len = prefix.len();
+        for segment in simple.attr().segments() {
+            match *segment {
+                AttrSegment::Ident(ref ident) => {
+                    prefix.push((ident.as_str().to_owned(), ident.span()));
+                }
+                _ => break,
+            }
+        }
+
+        if prefix.len() - len == simple.attr().segments().len() {
+            match *simple.expr() {
+                Expr::Set(ref set) => flatten(set.binds(), prefix, out),
+                ref value => out.push((prefix.clone(), value)),
+            }
+        }
+
+        prefix.truncate(len);
+    }
+}
+
+/// A locked flake input, as recorded in `flake.lock`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LockedInput {
+    /// The flake reference this input was locked to, e.g. `github:NixOS/nixpkgs/<rev>`.
+    pub reference: String,
+    pub rev: Option<String>,
+    pub last_modified: Option<u64>,
+    pub nar_hash: Option<String>,
+    /// The input path this input follows instead of being locked directly, if any.
+    pub follows: Option<String>,
+}
+
+/// The contents of a `flake.lock` file, keyed by the names of the root inputs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LockFile {
+    pub inputs: HashMap<String, LockedInput>,
+}
+
+impl LockFile {
+    /// Reads the lock file sitting next to the given `flake.nix`, if one exists.
+    pub fn for_flake(flake: &Path, loader: &dyn FileLoader) -> Option<Self> {
+        let text = loader.load(&flake.with_file_name("flake.lock")).ok()?;
+        LockFile::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let json: Value = serde_json::from_str(text).ok()?;
+        let nodes = json.get("nodes")?.as_object()?;
+        let root = json.get("root").and_then(Value::as_str).unwrap_or("root");
+        let root_inputs = nodes.get(root)?.get("inputs")?.as_object()?;
+
+        let mut inputs = HashMap::new();
+        for (name, target) in root_inputs {
+            let input = match *target {
+                Value::String(ref node) => nodes.get(node).map(locked_input).unwrap_or_default(),
+                Value::Array(ref path) => {
+                    let path: Vec<_> = path.iter().filter_map(Value::as_str).collect();
+                    LockedInput {
+                        follows: Some(path.join("/")),
+                        ..LockedInput::default()
+                    }
+                }
+                _ => continue,
+            };
+            inputs.insert(name.clone(), input);
+        }
+
+        Some(LockFile { inputs })
+    }
+}
+
+fn locked_input(node: &Value) -> LockedInput {
+    let locked = match node.get("locked") {
+        Some(locked) => locked,
+        None => return LockedInput::default(),
+    };
+
+    let field = |key: &str| locked.get(key).and_then(Value::as_str).map(str::to_owned);
+    let rev = field("rev");
+    let kind = field("type").unwrap_or_default();
+    let reference = match (field("owner"), field("repo")) {
+        (Some(owner), Some(repo)) => {
+            let rev = rev.as_ref().map(|r| format!("/{}", r)).unwrap_or_default();
+            format!("{}:{}/{}{}", kind, owner, repo, rev)
+        }
+        _ => field("url")
+            .or_else(|| field("path"))
+            .map(|url| format!("{}+{}", kind, url))
+            .unwrap_or(kind),
+    };
+
+    LockedInput {
+        reference,
+        rev,
+        last_modified: locked.get("lastModified").and_then(Value::as_u64),
+        nar_hash: field("narHash"),
+        follows: None,
+    }
+}
+
+/// Returns the flake IDs available in the flake registries at the given paths.
+pub fn registry_ids(paths: &[PathBuf], loader: &dyn FileLoader) -> Vec<String> {
+    let mut ids: Vec<String> = paths
+        .iter()
+        .filter_map(|path| loader.load(path).ok())
+        .filter_map(|text| serde_json::from_str::<Value>(&text).ok())
+        .filter_map(|json| json.get("flakes").and_then(Value::as_array).cloned())
+        .flatten()
+        .filter_map(|flake| {
+            let id = flake.get("from")?.get("id")?.as_str()?;
+            Some(id.to_owned())
+        })
+        .collect();
+
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLAKE: &str = r#"{
+        inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+        inputs.utils = {
+            url = "github:numtide/flake-utils";
+            inputs.nixpkgs.follows = "nixpkgs";
+            inputs.systems.follows = "unknown";
+        };
+        outputs = { self, nixpkgs, utils }: { };
+    }"#;
+
+    #[test]
+    fn analyze_flake() {
+        let source: SourceFile = FLAKE.parse().expect("failed to parse flake");
+        let flake = Flake::analyze(&source).unwrap();
+
+        let names = |list: &[(String, Span)]| -> Vec<String> {
+            list.iter().map(|(name, _)| name.clone()).collect()
+        };
+        assert_eq!(names(&flake.inputs), vec!["nixpkgs", "utils"]);
+        assert_eq!(names(&flake.outputs), vec!["nixpkgs", "utils"]);
+        assert_eq!(names(&flake.follows), vec!["nixpkgs", "unknown"]);
+
+        let file = codespan::Files::new().add("flake.nix", FLAKE);
+        let diagnostics = flake.check(file);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "`follows` refers to unknown input `unknown`"
+        );
+    }
+
+    #[test]
+    fn accepts_registry_inputs() {
+        let source = r#"{
+            inputs.nixpkgs.url = "nixpkgs";
+            inputs.foo.flake = false;
+            inputs.bar.inputs.nixpkgs.follows = "utils";
+            outputs = { self, nixpkgs, foo, bar, utils }: { };
+        }"#;
+        let flake = Flake::analyze(&source.parse().unwrap()).unwrap();
+        assert!(flake.has_input("foo"));
+        assert!(flake.has_input("utils"));
+        assert!(!flake.has_input("self"));
+
+        let file = codespan::Files::new().add("flake.nix", source);
+        assert!(flake.check(file).is_empty());
+    }
+
+    #[test]
+    fn parse_lock_file() {
+        let lock = LockFile::parse(
+            r#"{
+                "nodes": {
+                    "nixpkgs": {
+                        "locked": {
+                            "lastModified": 1600000000,
+                            "narHash": "sha256-abc",
+                            "owner": "NixOS",
+                            "repo": "nixpkgs",
+                            "rev": "0123abcd",
+                            "type": "github"
+                        }
+                    },
+                    "root": { "inputs": { "nixpkgs": "nixpkgs", "other": ["utils", "nixpkgs"] } }
+                },
+                "root": "root",
+                "version": 7
+            }"#,
+        )
+        .unwrap();
+
+        let nixpkgs = &lock.inputs["nixpkgs"];
+        assert_eq!(nixpkgs.reference, "github:NixOS/nixpkgs/0123abcd");
+        assert_eq!(nixpkgs.rev.as_deref(), Some("0123abcd"));
+        assert_eq!(nixpkgs.last_modified, Some(1_600_000_000));
+        assert_eq!(
+            lock.inputs["other"].follows,
+            Some("utils/nixpkgs".to_owned())
+        );
+    }
+}
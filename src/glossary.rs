@@ -0,0 +1,345 @@
+//! Short explanations of the operators and keywords of the language, shown when hovering them.
+//!
+//! Precedences follow the table of the Nix manual, where lower numbers bind tighter: function
+//! application has precedence 2, so `f a // b` is `(f a) // b`.
+
+use std::fmt::Write;
+
+use codespan::Span;
+use nix_parser::lexer::{Lexer, Token};
+use nix_parser::ToSpan;
+
+const OPERATORS: &str = "https://nix.dev/manual/nix/stable/language/operators";
+const SYNTAX: &str = "https://nix.dev/manual/nix/stable/language/syntax";
+
+/// How an operator groups with itself, e.g. `a // b // c` as `a // (b // c)`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Assoc {
+    Left,
+    Right,
+    None,
+}
+
+/// The documentation of an operator or keyword.
+struct Entry {
+    text: &'static str,
+    title: &'static str,
+    summary: &'static str,
+    precedence: Option<(u8, Assoc)>,
+    reference: &'static str,
+}
+
+const fn operator(
+    text: &'static str,
+    title: &'static str,
+    summary: &'static str,
+    precedence: u8,
+    assoc: Assoc,
+) -> Entry {
+    Entry {
+        text,
+        title,
+        summary,
+        precedence: Some((precedence, assoc)),
+        reference: OPERATORS,
+    }
+}
+
+const fn keyword(text: &'static str, title: &'static str, summary: &'static str) -> Entry {
+    Entry {
+        text,
+        title,
+        summary,
+        precedence: None,
+        reference: SYNTAX,
+    }
+}
+
+const ENTRIES: &[Entry] = &[
+    operator(
+        "?",
+        "has attribute",
+        "`set ? name` is `true` if `set` has the attribute `name`, which may be a path such as \
+         `a.b`. It is `false`, rather than an error, if `set` is not a set.",
+        4,
+        Assoc::None,
+    ),
+    operator(
+        "++",
+        "list concatenation",
+        "`xs ++ ys` is the list of the elements of `xs` followed by those of `ys`.",
+        5,
+        Assoc::Right,
+    ),
+    operator(
+        "*",
+        "multiplication",
+        "`a * b` multiplies two numbers.",
+        6,
+        Assoc::Left,
+    ),
+    operator(
+        "/",
+        "division",
+        "`a / b` divides two numbers, rounding towards zero if both are integers. A `/` \
+         without spaces around it may be read as part of a path instead.",
+        6,
+        Assoc::Left,
+    ),
+    operator(
+        "+",
+        "addition or concatenation",
+        "`a + b` adds two numbers, or concatenates two strings or paths. Adding a string to a \
+         path gives a path, and adding a path to a string copies the file to the store.",
+        7,
+        Assoc::Left,
+    ),
+    operator(
+        "-",
+        "subtraction or negation",
+        "`a - b` subtracts two numbers, and `-a` negates a number, with precedence 3.",
+        7,
+        Assoc::Left,
+    ),
+    operator(
+        "!",
+        "logical negation",
+        "`!b` is `true` if `b` is `false`, and the other way round.",
+        8,
+        Assoc::None,
+    ),
+    operator(
+        "//",
+        "attribute set update",
+        "`a // b` is a set with the attributes of both sets, those of `b` taking precedence. \
+         Only top-level attributes are merged: a nested set in `b` replaces the one in `a` \
+         whole, e.g. `{ x.y = 1; } // { x.z = 2; }` is `{ x.z = 2; }`.",
+        9,
+        Assoc::Right,
+    ),
+    operator(
+        "<",
+        "less than",
+        "Compares two numbers, strings or paths, or lists element by element.",
+        10,
+        Assoc::None,
+    ),
+    operator(
+        "<=",
+        "less than or equal",
+        "Compares two numbers, strings or paths, or lists element by element.",
+        10,
+        Assoc::None,
+    ),
+    operator(
+        ">",
+        "greater than",
+        "Compares two numbers, strings or paths, or lists element by element.",
+        10,
+        Assoc::None,
+    ),
+    operator(
+        ">=",
+        "greater than or equal",
+        "Compares two numbers, strings or paths, or lists element by element.",
+        10,
+        Assoc::None,
+    ),
+    operator(
+        "==",
+        "equality",
+        "`a == b` compares two values deeply. Comparing functions is unreliable: they are \
+         only equal when they are the very same value within a set or list.",
+        11,
+        Assoc::None,
+    ),
+    operator(
+        "!=",
+        "inequality",
+        "`a != b` is `!(a == b)`.",
+        11,
+        Assoc::None,
+    ),
+    operator(
+        "&&",
+        "logical and",
+        "`a && b` is `true` if both are `true`. `b` is only evaluated if `a` is `true`.",
+        12,
+        Assoc::Left,
+    ),
+    operator(
+        "||",
+        "logical or",
+        "`a || b` is `true` if either is `true`. `b` is only evaluated if `a` is `false`.",
+        13,
+        Assoc::Left,
+    ),
+    operator(
+        "->",
+        "logical implication",
+        "`a -> b` is `!a || b`, and mostly reads well in `assert` conditions, e.g. \
+         `assert withGui -> gtk != null;`.",
+        14,
+        Assoc::Right,
+    ),
+    keyword(
+        "assert",
+        "assertion",
+        "`assert cond; body` evaluates to `body` if `cond` is `true`, and aborts evaluation \
+         otherwise.",
+    ),
+    keyword(
+        "if",
+        "conditional",
+        "`if cond then a else b` evaluates to `a` if `cond` is `true` and to `b` otherwise. \
+         The `else` branch is required.",
+    ),
+    keyword(
+        "then",
+        "conditional",
+        "`if cond then a else b` evaluates to `a` if `cond` is `true` and to `b` otherwise. \
+         The `else` branch is required.",
+    ),
+    keyword(
+        "else",
+        "conditional",
+        "`if cond then a else b` evaluates to `a` if `cond` is `true` and to `b` otherwise. \
+         The `else` branch is required.",
+    ),
+    keyword(
+        "inherit",
+        "inherit",
+        "`inherit x;` binds `x` to the variable `x` in scope, and `inherit (set) x;` binds it \
+         to `set.x`.",
+    ),
+    keyword(
+        "let",
+        "let expression",
+        "`let x = 1; in body` binds names for use in `body`. The bindings may refer to each \
+         other, in any order.",
+    ),
+    keyword(
+        "in",
+        "let expression",
+        "`let x = 1; in body` binds names for use in `body`. The bindings may refer to each \
+         other, in any order.",
+    ),
+    keyword(
+        "or",
+        "default value",
+        "`set.name or fallback` evaluates to `fallback` if `set` has no attribute `name`.",
+    ),
+    keyword(
+        "rec",
+        "recursive set",
+        "`rec { a = 1; b = a + 1; }` is a set whose attributes may refer to each other. An \
+         attribute referring to a variable of its own name, as in `rec { src = src; }`, refers \
+         to itself, which is a common source of infinite recursion.",
+    ),
+    keyword(
+        "with",
+        "with expression",
+        "`with set; body` makes the attributes of `set` available as variables in `body`. \
+         Variables bound by `let` or function arguments take precedence, even when defined \
+         outside of the `with`, and the names it brings into scope are only known when \
+         evaluating.",
+    ),
+];
+
+/// Returns the text of an operator or keyword token.
+fn token_text(token: &Token) -> Option<&'static str> {
+    let text = match *token {
+        Token::Add(_) => "+",
+        Token::Sub(_) => "-",
+        Token::Mul(_) => "*",
+        Token::Div(_) => "/",
+        Token::IsEq(_) => "==",
+        Token::NotEq(_) => "!=",
+        Token::LessThan(_) => "<",
+        Token::LessThanEq(_) => "<=",
+        Token::GreaterThan(_) => ">",
+        Token::GreaterThanEq(_) => ">=",
+        Token::LogicalAnd(_) => "&&",
+        Token::LogicalOr(_) => "||",
+        Token::Concat(_) => "++",
+        Token::Update(_) => "//",
+        Token::Question(_) => "?",
+        Token::Imply(_) => "->",
+        Token::Not(_) => "!",
+        Token::Assert(_) => "assert",
+        Token::Else(_) => "else",
+        Token::If(_) => "if",
+        Token::In(_) => "in",
+        Token::Inherit(_) => "inherit",
+        Token::Let(_) => "let",
+        Token::Or(_) => "or",
+        Token::Rec(_) => "rec",
+        Token::Then(_) => "then",
+        Token::With(_) => "with",
+        _ => return None,
+    };
+    Some(text)
+}
+
+/// Returns the span of the operator or keyword at `offset` in `source`, along with its
+/// documentation as Markdown.
+pub fn describe_at(source: &str, offset: usize) -> Option<(Span, String)> {
+    let lexer = Lexer::new(source).ok()?;
+    let token = lexer.tokens().iter().find(|token| {
+        let span = token.to_span();
+        span.start().to_usize() <= offset && offset < span.end().to_usize()
+    })?;
+    let text = token_text(token)?;
+    let entry = ENTRIES.iter().find(|entry| entry.text == text)?;
+
+    let mut value = format!("**`{}`**: {}\n\n{}", entry.text, entry.title, entry.summary);
+    if let Some((precedence, assoc)) = entry.precedence {
+        let assoc = match assoc {
+            Assoc::Left => ", left-associative",
+            Assoc::Right => ", right-associative",
+            Assoc::None => "",
+        };
+        let _ = write!(
+            value,
+            "\n\nPrecedence {}{} (lower binds tighter; function application is 2).",
+            precedence, assoc
+        );
+    }
+    let _ = write!(value, "\n\n[Nix manual]({})", entry.reference);
+    Some((token.to_span(), value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_operators_and_keywords() {
+        let source = "rec { a = with b; c // d; e = f ? g -> h; }";
+        let (span, update) = describe_at(source, source.find("//").unwrap() + 1).unwrap();
+        assert_eq!(
+            &source[span.start().to_usize()..span.end().to_usize()],
+            "//"
+        );
+        assert!(update.starts_with("**`//`**: attribute set update"));
+        assert!(update.contains("Precedence 9, right-associative"));
+
+        let (_, with) = describe_at(source, source.find("with").unwrap()).unwrap();
+        assert!(with.contains("with set; body"));
+        assert!(with.ends_with(&format!("[Nix manual]({})", SYNTAX)));
+        assert!(describe_at(source, 0).unwrap().1.contains("recursive set"));
+        assert!(describe_at(source, source.find('f').unwrap()).is_none());
+    }
+
+    #[test]
+    fn documents_every_operator_and_keyword() {
+        let source = "a + - * / == != < <= > >= && || ++ // ? -> ! assert if then else \
+                      inherit let in or rec with";
+        let lexer = Lexer::new(source).unwrap();
+        for token in lexer.tokens().iter() {
+            if let Some(text) = token_text(token) {
+                assert!(ENTRIES.iter().any(|entry| entry.text == text), "{}", text);
+            }
+        }
+    }
+}
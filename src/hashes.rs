@@ -0,0 +1,359 @@
+//! Recognition and conversion of the hashes and store paths found in packages.
+//!
+//! Nix accepts hashes in several encodings: base16, its own base32 alphabet, base64, and SRI
+//! strings of the form `<algo>-<base64>`. The encoding of a bare hash is determined by its length,
+//! while its algorithm comes from the attribute it is assigned to, e.g. `sha256 = "..."`.
+
+use std::borrow::Cow;
+
+use codespan::Span;
+use nix_parser::ast::tokens::Literal;
+use nix_parser::ast::visit::{descendants, path_at};
+use nix_parser::ast::{AttrSegment, Bind, BindSimple, Expr, SourceFile};
+use nix_parser::HasSpan;
+
+use crate::vfs::PathResolver;
+
+const BASE32_CHARS: &[u8] = b"0123456789abcdfghijklmnpqrsvwxyz";
+const BASE64_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+const STORE_DIR: &str = "/nix/store/";
+
+/// A hash algorithm supported by Nix.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Algo {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl Algo {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "md5" => Some(Algo::Md5),
+            "sha1" => Some(Algo::Sha1),
+            "sha256" => Some(Algo::Sha256),
+            "sha512" => Some(Algo::Sha512),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Algo::Md5 => "md5",
+            Algo::Sha1 => "sha1",
+            Algo::Sha256 => "sha256",
+            Algo::Sha512 => "sha512",
+        }
+    }
+
+    /// Returns the size of a digest in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            Algo::Md5 => 16,
+            Algo::Sha1 => 20,
+            Algo::Sha256 => 32,
+            Algo::Sha512 => 64,
+        }
+    }
+}
+
+/// A decoded hash.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Hash {
+    algo: Algo,
+    bytes: Vec<u8>,
+}
+
+impl Hash {
+    /// Parses a hash in any encoding accepted by Nix.
+    ///
+    /// Hashes prefixed with `<algo>-` or `<algo>:` name their own algorithm, which must match
+    /// `algo` if one is given. Bare hashes need `algo` to be decoded.
+    pub fn parse(text: &str, algo: Option<Algo>) -> Option<Self> {
+        let prefixed = text
+            .find(['-', ':'])
+            .and_then(|i| Some((Algo::from_name(&text[..i])?, &text[i..])));
+
+        let (algo, sri, digest) = match (prefixed, algo) {
+            (Some((own, _)), Some(algo)) if own != algo => return None,
+            (Some((own, rest)), _) => (own, rest.starts_with('-'), &rest[1..]),
+            (None, Some(algo)) => (algo, false, text),
+            (None, None) => return None,
+        };
+
+        let size = algo.size();
+        let bytes = if sri {
+            decode_base64(digest)?
+        } else if digest.len() == size * 2 {
+            decode_base16(digest)?
+        } else if digest.len() == base32_len(size) {
+            decode_base32(digest, size)?
+        } else {
+            decode_base64(digest)?
+        };
+
+        if bytes.len() == size {
+            Some(Hash { algo, bytes })
+        } else {
+            None
+        }
+    }
+
+    pub fn algo(&self) -> Algo {
+        self.algo
+    }
+
+    pub fn to_base16(&self) -> String {
+        self.bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn to_base32(&self) -> String {
+        let len = base32_len(self.bytes.len());
+        (0..len)
+            .rev()
+            .map(|n| {
+                let (i, j) = (n * 5 / 8, n * 5 % 8);
+                let low = u16::from(self.bytes[i]) >> j;
+                let high = self
+                    .bytes
+                    .get(i + 1)
+                    .map_or(0, |&b| u16::from(b) << (8 - j));
+                BASE32_CHARS[((low | high) & 0x1f) as usize] as char
+            })
+            .collect()
+    }
+
+    pub fn to_base64(&self) -> String {
+        let mut out = String::new();
+        for chunk in self.bytes.chunks(3) {
+            let group = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |acc, (i, &b)| acc | u32::from(b) << (16 - 8 * i));
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    let index = (group >> (18 - 6 * i)) & 0x3f;
+                    out.push(BASE64_CHARS[index as usize] as char);
+                } else {
+                    out.push('=');
+                }
+            }
+        }
+        out
+    }
+
+    /// Returns the Subresource Integrity form of this hash, as used by the `hash` attribute.
+    pub fn to_sri(&self) -> String {
+        format!("{}-{}", self.algo.name(), self.to_base64())
+    }
+}
+
+fn base32_len(size: usize) -> usize {
+    (size * 8 - 1) / 5 + 1
+}
+
+fn decode_base16(text: &str) -> Option<Vec<u8>> {
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn decode_base32(text: &str, size: usize) -> Option<Vec<u8>> {
+    let mut bytes = vec![0u8; size];
+    for (n, c) in text.bytes().rev().enumerate() {
+        let digit = BASE32_CHARS.iter().position(|&d| d == c)? as u16;
+        let (i, j) = (n * 5 / 8, n * 5 % 8);
+        bytes[i] |= (digit << j) as u8;
+        let carry = digit >> (8 - j);
+        match bytes.get_mut(i + 1) {
+            Some(next) => *next |= carry as u8,
+            None if carry != 0 => return None,
+            None => {}
+        }
+    }
+    Some(bytes)
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut bytes = Vec::new();
+    let mut group = 0u32;
+    for (i, c) in text.bytes().enumerate() {
+        let digit = BASE64_CHARS.iter().position(|&d| d == c)? as u32;
+        group = group << 6 | digit;
+        if i % 4 == 3 {
+            bytes.extend_from_slice(&group.to_be_bytes()[1..]);
+            group = 0;
+        }
+    }
+    match text.len() % 4 {
+        0 => {}
+        2 => bytes.push((group >> 4) as u8),
+        3 => bytes.extend_from_slice(&((group >> 2) as u16).to_be_bytes()),
+        _ => return None,
+    }
+    Some(bytes)
+}
+
+/// A hash or store path found in a source file.
+#[derive(Debug)]
+pub enum Found<'a> {
+    /// A string assigned to a hash attribute such as `sha256` or `hash`.
+    Hash {
+        bind: &'a BindSimple,
+        attr: String,
+        value: String,
+    },
+    /// A path into the Nix store, truncated to the store object it belongs to.
+    StorePath { path: String, span: Span },
+}
+
+impl Found<'_> {
+    /// Decodes the hash, if this is a valid one.
+    pub fn hash(&self) -> Option<Hash> {
+        match *self {
+            Found::Hash {
+                ref attr,
+                ref value,
+                ..
+            } => Hash::parse(value, Algo::from_name(attr)),
+            Found::StorePath { .. } => None,
+        }
+    }
+}
+
+/// Returns the hash or store path at byte `offset` of `file`.
+pub fn find_at(file: &SourceFile, offset: usize) -> Option<Found<'_>> {
+    let contains =
+        |span: Span| span.start().to_usize() <= offset && offset <= span.end().to_usize();
+
+    for expr in descendants(file.expr()) {
+        let binds = match *expr {
+            Expr::Set(ref set) => set.binds(),
+            Expr::Rec(ref rec) => rec.binds(),
+            Expr::Let(ref let_) => let_.binds(),
+            Expr::LetIn(ref let_in) => let_in.binds(),
+            _ => continue,
+        };
+
+        for bind in binds {
+            let bind = match *bind {
+                Bind::Simple(ref bind) if contains(bind.span()) => bind,
+                _ => continue,
+            };
+            let attr = match bind.attr().segments() {
+                [AttrSegment::Ident(ref ident)] => ident.to_string(),
+                _ => continue,
+            };
+            let value = match *bind.expr() {
+                Expr::String(ref string) => string.as_literal().map(Cow::into_owned),
+                _ => None,
+            };
+            if let Some(value) =
+                value.filter(|_| attr == "hash" || Algo::from_name(&attr).is_some())
+            {
+                return Some(Found::Hash { bind, attr, value });
+            }
+        }
+    }
+
+    let expr = *path_at(file.expr(), offset).last()?;
+    let (text, span) = match *expr {
+        Expr::String(ref string) => (string.as_literal()?.into_owned(), string.span()),
+        Expr::Literal(Literal::Path(ref path, span)) => (path.to_string_lossy().into_owned(), span),
+        _ => return None,
+    };
+
+    let name = text.strip_prefix(STORE_DIR)?;
+    let end = name.find('/').unwrap_or(name.len());
+    let path = format!("{}{}", STORE_DIR, &name[..end]);
+    Some(Found::StorePath { path, span })
+}
+
+/// Describes a hash or store path in Markdown, checking whether store paths exist with `resolver`.
+pub fn describe(found: &Found, resolver: &dyn PathResolver) -> Option<String> {
+    match *found {
+        Found::Hash { .. } => {
+            let hash = found.hash()?;
+            Some(format!(
+                "**{}** hash\n\n- base16: `{}`\n- base32: `{}`\n- base64: `{}`\n- SRI: `{}`",
+                hash.algo().name(),
+                hash.to_base16(),
+                hash.to_base32(),
+                hash.to_base64(),
+                hash.to_sri()
+            ))
+        }
+        Found::StorePath { ref path, .. } => {
+            let status = if resolver.exists(path.as_ref()) {
+                "present in the local store"
+            } else {
+                "not present in the local store"
+            };
+            Some(format!("`{}`\n\n{}", path, status))
+        }
+    }
+}
+
+/// Returns the replacement for an old-style hash binding such as `sha256 = "..."`, which uses
+/// the `hash` attribute with an SRI hash instead.
+pub fn to_sri_binding(found: &Found) -> Option<(Span, String)> {
+    match *found {
+        Found::Hash { bind, ref attr, .. } if attr != "hash" => {
+            let hash = found.hash()?;
+            Some((bind.span(), format!("hash = \"{}\"", hash.to_sri())))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE16: &str = "0263829989b6fd954f72baaf2fc64bc2e2f01d692d4de72986ea808f6e99813f";
+    const BASE32: &str = "0gw1k5p8z07ahqlyfk9dd4fz1qn29g32zbxsf97rbzdni6cq4qq2";
+    const BASE64: &str = "AmOCmYm2/ZVPcrqvL8ZLwuLwHWktTecphuqAj26ZgT8=";
+
+    #[test]
+    fn converts_between_encodings() {
+        for text in &[BASE16, BASE32, BASE64] {
+            let hash = Hash::parse(text, Some(Algo::Sha256)).unwrap();
+            assert_eq!(hash.to_base16(), BASE16);
+            assert_eq!(hash.to_base32(), BASE32);
+            assert_eq!(hash.to_base64(), BASE64);
+        }
+
+        let sri = format!("sha256-{}", BASE64);
+        assert_eq!(Hash::parse(&sri, None).unwrap().to_base16(), BASE16);
+        assert_eq!(Hash::parse(&sri, Some(Algo::Sha512)), None);
+        assert_eq!(Hash::parse("abc", Some(Algo::Sha256)), None);
+    }
+
+    #[test]
+    fn finds_hashes_and_store_paths() {
+        let source = format!(
+            "{{ src = fetchurl {{ sha256 = \"{}\"; }}; out = \"/nix/store/{}-hello/bin\"; }}",
+            BASE32, BASE32
+        );
+        let file = source.parse().unwrap();
+
+        let offset = source.find("sha256").unwrap();
+        let found = find_at(&file, offset).unwrap();
+        let (span, text) = to_sri_binding(&found).unwrap();
+        let start = span.start().to_usize();
+        assert_eq!(start, offset);
+        assert_eq!(text, format!("hash = \"sha256-{}\"", BASE64));
+
+        let offset = source.find("/nix/store").unwrap();
+        match find_at(&file, offset).unwrap() {
+            Found::StorePath { path, .. } => {
+                assert_eq!(path, format!("/nix/store/{}-hello", BASE32))
+            }
+            other => panic!("expected a store path, found {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! Lexical highlighting for clients without support for semantic tokens.
+//!
+//! Regular expression grammars struggle with Nix strings: indented strings span many lines, and
+//! interpolations may contain further strings. The ranges computed here come from the lexer
+//! instead, splitting strings at the boundaries of their interpolations so that the code inside
+//! is highlighted like any other.
+
+use codespan::Span;
+use nix_parser::lexer::{Lexer, StringFragment, Token};
+use nix_parser::ToSpan;
+use serde::{Deserialize, Serialize};
+
+use crate::resolve::GLOBALS;
+
+/// The kind of a highlighted range.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Kind {
+    Keyword,
+    /// A name which is always in scope, such as `import` or `builtins`.
+    Builtin,
+    /// `true`, `false` or `null`.
+    Constant,
+    Number,
+    /// The literal text of a string, excluding interpolations.
+    String,
+    /// The `${` and `}` delimiting an interpolation.
+    Interpolation,
+    Path,
+    Uri,
+    Comment,
+    Operator,
+}
+
+/// A highlighted range of a source file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Highlight {
+    pub span: Span,
+    pub kind: Kind,
+}
+
+/// Returns the highlighted ranges of `source` in order, or nothing if it cannot be lexed.
+pub fn highlights(source: &str) -> Vec<Highlight> {
+    let mut out = Vec::new();
+    if let Ok(lexer) = Lexer::new(source) {
+        tokens(lexer.tokens().iter(), &mut out);
+    }
+    out
+}
+
+fn tokens<'a, I: IntoIterator<Item = &'a Token<'a>>>(tokens: I, out: &mut Vec<Highlight>) {
+    for token in tokens {
+        let span = token.to_span();
+        let kind = match *token {
+            Token::String(ref fragments, ..) => {
+                string(span, fragments, out);
+                continue;
+            }
+            Token::Interpolation(ref inner, _) => {
+                interpolation(span, inner, out);
+                continue;
+            }
+            Token::Identifier(ref name, _) if GLOBALS.contains(&&**name) => Kind::Builtin,
+            Token::Null(_) | Token::Boolean(..) => Kind::Constant,
+            Token::Integer(..) | Token::Float(..) => Kind::Number,
+            Token::Path(..) | Token::PathTemplate(..) => Kind::Path,
+            Token::Uri(..) => Kind::Uri,
+            Token::Comment(..) => Kind::Comment,
+            ref token if token.is_keyword() => Kind::Keyword,
+            Token::Add(_)
+            | Token::Sub(_)
+            | Token::Mul(_)
+            | Token::Div(_)
+            | Token::IsEq(_)
+            | Token::NotEq(_)
+            | Token::LessThan(_)
+            | Token::LessThanEq(_)
+            | Token::GreaterThan(_)
+            | Token::GreaterThanEq(_)
+            | Token::LogicalAnd(_)
+            | Token::LogicalOr(_)
+            | Token::Concat(_)
+            | Token::Update(_)
+            | Token::Question(_)
+            | Token::Imply(_)
+            | Token::Not(_) => Kind::Operator,
+            _ => continue,
+        };
+
+        out.push(Highlight { span, kind });
+    }
+}
+
+/// Highlights a string, splitting it around its interpolations.
+fn string(span: Span, fragments: &[StringFragment], out: &mut Vec<Highlight>) {
+    let mut start = span.start();
+    for fragment in fragments {
+        if let StringFragment::Interpolation(ref inner, interp) = *fragment {
+            if start < interp.start() {
+                let span = Span::new(start, interp.start());
+                out.push(Highlight {
+                    span,
+                    kind: Kind::String,
+                });
+            }
+            interpolation(interp, inner, out);
+            start = interp.end();
+        }
+    }
+
+    if start < span.end() {
+        let span = Span::new(start, span.end());
+        out.push(Highlight {
+            span,
+            kind: Kind::String,
+        });
+    }
+}
+
+/// Highlights the delimiters of an interpolation spanning `span` and the tokens inside it.
+fn interpolation(span: Span, inner: &[Token], out: &mut Vec<Highlight>) {
+    let (start, end) = (span.start().to_usize(), span.end().to_usize());
+    let open = Span::new(start as u32, (start + 2) as u32);
+    out.push(Highlight {
+        span: open,
+        kind: Kind::Interpolation,
+    });
+    tokens(inner.iter().filter(|t| !matches!(t, Token::Eof(_))), out);
+    let close = Span::new((end - 1) as u32, end as u32);
+    out.push(Highlight {
+        span: close,
+        kind: Kind::Interpolation,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_strings_at_interpolations() {
+        let source = "let a = ''\n  x ${import ./b.nix + \"${c}\"} y\n''; in a # done";
+        let ranges: Vec<_> = highlights(source)
+            .into_iter()
+            .map(|h| {
+                (
+                    &source[h.span.start().to_usize()..h.span.end().to_usize()],
+                    h.kind,
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            ranges,
+            [
+                ("let", Kind::Keyword),
+                ("''\n  x ", Kind::String),
+                ("${", Kind::Interpolation),
+                ("import", Kind::Builtin),
+                ("./b.nix", Kind::Path),
+                ("+", Kind::Operator),
+                ("\"", Kind::String),
+                ("${", Kind::Interpolation),
+                ("}", Kind::Interpolation),
+                ("\"", Kind::String),
+                ("}", Kind::Interpolation),
+                (" y\n''", Kind::String),
+                ("in", Kind::Keyword),
+                ("# done", Kind::Comment),
+            ]
+        );
+    }
+}
@@ -0,0 +1,170 @@
+//! Entry points of a project and the files each of them depends on.
+//!
+//! A problem in a helper file breaks everything importing it, directly or not, but a list of
+//! problems per file does not say what that is. Grouping problems by the entry points reaching
+//! the files they occur in does: an entry point is a file given by the user, or for a flake, each
+//! attribute returned by its `outputs` function.
+
+use std::collections::BTreeSet;
+use std::iter;
+use std::path::{Path, PathBuf};
+
+use nix_parser::ast::{Bind, Expr, ExprFnDecl, SourceFile};
+use nix_parser::HasSpan;
+
+use crate::flake::is_flake;
+use crate::imports::{imports, ImportGraph};
+use crate::vfs::{FileLoader, PathResolver};
+
+/// An entry point into a project.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entry {
+    /// The file defining the entry point.
+    pub root: PathBuf,
+    /// The flake output defined by the entry point, if it is one.
+    pub output: Option<String>,
+    /// The files the entry point depends on, including its root.
+    pub files: BTreeSet<PathBuf>,
+}
+
+/// Returns the entry points defined by the file `root` of `graph`.
+pub fn entries<F>(root: &Path, graph: &ImportGraph, fs: &F) -> Vec<Entry>
+where
+    F: FileLoader + PathResolver,
+{
+    let whole = || Entry {
+        root: root.to_owned(),
+        output: None,
+        files: graph.reachable(iter::once(root)),
+    };
+
+    if !is_flake(root) {
+        return vec![whole()];
+    }
+    let file = match fs.load(root).ok().and_then(|text| text.parse().ok()) {
+        Some(file) => file,
+        None => return vec![whole()],
+    };
+    let outputs = match outputs(&file) {
+        Some(outputs) => outputs,
+        None => return vec![whole()],
+    };
+
+    // Imports outside of any output, e.g. in a `let` block around them, are shared by all.
+    let imports = imports(&file);
+    let shared: Vec<_> = imports
+        .iter()
+        .filter(|import| {
+            let start = import.span.start();
+            !outputs
+                .iter()
+                .any(|bind| bind.span().start() <= start && start < bind.span().end())
+        })
+        .map(|import| import.resolve(root, fs))
+        .collect();
+
+    outputs
+        .iter()
+        .filter_map(|bind| {
+            let simple = match *bind {
+                Bind::Simple(ref simple) => simple,
+                _ => return None,
+            };
+            let span = simple.span();
+            let own = imports
+                .iter()
+                .filter(|import| {
+                    span.start() <= import.span.start() && import.span.end() <= span.end()
+                })
+                .map(|import| import.resolve(root, fs));
+            let targets: Vec<_> = own.chain(shared.iter().cloned()).collect();
+
+            let mut files = graph.reachable(targets.iter().map(PathBuf::as_path));
+            files.insert(root.to_owned());
+            Some(Entry {
+                root: root.to_owned(),
+                output: Some(simple.attr().to_string()),
+                files,
+            })
+        })
+        .collect()
+}
+
+/// Returns the bindings of the attribute set returned by the `outputs` function of a flake.
+fn outputs(file: &SourceFile) -> Option<&[Bind]> {
+    let mut expr = match *file.expr() {
+        Expr::Set(ref set) => set.get(&["outputs"])?,
+        Expr::Rec(ref rec) => rec.get(&["outputs"])?,
+        _ => return None,
+    };
+
+    loop {
+        expr = match *expr {
+            Expr::FnDecl(ref decl) => match **decl {
+                ExprFnDecl::Simple(ref decl) => decl.body(),
+                ExprFnDecl::Formals(ref decl) => decl.body(),
+            },
+            Expr::LetIn(ref let_in) => let_in.body(),
+            Expr::Paren(ref paren) => paren.expr(),
+            Expr::Set(ref set) => return Some(set.binds()),
+            Expr::Rec(ref rec) => return Some(rec.binds()),
+            _ => return None,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::MemoryFs;
+
+    #[test]
+    fn splits_flakes_by_output() {
+        let mut fs = MemoryFs::new();
+        let flake = "{
+  outputs = { self }: let lib = import ./lib.nix; in {
+    packages = import ./pkgs.nix;
+    checks.default = import ./checks.nix;
+    inherit lib;
+  };
+}";
+        fs.insert("/p/flake.nix", flake);
+        fs.insert("/p/lib.nix", "{ }");
+        fs.insert("/p/pkgs.nix", "import ./util.nix");
+        fs.insert("/p/util.nix", "{ }");
+        fs.insert("/p/checks.nix", "{ }");
+
+        let root = Path::new("/p/flake.nix");
+        let graph = ImportGraph::build(vec![root.to_owned()], &fs).unwrap();
+        let summary: Vec<_> = entries(root, &graph, &fs)
+            .into_iter()
+            .map(|entry| {
+                let files: Vec<_> = entry
+                    .files
+                    .iter()
+                    .map(|f| f.display().to_string())
+                    .collect();
+                (entry.output.unwrap(), files.join(" "))
+            })
+            .collect();
+
+        assert_eq!(
+            summary,
+            [
+                (
+                    "packages".to_string(),
+                    "/p/flake.nix /p/lib.nix /p/pkgs.nix /p/util.nix".to_string()
+                ),
+                (
+                    "checks.default".to_string(),
+                    "/p/checks.nix /p/flake.nix /p/lib.nix".to_string()
+                ),
+            ]
+        );
+
+        let order = graph.dependency_order();
+        let position = |name: &str| order.iter().position(|p| p.ends_with(name)).unwrap();
+        assert!(position("util.nix") < position("pkgs.nix"));
+        assert!(position("pkgs.nix") < position("flake.nix"));
+    }
+}
@@ -0,0 +1,303 @@
+//! Discovery of the files imported by a Nix expression.
+//!
+//! Only imports of literal paths are found, i.e. `import ./foo.nix` and `callPackage ./bar {}`.
+//! Imports of computed paths and search paths such as `<nixpkgs>` cannot be resolved statically.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use codespan::Span;
+use nix_parser::ast::paths::normalize;
+use nix_parser::ast::tokens::Literal;
+use nix_parser::ast::visit::descendants;
+use nix_parser::ast::{AttrSegment, Expr, SourceFile};
+use nix_parser::parser::parse_source_file_partial;
+use nix_parser::HasSpan;
+
+use crate::vfs::{FileLoader, PathResolver};
+
+/// Functions whose first argument is a path to another Nix file.
+const IMPORTERS: &[&str] = &["import", "callPackage", "scopedImport"];
+
+/// A literal path imported by an expression.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Import {
+    /// The path as written in the source, relative to the importing file.
+    pub path: PathBuf,
+    pub span: Span,
+}
+
+impl Import {
+    /// Resolves the imported path relative to the file containing the import, substituting
+    /// `default.nix` for directories as Nix does.
+    pub fn resolve(&self, importer: &Path, resolver: &dyn PathResolver) -> PathBuf {
+        let base = importer.parent().unwrap_or_else(|| Path::new(""));
+        let path = normalize(&base.join(&self.path));
+        if resolver.is_dir(&path) {
+            path.join("default.nix")
+        } else {
+            path
+        }
+    }
+}
+
+/// Returns every literal path imported by the given file, in source order.
+pub fn imports(file: &SourceFile) -> Vec<Import> {
+    let mut imports: Vec<_> = descendants(file.expr())
+        .filter_map(|expr| match *expr {
+            Expr::FnApp(ref app) if is_importer(app.function()) => match *app.argument() {
+                Expr::Literal(Literal::Path(ref path, _)) => Some(Import {
+                    path: (**path).clone(),
+                    span: app.argument().span(),
+                }),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+
+    imports.sort_by_key(|import| import.span.start());
+    imports
+}
+
+/// Returns every literal path in the given file, in source order, whether it is imported or
+/// passed around as a value, e.g. in the `imports` of a NixOS module.
+pub fn references(file: &SourceFile) -> Vec<Import> {
+    let mut references: Vec<_> = descendants(file.expr())
+        .filter_map(|expr| match *expr {
+            Expr::Literal(Literal::Path(ref path, span)) => Some(Import {
+                path: (**path).clone(),
+                span,
+            }),
+            _ => None,
+        })
+        .collect();
+
+    references.sort_by_key(|reference| reference.span.start());
+    references
+}
+
+fn is_importer(function: &Expr) -> bool {
+    let name = match *function {
+        Expr::Ident(ref ident) => ident.as_str(),
+        Expr::Proj(ref proj) => match proj.attr().segments().last() {
+            Some(AttrSegment::Ident(ref ident)) => ident.as_str(),
+            _ => return false,
+        },
+        _ => return false,
+    };
+    IMPORTERS.contains(&name)
+}
+
+/// The files reachable from a set of roots by following imports.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ImportGraph {
+    /// The files imported by each file. Files which could not be read have no entry, while files
+    /// with syntax errors only list the imports found in the parts which could be parsed.
+    pub edges: BTreeMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl ImportGraph {
+    /// Builds the import graph of the given files, following imports transitively.
+    pub fn build<I, F>(roots: I, fs: &F) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = PathBuf>,
+        F: FileLoader + PathResolver,
+    {
+        Self::build_with(roots, fs, imports)
+    }
+
+    /// Builds the graph of the files referenced by the given files through any literal path,
+    /// which overapproximates the files they import.
+    pub fn build_references<I, F>(roots: I, fs: &F) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = PathBuf>,
+        F: FileLoader + PathResolver,
+    {
+        Self::build_with(roots, fs, references)
+    }
+
+    fn build_with<I, F>(roots: I, fs: &F, edges: fn(&SourceFile) -> Vec<Import>) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = PathBuf>,
+        F: FileLoader + PathResolver,
+    {
+        let mut graph = ImportGraph::default();
+        let mut pending: Vec<_> = roots.into_iter().map(|p| normalize(&p)).collect();
+
+        while let Some(path) = pending.pop() {
+            if graph.edges.contains_key(&path) {
+                continue;
+            }
+
+            let text = match fs.load(&path) {
+                Ok(text) => text,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+
+            let partial = parse_source_file_partial(&text).ok();
+            let targets: Vec<_> = partial
+                .as_ref()
+                .and_then(|partial| partial.value())
+                .map(edges)
+                .unwrap_or_default()
+                .iter()
+                .map(|import| import.resolve(&path, fs))
+                .collect();
+            pending.extend(targets.iter().cloned());
+            graph.edges.insert(path, targets);
+        }
+
+        Ok(graph)
+    }
+
+    /// Returns the files of the graph ordered so that each comes after the files it imports.
+    /// Files in an import cycle are ordered arbitrarily among themselves.
+    pub fn dependency_order(&self) -> Vec<PathBuf> {
+        fn visit(
+            graph: &ImportGraph,
+            path: &Path,
+            seen: &mut BTreeSet<PathBuf>,
+            out: &mut Vec<PathBuf>,
+        ) {
+            if !seen.insert(path.to_owned()) {
+                return;
+            }
+            if let Some(targets) = graph.edges.get(path) {
+                for target in targets {
+                    visit(graph, target, seen, out);
+                }
+                out.push(path.to_owned());
+            }
+        }
+
+        let mut seen = BTreeSet::new();
+        let mut order = Vec::new();
+        for path in self.edges.keys() {
+            visit(self, path, &mut seen, &mut order);
+        }
+        order
+    }
+
+    /// Returns the files of the graph reachable from `roots` by following imports, including the
+    /// roots themselves.
+    pub fn reachable<'a, I>(&self, roots: I) -> BTreeSet<PathBuf>
+    where
+        I: IntoIterator<Item = &'a Path>,
+    {
+        let mut reached = BTreeSet::new();
+        let mut pending: Vec<_> = roots.into_iter().collect();
+        while let Some(path) = pending.pop() {
+            if let Some(targets) = self.edges.get(path) {
+                if reached.insert(path.to_owned()) {
+                    pending.extend(targets.iter().map(PathBuf::as_path));
+                }
+            }
+        }
+        reached
+    }
+}
+
+/// Returns the `.nix` files among `files` which no file reachable from `entries` imports or
+/// otherwise refers to by a literal path.
+pub fn unreferenced<F>(files: &[PathBuf], entries: &[PathBuf], fs: &F) -> io::Result<Vec<PathBuf>>
+where
+    F: FileLoader + PathResolver,
+{
+    let graph = ImportGraph::build_references(entries.iter().cloned(), fs)?;
+    let roots: Vec<_> = entries.iter().map(|entry| normalize(entry)).collect();
+    let reached = graph.reachable(roots.iter().map(PathBuf::as_path));
+    Ok(files
+        .iter()
+        .filter(|file| file.extension().is_some_and(|ext| ext == "nix"))
+        .filter(|file| !reached.contains(&normalize(file)))
+        .cloned()
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::MemoryFs;
+
+    #[test]
+    fn finds_literal_imports() {
+        let source = "{ a = import ./a.nix; b = pkgs.callPackage ./b { }; c = import <nixpkgs>; }";
+        let file: SourceFile = source.parse().unwrap();
+        let paths: Vec<_> = imports(&file).into_iter().map(|i| i.path).collect();
+        assert_eq!(paths, [PathBuf::from("./a.nix"), PathBuf::from("./b")]);
+
+        let import = &imports(&file)[0];
+        assert_eq!(
+            &source[import.span.start().to_usize()..import.span.end().to_usize()],
+            "./a.nix"
+        );
+        let resolved = import.resolve(Path::new("/tmp/project/default.nix"), &MemoryFs::new());
+        assert_eq!(resolved, Path::new("/tmp/project/a.nix"));
+        let parent = Import {
+            path: PathBuf::from("../lib/./b.nix"),
+            span: import.span,
+        };
+        let resolved = parent.resolve(Path::new("/tmp/project/default.nix"), &MemoryFs::new());
+        assert_eq!(resolved, Path::new("/tmp/lib/b.nix"));
+    }
+
+    #[test]
+    fn builds_graph_from_loader() {
+        let mut fs = MemoryFs::new();
+        fs.insert(
+            "/p/default.nix",
+            "{ a = import ./a.nix; b = import ./lib; }",
+        );
+        fs.insert("/p/a.nix", "import ./lib/default.nix");
+        fs.insert("/p/lib/default.nix", "{ c = import ./missing.nix; }");
+
+        let graph = ImportGraph::build(vec![PathBuf::from("/p/default.nix")], &fs).unwrap();
+        let lib = PathBuf::from("/p/lib/default.nix");
+        let a = PathBuf::from("/p/a.nix");
+        assert_eq!(
+            graph.edges[Path::new("/p/default.nix")],
+            [a.clone(), lib.clone()]
+        );
+        assert_eq!(graph.edges[&a], vec![lib.clone()]);
+        assert_eq!(graph.edges[&lib], [PathBuf::from("/p/lib/missing.nix")]);
+        assert_eq!(graph.edges.len(), 3);
+    }
+
+    #[test]
+    fn finds_unreferenced_files() {
+        let mut fs = MemoryFs::new();
+        fs.insert(
+            "/p/flake.nix",
+            "{ outputs = { self }: { nixosModules.a = ./modules/a.nix; lib = import ./lib; }; }",
+        );
+        fs.insert("/p/modules/a.nix", "{ imports = [ ./b.nix ]; }");
+        fs.insert("/p/modules/b.nix", "{ }");
+        fs.insert("/p/lib/default.nix", "{ }");
+        fs.insert("/p/old.nix", "import ./modules/c.nix");
+        fs.insert("/p/modules/c.nix", "{ }");
+
+        let files: Vec<_> = [
+            "/p/flake.nix",
+            "/p/lib/default.nix",
+            "/p/modules/a.nix",
+            "/p/modules/b.nix",
+            "/p/modules/c.nix",
+            "/p/old.nix",
+            "/p/README.md",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        let entries = [PathBuf::from("/p/flake.nix")];
+        assert_eq!(
+            unreferenced(&files, &entries, &fs).unwrap(),
+            [
+                PathBuf::from("/p/modules/c.nix"),
+                PathBuf::from("/p/old.nix")
+            ]
+        );
+    }
+}
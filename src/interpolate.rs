@@ -0,0 +1,328 @@
+//! Refactors between string interpolation and concatenation.
+//!
+//! `"pre ${x} post"` and `"pre " + x + " post"` build the same string, and either can be easier
+//! to read depending on how much is interpolated. These refactors rewrite one form into the other
+//! and extract an interpolated expression into a `let` binding. Only double-quoted strings are
+//! rewritten, since their escapes carry over unchanged between the two forms; text is copied from
+//! the source as written rather than printed back from the syntax tree.
+
+use codespan::Span;
+use nix_parser::ast::visit::path_at;
+use nix_parser::ast::{BinaryOp, Expr, ExprString, SourceFile, StringFragment};
+use nix_parser::HasSpan;
+
+/// A refactor which can be applied to a string.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    /// Rewrites `"pre ${x} post"` into `"pre " + x + " post"`.
+    ToConcatenation,
+    /// Rewrites `"pre " + x + " post"` into `"pre ${x} post"`.
+    ToInterpolation,
+    /// Rewrites `"pre ${f x} post"` into `let value = f x; in "pre ${value} post"`.
+    Extract,
+}
+
+impl Action {
+    pub fn title(self) -> &'static str {
+        match self {
+            Action::ToConcatenation => "Convert to concatenation",
+            Action::ToInterpolation => "Convert to interpolation",
+            Action::Extract => "Extract interpolated expression into `let`",
+        }
+    }
+}
+
+/// Returns the refactors applicable at `offset`, each with the span to replace and its new text.
+pub fn actions(source: &str, file: &SourceFile, offset: usize) -> Vec<(Action, Span, String)> {
+    let path = path_at(file.expr(), offset);
+    let mut actions = Vec::new();
+    if let Some(i) = innermost_string(source, &path) {
+        if let Expr::String(ref string) = *path[i] {
+            actions.extend(
+                to_concatenation(source, string).map(|(s, t)| (Action::ToConcatenation, s, t)),
+            );
+            actions
+                .extend(extract(source, &path[..=i], offset).map(|(s, t)| (Action::Extract, s, t)));
+        }
+    }
+    actions.extend(to_interpolation(source, &path).map(|(s, t)| (Action::ToInterpolation, s, t)));
+    actions
+}
+
+fn to_concatenation(source: &str, string: &ExprString) -> Option<(Span, String)> {
+    let fragments = string.fragments();
+    if !fragments
+        .iter()
+        .any(|f| matches!(*f, StringFragment::Interpolation(_)))
+    {
+        return None;
+    }
+
+    let mut operands = Vec::new();
+    if let Some(StringFragment::Interpolation(_)) = fragments.first() {
+        // Starting with a string keeps the result a string, e.g. when the first value is a path.
+        operands.push("\"\"".to_owned());
+    }
+    for fragment in fragments {
+        match *fragment {
+            StringFragment::Literal(_, span) => {
+                operands.push(format!("\"{}\"", slice(source, span)))
+            }
+            StringFragment::Interpolation(ref interpolation) => {
+                let inner = interpolation.inner();
+                let text = slice(source, inner.span());
+                if is_operand(inner) {
+                    operands.push(text.to_owned());
+                } else {
+                    operands.push(format!("({})", text));
+                }
+            }
+        }
+    }
+
+    Some((string.span(), operands.join(" + ")))
+}
+
+/// Rewrites the innermost chain of `+` among the expressions of `path`, which enclose one another.
+fn to_interpolation(source: &str, path: &[&Expr]) -> Option<(Span, String)> {
+    let (span, operands) = path.iter().enumerate().rev().find_map(|(i, &expr)| {
+        let operands = operands(expr)?;
+        // Skip chains which are themselves operands of a larger one.
+        let parent = i.checked_sub(1).map(|i| path[i]);
+        if parent
+            .and_then(operands_of)
+            .is_some_and(|(left, _)| std::ptr::eq(left, expr))
+        {
+            return None;
+        }
+        Some((expr.span(), operands))
+    })?;
+
+    if !is_double_quoted(source, operands[0]) {
+        return None;
+    }
+
+    let mut text = String::from("\"");
+    for operand in operands {
+        match *operand {
+            Expr::String(ref string) if is_double_quoted(source, operand) => {
+                let span = string.span();
+                text.push_str(&source[span.start().to_usize() + 1..span.end().to_usize() - 1]);
+            }
+            Expr::String(_) => return None,
+            ref other => {
+                let inner = match *other {
+                    Expr::Paren(ref paren) => paren.expr(),
+                    ref other => other,
+                };
+                text.push_str("${");
+                text.push_str(slice(source, inner.span()));
+                text.push('}');
+            }
+        }
+    }
+    text.push('"');
+    Some((span, text))
+}
+
+/// Extracts the interpolation at `offset` out of the last expression of `path`, a string enclosed
+/// by the others.
+fn extract(source: &str, path: &[&Expr], offset: usize) -> Option<(Span, String)> {
+    let string = match **path.last()? {
+        Expr::String(ref string) => string,
+        _ => return None,
+    };
+    let interpolation = string
+        .fragments()
+        .iter()
+        .find_map(|fragment| match *fragment {
+            StringFragment::Interpolation(ref interpolation)
+                if contains(interpolation.span(), offset) =>
+            {
+                Some(interpolation)
+            }
+            _ => None,
+        })?;
+
+    let inner = interpolation.inner();
+    let base = match *inner {
+        Expr::Ident(_) => return None,
+        Expr::Proj(ref proj) => proj
+            .attr()
+            .segments()
+            .last()
+            .and_then(|s| s.name())
+            .unwrap_or("value"),
+        _ => "value",
+    };
+    let name = fresh_name(source, base);
+
+    let mut body = String::new();
+    let span = string.span();
+    body.push_str(slice(
+        source,
+        Span::new(span.start(), interpolation.span().start()),
+    ));
+    body.push_str(&format!("${{{}}}", name));
+    body.push_str(slice(
+        source,
+        Span::new(interpolation.span().end(), span.end()),
+    ));
+
+    let text = format!(
+        "let {} = {}; in {}",
+        name,
+        slice(source, inner.span()),
+        body
+    );
+    let bare = match path.len().checked_sub(2).map(|i| path[i]) {
+        None => true,
+        Some(parent) => matches!(
+            *parent,
+            Expr::Set(_)
+                | Expr::Rec(_)
+                | Expr::Let(_)
+                | Expr::LetIn(_)
+                | Expr::Paren(_)
+                | Expr::FnDecl(_)
+        ),
+    };
+    if bare {
+        Some((span, text))
+    } else {
+        Some((span, format!("({})", text)))
+    }
+}
+
+/// Returns the position in `path` of the innermost double-quoted string.
+fn innermost_string(source: &str, path: &[&Expr]) -> Option<usize> {
+    path.iter()
+        .rposition(|expr| matches!(**expr, Expr::String(_)) && is_double_quoted(source, expr))
+}
+
+/// Returns the operands of a chain of `+` with a string among them, from left to right.
+fn operands(expr: &Expr) -> Option<Vec<&Expr>> {
+    let (mut left, right) = operands_of(expr)?;
+    let mut operands = vec![right];
+    while let Some((next, right)) = operands_of(left) {
+        operands.push(right);
+        left = next;
+    }
+    operands.push(left);
+    operands.reverse();
+
+    if operands.iter().any(|e| matches!(**e, Expr::String(_))) {
+        Some(operands)
+    } else {
+        None
+    }
+}
+
+fn operands_of(expr: &Expr) -> Option<(&Expr, &Expr)> {
+    match *expr {
+        Expr::Binary(ref binary) if binary.op() == BinaryOp::Add => {
+            Some((binary.left(), binary.right()))
+        }
+        _ => None,
+    }
+}
+
+/// Returns whether `expr` can be an operand of `+` without parentheses.
+fn is_operand(expr: &Expr) -> bool {
+    matches!(
+        *expr,
+        Expr::Ident(_)
+            | Expr::Literal(_)
+            | Expr::String(_)
+            | Expr::List(_)
+            | Expr::Set(_)
+            | Expr::Rec(_)
+            | Expr::Paren(_)
+            | Expr::Proj(_)
+    )
+}
+
+fn is_double_quoted(source: &str, expr: &Expr) -> bool {
+    matches!(*expr, Expr::String(_)) && source[expr.span().start().to_usize()..].starts_with('"')
+}
+
+/// Returns `base`, or `base` with a number appended, such that it does not occur in `source`.
+fn fresh_name(source: &str, base: &str) -> String {
+    let taken = |name: &str| {
+        source.match_indices(name).any(|(i, _)| {
+            let before = source[..i].chars().next_back();
+            let after = source[i + name.len()..].chars().next();
+            !before.is_some_and(is_ident_char) && !after.is_some_and(is_ident_char)
+        })
+    };
+
+    if !taken(base) {
+        return base.to_owned();
+    }
+    (1..)
+        .map(|n| format!("{}{}", base, n))
+        .find(|name| !taken(name))
+        .unwrap()
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '\''
+}
+
+fn contains(span: Span, offset: usize) -> bool {
+    span.start().to_usize() <= offset && offset <= span.end().to_usize()
+}
+
+fn slice(source: &str, span: Span) -> &str {
+    &source[span.start().to_usize()..span.end().to_usize()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(source: &str, at: &str, action: Action) -> Option<String> {
+        let file: SourceFile = source.parse().unwrap();
+        let offset = source.find(at).unwrap();
+        let (_, span, text) = actions(source, &file, offset)
+            .into_iter()
+            .find(|&(a, _, _)| a == action)?;
+        let mut result = source.to_owned();
+        result.replace_range(span.start().to_usize()..span.end().to_usize(), &text);
+        Some(result)
+    }
+
+    #[test]
+    fn converts_between_forms() {
+        let source = r#"{ a = "pre\n ${x.y} ${f x} post"; b = "${p}/bin"; }"#;
+        assert_eq!(
+            apply(source, "pre", Action::ToConcatenation).unwrap(),
+            r#"{ a = "pre\n " + x.y + " " + (f x) + " post"; b = "${p}/bin"; }"#
+        );
+        assert_eq!(
+            apply(source, "/bin", Action::ToConcatenation).unwrap(),
+            r#"{ a = "pre\n ${x.y} ${f x} post"; b = "" + p + "/bin"; }"#
+        );
+
+        let source = r#"{ a = "pre " + x + " " + (f x) + "${y} post"; b = p + "/bin"; }"#;
+        assert_eq!(
+            apply(source, "pre", Action::ToInterpolation).unwrap(),
+            r#"{ a = "pre ${x} ${f x}${y} post"; b = p + "/bin"; }"#
+        );
+        assert_eq!(apply(source, "/bin", Action::ToInterpolation), None);
+    }
+
+    #[test]
+    fn extracts_interpolations() {
+        let source = r#"{ a = "${pkgs.hello}/bin ${hello}"; b = f "${g x}"; }"#;
+        assert_eq!(
+            apply(source, "pkgs", Action::Extract).unwrap(),
+            r#"{ a = let hello1 = pkgs.hello; in "${hello1}/bin ${hello}"; b = f "${g x}"; }"#
+        );
+        assert_eq!(
+            apply(source, "g x", Action::Extract).unwrap(),
+            r#"{ a = "${pkgs.hello}/bin ${hello}"; b = f (let value = g x; in "${value}"); }"#
+        );
+        assert_eq!(apply(source, "hello}\"", Action::Extract), None);
+    }
+}
@@ -1,12 +1,85 @@
 #![forbid(unsafe_code)]
 
+use std::path::PathBuf;
+
+use futures::sync::mpsc;
+use futures::{Future, Stream};
+use jsonrpc_core::{IoHandler, Params};
 use log::info;
 use structopt::StructOpt;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tower_lsp::lsp_types::request::{
+    CodeActionRequest, CodeLensRequest, DocumentSymbolRequest, Formatting, GotoDefinition,
+    GotoTypeDefinition, References, Rename, Request,
+};
+use tower_lsp::lsp_types::{
+    CodeActionParams, CodeLensParams, DocumentFormattingParams, DocumentSymbolParams,
+    ReferenceParams, RenameParams, TextDocumentIdentifier, TextDocumentPositionParams,
+};
 use tower_lsp::{LspService, Server};
 
-use crate::backend::Nix;
+pub use crate::cli::Command;
+
+use crate::backend::{HighlightParams, Nix};
+use crate::metrics::METRICS;
+use crate::progress::Routed;
+use crate::session::{Direction, Tap, RECORDER};
 
+mod attrs;
 mod backend;
+mod baseline;
+mod binding;
+mod breadcrumb;
+mod builtins;
+mod bump;
+mod call_package;
+mod canonical;
+mod cli;
+mod compat;
+mod constant;
+mod dap;
+mod db;
+mod disk;
+mod dot;
+mod eval;
+mod explain;
+mod failures;
+mod fixes;
+mod flake;
+mod glossary;
+mod hashes;
+mod highlight;
+mod impact;
+mod imports;
+mod interpolate;
+mod lint;
+mod metrics;
+mod naming;
+mod options;
+mod organize;
+mod outline;
+mod overrides;
+mod paths;
+mod preview;
+mod progress;
+mod rename;
+mod resolve;
+mod role;
+mod security;
+mod session;
+mod severity;
+mod sexp;
+mod shell;
+mod snapshot;
+mod suggest;
+mod suppress;
+mod sync;
+mod systems;
+mod text;
+mod todo;
+mod unused;
+pub mod vfs;
+mod workspace;
 
 pub type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
@@ -14,21 +87,132 @@ pub type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 pub struct Args {
     /// Enable interactive mode
     #[structopt(short = "i", long = "interactive")]
-    interactive: bool,
+    pub interactive: bool,
+    /// Record every message exchanged with the client to the given file, for `replay`
+    #[structopt(long = "record", parse(from_os_str))]
+    pub record: Option<PathBuf>,
+    /// Run a command over files on disk instead of starting the server
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
 }
 
-pub fn run(_args: Args) {
+pub fn run(args: Args) {
+    if let Some(command) = args.command {
+        std::process::exit(cli::run(command));
+    }
+
     env_logger::init();
     info!("Nix Language Server {}", env!("CARGO_PKG_VERSION"));
 
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
+    match args.record {
+        Some(path) => {
+            if let Err(e) = RECORDER.start(&path) {
+                eprintln!("error: cannot record to {}: {}", path.display(), e);
+                std::process::exit(2);
+            }
+            let stdin = Tap::new(stdin, Direction::In);
+            let stdout = Tap::new(stdout, Direction::Out);
+            tokio::run(serve(stdin, stdout));
+        }
+        None => tokio::run(serve(stdin, stdout)),
+    }
+}
+
+/// Returns a future serving the language server over the given streams until the client exits.
+fn serve<I, O>(stdin: I, stdout: O) -> impl Future<Item = (), Error = ()> + Send
+where
+    I: AsyncRead + Send + 'static,
+    O: AsyncWrite + Send + 'static,
+{
+    let mut handler = IoHandler::new();
+    handler.add_method("nix/metrics", |_| {
+        Ok(serde_json::to_value(METRICS.report()).unwrap())
+    });
+
+    let (notifications, background) = mpsc::unbounded();
+    let server = Nix::new(notifications);
+    let backend = server.clone();
+    handler.add_method(CodeActionRequest::METHOD, move |params: Params| {
+        let _timer = METRICS.timer(CodeActionRequest::METHOD);
+        let params: CodeActionParams = params.parse()?;
+        Ok(serde_json::to_value(backend.code_action(params)).unwrap())
+    });
+
+    let backend = server.clone();
+    handler.add_method(GotoDefinition::METHOD, move |params: Params| {
+        let params: TextDocumentPositionParams = params.parse()?;
+        Ok(serde_json::to_value(backend.definition(params)).unwrap())
+    });
+
+    let backend = server.clone();
+    handler.add_method(References::METHOD, move |params: Params| {
+        let params: ReferenceParams = params.parse()?;
+        Ok(serde_json::to_value(backend.references(params)).unwrap())
+    });
+
+    let backend = server.clone();
+    handler.add_method(GotoTypeDefinition::METHOD, move |params: Params| {
+        let params: TextDocumentPositionParams = params.parse()?;
+        Ok(serde_json::to_value(backend.type_definition(params)).unwrap())
+    });
+
+    let backend = server.clone();
+    handler.add_method(DocumentSymbolRequest::METHOD, move |params: Params| {
+        let params: DocumentSymbolParams = params.parse()?;
+        Ok(serde_json::to_value(backend.document_symbol(params)).unwrap())
+    });
+
+    let backend = server.clone();
+    handler.add_method(Formatting::METHOD, move |params: Params| {
+        let params: DocumentFormattingParams = params.parse()?;
+        Ok(serde_json::to_value(backend.formatting(params)).unwrap())
+    });
+
+    let backend = server.clone();
+    handler.add_method("nix/embeddedShell", move |params: Params| {
+        let params: TextDocumentIdentifier = params.parse()?;
+        Ok(serde_json::to_value(backend.embedded_shell(params)).unwrap())
+    });
+
+    let backend = server.clone();
+    handler.add_method("nix/highlightRanges", move |params: Params| {
+        let params: HighlightParams = params.parse()?;
+        Ok(serde_json::to_value(backend.highlight_ranges(params)).unwrap())
+    });
+
+    let backend = server.clone();
+    handler.add_method(CodeLensRequest::METHOD, move |params: Params| {
+        let _timer = METRICS.timer(CodeLensRequest::METHOD);
+        let params: CodeLensParams = params.parse()?;
+        Ok(serde_json::to_value(backend.code_lens(params)).unwrap())
+    });
+
+    let backend = server.clone();
+    handler.add_method("nix/valuePreview", move |params: Params| {
+        let params: TextDocumentPositionParams = params.parse()?;
+        Ok(serde_json::to_value(backend.value_preview(params)?).unwrap())
+    });
+
+    let backend = server.clone();
+    handler.add_method(Rename::METHOD, move |params: Params| {
+        let params: RenameParams = params.parse()?;
+        Ok(serde_json::to_value(backend.rename(params)?).unwrap())
+    });
+
+    let backend = server.clone();
+    handler.add_method("nix/renameReport", move |params: Params| {
+        let params: RenameParams = params.parse()?;
+        Ok(serde_json::to_value(backend.rename_report(params)?).unwrap())
+    });
 
-    let (service, messages) = LspService::new(Nix::new());
+    let client = server.client();
+    let (service, messages) = LspService::with_handler(server, handler);
     let handle = service.close_handle();
     let server = Server::new(stdin, stdout)
-        .interleave(messages)
-        .serve(service);
+        .interleave(messages.select(background))
+        .serve(Routed::new(service, client));
 
-    tokio::run(handle.run_until_exit(server));
+    handle.run_until_exit(server)
 }
@@ -0,0 +1,59 @@
+//! Diagnostics for the rules registered in `nix_parser::lint`, such as deprecated syntax.
+//!
+//! Each diagnostic has the name of its rule as code. The fixes suggested by the rules are offered
+//! as quick fixes by recomputing the lints of the document a diagnostic was published for.
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use nix_parser::ast::SourceFile;
+use nix_parser::lint::{self, Suggestion};
+
+/// Returns a warning for every lint found in `file`, parsed from `source`.
+pub fn check(id: FileId, source: &str, file: &SourceFile) -> Vec<Diagnostic> {
+    lint::check(source, file)
+        .into_iter()
+        .map(|lint| {
+            let label = Label::new(id, lint.span, lint.label);
+            Diagnostic::new_warning(lint.message, label).with_code(lint.rule)
+        })
+        .collect()
+}
+
+/// Returns the fix suggested for the lint of rule `code` at `span`, if any.
+pub fn suggestion(source: &str, file: &SourceFile, code: &str, span: Span) -> Option<Suggestion> {
+    let rule = lint::rule(code)?;
+    rule.check(source, file)
+        .into_iter()
+        .find(|lint| lint.span == span)?
+        .suggestion
+}
+
+#[cfg(test)]
+mod tests {
+    use codespan::Files;
+
+    use super::*;
+    use crate::suppress;
+
+    #[test]
+    fn reports_deprecated_syntax() {
+        let source = "let { src = https://example.org; body = src; }\n";
+        let file: SourceFile = source.parse().unwrap();
+        let id = Files::new().add("default.nix", source);
+        let diagnostics = check(id, source, &file);
+        let codes: Vec<_> = diagnostics.iter().filter_map(|d| d.code.clone()).collect();
+        assert_eq!(codes, ["deprecated-let", "deprecated-uri"]);
+
+        let span = diagnostics[1].primary_label.span;
+        let fix = suggestion(source, &file, "deprecated-uri", span).unwrap();
+        assert_eq!(fix.replacement, "\"https://example.org\"");
+        assert!(suggestion(source, &file, "undefined-variable", span).is_none());
+    }
+
+    #[test]
+    fn registers_rules_for_suppression() {
+        for rule in lint::RULES {
+            assert!(suppress::RULES.contains(&rule.name), "{}", rule.name);
+        }
+    }
+}
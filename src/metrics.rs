@@ -0,0 +1,164 @@
+//! Timing and cache statistics collected over the lifetime of the server.
+//!
+//! Statistics are recorded into the global [`METRICS`] registry and reported through the custom
+//! `nix/metrics` request. When built with the `tracing` feature, every timed operation is also
+//! wrapped in a `tracing` span.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+/// The metrics registry shared by the whole server.
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+/// Aggregated durations of a single kind of operation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Timing {
+    pub count: u64,
+    pub total_ms: f64,
+    pub max_ms: f64,
+}
+
+impl Timing {
+    fn record(&mut self, elapsed: Duration) {
+        let ms = elapsed.as_secs_f64() * 1000.0;
+        self.count += 1;
+        self.total_ms += ms;
+        self.max_ms = self.max_ms.max(ms);
+    }
+}
+
+/// Hit and miss counts of a memoized query.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// The fraction of lookups served from the cache.
+    pub hit_rate: f64,
+}
+
+impl CacheStats {
+    fn record(&mut self, hit: bool) {
+        if hit {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        self.hit_rate = self.hits as f64 / (self.hits + self.misses) as f64;
+    }
+}
+
+/// A point-in-time copy of all metrics, as returned by `nix/metrics`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    pub uptime_ms: f64,
+    pub timings: BTreeMap<&'static str, Timing>,
+    pub caches: BTreeMap<&'static str, CacheStats>,
+}
+
+#[derive(Debug)]
+pub struct Metrics {
+    started: Instant,
+    timings: Mutex<BTreeMap<&'static str, Timing>>,
+    caches: Mutex<BTreeMap<&'static str, CacheStats>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            started: Instant::now(),
+            timings: Mutex::new(BTreeMap::new()),
+            caches: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Runs `f`, recording how long it took under the given name.
+    pub fn time<T, F: FnOnce() -> T>(&self, name: &'static str, f: F) -> T {
+        let _timer = self.timer(name);
+        f()
+    }
+
+    /// Starts timing an operation, which is recorded under the given name once the returned
+    /// guard is dropped.
+    pub fn timer(&self, name: &'static str) -> Timer<'_> {
+        Timer {
+            metrics: self,
+            name,
+            start: Instant::now(),
+            #[cfg(feature = "tracing")]
+            _span: tracing::info_span!("timed", name).entered(),
+        }
+    }
+
+    pub fn record(&self, name: &'static str, elapsed: Duration) {
+        let mut timings = self.timings.lock().unwrap_or_else(|e| e.into_inner());
+        timings.entry(name).or_default().record(elapsed);
+    }
+
+    /// Records a lookup of the named query, which was either served from the cache or recomputed.
+    pub fn cache(&self, name: &'static str, hit: bool) {
+        let mut caches = self.caches.lock().unwrap_or_else(|e| e.into_inner());
+        caches.entry(name).or_default().record(hit);
+    }
+
+    pub fn report(&self) -> Report {
+        let timings = self.timings.lock().unwrap_or_else(|e| e.into_inner());
+        let caches = self.caches.lock().unwrap_or_else(|e| e.into_inner());
+        Report {
+            uptime_ms: self.started.elapsed().as_secs_f64() * 1000.0,
+            timings: timings.clone(),
+            caches: caches.clone(),
+        }
+    }
+}
+
+/// Guard returned by [`Metrics::timer`].
+#[derive(Debug)]
+pub struct Timer<'a> {
+    metrics: &'a Metrics,
+    name: &'static str,
+    start: Instant,
+    #[cfg(feature = "tracing")]
+    _span: tracing::span::EnteredSpan,
+}
+
+impl Drop for Timer<'_> {
+    fn drop(&mut self) {
+        self.metrics.record(self.name, self.start.elapsed());
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_timings_and_cache_lookups() {
+        let metrics = Metrics::new();
+        assert_eq!(metrics.time("parse", || 42), 42);
+        metrics.record("parse", Duration::from_millis(5));
+        metrics.cache("parse", true);
+        metrics.cache("parse", true);
+        metrics.cache("parse", false);
+
+        let report = metrics.report();
+        let parse = report.timings["parse"];
+        assert_eq!(parse.count, 2);
+        assert!(parse.max_ms >= 5.0 && parse.total_ms >= parse.max_ms);
+        let cache = report.caches["parse"];
+        assert_eq!((cache.hits, cache.misses), (2, 1));
+        assert!((cache.hit_rate - 2.0 / 3.0).abs() < f64::EPSILON);
+    }
+}
@@ -0,0 +1,401 @@
+//! Checks that bound names follow a naming convention.
+//!
+//! The convention is configured through the `namingStyle` setting, either as a single style for
+//! every name or as an object with a style for each kind of binding: `let` for names bound by
+//! `let`, `attrs` for attributes of sets and `params` for function arguments. Nothing is checked
+//! for kinds without a style. Names starting with `_`, inherited names and quoted attributes are
+//! never reported, since they are usually dictated by something else.
+//!
+//! Renaming an attribute may break code outside of the file, so a new name is only suggested for
+//! `let` bindings and function arguments, and only when every occurrence can be renamed safely.
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use nix_parser::ast::tokens::Ident;
+use nix_parser::ast::visit::descendants;
+use nix_parser::ast::{AttrSegment, Bind, Expr, ExprFnDecl, Formal, SourceFile};
+use nix_parser::HasSpan;
+use serde_json::Value;
+
+use crate::rename;
+
+pub const CODE: &str = "naming-style";
+
+/// A naming convention.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Style {
+    /// `fooBar`, as used by nixpkgs.
+    CamelCase,
+    /// `foo_bar`.
+    SnakeCase,
+    /// Anything but `foo-bar`, which cannot be referenced as a variable after `-` in many places.
+    NoKebab,
+}
+
+impl Style {
+    fn from_setting(value: &str) -> Option<Self> {
+        match value {
+            "camelCase" => Some(Style::CamelCase),
+            "snake_case" => Some(Style::SnakeCase),
+            "no-kebab" => Some(Style::NoKebab),
+            _ => None,
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Style::CamelCase => "camelCase",
+            Style::SnakeCase => "snake_case",
+            Style::NoKebab => "free of dashes",
+        }
+    }
+
+    /// Returns whether `name` follows this style.
+    pub fn accepts(self, name: &str) -> bool {
+        let name = name.trim_start_matches('_');
+        match self {
+            Style::CamelCase => {
+                !name.contains(['_', '-']) && !name.starts_with(|c: char| c.is_ascii_uppercase())
+            }
+            Style::SnakeCase => !name.contains('-') && !name.contains(|c: char| c.is_uppercase()),
+            Style::NoKebab => !name.contains('-'),
+        }
+    }
+
+    /// Converts `name` to this style, keeping any leading underscores and trailing quotes.
+    pub fn convert(self, name: &str) -> String {
+        let body = name.trim_start_matches('_');
+        let prefix = &name[..name.len() - body.len()];
+        let stem = body.trim_end_matches('\'');
+        let suffix = &body[stem.len()..];
+
+        let words = words(stem);
+        let converted = match self {
+            Style::CamelCase | Style::NoKebab => words
+                .iter()
+                .enumerate()
+                .map(|(i, word)| match i {
+                    0 => word.to_lowercase(),
+                    _ => capitalize(word),
+                })
+                .collect::<String>(),
+            Style::SnakeCase => words
+                .iter()
+                .map(|word| word.to_lowercase())
+                .collect::<Vec<_>>()
+                .join("_"),
+        };
+        format!("{}{}{}", prefix, converted, suffix)
+    }
+}
+
+/// Splits a name into words at dashes, underscores and lowercase to uppercase boundaries.
+fn words(name: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    let mut previous = None;
+    for (i, c) in name.char_indices() {
+        if c == '_' || c == '-' {
+            if start < i {
+                words.push(&name[start..i]);
+            }
+            start = i + 1;
+        } else if c.is_uppercase() && previous.is_some_and(|p: char| p.is_lowercase()) {
+            words.push(&name[start..i]);
+            start = i;
+        }
+        previous = Some(c);
+    }
+    if start < name.len() {
+        words.push(&name[start..]);
+    }
+    words
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first
+            .to_uppercase()
+            .chain(chars.flat_map(char::to_lowercase))
+            .collect(),
+        None => String::new(),
+    }
+}
+
+/// The kinds of bindings which can be given different styles.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Kind {
+    Let,
+    Attr,
+    Param,
+}
+
+impl Kind {
+    fn description(self) -> &'static str {
+        match self {
+            Kind::Let => "variable",
+            Kind::Attr => "attribute",
+            Kind::Param => "argument",
+        }
+    }
+}
+
+/// The style of each kind of binding, if any.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Config {
+    pub let_bindings: Option<Style>,
+    pub attrs: Option<Style>,
+    pub params: Option<Style>,
+}
+
+impl Config {
+    /// Reads the `namingStyle` setting from initialization options or workspace settings, which
+    /// may nest it under a `nix` section.
+    pub fn from_settings(settings: &Value) -> Self {
+        let section = settings.get("nix").unwrap_or(settings);
+        let style = |value: Option<&Value>| value?.as_str().and_then(Style::from_setting);
+        match section.get("namingStyle") {
+            Some(Value::Object(scopes)) => Config {
+                let_bindings: style(scopes.get("let")),
+                attrs: style(scopes.get("attrs")),
+                params: style(scopes.get("params")),
+            },
+            value => {
+                let style = style(value);
+                Config {
+                    let_bindings: style,
+                    attrs: style,
+                    params: style,
+                }
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Config::default()
+    }
+
+    fn style(&self, kind: Kind) -> Option<Style> {
+        match kind {
+            Kind::Let => self.let_bindings,
+            Kind::Attr => self.attrs,
+            Kind::Param => self.params,
+        }
+    }
+}
+
+/// Returns a diagnostic for every name in `file` which does not follow the style configured for
+/// its kind of binding.
+pub fn check(id: FileId, file: &SourceFile, config: &Config) -> Vec<Diagnostic> {
+    if config.is_empty() {
+        return Vec::new();
+    }
+
+    names(file)
+        .into_iter()
+        .filter_map(|(name, kind)| {
+            let style = config.style(kind)?;
+            let text = name.as_str();
+            if text.starts_with('_') || style.accepts(text) {
+                return None;
+            }
+
+            let message = format!(
+                "{} `{}` is not {}",
+                kind.description(),
+                text,
+                style.description()
+            );
+            let label = Label::new(id, name.span(), "does not follow the naming style");
+            let diagnostic = Diagnostic::new_warning(message, label).with_code(CODE);
+            match suggestion(file, name, kind, style) {
+                Some((new_name, _)) => Some(diagnostic.with_notes(vec![rename_note(&new_name)])),
+                None => Some(diagnostic),
+            }
+        })
+        .collect()
+}
+
+/// Returns the names bound in `file` along with their kind of binding, in source order.
+fn names(file: &SourceFile) -> Vec<(&Ident, Kind)> {
+    let mut names = Vec::new();
+    for expr in descendants(file.expr()) {
+        match *expr {
+            Expr::Let(ref e) => binds(e.binds(), Kind::Let, &mut names),
+            Expr::LetIn(ref e) => binds(e.binds(), Kind::Let, &mut names),
+            Expr::Set(ref set) => binds(set.binds(), Kind::Attr, &mut names),
+            Expr::Rec(ref rec) => binds(rec.binds(), Kind::Attr, &mut names),
+            Expr::FnDecl(ref decl) => match **decl {
+                ExprFnDecl::Simple(ref simple) => names.push((simple.name(), Kind::Param)),
+                ExprFnDecl::Formals(ref formals) => {
+                    let params = formals.formals().iter().map(Formal::name);
+                    let params = params.chain(formals.extra());
+                    names.extend(params.map(|name| (name, Kind::Param)));
+                }
+            },
+            _ => {}
+        }
+    }
+    names.sort_by_key(|(name, _)| name.span().start());
+    names
+}
+
+/// Collects the identifiers bound by `binds`. Only the first segment of an attribute path is a
+/// variable in a `let`, the rest being attributes of its value.
+fn binds<'a>(binds: &'a [Bind], kind: Kind, names: &mut Vec<(&'a Ident, Kind)>) {
+    for bind in binds {
+        if let Bind::Simple(ref simple) = *bind {
+            for (i, segment) in simple.attr().segments().iter().enumerate() {
+                if let AttrSegment::Ident(ref ident) = *segment {
+                    names.push((ident, if i == 0 { kind } else { Kind::Attr }));
+                }
+            }
+        }
+    }
+}
+
+/// Returns the name in `style` to rename `name` to, along with the spans of its occurrences to
+/// replace, if every occurrence of it can be renamed.
+fn suggestion(
+    file: &SourceFile,
+    name: &Ident,
+    kind: Kind,
+    style: Style,
+) -> Option<(String, Vec<Span>)> {
+    if kind == Kind::Attr {
+        return None;
+    }
+
+    let new_name = style.convert(name.as_str());
+    if new_name == name.as_str() || !style.accepts(&new_name) || !rename::is_identifier(&new_name) {
+        return None;
+    }
+    let spans = rename_spans(file, name.span().start().to_usize(), &new_name)?;
+    Some((new_name, spans))
+}
+
+/// Returns the name to rename the binding reported at `span` to, along with the spans of its
+/// occurrences to replace, if it breaks the style configured for its kind and every occurrence of
+/// it can be renamed.
+pub fn renaming(file: &SourceFile, config: &Config, span: Span) -> Option<(String, Vec<Span>)> {
+    let (name, kind) = names(file)
+        .into_iter()
+        .find(|(name, _)| name.span() == span)?;
+    let style = config.style(kind)?;
+    if name.as_str().starts_with('_') || style.accepts(name.as_str()) {
+        return None;
+    }
+    suggestion(file, name, kind, style)
+}
+
+/// Formats the note attached to diagnostics for names which can be renamed safely.
+fn rename_note(new_name: &str) -> String {
+    format!("can be renamed to `{}` throughout the file", new_name)
+}
+
+/// Returns the spans to replace with `new_name` to rename the name at `offset`, if every
+/// occurrence of it can be renamed.
+fn rename_spans(file: &SourceFile, offset: usize, new_name: &str) -> Option<Vec<Span>> {
+    let report = rename::report(file, offset, Some(new_name))?;
+    if report.uncertain.is_empty() {
+        Some(report.definite)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use codespan::Files;
+    use serde_json::json;
+
+    use super::*;
+    use crate::fixes::{fix, Problem};
+
+    #[test]
+    fn converts_between_styles() {
+        assert_eq!(Style::CamelCase.convert("foo_bar-baz"), "fooBarBaz");
+        assert_eq!(Style::CamelCase.convert("_fooBar'"), "_fooBar'");
+        assert_eq!(Style::SnakeCase.convert("fooBarBaz"), "foo_bar_baz");
+        assert_eq!(Style::SnakeCase.convert("foo-bar"), "foo_bar");
+        assert_eq!(Style::NoKebab.convert("foo-bar"), "fooBar");
+
+        assert!(Style::CamelCase.accepts("fooBar2"));
+        assert!(!Style::CamelCase.accepts("foo_bar"));
+        assert!(Style::SnakeCase.accepts("foo_bar"));
+        assert!(!Style::SnakeCase.accepts("fooBar"));
+        assert!(Style::NoKebab.accepts("foo_barBaz"));
+    }
+
+    #[test]
+    fn reads_per_scope_settings() {
+        let config = Config::from_settings(&json!({ "namingStyle": "camelCase" }));
+        assert_eq!(config.attrs, Some(Style::CamelCase));
+        assert_eq!(config.params, Some(Style::CamelCase));
+
+        let settings = json!({ "nix": { "namingStyle": { "let": "snake_case" } } });
+        let config = Config::from_settings(&settings);
+        assert_eq!(config.let_bindings, Some(Style::SnakeCase));
+        assert_eq!((config.attrs, config.params), (None, None));
+        assert!(Config::from_settings(&json!({})).is_empty());
+    }
+
+    #[test]
+    fn suggests_safe_renames() {
+        let source = r#"{ my_arg, ... }:
+let
+  foo_bar = my_arg;
+  taken_name = 1;
+  takenName = 2;
+  _private_name = 1;
+in {
+  some_attr = foo_bar;
+  "quoted_attr" = taken_name + takenName + _private_name;
+}"#;
+        let mut files = Files::new();
+        let id = files.add("default.nix", source);
+        let file: SourceFile = source.parse().unwrap();
+        let config = Config {
+            let_bindings: Some(Style::CamelCase),
+            attrs: Some(Style::CamelCase),
+            params: Some(Style::CamelCase),
+        };
+
+        let diagnostics = check(id, &file, &config);
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "argument `my_arg` is not camelCase",
+                "variable `foo_bar` is not camelCase",
+                "variable `taken_name` is not camelCase",
+                "attribute `some_attr` is not camelCase",
+            ]
+        );
+
+        let renames: Vec<_> = diagnostics
+            .iter()
+            .map(|d| {
+                let problem = Problem {
+                    source,
+                    path: None,
+                    file: Some(&file),
+                    code: d.code.as_deref(),
+                    span: d.primary_label.span,
+                    naming: config,
+                };
+                fix(&problem).map(|fix| (fix.title, fix.edits.len()))
+            })
+            .collect();
+        assert_eq!(
+            renames,
+            [
+                Some(("Rename to `myArg`".to_owned(), 2)),
+                Some(("Rename to `fooBar`".to_owned(), 2)),
+                None,
+                None,
+            ]
+        );
+    }
+}
@@ -0,0 +1,343 @@
+//! Declarations of and references to NixOS module options.
+//!
+//! A module declares options under its `options` attribute with `mkOption`, and sets them under
+//! `config`, or at the top level when it declares no options at all. Both are found syntactically,
+//! so only options written out as attribute paths are understood: `config.services.foo.enable`
+//! and `services.foo.enable = true;` refer to the option, while `cfg.enable` does not.
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use codespan::Span;
+use nix_parser::ast::tokens::Literal;
+use nix_parser::ast::visit::descendants;
+use nix_parser::ast::{AttrSegment, Bind, Expr, ExprFnDecl, SourceFile};
+use nix_parser::HasSpan;
+
+use crate::imports::Import;
+use crate::vfs::{FileLoader, PathResolver};
+
+/// An option declared with `mkOption`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Declaration {
+    /// The attribute path of the option below `options`.
+    pub path: Vec<String>,
+    /// The span of the `mkOption` call.
+    pub span: Span,
+    /// The value of the `type` attribute, as written in the source.
+    pub type_expr: Option<(String, Span)>,
+    /// The value of the `default` attribute, as written in the source.
+    pub default: Option<String>,
+}
+
+/// A declaration found in one of the modules searched by [`find`].
+#[derive(Clone, Debug)]
+pub struct Found {
+    pub file: PathBuf,
+    pub text: String,
+    pub declaration: Declaration,
+}
+
+/// Returns the options declared by the given module.
+pub fn declarations(file: &SourceFile) -> Vec<Declaration> {
+    let mut paths = Vec::new();
+    if let Some(binds) = module_binds(file.expr()) {
+        walk(binds, &mut Vec::new(), &mut paths);
+    }
+
+    paths
+        .into_iter()
+        .filter(|(path, _)| path.first().map(String::as_str) == Some("options"))
+        .filter_map(|(path, expr)| {
+            let app = match *expr {
+                Expr::FnApp(ref app) if is_mk_option(app.function()) => app,
+                _ => return None,
+            };
+            let attrs = match *app.argument() {
+                Expr::Set(ref set) => set,
+                _ => return None,
+            };
+            Some(Declaration {
+                path: path[1..].to_vec(),
+                span: expr.span(),
+                type_expr: attrs.get(&["type"]).map(|ty| (ty.to_string(), ty.span())),
+                default: attrs.get(&["default"]).map(ToString::to_string),
+            })
+        })
+        .collect()
+}
+
+/// Returns the path of the option referred to at `offset`, either by a projection on `config`
+/// or by a binding which declares or sets it.
+pub fn reference_at(file: &SourceFile, offset: usize) -> Option<Vec<String>> {
+    let projection = descendants(file.expr())
+        .filter(|expr| contains(expr.span(), offset))
+        .filter_map(|expr| match *expr {
+            Expr::Proj(ref proj) => match *proj.base() {
+                Expr::Ident(ref base) if base.as_str() == "config" => {
+                    Some((proj.span(), path_until(proj.attr().segments(), offset)?))
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+        .min_by_key(|(span, _)| span.end() - span.start());
+    if let Some((_, path)) = projection {
+        return Some(path);
+    }
+
+    let binds = module_binds(file.expr())?;
+    let structured = binds.iter().any(|bind| match *bind {
+        Bind::Simple(ref simple) => match simple.attr().segments().first() {
+            Some(first) => first.name() == Some("options") || first.name() == Some("config"),
+            None => false,
+        },
+        _ => false,
+    });
+
+    let path = bind_path_at(binds, offset, Vec::new())?;
+    match path.first().map(String::as_str) {
+        Some("options") | Some("config") => Some(path[1..].to_vec()).filter(|p| !p.is_empty()),
+        Some("imports") => None,
+        _ if structured => None,
+        _ => Some(path),
+    }
+}
+
+/// Returns the modules imported through the `imports` list of the given module.
+pub fn module_imports(file: &SourceFile) -> Vec<Import> {
+    let imports = module_binds(file.expr()).and_then(|binds| {
+        binds.iter().find_map(|bind| match *bind {
+            Bind::Simple(ref simple) => match simple.attr().segments() {
+                [segment] if segment.name() == Some("imports") => Some(simple.expr()),
+                _ => None,
+            },
+            _ => None,
+        })
+    });
+
+    match imports {
+        Some(Expr::List(ref list)) => list
+            .elems()
+            .iter()
+            .filter_map(|elem| match *elem {
+                Expr::Literal(Literal::Path(ref path, span)) => Some(Import {
+                    path: (**path).clone(),
+                    span,
+                }),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Searches the modules reachable from `roots` through their `imports` lists for the declaration
+/// of `option`, returning the one with the longest path which is a prefix of it.
+///
+/// Options of a submodule type declare their own nested options elsewhere, so a reference to
+/// `services.foo.settings.port` is answered by the declaration of `services.foo.settings`.
+pub fn find<F>(option: &[String], roots: Vec<PathBuf>, fs: &F) -> Option<Found>
+where
+    F: FileLoader + PathResolver,
+{
+    let mut seen = BTreeSet::new();
+    let mut pending = roots;
+    let mut best: Option<Found> = None;
+
+    while let Some(path) = pending.pop() {
+        if !seen.insert(path.clone()) {
+            continue;
+        }
+        let text = match fs.load(&path) {
+            Ok(text) => text,
+            Err(_) => continue,
+        };
+        let file: SourceFile = match text.parse() {
+            Ok(file) => file,
+            Err(_) => continue,
+        };
+
+        pending.extend(
+            module_imports(&file)
+                .iter()
+                .map(|import| import.resolve(&path, fs)),
+        );
+
+        let declaration = declarations(&file)
+            .into_iter()
+            .filter(|decl| option.starts_with(&decl.path))
+            .max_by_key(|decl| decl.path.len());
+        if let Some(declaration) = declaration {
+            let longer = best
+                .as_ref()
+                .is_none_or(|best| best.declaration.path.len() < declaration.path.len());
+            if longer {
+                best = Some(Found {
+                    file: path,
+                    text,
+                    declaration,
+                });
+            }
+        }
+    }
+
+    best
+}
+
+/// Returns the bindings of the attribute set a module evaluates to, looking through the function
+/// receiving the module arguments and any `let` or `with` around the set.
+fn module_binds(mut expr: &Expr) -> Option<&[Bind]> {
+    loop {
+        expr = match *expr {
+            Expr::FnDecl(ref decl) => match **decl {
+                ExprFnDecl::Simple(ref decl) => decl.body(),
+                ExprFnDecl::Formals(ref decl) => decl.body(),
+            },
+            Expr::LetIn(ref let_in) => let_in.body(),
+            Expr::With(ref with) => with.expr(),
+            Expr::Paren(ref paren) => paren.expr(),
+            Expr::Set(ref set) => return Some(set.binds()),
+            Expr::Rec(ref rec) => return Some(rec.binds()),
+            _ => return None,
+        };
+    }
+}
+
+/// Collects the full attribute path of every value bound in `binds`, descending into nested sets.
+fn walk<'a>(binds: &'a [Bind], prefix: &mut Vec<String>, out: &mut Vec<(Vec<String>, &'a Expr)>) {
+    for bind in binds {
+        let simple = match *bind {
+            Bind::Simple(ref simple) => simple,
+            _ => continue,
+        };
+        let names: Option<Vec<_>> = simple
+            .attr()
+            .segments()
+            .iter()
+            .map(|segment| segment.name().map(str::to_owned))
+            .collect();
+        let names = match names {
+            Some(names) => names,
+            None => continue,
+        };
+
+        let len = prefix.len();
+        prefix.extend(names);
+        out.push((prefix.clone(), simple.expr()));
+        if let Some(nested) = set_binds(simple.expr()) {
+            walk(nested, prefix, out);
+        }
+        prefix.truncate(len);
+    }
+}
+
+/// Returns the attribute path up to the binding segment containing `offset`, looking into the
+/// values of `lib.mkIf`, `lib.mkDefault` and similar wrappers.
+fn bind_path_at(binds: &[Bind], offset: usize, mut prefix: Vec<String>) -> Option<Vec<String>> {
+    let simple = binds.iter().find_map(|bind| match *bind {
+        Bind::Simple(ref simple) if contains(simple.span(), offset) => Some(simple),
+        _ => None,
+    })?;
+
+    let segments = simple.attr().segments();
+    if contains(simple.attr().span(), offset) {
+        prefix.extend(path_until(segments, offset)?);
+        return Some(prefix);
+    }
+
+    for segment in segments {
+        prefix.push(segment.name()?.to_owned());
+    }
+    let mut value = simple.expr();
+    while let Expr::FnApp(ref app) = *value {
+        value = app.argument();
+    }
+    bind_path_at(set_binds(value)?, offset, prefix)
+}
+
+/// Returns the names of `segments` up to and including the one containing `offset`.
+fn path_until(segments: &[AttrSegment], offset: usize) -> Option<Vec<String>> {
+    let end = segments
+        .iter()
+        .position(|segment| contains(segment.span(), offset))?;
+    segments[..=end]
+        .iter()
+        .map(|segment| segment.name().map(str::to_owned))
+        .collect()
+}
+
+fn set_binds(expr: &Expr) -> Option<&[Bind]> {
+    match *expr {
+        Expr::Set(ref set) => Some(set.binds()),
+        Expr::Rec(ref rec) => Some(rec.binds()),
+        Expr::Paren(ref paren) => set_binds(paren.expr()),
+        _ => None,
+    }
+}
+
+fn is_mk_option(function: &Expr) -> bool {
+    match *function {
+        Expr::Ident(ref ident) => ident.as_str() == "mkOption",
+        Expr::Proj(ref proj) => match proj.attr().segments() {
+            [segment] => segment.name() == Some("mkOption"),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn contains(span: Span, offset: usize) -> bool {
+    span.start().to_usize() <= offset && offset <= span.end().to_usize()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::vfs::MemoryFs;
+
+    #[test]
+    fn finds_declarations_of_references() {
+        let mut fs = MemoryFs::new();
+        let module = "{ config, lib, ... }: with lib; {
+  options.services.foo = {
+    enable = mkOption { type = types.bool; default = false; };
+    settings = lib.mkOption { type = types.attrs; };
+  };
+}";
+        let config = "{ config, ... }: {
+  imports = [ ./foo.nix ];
+  config = lib.mkIf config.services.foo.enable {
+    services.foo.settings.port = 80;
+  };
+}";
+        fs.insert("/m/foo.nix", module);
+        fs.insert("/m/default.nix", config);
+
+        let file: SourceFile = config.parse().unwrap();
+        let at = |needle: &str| {
+            reference_at(&file, config.find(needle).unwrap()).map(|path| path.join("."))
+        };
+        assert_eq!(at("enable {"), Some("services.foo.enable".to_string()));
+        assert_eq!(at("port ="), Some("services.foo.settings.port".to_string()));
+        assert_eq!(at("imports"), None);
+
+        let option = reference_at(&file, config.find("port =").unwrap()).unwrap();
+        let found = find(&option, vec![PathBuf::from("/m/default.nix")], &fs).unwrap();
+        assert_eq!(found.file, Path::new("/m/foo.nix"));
+        assert_eq!(found.declaration.path, ["services", "foo", "settings"]);
+        let (ty, span) = found.declaration.type_expr.unwrap();
+        assert_eq!(ty, "types.attrs");
+        assert_eq!(
+            &module[span.start().to_usize()..span.end().to_usize()],
+            "types.attrs"
+        );
+
+        let file: SourceFile = module.parse().unwrap();
+        let enable = &declarations(&file)[0];
+        assert_eq!(enable.path, ["services", "foo", "enable"]);
+        assert_eq!(enable.default.as_deref(), Some("false"));
+    }
+}
@@ -0,0 +1,262 @@
+//! The "organize bindings" source action.
+//!
+//! This rewrites the bindings of the attribute set or `let` block around the cursor so that
+//! `inherit` statements taking attributes from the same place are merged into one, and all
+//! `inherit` statements are grouped before or after the other bindings. The order of bindings
+//! within each group is kept. Bindings are moved as source text together with the comments
+//! preceding them and a comment trailing them on the same line, so nothing is reformatted.
+
+use codespan::Span;
+use nix_parser::ast::visit::path_at;
+use nix_parser::ast::{Bind, Expr, SourceFile};
+use nix_parser::HasSpan;
+
+/// Where `inherit` statements are placed relative to the other bindings.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Placement {
+    #[default]
+    First,
+    Last,
+}
+
+impl Placement {
+    /// Parses the value of the `organizeInherits` initialization option.
+    pub fn from_option(value: &str) -> Option<Self> {
+        match value {
+            "first" => Some(Placement::First),
+            "last" => Some(Placement::Last),
+            _ => None,
+        }
+    }
+}
+
+/// A binding together with its surrounding comments.
+struct Chunk<'a> {
+    bind: &'a Bind,
+    /// The comments preceding the binding, including the layout up to the binding itself.
+    leading: &'a str,
+    text: &'a str,
+}
+
+/// A group of `inherit` statements taking attributes from the same place.
+struct Inherits<'a> {
+    from: Option<&'a str>,
+    chunks: Vec<Chunk<'a>>,
+}
+
+impl Inherits<'_> {
+    fn render(&self, out: &mut String) {
+        if let [ref chunk] = self.chunks[..] {
+            out.push_str(chunk.text);
+            return;
+        }
+
+        let mut names: Vec<String> = Vec::new();
+        for chunk in &self.chunks {
+            out.push_str(chunk.leading);
+            let idents = match *chunk.bind {
+                Bind::Inherit(ref inherit) => inherit.names(),
+                Bind::InheritExpr(ref inherit) => inherit.names(),
+                Bind::Simple(_) => &[],
+            };
+            for name in idents.iter().map(ToString::to_string) {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+
+        out.push_str("inherit");
+        if let Some(from) = self.from {
+            out.push_str(&format!(" ({})", from));
+        }
+        for name in names {
+            out.push(' ');
+            out.push_str(&name);
+        }
+        out.push(';');
+    }
+}
+
+/// Returns the span and replacement text of the bindings of the attribute set or `let` block
+/// enclosing byte `offset`, or `None` if they are organized already.
+pub fn organize(
+    source: &str,
+    file: &SourceFile,
+    offset: usize,
+    placement: Placement,
+) -> Option<(Span, String)> {
+    let binds = path_at(file.expr(), offset)
+        .into_iter()
+        .rev()
+        .find_map(|expr| match *expr {
+            Expr::Set(ref set) => Some(set.binds()),
+            Expr::Rec(ref rec) => Some(rec.binds()),
+            Expr::Let(ref let_) => Some(let_.binds()),
+            Expr::LetIn(ref let_in) => Some(let_in.binds()),
+            _ => None,
+        })?;
+
+    if binds.len() < 2 {
+        return None;
+    }
+
+    let chunks = split_chunks(source, binds)?;
+    let start = chunks[0].text.as_ptr() as usize - source.as_ptr() as usize;
+    let last = &chunks[chunks.len() - 1];
+    let end = last.text.as_ptr() as usize - source.as_ptr() as usize + last.text.len();
+    let separator = {
+        let first_end = start + chunks[0].text.len();
+        let second = chunks[1].text.as_ptr() as usize - source.as_ptr() as usize;
+        &source[first_end..second]
+    };
+
+    let mut groups: Vec<Inherits> = Vec::new();
+    let mut others = Vec::new();
+    for chunk in chunks {
+        let from = match *chunk.bind {
+            Bind::Simple(_) => {
+                others.push(chunk);
+                continue;
+            }
+            Bind::Inherit(_) => None,
+            Bind::InheritExpr(ref inherit) => Some(slice(source, inherit.expr().span()).trim()),
+        };
+        match groups.iter_mut().find(|group| group.from == from) {
+            Some(group) => group.chunks.push(chunk),
+            None => groups.push(Inherits {
+                from,
+                chunks: vec![chunk],
+            }),
+        }
+    }
+    // Plain `inherit` statements come before those taking attributes from an expression.
+    groups.sort_by_key(|group| group.from.is_some());
+
+    let mut parts: Vec<String> = Vec::new();
+    let mut inherits: Vec<String> = groups
+        .iter()
+        .map(|group| {
+            let mut text = String::new();
+            group.render(&mut text);
+            text
+        })
+        .collect();
+    let others = others.into_iter().map(|chunk| chunk.text.to_owned());
+    match placement {
+        Placement::First => {
+            parts.append(&mut inherits);
+            parts.extend(others);
+        }
+        Placement::Last => {
+            parts.extend(others);
+            parts.append(&mut inherits);
+        }
+    }
+
+    let text = parts.join(separator);
+    let span = Span::new(start as u32, end as u32);
+    if text == slice(source, span) {
+        None
+    } else {
+        Some((span, text))
+    }
+}
+
+/// Splits the source text of `binds` into chunks, each covering one binding with its comments.
+fn split_chunks<'a>(source: &'a str, binds: &'a [Bind]) -> Option<Vec<Chunk<'a>>> {
+    let mut chunks = Vec::new();
+    let mut previous_end = None;
+    for bind in binds {
+        let span = bind.span();
+        // The span of an `inherit` statement starts at the comments preceding it.
+        let from = previous_end.map_or(span.start().to_usize(), |end: usize| {
+            end.max(span.start().to_usize())
+        });
+        let bind_start = skip_comments(source, from);
+        let start = match previous_end {
+            Some(end) => bind_start - source[end..bind_start].trim_start().len(),
+            None => match *bind {
+                Bind::Simple(ref simple) => simple
+                    .comment()
+                    .map(|comment| comment.span().start().to_usize())
+                    .unwrap_or(from),
+                _ => from,
+            },
+        };
+
+        let semi = span.end().to_usize() + source[span.end().to_usize()..].find(';')?;
+        let mut end = semi + 1;
+        let rest = &source[end..];
+        let line = &rest[..rest.find('\n').unwrap_or(rest.len())];
+        if line.trim_start().starts_with('#') {
+            end += line.trim_end().len();
+        }
+
+        chunks.push(Chunk {
+            bind,
+            leading: &source[start..bind_start],
+            text: &source[start..end],
+        });
+        previous_end = Some(end);
+    }
+
+    Some(chunks)
+}
+
+/// Returns the offset of the first token at or after `offset` which is not a comment.
+fn skip_comments(source: &str, mut offset: usize) -> usize {
+    loop {
+        let rest = &source[offset..];
+        let trimmed = rest.trim_start();
+        offset += rest.len() - trimmed.len();
+        if trimmed.starts_with('#') {
+            offset += trimmed.find('\n').unwrap_or(trimmed.len());
+        } else if trimmed.starts_with("/*") {
+            offset += trimmed.find("*/").map_or(trimmed.len(), |end| end + 2);
+        } else {
+            return offset;
+        }
+    }
+}
+
+fn slice(source: &str, span: Span) -> &str {
+    &source[span.start().to_usize()..span.end().to_usize()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn organized(source: &str, placement: Placement) -> Option<String> {
+        let file = source.parse().unwrap();
+        let (span, text) = organize(source, &file, 0, placement)?;
+        let (start, end) = (span.start().to_usize(), span.end().to_usize());
+        Some(format!("{}{}{}", &source[..start], text, &source[end..]))
+    }
+
+    #[test]
+    fn merges_and_groups_inherits() {
+        let source = "{
+  a = 1; # one
+  inherit x;
+  # from pkgs
+  inherit (pkgs) y;
+  b = 2;
+  inherit x z;
+  inherit (pkgs) w;
+}";
+        let expected = "{
+  inherit x z;
+  # from pkgs
+  inherit (pkgs) y w;
+  a = 1; # one
+  b = 2;
+}";
+        assert_eq!(organized(source, Placement::First).unwrap(), expected);
+
+        let last = organized(expected, Placement::Last).unwrap();
+        assert!(last.ends_with("b = 2;\n  inherit x z;\n  # from pkgs\n  inherit (pkgs) y w;\n}"));
+        assert_eq!(organized(expected, Placement::First), None);
+    }
+}
@@ -0,0 +1,201 @@
+//! The outline of a file, answering `textDocument/documentSymbol` requests.
+//!
+//! Every binding of the attribute set a file evaluates to becomes a symbol named by its attribute
+//! path, and so do the bindings of the sets nested in its value. As with the file itself, values
+//! are looked through functions, `let`, `with` and function applications, so that the attributes
+//! given to `stdenv.mkDerivation { ... }` are listed under the binding of the derivation. The
+//! bindings of a `let` are listed as variables before the bindings of its body.
+
+use codespan::Span;
+use nix_parser::ast::tokens::Ident;
+use nix_parser::ast::visit::{self, Visitor};
+use nix_parser::ast::{
+    Bind, BindInherit, BindInheritExpr, BindSimple, Expr, ExprAssert, ExprFnApp, ExprLet,
+    ExprLetIn, ExprRec, ExprSet, ExprWith, FnDeclFormals, FnDeclSimple,
+};
+use nix_parser::HasSpan;
+use tower_lsp::lsp_types::SymbolKind;
+
+use crate::attrs;
+
+/// A binding in the outline of a file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// The span of the whole binding.
+    pub span: Span,
+    /// The span of the name of the binding.
+    pub selection: Span,
+    pub children: Vec<Symbol>,
+}
+
+/// Returns the outline of the value `expr`.
+pub fn symbols(expr: &Expr) -> Vec<Symbol> {
+    let mut outline = Outline {
+        kind: SymbolKind::Field,
+        symbols: Vec::new(),
+    };
+    outline.visit_expr(expr);
+    outline.symbols
+}
+
+/// Collects the bindings of a value, only descending into the parts of expressions which make up
+/// their value.
+struct Outline {
+    /// The kind of the bindings being visited, unless their value is more specific.
+    kind: SymbolKind,
+    symbols: Vec<Symbol>,
+}
+
+impl Outline {
+    fn binds(&mut self, binds: &[Bind], kind: SymbolKind) {
+        self.kind = kind;
+        binds.iter().for_each(|bind| self.visit_bind(bind));
+    }
+
+    fn inherited(&mut self, names: &[Ident]) {
+        let kind = self.kind;
+        self.symbols.extend(names.iter().map(|name| Symbol {
+            name: name.to_string(),
+            kind,
+            span: name.span(),
+            selection: name.span(),
+            children: Vec::new(),
+        }));
+    }
+}
+
+impl<'a> Visitor<'a> for Outline {
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        match *expr {
+            Expr::Set(_) | Expr::Rec(_) | Expr::Let(_) | Expr::LetIn(_) | Expr::Paren(_) => {}
+            Expr::With(_) | Expr::Assert(_) | Expr::FnApp(_) | Expr::FnDecl(_) => {}
+            _ => return,
+        }
+        visit::walk_expr(self, expr);
+    }
+
+    fn visit_set(&mut self, set: &'a ExprSet) {
+        self.binds(set.binds(), SymbolKind::Field);
+    }
+
+    fn visit_rec(&mut self, rec: &'a ExprRec) {
+        self.binds(rec.binds(), SymbolKind::Field);
+    }
+
+    fn visit_let(&mut self, let_: &'a ExprLet) {
+        self.binds(let_.binds(), SymbolKind::Variable);
+    }
+
+    fn visit_let_in(&mut self, let_in: &'a ExprLetIn) {
+        self.binds(let_in.binds(), SymbolKind::Variable);
+        self.visit_expr(let_in.body());
+    }
+
+    fn visit_with(&mut self, with: &'a ExprWith) {
+        self.visit_expr(with.expr());
+    }
+
+    fn visit_assert(&mut self, assert: &'a ExprAssert) {
+        self.visit_expr(assert.expr());
+    }
+
+    fn visit_fn_app(&mut self, app: &'a ExprFnApp) {
+        self.visit_expr(app.argument());
+    }
+
+    fn visit_fn_decl_simple(&mut self, decl: &'a FnDeclSimple) {
+        self.visit_expr(decl.body());
+    }
+
+    fn visit_fn_decl_formals(&mut self, decl: &'a FnDeclFormals) {
+        self.visit_expr(decl.body());
+    }
+
+    fn visit_bind_simple(&mut self, bind: &'a BindSimple) {
+        let segments = bind.attr().segments();
+        let name: Vec<_> = segments.iter().map(attrs::symbol_name).collect();
+        let value = bind.expr();
+        self.symbols.push(Symbol {
+            name: name.join("."),
+            kind: value_kind(value).unwrap_or(self.kind),
+            span: bind.span(),
+            selection: bind.attr().span(),
+            children: symbols(value),
+        });
+    }
+
+    fn visit_bind_inherit(&mut self, inherit: &'a BindInherit) {
+        self.inherited(inherit.names());
+    }
+
+    fn visit_bind_inherit_expr(&mut self, inherit: &'a BindInheritExpr) {
+        self.inherited(inherit.names());
+    }
+}
+
+/// Returns the kind of symbol to show for a binding of `value`, if it is more specific than that
+/// of the bindings around it.
+fn value_kind(value: &Expr) -> Option<SymbolKind> {
+    match *value {
+        Expr::Set(_) | Expr::Rec(_) | Expr::Let(_) => Some(SymbolKind::Object),
+        Expr::FnDecl(_) => Some(SymbolKind::Function),
+        Expr::List(_) => Some(SymbolKind::Array),
+        Expr::String(_) => Some(SymbolKind::String),
+        Expr::Paren(ref paren) => value_kind(paren.expr()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nix_parser::ast::SourceFile;
+
+    use super::*;
+
+    fn outline(symbols: &[Symbol], depth: usize, out: &mut Vec<String>) {
+        for symbol in symbols {
+            let indent = "  ".repeat(depth);
+            out.push(format!("{}{} {:?}", indent, symbol.name, symbol.kind));
+            outline(&symbol.children, depth + 1, out);
+        }
+    }
+
+    #[test]
+    fn nests_attribute_sets() {
+        let source = r#"{ stdenv, fetchurl }:
+let
+  version = "1.0";
+in stdenv.mkDerivation {
+  pname = "hello";
+  inherit version;
+  src = fetchurl { url = "https://example.org"; };
+  meta.license = [ ];
+  passthru = { tests = { }; update = x: x; };
+}
+"#;
+        let file: SourceFile = source.parse().unwrap();
+        let symbols = symbols(file.expr());
+        let mut lines = Vec::new();
+        outline(&symbols, 0, &mut lines);
+        assert_eq!(
+            lines,
+            [
+                "version String",
+                "pname String",
+                "version Field",
+                "src Field",
+                "  url String",
+                "meta.license Array",
+                "passthru Object",
+                "  tests Object",
+                "  update Function",
+            ]
+        );
+
+        let src = &symbols[3];
+        assert_eq!(&source[src.selection.start().to_usize()..][..3], "src");
+        assert!(source[src.span.start().to_usize()..].starts_with("src = fetchurl"));
+    }
+}
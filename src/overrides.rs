@@ -0,0 +1,260 @@
+//! Completion of the attributes given to `pkg.override` and `pkg.overrideAttrs`.
+//!
+//! `override` takes new arguments for the function the package was called with, and
+//! `overrideAttrs` new attributes for the derivation it builds. Both are looked up statically when
+//! the package is bound in the same file to a `callPackage ./foo.nix { }` call. Packages taken
+//! from `pkgs` are instead looked up by evaluating a small query against `<nixpkgs>`.
+
+use codespan::Span;
+use nix_parser::ast::visit::descendants;
+use nix_parser::ast::{Bind, Expr, ExprFnDecl, SourceFile};
+use nix_parser::HasSpan;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::call_package::{self, Call};
+
+static STRING: Lazy<Regex> = Lazy::new(|| Regex::new(r#""((?:[^"\\]|\\.)*)""#).unwrap());
+
+/// Functions building derivations, whose set argument `overrideAttrs` replaces attributes of.
+const DERIVATION_BUILDERS: &[&str] = &["mkDerivation", "buildPythonPackage", "buildGoModule"];
+
+/// The override function being called.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Kind {
+    Override,
+    OverrideAttrs,
+}
+
+/// The set of new attributes passed to an override function.
+#[derive(Clone, Debug)]
+pub struct Site<'a> {
+    pub kind: Kind,
+    /// The expression the package is projected from, e.g. `pkgs` in `pkgs.hello.override`.
+    pub base: &'a Expr,
+    /// The attribute path of the package below `base`, which is empty if `base` is the package.
+    pub path: Vec<&'a str>,
+    /// The attributes already given.
+    pub binds: &'a [Bind],
+}
+
+impl<'a> Site<'a> {
+    /// Returns the names of the attributes already given.
+    pub fn given(&self) -> Vec<&'a str> {
+        self.binds
+            .iter()
+            .flat_map(|bind| match *bind {
+                Bind::Simple(ref simple) => simple
+                    .attr()
+                    .segments()
+                    .first()
+                    .and_then(|segment| segment.name())
+                    .into_iter()
+                    .collect::<Vec<_>>(),
+                Bind::Inherit(ref inherit) => inherit.names().iter().map(|n| n.as_str()).collect(),
+                Bind::InheritExpr(ref inherit) => {
+                    inherit.names().iter().map(|n| n.as_str()).collect()
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the name the package is bound to, which is the last segment of its path.
+    pub fn name(&self) -> Option<&'a str> {
+        match (self.path.last(), self.base) {
+            (Some(name), _) => Some(name),
+            (None, Expr::Ident(ref ident)) => Some(ident.as_str()),
+            _ => None,
+        }
+    }
+
+    /// Returns a Nix expression listing the names this override accepts, if the package comes
+    /// from `pkgs` and can therefore be evaluated on its own.
+    pub fn query(&self) -> Option<String> {
+        match *self.base {
+            Expr::Ident(ref ident) if ident.as_str() == "pkgs" && !self.path.is_empty() => {}
+            _ => return None,
+        }
+
+        let package = format!("pkgs.{}", self.path.join("."));
+        let names = match self.kind {
+            Kind::Override => format!(
+                "let f = {}.override; in builtins.attrNames (f.__functionArgs or (builtins.functionArgs f))",
+                package
+            ),
+            Kind::OverrideAttrs => format!("builtins.attrNames {}.drvAttrs", package),
+        };
+        Some(format!("let pkgs = import <nixpkgs> {{ }}; in {}", names))
+    }
+}
+
+/// Returns the override call whose set of new attributes contains `offset` where a name can be
+/// written.
+pub fn site_at(file: &SourceFile, offset: usize) -> Option<Site<'_>> {
+    let site = descendants(file.expr()).find_map(|expr| {
+        let app = match *expr {
+            Expr::FnApp(ref app) => app,
+            _ => return None,
+        };
+        let proj = match *app.function() {
+            Expr::Proj(ref proj) if proj.fallback().is_none() => proj,
+            _ => return None,
+        };
+
+        let names: Option<Vec<_>> = proj.attr().segments().iter().map(|s| s.name()).collect();
+        let mut path = names?;
+        let kind = match path.pop()? {
+            "override" => Kind::Override,
+            "overrideAttrs" => Kind::OverrideAttrs,
+            _ => return None,
+        };
+
+        let (binds, span) = argument_set(app.argument())?;
+        if !call_package::is_name_position(binds, span, offset) {
+            return None;
+        }
+        Some(Site {
+            kind,
+            base: proj.base(),
+            path,
+            binds,
+        })
+    });
+    site
+}
+
+/// Returns the `callPackage` call the package overridden at `site` is bound to in `file`.
+pub fn callee<'a>(file: &'a SourceFile, site: &Site) -> Option<Call<'a>> {
+    let name = site.name()?;
+    let calls = call_package::calls(file);
+    let call = descendants(file.expr()).find_map(|expr| {
+        let binds = match *expr {
+            Expr::Set(ref set) => set.binds(),
+            Expr::Rec(ref rec) => rec.binds(),
+            Expr::LetIn(ref let_in) => let_in.binds(),
+            _ => return None,
+        };
+        binds.iter().find_map(|bind| match *bind {
+            Bind::Simple(ref simple) => match simple.attr().segments() {
+                [segment] if segment.name() == Some(name) => {
+                    let span = simple.expr().span();
+                    calls
+                        .iter()
+                        .find(|call| call.overrides.span().end() == span.end())
+                        .cloned()
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+    });
+    call
+}
+
+/// Returns the names of the attributes given to the derivation built by `file`.
+pub fn derivation_attrs(file: &SourceFile) -> Vec<String> {
+    let binds = descendants(file.expr()).find_map(|expr| match *expr {
+        Expr::FnApp(ref app) if is_derivation_builder(app.function()) => {
+            argument_set(app.argument()).map(|(binds, _)| binds)
+        }
+        _ => None,
+    });
+
+    let mut names = Vec::new();
+    for bind in binds.unwrap_or_default() {
+        let new: Vec<_> = match *bind {
+            Bind::Simple(ref simple) => simple
+                .attr()
+                .segments()
+                .first()
+                .and_then(|segment| segment.name())
+                .map(str::to_owned)
+                .into_iter()
+                .collect(),
+            Bind::Inherit(ref inherit) => inherit.names().iter().map(|n| n.to_string()).collect(),
+            Bind::InheritExpr(ref inherit) => {
+                inherit.names().iter().map(|n| n.to_string()).collect()
+            }
+        };
+        for name in new {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    names
+}
+
+/// Extracts the names from a list of strings printed by `nix-instantiate --eval`.
+pub fn parse_names(output: &str) -> Vec<String> {
+    STRING
+        .captures_iter(output)
+        .map(|caps| caps[1].to_owned())
+        .collect()
+}
+
+/// Returns the set an override function is given, looking through the functions of the old
+/// attributes taken by `overrideAttrs`, e.g. `old: { ... }` and `final: prev: { ... }`.
+fn argument_set(mut expr: &Expr) -> Option<(&[Bind], Span)> {
+    loop {
+        expr = match *expr {
+            Expr::Paren(ref paren) => paren.expr(),
+            Expr::FnDecl(ref decl) => match **decl {
+                ExprFnDecl::Simple(ref decl) => decl.body(),
+                ExprFnDecl::Formals(ref decl) => decl.body(),
+            },
+            Expr::Set(ref set) => return Some((set.binds(), set.span())),
+            Expr::Rec(ref rec) => return Some((rec.binds(), rec.span())),
+            _ => return None,
+        };
+    }
+}
+
+fn is_derivation_builder(function: &Expr) -> bool {
+    let name = match *function {
+        Expr::Ident(ref ident) => Some(ident.as_str()),
+        Expr::Proj(ref proj) => proj.attr().segments().last().and_then(|s| s.name()),
+        _ => None,
+    };
+    name.is_some_and(|name| DERIVATION_BUILDERS.contains(&name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_override_sites() {
+        let source = "let
+  hello = callPackage ./hello.nix { };
+  a = hello.override { stdenv = clangStdenv; };
+  b = pkgs.curl.overrideAttrs (old: rec { patches = [ ]; });
+in { inherit a b; }";
+        let file: SourceFile = source.parse().unwrap();
+
+        let site = site_at(&file, source.find(" stdenv").unwrap()).unwrap();
+        assert_eq!(site.kind, Kind::Override);
+        assert_eq!(site.name(), Some("hello"));
+        assert_eq!(site.given(), ["stdenv"]);
+        assert_eq!(site.query(), None);
+        let call = callee(&file, &site).unwrap();
+        assert_eq!(call.target.path.to_str(), Some("./hello.nix"));
+        assert!(site_at(&file, source.find("clangStdenv").unwrap()).is_none());
+
+        let site = site_at(&file, source.find(" patches").unwrap()).unwrap();
+        assert_eq!(site.kind, Kind::OverrideAttrs);
+        assert_eq!(
+            site.query().unwrap(),
+            "let pkgs = import <nixpkgs> { }; in builtins.attrNames pkgs.curl.drvAttrs"
+        );
+        assert_eq!(
+            parse_names(r#"[ "name" "src" "weird\"name" ]"#),
+            ["name", "src", r#"weird\"name"#]
+        );
+
+        let package =
+            "{ stdenv }: stdenv.mkDerivation (finalAttrs: { pname = \"x\"; inherit (a) src; })";
+        let file: SourceFile = package.parse().unwrap();
+        assert_eq!(derivation_attrs(&file), ["pname", "src"]);
+    }
+}
@@ -0,0 +1,88 @@
+//! Warnings about path literals pointing to files which do not exist.
+//!
+//! Relative paths are resolved against the directory of the document, and search paths such as
+//! `<nixpkgs>` against the configured `NIX_PATH`. Search paths are only checked when one is
+//! configured, since Nix falls back to channels whose location depends on the installation.
+//! Entries of the search path which are URLs are not fetched, so lookups they may answer are
+//! never reported.
+
+use codespan::FileId;
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use nix_parser::ast::paths::PathContext;
+use nix_parser::ast::tokens::Literal;
+use nix_parser::ast::SourceFile;
+
+use crate::vfs::PathResolver;
+
+/// Returns a warning for every path literal in `file` which points to nothing.
+pub fn check<F: PathResolver>(
+    id: FileId,
+    file: &SourceFile,
+    context: &PathContext,
+    fs: &F,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for (literal, span) in file.path_literals() {
+        let search = matches!(*literal, Literal::PathTemplate(..));
+        if search && context.search_path().is_empty() {
+            continue;
+        }
+        let candidates = match context.resolve(literal) {
+            Some(candidates) => candidates,
+            None => continue,
+        };
+        if candidates.iter().any(|path| fs.exists(path)) {
+            continue;
+        }
+
+        let (message, label) = if search {
+            let message = format!("`{}` was not found in the search path", literal);
+            let label = "no entry of `NIX_PATH` contains this path".to_owned();
+            (message, label)
+        } else {
+            let message = format!("path `{}` does not exist", literal);
+            (message, format!("no file at {}", candidates[0].display()))
+        };
+        let label = Label::new(id, span, label);
+        diagnostics.push(Diagnostic::new_warning(message, label).with_code("missing-path"));
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use codespan::Files;
+    use nix_parser::ast::paths::SearchPath;
+
+    use super::*;
+    use crate::vfs::MemoryFs;
+
+    #[test]
+    fn reports_missing_paths() {
+        let source = "[ ./a.nix ./lib ./b.nix <nixpkgs> <nixpkgs/lib> <other> ]\n";
+        let file: SourceFile = source.parse().unwrap();
+        let id = Files::new().add("default.nix", source);
+        let mut fs = MemoryFs::new();
+        fs.insert("/p/a.nix", "1");
+        fs.insert("/p/lib/default.nix", "1");
+        fs.insert("/channels/nixpkgs/default.nix", "1");
+        fs.insert("/channels/nixpkgs/lib/default.nix", "1");
+
+        let messages = |context: &PathContext| -> Vec<String> {
+            let diagnostics = check(id, &file, context, &fs);
+            diagnostics.into_iter().map(|d| d.message).collect()
+        };
+        let context = PathContext::new("/p");
+        assert_eq!(messages(&context), ["path `./b.nix` does not exist"]);
+
+        let search_path = SearchPath::new("nixpkgs=/channels/nixpkgs");
+        let context = context.with_search_path(search_path);
+        assert_eq!(
+            messages(&context),
+            [
+                "path `./b.nix` does not exist",
+                "`<other>` was not found in the search path",
+            ]
+        );
+    }
+}
@@ -0,0 +1,203 @@
+//! Previews of evaluated values, too large to inspect in a hover, as read-only documents.
+//!
+//! The value of the binding at a position is evaluated with `nix-instantiate`, starting from the
+//! file on disk and selecting the attribute path leading to the binding. Functions along the path
+//! are called with an empty set, and `outputs` of a flake are taken from `builtins.getFlake`.
+//! Evaluating a value strictly can force a lot, so the value is cut off at a fixed depth, and
+//! derivations are summarized by their name rather than expanded.
+//!
+//! The printed value is then laid out with one binding per line, recording where the name of
+//! every attribute ended up so that clients can offer navigation between attribute paths.
+
+use std::fmt::Write;
+use std::path::Path;
+
+use codespan::Span;
+use nix_parser::ast::{Bind, Expr, SourceFile};
+
+/// The URI scheme of the documents holding previews.
+pub const SCHEME: &str = "nix-value";
+
+/// The command opening a preview of the value at a position.
+pub const COMMAND: &str = "nix.previewValue";
+
+/// The nesting depth below which sets and lists are summarized instead of evaluated.
+pub const DEPTH: usize = 4;
+
+/// Returns the expression evaluating to a preview of the value at `attr_path` in `file`.
+pub fn expression(file: &Path, attr_path: &[String], depth: usize) -> String {
+    let is_flake = file.file_name().is_some_and(|name| name == "flake.nix");
+    let (root, attr_path) = match attr_path.split_first() {
+        Some((first, rest)) if is_flake && first == "outputs" => {
+            let dir = file.parent().unwrap_or(file);
+            let flake = format!("builtins.getFlake {}", nix_string(&dir.to_string_lossy()));
+            (flake, rest)
+        }
+        _ => (
+            format!("import {}", nix_string(&file.to_string_lossy())),
+            attr_path,
+        ),
+    };
+    let attr_path: Vec<_> = attr_path.iter().map(|name| nix_string(name)).collect();
+
+    format!(
+        r#"let
+  call = v: if builtins.isFunction v then v {{ }} else v;
+  select = v: path: if path == [ ] then v else select (call v).${{builtins.head path}} (builtins.tail path);
+  summarize = depth: v:
+    let forced = builtins.tryEval v; in
+    if !forced.success then "«error»"
+    else if builtins.isAttrs v && v.type or null == "derivation" then "«derivation ${{v.name or "?"}}»"
+    else if builtins.isAttrs v then
+      if depth == 0 then "«${{toString (builtins.length (builtins.attrNames v))}} attributes»"
+      else builtins.mapAttrs (_: summarize (depth - 1)) v
+    else if builtins.isList v then
+      if depth == 0 then "«${{toString (builtins.length v)}} elements»"
+      else map (summarize (depth - 1)) v
+    else if builtins.isFunction v then "«lambda»"
+    else v;
+in summarize {} (select ({}) [ {} ])"#,
+        depth,
+        root,
+        attr_path.join(" ")
+    )
+}
+
+/// Quotes `text` as a Nix string.
+fn nix_string(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${");
+    format!("\"{}\"", escaped)
+}
+
+/// A value laid out for reading.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Rendered {
+    pub text: String,
+    /// The path of every attribute, along with the span of its name in `text`.
+    pub attrs: Vec<(Vec<String>, Span)>,
+}
+
+/// Lays out a value printed by `nix-instantiate --eval --strict`, or returns `None` if it cannot
+/// be read back.
+pub fn render(value: &str) -> Option<Rendered> {
+    // Values reachable twice are printed as `«repeated»`, which is not valid syntax.
+    let value = value.replace("«repeated»", "\"«repeated»\"");
+    let file: SourceFile = value.parse().ok()?;
+
+    let mut rendered = Rendered::default();
+    render_expr(file.expr(), 0, &mut Vec::new(), &mut rendered);
+    rendered.text.push('\n');
+    Some(rendered)
+}
+
+fn render_expr(expr: &Expr, depth: usize, path: &mut Vec<String>, out: &mut Rendered) {
+    let indent = "  ".repeat(depth + 1);
+    match *expr {
+        Expr::Set(ref set) if !set.binds().is_empty() => {
+            out.text.push_str("{\n");
+            for bind in set.binds() {
+                let simple = match *bind {
+                    Bind::Simple(ref simple) => simple,
+                    ref other => {
+                        let _ = writeln!(out.text, "{}{}", indent, other);
+                        continue;
+                    }
+                };
+
+                out.text.push_str(&indent);
+                let start = out.text.len();
+                let _ = write!(out.text, "{}", simple.attr());
+                let span = Span::new(start as u32, out.text.len() as u32);
+
+                let segments = simple.attr().segments();
+                path.extend(segments.iter().map(|segment| segment.to_string()));
+                out.attrs.push((path.clone(), span));
+                out.text.push_str(" = ");
+                render_expr(simple.expr(), depth + 1, path, out);
+                path.truncate(path.len() - segments.len());
+                out.text.push_str(";\n");
+            }
+            let _ = write!(out.text, "{}}}", "  ".repeat(depth));
+        }
+        Expr::List(ref list) if !list.elems().is_empty() => {
+            out.text.push_str("[\n");
+            for elem in list.elems() {
+                out.text.push_str(&indent);
+                render_expr(elem, depth + 1, path, out);
+                out.text.push('\n');
+            }
+            let _ = write!(out.text, "{}]", "  ".repeat(depth));
+        }
+        Expr::Set(_) => out.text.push_str("{ }"),
+        Expr::List(_) => out.text.push_str("[ ]"),
+        ref other => {
+            let _ = write!(out.text, "{}", other);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn selects_flake_outputs() {
+        let path = [
+            "outputs".to_owned(),
+            "packages".to_owned(),
+            "x86_64-linux".to_owned(),
+        ];
+        let expr = expression(&PathBuf::from("/src/flake.nix"), &path, 2);
+        assert!(expr.contains(r#"select (builtins.getFlake "/src") [ "packages" "x86_64-linux" ]"#));
+        assert!(expr.contains("in summarize 2"));
+
+        let expr = expression(&PathBuf::from("/src/default.nix"), &path[1..], 2);
+        assert!(expr.contains(r#"select (import "/src/default.nix") [ "packages""#));
+        assert_eq!(nix_string("a\"${b}"), r#""a\"\${b}""#);
+    }
+
+    #[test]
+    fn lays_out_values() {
+        let value = r#"{ a = 1; b = { c = [ "x" «repeated» ]; d = { }; }; "e f" = "«lambda»"; }"#;
+        let rendered = render(value).unwrap();
+        assert_eq!(
+            rendered.text,
+            r#"{
+  a = 1;
+  b = {
+    c = [
+      "x"
+      "«repeated»"
+    ];
+    d = { };
+  };
+  "e f" = "«lambda»";
+}
+"#
+        );
+
+        let paths: Vec<_> = rendered
+            .attrs
+            .iter()
+            .map(|(path, span)| {
+                let name = &rendered.text[span.start().to_usize()..span.end().to_usize()];
+                (path.join("."), name)
+            })
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                ("a".to_owned(), "a"),
+                ("b".to_owned(), "b"),
+                ("b.c".to_owned(), "c"),
+                ("b.d".to_owned(), "d"),
+                ("\"e f\"".to_owned(), "\"e f\""),
+            ]
+        );
+    }
+}
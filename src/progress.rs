@@ -0,0 +1,281 @@
+//! Progress of long-running work, reported to the client with `$/progress` notifications.
+//!
+//! `lsp-types` only has the work done progress types behind its `proposed` feature, so the
+//! messages are built as JSON, like the other messages the server sends on its own. For the same
+//! reason, and because `tower-lsp` drops the responses of the client, [`Routed`] looks at the
+//! messages from the client before the service does: it records whether the client supports
+//! progress, and hands the responses to `window/workDoneProgress/create` to [`Progress::begin`].
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future::{self, Either, FutureResult};
+use futures::sync::mpsc::UnboundedSender;
+use futures::Poll;
+use jsonrpc_core::{Id, Output, Params};
+use serde_json::{json, Value};
+use tower_lsp::Incoming;
+use tower_service::Service;
+
+/// How long to wait for the client to create a progress before going on without it.
+const CREATE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the server learns from the client outside of the messages `tower-lsp` passes on.
+#[derive(Debug, Default)]
+pub struct Client {
+    /// Whether the client announced `window.workDoneProgress` when initializing.
+    work_done_progress: AtomicBool,
+    /// The senders waiting for the response to each request sent to the client, by request id.
+    pending: Mutex<HashMap<String, Sender<bool>>>,
+}
+
+impl Client {
+    /// Returns whether the client can show progress created by the server.
+    pub fn supports_progress(&self) -> bool {
+        self.work_done_progress.load(Ordering::Relaxed)
+    }
+
+    fn initialize(&self, params: &Params) {
+        if let Params::Map(ref params) = *params {
+            let supported = params
+                .get("capabilities")
+                .and_then(|capabilities| capabilities.pointer("/window/workDoneProgress"))
+                .and_then(Value::as_bool);
+            self.work_done_progress
+                .store(supported == Some(true), Ordering::Relaxed);
+        }
+    }
+
+    /// Passes `output` to the request awaiting it, returning whether there was one.
+    fn respond(&self, output: &Output) -> bool {
+        let id = match *output.id() {
+            Id::Str(ref id) => id,
+            _ => return false,
+        };
+        let pending = self
+            .pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(id);
+        match pending {
+            Some(sender) => {
+                let _ = sender.send(matches!(*output, Output::Success(_)));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Sends the request `id` to the client and blocks until it responds, returning whether the
+    /// request succeeded, or `false` if no response came within `timeout`.
+    fn request(
+        &self,
+        notifications: &UnboundedSender<String>,
+        id: &str,
+        method: &str,
+        params: Value,
+        timeout: Duration,
+    ) -> bool {
+        let (sender, receiver) = mpsc::channel();
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        pending.insert(id.to_owned(), sender);
+        drop(pending);
+
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        let _ = notifications.unbounded_send(request.to_string());
+        let succeeded = receiver.recv_timeout(timeout).unwrap_or(false);
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(id);
+        succeeded
+    }
+}
+
+/// A service passing the messages of the client on to `inner`, after taking those [`Client`]
+/// needs to see.
+#[derive(Debug)]
+pub struct Routed<S> {
+    inner: S,
+    client: Arc<Client>,
+}
+
+impl<S> Routed<S> {
+    pub fn new(inner: S, client: Arc<Client>) -> Self {
+        Routed { inner, client }
+    }
+}
+
+impl<S> Service<Incoming> for Routed<S>
+where
+    S: Service<Incoming, Response = String>,
+{
+    type Response = String;
+    type Error = S::Error;
+    type Future = Either<FutureResult<String, S::Error>, S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, message: Incoming) -> Self::Future {
+        match message {
+            Incoming::Request(ref call) if call.method == "initialize" => {
+                self.client.initialize(&call.params)
+            }
+            Incoming::Response(ref output) if self.client.respond(output) => {
+                return Either::A(future::ok(String::new()));
+            }
+            _ => {}
+        }
+        Either::B(self.inner.call(message))
+    }
+}
+
+/// A piece of work shown as progressing in the client.
+#[derive(Debug)]
+pub struct Progress {
+    notifications: UnboundedSender<String>,
+    token: String,
+    /// The percentage reported last.
+    percentage: AtomicUsize,
+}
+
+impl Progress {
+    /// Asks the client to create the progress `token` and begins it with `title`.
+    ///
+    /// Returns `None` without sending anything if the client does not support progress, and
+    /// without beginning the progress if the client does not create it. This blocks until the
+    /// client responds, so it must not be called from the thread serving requests.
+    pub fn begin(
+        client: &Client,
+        notifications: UnboundedSender<String>,
+        token: &str,
+        title: &str,
+    ) -> Option<Self> {
+        if !client.supports_progress() {
+            return None;
+        }
+        let method = "window/workDoneProgress/create";
+        let params = json!({ "token": token });
+        if !client.request(&notifications, token, method, params, CREATE_TIMEOUT) {
+            return None;
+        }
+
+        let progress = Progress {
+            notifications,
+            token: token.to_owned(),
+            percentage: AtomicUsize::new(0),
+        };
+        progress.send(json!({ "kind": "begin", "title": title, "percentage": 0 }));
+        Some(progress)
+    }
+
+    /// Reports that `done` out of `total` units of work are done, if that moves the percentage
+    /// forward.
+    pub fn report(&self, done: usize, total: usize) {
+        let percentage = done * 100 / total.max(1);
+        if self.percentage.fetch_max(percentage, Ordering::Relaxed) < percentage {
+            self.send(json!({
+                "kind": "report",
+                "message": format!("{}/{}", done, total),
+                "percentage": percentage,
+            }));
+        }
+    }
+
+    /// Ends the work with a closing `message`.
+    pub fn end(self, message: &str) {
+        self.send(json!({ "kind": "end", "message": message }));
+    }
+
+    fn send(&self, value: Value) {
+        let message = json!({
+            "jsonrpc": "2.0",
+            "method": "$/progress",
+            "params": { "token": self.token, "value": value },
+        });
+        let _ = self.notifications.unbounded_send(message.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use futures::sync::mpsc;
+    use futures::{Future, Stream};
+    use jsonrpc_core::{Success, Version};
+
+    use super::*;
+
+    fn initialized(work_done_progress: bool) -> Client {
+        let client = Client::default();
+        let params =
+            json!({ "capabilities": { "window": { "workDoneProgress": work_done_progress } } });
+        client.initialize(&serde_json::from_value(params).unwrap());
+        client
+    }
+
+    fn success(id: &str) -> Output {
+        Output::Success(Success {
+            jsonrpc: Some(Version::V2),
+            result: Value::Null,
+            id: Id::Str(id.to_owned()),
+        })
+    }
+
+    #[test]
+    fn reports_each_percentage_once() {
+        let client = Arc::new(initialized(true));
+        let (notifications, messages) = mpsc::unbounded();
+        let worker = client.clone();
+        let handle = thread::spawn(move || {
+            let progress = Progress::begin(&worker, notifications, "nix/index", "Indexing");
+            let progress = progress.unwrap();
+            for done in 1..=400 {
+                progress.report(done, 400);
+            }
+            progress.end("Indexed 400 files");
+        });
+
+        let (create, messages) = messages.into_future().wait().ok().unwrap();
+        let create: Value = serde_json::from_str(&create.unwrap()).unwrap();
+        assert_eq!(create["method"], "window/workDoneProgress/create");
+        assert!(client.respond(&success("nix/index")));
+        handle.join().unwrap();
+
+        let messages: Vec<Value> = messages
+            .wait()
+            .map(|message| serde_json::from_str(&message.unwrap()).unwrap())
+            .collect();
+        assert_eq!(messages.len(), 102);
+        assert_eq!(messages[0]["params"]["value"]["kind"], "begin");
+        assert_eq!(messages[1]["params"]["value"]["message"], "4/400");
+        assert_eq!(messages[100]["params"]["value"]["percentage"], 100);
+        assert_eq!(messages[101]["params"]["value"]["kind"], "end");
+        assert_eq!(messages[101]["params"]["token"], "nix/index");
+    }
+
+    #[test]
+    fn waits_for_the_client() {
+        let (notifications, messages) = mpsc::unbounded();
+        let unsupported = initialized(false);
+        assert!(Progress::begin(&unsupported, notifications.clone(), "a", "A").is_none());
+        assert!(!unsupported.respond(&success("a")));
+
+        let refused = Arc::new(initialized(true));
+        let worker = refused.clone();
+        let handle = thread::spawn(move || Progress::begin(&worker, notifications, "b", "B"));
+        let (create, messages) = messages.into_future().wait().ok().unwrap();
+        assert!(create.unwrap().contains("window/workDoneProgress/create"));
+        let failure =
+            json!({ "jsonrpc": "2.0", "id": "b", "error": { "code": -1, "message": "no" } });
+        assert!(refused.respond(&serde_json::from_value(failure).unwrap()));
+        assert!(handle.join().unwrap().is_none());
+        assert_eq!(messages.wait().count(), 0);
+    }
+}
@@ -0,0 +1,832 @@
+//! Occurrences of a symbol, and how safely each of them can be renamed.
+//!
+//! Scoping in Nix is lexical except for `with`, and attribute sets can be accessed through any
+//! expression, computed names and strings. An occurrence is therefore only reported as definite
+//! when it provably refers to the symbol being renamed. Everything which might refer to it, as
+//! well as references which the new name would capture or be captured by, is reported separately
+//! for the user to review, since a silent partial rename is worse than no rename at all. What a
+//! variable refers to is looked up with [`resolve`], so that renaming agrees with go-to-definition
+//! and find-references.
+//!
+//! Attributes named by constant strings are occurrences like any other: `set."name"`,
+//! `set.${"name"}`, `set ? "name"` and `builtins.getAttr "name" set`, as well as bindings written
+//! `${"name"} = ...`. For these, the span of the string is reported, so that the edit replaces
+//! the string rather than the surrounding interpolation.
+
+use std::borrow::Cow;
+use std::fmt::{self, Display, Formatter};
+
+use codespan::Span;
+use nix_parser::ast::tokens::Ident;
+use nix_parser::ast::visit::{self, descendants, Visitor};
+use nix_parser::ast::{
+    AttrPath, AttrSegment, BinaryOp, Bind, BindInherit, BindInheritExpr, Expr, ExprBinary,
+    ExprFnApp, ExprLetIn, ExprProj, ExprString, FnDeclFormals, FnDeclSimple, SourceFile,
+};
+use nix_parser::HasSpan;
+
+use crate::resolve::{self, Bound, Target};
+use crate::security::builtin_name;
+
+/// Why an occurrence might, but cannot be proven to, be affected by a rename.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Reason {
+    /// A free variable inside `with`, which may take the attribute from the set in scope.
+    With,
+    /// An attribute selected from a value which is not known to be the symbol's set.
+    UnknownSet,
+    /// An attribute with a computed name, which may evaluate to the symbol's name.
+    Dynamic,
+    /// A string equal to the symbol's name, e.g. passed to `builtins.getAttr`.
+    String,
+    /// A reference to the symbol which would refer to the binding at this span instead.
+    Shadowed(Span),
+    /// A reference to the new name which would refer to the renamed symbol instead.
+    Captured,
+    /// A binding of the new name next to the symbol.
+    Duplicate,
+}
+
+impl Display for Reason {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match *self {
+            Reason::With => write!(fmt, "may be brought into scope by `with`"),
+            Reason::UnknownSet => write!(fmt, "selected from a set which may be a different one"),
+            Reason::Dynamic => write!(fmt, "computed attribute name may evaluate to this name"),
+            Reason::String => write!(fmt, "string may be used to look up this name"),
+            Reason::Shadowed(_) => write!(fmt, "would refer to another binding of the new name"),
+            Reason::Captured => write!(fmt, "would refer to the renamed binding"),
+            Reason::Duplicate => write!(fmt, "the new name is already bound here"),
+        }
+    }
+}
+
+/// The occurrences of a symbol found in a file.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Report {
+    pub name: String,
+    /// Occurrences which provably refer to the symbol, including its definitions.
+    pub definite: Vec<Span>,
+    /// Occurrences which might be affected by renaming the symbol, in source order.
+    pub uncertain: Vec<(Span, Reason)>,
+    /// Names of `inherit`s of which only the value or only the attribute is renamed, which must
+    /// be written as a binding instead.
+    pub inherited: Vec<(Span, Inherited)>,
+}
+
+/// The side of an `inherit x;`, which is short for `x = x;`, that a rename affects.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Inherited {
+    /// The variable is renamed, so the attribute keeps the old name: `x = new;`.
+    Value,
+    /// The attribute is renamed, so it takes the value of the old name: `new = x;`.
+    Attribute,
+}
+
+/// Keywords, which cannot be used as names without quoting them.
+const KEYWORDS: &[&str] = &[
+    "assert", "else", "if", "in", "inherit", "let", "or", "rec", "then", "with",
+];
+
+/// Returns whether `name` can be written as a variable without quoting it.
+pub fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    let first = match chars.next() {
+        Some(first) => first,
+        None => return false,
+    };
+    (first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '\'')
+        && !KEYWORDS.contains(&name)
+}
+
+/// Reports the occurrences of the symbol at `offset`, taking conflicts with `new_name` into
+/// account if it is given. Returns `None` if there is no renameable symbol at `offset`.
+pub fn report(file: &SourceFile, offset: usize, new_name: Option<&str>) -> Option<Report> {
+    let mut collector = Collector::new(file);
+    collector.visit_expr(file.expr());
+    collector.analyze(file, offset, new_name)
+}
+
+/// Returns the edits renaming the occurrences in `report` to `new_name` in `file`, parsed from
+/// `source`: its definite occurrences, and the `inherit`s of which it renames one side.
+pub fn edits(
+    source: &str,
+    file: &SourceFile,
+    report: &Report,
+    new_name: &str,
+) -> Vec<(Span, String)> {
+    let mut edits: Vec<_> = report
+        .definite
+        .iter()
+        .map(|&span| {
+            let quoted = source[span.start().to_usize()..].starts_with('"');
+            let text = if quoted {
+                format!("\"{}\"", new_name)
+            } else {
+                new_name.to_owned()
+            };
+            (span, text)
+        })
+        .collect();
+
+    for &(span, side) in &report.inherited {
+        edits.extend(split_inherit(
+            source,
+            file,
+            span,
+            side,
+            &report.name,
+            new_name,
+        ));
+    }
+    edits.sort_by_key(|(span, _)| span.start());
+    edits
+}
+
+/// Rewrites the `inherit` naming the name at `span` to bind that name on its own, with only the
+/// `side` of it renamed from `name` to `new_name`.
+fn split_inherit(
+    source: &str,
+    file: &SourceFile,
+    span: Span,
+    side: Inherited,
+    name: &str,
+    new_name: &str,
+) -> Option<(Span, String)> {
+    let inherit = descendants(file.expr())
+        .filter_map(|expr| match *expr {
+            Expr::Set(ref set) => Some(set.binds()),
+            Expr::Rec(ref rec) => Some(rec.binds()),
+            Expr::Let(ref let_) => Some(let_.binds()),
+            Expr::LetIn(ref let_in) => Some(let_in.binds()),
+            _ => None,
+        })
+        .flatten()
+        .find_map(|bind| {
+            let (from, names) = match *bind {
+                Bind::Inherit(ref inherit) => (None, inherit.names()),
+                Bind::InheritExpr(ref inherit) => (Some(inherit.expr()), inherit.names()),
+                Bind::Simple(_) => return None,
+            };
+            names
+                .iter()
+                .any(|n| n.span() == span)
+                .then_some((bind, from, names))
+        });
+    let (bind, from, names) = inherit?;
+    let text = |span: Span| source.get(span.start().to_usize()..span.end().to_usize());
+
+    let base = match from {
+        Some(from @ &Expr::Ident(_)) | Some(from @ &Expr::Proj(_)) => {
+            Some(text(from.span())?.into())
+        }
+        Some(from) => Some(format!("({})", text(from.span())?)),
+        None => None,
+    };
+    let from = match from {
+        Some(from) => Some(text(from.span())?),
+        None => None,
+    };
+    let binding = match (side, base) {
+        (Inherited::Value, _) => format!("{} = {};", name, new_name),
+        (Inherited::Attribute, None) => format!("{} = {};", new_name, name),
+        (Inherited::Attribute, Some(base)) => format!("{} = {}.{};", new_name, base, name),
+    };
+    let rest: Vec<_> = names
+        .iter()
+        .filter(|n| n.span() != span)
+        .map(Ident::as_str)
+        .collect();
+    let replacement = match from {
+        _ if rest.is_empty() => binding,
+        Some(from) => format!("inherit ({}) {}; {}", from, rest.join(" "), binding),
+        None => format!("inherit {}; {}", rest.join(" "), binding),
+    };
+
+    let end = bind.span().end().to_usize();
+    let end = end + source.get(end..)?.find(';')? + 1;
+    Some((Span::new(bind.span().start(), end as u32), replacement))
+}
+
+/// A name bound by a `let`, a function argument or an attribute set.
+#[derive(Debug)]
+struct Binder {
+    name: String,
+    span: Span,
+    /// The bindings or function the binder is declared in, whose other names it conflicts with.
+    container: Span,
+    /// The set literal the binder is an attribute of, if any.
+    set: Option<Span>,
+    /// The set literal bound to the binder, if any.
+    value: Option<Span>,
+    inherited: bool,
+}
+
+/// A variable referenced by name, or a name of a plain `inherit`.
+#[derive(Debug)]
+struct Variable {
+    name: String,
+    span: Span,
+    inherited: bool,
+}
+
+/// Where an attribute is selected from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Base {
+    Set(Span),
+    Unknown,
+}
+
+/// An attribute selected by `.`, `?` or `inherit (...)`, with `None` for computed names.
+#[derive(Debug)]
+struct Access {
+    name: Option<String>,
+    span: Span,
+    base: Base,
+}
+
+/// Collects the bindings, variables and attribute accesses of a file. What variables refer to is
+/// looked up in `references`, as found by [`resolve::references`].
+#[derive(Debug)]
+struct Collector {
+    references: Vec<resolve::Reference>,
+    binders: Vec<Binder>,
+    variables: Vec<Variable>,
+    accesses: Vec<Access>,
+    strings: Vec<(String, Span)>,
+    /// Computed names of bindings, along with the set they are bound in.
+    dynamic: Vec<(Span, Span)>,
+}
+
+impl Collector {
+    fn new(file: &SourceFile) -> Self {
+        Collector {
+            references: resolve::references(file),
+            binders: Vec::new(),
+            variables: Vec::new(),
+            accesses: Vec::new(),
+            strings: Vec::new(),
+            dynamic: Vec::new(),
+        }
+    }
+
+    /// Returns the span of the binding the variable at `span` refers to, if any.
+    fn target(&self, span: Span) -> Option<Span> {
+        let reference = self.references.iter().find(|r| r.span == span)?;
+        match reference.target {
+            Target::Binding(binding) => Some(binding),
+            Target::With(_) => None,
+        }
+    }
+
+    fn base(&self, expr: &Expr) -> Base {
+        match *expr {
+            Expr::Paren(ref paren) => self.base(paren.expr()),
+            Expr::Set(_) | Expr::Rec(_) => Base::Set(expr.span()),
+            Expr::Ident(ref ident) => self
+                .target(ident.span())
+                .and_then(|binding| self.binders.iter().find(|b| b.span == binding))
+                .and_then(|binder| binder.value)
+                .map_or(Base::Unknown, Base::Set),
+            _ => Base::Unknown,
+        }
+    }
+
+    fn variable(&mut self, name: &Ident, container: Span) {
+        self.binders.push(Binder {
+            name: name.to_string(),
+            span: name.span(),
+            container,
+            set: None,
+            value: None,
+            inherited: false,
+        });
+    }
+
+    /// Records the lookup of the attribute named by `string` in `base`, which is computed unless
+    /// the string is constant.
+    fn attr_string(&mut self, string: &ExprString, base: Base) {
+        let name = string.as_literal().map(Cow::into_owned);
+        let computed = name.is_none();
+        self.accesses.push(Access {
+            name,
+            span: string.span(),
+            base,
+        });
+        if computed {
+            self.visit_string(string);
+        }
+    }
+
+    /// Records the attributes selected by `path`, of which only the first is selected from
+    /// `base` itself.
+    fn access(&mut self, path: &AttrPath, mut base: Base) {
+        for segment in path.segments() {
+            let (name, span) = segment_name(segment);
+            self.accesses.push(Access {
+                name: name.map(Cow::into_owned),
+                span,
+                base,
+            });
+            base = Base::Unknown;
+            self.visit_attr_segment(segment);
+        }
+    }
+
+    /// Declares the names bound by `binds` in `container`, as attributes of `set` if given.
+    fn names(&mut self, binds: &[Bind], container: Span, set: Option<Span>) {
+        for bind in binds {
+            let names = match *bind {
+                Bind::Simple(ref simple) => {
+                    let first = match simple.attr().segments().first() {
+                        Some(first) => first,
+                        None => continue,
+                    };
+                    let (name, span) = segment_name(first);
+                    let name = match name {
+                        Some(name) => name,
+                        None => {
+                            if let Some(set) = set {
+                                self.dynamic.push((first.span(), set));
+                            }
+                            continue;
+                        }
+                    };
+                    let value = match (simple.attr().segments().len(), simple.expr()) {
+                        (1, value) => match *value {
+                            Expr::Set(_) | Expr::Rec(_) => Some(value.span()),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    self.binders.push(Binder {
+                        name: name.into_owned(),
+                        span,
+                        container,
+                        set,
+                        value,
+                        inherited: false,
+                    });
+                    continue;
+                }
+                Bind::Inherit(ref inherit) => inherit.names(),
+                Bind::InheritExpr(ref inherit) => inherit.names(),
+            };
+            for name in names {
+                self.binders.push(Binder {
+                    name: name.to_string(),
+                    span: name.span(),
+                    container,
+                    set,
+                    value: None,
+                    inherited: true,
+                });
+            }
+        }
+    }
+
+    fn analyze(&self, file: &SourceFile, offset: usize, new_name: Option<&str>) -> Option<Report> {
+        let contains =
+            |span: Span| span.start().to_usize() <= offset && offset <= span.end().to_usize();
+
+        let id = self
+            .binders
+            .iter()
+            .position(|b| contains(b.span) && !b.inherited)
+            .or_else(|| {
+                let v = self
+                    .variables
+                    .iter()
+                    .find(|v| contains(v.span) && !v.inherited)?;
+                let binding = self.target(v.span)?;
+                self.binders.iter().position(|b| b.span == binding)
+            })
+            .or_else(|| {
+                let access = self.accesses.iter().find(|a| contains(a.span))?;
+                let name = access.name.as_ref()?;
+                match access.base {
+                    Base::Set(set) => self
+                        .binders
+                        .iter()
+                        .position(|b| b.set == Some(set) && b.name == *name),
+                    Base::Unknown => None,
+                }
+            })?;
+
+        // Names may be bound by several bindings, as in `a.b = 1; a.c = 2;`.
+        let target = &self.binders[id];
+        let group: Vec<&Binder> = self
+            .binders
+            .iter()
+            .filter(|b| b.container == target.container && b.name == target.name)
+            .collect();
+        let spans: Vec<Span> = group.iter().map(|b| b.span).collect();
+        let name = &target.name;
+
+        let mut report = Report {
+            name: name.clone(),
+            ..Report::default()
+        };
+        for binder in &group {
+            if binder.inherited {
+                report.inherited.push((binder.span, Inherited::Attribute));
+            } else {
+                report.definite.push(binder.span);
+            }
+        }
+
+        for variable in &self.variables {
+            if variable.name == *name {
+                let reference = self.references.iter().find(|r| r.span == variable.span);
+                match reference.map(|r| r.target) {
+                    Some(Target::Binding(binding)) if spans.contains(&binding) => {
+                        if variable.inherited {
+                            report.inherited.push((variable.span, Inherited::Value));
+                            continue;
+                        }
+                        report.definite.push(variable.span);
+                        let shadow = new_name.and_then(|new| shadow(file, variable, &spans, new));
+                        if let Some(span) = shadow {
+                            report
+                                .uncertain
+                                .push((variable.span, Reason::Shadowed(span)));
+                        }
+                    }
+                    Some(Target::With(_)) if target.set.is_some() => {
+                        report.uncertain.push((variable.span, Reason::With));
+                    }
+                    _ => {}
+                }
+            } else if Some(variable.name.as_str()) == new_name && captures(file, variable, &spans) {
+                report.uncertain.push((variable.span, Reason::Captured));
+            }
+        }
+
+        if let Some(set) = target.set {
+            for access in &self.accesses {
+                match (access.name.as_ref(), access.base) {
+                    (Some(n), Base::Set(base)) if n == name && base == set => {
+                        report.definite.push(access.span)
+                    }
+                    (Some(n), Base::Unknown) if n == name => {
+                        report.uncertain.push((access.span, Reason::UnknownSet))
+                    }
+                    (None, Base::Unknown) => report.uncertain.push((access.span, Reason::Dynamic)),
+                    (None, Base::Set(base)) if base == set => {
+                        report.uncertain.push((access.span, Reason::Dynamic))
+                    }
+                    _ => {}
+                }
+            }
+            for &(span, in_set) in &self.dynamic {
+                if in_set == set {
+                    report.uncertain.push((span, Reason::Dynamic));
+                }
+            }
+            for (text, span) in &self.strings {
+                if text == name {
+                    report.uncertain.push((*span, Reason::String));
+                }
+            }
+        }
+
+        if let Some(new) = new_name {
+            let duplicate = self
+                .binders
+                .iter()
+                .find(|b| b.name == new && b.container == target.container);
+            if let Some(binder) = duplicate {
+                report.uncertain.push((binder.span, Reason::Duplicate));
+            }
+        }
+
+        report.definite.sort_by_key(|span| span.start());
+        report.definite.dedup();
+        report.uncertain.sort_by_key(|(span, _)| span.start());
+        report.inherited.sort_by_key(|(span, _)| span.start());
+        Some(report)
+    }
+}
+
+/// Names are declared on entering the bindings or function binding them, so that the set bound
+/// to a variable is known by the time an attribute is selected from it.
+impl<'a> Visitor<'a> for Collector {
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        match *expr {
+            Expr::Ident(ref ident) => self.variables.push(Variable {
+                name: ident.to_string(),
+                span: ident.span(),
+                inherited: false,
+            }),
+            Expr::Set(ref set) => {
+                self.names(set.binds(), expr.span(), Some(expr.span()));
+                visit::walk_set(self, set);
+            }
+            Expr::Rec(ref rec) => {
+                self.names(rec.binds(), expr.span(), Some(expr.span()));
+                visit::walk_rec(self, rec);
+            }
+            Expr::Let(ref let_) => {
+                self.names(let_.binds(), expr.span(), Some(expr.span()));
+                visit::walk_let(self, let_);
+            }
+            _ => visit::walk_expr(self, expr),
+        }
+    }
+
+    fn visit_string(&mut self, string: &'a ExprString) {
+        if let Some(text) = string.as_literal() {
+            self.strings.push((text.into_owned(), string.span()));
+        }
+        visit::walk_string(self, string);
+    }
+
+    fn visit_binary(&mut self, binary: &'a ExprBinary) {
+        self.visit_expr(binary.left());
+        match (binary.op(), binary.right()) {
+            (BinaryOp::HasAttr, Expr::Ident(name)) => {
+                let base = self.base(binary.left());
+                self.accesses.push(Access {
+                    name: Some(name.to_string()),
+                    span: name.span(),
+                    base,
+                });
+            }
+            (BinaryOp::HasAttr, Expr::String(string)) => {
+                let base = self.base(binary.left());
+                self.attr_string(string, base);
+            }
+            (_, right) => self.visit_expr(right),
+        }
+    }
+
+    fn visit_proj(&mut self, proj: &'a ExprProj) {
+        self.visit_expr(proj.base());
+        let base = self.base(proj.base());
+        self.access(proj.attr(), base);
+        if let Some(fallback) = proj.fallback() {
+            self.visit_expr(fallback);
+        }
+    }
+
+    fn visit_let_in(&mut self, let_in: &'a ExprLetIn) {
+        self.names(let_in.binds(), let_in.span(), None);
+        visit::walk_let_in(self, let_in);
+    }
+
+    fn visit_fn_decl_simple(&mut self, decl: &'a FnDeclSimple) {
+        self.variable(decl.name(), decl.span());
+        self.visit_expr(decl.body());
+    }
+
+    fn visit_fn_decl_formals(&mut self, decl: &'a FnDeclFormals) {
+        for formal in decl.formals() {
+            self.variable(formal.name(), decl.span());
+        }
+        if let Some(extra) = decl.extra() {
+            self.variable(extra, decl.span());
+        }
+        for default in decl.formals().iter().filter_map(|f| f.default()) {
+            self.visit_expr(default);
+        }
+        self.visit_expr(decl.body());
+    }
+
+    fn visit_fn_app(&mut self, app: &'a ExprFnApp) {
+        match attr_lookup(app) {
+            Some((function, string, set)) => {
+                self.visit_expr(function);
+                self.visit_expr(set);
+                let base = self.base(set);
+                self.attr_string(string, base);
+            }
+            None => visit::walk_fn_app(self, app),
+        }
+    }
+
+    fn visit_bind_inherit(&mut self, inherit: &'a BindInherit) {
+        for name in inherit.names() {
+            self.variables.push(Variable {
+                name: name.to_string(),
+                span: name.span(),
+                inherited: true,
+            });
+        }
+    }
+
+    fn visit_bind_inherit_expr(&mut self, inherit: &'a BindInheritExpr) {
+        self.visit_expr(inherit.expr());
+        let base = self.base(inherit.expr());
+        for name in inherit.names() {
+            self.accesses.push(Access {
+                name: Some(name.to_string()),
+                span: name.span(),
+                base,
+            });
+        }
+    }
+
+    fn visit_attr_segment(&mut self, segment: &'a AttrSegment) {
+        match *segment {
+            AttrSegment::Ident(_) => {}
+            AttrSegment::Interpolation(ref e) => match *e.inner() {
+                Expr::String(ref string) if string.as_literal().is_some() => {}
+                ref inner => self.visit_expr(inner),
+            },
+            AttrSegment::String(ref s) => {
+                for fragment in s.fragments() {
+                    visit::walk_string_fragment(self, fragment);
+                }
+            }
+        }
+    }
+}
+
+/// Returns the position among `scopes` of the innermost one binding a name for which `bound`
+/// holds.
+fn innermost(scopes: &[Vec<Bound>], bound: impl Fn(&Bound) -> bool) -> Option<usize> {
+    scopes.iter().rposition(|scope| scope.iter().any(&bound))
+}
+
+/// Returns the span of the binding of `new_name` which `variable`, referring to one of the
+/// bindings at `spans`, would refer to instead once renamed, if any.
+fn shadow(file: &SourceFile, variable: &Variable, spans: &[Span], new_name: &str) -> Option<Span> {
+    let scopes = resolve::scopes_at(file, variable.span.start().to_usize());
+    let renamed = innermost(&scopes, |b| spans.contains(&b.span))?;
+    let new = innermost(&scopes, |b| b.name == new_name).filter(|&new| new > renamed)?;
+    scopes[new]
+        .iter()
+        .find(|b| b.name == new_name)
+        .map(|b| b.span)
+}
+
+/// Returns whether `variable`, which has the new name, would refer to one of the bindings at
+/// `spans` once they are renamed.
+fn captures(file: &SourceFile, variable: &Variable, spans: &[Span]) -> bool {
+    let scopes = resolve::scopes_at(file, variable.span.start().to_usize());
+    let renamed = innermost(&scopes, |b| spans.contains(&b.span));
+    let current = innermost(&scopes, |b| b.name == variable.name);
+    match (renamed, current) {
+        (Some(renamed), Some(current)) => renamed > current,
+        (renamed, _) => renamed.is_some(),
+    }
+}
+
+/// Returns the name of a segment of an attribute path, if it is constant, along with the span to
+/// replace when renaming it. For `${"name"}`, this is the span of the string.
+fn segment_name(segment: &AttrSegment) -> (Option<Cow<'_, str>>, Span) {
+    match *segment {
+        AttrSegment::Interpolation(ref interpolation) => match *interpolation.inner() {
+            Expr::String(ref string) => (string.as_literal(), string.span()),
+            _ => (None, segment.span()),
+        },
+        _ => (segment.name().map(Cow::Borrowed), segment.span()),
+    }
+}
+
+/// Matches `getAttr "name" set` and `hasAttr "name" set`, returning the function applied, the
+/// string naming the attribute and the set.
+fn attr_lookup(app: &ExprFnApp) -> Option<(&Expr, &ExprString, &Expr)> {
+    let inner = match *app.function() {
+        Expr::FnApp(ref inner) => inner,
+        _ => return None,
+    };
+    let function = inner.function();
+    let name = match *function {
+        Expr::Ident(ref ident) => Some(ident.as_str()),
+        _ => builtin_name(function),
+    };
+    match (name, inner.argument()) {
+        (Some("getAttr" | "__getAttr" | "hasAttr" | "__hasAttr"), Expr::String(string)) => {
+            Some((function, string, app.argument()))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(source: &str, at: &str, new_name: Option<&str>) -> (usize, Vec<(String, String)>) {
+        let file: SourceFile = source.parse().unwrap();
+        let report = report(&file, source.find(at).unwrap(), new_name).unwrap();
+        let uncertain = report
+            .uncertain
+            .iter()
+            .map(|&(span, ref reason)| {
+                let context = &source[span.start().to_usize()..];
+                (context.chars().take(6).collect(), reason.to_string())
+            })
+            .collect();
+        (report.definite.len(), uncertain)
+    }
+
+    fn pairs(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+        expected
+            .iter()
+            .map(|&(at, reason)| (at.to_owned(), reason.to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn renames_variables() {
+        let source = "let x = 1; f = y: x + y; in { inherit x; z = f x; w = y: x; }";
+        let (definite, uncertain) = summary(source, "x =", Some("y"));
+        assert_eq!(definite, 4);
+        assert_eq!(
+            uncertain,
+            pairs(&[
+                ("x + y;", "would refer to another binding of the new name"),
+                ("x; }", "would refer to another binding of the new name"),
+            ])
+        );
+
+        let source = "let x = 1; y = 2; in x + y";
+        let (definite, uncertain) = summary(source, "x +", Some("y"));
+        assert_eq!(definite, 2);
+        assert_eq!(
+            uncertain,
+            pairs(&[("y = 2;", "the new name is already bound here")])
+        );
+
+        let source = "y: let x = 1; in x + y";
+        let (_, uncertain) = summary(source, "x +", Some("y"));
+        assert_eq!(
+            uncertain,
+            pairs(&[("y", "would refer to the renamed binding")])
+        );
+    }
+
+    #[test]
+    fn renames_attributes() {
+        let source = r#"let s = { a = 1; }; in [ s.a (with s; a) t.a (getAttr "a" s) s.${k} ]"#;
+        let (definite, uncertain) = summary(source, "a =", None);
+        assert_eq!(definite, 3);
+        assert_eq!(
+            uncertain,
+            pairs(&[
+                ("a) t.a", "may be brought into scope by `with`"),
+                ("a (get", "selected from a set which may be a different one"),
+                (
+                    "${k} ]",
+                    "computed attribute name may evaluate to this name"
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn renames_constant_strings() {
+        let source = r#"let s = { ${"a"} = 1; }; in [ s."a" s.${"a"} (s ? "a") (builtins.getAttr "a" s) (getAttr "a${k}" s) ]"#;
+        let file: SourceFile = source.parse().unwrap();
+        let report = report(&file, source.find("a\"} =").unwrap(), Some("b")).unwrap();
+        let spans: Vec<_> = report
+            .definite
+            .iter()
+            .map(|span| &source[span.start().to_usize()..span.end().to_usize()])
+            .collect();
+        assert_eq!(spans, ["\"a\""; 5]);
+
+        let (_, uncertain) = summary(source, "a\"} =", None);
+        assert_eq!(
+            uncertain,
+            pairs(&[(
+                "\"a${k}",
+                "computed attribute name may evaluate to this name"
+            )])
+        );
+    }
+
+    /// Returns `source` with the symbol at its last occurrence of `at` renamed to `new_name`.
+    fn renamed(source: &str, at: &str, new_name: &str) -> String {
+        let file: SourceFile = source.parse().unwrap();
+        let report = report(&file, source.rfind(at).unwrap(), Some(new_name)).unwrap();
+        let mut text = source.to_owned();
+        for (span, replacement) in edits(source, &file, &report, new_name).into_iter().rev() {
+            text.replace_range(span.start().to_usize()..span.end().to_usize(), &replacement);
+        }
+        text
+    }
+
+    #[test]
+    fn splits_inherits() {
+        assert_eq!(
+            renamed("let x = 1; in { inherit x; }", "x = 1", "y"),
+            "let y = 1; in { x = y; }"
+        );
+        assert_eq!(
+            renamed("let x = 1; in { inherit a x b; }", "x = 1", "y"),
+            "let y = 1; in { inherit a b; x = y; }"
+        );
+        assert_eq!(
+            renamed("let x = 1; in { inherit x; }.x", "x", "y"),
+            "let x = 1; in { y = x; }.y"
+        );
+        assert_eq!(
+            renamed("{ inherit (s) a x; }.x", "x", "y"),
+            "{ inherit (s) a; y = s.x; }.y"
+        );
+    }
+}
@@ -0,0 +1,994 @@
+//! Name resolution for Nix expressions.
+//!
+//! This performs a purely syntactic scope analysis over the AST, reporting any variables which
+//! cannot be found in scope as well as projections into attribute sets whose keys are statically
+//! known but do not contain the requested attribute.
+//!
+//! The same pass also records what every resolved variable refers to, which answers
+//! `textDocument/definition` and `textDocument/references`: a binding of `let`, `rec`, `inherit`
+//! or a function formal, or otherwise the innermost `with` expression which may provide it.
+//!
+//! Names bound by `let` or a function which hide a binding of an enclosing scope are reported as
+//! well, since using the outer one is a common mistake in large expressions. Their severity can be
+//! changed like that of any other code, through the `diagnosticSeverity` setting.
+
+use std::collections::BTreeMap;
+
+use codespan::{ByteOffset, FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use nix_parser::ast::tokens::Ident;
+use nix_parser::ast::visit::{self, walk, Visitor};
+use nix_parser::ast::{
+    AttrPath, AttrSegment, Bind, BindInherit, BindInheritExpr, Expr, ExprFnDecl, ExprLet,
+    ExprLetIn, ExprProj, ExprRec, ExprSet, ExprWith, FnDeclFormals, FnDeclSimple, SourceFile,
+};
+use nix_parser::HasSpan;
+
+use crate::suggest::did_you_mean;
+
+/// Names which are always in scope, regardless of where they are referenced.
+pub const GLOBALS: &[&str] = &[
+    "abort",
+    "baseNameOf",
+    "builtins",
+    "derivation",
+    "dirOf",
+    "false",
+    "fetchGit",
+    "fetchMercurial",
+    "fetchTarball",
+    "fetchTree",
+    "fromTOML",
+    "import",
+    "isNull",
+    "map",
+    "null",
+    "placeholder",
+    "removeAttrs",
+    "scopedImport",
+    "throw",
+    "toString",
+    "true",
+];
+
+/// The kind of name which could not be resolved.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnresolvedKind {
+    /// A variable which is not bound in any enclosing scope.
+    Variable,
+    /// An attribute which is missing from an attribute set with statically known keys.
+    Attribute,
+}
+
+/// A name which could not be resolved, along with a possible correction.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Unresolved {
+    pub kind: UnresolvedKind,
+    pub name: String,
+    pub span: Span,
+    pub suggestion: Option<String>,
+}
+
+impl Unresolved {
+    pub fn to_diagnostic(&self, file: FileId) -> Diagnostic {
+        let diagnostic = match self.kind {
+            UnresolvedKind::Variable => {
+                let label = Label::new(file, self.span, "not found in this scope");
+                Diagnostic::new_error(format!("undefined variable `{}`", self.name), label)
+                    .with_code("undefined-variable")
+            }
+            UnresolvedKind::Attribute => {
+                let label = Label::new(file, self.span, "attribute not found in this set");
+                Diagnostic::new_warning(format!("undefined attribute `{}`", self.name), label)
+                    .with_code("undefined-attribute")
+            }
+        };
+
+        match self.suggestion {
+            Some(ref suggestion) => diagnostic.with_notes(vec![did_you_mean_note(suggestion)]),
+            None => diagnostic,
+        }
+    }
+}
+
+/// A name bound by `let` or a function which hides a binding of an enclosing scope.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Shadow {
+    pub name: String,
+    pub span: Span,
+    /// The span of the binding which is hidden.
+    pub shadowed: Span,
+}
+
+impl Shadow {
+    pub fn to_diagnostic(&self, file: FileId) -> Diagnostic {
+        let label = Label::new(
+            file,
+            self.span,
+            "the outer binding is not visible past this one",
+        );
+        let outer = Label::new(file, self.shadowed, "outer binding defined here");
+        let message = format!("`{}` shadows an outer binding", self.name);
+        Diagnostic::new_warning(message, label)
+            .with_code("shadowed-binding")
+            .with_secondary_labels(vec![outer])
+    }
+}
+
+/// Formats the note attached to diagnostics which have a spelling suggestion.
+pub fn did_you_mean_note(suggestion: &str) -> String {
+    format!("did you mean `{}`?", suggestion)
+}
+
+/// What a variable refers to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Target {
+    /// The name bound by `let`, `rec`, `inherit` or a function formal at this span.
+    Binding(Span),
+    /// The set of the innermost `with` expression enclosing the variable, at this span, which is
+    /// the only place the variable can come from.
+    With(Span),
+}
+
+/// A variable and what it refers to.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Reference {
+    pub name: String,
+    pub span: Span,
+    pub target: Target,
+}
+
+/// Returns every variable in the given source file which refers to a binding in the file or to
+/// an enclosing `with`, in source order.
+///
+/// Names brought in by `inherit` count as variables of the scope they are inherited from.
+pub fn references(source: &SourceFile) -> Vec<Reference> {
+    let mut resolver = Resolver::default();
+    resolver.visit_expr(source.expr());
+    resolver.references.sort_by_key(|r| r.span.start());
+    resolver.references
+}
+
+/// Returns what the variable at `offset` refers to, if anything.
+pub fn definition_at(source: &SourceFile, offset: usize) -> Option<Target> {
+    references(source)
+        .into_iter()
+        .find(|r| r.span.start().to_usize() <= offset && offset <= r.span.end().to_usize())
+        .map(|r| r.target)
+}
+
+/// Returns the span of the binding at `offset`, or of the binding the variable at `offset` refers
+/// to, along with the spans of every variable referring to it in source order.
+pub fn references_at(source: &SourceFile, offset: usize) -> Option<(Span, Vec<Span>)> {
+    let contains =
+        |span: Span| span.start().to_usize() <= offset && offset <= span.end().to_usize();
+    let mut resolver = Resolver::default();
+    resolver.visit_expr(source.expr());
+    let mut references = resolver.references;
+    references.sort_by_key(|r| r.span.start());
+    let binding = references
+        .iter()
+        .find(|r| contains(r.span))
+        .map(|r| r.target)
+        .or_else(|| {
+            let mut targets = references.iter().map(|r| r.target);
+            targets.find(|&target| matches!(target, Target::Binding(span) if contains(span)))
+        });
+    let binding = match binding {
+        Some(Target::Binding(span)) => span,
+        Some(Target::With(_)) => return None,
+        None => resolver.binders.into_iter().find(|&span| contains(span))?,
+    };
+
+    let spans = references
+        .iter()
+        .filter(|r| r.target == Target::Binding(binding))
+        .map(|r| r.span)
+        .collect();
+    Some((binding, spans))
+}
+
+/// What kind of value a name is bound to, as far as the syntax tells.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NameKind {
+    Variable,
+    Function,
+    Set,
+}
+
+/// A name in scope at some position.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Bound {
+    pub name: String,
+    pub kind: NameKind,
+    /// The span of the name where it is bound.
+    pub span: Span,
+}
+
+/// Returns the names bound by enclosing `let`, `rec`, `inherit` and function formals at `offset`,
+/// innermost first and without those which are shadowed.
+pub fn names_at(source: &SourceFile, offset: usize) -> Vec<Bound> {
+    let mut names: Vec<Bound> = Vec::new();
+    for scope in scopes_at(source, offset).into_iter().rev() {
+        for bound in scope {
+            if names.iter().all(|b| b.name != bound.name) {
+                names.push(bound);
+            }
+        }
+    }
+    names
+}
+
+/// Returns the names bound by each scope enclosing `offset`, from the outermost.
+pub fn scopes_at(source: &SourceFile, offset: usize) -> Vec<Vec<Bound>> {
+    let mut resolver = Resolver {
+        probe: Some(offset),
+        ..Resolver::default()
+    };
+    resolver.visit_expr(source.expr());
+
+    resolver
+        .probed
+        .into_iter()
+        .map(|scope| {
+            scope
+                .into_iter()
+                .map(|(name, binder)| Bound {
+                    name,
+                    kind: binder.kind,
+                    span: binder.span,
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Resolves all names referenced in the given source file, returning those which could not be
+/// resolved in source order.
+pub fn resolve(source: &SourceFile) -> Vec<Unresolved> {
+    let mut resolver = Resolver::default();
+    resolver.visit_expr(source.expr());
+    resolver.unresolved.sort_by_key(|u| u.span.start());
+    resolver.unresolved
+}
+
+/// Returns the name suggested in place of the unresolved name at `span`, if any.
+pub fn suggestion(source: &SourceFile, span: Span) -> Option<String> {
+    resolve(source)
+        .into_iter()
+        .find(|unresolved| unresolved.span == span)?
+        .suggestion
+}
+
+/// Returns the names bound by `let` or a function in the given source file which hide a binding
+/// of an enclosing scope, in source order.
+pub fn shadows(source: &SourceFile) -> Vec<Shadow> {
+    let mut resolver = Resolver::default();
+    resolver.visit_expr(source.expr());
+    resolver.shadows.sort_by_key(|shadow| shadow.span.start());
+    resolver.shadows
+}
+
+/// Updates `previous`, the names unresolved in `old`, for `new`, which is `old` with the bytes in
+/// `edit` replaced by `new_len` bytes.
+///
+/// Only the innermost function body or binding value enclosing the edit is resolved again, as long
+/// as the edit cannot have changed what is in scope outside of it: function arguments are outside
+/// of the body, and a binding value must keep the same statically known keys. Returns `None` if
+/// there is no such region, in which case the file must be resolved from scratch.
+pub fn reresolve(
+    previous: &[Unresolved],
+    old: &SourceFile,
+    new: &SourceFile,
+    edit: Span,
+    new_len: u32,
+) -> Option<Vec<Unresolved>> {
+    let delta = i64::from(new_len) - (edit.end() - edit.start()).to_usize() as i64;
+    let edited = Span::new(
+        edit.start(),
+        edit.start() + ByteOffset::from(i64::from(new_len)),
+    );
+
+    let old_regions = regions(old.expr(), edit);
+    let mut new_regions = regions(new.expr(), edited);
+    new_regions.sort_by_key(|&(_, span, _)| span.end() - span.start());
+
+    let (region, old_region) = new_regions.into_iter().find_map(|(role, span, keys)| {
+        let end = (span.end().to_usize() as i64 - delta) as u32;
+        let old_span = Span::new(span.start().to_usize() as u32, end);
+        old_regions
+            .iter()
+            .find(|&&(r, s, ref k)| r == role && s == old_span && *k == keys)
+            .map(|_| (span, old_span))
+    })?;
+
+    let mut resolver = Resolver {
+        focus: Some(region),
+        ..Resolver::default()
+    };
+    resolver.visit_expr(new.expr());
+
+    let mut unresolved = resolver.unresolved;
+    for u in previous {
+        if u.span.end() <= old_region.start() {
+            unresolved.push(u.clone());
+        } else if u.span.start() >= old_region.end() {
+            let start = (u.span.start().to_usize() as i64 + delta) as u32;
+            let end = (u.span.end().to_usize() as i64 + delta) as u32;
+            unresolved.push(Unresolved {
+                span: Span::new(start, end),
+                ..u.clone()
+            });
+        }
+    }
+    unresolved.sort_by_key(|u| u.span.start());
+    Some(unresolved)
+}
+
+/// Where a region which can be resolved on its own occurs.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Role {
+    Body,
+    Value,
+}
+
+/// Returns the function bodies and binding values containing `range`, along with the keys which
+/// a binding value contributes to the scope of its siblings.
+fn regions(root: &Expr, range: Span) -> Vec<(Role, Span, Keys)> {
+    let contains = |span: Span| span.start() <= range.start() && range.end() <= span.end();
+    let mut regions = Vec::new();
+    walk(root, |expr, enclosing| {
+        if let Some(&parent) = enclosing.last().filter(|_| contains(expr.span())) {
+            regions.extend(region(parent, expr));
+        }
+    });
+    regions
+}
+
+/// Returns the role of `expr` in its parent, if it is a function body or a binding value.
+fn region(parent: &Expr, expr: &Expr) -> Option<(Role, Span, Keys)> {
+    let binds = match *parent {
+        Expr::FnDecl(ref decl) => {
+            let body = match **decl {
+                ExprFnDecl::Simple(ref simple) => simple.body(),
+                ExprFnDecl::Formals(ref formals) => formals.body(),
+            };
+            return Some((Role::Body, expr.span(), None)).filter(|_| std::ptr::eq(body, expr));
+        }
+        Expr::Set(ref set) => set.binds(),
+        Expr::Rec(ref rec) => rec.binds(),
+        Expr::Let(ref e) => e.binds(),
+        Expr::LetIn(ref e) => e.binds(),
+        _ => return None,
+    };
+    binds.iter().find_map(|bind| match *bind {
+        Bind::Simple(ref simple) if std::ptr::eq(simple.expr(), expr) => {
+            let keys = match simple.attr().segments().len() {
+                1 => known_keys(expr),
+                _ => None,
+            };
+            Some((Role::Value, expr.span(), keys))
+        }
+        _ => None,
+    })
+}
+
+/// The statically known keys of an attribute set, if any.
+type Keys = Option<Vec<String>>;
+
+/// A name bound in a scope.
+#[derive(Clone, Debug)]
+struct Binder {
+    /// The span of the name where it is bound first.
+    span: Span,
+    /// The keys of the value bound.
+    keys: Keys,
+    kind: NameKind,
+}
+
+/// A lexical scope, mapping each bound name to where it is bound.
+///
+/// Names are ordered so that spelling suggestions do not depend on hashing, which keeps results
+/// computed incrementally by [`reresolve`] equal to those computed from scratch.
+type Scope = BTreeMap<String, Binder>;
+
+#[derive(Debug, Default)]
+struct Resolver {
+    scopes: Vec<Scope>,
+    /// The spans of the sets of the enclosing `with` expressions, innermost last.
+    withs: Vec<Span>,
+    /// The region to resolve, skipping expressions outside of it.
+    focus: Option<Span>,
+    unresolved: Vec<Unresolved>,
+    references: Vec<Reference>,
+    /// The offset to record the scopes of, in `probed`.
+    probe: Option<usize>,
+    /// The scopes of the innermost expression containing `probe`.
+    probed: Vec<Scope>,
+    /// The spans of every name bound so far.
+    binders: Vec<Span>,
+    shadows: Vec<Shadow>,
+}
+
+impl Resolver {
+    fn push_scope(&mut self, scope: Scope) {
+        self.binders
+            .extend(scope.values().map(|binder| binder.span));
+        self.scopes.push(scope);
+    }
+
+    /// Records the names of `scope`, bound by `binds` or by a function if there are none, which
+    /// hide a name of an enclosing scope. A plain `inherit` is not reported, since it binds the
+    /// very value it hides.
+    fn shadow(&mut self, scope: &Scope, binds: &[Bind]) {
+        for (name, binder) in scope {
+            let inherited = binds.iter().any(|bind| match *bind {
+                Bind::Inherit(ref inherit) => inherit.names().iter().any(|n| n.as_str() == name),
+                _ => false,
+            });
+            let outer = match self.lookup(name) {
+                Some(outer) if !inherited && self.in_focus(binder.span) => outer.span,
+                _ => continue,
+            };
+            self.shadows.push(Shadow {
+                name: name.clone(),
+                span: binder.span,
+                shadowed: outer,
+            });
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Binder> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn candidates(&self) -> impl Iterator<Item = &str> {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.keys().map(String::as_str))
+            .chain(GLOBALS.iter().cloned())
+    }
+
+    fn in_focus(&self, span: Span) -> bool {
+        self.focus
+            .is_none_or(|focus| focus.start() <= span.start() && span.end() <= focus.end())
+    }
+
+    fn ident(&mut self, ident: &Ident) {
+        let name = ident.as_str();
+        if !self.in_focus(ident.span()) {
+            return;
+        }
+        if let Some(binder) = self.lookup(name) {
+            self.references.push(Reference {
+                name: name.to_owned(),
+                span: ident.span(),
+                target: Target::Binding(binder.span),
+            });
+            return;
+        }
+        if GLOBALS.contains(&name) || name.starts_with("__") {
+            return;
+        }
+
+        // Any name could be brought into scope by an enclosing `with` expression.
+        if let Some(&with) = self.withs.last() {
+            self.references.push(Reference {
+                name: name.to_owned(),
+                span: ident.span(),
+                target: Target::With(with),
+            });
+            return;
+        }
+
+        let suggestion = did_you_mean(name, self.candidates()).map(ToOwned::to_owned);
+        self.unresolved.push(Unresolved {
+            kind: UnresolvedKind::Variable,
+            name: name.to_owned(),
+            span: ident.span(),
+            suggestion,
+        });
+    }
+
+    fn projection(&mut self, base: &Expr, path: &AttrPath) {
+        let keys = match known_keys(base) {
+            Some(keys) => Some(keys),
+            None => match *base {
+                Expr::Ident(ref ident) => self
+                    .lookup(ident.as_str())
+                    .and_then(|binder| binder.keys.clone()),
+                _ => None,
+            },
+        };
+
+        let (keys, first) = match (keys, path.segments().first()) {
+            (Some(keys), Some(AttrSegment::Ident(first))) => (keys, first),
+            _ => return,
+        };
+
+        let name = first.as_str();
+        if !keys.iter().any(|key| key == name) && self.in_focus(first.span()) {
+            let suggestion =
+                did_you_mean(name, keys.iter().map(String::as_str)).map(ToOwned::to_owned);
+            self.unresolved.push(Unresolved {
+                kind: UnresolvedKind::Attribute,
+                name: name.to_owned(),
+                span: first.span(),
+                suggestion,
+            });
+        }
+    }
+
+    /// Resolves a list of bindings, which are either recursive (as in `rec { ... }`) or not.
+    fn binds(&mut self, binds: &[Bind], recursive: bool) {
+        self.inherits(binds);
+        if recursive {
+            self.push_scope(scope_of(binds));
+            binds.iter().for_each(|bind| self.visit_bind(bind));
+            self.scopes.pop();
+        } else {
+            binds.iter().for_each(|bind| self.visit_bind(bind));
+        }
+    }
+
+    /// Resolves the names brought in by `inherit`, which always refer to the enclosing scope.
+    fn inherits(&mut self, binds: &[Bind]) {
+        for bind in binds {
+            if let Bind::Inherit(ref inherit) = *bind {
+                inherit.names().iter().for_each(|name| self.ident(name));
+            }
+        }
+    }
+}
+
+/// Only the names referenced by expressions are resolved: names which are bound, or select an
+/// attribute, are skipped, and the names of an `inherit` are resolved before the scope of its
+/// bindings is entered.
+impl<'a> Visitor<'a> for Resolver {
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        let span = expr.span();
+        if let Some(focus) = self.focus {
+            if span.end() <= focus.start() || span.start() >= focus.end() {
+                return;
+            }
+        }
+        if let Some(probe) = self.probe {
+            if span.start().to_usize() <= probe && probe <= span.end().to_usize() {
+                self.probed = self.scopes.clone();
+            }
+        }
+        visit::walk_expr(self, expr);
+    }
+
+    fn visit_ident(&mut self, ident: &'a Ident) {
+        self.ident(ident);
+    }
+
+    fn visit_set(&mut self, set: &'a ExprSet) {
+        self.binds(set.binds(), false);
+    }
+
+    fn visit_let(&mut self, let_: &'a ExprLet) {
+        self.binds(let_.binds(), true);
+    }
+
+    fn visit_rec(&mut self, rec: &'a ExprRec) {
+        self.binds(rec.binds(), true);
+    }
+
+    fn visit_proj(&mut self, proj: &'a ExprProj) {
+        self.visit_expr(proj.base());
+        self.visit_attr_path(proj.attr());
+        if let Some(fallback) = proj.fallback() {
+            self.visit_expr(fallback);
+        } else {
+            self.projection(proj.base(), proj.attr());
+        }
+    }
+
+    fn visit_with(&mut self, with: &'a ExprWith) {
+        self.visit_expr(with.with());
+        self.withs.push(with.with().span());
+        self.visit_expr(with.expr());
+        self.withs.pop();
+    }
+
+    fn visit_let_in(&mut self, let_in: &'a ExprLetIn) {
+        self.inherits(let_in.binds());
+        let scope = scope_of(let_in.binds());
+        self.shadow(&scope, let_in.binds());
+        self.push_scope(scope);
+        let_in.binds().iter().for_each(|bind| self.visit_bind(bind));
+        self.visit_expr(let_in.body());
+        self.scopes.pop();
+    }
+
+    fn visit_fn_decl_simple(&mut self, decl: &'a FnDeclSimple) {
+        let mut scope = Scope::new();
+        insert(&mut scope, decl.name(), None, NameKind::Variable);
+        self.shadow(&scope, &[]);
+        self.push_scope(scope);
+        self.visit_expr(decl.body());
+        self.scopes.pop();
+    }
+
+    fn visit_fn_decl_formals(&mut self, decl: &'a FnDeclFormals) {
+        let mut scope = Scope::new();
+        for formal in decl.formals() {
+            insert(&mut scope, formal.name(), None, NameKind::Variable);
+        }
+        if let Some(extra) = decl.extra() {
+            insert(&mut scope, extra, None, NameKind::Set);
+        }
+
+        self.shadow(&scope, &[]);
+        self.push_scope(scope);
+        for default in decl.formals().iter().filter_map(|f| f.default()) {
+            self.visit_expr(default);
+        }
+        self.visit_expr(decl.body());
+        self.scopes.pop();
+    }
+
+    fn visit_bind_inherit(&mut self, _inherit: &'a BindInherit) {}
+
+    fn visit_bind_inherit_expr(&mut self, inherit: &'a BindInheritExpr) {
+        self.visit_expr(inherit.expr());
+    }
+
+    fn visit_attr_segment(&mut self, segment: &'a AttrSegment) {
+        if !matches!(*segment, AttrSegment::Ident(_)) {
+            visit::walk_attr_segment(self, segment);
+        }
+    }
+}
+
+fn insert(scope: &mut Scope, ident: &Ident, keys: Keys, kind: NameKind) {
+    let span = ident.span();
+    scope.insert(ident.as_str().to_owned(), Binder { span, keys, kind });
+}
+
+/// Returns the kind of the value `expr`.
+fn kind_of(expr: &Expr) -> NameKind {
+    match *expr {
+        Expr::Paren(ref e) => kind_of(e.expr()),
+        Expr::FnDecl(_) => NameKind::Function,
+        Expr::Set(_) | Expr::Rec(_) => NameKind::Set,
+        _ => NameKind::Variable,
+    }
+}
+
+/// Returns the statically known name of an attribute path segment, if any.
+fn static_name(segment: &AttrSegment) -> Option<String> {
+    segment.name().map(str::to_owned)
+}
+
+/// Returns the keys of the given expression, if it is an attribute set literal whose keys are
+/// entirely static.
+fn known_keys(expr: &Expr) -> Keys {
+    match *expr {
+        Expr::Paren(ref e) => known_keys(e.expr()),
+        Expr::Set(ref set) => keys_of(set.binds()),
+        Expr::Rec(ref rec) => keys_of(rec.binds()),
+        _ => None,
+    }
+}
+
+fn keys_of(binds: &[Bind]) -> Keys {
+    let mut keys = Vec::new();
+    for bind in binds {
+        match *bind {
+            Bind::Simple(ref simple) => {
+                keys.push(simple.attr().segments().first().and_then(static_name)?);
+            }
+            Bind::Inherit(ref inherit) => {
+                keys.extend(inherit.names().iter().map(|n| n.as_str().to_owned()));
+            }
+            Bind::InheritExpr(ref inherit) => {
+                keys.extend(inherit.names().iter().map(|n| n.as_str().to_owned()));
+            }
+        }
+    }
+
+    Some(keys)
+}
+
+/// Builds the scope introduced by a recursive list of bindings, such as `let` or `rec`.
+fn scope_of(binds: &[Bind]) -> Scope {
+    let mut scope = Scope::new();
+
+    for bind in binds {
+        match *bind {
+            Bind::Simple(ref simple) => {
+                let segments = simple.attr().segments();
+                let first = match segments.first() {
+                    Some(AttrSegment::Ident(first)) => first,
+                    _ => continue,
+                };
+
+                // Nested attribute paths such as `a.b = 1;` implicitly define keys on `a`.
+                let (keys, kind) = if segments.len() == 1 {
+                    (known_keys(simple.expr()), kind_of(simple.expr()))
+                } else {
+                    let keys = static_name(&segments[1]).map(|name| vec![name]);
+                    (keys, NameKind::Set)
+                };
+
+                match scope.get_mut(first.as_str()) {
+                    Some(existing) => {
+                        existing.keys = match (existing.keys.take(), keys) {
+                            (Some(mut lhs), Some(rhs)) => {
+                                lhs.extend(rhs);
+                                Some(lhs)
+                            }
+                            _ => None,
+                        };
+                    }
+                    None => insert(&mut scope, first, keys, kind),
+                }
+            }
+            Bind::Inherit(ref inherit) => {
+                inherit
+                    .names()
+                    .iter()
+                    .for_each(|n| insert(&mut scope, n, None, NameKind::Variable));
+            }
+            Bind::InheritExpr(ref inherit) => {
+                inherit
+                    .names()
+                    .iter()
+                    .for_each(|n| insert(&mut scope, n, None, NameKind::Variable));
+            }
+        }
+    }
+
+    scope
+}
+
+#[cfg(test)]
+mod tests {
+    use codespan::Files;
+
+    use super::*;
+
+    fn unresolved(source: &str) -> Vec<(UnresolvedKind, String, Option<String>)> {
+        let source: SourceFile = source.parse().expect("failed to parse source");
+        resolve(&source)
+            .into_iter()
+            .map(|u| (u.kind, u.name, u.suggestion))
+            .collect()
+    }
+
+    #[test]
+    fn unknown_variables() {
+        let found = unresolved("{ stdenv, lib }: stdenb.mkDerivation { name = lib.version; }");
+        assert_eq!(
+            found,
+            vec![(
+                UnresolvedKind::Variable,
+                "stdenb".to_owned(),
+                Some("stdenv".to_owned())
+            )]
+        );
+
+        assert!(unresolved("let a = 1; b = a; in b").is_empty());
+        assert!(unresolved("rec { a = 1; b = a; }").is_empty());
+        assert!(unresolved("with import <nixpkgs> {}; hello").is_empty());
+        assert!(unresolved("x: builtins.map (y: x + y) [ 1 ]").is_empty());
+        assert_eq!(unresolved("{ a = 1; b = a; }").len(), 1);
+        assert_eq!(unresolved("let inherit a; in a").len(), 1);
+    }
+
+    #[test]
+    fn unknown_attributes() {
+        let found = unresolved("let set = { foo = 1; bar = 2; }; in set.fooo");
+        assert_eq!(
+            found,
+            vec![(
+                UnresolvedKind::Attribute,
+                "fooo".to_owned(),
+                Some("foo".to_owned())
+            )]
+        );
+
+        assert!(unresolved("let set.foo = 1; set.bar = 2; in set.bar").is_empty());
+        assert!(unresolved("let set = { ${\"a\"} = 1; }; in set.b").is_empty());
+    }
+
+    #[test]
+    fn finds_definitions() {
+        let source = r#"{ pkgs, lib ? pkgs.lib }:
+let
+  inherit (lib) version;
+  name = "hello";
+in with pkgs; rec {
+  inherit name;
+  pname = name;
+  src = fetchurl { inherit pname version; };
+  f = name: name;
+  g = [ src map ];
+}
+"#;
+        let file: SourceFile = source.parse().unwrap();
+        let definition = |needle: &str| {
+            let offset = source.find(needle).unwrap();
+            let span = match definition_at(&file, offset)? {
+                Target::Binding(span) => span,
+                Target::With(span) => span,
+            };
+            Some(&source[span.start().to_usize()..span.end().to_usize()])
+        };
+        let find = |needle: &str| source.find(needle).unwrap() as u32;
+
+        assert_eq!(definition("pkgs.lib"), Some("pkgs"));
+        assert_eq!(definition("lib)"), Some("lib"));
+        assert_eq!(definition("name;"), Some("name"));
+        assert_eq!(
+            definition_at(&file, source.find("name;").unwrap()),
+            Some(Target::Binding(Span::new(
+                find("name ="),
+                find("name =") + 4
+            )))
+        );
+        assert_eq!(definition("name;\n  src"), Some("name"));
+        assert_eq!(definition("fetchurl"), Some("pkgs"));
+        assert_eq!(definition("version; }"), Some("version"));
+        assert_eq!(definition("src map"), Some("src"));
+        assert_eq!(definition("map ]"), None);
+
+        // The formal of `f` shadows the binding of `name`.
+        let body = source.find("name: name").unwrap() + "name: ".len();
+        let formal = find("name: name");
+        assert_eq!(
+            definition_at(&file, body),
+            Some(Target::Binding(Span::new(formal, formal + 4)))
+        );
+    }
+
+    #[test]
+    fn finds_references() {
+        let source = "{ x, y }:
+let
+  a = x + y;
+  b = let x = a; in x;
+  unused = 1;
+in { inherit a; c = x; d = z; }
+";
+        let file: SourceFile = source.parse().unwrap();
+        let references = |needle: &str| {
+            let (binding, spans) = references_at(&file, source.find(needle).unwrap())?;
+            let text = |span: Span| span.start().to_usize()..span.end().to_usize();
+            let spans: Vec<_> = spans.into_iter().map(text).collect();
+            Some((text(binding), spans))
+        };
+        let at = |needle: &str, len: usize| {
+            let start = source.find(needle).unwrap();
+            start..start + len
+        };
+
+        let formal = Some((at("x, y", 1), vec![at("x + y", 1), at("x; d", 1)]));
+        assert_eq!(references("x, y"), formal);
+        assert_eq!(references("x + y"), formal);
+        assert_eq!(references("x; d"), formal);
+
+        let a = Some((at("a = x", 1), vec![at("a; in", 1), at("a; c", 1)]));
+        assert_eq!(references("a = x"), a);
+        assert_eq!(references("a; c"), a);
+        assert_eq!(
+            references("x = a"),
+            Some((at("x = a", 1), vec![at("x;\n", 1)]))
+        );
+        assert_eq!(references("unused"), Some((at("unused", 6), vec![])));
+        assert_eq!(references("z;"), None);
+    }
+
+    #[test]
+    fn finds_shadowed_bindings() {
+        let source = "{ lib, pkgs, x }:
+let
+  inherit x;
+  inherit (pkgs) lib;
+  f = x: y: let y = 1; in x + y;
+in rec { pkgs = f lib; g = pkgs: pkgs; }
+";
+        let file: SourceFile = source.parse().unwrap();
+        let at = |needle: &str, len: u32| {
+            let start = source.find(needle).unwrap() as u32;
+            Span::new(start, start + len)
+        };
+        let found: Vec<_> = shadows(&file)
+            .into_iter()
+            .map(|shadow| (shadow.name, shadow.span, shadow.shadowed))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("lib".to_owned(), at("lib;", 3), at("lib,", 3)),
+                ("x".to_owned(), at("x: y", 1), at("x;", 1)),
+                ("y".to_owned(), at("y = 1", 1), at("y: let", 1)),
+                ("pkgs".to_owned(), at("pkgs: pkgs", 4), at("pkgs = f", 4)),
+            ]
+        );
+
+        let id = Files::new().add("default.nix", source);
+        let diagnostic = shadows(&file)[0].to_diagnostic(id);
+        assert_eq!(diagnostic.message, "`lib` shadows an outer binding");
+        assert_eq!(diagnostic.code.as_deref(), Some("shadowed-binding"));
+    }
+
+    #[test]
+    fn names_in_scope() {
+        let source = r#"{ stdenv, ... }:
+let
+  version = "1.0";
+  mkName = v: "hello-${v}";
+  meta.license = null;
+in rec {
+  name = mkName version;
+  src = let stdenv = null; in stdenv;
+}
+"#;
+        let file: SourceFile = source.parse().unwrap();
+        let names = |needle: &str| {
+            let offset = source.find(needle).unwrap();
+            names_at(&file, offset)
+                .into_iter()
+                .map(|bound| format!("{} {:?}", bound.name, bound.kind))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names("version;"),
+            [
+                "name Variable",
+                "src Variable",
+                "meta Set",
+                "mkName Function",
+                "version Variable",
+                "stdenv Variable",
+            ]
+        );
+        assert_eq!(names("stdenv;")[0], "stdenv Variable");
+        assert_eq!(names("stdenv;").len(), 6);
+        assert_eq!(names("\"hello")[0], "v Variable");
+    }
+
+    #[test]
+    fn resolves_edited_regions_again() {
+        let source = "let f = x: { a = x; b = y; }; s = { k = 1; }; in [ (f z) s.q ]";
+        let old: SourceFile = source.parse().unwrap();
+        let previous = resolve(&old);
+        assert_eq!(previous.len(), 3);
+
+        let edit = |needle: &str, text: &str| {
+            let start = source.find(needle).unwrap();
+            let span = Span::new(start as u32, (start + needle.len()) as u32);
+            let mut edited = source.to_owned();
+            edited.replace_range(start..start + needle.len(), text);
+            let new: SourceFile = edited.parse().unwrap();
+            let result = reresolve(&previous, &old, &new, span, text.len() as u32);
+            (result, resolve(&new))
+        };
+
+        let (result, expected) = edit("y;", "x + yy;");
+        assert_eq!(result, Some(expected));
+        let (result, expected) = edit("1;", "2;");
+        assert_eq!(result, Some(expected));
+
+        // Renaming `k` changes the keys of `s`, which its sibling `s.q` depends on.
+        let (result, _) = edit("k", "q");
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn suggests_by_span() {
+        let source: SourceFile = "let value = 1; in valu + valeu.x".parse().unwrap();
+        let unresolved = resolve(&source);
+        assert_eq!(
+            suggestion(&source, unresolved[0].span).as_deref(),
+            Some("value")
+        );
+        assert_eq!(suggestion(&source, Span::new(0, 3)), None);
+    }
+}
@@ -0,0 +1,595 @@
+//! Detection of the role a file plays, enabling checks and completions specific to it.
+//!
+//! The role of a file is, in order of priority:
+//!
+//! * given by a comment of the form `# nix-role: module` before any code,
+//! * given by the first pattern of the `fileRoles` setting matching its path, e.g.
+//!   `{ "modules/**/*.nix": "module" }`, where `*` matches within a path segment and `**/` matches
+//!   any number of directories,
+//! * implied by its name, such as `flake.nix`, `shell.nix` or `overlay.nix`,
+//! * or guessed from its shape: a module is a function taking `config` or `options`, or returns a
+//!   set with `imports`, `options` or `config`, an overlay is a function `final: prev: { ... }`, a shell
+//!   calls `mkShell`, and a Hydra jobset uses `hydraJob`.
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use nix_parser::ast::visit::descendants;
+use nix_parser::ast::{Bind, Expr, ExprFnDecl, FnDeclFormals, SourceFile};
+use nix_parser::lexer::{Lexer, Token};
+use nix_parser::{HasSpan, ToSpan};
+use regex::Regex;
+use serde_json::Value;
+
+const PREFIX: &str = "nix-role:";
+
+/// The attributes a module may have besides its option definitions.
+const MODULE_ATTRS: &[(&str, &str)] = &[
+    ("_file", "the file name shown in errors"),
+    ("config", "option definitions"),
+    ("disabledModules", "modules to exclude from the imports"),
+    ("imports", "modules to include"),
+    ("key", "identifies the module when deduplicating imports"),
+    ("meta", "module metadata, such as maintainers"),
+    ("options", "option declarations"),
+];
+
+/// The attributes commonly given to `mkShell`.
+const SHELL_ATTRS: &[(&str, &str)] = &[
+    ("buildInputs", "dependencies for the host platform"),
+    ("inputsFrom", "derivations whose inputs to include"),
+    ("nativeBuildInputs", "tools to run in the shell"),
+    ("packages", "tools to run in the shell"),
+    ("shellHook", "shell code run when entering the shell"),
+];
+
+/// What a file is for.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Role {
+    Flake,
+    Shell,
+    Overlay,
+    Module,
+    Jobset,
+    /// Any other expression, which gets no role-specific treatment.
+    Expression,
+}
+
+impl Role {
+    fn from_name(name: &str) -> Option<Role> {
+        match name {
+            "flake" => Some(Role::Flake),
+            "shell" => Some(Role::Shell),
+            "overlay" => Some(Role::Overlay),
+            "module" => Some(Role::Module),
+            "jobset" => Some(Role::Jobset),
+            "expression" => Some(Role::Expression),
+            _ => None,
+        }
+    }
+
+    /// Returns the role implied by the name of a file, given as a path or URI.
+    fn from_path(path: &str) -> Option<Role> {
+        let mut segments = path.rsplit('/');
+        let file = segments.next()?;
+        let dir = segments.next().unwrap_or("");
+        match file {
+            "flake.nix" => Some(Role::Flake),
+            "shell.nix" => Some(Role::Shell),
+            "overlay.nix" => Some(Role::Overlay),
+            "configuration.nix" | "hardware-configuration.nix" | "module.nix" => Some(Role::Module),
+            "jobset.nix" | "jobsets.nix" | "release.nix" | "hydra.nix" => Some(Role::Jobset),
+            _ if dir == "overlays" && file.ends_with(".nix") => Some(Role::Overlay),
+            _ => None,
+        }
+    }
+
+    /// Guesses the role of a file from the shape of its value.
+    fn from_shape(source: &str, file: &SourceFile) -> Role {
+        let expr = file.expr();
+        if overlay_args(expr).is_some() {
+            return Role::Overlay;
+        }
+        if let Some(formals) = formals(expr) {
+            // Packages take `lib` too, so only the arguments specific to modules count.
+            let names = ["config", "options"];
+            if formals
+                .formals()
+                .iter()
+                .any(|f| names.contains(&f.name().as_str()))
+            {
+                return Role::Module;
+            }
+        }
+        match *value(expr) {
+            Expr::Set(ref set) if set.binds().iter().any(is_module_attr) => Role::Module,
+            Expr::FnApp(ref app) if is_mk_shell(app.function()) => Role::Shell,
+            _ if source.contains("hydraJob") => Role::Jobset,
+            _ => Role::Expression,
+        }
+    }
+}
+
+/// The roles configured for file paths by the `fileRoles` setting.
+#[derive(Clone, Debug, Default)]
+pub struct Overrides(Vec<(String, Regex, Role)>);
+
+impl PartialEq for Overrides {
+    fn eq(&self, other: &Overrides) -> bool {
+        let key = |(pattern, _, role): &(String, Regex, Role)| (pattern.clone(), *role);
+        self.0.iter().map(key).eq(other.0.iter().map(key))
+    }
+}
+
+impl Overrides {
+    /// Reads the `fileRoles` setting from initialization options or workspace settings, which may
+    /// nest it under a `nix` section. Patterns with an unknown role are ignored.
+    pub fn from_settings(settings: &Value) -> Self {
+        let section = settings.get("nix").unwrap_or(settings);
+        let patterns = match section.get("fileRoles") {
+            Some(Value::Object(patterns)) => patterns,
+            _ => return Overrides::default(),
+        };
+        let overrides = patterns
+            .iter()
+            .filter_map(|(pattern, role)| {
+                let role = Role::from_name(role.as_str()?)?;
+                Some((pattern.clone(), glob(pattern)?, role))
+            })
+            .collect();
+        Overrides(overrides)
+    }
+
+    fn find(&self, path: &str) -> Option<Role> {
+        self.0
+            .iter()
+            .find(|(_, regex, _)| regex.is_match(path))
+            .map(|&(_, _, role)| role)
+    }
+}
+
+/// Converts a glob matching the end of a path to a regular expression.
+fn glob(pattern: &str) -> Option<Regex> {
+    let mut regex = String::from("(?:^|/)");
+    let mut rest = pattern.trim_start_matches('/');
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("**/") {
+            regex.push_str("(?:[^/]*/)*");
+            rest = after;
+            continue;
+        }
+        match c {
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    regex.push('$');
+    Regex::new(&regex).ok()
+}
+
+/// Returns the role of the file at `path`, given as a path or URI.
+pub fn detect(path: &str, source: &str, file: Option<&SourceFile>, overrides: &Overrides) -> Role {
+    annotated(source)
+        .or_else(|| overrides.find(path))
+        .or_else(|| Role::from_path(path))
+        .or_else(|| file.map(|file| Role::from_shape(source, file)))
+        .unwrap_or(Role::Expression)
+}
+
+/// Returns the role given by a `nix-role:` comment before any code.
+fn annotated(source: &str) -> Option<Role> {
+    let lexer = Lexer::new(source).ok()?;
+    lexer
+        .tokens()
+        .iter()
+        .map_while(|token| match *token {
+            Token::Comment(ref text, _, _) => Some(text),
+            _ => None,
+        })
+        .flat_map(|text| text.lines())
+        .filter_map(|line| line.trim().strip_prefix(PREFIX))
+        .find_map(|name| Role::from_name(name.trim()))
+}
+
+/// Returns the value of a file, looking through functions and any `let` or `with` around it.
+fn value(mut expr: &Expr) -> &Expr {
+    loop {
+        expr = match *expr {
+            Expr::FnDecl(ref decl) => match **decl {
+                ExprFnDecl::Simple(ref decl) => decl.body(),
+                ExprFnDecl::Formals(ref decl) => decl.body(),
+            },
+            Expr::LetIn(ref let_in) => let_in.body(),
+            Expr::With(ref with) => with.expr(),
+            Expr::Paren(ref paren) => paren.expr(),
+            _ => return expr,
+        };
+    }
+}
+
+fn formals(expr: &Expr) -> Option<&FnDeclFormals> {
+    match *expr {
+        Expr::FnDecl(ref decl) => match **decl {
+            ExprFnDecl::Formals(ref formals) => Some(formals),
+            ExprFnDecl::Simple(_) => None,
+        },
+        Expr::Paren(ref paren) => formals(paren.expr()),
+        _ => None,
+    }
+}
+
+/// Returns the names of the arguments of an overlay, `final: prev: { ... }`, along with the set
+/// it returns.
+fn overlay_args(expr: &Expr) -> Option<(&str, &str, &Expr)> {
+    fn simple(expr: &Expr) -> Option<(&str, &Expr)> {
+        match *expr {
+            Expr::FnDecl(ref decl) => match **decl {
+                ExprFnDecl::Simple(ref decl) => Some((decl.name().as_str(), decl.body())),
+                ExprFnDecl::Formals(_) => None,
+            },
+            _ => None,
+        }
+    }
+    let (first, body) = simple(expr)?;
+    let (second, body) = simple(body)?;
+    let names = ["final", "prev", "self", "super"];
+    if !names.contains(&first) || !names.contains(&second) {
+        return None;
+    }
+    match *value(body) {
+        ref set @ Expr::Set(_) | ref set @ Expr::Rec(_) => Some((first, second, set)),
+        _ => None,
+    }
+}
+
+fn is_module_attr(bind: &Bind) -> bool {
+    match *bind {
+        Bind::Simple(ref simple) => match simple.attr().segments().first() {
+            Some(segment) => matches!(segment.name(), Some("imports" | "options" | "config")),
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+fn is_mk_shell(function: &Expr) -> bool {
+    let name = match *function {
+        Expr::Ident(ref ident) => ident.as_str(),
+        Expr::Proj(ref proj) => match proj.attr().segments().last().and_then(|s| s.name()) {
+            Some(name) => name,
+            None => return false,
+        },
+        _ => return false,
+    };
+    name == "mkShell" || name == "mkShellNoCC"
+}
+
+fn binds(expr: &Expr) -> Option<&[Bind]> {
+    match *expr {
+        Expr::Set(ref set) => Some(set.binds()),
+        Expr::Rec(ref rec) => Some(rec.binds()),
+        _ => None,
+    }
+}
+
+/// Returns the span of the argument set of a function, up to its closing brace, which the span
+/// of the function itself extends past to the end of its body.
+fn formals_span(source: &str, formals: &FnDeclFormals) -> Span {
+    let start = formals.span().start();
+    let close = Lexer::new(source).ok().and_then(|lexer| {
+        let mut depth = 0;
+        lexer
+            .tokens()
+            .iter()
+            .skip_while(|token| token.to_span().start() < start)
+            .find_map(|token| {
+                match *token {
+                    Token::LBrace(_) | Token::Interpolate(_) => depth += 1,
+                    Token::RBrace(span) if depth == 1 => return Some(span.end()),
+                    Token::RBrace(_) => depth -= 1,
+                    _ => {}
+                }
+                None
+            })
+    });
+    close.map_or(formals.span(), |end| Span::new(start, end))
+}
+
+/// Returns the diagnostics specific to the role of `file`, parsed from `source`.
+pub fn check(id: FileId, source: &str, file: &SourceFile, role: Role) -> Vec<Diagnostic> {
+    match role {
+        Role::Shell => check_shell(id, file),
+        Role::Overlay => check_overlay(id, file),
+        Role::Module => check_module(id, source, file),
+        Role::Flake | Role::Jobset | Role::Expression => Vec::new(),
+    }
+}
+
+/// `nix-shell` calls the function in `shell.nix` without arguments, so each must have a default.
+fn check_shell(id: FileId, file: &SourceFile) -> Vec<Diagnostic> {
+    let formals = match formals(file.expr()) {
+        Some(formals) => formals,
+        None => return Vec::new(),
+    };
+    formals
+        .formals()
+        .iter()
+        .filter(|formal| formal.default().is_none())
+        .map(|formal| {
+            let name = formal.name();
+            let label = Label::new(id, name.span(), "no default value");
+            let message = format!("`nix-shell` cannot pass the argument `{}`", name);
+            Diagnostic::new_warning(message, label)
+                .with_code("shell-argument-without-default")
+                .with_notes(vec![format!(
+                    "give it a default, e.g. `{} ? import <nixpkgs> {{ }}`, or pass it with `--arg`",
+                    name
+                )])
+        })
+        .collect()
+}
+
+/// An overlay defining a package in terms of the same package of its first argument refers to
+/// its own result, which recurses infinitely.
+fn check_overlay(id: FileId, file: &SourceFile) -> Vec<Diagnostic> {
+    let (first, second, set) = match overlay_args(file.expr()) {
+        Some(args) => args,
+        None => {
+            let label = Label::new(id, file.expr().span(), "not an overlay");
+            let message = "an overlay must be a function of two arguments, `final: prev: { ... }`";
+            let diagnostic = Diagnostic::new_warning(message, label).with_code("overlay-shape");
+            return vec![diagnostic];
+        }
+    };
+
+    let mut diagnostics = Vec::new();
+    for bind in binds(set).unwrap_or_default() {
+        let simple = match *bind {
+            Bind::Simple(ref simple) => simple,
+            _ => continue,
+        };
+        let name = match simple.attr().segments() {
+            [segment] => match segment.name() {
+                Some(name) => name,
+                None => continue,
+            },
+            _ => continue,
+        };
+
+        for expr in descendants(simple.expr()) {
+            let proj = match *expr {
+                Expr::Proj(ref proj) => proj,
+                _ => continue,
+            };
+            let refers = match (proj.base(), proj.attr().segments().first()) {
+                (Expr::Ident(ref base), Some(segment)) => {
+                    base.as_str() == first && segment.name() == Some(name)
+                }
+                _ => false,
+            };
+            if refers {
+                let span = Span::new(proj.base().span().start(), proj.attr().span().end());
+                let label = Label::new(id, span, "refers to the result of this overlay");
+                let message = format!("`{}` is defined in terms of itself", name);
+                diagnostics.push(
+                    Diagnostic::new_warning(message, label)
+                        .with_code("overlay-self-reference")
+                        .with_notes(vec![format!(
+                            "use `{}.{}` to refer to the package being overridden",
+                            second, name
+                        )]),
+                );
+                break;
+            }
+        }
+    }
+    diagnostics
+}
+
+/// A module with an explicit `config` or `options` attribute may only have the attributes of
+/// `MODULE_ATTRS`, and is called with more arguments than it names.
+fn check_module(id: FileId, source: &str, file: &SourceFile) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    if let Some(formals) = formals(file.expr()) {
+        if formals.ellipsis().is_none() {
+            let label = Label::new(id, formals_span(source, formals), "missing `...`");
+            let message = "a module is called with arguments it does not name";
+            diagnostics.push(
+                Diagnostic::new_warning(message, label)
+                    .with_code("module-missing-ellipsis")
+                    .with_notes(vec!["add `...` to the argument set".to_owned()]),
+            );
+        }
+    }
+
+    let binds = binds(value(file.expr())).unwrap_or_default();
+    let explicit = binds.iter().any(|bind| match *bind {
+        Bind::Simple(ref simple) => matches!(
+            simple.attr().segments().first().and_then(|s| s.name()),
+            Some("config" | "options")
+        ),
+        _ => false,
+    });
+    if !explicit {
+        return diagnostics;
+    }
+
+    for bind in binds {
+        let segment = match *bind {
+            Bind::Simple(ref simple) => match simple.attr().segments().first() {
+                Some(segment) => segment,
+                None => continue,
+            },
+            _ => continue,
+        };
+        if let Some(name) = segment.name() {
+            if !MODULE_ATTRS.iter().any(|&(attr, _)| attr == name) {
+                let label = Label::new(id, segment.span(), "not a module attribute");
+                let message = format!("unsupported module attribute `{}`", name);
+                diagnostics.push(
+                    Diagnostic::new_error(message, label)
+                        .with_code("module-unsupported-attribute")
+                        .with_notes(vec![format!(
+                            "the module has a `config` or `options` attribute, so `{}` belongs \
+                             under `config`",
+                            name
+                        )]),
+                );
+            }
+        }
+    }
+    diagnostics
+}
+
+/// Returns the attribute names specific to `role` which can be completed at `offset`, with a
+/// description of each.
+pub fn completions(
+    role: Role,
+    file: &SourceFile,
+    offset: usize,
+) -> Vec<(&'static str, &'static str)> {
+    let (set, names) = match role {
+        Role::Module => (value(file.expr()), MODULE_ATTRS),
+        Role::Shell => match *value(file.expr()) {
+            Expr::FnApp(ref app) if is_mk_shell(app.function()) => (app.argument(), SHELL_ATTRS),
+            _ => return Vec::new(),
+        },
+        _ => return Vec::new(),
+    };
+    let binds = match binds(set) {
+        Some(binds) => binds,
+        None => return Vec::new(),
+    };
+    let span = set.span();
+    if offset <= span.start().to_usize() || offset >= span.end().to_usize() {
+        return Vec::new();
+    }
+    let inside = binds.iter().any(|bind| {
+        let span = bind.span();
+        let name_end = match *bind {
+            Bind::Simple(ref simple) => simple.attr().span().end(),
+            _ => span.end(),
+        };
+        span.start().to_usize() < offset
+            && offset <= span.end().to_usize()
+            && offset > name_end.to_usize()
+    });
+    if inside {
+        return Vec::new();
+    }
+
+    let bound: Vec<&str> = binds
+        .iter()
+        .filter_map(|bind| match *bind {
+            Bind::Simple(ref simple) => simple.attr().segments().first()?.name(),
+            _ => None,
+        })
+        .collect();
+    names
+        .iter()
+        .filter(|(name, _)| !bound.contains(name))
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use codespan::Files;
+    use serde_json::json;
+
+    use super::*;
+
+    fn role(path: &str, source: &str) -> Role {
+        let file: SourceFile = source.parse().unwrap();
+        detect(path, source, Some(&file), &Overrides::default())
+    }
+
+    fn codes(role: Role, source: &str) -> Vec<String> {
+        let file: SourceFile = source.parse().unwrap();
+        let id = Files::new().add("test.nix", source);
+        check(id, source, &file, role)
+            .into_iter()
+            .filter_map(|diagnostic| diagnostic.code)
+            .collect()
+    }
+
+    #[test]
+    fn detects_roles() {
+        assert_eq!(role("file:///p/flake.nix", "{ }"), Role::Flake);
+        assert_eq!(role("file:///p/overlays/rust.nix", "{ }"), Role::Overlay);
+        assert_eq!(role("/p/a.nix", "final: prev: { }"), Role::Overlay);
+        assert_eq!(role("/p/a.nix", "{ config, ... }: { }"), Role::Module);
+        assert_eq!(
+            role("/p/a.nix", "{ lib, ... }: { imports = [ ]; }"),
+            Role::Module
+        );
+        assert_eq!(role("/p/a.nix", "{ imports = [ ]; }"), Role::Module);
+        assert_eq!(role("/p/a.nix", "{ pkgs }: pkgs.mkShell { }"), Role::Shell);
+        assert_eq!(role("/p/a.nix", "{ a = lib.hydraJob b; }"), Role::Jobset);
+        assert_eq!(role("/p/a.nix", "x: y: x"), Role::Expression);
+
+        let source = "# nix-role: module\n{ a = 1; }";
+        assert_eq!(role("/p/shell.nix", source), Role::Module);
+
+        let settings = json!({ "nix": { "fileRoles": { "hosts/**/*.nix": "module" } } });
+        let overrides = Overrides::from_settings(&settings);
+        let file: SourceFile = "final: prev: { }".parse().unwrap();
+        let detected = detect("/p/hosts/a/b.nix", "", Some(&file), &overrides);
+        assert_eq!(detected, Role::Module);
+        let detected = detect("/p/hosts.nix", "", Some(&file), &overrides);
+        assert_eq!(detected, Role::Overlay);
+    }
+
+    #[test]
+    fn checks_roles() {
+        let shell = "{ pkgs ? import <nixpkgs> { }, system }: pkgs.mkShell { }";
+        assert_eq!(
+            codes(Role::Shell, shell),
+            ["shell-argument-without-default"]
+        );
+
+        let overlay = "final: prev: { hello = final.hello.override { }; foo = prev.foo; }";
+        assert_eq!(codes(Role::Overlay, overlay), ["overlay-self-reference"]);
+        assert_eq!(codes(Role::Overlay, "{ }"), ["overlay-shape"]);
+
+        let module = "{ config }: { options.a = 1; services.b = 2; imports = [ ]; }";
+        assert_eq!(
+            codes(Role::Module, module),
+            ["module-missing-ellipsis", "module-unsupported-attribute"]
+        );
+        assert!(codes(Role::Module, "{ ... }: { services.b = 2; }").is_empty());
+
+        let file: SourceFile = module.parse().unwrap();
+        let id = Files::new().add("test.nix", module);
+        let span = check(id, module, &file, Role::Module)[0].primary_label.span;
+        assert_eq!(
+            &module[span.start().to_usize()..span.end().to_usize()],
+            "{ config }"
+        );
+    }
+
+    #[test]
+    fn does_not_take_packages_for_modules() {
+        let package =
+            "{ lib, stdenv, fetchurl }:\nstdenv.mkDerivation { pname = \"a\"; meta = { }; }";
+        assert_eq!(role("/p/pkgs/a/default.nix", package), Role::Expression);
+        let file: SourceFile = package.parse().unwrap();
+        let role = detect("/p/a.nix", package, Some(&file), &Overrides::default());
+        assert!(codes(role, package).is_empty());
+    }
+
+    #[test]
+    fn completes_role_attributes() {
+        let source = "{ ... }: {\n  imports = [ ];\n  \n}\n";
+        let file: SourceFile = source.parse().unwrap();
+        let offset = source.find("  \n").unwrap() + 2;
+        let names: Vec<_> = completions(Role::Module, &file, offset)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert!(names.contains(&"options") && !names.contains(&"imports"));
+        let inside = source.find('[').unwrap() + 1;
+        assert!(completions(Role::Module, &file, inside).is_empty());
+    }
+}
@@ -0,0 +1,316 @@
+//! Lints flagging patterns which are risky from a security standpoint.
+//!
+//! Every pattern is reported with a code of its own, so that each can be suppressed or have its
+//! severity changed on its own. The `security` group in the `diagnosticSeverity` setting changes
+//! all of them at once. The checks are syntactic heuristics: a `builtins` shadowed by a local
+//! binding, or a secret assembled through interpolation, is not recognized.
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use nix_parser::ast::tokens::Literal;
+use nix_parser::ast::visit::walk;
+use nix_parser::ast::{Bind, Expr, ExprFnApp, ExprFnDecl, SourceFile, StringFragment};
+use nix_parser::HasSpan;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// The name of the group containing all security lints.
+pub const GROUP: &str = "security";
+
+/// The codes of the security lints.
+pub const RULES: &[&str] = &[
+    "builtins-exec",
+    "impure-builtin",
+    "import-from-input",
+    "insecure-fetch",
+    "secret-in-store",
+];
+
+/// Builtins whose result depends on the machine or environment evaluating the expression, which
+/// are unavailable or fixed in pure evaluation mode.
+const IMPURE_BUILTINS: &[&str] = &["currentSystem", "currentTime", "getEnv", "nixPath"];
+
+/// Functions downloading the URL they are given, either directly or as a `url` or `urls`
+/// attribute.
+const FETCHERS: &[&str] = &[
+    "fetchTarball",
+    "fetchgit",
+    "fetchGit",
+    "fetchurl",
+    "fetchzip",
+];
+
+/// Attribute names which usually hold secrets, unless they are hashed or name a file.
+static SECRET_NAME: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)(password|passwd|passphrase|secret|token|api_?key|private_?key)$").unwrap()
+});
+
+/// Returns whether `code` belongs to the security group.
+pub fn is_security_lint(code: &str) -> bool {
+    RULES.contains(&code)
+}
+
+/// Reports the risky patterns in `file`.
+pub fn check(id: FileId, file: &SourceFile) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    walk(file.expr(), |expr, enclosing| {
+        match builtin_name(expr) {
+            Some("exec") => {
+                let label = Label::new(id, expr.span(), "runs a command while evaluating");
+                let message = "`builtins.exec` runs arbitrary commands at evaluation time";
+                diagnostics.push(
+                    Diagnostic::new_warning(message, label)
+                        .with_code("builtins-exec")
+                        .with_notes(vec![
+                            "it requires `allow-unsafe-native-code-during-evaluation`".to_owned(),
+                        ]),
+                );
+            }
+            Some(name) if IMPURE_BUILTINS.contains(&name) => {
+                let label = Label::new(id, expr.span(), "requires `--impure`");
+                let message = format!(
+                    "`builtins.{}` makes evaluation depend on the machine evaluating it",
+                    name
+                );
+                diagnostics
+                    .push(Diagnostic::new_warning(message, label).with_code("impure-builtin"));
+            }
+            _ => {}
+        }
+
+        match *expr {
+            Expr::FnApp(ref app) => {
+                if let Some(fetcher) = fetcher_name(app.function()) {
+                    for span in insecure_urls(app.argument()) {
+                        let label = Label::new(id, span, "use `https://` instead");
+                        let message = format!("`{}` downloads over unencrypted HTTP", fetcher);
+                        diagnostics.push(
+                            Diagnostic::new_warning(message, label).with_code("insecure-fetch"),
+                        );
+                    }
+                }
+
+                if is_import(app.function()) && is_derived_from_input(app, enclosing) {
+                    let label = Label::new(id, app.argument().span(), "path computed from input");
+                    let message = "imported path is derived from user input";
+                    diagnostics.push(
+                        Diagnostic::new_warning(message, label)
+                            .with_code("import-from-input")
+                            .with_notes(vec![
+                                "anyone controlling the input controls the code evaluated"
+                                    .to_owned(),
+                            ]),
+                    );
+                }
+            }
+            Expr::Set(ref set) => diagnostics.extend(secrets(id, set.binds())),
+            Expr::Rec(ref rec) => diagnostics.extend(secrets(id, rec.binds())),
+            Expr::Let(ref e) => diagnostics.extend(secrets(id, e.binds())),
+            Expr::LetIn(ref e) => diagnostics.extend(secrets(id, e.binds())),
+            _ => {}
+        }
+    });
+
+    diagnostics
+}
+
+/// Reports bindings of secrets to string literals, which end up in the world-readable store.
+fn secrets(id: FileId, binds: &[Bind]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for bind in binds {
+        let simple = match *bind {
+            Bind::Simple(ref simple) => simple,
+            _ => continue,
+        };
+        let name = match simple.attr().segments().last().and_then(|s| s.name()) {
+            Some(name) => name,
+            None => continue,
+        };
+        if !SECRET_NAME.is_match(name) || name.to_lowercase().starts_with("hashed") {
+            continue;
+        }
+
+        let text = match *simple.expr() {
+            Expr::String(ref string) => match string.fragments() {
+                [StringFragment::Literal(ref text, _)] => text,
+                _ => continue,
+            },
+            _ => continue,
+        };
+        // Absolute paths name a file holding the secret, which is what should be done instead.
+        if text.trim().is_empty() || text.starts_with('/') {
+            continue;
+        }
+
+        let label = Label::new(
+            id,
+            simple.expr().span(),
+            "readable by every user of the machine",
+        );
+        let message = format!("`{}` is written to the world-readable Nix store", name);
+        diagnostics.push(
+            Diagnostic::new_warning(message, label)
+                .with_code("secret-in-store")
+                .with_notes(vec![
+                    "read the secret at runtime from a file outside of the store".to_owned(),
+                ]),
+        );
+    }
+    diagnostics
+}
+
+/// Returns the spans of the `http://` URLs passed to a fetcher, either directly or as its `url`
+/// or `urls` attribute.
+fn insecure_urls(argument: &Expr) -> Vec<Span> {
+    let mut urls = Vec::new();
+    match *argument {
+        Expr::Paren(ref paren) => return insecure_urls(paren.expr()),
+        Expr::Set(ref set) => {
+            for bind in set.binds() {
+                let simple = match *bind {
+                    Bind::Simple(ref simple) => simple,
+                    _ => continue,
+                };
+                match simple.attr().segments() {
+                    [segment] if segment.name() == Some("url") => urls.push(simple.expr()),
+                    [segment] if segment.name() == Some("urls") => {
+                        if let Expr::List(ref list) = *simple.expr() {
+                            urls.extend(list.elems());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        ref other => urls.push(other),
+    }
+
+    urls.into_iter()
+        .filter(|url| is_http(url))
+        .map(HasSpan::span)
+        .collect()
+}
+
+fn is_http(expr: &Expr) -> bool {
+    match *expr {
+        Expr::String(ref string) => match string.fragments().first() {
+            Some(StringFragment::Literal(ref text, _)) => text.starts_with("http://"),
+            _ => false,
+        },
+        Expr::Literal(Literal::Uri(ref uri, _)) => uri.scheme() == "http",
+        _ => false,
+    }
+}
+
+/// Returns whether the path imported by `app` is computed from the environment, or by
+/// interpolating or appending an argument of the functions among `enclosing`.
+fn is_derived_from_input(app: &ExprFnApp, enclosing: &[&Expr]) -> bool {
+    let mut from_env = false;
+    let mut composed = Vec::new();
+    walk(app.argument(), |expr, path| {
+        from_env |= builtin_name(expr) == Some("getEnv");
+        let in_composition = matches!(
+            path.last().map(|parent| &**parent),
+            Some(Expr::Binary(_)) | Some(Expr::Interpolation(_)) | Some(Expr::String(_))
+        );
+        match *expr {
+            Expr::Ident(ref ident) if in_composition => composed.push(ident.as_str()),
+            _ => {}
+        }
+    });
+
+    let params = parameters(enclosing);
+    from_env || composed.iter().any(|name| params.contains(name))
+}
+
+/// Returns the names of the arguments of the functions among `enclosing`, innermost first.
+fn parameters<'a>(enclosing: &[&'a Expr]) -> Vec<&'a str> {
+    let mut params = Vec::new();
+    for expr in enclosing.iter().rev() {
+        if let Expr::FnDecl(ref decl) = **expr {
+            match **decl {
+                ExprFnDecl::Simple(ref simple) => params.push(simple.name().as_str()),
+                ExprFnDecl::Formals(ref formals) => {
+                    params.extend(formals.formals().iter().map(|f| f.name().as_str()));
+                    params.extend(formals.extra().map(|extra| extra.as_str()));
+                }
+            }
+        }
+    }
+    params
+}
+
+/// Returns the name of the builtin `expr` refers to, as `builtins.name` or `__name`.
+pub fn builtin_name(expr: &Expr) -> Option<&str> {
+    match *expr {
+        Expr::Proj(ref proj) if proj.fallback().is_none() => {
+            match (proj.base(), proj.attr().segments()) {
+                (Expr::Ident(ref base), [segment]) if base.as_str() == "builtins" => segment.name(),
+                _ => None,
+            }
+        }
+        Expr::Ident(ref ident) => ident.as_str().strip_prefix("__"),
+        _ => None,
+    }
+}
+
+fn fetcher_name(function: &Expr) -> Option<&str> {
+    let name = match *function {
+        Expr::Ident(ref ident) => ident.as_str(),
+        Expr::Proj(ref proj) => proj.attr().segments().last()?.name()?,
+        _ => return None,
+    };
+    FETCHERS.iter().find(|&&fetcher| fetcher == name).cloned()
+}
+
+fn is_import(function: &Expr) -> bool {
+    match *function {
+        Expr::Ident(ref ident) => ident.as_str() == "import" || ident.as_str() == "scopedImport",
+        ref other => builtin_name(other) == Some("import"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(source: &str) -> Vec<(String, String)> {
+        let mut files = codespan::Files::new();
+        let id = files.add("test.nix", source);
+        let file: SourceFile = source.parse().unwrap();
+        check(id, &file)
+            .into_iter()
+            .map(|d| {
+                let span = d.primary_label.span;
+                let text = &source[span.start().to_usize()..span.end().to_usize()];
+                (d.code.unwrap(), text.to_owned())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn flags_risky_patterns() {
+        let source = r#"{ name, ... }: {
+  a = builtins.exec [ "ls" ];
+  b = builtins.getEnv "HOME";
+  c = fetchurl { url = "http://example.org/a.tar.gz"; sha256 = ""; };
+  d = builtins.fetchTarball "https://example.org/b.tar.gz";
+  e = import (./modules + "/${name}.nix");
+  f = import ./default.nix { inherit name; };
+  g = { password = "hunter2"; passwordFile = "/run/keys/pw"; hashedPassword = "$6$x"; };
+}"#;
+        assert_eq!(
+            codes(source),
+            [
+                ("builtins-exec", "builtins.exec"),
+                ("impure-builtin", "builtins.getEnv"),
+                ("insecure-fetch", "\"http://example.org/a.tar.gz\""),
+                ("import-from-input", "(./modules + \"/${name}.nix\")"),
+                ("secret-in-store", "\"hunter2\""),
+            ]
+            .iter()
+            .map(|&(code, text)| (code.to_owned(), text.to_owned()))
+            .collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,458 @@
+//! Recording of language server sessions, and replaying them against the current build.
+//!
+//! With `--record session.jsonl`, every message read from or written to the client is appended
+//! to the given file as one JSON object per line, along with internal events such as edits being
+//! applied to the database, which help to tell what the server made of the messages:
+//!
+//! ```text
+//! {"timeMs":0.4,"direction":"in","message":{"jsonrpc":"2.0","id":0,"method":"initialize",...}}
+//! {"timeMs":1.2,"event":"edit","detail":{"uri":"file:///a.nix","start":4,"end":5,"len":2}}
+//! ```
+//!
+//! The `replay` subcommand feeds the messages which were read from the client to a fresh server,
+//! and compares what it answers with the recorded answers. Requests are compared by ID, while
+//! diagnostics, whose intermediate states depend on timing, are only compared once the server has
+//! settled.
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::{Future, Poll};
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// The recorder shared by the whole server, which records nothing until started.
+pub static RECORDER: Lazy<Recorder> = Lazy::new(Recorder::new);
+
+/// Responses to these requests vary between runs, so they are not compared when replaying.
+const NONDETERMINISTIC: &[&str] = &["nix/metrics"];
+
+/// How often the output of a replayed session is checked for having settled.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The direction in which a message was sent.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Direction {
+    /// From the client to the server.
+    In,
+    /// From the server to the client.
+    Out,
+}
+
+impl Direction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Direction::In => "in",
+            Direction::Out => "out",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Recorder {
+    started: Instant,
+    file: Mutex<Option<File>>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Recorder {
+            started: Instant::now(),
+            file: Mutex::new(None),
+        }
+    }
+
+    /// Starts appending the session to the file at `path`, truncating it first.
+    pub fn start(&self, path: &Path) -> io::Result<()> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+        *self.file.lock().unwrap_or_else(|e| e.into_inner()) = Some(file);
+        Ok(())
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.file
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_some()
+    }
+
+    /// Records a message sent in the given direction.
+    pub fn message(&self, direction: Direction, message: Value) {
+        self.append(json!({
+            "direction": direction.as_str(),
+            "message": message,
+        }));
+    }
+
+    /// Records an internal event, computing its details only while recording.
+    pub fn event<F: FnOnce() -> Value>(&self, name: &str, detail: F) {
+        if self.is_recording() {
+            self.append(json!({ "event": name, "detail": detail() }));
+        }
+    }
+
+    fn append(&self, mut entry: Value) {
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(ref mut file) = *file {
+            entry["timeMs"] = json!(self.started.elapsed().as_secs_f64() * 1000.0);
+            // Losing part of the log is preferable to taking down the server.
+            let _ = writeln!(file, "{}", entry);
+        }
+    }
+}
+
+/// Splits a stream of bytes into the JSON bodies of the `Content-Length` framed messages in it.
+#[derive(Debug, Default)]
+pub struct Framer {
+    buffer: Vec<u8>,
+}
+
+impl Framer {
+    /// Adds the given bytes, returning the messages they complete.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Value> {
+        self.buffer.extend_from_slice(bytes);
+
+        let mut messages = Vec::new();
+        while let Some(header_end) = self.buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            let header = String::from_utf8_lossy(&self.buffer[..header_end]).into_owned();
+            let length = header
+                .lines()
+                .filter_map(|line| line.strip_prefix("Content-Length:"))
+                .find_map(|value| value.trim().parse::<usize>().ok());
+            let body_start = header_end + 4;
+            let length = match length {
+                Some(length) => length,
+                None => {
+                    // Skip over a malformed header rather than stalling on it forever.
+                    self.buffer.drain(..body_start);
+                    continue;
+                }
+            };
+            if self.buffer.len() < body_start + length {
+                break;
+            }
+
+            let body: Vec<_> = self.buffer.drain(..body_start + length).collect();
+            if let Ok(message) = serde_json::from_slice(&body[body_start..]) {
+                messages.push(message);
+            }
+        }
+        messages
+    }
+}
+
+/// A reader or writer which records the messages passing through it.
+#[derive(Debug)]
+pub struct Tap<T> {
+    inner: T,
+    direction: Direction,
+    framer: Framer,
+}
+
+impl<T> Tap<T> {
+    pub fn new(inner: T, direction: Direction) -> Self {
+        Tap {
+            inner,
+            direction,
+            framer: Framer::default(),
+        }
+    }
+
+    fn record(&mut self, bytes: &[u8]) {
+        for message in self.framer.push(bytes) {
+            RECORDER.message(self.direction, message);
+        }
+    }
+}
+
+impl<T: Read> Read for Tap<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.record(&buf[..len]);
+        Ok(len)
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for Tap<T> {}
+
+impl<T: Write> Write for Tap<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.record(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for Tap<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.inner.shutdown()
+    }
+}
+
+/// Collects everything written by a replayed server.
+#[derive(Clone, Debug, Default)]
+struct Capture(Arc<Mutex<Vec<u8>>>);
+
+impl Capture {
+    fn contents(&self) -> Vec<u8> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncWrite for Capture {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        Ok(().into())
+    }
+}
+
+/// The messages of a recorded session, in the order they were sent.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Session {
+    pub incoming: Vec<Value>,
+    pub outgoing: Vec<Value>,
+}
+
+impl Session {
+    /// Reads a session recorded with `--record`, skipping internal events.
+    pub fn read<R: BufRead>(input: R) -> io::Result<Self> {
+        let mut session = Session::default();
+        for line in input.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let mut entry: Value = serde_json::from_str(&line)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let message = entry["message"].take();
+            match entry["direction"].as_str() {
+                Some("in") => session.incoming.push(message),
+                Some("out") => session.outgoing.push(message),
+                _ => {}
+            }
+        }
+        Ok(session)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Session::read(BufReader::new(File::open(path)?))
+    }
+}
+
+/// Runs the incoming messages of `session` through a fresh server, returning the messages it
+/// sent once no new output has appeared for `settle` after every request was answered.
+pub fn replay(session: &Session, settle: Duration) -> io::Result<Vec<Value>> {
+    let mut input = Vec::new();
+    for message in &session.incoming {
+        let body = message.to_string();
+        write!(input, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    }
+
+    let requests = session
+        .incoming
+        .iter()
+        .filter(|message| message.get("id").is_some() && message.get("method").is_some())
+        .count();
+
+    let capture = Capture::default();
+    let mut runtime = tokio::runtime::Runtime::new()?;
+    runtime.spawn(crate::serve(Cursor::new(input), capture.clone()));
+
+    let mut last_change = Instant::now();
+    let mut seen = 0;
+    let output = loop {
+        thread::sleep(POLL_INTERVAL);
+        let output = capture.contents();
+        if output.len() != seen {
+            seen = output.len();
+            last_change = Instant::now();
+            continue;
+        }
+
+        let messages = Framer::default().push(&output);
+        let answered = messages.iter().filter(|m| is_response(m)).count();
+        if answered >= requests && last_change.elapsed() >= settle {
+            break messages;
+        }
+        // Give up waiting for requests which are never answered, and report them as missing.
+        if last_change.elapsed() >= settle * 10 {
+            break messages;
+        }
+    };
+
+    let _ = runtime.shutdown_now().wait();
+    Ok(output)
+}
+
+/// Returns a description of every difference between the messages recorded in `session` and the
+/// messages sent when replaying it.
+pub fn compare(session: &Session, replayed: &[Value]) -> Vec<String> {
+    let methods: BTreeMap<String, &str> = session
+        .incoming
+        .iter()
+        .filter_map(|message| {
+            let method = message.get("method")?.as_str()?;
+            Some((message.get("id")?.to_string(), method))
+        })
+        .collect();
+
+    let expected = Outcome::of(&session.outgoing, &methods);
+    let actual = Outcome::of(replayed, &methods);
+
+    let mut differences = Vec::new();
+    for (id, (method, response)) in &expected.responses {
+        match actual.responses.get(id) {
+            Some((_, replayed)) if replayed == response => {}
+            Some((_, replayed)) => differences.push(format!(
+                "response to `{}` request {} differs\n  recorded: {}\n  replayed: {}",
+                method, id, response, replayed
+            )),
+            None => differences.push(format!("`{}` request {} was not answered", method, id)),
+        }
+    }
+    for (uri, diagnostics) in &expected.diagnostics {
+        match actual.diagnostics.get(uri) {
+            Some(replayed) if replayed == diagnostics => {}
+            Some(replayed) => differences.push(format!(
+                "diagnostics of {} differ\n  recorded: {}\n  replayed: {}",
+                uri, diagnostics, replayed
+            )),
+            None => differences.push(format!("no diagnostics were published for {}", uri)),
+        }
+    }
+    for uri in actual.diagnostics.keys() {
+        if !expected.diagnostics.contains_key(uri) {
+            differences.push(format!("diagnostics were published for {}", uri));
+        }
+    }
+    differences
+}
+
+/// What a server answered over a session, in a form which does not depend on timing.
+#[derive(Debug, Default)]
+struct Outcome {
+    /// The method and response of every request, by ID.
+    responses: BTreeMap<String, (String, Value)>,
+    /// The last diagnostics published for every document.
+    diagnostics: BTreeMap<String, Value>,
+}
+
+impl Outcome {
+    fn of(messages: &[Value], methods: &BTreeMap<String, &str>) -> Self {
+        let mut outcome = Outcome::default();
+        for message in messages {
+            if is_response(message) {
+                let id = message["id"].to_string();
+                let method = methods.get(&id).cloned().unwrap_or("unknown");
+                if NONDETERMINISTIC.contains(&method) {
+                    continue;
+                }
+                let response = message
+                    .get("result")
+                    .or_else(|| message.get("error"))
+                    .cloned()
+                    .unwrap_or(Value::Null);
+                outcome.responses.insert(id, (method.to_owned(), response));
+            } else if message["method"] == "textDocument/publishDiagnostics" {
+                let params = &message["params"];
+                let uri = params["uri"].as_str().unwrap_or_default().to_owned();
+                outcome
+                    .diagnostics
+                    .insert(uri, params["diagnostics"].clone());
+            }
+        }
+        outcome
+    }
+}
+
+fn is_response(message: &Value) -> bool {
+    message.get("id").is_some() && message.get("method").is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(message: &Value) -> Vec<u8> {
+        let body = message.to_string();
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body).into_bytes()
+    }
+
+    #[test]
+    fn splits_framed_messages() {
+        let (first, second) = (json!({ "id": 1 }), json!({ "method": "exit" }));
+        let mut bytes = frame(&first);
+        bytes.extend(frame(&second));
+
+        let mut framer = Framer::default();
+        let (head, tail) = bytes.split_at(bytes.len() - 3);
+        assert_eq!(framer.push(head), [first]);
+        assert_eq!(framer.push(tail), [second]);
+        assert!(framer.buffer.is_empty());
+    }
+
+    #[test]
+    fn compares_replayed_sessions() {
+        let log = r#"
+{"direction":"in","message":{"id":1,"method":"initialize"},"timeMs":0.1}
+{"event":"snapshot","detail":{},"timeMs":0.2}
+{"direction":"out","message":{"id":1,"result":{"capabilities":{}}},"timeMs":0.3}
+{"direction":"in","message":{"id":2,"method":"nix/metrics"},"timeMs":0.4}
+{"direction":"out","message":{"id":2,"result":{"uptimeMs":1.0}},"timeMs":0.5}
+{"direction":"in","message":{"id":3,"method":"textDocument/hover"},"timeMs":0.6}
+{"direction":"out","message":{"method":"textDocument/publishDiagnostics","params":{"uri":"a","diagnostics":[1]}},"timeMs":0.7}
+{"direction":"out","message":{"method":"textDocument/publishDiagnostics","params":{"uri":"a","diagnostics":[]}},"timeMs":0.8}
+{"direction":"out","message":{"id":3,"result":null},"timeMs":0.9}
+"#;
+        let session = Session::read(log.as_bytes()).unwrap();
+        assert_eq!(session.incoming.len(), 3);
+        assert_eq!(session.outgoing.len(), 5);
+        assert!(compare(&session, &session.outgoing).is_empty());
+
+        let replayed = [
+            json!({ "method": "textDocument/publishDiagnostics", "params": { "uri": "a", "diagnostics": [] } }),
+            json!({ "id": 1, "result": { "capabilities": {} } }),
+            json!({ "id": 2, "result": { "uptimeMs": 2.0 } }),
+            json!({ "id": 3, "result": { "contents": "x" } }),
+        ];
+        assert_eq!(
+            compare(&session, &replayed),
+            [
+                "response to `textDocument/hover` request 3 differs\n  recorded: null\n  \
+                 replayed: {\"contents\":\"x\"}"
+            ]
+        );
+        assert_eq!(
+            compare(&session, &replayed[..2]),
+            ["`textDocument/hover` request 3 was not answered"]
+        );
+    }
+}
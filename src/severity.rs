@@ -0,0 +1,172 @@
+//! Per-code overrides of diagnostic severities.
+//!
+//! Clients configure these through the `diagnosticSeverity` setting, which maps diagnostic codes
+//! such as `undefined-variable`, `E0004` or `SC2086` to one of `error`, `warning`, `info`, `hint` or `off`.
+//! The key `security` sets the level of all security lints not configured by their own code.
+//! This lets a project demote the problems it cannot fix yet instead of silencing the whole
+//! analysis which reports them.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde_json::Value;
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, NumberOrString};
+
+use crate::security;
+
+/// The severity to report a diagnostic with, if any.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warning,
+    Info,
+    Hint,
+    Off,
+}
+
+impl Level {
+    fn to_lsp(self) -> Option<DiagnosticSeverity> {
+        match self {
+            Level::Error => Some(DiagnosticSeverity::Error),
+            Level::Warning => Some(DiagnosticSeverity::Warning),
+            Level::Info => Some(DiagnosticSeverity::Information),
+            Level::Hint => Some(DiagnosticSeverity::Hint),
+            Level::Off => None,
+        }
+    }
+}
+
+/// Severity overrides keyed by diagnostic code.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Severities {
+    levels: HashMap<String, Level>,
+}
+
+impl Severities {
+    /// Reads the `diagnosticSeverity` setting from initialization options or workspace settings,
+    /// which may nest it under a `nix` section. Entries with unknown levels are ignored.
+    pub fn from_settings(settings: &Value) -> Self {
+        let section = settings.get("nix").unwrap_or(settings);
+        let levels = section
+            .get("diagnosticSeverity")
+            .and_then(Value::as_object)
+            .map(|map| {
+                map.iter()
+                    .filter_map(|(code, level)| {
+                        let level = Level::deserialize(level).ok()?;
+                        Some((code.clone(), level))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Severities { levels }
+    }
+
+    /// Applies the overrides to `diagnostics`, removing those which are turned off.
+    pub fn apply(&self, diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+        if self.levels.is_empty() {
+            return diagnostics;
+        }
+
+        diagnostics
+            .into_iter()
+            .filter_map(|mut diagnostic| {
+                let code = match diagnostic.code {
+                    Some(NumberOrString::String(ref code)) => code.clone(),
+                    Some(NumberOrString::Number(code)) => code.to_string(),
+                    None => return Some(diagnostic),
+                };
+                let group = if security::is_security_lint(&code) {
+                    self.levels.get(security::GROUP)
+                } else {
+                    None
+                };
+                if let Some(level) = self.levels.get(&code).or(group) {
+                    diagnostic.severity = Some(level.to_lsp()?);
+                }
+                Some(diagnostic)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tower_lsp::lsp_types::Range;
+
+    use super::*;
+
+    #[test]
+    fn overrides_by_code() {
+        let settings = json!({
+            "nix": {
+                "diagnosticSeverity": {
+                    "undefined-variable": "warning",
+                    "SC2086": "off",
+                    "duplicate-attribute": "loud",
+                }
+            }
+        });
+        let severities = Severities::from_settings(&settings);
+
+        let diagnostic = |code: Option<&str>| Diagnostic {
+            severity: Some(DiagnosticSeverity::Error),
+            code: code.map(|code| NumberOrString::String(code.to_owned())),
+            ..Diagnostic::new_simple(Range::default(), String::new())
+        };
+        let diagnostics = vec![
+            diagnostic(Some("undefined-variable")),
+            diagnostic(Some("SC2086")),
+            diagnostic(Some("duplicate-attribute")),
+            diagnostic(None),
+        ];
+
+        let severities: Vec<_> = severities
+            .apply(diagnostics)
+            .into_iter()
+            .map(|d| d.severity)
+            .collect();
+        assert_eq!(
+            severities,
+            [
+                Some(DiagnosticSeverity::Warning),
+                Some(DiagnosticSeverity::Error),
+                Some(DiagnosticSeverity::Error),
+            ]
+        );
+    }
+
+    #[test]
+    fn overrides_security_group() {
+        let settings = json!({
+            "diagnosticSeverity": { "security": "off", "secret-in-store": "error" }
+        });
+        let severities = Severities::from_settings(&settings);
+
+        let diagnostic = |code: &str| Diagnostic {
+            severity: Some(DiagnosticSeverity::Warning),
+            code: Some(NumberOrString::String(code.to_owned())),
+            ..Diagnostic::new_simple(Range::default(), String::new())
+        };
+        let diagnostics = vec![
+            diagnostic("builtins-exec"),
+            diagnostic("secret-in-store"),
+            diagnostic("undefined-variable"),
+        ];
+
+        let severities: Vec<_> = severities
+            .apply(diagnostics)
+            .into_iter()
+            .map(|d| d.severity)
+            .collect();
+        assert_eq!(
+            severities,
+            [
+                Some(DiagnosticSeverity::Error),
+                Some(DiagnosticSeverity::Warning),
+            ]
+        );
+    }
+}
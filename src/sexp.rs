@@ -0,0 +1,321 @@
+//! Rendering of syntax trees as S-expressions using the node names of `tree-sitter-nix`.
+//!
+//! The output follows the format of tree-sitter test corpora: named nodes only, with field names
+//! where the tree-sitter grammar defines them. This allows query files written for tree-sitter,
+//! such as `highlights.scm`, to be checked against the trees produced by this parser. Escape
+//! sequences are not represented, and all strings are rendered as `string_expression` since the
+//! syntax tree does not record whether a string was indented.
+
+use std::fmt::{self, Display, Formatter};
+
+use nix_parser::ast::tokens::{Comment, Literal};
+use nix_parser::ast::{
+    AttrPath, AttrSegment, BinaryOp, Bind, Expr, ExprFnDecl, ExprString, SourceFile, StringFragment,
+};
+
+/// A named node of a tree-sitter syntax tree.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Node {
+    kind: &'static str,
+    field: Option<&'static str>,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn new(kind: &'static str, children: Vec<Node>) -> Self {
+        Node {
+            kind,
+            field: None,
+            children,
+        }
+    }
+
+    fn leaf(kind: &'static str) -> Self {
+        Node::new(kind, Vec::new())
+    }
+
+    fn field(mut self, field: &'static str) -> Self {
+        self.field = Some(field);
+        self
+    }
+
+    fn write(&self, fmt: &mut Formatter, depth: usize) -> fmt::Result {
+        write!(fmt, "{}", "  ".repeat(depth))?;
+        if let Some(field) = self.field {
+            write!(fmt, "{}: ", field)?;
+        }
+
+        write!(fmt, "({}", self.kind)?;
+        for child in &self.children {
+            writeln!(fmt)?;
+            child.write(fmt, depth + 1)?;
+        }
+        write!(fmt, ")")
+    }
+}
+
+impl Display for Node {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        self.write(fmt, 0)
+    }
+}
+
+/// Returns the tree-sitter syntax tree of a source file.
+pub fn to_tree_sitter(file: &SourceFile) -> Node {
+    let mut children: Vec<_> = file.comment().map(comment).into_iter().collect();
+    children.push(expr(file.expr()).field("expression"));
+    Node::new("source_code", children)
+}
+
+fn comment(_: &Comment) -> Node {
+    Node::leaf("comment")
+}
+
+fn identifier() -> Node {
+    Node::leaf("identifier")
+}
+
+fn expr(e: &Expr) -> Node {
+    match *e {
+        Expr::Paren(ref paren) => Node::new(
+            "parenthesized_expression",
+            vec![expr(paren.expr()).field("expression")],
+        ),
+        Expr::Ident(_) => Node::new("variable_expression", vec![identifier().field("name")]),
+        Expr::Interpolation(ref interp) => interpolation(interp.inner()),
+        Expr::Literal(ref lit) => literal(lit),
+        Expr::List(ref list) => {
+            let elems = list.elems().iter().map(|e| expr(e).field("element"));
+            Node::new("list_expression", elems.collect())
+        }
+        Expr::String(ref string) => string_expr(string),
+        Expr::Set(ref set) => Node::new("attrset_expression", binding_set(set.binds())),
+        Expr::Rec(ref rec) => Node::new("rec_attrset_expression", binding_set(rec.binds())),
+        Expr::Let(ref let_) => Node::new("let_attrset_expression", binding_set(let_.binds())),
+        Expr::Unary(ref unary) => Node::new(
+            "unary_expression",
+            vec![expr(unary.expr()).field("argument")],
+        ),
+        Expr::Binary(ref binary) if binary.op() == BinaryOp::HasAttr => Node::new(
+            "has_attr_expression",
+            vec![
+                expr(binary.left()).field("expression"),
+                Node::new("attrpath", vec![attr_of(binary.right())]).field("attrpath"),
+            ],
+        ),
+        Expr::Binary(ref binary) => Node::new(
+            "binary_expression",
+            vec![
+                expr(binary.left()).field("left"),
+                expr(binary.right()).field("right"),
+            ],
+        ),
+        Expr::Proj(ref proj) => {
+            let mut children = vec![
+                expr(proj.base()).field("expression"),
+                attrpath(proj.attr()).field("attrpath"),
+            ];
+            children.extend(proj.fallback().map(|e| expr(e).field("default")));
+            Node::new("select_expression", children)
+        }
+        Expr::Or(ref or) => {
+            let mut node = expr(or.expr());
+            node.children.push(expr(or.fallback()).field("default"));
+            node
+        }
+        Expr::If(ref if_) => Node::new(
+            "if_expression",
+            vec![
+                expr(if_.condition()).field("condition"),
+                expr(if_.body()).field("consequence"),
+                expr(if_.fallback()).field("alternative"),
+            ],
+        ),
+        Expr::Assert(ref assert) => Node::new(
+            "assert_expression",
+            vec![
+                expr(assert.condition()).field("condition"),
+                expr(assert.expr()).field("body"),
+            ],
+        ),
+        Expr::With(ref with) => Node::new(
+            "with_expression",
+            vec![
+                expr(with.with()).field("environment"),
+                expr(with.expr()).field("body"),
+            ],
+        ),
+        Expr::LetIn(ref let_in) => {
+            let mut children = binding_set(let_in.binds());
+            children.extend(let_in.comment().map(comment));
+            children.push(expr(let_in.body()).field("body"));
+            Node::new("let_expression", children)
+        }
+        Expr::FnDecl(ref decl) => match **decl {
+            ExprFnDecl::Simple(ref simple) => Node::new(
+                "function_expression",
+                vec![
+                    identifier().field("universal"),
+                    expr(simple.body()).field("body"),
+                ],
+            ),
+            ExprFnDecl::Formals(ref formals) => {
+                let mut params: Vec<_> = formals
+                    .formals()
+                    .iter()
+                    .map(|formal| {
+                        let mut children = vec![identifier().field("name")];
+                        children.extend(formal.default().map(|e| expr(e).field("default")));
+                        Node::new("formal", children).field("formal")
+                    })
+                    .collect();
+                if formals.ellipsis().is_some() {
+                    params.push(Node::leaf("ellipses").field("ellipses"));
+                }
+
+                let mut children = vec![Node::new("formals", params).field("formals")];
+                if formals.extra().is_some() {
+                    children.push(identifier().field("universal"));
+                }
+                children.push(expr(formals.body()).field("body"));
+                Node::new("function_expression", children)
+            }
+        },
+        Expr::FnApp(ref app) => Node::new(
+            "apply_expression",
+            vec![
+                expr(app.function()).field("function"),
+                expr(app.argument()).field("argument"),
+            ],
+        ),
+        Expr::Error(_) | Expr::Trap(_) => Node::leaf("ERROR"),
+    }
+}
+
+fn literal(lit: &Literal) -> Node {
+    match *lit {
+        Literal::Null(_) | Literal::Boolean(..) => {
+            Node::new("variable_expression", vec![identifier().field("name")])
+        }
+        Literal::Float(..) => Node::leaf("float_expression"),
+        Literal::Integer(..) => Node::leaf("integer_expression"),
+        Literal::Path(ref path, _) if path.starts_with("~") => {
+            Node::new("hpath_expression", vec![Node::leaf("path_fragment")])
+        }
+        Literal::Path(..) => Node::new("path_expression", vec![Node::leaf("path_fragment")]),
+        Literal::PathTemplate(..) => Node::leaf("spath_expression"),
+        Literal::Uri(..) => Node::leaf("uri_expression"),
+    }
+}
+
+fn interpolation(inner: &Expr) -> Node {
+    Node::new("interpolation", vec![expr(inner).field("expression")])
+}
+
+fn string_expr(string: &ExprString) -> Node {
+    let fragments = string.fragments().iter().map(|fragment| match *fragment {
+        StringFragment::Literal(..) => Node::leaf("string_fragment"),
+        StringFragment::Interpolation(ref interp) => interpolation(interp.inner()),
+    });
+    Node::new("string_expression", fragments.collect())
+}
+
+/// Returns the `binding_set` of the given bindings, or nothing if there are none, as tree-sitter
+/// omits the node for empty sets.
+fn binding_set(binds: &[Bind]) -> Vec<Node> {
+    if binds.is_empty() {
+        return Vec::new();
+    }
+
+    let mut children = Vec::new();
+    for bind in binds {
+        match *bind {
+            Bind::Simple(ref simple) => {
+                children.extend(simple.comment().map(comment));
+                let binding = Node::new(
+                    "binding",
+                    vec![
+                        attrpath(simple.attr()).field("attrpath"),
+                        expr(simple.expr()).field("expression"),
+                    ],
+                );
+                children.push(binding.field("binding"));
+            }
+            Bind::Inherit(ref inherit) => {
+                let attrs = inherited_attrs(inherit.names().len());
+                children.push(Node::new("inherit", vec![attrs]).field("binding"));
+            }
+            Bind::InheritExpr(ref inherit) => {
+                let from = Node::new(
+                    "inherit_from",
+                    vec![
+                        expr(inherit.expr()).field("expression"),
+                        inherited_attrs(inherit.names().len()),
+                    ],
+                );
+                children.push(from.field("binding"));
+            }
+        }
+    }
+
+    vec![Node::new("binding_set", children)]
+}
+
+fn inherited_attrs(count: usize) -> Node {
+    let attrs = (0..count).map(|_| identifier().field("attr")).collect();
+    Node::new("inherited_attrs", attrs).field("attrs")
+}
+
+fn attrpath(path: &AttrPath) -> Node {
+    let segments = path.segments().iter().map(|segment| match *segment {
+        AttrSegment::Ident(_) => identifier().field("attr"),
+        AttrSegment::String(ref string) => string_expr(string).field("attr"),
+        AttrSegment::Interpolation(ref interp) => interpolation(interp.inner()).field("attr"),
+    });
+    Node::new("attrpath", segments.collect())
+}
+
+/// Converts the right-hand side of `?`, which is parsed as an expression, to an attribute.
+fn attr_of(e: &Expr) -> Node {
+    match *e {
+        Expr::Ident(_) => identifier().field("attr"),
+        Expr::String(ref string) => string_expr(string).field("attr"),
+        Expr::Interpolation(ref interp) => interpolation(interp.inner()).field("attr"),
+        ref other => expr(other).field("attr"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uses_tree_sitter_node_names() {
+        let file = "{ a = x: x.y; b = [ 1 ./p ]; inherit c; }".parse().unwrap();
+        let expected = "\
+(source_code
+  expression: (attrset_expression
+    (binding_set
+      binding: (binding
+        attrpath: (attrpath
+          attr: (identifier))
+        expression: (function_expression
+          universal: (identifier)
+          body: (select_expression
+            expression: (variable_expression
+              name: (identifier))
+            attrpath: (attrpath
+              attr: (identifier)))))
+      binding: (binding
+        attrpath: (attrpath
+          attr: (identifier))
+        expression: (list_expression
+          element: (integer_expression)
+          element: (path_expression
+            (path_fragment))))
+      binding: (inherit
+        attrs: (inherited_attrs
+          attr: (identifier))))))";
+        assert_eq!(to_tree_sitter(&file).to_string(), expected);
+    }
+}
@@ -0,0 +1,427 @@
+//! Detection of shell scripts embedded in Nix strings.
+//!
+//! Build phases and trivial builders such as `writeShellScript` contain Bash code inside Nix
+//! strings. This module locates those regions so that clients can hand them off to a shell
+//! language server, and optionally runs `shellcheck` over them, mapping its diagnostics back into
+//! the original Nix source.
+
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label, Severity};
+use nix_parser::ast::{Bind, Expr, ExprFnDecl, ExprString, SourceFile, StringFragment};
+use nix_parser::HasSpan;
+use serde_json::Value;
+
+/// Attributes whose string values are executed by the stdenv builder as Bash code.
+pub const SHELL_ATTRS: &[&str] = &[
+    "buildCommand",
+    "buildPhase",
+    "checkPhase",
+    "configurePhase",
+    "distPhase",
+    "fixupPhase",
+    "installCheckPhase",
+    "installPhase",
+    "patchPhase",
+    "postBuild",
+    "postCheck",
+    "postConfigure",
+    "postDist",
+    "postFixup",
+    "postInstall",
+    "postInstallCheck",
+    "postPatch",
+    "postUnpack",
+    "preBuild",
+    "preCheck",
+    "preConfigure",
+    "preDist",
+    "preFixup",
+    "preInstall",
+    "preInstallCheck",
+    "prePatch",
+    "preUnpack",
+    "script",
+    "shellHook",
+    "unpackPhase",
+];
+
+/// Functions whose final string argument is a Bash script, along with their arity.
+pub const SHELL_FUNCTIONS: &[(&str, usize)] = &[
+    ("runCommand", 3),
+    ("runCommandCC", 3),
+    ("runCommandLocal", 3),
+    ("runCommandNoCC", 3),
+    ("writeShellScript", 2),
+    ("writeShellScriptBin", 2),
+];
+
+/// A region of the source file containing an embedded shell script.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ShellRegion {
+    /// The attribute or function name which marks this string as a shell script.
+    pub context: String,
+    /// The span of the string contents, excluding the delimiters.
+    pub span: Span,
+}
+
+impl ShellRegion {
+    /// Extracts the script from the source text.
+    ///
+    /// Interpolations are replaced with shell parameter expansions of identical length, so that
+    /// every byte offset in the script corresponds to the same offset in the original source.
+    pub fn script(&self, source: &str, string: &ExprString) -> String {
+        let start = self.span.start().to_usize();
+        let end = self.span.end().to_usize();
+        let mut script = source[start..end].to_owned();
+
+        for fragment in string.fragments() {
+            if let StringFragment::Interpolation(ref interp) = *fragment {
+                let span = interp.span();
+                let (lo, hi) = (
+                    span.start().to_usize() - start,
+                    span.end().to_usize() - start,
+                );
+                let placeholder = placeholder(&script[lo..hi]);
+                script.replace_range(lo..hi, &placeholder);
+            }
+        }
+
+        script
+    }
+}
+
+fn placeholder(text: &str) -> String {
+    let len = text.len();
+    if text.contains('\n') || len < 4 {
+        return text
+            .chars()
+            .flat_map(|c| {
+                let replacement = if c == '\n' { '\n' } else { ' ' };
+                std::iter::repeat_n(replacement, c.len_utf8())
+            })
+            .collect();
+    }
+
+    format!("${{{}}}", "_".repeat(len - 3))
+}
+
+/// Finds all embedded shell scripts in the given source file.
+pub fn find_scripts<'a>(source: &str, file: &'a SourceFile) -> Vec<(ShellRegion, &'a ExprString)> {
+    let mut regions = Vec::new();
+    walk(source, file.expr(), &mut regions);
+    regions
+}
+
+fn region(source: &str, context: &str, string: &ExprString) -> ShellRegion {
+    let span = string.span();
+    let (start, end) = (span.start().to_usize(), span.end().to_usize());
+    let delimiter = if source[start..].starts_with("''") {
+        2
+    } else {
+        1
+    };
+    let span = Span::new(
+        (start + delimiter) as u32,
+        (end - delimiter).max(start) as u32,
+    );
+
+    ShellRegion {
+        context: context.to_owned(),
+        span,
+    }
+}
+
+/// Returns the name of the function being applied and the number of arguments applied so far,
+/// looking through partial applications.
+fn function_name(expr: &Expr) -> Option<(String, usize)> {
+    match *expr {
+        Expr::Ident(ref ident) => Some((ident.as_str().to_owned(), 1)),
+        Expr::Proj(ref proj) => proj.attr().segments().last().map(|s| (s.to_string(), 1)),
+        Expr::FnApp(ref app) => function_name(app.function()).map(|(name, n)| (name, n + 1)),
+        Expr::Paren(ref paren) => function_name(paren.expr()),
+        _ => None,
+    }
+}
+
+fn walk<'a>(source: &str, expr: &'a Expr, out: &mut Vec<(ShellRegion, &'a ExprString)>) {
+    match *expr {
+        Expr::Paren(ref e) => walk(source, e.expr(), out),
+        Expr::Interpolation(ref e) => walk(source, e.inner(), out),
+        Expr::List(ref list) => list.elems().iter().for_each(|e| walk(source, e, out)),
+        Expr::String(ref string) => walk_string(source, string, out),
+        Expr::Set(ref set) => walk_binds(source, set.binds(), out),
+        Expr::Let(ref e) => walk_binds(source, e.binds(), out),
+        Expr::Rec(ref e) => walk_binds(source, e.binds(), out),
+
+        Expr::Unary(ref e) => walk(source, e.expr(), out),
+        Expr::Binary(ref e) => {
+            walk(source, e.left(), out);
+            walk(source, e.right(), out);
+        }
+        Expr::Proj(ref e) => {
+            walk(source, e.base(), out);
+            if let Some(fallback) = e.fallback() {
+                walk(source, fallback, out);
+            }
+        }
+
+        Expr::If(ref e) => {
+            walk(source, e.condition(), out);
+            walk(source, e.body(), out);
+            walk(source, e.fallback(), out);
+        }
+        Expr::Or(ref e) => {
+            walk(source, e.expr(), out);
+            walk(source, e.fallback(), out);
+        }
+        Expr::Assert(ref e) => {
+            walk(source, e.condition(), out);
+            walk(source, e.expr(), out);
+        }
+        Expr::With(ref e) => {
+            walk(source, e.with(), out);
+            walk(source, e.expr(), out);
+        }
+
+        Expr::LetIn(ref e) => {
+            walk_binds(source, e.binds(), out);
+            walk(source, e.body(), out);
+        }
+        Expr::FnDecl(ref decl) => match **decl {
+            ExprFnDecl::Simple(ref simple) => walk(source, simple.body(), out),
+            ExprFnDecl::Formals(ref formals) => {
+                for default in formals.formals().iter().filter_map(|f| f.default()) {
+                    walk(source, default, out);
+                }
+                walk(source, formals.body(), out);
+            }
+        },
+        Expr::FnApp(ref app) => {
+            let name = function_name(app.function());
+            match (name, app.argument()) {
+                (Some((ref name, arity)), Expr::String(ref string))
+                    if SHELL_FUNCTIONS.contains(&(name.as_str(), arity)) =>
+                {
+                    out.push((region(source, name, string), string));
+                    walk_string(source, string, out);
+                }
+                (Some((ref name, 1)), Expr::Set(ref set)) if name == "writeShellApplication" => {
+                    for bind in set.binds() {
+                        if let Bind::Simple(ref simple) = *bind {
+                            if let Expr::String(ref string) = *simple.expr() {
+                                if simple.attr().to_string() == "text" {
+                                    out.push((region(source, name, string), string));
+                                }
+                            }
+                        }
+                    }
+                    walk_binds(source, set.binds(), out);
+                }
+                _ => walk(source, app.argument(), out),
+            }
+            walk(source, app.function(), out);
+        }
+
+        Expr::Ident(_) | Expr::Literal(_) | Expr::Error(_) | Expr::Trap(_) => {}
+    }
+}
+
+fn walk_string<'a>(
+    source: &str,
+    string: &'a ExprString,
+    out: &mut Vec<(ShellRegion, &'a ExprString)>,
+) {
+    for fragment in string.fragments() {
+        if let StringFragment::Interpolation(ref e) = *fragment {
+            walk(source, e.inner(), out);
+        }
+    }
+}
+
+fn walk_binds<'a>(source: &str, binds: &'a [Bind], out: &mut Vec<(ShellRegion, &'a ExprString)>) {
+    for bind in binds {
+        match *bind {
+            Bind::Simple(ref simple) => {
+                let name = simple.attr().segments().last().map(|s| s.to_string());
+                match (name, simple.expr()) {
+                    (Some(ref name), Expr::String(ref string))
+                        if SHELL_ATTRS.contains(&name.as_str()) =>
+                    {
+                        out.push((region(source, name, string), string));
+                        walk_string(source, string, out);
+                    }
+                    (_, expr) => walk(source, expr, out),
+                }
+            }
+            Bind::Inherit(_) => {}
+            Bind::InheritExpr(ref inherit) => walk(source, inherit.expr(), out),
+        }
+    }
+}
+
+/// Runs `shellcheck` over all embedded scripts, mapping its diagnostics back into `source`.
+///
+/// Returns an empty list if `shellcheck` is not installed or fails to run, and stops at the first
+/// script not checked within `limit` of starting, keeping what was found until then.
+pub fn shellcheck(
+    file: FileId,
+    source: &str,
+    expr: &SourceFile,
+    limit: Duration,
+) -> Vec<Diagnostic> {
+    let deadline = Instant::now() + limit;
+    let mut diagnostics = Vec::new();
+
+    for (region, string) in find_scripts(source, expr) {
+        let script = region.script(source, string);
+        let output = match run_shellcheck(&script, deadline) {
+            Some(output) => output,
+            None => return diagnostics,
+        };
+
+        let comments = output.as_array().cloned().unwrap_or_default();
+        for comment in comments {
+            let position = |line: &str, column: &str| -> Option<usize> {
+                let line = comment.get(line)?.as_u64()? as usize;
+                let column = comment.get(column)?.as_u64()? as usize;
+                script_offset(&script, line, column)
+            };
+
+            let start = position("line", "column");
+            let end = position("endLine", "endColumn");
+            let (start, end) = match (start, end) {
+                (Some(start), Some(end)) => (start, end.max(start)),
+                (Some(start), None) => (start, start),
+                _ => continue,
+            };
+
+            let base = region.span.start().to_usize();
+            let span = Span::new((base + start) as u32, (base + end) as u32);
+            let message = comment.get("message").and_then(Value::as_str);
+            let code = comment.get("code").and_then(Value::as_u64);
+            let severity = match comment.get("level").and_then(Value::as_str) {
+                Some("error") => Severity::Error,
+                Some("warning") => Severity::Warning,
+                Some("info") => Severity::Note,
+                _ => Severity::Help,
+            };
+
+            let label = Label::new(file, span, format!("in `{}`", region.context));
+            let mut diagnostic = Diagnostic::new(severity, message.unwrap_or_default(), label);
+            if let Some(code) = code {
+                diagnostic = diagnostic.with_code(format!("SC{}", code));
+            }
+            diagnostics.push(diagnostic);
+        }
+    }
+
+    diagnostics
+}
+
+/// Runs `shellcheck` over `script`, killing it if it is still running at `deadline`.
+fn run_shellcheck(script: &str, deadline: Instant) -> Option<Value> {
+    let mut child = Command::new("shellcheck")
+        .args(["--format=json", "--shell=bash", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+
+    child.stdin.take()?.write_all(script.as_bytes()).ok()?;
+    // The output is read while waiting, so that `shellcheck` never blocks on a full pipe.
+    let mut stdout = child.stdout.take()?;
+    let reader = thread::spawn(move || {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+            Ok(None) | Err(_) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            }
+        }
+    }
+
+    let output = reader.join().ok()?.ok()?;
+    serde_json::from_slice(&output).ok()
+}
+
+/// Converts a one-based line and column reported by `shellcheck` into a byte offset.
+///
+/// `shellcheck` counts columns in characters, expanding tabs to the next multiple of eight.
+fn script_offset(script: &str, line: usize, column: usize) -> Option<usize> {
+    let line_start: usize = script
+        .split('\n')
+        .take(line.checked_sub(1)?)
+        .map(|l| l.len() + 1)
+        .sum();
+    let text = script.get(line_start..)?.split('\n').next()?;
+
+    let mut current = 1;
+    for (offset, c) in text.char_indices() {
+        if current >= column {
+            return Some(line_start + offset);
+        }
+        current = if c == '\t' {
+            current.div_ceil(8) * 8 + 1
+        } else {
+            current + 1
+        };
+    }
+
+    Some(line_start + text.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_embedded_scripts() {
+        let source = r#"{
+            drv = stdenv.mkDerivation {
+                buildPhase = ''
+                  make -j $NIX_BUILD_CORES ${flags}
+                '';
+                name = "foo";
+            };
+            hello = writeShellScript "hello" "echo hi";
+        }"#;
+
+        let expr: SourceFile = source.parse().unwrap();
+        let scripts = find_scripts(source, &expr);
+        let contexts: Vec<_> = scripts.iter().map(|(r, _)| r.context.as_str()).collect();
+        assert_eq!(contexts, vec!["buildPhase", "writeShellScript"]);
+
+        let (ref region, string) = scripts[0];
+        let script = region.script(source, string);
+        assert_eq!(
+            script.len(),
+            region.span.end().to_usize() - region.span.start().to_usize()
+        );
+        assert!(script.contains("make -j $NIX_BUILD_CORES ${_____}"));
+
+        let (ref region, string) = scripts[1];
+        assert_eq!(region.script(source, string), "echo hi");
+    }
+
+    #[test]
+    fn maps_shellcheck_positions() {
+        let script = "echo\n\tfoo bar";
+        assert_eq!(script_offset(script, 1, 1), Some(0));
+        assert_eq!(script_offset(script, 2, 1), Some(5));
+        assert_eq!(script_offset(script, 2, 9), Some(6));
+        assert_eq!(script_offset(script, 2, 13), Some(10));
+    }
+}
@@ -0,0 +1,232 @@
+//! Immutable snapshots of the analysis state.
+//!
+//! Edits are applied to the [`Database`](crate::db::Database) while holding the server's state
+//! lock. After each edit a new [`Snapshot`] is published, which requests that only read the state
+//! can load without taking the lock. A long-running request therefore sees a consistent view of
+//! every document, while edits carry on and publish newer snapshots next to it.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use codespan::{ByteIndex, Span};
+use codespan_lsp::Error;
+use nix_parser::ast::SourceFile;
+use tower_lsp::lsp_types::{Position, Range, Url};
+
+use crate::db::{ParseResult, Revision};
+use crate::role::Role;
+use crate::text::Text;
+use crate::vfs::{FileLoader, PathResolver, RealFs};
+
+/// The state of a single open document at the time a snapshot was taken.
+///
+/// The text is shared with the database rather than copied, along with the index of its lines.
+#[derive(Debug)]
+pub struct Document {
+    text: Arc<Text>,
+    parse: Arc<ParseResult>,
+    role: Role,
+}
+
+impl Document {
+    pub fn new(text: Arc<Text>, parse: Arc<ParseResult>) -> Self {
+        Document {
+            text,
+            parse,
+            role: Role::Expression,
+        }
+    }
+
+    /// Sets the role of the document, as detected by the database.
+    pub fn with_role(mut self, role: Role) -> Self {
+        self.role = role;
+        self
+    }
+
+    pub fn text(&self) -> &str {
+        self.text.as_str()
+    }
+
+    /// Returns the range of positions covered by `span`, counting columns in UTF-16 code units as
+    /// the protocol does.
+    pub fn range(&self, span: Span) -> Result<Range, Error> {
+        self.text.range(span)
+    }
+
+    /// Returns the index of the byte at `position`, the inverse of [`Text::position`].
+    pub fn byte_index(&self, position: &Position) -> Result<ByteIndex, Error> {
+        self.text.byte_index(position)
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn parse(&self) -> &ParseResult {
+        &self.parse
+    }
+
+    /// Returns the syntax tree of the document, if it could be parsed at all.
+    pub fn source_file(&self) -> Option<&SourceFile> {
+        (*self.parse)
+            .as_ref()
+            .ok()
+            .and_then(|partial| partial.value())
+    }
+}
+
+/// An immutable view of all open documents.
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    revision: Revision,
+    documents: HashMap<Url, Arc<Document>>,
+}
+
+impl Snapshot {
+    /// Returns the database revision this snapshot was taken at.
+    pub fn revision(&self) -> Revision {
+        self.revision
+    }
+
+    pub fn document(&self, uri: &Url) -> Option<&Arc<Document>> {
+        self.documents.get(uri)
+    }
+
+    pub fn documents(&self) -> impl Iterator<Item = (&Url, &Arc<Document>)> {
+        self.documents.iter()
+    }
+
+    /// Returns a new snapshot with the given document added or replaced.
+    ///
+    /// Unchanged documents are shared between both snapshots.
+    pub fn with_document(&self, revision: Revision, uri: Url, document: Document) -> Self {
+        let mut documents = self.documents.clone();
+        documents.insert(uri, Arc::new(document));
+        Snapshot {
+            revision,
+            documents,
+        }
+    }
+
+    /// Returns a new snapshot without the given document, once it has been closed.
+    pub fn without_document(&self, revision: Revision, uri: &Url) -> Self {
+        let mut documents = self.documents.clone();
+        documents.remove(uri);
+        Snapshot {
+            revision,
+            documents,
+        }
+    }
+}
+
+/// Reads open documents as the editor has them, and every other file from disk.
+impl FileLoader for Snapshot {
+    fn load(&self, path: &Path) -> io::Result<String> {
+        let document = Url::from_file_path(path)
+            .ok()
+            .and_then(|uri| self.document(&uri).cloned());
+        match document {
+            Some(document) => Ok(document.text().to_owned()),
+            None => RealFs.load(path),
+        }
+    }
+}
+
+impl PathResolver for Snapshot {
+    fn exists(&self, path: &Path) -> bool {
+        RealFs.exists(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        RealFs.is_dir(path)
+    }
+}
+
+/// Holds the latest snapshot, which can be loaded and replaced without locking.
+#[derive(Debug, Default)]
+pub struct Snapshots(ArcSwap<Snapshot>);
+
+impl Snapshots {
+    pub fn new() -> Self {
+        Snapshots::default()
+    }
+
+    /// Returns the latest snapshot.
+    pub fn load(&self) -> Arc<Snapshot> {
+        self.0.load_full()
+    }
+
+    /// Replaces the latest snapshot. Snapshots loaded before remain valid.
+    pub fn publish(&self, snapshot: Snapshot) {
+        self.0.store(Arc::new(snapshot));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nix_parser::parser::parse_source_file_partial;
+
+    use super::*;
+
+    fn document(text: &str) -> Document {
+        Document::new(
+            Arc::new(Text::new(text.to_owned())),
+            Arc::new(parse_source_file_partial(text)),
+        )
+    }
+
+    #[test]
+    fn snapshots_are_isolated_from_later_edits() {
+        let uri = Url::parse("file:///tmp/default.nix").unwrap();
+        let snapshots = Snapshots::new();
+        snapshots.publish(Snapshot::default().with_document(1, uri.clone(), document("1")));
+
+        let before = snapshots.load();
+        let after = before.with_document(2, uri.clone(), document("{ }"));
+        snapshots.publish(after);
+
+        assert_eq!(before.revision(), 1);
+        assert_eq!(before.document(&uri).unwrap().text(), "1");
+        assert_eq!(snapshots.load().revision(), 2);
+        assert_eq!(snapshots.load().document(&uri).unwrap().text(), "{ }");
+
+        let closed = snapshots.load().without_document(3, &uri);
+        assert!(closed.document(&uri).is_none());
+        assert_eq!(snapshots.load().document(&uri).unwrap().text(), "{ }");
+    }
+
+    #[test]
+    fn converts_positions_like_codespan() {
+        use codespan::{FileId, Files};
+        use codespan_lsp::{byte_span_to_range, position_to_byte_index};
+
+        let text = "let\r\n  é = \"𝄞\";\nin é\n";
+        let document = document(text);
+        let mut files = Files::new();
+        let id: FileId = files.add("default.nix", text);
+
+        for offset in 0..=text.len() as u32 + 1 {
+            let span = Span::new(0, offset);
+            assert_eq!(
+                document.range(span).ok(),
+                byte_span_to_range(&files, id, span).ok(),
+                "{}",
+                offset
+            );
+        }
+        for line in 0..5 {
+            for character in 0..12 {
+                let position = Position { line, character };
+                assert_eq!(
+                    document.byte_index(&position).ok(),
+                    position_to_byte_index(&files, id, &position).ok(),
+                    "{:?}",
+                    position
+                );
+            }
+        }
+    }
+}
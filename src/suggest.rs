@@ -0,0 +1,74 @@
+//! Spelling suggestions for misspelled names.
+
+/// Returns the candidate closest to `name`, if any is close enough to be a plausible typo.
+///
+/// Candidates are compared using the Levenshtein edit distance. A candidate is only accepted if
+/// its distance to `name` is at most one third of the length of `name` (rounded down, and at
+/// least one). Ties are broken by picking the candidate which appears first.
+pub fn did_you_mean<'a, I>(name: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let max_distance = std::cmp::max(1, name.chars().count() / 3);
+    candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|&(distance, _)| distance <= max_distance)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Computes the Levenshtein edit distance between two strings.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, ca) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            let insertion = current[j] + 1;
+            let deletion = previous[j + 1] + 1;
+            current[j + 1] = substitution.min(insertion).min(deletion);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("stdenv", "stdenv"), 0);
+        assert_eq!(edit_distance("stdnv", "stdenv"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn suggestions() {
+        let names = ["stdenv", "lib", "fetchurl"];
+        assert_eq!(
+            did_you_mean("stdnev", names.iter().cloned()),
+            Some("stdenv")
+        );
+        assert_eq!(
+            did_you_mean("stdenb", names.iter().cloned()),
+            Some("stdenv")
+        );
+        assert_eq!(did_you_mean("lbi", names.iter().cloned()), None);
+        assert_eq!(did_you_mean("lb", names.iter().cloned()), Some("lib"));
+        assert_eq!(
+            did_you_mean("fetchUrl", names.iter().cloned()),
+            Some("fetchurl")
+        );
+        assert_eq!(did_you_mean("completely", names.iter().cloned()), None);
+    }
+}
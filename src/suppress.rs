@@ -0,0 +1,258 @@
+//! Suppression of lints by annotations in comments.
+//!
+//! A comment of the form `# nix-lint: disable=rule-name` silences the named lints, given as a
+//! comma separated list. Written after code, it applies to the rest of its line. Written on a line
+//! of its own, it applies to the construct following it: up to the `;` ending a binding, the
+//! bracket closing the enclosing block, or the end of the file.
+//!
+//! Suppressions which name unknown lints or silence nothing are reported in turn, so that they do
+//! not outlive the problems they were written for.
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use nix_parser::lexer::{Lexer, Token};
+use nix_parser::ToSpan;
+
+use crate::resolve::did_you_mean_note;
+use crate::security;
+use crate::suggest::did_you_mean;
+
+/// The names of the lints which can be suppressed, besides the security lints.
+pub const RULES: &[&str] = &[
+    "constant-condition",
+    "constant-dynamic-attribute",
+    "deprecated-let",
+    "deprecated-uri",
+    "duplicate-attribute",
+    "failing-assertion",
+    "module-missing-ellipsis",
+    "module-unsupported-attribute",
+    "naming-style",
+    "overlay-self-reference",
+    "overlay-shape",
+    "shadowed-binding",
+    "shell-argument-without-default",
+    "todo-comment",
+    "unconditional-throw",
+    "undefined-attribute",
+    "undefined-variable",
+    "unknown-flake-input",
+    "unsupported-builtin",
+    "unused-binding",
+];
+
+const PREFIX: &str = "nix-lint:";
+
+/// A lint suppressed in part of a file.
+#[derive(Debug)]
+struct Suppression {
+    rule: String,
+    /// The comment containing the annotation.
+    comment: Span,
+    /// Where the rule is named in the comment.
+    name: Span,
+    scope: Span,
+    used: bool,
+}
+
+/// Removes the lints suppressed in `source`, adding diagnostics for suppressions which are not
+/// valid or not needed.
+pub fn apply(id: FileId, source: &str, diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+    let mut suppressions = find(source);
+    let mut kept: Vec<_> = diagnostics
+        .into_iter()
+        .filter(|diagnostic| {
+            let code = match diagnostic.code {
+                Some(ref code) => code,
+                None => return true,
+            };
+            let start = diagnostic.primary_label.span.start();
+            let found = suppressions
+                .iter_mut()
+                .find(|s| s.rule == *code && s.scope.start() <= start && start < s.scope.end());
+            match found {
+                Some(suppression) => {
+                    suppression.used = true;
+                    false
+                }
+                None => true,
+            }
+        })
+        .collect();
+
+    for suppression in suppressions {
+        if !RULES.contains(&&*suppression.rule) && !security::is_security_lint(&suppression.rule) {
+            let label = Label::new(id, suppression.name, "no lint with this name");
+            let message = format!("unknown lint `{}`", suppression.rule);
+            let mut diagnostic = Diagnostic::new_warning(message, label).with_code("unknown-lint");
+            if let Some(rule) = suggestion(&suppression.rule) {
+                diagnostic = diagnostic.with_notes(vec![did_you_mean_note(rule)]);
+            }
+            kept.push(diagnostic);
+        } else if !suppression.used {
+            let label = Label::new(id, suppression.comment, "nothing is suppressed here");
+            let message = format!("unused suppression of `{}`", suppression.rule);
+            kept.push(Diagnostic::new_warning(message, label).with_code("unused-suppression"));
+        }
+    }
+
+    kept
+}
+
+/// Returns the name of the lint spelled most like the unknown `rule`, if any.
+pub fn suggestion(rule: &str) -> Option<&'static str> {
+    did_you_mean(rule, RULES.iter().chain(security::RULES).cloned())
+}
+
+/// Returns the suppressions annotated in the comments of `source`.
+fn find(source: &str) -> Vec<Suppression> {
+    let lexer = match Lexer::new(source) {
+        Ok(lexer) => lexer,
+        Err(_) => return Vec::new(),
+    };
+    let tokens = lexer.tokens();
+
+    let mut suppressions = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let (text, comment) = match *token {
+            Token::Comment(ref text, _, span) => (text, span),
+            _ => continue,
+        };
+        let rules: Vec<&str> = text
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix(PREFIX))
+            .filter_map(|rest| rest.trim().strip_prefix("disable="))
+            .flat_map(|rules| rules.split(','))
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+            .collect();
+        if rules.is_empty() {
+            continue;
+        }
+
+        let start = comment.start().to_usize();
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let scope = if source[line_start..start].trim().is_empty() {
+            following(tokens.iter().skip(i + 1), comment.end().to_usize())
+        } else {
+            let line_end = source[start..]
+                .find('\n')
+                .map_or(source.len(), |i| start + i);
+            Span::new(line_start as u32, line_end as u32)
+        };
+
+        let text = &source[start..comment.end().to_usize()];
+        suppressions.extend(rules.into_iter().map(|rule| {
+            let name = offset_of(text, rule).map_or(comment, |at| {
+                let at = (start + at) as u32;
+                Span::new(at, at + rule.len() as u32)
+            });
+            Suppression {
+                rule: rule.to_owned(),
+                comment,
+                name,
+                scope,
+                used: false,
+            }
+        }));
+    }
+
+    suppressions
+}
+
+/// Returns the offset of `rule` in the text of the comment naming it, skipping the longer names
+/// it is a part of.
+fn offset_of(comment: &str, rule: &str) -> Option<usize> {
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '-';
+    comment.match_indices(rule).map(|(at, _)| at).find(|&at| {
+        !comment[..at].ends_with(is_name) && !comment[at + rule.len()..].starts_with(is_name)
+    })
+}
+
+/// Returns the span of the construct made of `tokens`, starting at byte `start`.
+fn following<'a, I: IntoIterator<Item = &'a Token<'a>>>(tokens: I, start: usize) -> Span {
+    let mut depth = 0usize;
+    let mut end = start;
+    for token in tokens {
+        match *token {
+            Token::LBrace(_) | Token::LBracket(_) | Token::LParen(_) | Token::Let(_) => depth += 1,
+            Token::RBrace(_) | Token::RBracket(_) | Token::RParen(_) | Token::In(_)
+                if depth == 0 =>
+            {
+                break
+            }
+            Token::RBrace(_) | Token::RBracket(_) | Token::RParen(_) | Token::In(_) => depth -= 1,
+            Token::Semi(_) | Token::Comma(_) if depth == 0 => {
+                end = token.to_span().end().to_usize();
+                break;
+            }
+            Token::Eof(_) => break,
+            _ => {}
+        }
+        end = token.to_span().end().to_usize();
+    }
+    Span::new(start as u32, end as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(source: &str, lints: &[(&str, &str)]) -> Vec<String> {
+        let mut files = codespan::Files::new();
+        let id = files.add("test.nix", source);
+        let diagnostics = lints
+            .iter()
+            .map(|&(rule, at)| {
+                let start = source.find(at).unwrap() as u32;
+                let label = Label::new(id, Span::new(start, start + at.len() as u32), "");
+                Diagnostic::new_error(at, label).with_code(rule)
+            })
+            .collect();
+        let diagnostics = apply(id, source, diagnostics);
+        diagnostics.into_iter().map(|d| d.message).collect()
+    }
+
+    #[test]
+    fn suppresses_lines_and_blocks() {
+        let source = "{
+  a = x1; # nix-lint: disable=undefined-variable
+  b = y2;
+  # nix-lint: disable=undefined-variable, duplicate-attribute
+  c = let d = z3; in { e = w4; };
+  f = v5;
+}";
+        let lints = [
+            ("undefined-variable", "x1"),
+            ("undefined-variable", "y2"),
+            ("undefined-variable", "z3"),
+            ("undefined-variable", "w4"),
+            ("undefined-variable", "v5"),
+        ];
+        assert_eq!(
+            messages(source, &lints),
+            ["y2", "v5", "unused suppression of `duplicate-attribute`"]
+        );
+
+        let source = "# nix-lint: disable=undefined-varaible\nx1";
+        assert_eq!(
+            messages(source, &[("undefined-variable", "x1")]),
+            ["x1", "unknown lint `undefined-varaible`"]
+        );
+    }
+
+    #[test]
+    fn points_at_unknown_lints() {
+        let source = "# nix-lint: disable=unused, unused-bindings\n1";
+        let id = codespan::Files::new().add("test.nix", source);
+        let spans: Vec<_> = apply(id, source, Vec::new())
+            .iter()
+            .map(|d| {
+                let span = d.primary_label.span;
+                &source[span.start().to_usize()..span.end().to_usize()]
+            })
+            .collect();
+        assert_eq!(spans, ["unused", "unused-bindings"]);
+        assert_eq!(suggestion("unused-bindings"), Some("unused-binding"));
+    }
+}
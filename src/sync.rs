@@ -0,0 +1,97 @@
+//! Conversion of full document syncs into incremental edits.
+//!
+//! Clients which only support full document sync send the whole text on every change, which
+//! would otherwise reparse and resolve the whole file each time. Instead, the new text is
+//! compared with the old one to find the smallest region which changed, widened to the tokens it
+//! touches, and applied as an edit so that only the enclosing part of the tree is reparsed.
+
+use std::ops::Range;
+
+use codespan::Span;
+use nix_parser::lexer::Lexer;
+use nix_parser::ToSpan;
+
+/// Returns the span of `old` to replace with the given range of `new` to turn one into the
+/// other, or `None` if they are equal.
+///
+/// The span covers every token of `old` which the change overlaps or adjoins, so that an edit to
+/// part of an identifier or string, or an insertion next to it, replaces the whole token.
+pub fn minimal_edit(old: &str, new: &str) -> Option<(Span, Range<usize>)> {
+    if old == new {
+        return None;
+    }
+
+    let prefix = common_prefix(old, new);
+    let suffix = common_suffix(&old[prefix..], &new[prefix..]);
+    let (changed_start, changed_end) = (prefix, old.len() - suffix);
+    let (mut start, mut end) = (changed_start, changed_end);
+
+    if let Ok(lexer) = Lexer::new(old) {
+        for token in lexer.tokens().iter() {
+            let span = token.to_span();
+            let (token_start, token_end) = (span.start().to_usize(), span.end().to_usize());
+            if token_start < changed_start && changed_start <= token_end {
+                start = start.min(token_start);
+            }
+            if token_start <= changed_end && changed_end < token_end {
+                end = end.max(token_end);
+            }
+        }
+    }
+
+    let replacement = start..new.len() - (old.len() - end);
+    Some((Span::new(start as u32, end as u32), replacement))
+}
+
+/// Returns the length in bytes of the longest common prefix of `a` and `b`, on a char boundary.
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|&((_, x), y)| x != y)
+        .map(|((i, _), _)| i)
+        .unwrap_or_else(|| a.len().min(b.len()))
+}
+
+/// Returns the length in bytes of the longest common suffix of `a` and `b`, on a char boundary.
+fn common_suffix(a: &str, b: &str) -> usize {
+    a.chars()
+        .rev()
+        .zip(b.chars().rev())
+        .take_while(|(x, y)| x == y)
+        .map(|(x, _)| x.len_utf8())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(old: &str, new: &str) -> (String, String) {
+        let (span, range) = minimal_edit(old, new).unwrap();
+        let (start, end) = (span.start().to_usize(), span.end().to_usize());
+        let mut text = old.to_owned();
+        text.replace_range(start..end, &new[range]);
+        (text, old[start..end].to_owned())
+    }
+
+    #[test]
+    fn finds_smallest_token_edit() {
+        let old = "let foo = 1; bar = 2; in foo + bar";
+        assert_eq!(minimal_edit(old, old), None);
+
+        let new = "let foo = 1; baz = 2; in foo + baz";
+        assert_eq!(
+            apply(old, new),
+            (new.to_owned(), "bar = 2; in foo + bar".to_owned())
+        );
+
+        let new = "let foo = 1; bar = 42; in foo + bar";
+        assert_eq!(apply(old, new), (new.to_owned(), "2".to_owned()));
+
+        let new = "let foo = 1; in foo";
+        assert_eq!(apply(old, new).0, new);
+
+        let (old, new) = ("{ a = \"héllo\"; }", "{ a = \"hällo\"; }");
+        assert_eq!(apply(old, new), (new.to_owned(), "\"héllo\"".to_owned()));
+    }
+}
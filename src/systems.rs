@@ -0,0 +1,286 @@
+//! Refactors adding the per-system outputs of a flake for more systems.
+//!
+//! Outputs such as `packages` or `devShells` are keyed by the system they are built for, as in
+//! `packages.x86_64-linux.default`. At a binding of such an output in the set returned by
+//! `outputs`, two kinds of edits are offered:
+//!
+//! * An output which is not keyed by system yet, e.g. `packages.default = ...`, is moved under
+//!   `x86_64-linux`. If the `outputs` function binds a helper such as `forAllSystems` in a `let`,
+//!   the value of `packages = ...` can be wrapped in a call to it instead.
+//! * An output defined for some of the common systems is copied for each missing one, replacing
+//!   every mention of the system in the copy, so that `nixpkgs.legacyPackages.aarch64-linux`
+//!   becomes `nixpkgs.legacyPackages.x86_64-linux`.
+//!
+//! The copies are made from the source text, so they keep their layout and comments.
+
+use codespan::Span;
+use nix_parser::ast::{AttrSegment, Bind, BindSimple, Expr, ExprFnDecl, SourceFile};
+use nix_parser::HasSpan;
+
+/// The systems a copy of an output is offered for, the first being the one outputs are moved
+/// under.
+const SYSTEMS: &[&str] = &[
+    "x86_64-linux",
+    "aarch64-linux",
+    "x86_64-darwin",
+    "aarch64-darwin",
+];
+
+/// Other systems recognized as the key of an output.
+const OTHER_SYSTEMS: &[&str] = &["armv7l-linux", "i686-linux", "riscv64-linux"];
+
+/// The outputs of a flake which are keyed by system.
+const PER_SYSTEM: &[&str] = &[
+    "apps",
+    "checks",
+    "devShells",
+    "formatter",
+    "legacyPackages",
+    "packages",
+];
+
+/// Names commonly given to a function applying a function to every system.
+const HELPERS: &[&str] = &["forAllSystems", "forEachSystem", "eachSystem"];
+
+/// Returns the refactors applicable at `offset` in a `flake.nix` file, each with a title, the span
+/// to replace and its new text.
+pub fn actions(source: &str, file: &SourceFile, offset: usize) -> Vec<(String, Span, String)> {
+    let (binds, helper) = match outputs(file) {
+        Some(outputs) => outputs,
+        None => return Vec::new(),
+    };
+    let (bind, names) = match containing(binds, offset) {
+        Some(found) => found,
+        None => return Vec::new(),
+    };
+    if !PER_SYSTEM.contains(&names[0]) {
+        return Vec::new();
+    }
+
+    match (names.get(1), bind.expr()) {
+        (Some(name), _) if is_system(name) => copy(source, binds, bind, 1),
+        (Some(_), _) => move_under_system(bind).into_iter().collect(),
+        (None, Expr::Set(ref set)) if set.binds().iter().any(keyed_by_system) => {
+            match containing(set.binds(), offset) {
+                Some((inner, names)) if is_system(names[0]) => copy(source, set.binds(), inner, 0),
+                _ => Vec::new(),
+            }
+        }
+        (None, value) => {
+            let mut actions: Vec<_> = move_under_system(bind).into_iter().collect();
+            if let Some(helper) = helper {
+                let text = format!("{} (system: {})", helper, slice(source, value.span()));
+                let title = format!("Wrap in `{}`", helper);
+                actions.push((title, value.span(), text));
+            }
+            actions
+        }
+    }
+}
+
+/// Returns the bindings of the set returned by the `outputs` function of a flake, along with the
+/// name of a helper for defining outputs for every system bound in a `let` around the set.
+fn outputs(file: &SourceFile) -> Option<(&[Bind], Option<&'static str>)> {
+    let binds = match *file.expr() {
+        Expr::Set(ref set) => set.binds(),
+        Expr::Rec(ref rec) => rec.binds(),
+        _ => return None,
+    };
+    let function = binds.iter().find_map(|bind| match *bind {
+        Bind::Simple(ref simple) if static_names(simple)? == ["outputs"] => Some(simple.expr()),
+        _ => None,
+    })?;
+
+    let mut body = match *function {
+        Expr::FnDecl(ref decl) => match **decl {
+            ExprFnDecl::Simple(ref simple) => simple.body(),
+            ExprFnDecl::Formals(ref formals) => formals.body(),
+        },
+        _ => return None,
+    };
+    let mut helper = None;
+    if let Expr::LetIn(ref let_in) = *body {
+        helper = HELPERS
+            .iter()
+            .find(|name| let_in.binding(name).is_some())
+            .copied();
+        body = let_in.body();
+    }
+
+    match *body {
+        Expr::Set(ref set) => Some((set.binds(), helper)),
+        Expr::Rec(ref rec) => Some((rec.binds(), helper)),
+        _ => None,
+    }
+}
+
+/// Returns the binding among `binds` containing `offset`, along with its attribute path if it is
+/// made of static names only.
+fn containing(binds: &[Bind], offset: usize) -> Option<(&BindSimple, Vec<&str>)> {
+    binds.iter().find_map(|bind| match *bind {
+        Bind::Simple(ref simple) if contains(simple.span(), offset) => {
+            Some((simple, static_names(simple)?))
+        }
+        _ => None,
+    })
+}
+
+fn static_names(bind: &BindSimple) -> Option<Vec<&str>> {
+    bind.attr()
+        .segments()
+        .iter()
+        .map(AttrSegment::name)
+        .collect()
+}
+
+fn keyed_by_system(bind: &Bind) -> bool {
+    match *bind {
+        Bind::Simple(ref simple) => simple
+            .attr()
+            .segments()
+            .first()
+            .and_then(AttrSegment::name)
+            .is_some_and(is_system),
+        _ => false,
+    }
+}
+
+fn is_system(name: &str) -> bool {
+    SYSTEMS.contains(&name) || OTHER_SYSTEMS.contains(&name)
+}
+
+/// Inserts the first of `SYSTEMS` after the name of the output defined by `bind`.
+fn move_under_system(bind: &BindSimple) -> Option<(String, Span, String)> {
+    let output = bind.attr().segments().first()?.span();
+    let title = format!("Move under `{}`", SYSTEMS[0]);
+    let text = format!(".{}", SYSTEMS[0]);
+    Some((title, Span::new(output.end(), output.end()), text))
+}
+
+/// Copies `bind` after itself for each of `SYSTEMS` for which none of `binds` defines the same
+/// path, the system being the segment at `index`.
+fn copy(
+    source: &str,
+    binds: &[Bind],
+    bind: &BindSimple,
+    index: usize,
+) -> Vec<(String, Span, String)> {
+    let names = match static_names(bind) {
+        Some(names) => names,
+        None => return Vec::new(),
+    };
+    let system = names[index];
+    let present: Vec<&str> = binds
+        .iter()
+        .filter_map(|other| match *other {
+            Bind::Simple(ref other) => static_names(other),
+            _ => None,
+        })
+        .filter(|other| {
+            other.len() == names.len()
+                && (0..names.len()).all(|i| i == index || other[i] == names[i])
+        })
+        .map(|other| other[index])
+        .collect();
+
+    let end = bind.span().end().to_usize();
+    let insert_at = match source[end..].find(';') {
+        Some(semi) => end + semi + 1,
+        None => return Vec::new(),
+    };
+    let start = bind.span().start().to_usize();
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let indent = &source[line_start..start];
+    let separator = if indent.trim().is_empty() {
+        format!("\n{}", indent)
+    } else {
+        " ".to_owned()
+    };
+
+    let text = slice(source, bind.span());
+    let at = Span::new(insert_at as u32, insert_at as u32);
+    SYSTEMS
+        .iter()
+        .filter(|target| !present.contains(target))
+        .map(|target| {
+            let title = format!("Add `{}` by copying `{}`", target, system);
+            let copy = format!("{}{};", separator, text.replace(system, target));
+            (title, at, copy)
+        })
+        .collect()
+}
+
+fn contains(span: Span, offset: usize) -> bool {
+    span.start().to_usize() <= offset && offset <= span.end().to_usize()
+}
+
+fn slice(source: &str, span: Span) -> &str {
+    &source[span.start().to_usize()..span.end().to_usize()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(source: &str, marker: &str, title: &str) -> Option<String> {
+        let file: SourceFile = source.parse().unwrap();
+        let offset = source.find(marker).unwrap();
+        let (_, span, text) = actions(source, &file, offset)
+            .into_iter()
+            .find(|(t, _, _)| t == title)?;
+        let mut edited = source.to_owned();
+        edited.replace_range(span.start().to_usize()..span.end().to_usize(), &text);
+        Some(edited)
+    }
+
+    #[test]
+    fn copies_outputs_for_missing_systems() {
+        let flake = r#"{
+  outputs = { self, nixpkgs }: {
+    packages.aarch64-linux.default = nixpkgs.legacyPackages.aarch64-linux.hello;
+    packages.x86_64-darwin.default = nixpkgs.legacyPackages.x86_64-darwin.hello;
+  };
+}"#;
+        let title = "Add `x86_64-linux` by copying `aarch64-linux`";
+        let edited = apply(flake, "hello", title).unwrap();
+        let copy = "packages.x86_64-linux.default = nixpkgs.legacyPackages.x86_64-linux.hello;";
+        assert!(edited.contains(&format!("aarch64-linux.hello;\n    {}\n", copy)));
+        let title = "Add `x86_64-darwin` by copying `aarch64-linux`";
+        assert_eq!(apply(flake, "hello", title), None);
+
+        let nested = r#"{
+  outputs = { self, nixpkgs }: {
+    devShells = {
+      aarch64-linux.default = mkShell "aarch64-linux";
+    };
+  };
+}"#;
+        let title = "Add `aarch64-darwin` by copying `aarch64-linux`";
+        let edited = apply(nested, "mkShell", title).unwrap();
+        assert!(edited.contains(
+            "\"aarch64-linux\";\n      aarch64-darwin.default = mkShell \"aarch64-darwin\";\n"
+        ));
+    }
+
+    #[test]
+    fn moves_outputs_under_a_system() {
+        let flake = r#"{
+  outputs = { self, nixpkgs }:
+    let
+      forAllSystems = f: nixpkgs.lib.genAttrs [ "x86_64-linux" ] (system: f system);
+    in
+    {
+      packages = { default = hello; };
+      formatter = nixfmt;
+    };
+}"#;
+        let edited = apply(flake, "formatter", "Move under `x86_64-linux`").unwrap();
+        assert!(edited.contains("formatter.x86_64-linux = nixfmt;"));
+
+        let edited = apply(flake, "default", "Wrap in `forAllSystems`").unwrap();
+        assert!(edited.contains("packages = forAllSystems (system: { default = hello; });"));
+
+        let other = flake.replace("forAllSystems", "systems");
+        assert_eq!(apply(&other, "default", "Wrap in `forAllSystems`"), None);
+    }
+}
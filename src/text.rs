@@ -0,0 +1,99 @@
+//! Source text indexed by line.
+//!
+//! The [`Database`](crate::db::Database) is the only owner of the text of each open file, which
+//! it shares with the snapshots of open documents and, once saved, with the workspace index.
+//! Converting between byte offsets and the positions of the protocol therefore never needs a
+//! second copy of the text.
+
+use std::iter;
+
+use codespan::{ByteIndex, LineIndex, LineIndexOutOfBoundsError, LocationError, Span};
+use codespan_lsp::{character_to_line_offset, Error};
+use tower_lsp::lsp_types::{Position, Range};
+
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct Text {
+    text: String,
+    /// The byte offset at which each line starts.
+    line_starts: Vec<usize>,
+}
+
+impl Text {
+    pub fn new(text: String) -> Self {
+        let line_starts = iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Text { text, line_starts }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// Returns the span of the given line, including its line break.
+    pub fn line_span(&self, line: usize) -> Option<Span> {
+        let start = *self.line_starts.get(line)?;
+        let end = self
+            .line_starts
+            .get(line + 1)
+            .map_or(self.text.len(), |&end| end);
+        Some(Span::new(start as u32, end as u32))
+    }
+
+    /// Returns the position of the byte at `index`, counting columns in UTF-16 code units as the
+    /// protocol does.
+    pub fn position(&self, index: ByteIndex) -> Result<Position, Error> {
+        let offset = index.to_usize();
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let before = self
+            .text
+            .get(self.line_starts[line]..offset)
+            .ok_or_else(|| {
+                if offset > self.text.len() {
+                    let span = Span::from_str(&self.text);
+                    LocationError::OutOfBounds { given: index, span }
+                } else {
+                    LocationError::InvalidCharBoundary { given: index }
+                }
+            })?;
+        Ok(Position {
+            line: line as u64,
+            character: before.encode_utf16().count() as u64,
+        })
+    }
+
+    /// Returns the range of positions covered by `span`.
+    pub fn range(&self, span: Span) -> Result<Range, Error> {
+        Ok(Range {
+            start: self.position(span.start())?,
+            end: self.position(span.end())?,
+        })
+    }
+
+    /// Returns the index of the byte at `position`, the inverse of [`Text::position`].
+    pub fn byte_index(&self, position: &Position) -> Result<ByteIndex, Error> {
+        let line = position.line as usize;
+        let span = self
+            .line_span(line)
+            .ok_or_else(|| LineIndexOutOfBoundsError {
+                given: LineIndex::from(position.line as u32),
+                max: LineIndex::from(self.line_starts.len() as u32),
+            })?;
+        let offset = character_to_line_offset(
+            &self.text[span.start().to_usize()..span.end().to_usize()],
+            position.character,
+        )?;
+        Ok(span.start() + offset)
+    }
+
+    /// Returns the span covered by `range`, the inverse of [`Text::range`].
+    pub fn span(&self, range: &Range) -> Result<Span, Error> {
+        Ok(Span::new(
+            self.byte_index(&range.start)?,
+            self.byte_index(&range.end)?,
+        ))
+    }
+}
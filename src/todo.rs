@@ -0,0 +1,155 @@
+//! Collection of `TODO`, `FIXME` and `XXX` comments, which teams use to track packaging debt.
+//!
+//! A marker is an uppercase word anywhere in a comment, optionally followed by its owner, either
+//! in parentheses as in `# TODO(alice): drop once upstream releases` or as a mention as in
+//! `/* FIXME @bob pin this */`. Only the first marker of a line counts, and the rest of the line is
+//! its text. Markers outside of comments, e.g. in strings, are ignored.
+//!
+//! The markers of all open documents are listed by the `nix.listTodos` command. They are also
+//! reported as information diagnostics when the `todoDiagnostics` setting is enabled.
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label, Severity};
+use nix_parser::lexer::{Lexer, Token};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::Value;
+
+pub const CODE: &str = "todo-comment";
+
+/// The command which lists the markers in every open document.
+pub const COMMAND: &str = "nix.listTodos";
+
+static MARKER: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\b(TODO|FIXME|XXX)\b(?:\(([^()\s]+)\)|\s+@([\w.-]+))?:?").unwrap());
+
+/// A marker found in a comment.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Todo {
+    pub marker: String,
+    pub owner: Option<String>,
+    pub text: String,
+    /// The span of the marker and the rest of its line.
+    pub span: Span,
+}
+
+/// Reads the `todoDiagnostics` setting from initialization options or workspace settings, which
+/// may nest it under a `nix` section.
+pub fn from_settings(settings: &Value) -> bool {
+    let section = settings.get("nix").unwrap_or(settings);
+    section.get("todoDiagnostics").and_then(Value::as_bool) == Some(true)
+}
+
+/// Returns the markers in the comments of `source`, in order.
+pub fn collect(source: &str) -> Vec<Todo> {
+    let lexer = match Lexer::new(source) {
+        Ok(lexer) => lexer,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut todos = Vec::new();
+    for token in lexer.tokens().iter() {
+        let comment = match *token {
+            Token::Comment(_, _, span) => span,
+            _ => continue,
+        };
+
+        let mut offset = comment.start().to_usize();
+        for line in source[offset..comment.end().to_usize()].split_inclusive('\n') {
+            todos.extend(todo(line, offset));
+            offset += line.len();
+        }
+    }
+    todos
+}
+
+/// Returns the marker on the given line of a comment starting at `offset`, if any.
+fn todo(line: &str, offset: usize) -> Option<Todo> {
+    let captures = MARKER.captures(line)?;
+    let marker = captures.get(0)?;
+    let owner = captures.get(2).or_else(|| captures.get(3));
+
+    let rest = line[marker.end()..].trim_end();
+    let rest = rest.strip_suffix("*/").unwrap_or(rest).trim_end();
+    let end = marker.end() + rest.len();
+
+    Some(Todo {
+        marker: captures[1].to_owned(),
+        owner: owner.map(|owner| owner.as_str().to_owned()),
+        text: rest.trim_start().to_owned(),
+        span: Span::new((offset + marker.start()) as u32, (offset + end) as u32),
+    })
+}
+
+/// Returns an information diagnostic for every marker in `source`.
+pub fn check(id: FileId, source: &str) -> Vec<Diagnostic> {
+    collect(source)
+        .into_iter()
+        .map(|todo| {
+            let message = if todo.text.is_empty() {
+                todo.marker.clone()
+            } else {
+                format!("{}: {}", todo.marker, todo.text)
+            };
+            let label = match todo.owner {
+                Some(ref owner) => format!("assigned to {}", owner),
+                None => "unassigned".to_owned(),
+            };
+            let label = Label::new(id, todo.span, label);
+            Diagnostic::new(Severity::Note, message, label).with_code(CODE)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_markers_in_comments() {
+        let source = r#"{
+  # TODO(alice): drop once upstream releases
+  src = "TODO not a comment";
+  /* nothing here
+     FIXME @bob pin this */
+  # XXX
+  # a TODOS list is no marker
+}"#;
+        let todos = collect(source);
+        let found: Vec<_> = todos
+            .iter()
+            .map(|todo| {
+                let span = &source[todo.span.start().to_usize()..todo.span.end().to_usize()];
+                (
+                    todo.marker.as_str(),
+                    todo.owner.as_deref(),
+                    todo.text.as_str(),
+                    span,
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (
+                    "TODO",
+                    Some("alice"),
+                    "drop once upstream releases",
+                    "TODO(alice): drop once upstream releases"
+                ),
+                ("FIXME", Some("bob"), "pin this", "FIXME @bob pin this"),
+                ("XXX", None, "", "XXX"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_setting() {
+        assert!(from_settings(
+            &serde_json::json!({ "nix": { "todoDiagnostics": true } })
+        ));
+        assert!(!from_settings(
+            &serde_json::json!({ "todoDiagnostics": "yes" })
+        ));
+    }
+}
@@ -0,0 +1,97 @@
+//! Bindings of `let` expressions which are never used.
+//!
+//! Only bindings of a single name are reported, since `a.b = ...;` may be one of several
+//! definitions merged into `a`. Names starting with `_` are taken to be unused on purpose.
+
+use std::collections::HashSet;
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use nix_parser::ast::tokens::Ident;
+use nix_parser::ast::visit::descendants;
+use nix_parser::ast::{AttrSegment, Bind, BindSimple, Expr, SourceFile};
+use nix_parser::HasSpan;
+
+use crate::resolve::{self, Target};
+
+pub const CODE: &str = "unused-binding";
+
+/// Returns a warning for every binding of a `let` expression in `file` which is never referred to.
+pub fn check(id: FileId, file: &SourceFile) -> Vec<Diagnostic> {
+    let used: HashSet<_> = resolve::references(file)
+        .into_iter()
+        .filter_map(|reference| match reference.target {
+            Target::Binding(span) => Some(span),
+            Target::With(_) => None,
+        })
+        .collect();
+
+    let mut diagnostics: Vec<_> = bindings(file)
+        .into_iter()
+        .filter(|&(name, _)| !used.contains(&name.span()) && !name.as_str().starts_with('_'))
+        .map(|(name, _)| {
+            let label = Label::new(id, name.span(), "never used");
+            let message = format!("unused binding `{}`", name);
+            Diagnostic::new_warning(message, label).with_code(CODE)
+        })
+        .collect();
+    diagnostics.sort_by_key(|diagnostic| diagnostic.primary_label.span.start());
+    diagnostics
+}
+
+/// Returns the span to delete from `source` to remove the binding whose name is at `name`, from
+/// the end of whatever precedes it to its `;`.
+pub fn removal(source: &str, file: &SourceFile, name: Span) -> Option<Span> {
+    let (_, bind) = bindings(file)
+        .into_iter()
+        .find(|&(ident, _)| ident.span() == name)?;
+    let end = bind.span().end().to_usize();
+    let end = end + source.get(end..)?.find(';')? + 1;
+    let before = source.get(..bind.span().start().to_usize())?;
+    let start = before.trim_end().len();
+    Some(Span::new(start as u32, end as u32))
+}
+
+/// Returns the bindings of a single name in the `let` expressions of `file`, with their names.
+fn bindings(file: &SourceFile) -> Vec<(&Ident, &BindSimple)> {
+    descendants(file.expr())
+        .filter_map(|expr| match *expr {
+            Expr::LetIn(ref let_in) => Some(let_in.binds()),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|bind| match *bind {
+            Bind::Simple(ref simple) => match simple.attr().segments() {
+                [AttrSegment::Ident(ref name)] => Some((name, simple)),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use codespan::Files;
+
+    use super::*;
+
+    #[test]
+    fn reports_unused_bindings() {
+        let source =
+            "let\n  a = 1;\n  b = a;\n  c = 2;\n  _d = 3;\n  e.f = 4;\nin { inherit b; }\n";
+        let file: SourceFile = source.parse().unwrap();
+        let id = Files::new().add("default.nix", source);
+        let diagnostics = check(id, &file);
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, ["unused binding `c`"]);
+
+        let source = "let\n  a = 1;\n  b = 2;\nin a\n";
+        let file: SourceFile = source.parse().unwrap();
+        let diagnostics = check(id, &file);
+        let span = removal(source, &file, diagnostics[0].primary_label.span).unwrap();
+        let (start, end) = (span.start().to_usize(), span.end().to_usize());
+        let fixed = format!("{}{}", &source[..start], &source[end..]);
+        assert_eq!(fixed, "let\n  a = 1;\nin a\n");
+    }
+}
@@ -0,0 +1,91 @@
+//! The boundary between analyses and the file system they run against.
+//!
+//! Analyses which follow imports or read files next to the one being edited, such as
+//! `flake.lock`, go through the [`FileLoader`] and [`PathResolver`] traits rather than `std::fs`.
+//! The server and the command line use [`RealFs`], while tests and targets without a file system
+//! can provide the files they care about with [`MemoryFs`].
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Reads the contents of files.
+pub trait FileLoader {
+    fn load(&self, path: &Path) -> io::Result<String>;
+}
+
+/// Answers questions about paths without reading them.
+pub trait PathResolver {
+    fn exists(&self, path: &Path) -> bool;
+    fn is_dir(&self, path: &Path) -> bool;
+}
+
+/// The file system of the host.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RealFs;
+
+impl FileLoader for RealFs {
+    fn load(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+}
+
+impl PathResolver for RealFs {
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+}
+
+/// A file system held in memory, in which directories exist implicitly by containing files.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryFs {
+    files: BTreeMap<PathBuf, String>,
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        MemoryFs::default()
+    }
+
+    pub fn insert<P: Into<PathBuf>, S: Into<String>>(&mut self, path: P, text: S) {
+        self.files.insert(path.into(), text.into());
+    }
+}
+
+impl FileLoader for MemoryFs {
+    fn load(&self, path: &Path) -> io::Result<String> {
+        self.files.get(path).cloned().ok_or_else(|| {
+            let message = format!("{} does not exist", path.display());
+            io::Error::new(io::ErrorKind::NotFound, message)
+        })
+    }
+}
+
+impl PathResolver for MemoryFs {
+    fn exists(&self, path: &Path) -> bool {
+        self.files.contains_key(path) || self.is_dir(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.files
+            .keys()
+            .any(|file| file != path && file.starts_with(path))
+    }
+}
+
+/// Returns the paths of the user and system flake registries on the host.
+pub fn registry_paths() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from("/etc/nix/registry.json")];
+    if let Some(config) = env::var_os("XDG_CONFIG_HOME") {
+        paths.push(PathBuf::from(config).join("nix/registry.json"));
+    } else if let Some(home) = env::var_os("HOME") {
+        paths.push(PathBuf::from(home).join(".config/nix/registry.json"));
+    }
+    paths
+}
@@ -0,0 +1,458 @@
+//! An index of the `.nix` files under the root of the workspace.
+//!
+//! When the client gives a root directory, every `.nix` file under it is read and parsed on a
+//! background thread, and the literal imports between the files are recorded in an
+//! [`ImportGraph`]. Requests can then follow imports into files which are not open: go to
+//! definition jumps from an imported path to the file, and from an attribute selected from an
+//! import, as in `(import ./lib.nix).f` or `lib.f` with `lib = import ./lib.nix`, to where the
+//! file binds it. Find references lists the imports of a file and the attributes selected from
+//! them across the workspace.
+//!
+//! Hidden directories and symbolic links are not followed. Saved files are indexed again from
+//! the parse the server already has of them.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use codespan::Span;
+use nix_parser::ast::tokens::{Ident, Literal};
+use nix_parser::ast::visit::descendants;
+use nix_parser::ast::{AttrSegment, Bind, Expr, ExprFnDecl, SourceFile};
+use nix_parser::parser::Partial;
+use nix_parser::workspace::parse_files_with_progress;
+use nix_parser::HasSpan;
+
+use crate::breadcrumb;
+use crate::db::ParseResult;
+use crate::imports::{imports, Import, ImportGraph};
+use crate::resolve::{self, Target};
+use crate::text::Text;
+use crate::vfs::{FileLoader, PathResolver};
+
+/// A span of an indexed file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Location {
+    pub path: PathBuf,
+    pub span: Span,
+}
+
+/// An indexed file with the text it was parsed from.
+#[derive(Clone, Debug)]
+pub struct IndexedFile {
+    /// The text of the file, shared with the database if the file was saved from the client.
+    pub text: Arc<Text>,
+    parse: Arc<ParseResult>,
+}
+
+impl IndexedFile {
+    pub fn source_file(&self) -> Option<&SourceFile> {
+        (*self.parse).as_ref().ok().and_then(Partial::value)
+    }
+}
+
+/// A file to be added to a [`Workspace`], along with the files it imports.
+#[derive(Debug)]
+pub struct Prepared {
+    path: PathBuf,
+    file: IndexedFile,
+    targets: Vec<PathBuf>,
+}
+
+/// The `.nix` files of a workspace and the imports between them.
+#[derive(Clone, Debug, Default)]
+pub struct Workspace {
+    files: BTreeMap<PathBuf, IndexedFile>,
+    graph: ImportGraph,
+}
+
+impl Workspace {
+    /// Reads and parses the files at `paths`, skipping those which cannot be read.
+    ///
+    /// `progress` is called with the number of files parsed so far and the number of files read
+    /// each time one is done, possibly from several threads at once.
+    pub fn index<F, P>(paths: Vec<PathBuf>, fs: &F, progress: P) -> Self
+    where
+        F: FileLoader + PathResolver,
+        P: Fn(usize, usize) + Sync,
+    {
+        let texts: Vec<_> = paths
+            .into_iter()
+            .filter_map(|path| fs.load(&path).ok().map(|text| (path, text)))
+            .collect();
+        let total = texts.len();
+        let parsed = parse_files_with_progress(&texts, |done| progress(done, total)).files;
+
+        let mut workspace = Workspace::default();
+        for ((path, text), parse) in texts.into_iter().zip(parsed) {
+            let (text, parse) = (Arc::new(Text::new(text)), Arc::new(parse.result));
+            workspace.insert(Workspace::prepare(path, text, parse, fs));
+        }
+        workspace
+    }
+
+    /// Resolves the imports of the file at `path`, parsed from `text`.
+    ///
+    /// Resolving them may read the file system, so it is done apart from [`Workspace::insert`],
+    /// which then only swaps the file in.
+    pub fn prepare<F: PathResolver>(
+        path: PathBuf,
+        text: Arc<Text>,
+        parse: Arc<ParseResult>,
+        fs: &F,
+    ) -> Prepared {
+        let file = IndexedFile { text, parse };
+        let targets = file
+            .source_file()
+            .map(imports)
+            .unwrap_or_default()
+            .iter()
+            .map(|import| import.resolve(&path, fs))
+            .collect();
+        Prepared {
+            path,
+            file,
+            targets,
+        }
+    }
+
+    /// Adds a file to the index, replacing any file indexed at the same path.
+    pub fn insert(&mut self, prepared: Prepared) {
+        let Prepared {
+            path,
+            file,
+            targets,
+        } = prepared;
+        self.graph.edges.insert(path.clone(), targets);
+        self.files.insert(path, file);
+    }
+
+    /// Returns whether the file at `path` is indexed.
+    pub fn contains(&self, path: &Path) -> bool {
+        self.files.contains_key(path)
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn file(&self, path: &Path) -> Option<&IndexedFile> {
+        self.files.get(path)
+    }
+
+    /// Returns where the path or selected attribute at `offset` of `file`, found at `path`, is
+    /// defined in another file.
+    pub fn definition<F: PathResolver>(
+        &self,
+        path: &Path,
+        file: &SourceFile,
+        offset: usize,
+        fs: &F,
+    ) -> Option<Location> {
+        if let Some(import) = imports(file).into_iter().find(|i| contains(i.span, offset)) {
+            let target = import.resolve(path, fs);
+            if !self.contains(&target) && !fs.exists(&target) {
+                return None;
+            }
+            return Some(Location {
+                path: target,
+                span: Span::initial(),
+            });
+        }
+
+        let location = descendants(file.expr()).find_map(|expr| {
+            let proj = match *expr {
+                Expr::Proj(ref proj) => proj,
+                _ => return None,
+            };
+            let segments = proj.attr().segments();
+            let index = segments.iter().position(|s| contains(s.span(), offset))?;
+            let names: Option<Vec<_>> = segments[..=index].iter().map(AttrSegment::name).collect();
+            let target = imported(file, proj.base())?.resolve(path, fs);
+            let binds = exports(self.file(&target)?.source_file()?.expr());
+            let span = binding(binds, &names?)?;
+            Some(Location { path: target, span })
+        });
+        location
+    }
+
+    /// Returns the references in the workspace to what is at `offset` of `file`, found at `path`:
+    /// the imports of the file an imported path points to, or the selections of an attribute
+    /// bound at the top level of the file.
+    pub fn references<F: PathResolver>(
+        &self,
+        path: &Path,
+        file: &SourceFile,
+        offset: usize,
+        fs: &F,
+    ) -> Vec<Location> {
+        if let Some(import) = imports(file).into_iter().find(|i| contains(i.span, offset)) {
+            return self.importers(&import.resolve(path, fs), fs);
+        }
+
+        let exported = exports(file.expr()).iter().find_map(|bind| match *bind {
+            Bind::Simple(ref simple) => simple
+                .attr()
+                .segments()
+                .first()
+                .filter(|segment| contains(segment.span(), offset))?
+                .name(),
+            _ => None,
+        });
+        match exported {
+            Some(name) => self.selections(path, name, fs),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns every import of the file at `target` in the workspace.
+    pub fn importers<F: PathResolver>(&self, target: &Path, fs: &F) -> Vec<Location> {
+        let mut locations = Vec::new();
+        for (path, indexed) in &self.files {
+            if !self.graph.edges[path].iter().any(|edge| edge == target) {
+                continue;
+            }
+            let file = match indexed.source_file() {
+                Some(file) => file,
+                None => continue,
+            };
+            locations.extend(
+                imports(file)
+                    .into_iter()
+                    .filter(|import| import.resolve(path, fs) == target)
+                    .map(|import| Location {
+                        path: path.clone(),
+                        span: import.span,
+                    }),
+            );
+        }
+        locations
+    }
+
+    /// Returns every selection of the attribute `name` from an import of the file at `target`,
+    /// as in `(import ./target.nix).name`, in the workspace.
+    pub fn selections<F: PathResolver>(&self, target: &Path, name: &str, fs: &F) -> Vec<Location> {
+        let mut locations = Vec::new();
+        for (path, indexed) in &self.files {
+            if !self.graph.edges[path].iter().any(|edge| edge == target) {
+                continue;
+            }
+            let file = match indexed.source_file() {
+                Some(file) => file,
+                None => continue,
+            };
+            locations.extend(descendants(file.expr()).filter_map(|expr| {
+                let (base, segment) = match *expr {
+                    Expr::Proj(ref proj) => (proj.base(), proj.attr().segments().first()?),
+                    _ => return None,
+                };
+                let imported = imported(file, base)?;
+                if segment.name() != Some(name) || imported.resolve(path, fs) != target {
+                    return None;
+                }
+                Some(Location {
+                    path: path.clone(),
+                    span: segment.span(),
+                })
+            }));
+        }
+        locations
+    }
+}
+
+/// Returns the paths of the `.nix` files under `root`, in sorted order.
+pub fn discover(root: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut entries = fs::read_dir(root)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+
+    let mut files = Vec::new();
+    for path in entries {
+        let metadata = fs::symlink_metadata(&path)?;
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if metadata.is_dir() && !hidden {
+            files.extend(discover(&path)?);
+        } else if metadata.is_file() && path.extension().is_some_and(|ext| ext == "nix") {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Returns the import whose value `expr` is, looking through parentheses, applications of the
+/// imported function and variables bound to an import.
+pub fn imported(file: &SourceFile, expr: &Expr) -> Option<Import> {
+    match *expr {
+        Expr::Paren(ref paren) => imported(file, paren.expr()),
+        Expr::FnApp(ref app) => match *app.argument() {
+            Expr::Literal(Literal::Path(_, span)) => {
+                imports(file).into_iter().find(|import| import.span == span)
+            }
+            _ => imported(file, app.function()),
+        },
+        Expr::Ident(ref ident) => {
+            let offset = ident.span().start().to_usize();
+            let span = match resolve::definition_at(file, offset)? {
+                Target::Binding(span) => span,
+                Target::With(_) => return None,
+            };
+            let value = descendants(file.expr()).find_map(|expr| {
+                breadcrumb::binds(expr)?
+                    .iter()
+                    .find_map(|bind| match *bind {
+                        Bind::Simple(ref simple) if simple.attr().span() == span => {
+                            Some(simple.expr())
+                        }
+                        _ => None,
+                    })
+            })?;
+            imported(file, value)
+        }
+        _ => None,
+    }
+}
+
+/// Returns the bindings of the attribute set a file evaluates to, looking through functions,
+/// `let` and parentheses.
+fn exports(expr: &Expr) -> &[Bind] {
+    match *expr {
+        Expr::Set(ref set) => set.binds(),
+        Expr::Rec(ref rec) => rec.binds(),
+        Expr::LetIn(ref let_in) => exports(let_in.body()),
+        Expr::Paren(ref paren) => exports(paren.expr()),
+        Expr::FnDecl(ref decl) => match **decl {
+            ExprFnDecl::Simple(ref simple) => exports(simple.body()),
+            ExprFnDecl::Formals(ref formals) => exports(formals.body()),
+        },
+        _ => &[],
+    }
+}
+
+/// Returns the span of the name binding the attribute at `path` in `binds`, following nested sets
+/// and attribute paths.
+fn binding(binds: &[Bind], path: &[&str]) -> Option<Span> {
+    binds.iter().find_map(|bind| match *bind {
+        Bind::Simple(ref simple) => {
+            let segments = simple.attr().segments();
+            let common = segments
+                .iter()
+                .zip(path)
+                .take_while(|(segment, name)| segment.name() == Some(**name))
+                .count();
+            if common == path.len() {
+                Some(segments[common - 1].span())
+            } else if common == segments.len() && common > 0 {
+                binding(exports(simple.expr()), &path[common..])
+            } else {
+                None
+            }
+        }
+        Bind::Inherit(ref inherit) if path.len() == 1 => names_span(inherit.names(), path[0]),
+        Bind::InheritExpr(ref inherit) if path.len() == 1 => names_span(inherit.names(), path[0]),
+        _ => None,
+    })
+}
+
+fn names_span(names: &[Ident], name: &str) -> Option<Span> {
+    names.iter().find(|n| n.as_str() == name).map(HasSpan::span)
+}
+
+fn contains(span: Span, offset: usize) -> bool {
+    span.start().to_usize() <= offset && offset <= span.end().to_usize()
+}
+
+#[cfg(test)]
+mod tests {
+    use nix_parser::parser::parse_source_file_partial;
+
+    use super::*;
+    use crate::vfs::MemoryFs;
+
+    fn workspace() -> (Workspace, MemoryFs) {
+        let mut fs = MemoryFs::new();
+        fs.insert(
+            "/p/default.nix",
+            "let
+  lib = import ./lib { };
+in { a = lib.greet; b = (import ./lib/default.nix { }).sub.name; }
+",
+        );
+        fs.insert(
+            "/p/lib/default.nix",
+            "{ }:\nlet x = 1; in { greet = \"hi\"; sub.name = x; inherit x; }\n",
+        );
+        fs.insert("/p/other.nix", "(import ./lib { }).greet\n");
+        let paths = ["/p/default.nix", "/p/lib/default.nix", "/p/other.nix"];
+        let workspace = Workspace::index(paths.iter().map(PathBuf::from).collect(), &fs, |_, _| {});
+        (workspace, fs)
+    }
+
+    fn text<'a>(workspace: &'a Workspace, location: &Location) -> &'a str {
+        let text = workspace.file(&location.path).unwrap().text.as_str();
+        &text[location.span.start().to_usize()..location.span.end().to_usize()]
+    }
+
+    #[test]
+    fn follows_imports_to_definitions() {
+        let (workspace, fs) = workspace();
+        assert_eq!(workspace.len(), 3);
+        let path = Path::new("/p/default.nix");
+        let indexed = workspace.file(path).unwrap();
+        let file = indexed.source_file().unwrap();
+        let definition = |needle: &str| {
+            let offset = indexed.text.as_str().find(needle).unwrap() + 1;
+            workspace.definition(path, file, offset, &fs)
+        };
+
+        let import = definition("./lib").unwrap();
+        assert_eq!(import.path, Path::new("/p/lib/default.nix"));
+
+        let greet = definition("greet").unwrap();
+        assert_eq!(greet.path, Path::new("/p/lib/default.nix"));
+        assert_eq!(text(&workspace, &greet), "greet");
+        let name = definition("name").unwrap();
+        assert_eq!(text(&workspace, &name), "name");
+        assert_eq!(definition("a ="), None);
+    }
+
+    #[test]
+    fn finds_imports_and_selections_across_files() {
+        let (workspace, fs) = workspace();
+        let lib = Path::new("/p/lib/default.nix");
+
+        let importers = workspace.importers(lib, &fs);
+        let paths: Vec<_> = importers.iter().map(|l| l.path.to_str().unwrap()).collect();
+        assert_eq!(paths, ["/p/default.nix", "/p/default.nix", "/p/other.nix"]);
+
+        let selections = workspace.selections(lib, "greet", &fs);
+        assert_eq!(selections.len(), 2);
+        assert!(selections.iter().all(|l| text(&workspace, l) == "greet"));
+        assert_eq!(workspace.selections(lib, "sub", &fs).len(), 1);
+
+        let indexed = workspace.file(lib).unwrap();
+        let file = indexed.source_file().unwrap();
+        let offset = indexed.text.as_str().find("greet").unwrap();
+        assert_eq!(workspace.references(lib, file, offset, &fs), selections);
+        let offset = indexed.text.as_str().find("x;").unwrap();
+        assert!(workspace.references(lib, file, offset, &fs).is_empty());
+    }
+
+    #[test]
+    fn replaces_saved_files() {
+        let (mut workspace, fs) = workspace();
+        let (path, text) = (PathBuf::from("/p/other.nix"), "1\n".to_owned());
+        let parse = Arc::new(parse_source_file_partial(&text));
+        let text = Arc::new(Text::new(text));
+        workspace.insert(Workspace::prepare(path.clone(), text, parse, &fs));
+
+        assert_eq!(workspace.len(), 3);
+        assert_eq!(workspace.file(&path).unwrap().text.as_str(), "1\n");
+        let importers = workspace.importers(Path::new("/p/lib/default.nix"), &fs);
+        assert!(importers.iter().all(|l| l.path != path));
+    }
+}